@@ -3,7 +3,6 @@ use guardian_core::{
 };
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::Semaphore;
 
 async fn example_simple_usage() {
     println!("=== Example 1: Simple Usage ===\n");
@@ -186,104 +185,6 @@ async fn example_cost_based() {
     println!();
 }
 
-async fn benchmark_throughput() {
-    println!("=== Benchmark: Maximum Throughput ===\n");
-
-    let config = TokenBucketConfig {
-        capacity: 1_000_000,
-        refill_rate: 100_000,
-        refill_interval: Duration::from_secs(1),
-    };
-
-    let backend = MemoryBackend::new(config);
-    let limiter = Arc::new(RateLimiter::new(backend, false));
-
-    let num_requests = 100_000;
-    let concurrency = 100;
-    let semaphore = Arc::new(Semaphore::new(concurrency));
-
-    println!("Running {} requests with {} concurrent workers...", num_requests, concurrency);
-    let start = Instant::now();
-
-    let mut handles = vec![];
-    for i in 0..num_requests {
-        let limiter = Arc::clone(&limiter);
-        let semaphore = Arc::clone(&semaphore);
-
-        let handle = tokio::spawn(async move {
-            let _permit = semaphore.acquire().await.unwrap();
-            limiter.check_limit(&format!("user_{}", i % 1000), 1).await
-        });
-        handles.push(handle);
-    }
-
-    let mut allowed = 0;
-    let mut denied = 0;
-    for handle in handles {
-        match handle.await.unwrap() {
-            Ok(LimitResult::Allowed) => allowed += 1,
-            Ok(LimitResult::Denied { .. }) => denied += 1,
-            Err(_) => {}
-        }
-    }
-
-    let elapsed = start.elapsed();
-    let throughput = num_requests as f64 / elapsed.as_secs_f64();
-
-    println!("\n📊 Results:");
-    println!("  Total requests: {}", num_requests);
-    println!("  Allowed: {}", allowed);
-    println!("  Denied: {}", denied);
-    println!("  Time: {:?}", elapsed);
-    println!("  Throughput: {:.0} req/sec", throughput);
-    println!("  Latency p50: ~{:.2}µs", elapsed.as_micros() as f64 / num_requests as f64);
-    println!();
-}
-
-
-async fn benchmark_latency() {
-    println!("=== Benchmark: Latency Distribution ===\n");
-
-    let config = TokenBucketConfig {
-        capacity: 10_000,
-        refill_rate: 1_000,
-        refill_interval: Duration::from_secs(1),
-    };
-
-    let backend = MemoryBackend::new(config);
-    let limiter = RateLimiter::new(backend, false);
-
-    let num_samples = 10_000;
-    let mut latencies = Vec::with_capacity(num_samples);
-
-    println!("Measuring {} samples...", num_samples);
-
-    for _ in 0..num_samples {
-        let start = Instant::now();
-        limiter.check_limit("latency_test", 1).await.unwrap();
-        let elapsed = start.elapsed();
-        latencies.push(elapsed.as_nanos() as u64);
-    }
-
-    latencies.sort_unstable();
-
-    let percentile = |p: f64| -> u64 {
-        let idx = ((num_samples as f64 * p) as usize).min(num_samples - 1);
-        latencies[idx]
-    };
-
-    println!("\n📊 Latency Distribution:");
-    println!("  p50:  {} ns", percentile(0.50));
-    println!("  p90:  {} ns", percentile(0.90));
-    println!("  p95:  {} ns", percentile(0.95));
-    println!("  p99:  {} ns", percentile(0.99));
-    println!("  p999: {} ns", percentile(0.999));
-    println!("  min:  {} ns", latencies[0]);
-    println!("  max:  {} ns", latencies[num_samples - 1]);
-    println!();
-}
-
-
 #[tokio::main]
 async fn main() {
     println!("\n🛡️  Guardian Rate Limiter - Complete Demo\n");
@@ -302,15 +203,9 @@ async fn main() {
     tokio::time::sleep(Duration::from_millis(500)).await;
 
     example_cost_based().await;
-    tokio::time::sleep(Duration::from_millis(500)).await;
-
-    benchmark_throughput().await;
-    tokio::time::sleep(Duration::from_millis(500)).await;
-
-    benchmark_latency().await;
 
     println!("═══════════════════════════════════════════════════════════");
-    println!("\n✅ All examples and benchmarks completed!\n");
+    println!("\n✅ All examples completed!\n");
 }
 
 
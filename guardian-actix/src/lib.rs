@@ -0,0 +1,403 @@
+//! [actix-web](https://actix.rs) middleware that checks each request with
+//! Guardian before its handler runs. Keys and response headers work as in
+//! `guardian-axum`: requests are keyed by client IP, a header, or a claim
+//! of the caller's JWT, and responses carry the decision's rate-limit
+//! headers.
+//!
+//! ```no_run
+//! use actix_web::{web, App, HttpServer};
+//! use guardian_actix::{GuardianMiddleware, Key};
+//! use guardian_client::GuardianClient;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let guardian = GuardianClient::connect("http://127.0.0.1:50051").await?;
+//! let limit = GuardianMiddleware::new(guardian).with_key(Key::JwtClaim("sub".to_string()));
+//! HttpServer::new(move || {
+//!     App::new()
+//!         .wrap(limit.clone())
+//!         .route("/", web::get().to(|| async { "hello" }))
+//!         // Uploads also count against the "upload:sub:<id>" bucket, 10
+//!         // tokens at a time.
+//!         .service(
+//!             web::resource("/upload")
+//!                 .wrap(limit.clone().with_scope("upload").with_cost(10))
+//!                 .route(web::post().to(|| async { "stored" })),
+//!         )
+//! })
+//! .bind("0.0.0.0:8080")?
+//! .run()
+//! .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A denied request gets `429 Too Many Requests` with `retry-after` and
+//! never reaches its handler.
+
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use actix_web::{Error, HttpResponse};
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use guardian_client::{GuardianApi, LimitCheckResult};
+use guardian_core::{LimitResult, RateLimiter, StorageBackend};
+use serde_json::Value;
+
+type KeyFn = dyn Fn(&ServiceRequest) -> Option<String> + Send + Sync;
+
+/// What a request is limited by. Requests without one (a missing header,
+/// no bearer token) go through unchecked.
+#[derive(Clone)]
+pub enum Key {
+    /// The peer's IP address, as `ip:<addr>`. Behind a proxy, use
+    /// [`Key::Header`] with the header the proxy sets instead.
+    ClientIp,
+    /// A header's value, as `<name>:<value>`, such as an API key.
+    Header(HeaderName),
+    /// A claim of the bearer token in `Authorization`, as
+    /// `<claim>:<value>`. The token's signature isn't checked here, so
+    /// register this middleware after (inside) the one that authenticates.
+    JwtClaim(String),
+    /// Whatever `f` returns for the request.
+    Custom(Arc<KeyFn>),
+}
+
+impl Key {
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> Option<String> + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(f))
+    }
+
+    fn of(&self, req: &ServiceRequest) -> Option<String> {
+        match self {
+            Self::ClientIp => Some(format!("ip:{}", req.peer_addr()?.ip())),
+            Self::Header(name) => {
+                let value = req.headers().get(name)?.to_str().ok()?;
+                Some(format!("{}:{}", name, value))
+            }
+            Self::JwtClaim(claim) => {
+                jwt_claim(req, claim).map(|value| format!("{}:{}", claim, value))
+            }
+            Self::Custom(f) => f(req),
+        }
+    }
+}
+
+/// `claim` from the payload of the request's bearer token.
+fn jwt_claim(req: &ServiceRequest, claim: &str) -> Option<String> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+    let payload = URL_SAFE_NO_PAD.decode(token.split('.').nth(1)?).ok()?;
+    let claims: Value = serde_json::from_slice(&payload).ok()?;
+    match claims.get(claim)? {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Where decisions come from.
+#[async_trait]
+trait Limiter: Send + Sync {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String>;
+}
+
+struct Remote<A>(A);
+
+#[async_trait]
+impl<A: GuardianApi> Limiter for Remote<A> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        self.0
+            .check_limit_detailed(key, cost)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend + 'static> Limiter for RateLimiter<B> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        let (result, bucket) = self
+            .check_limit_detailed(key, cost.into())
+            .await
+            .map_err(|e| e.to_string())?;
+        let retry_after = match result {
+            LimitResult::Allowed => Duration::ZERO,
+            LimitResult::Denied { retry_after } => retry_after,
+        };
+        Ok(LimitCheckResult {
+            allowed: matches!(result, LimitResult::Allowed),
+            retry_after_seconds: retry_after.as_secs_f64().ceil() as u32,
+            remaining_tokens: bucket.map_or(0, |bucket| bucket.remaining),
+            limit: bucket.map_or(0, |bucket| bucket.capacity),
+        })
+    }
+}
+
+/// Rate limits what it wraps; see the [crate docs](crate).
+///
+/// Wrap a whole `App`, or single resources and scopes, each with its own
+/// [scope](Self::with_scope) and [cost](Self::with_cost). Middleware
+/// stacks, so a route under two is checked against both buckets.
+#[derive(Clone)]
+pub struct GuardianMiddleware {
+    limiter: Arc<dyn Limiter>,
+    key: Key,
+    scope: Option<String>,
+    cost: u32,
+    fail_open: bool,
+}
+
+impl GuardianMiddleware {
+    /// Decide through a Guardian service: a
+    /// [`GuardianClient`](guardian_client::GuardianClient), or anything
+    /// else implementing [`GuardianApi`].
+    pub fn new(api: impl GuardianApi + 'static) -> Self {
+        Self::with_limiter(Remote(api))
+    }
+
+    /// Decide in this process, with a `guardian-core` limiter, for apps
+    /// that run no Guardian service. Give each scope a limiter of its own
+    /// for different limits per route.
+    pub fn embedded<B: StorageBackend + 'static>(limiter: RateLimiter<B>) -> Self {
+        Self::with_limiter(limiter)
+    }
+
+    fn with_limiter(limiter: impl Limiter + 'static) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            key: Key::ClientIp,
+            scope: None,
+            cost: 1,
+            fail_open: true,
+        }
+    }
+
+    /// What requests are limited by; [`Key::ClientIp`] unless set.
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Prefix keys with `scope:`, so these routes get buckets (and the
+    /// service's limit rules) of their own.
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Tokens each request costs; 1 unless set.
+    pub fn with_cost(mut self, cost: u32) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    /// Whether to let requests through when the limiter can't decide (the
+    /// default), or answer them with `503 Service Unavailable`.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    fn key_of(&self, req: &ServiceRequest) -> Option<String> {
+        let key = self.key.of(req)?;
+        Some(match &self.scope {
+            Some(scope) => format!("{}:{}", scope, key),
+            None => key,
+        })
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for GuardianMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = GuardianService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(GuardianService {
+            service: Rc::new(service),
+            middleware: self.clone(),
+        }))
+    }
+}
+
+/// A service that checks each request with Guardian before its handler.
+pub struct GuardianService<S> {
+    service: Rc<S>,
+    middleware: GuardianMiddleware,
+}
+
+impl<S, B> Service<ServiceRequest> for GuardianService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let middleware = self.middleware.clone();
+        Box::pin(async move {
+            let Some(key) = middleware.key_of(&req) else {
+                return Ok(service.call(req).await?.map_into_left_body());
+            };
+            let decision = match middleware.limiter.check(&key, middleware.cost).await {
+                Ok(decision) => decision,
+                Err(_) if middleware.fail_open => {
+                    return Ok(service.call(req).await?.map_into_left_body());
+                }
+                Err(_) => {
+                    let response = HttpResponse::ServiceUnavailable().finish();
+                    return Ok(req.into_response(response).map_into_right_body());
+                }
+            };
+            let mut response = if decision.allowed {
+                service.call(req).await?.map_into_left_body()
+            } else {
+                let response = HttpResponse::TooManyRequests().body("rate limited");
+                req.into_response(response).map_into_right_body()
+            };
+            for (name, value) in decision.to_headers() {
+                if let Ok(value) = HeaderValue::try_from(value) {
+                    response
+                        .headers_mut()
+                        .insert(HeaderName::from_static(name), value);
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+    use guardian_client::{ClientError, MockGuardianClient};
+
+    fn get(uri: &str) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri(uri)
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_limits_by_ip_per_route() {
+        let mock = MockGuardianClient::new();
+        let limit = GuardianMiddleware::new(mock.clone());
+        let app = test::init_service(
+            App::new()
+                .wrap(limit.clone())
+                .route("/", web::get().to(|| async { "hello" }))
+                .service(
+                    web::resource("/search")
+                        .wrap(limit.with_scope("search").with_cost(5))
+                        .route(web::get().to(|| async { "results" })),
+                ),
+        )
+        .await;
+
+        let response = test::call_service(&app, get("/").to_request()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("ratelimit-remaining").unwrap(), "0");
+
+        mock.deny("search:ip:10.0.0.1", 4);
+        let response = test::call_service(&app, get("/search").to_request()).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get("retry-after").unwrap(), "4");
+
+        let calls: Vec<_> = mock
+            .calls()
+            .into_iter()
+            .map(|call| (call.client_id, call.cost))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                ("ip:10.0.0.1".to_string(), 1),
+                ("ip:10.0.0.1".to_string(), 1),
+                ("search:ip:10.0.0.1".to_string(), 5),
+            ]
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_header_and_jwt_claim_keys() {
+        let mock = MockGuardianClient::new();
+        let payload = URL_SAFE_NO_PAD.encode(r#"{"sub":"user-7"}"#);
+        let bearer = format!("Bearer e30.{}.sig", payload);
+        for key in [
+            Key::Header(HeaderName::from_static("x-api-key")),
+            Key::JwtClaim("sub".to_string()),
+        ] {
+            let app = test::init_service(
+                App::new()
+                    .wrap(GuardianMiddleware::new(mock.clone()).with_key(key))
+                    .route("/", web::get().to(|| async { "hello" })),
+            )
+            .await;
+            let request = get("/")
+                .insert_header(("x-api-key", "k1"))
+                .insert_header((AUTHORIZATION, bearer.as_str()))
+                .to_request();
+            test::call_service(&app, request).await;
+            // Neither header: not checked.
+            test::call_service(&app, get("/").to_request()).await;
+        }
+
+        let keys: Vec<_> = mock
+            .calls()
+            .into_iter()
+            .map(|call| call.client_id)
+            .collect();
+        assert_eq!(keys, ["x-api-key:k1", "sub:user-7"]);
+    }
+
+    #[actix_web::test]
+    async fn test_fails_open_unless_told_otherwise() {
+        let mock = MockGuardianClient::new();
+        mock.push_response("ip:10.0.0.1", Err(ClientError::DeadlineExceeded));
+        mock.push_response("ip:10.0.0.1", Err(ClientError::DeadlineExceeded));
+        for (fail_open, status) in [
+            (true, StatusCode::OK),
+            (false, StatusCode::SERVICE_UNAVAILABLE),
+        ] {
+            let middleware = GuardianMiddleware::new(mock.clone()).with_fail_open(fail_open);
+            let app = test::init_service(
+                App::new()
+                    .wrap(middleware)
+                    .route("/", web::get().to(|| async { "hello" })),
+            )
+            .await;
+            let response = test::call_service(&app, get("/").to_request()).await;
+            assert_eq!(response.status(), status);
+        }
+    }
+}
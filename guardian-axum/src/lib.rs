@@ -0,0 +1,428 @@
+//! [axum](https://docs.rs/axum) middleware that checks each request with
+//! Guardian before its handler runs, keyed by client IP, a header, or a
+//! claim of the caller's JWT.
+//!
+//! ```no_run
+//! use axum::routing::{get, post};
+//! use axum::Router;
+//! use guardian_axum::{GuardianLayer, Key};
+//! use guardian_client::GuardianClient;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let guardian = GuardianClient::connect("http://127.0.0.1:50051").await?;
+//! let limit = GuardianLayer::new(guardian).with_key(Key::JwtClaim("sub".to_string()));
+//! let app: Router = Router::new()
+//!     .route("/", get(|| async { "hello" }))
+//!     // Uploads also count against the "upload:sub:<id>" bucket, 10
+//!     // tokens at a time.
+//!     .route(
+//!         "/upload",
+//!         post(|| async { "stored" })
+//!             .route_layer(limit.clone().with_scope("upload").with_cost(10)),
+//!     )
+//!     .layer(limit);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Responses carry the decision's `ratelimit-*` and `x-ratelimit-*`
+//! headers. A denied request gets `429 Too Many Requests` with
+//! `retry-after` and never reaches its handler. Limits for each scope are
+//! set on the service, as rules for key patterns such as `upload:*`.
+
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, MatchedPath, Request};
+use axum::http::header::{HeaderName, HeaderValue};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use guardian_client::{GuardianApi, LimitCheckResult};
+use guardian_core::{LimitResult, RateLimiter, StorageBackend};
+use guardian_extractors::HttpParts;
+use tower::{Layer, Service};
+
+pub use guardian_extractors::{Extractor, TrustedProxies};
+
+type KeyFn = dyn Fn(&Request) -> Option<String> + Send + Sync;
+
+/// What a request is limited by. Requests without one (no `ConnectInfo`,
+/// a missing header, no bearer token) go through unchecked.
+#[derive(Clone)]
+pub enum Key {
+    /// The peer's IP address, as `ip:<addr>`. Serve the router with
+    /// `into_make_service_with_connect_info::<SocketAddr>()` so requests
+    /// carry it; behind a proxy, use [`Extractor::client_ip`] with the
+    /// proxies' addresses instead.
+    ClientIp,
+    /// A header's value, as `<name>:<value>`, such as an API key.
+    Header(HeaderName),
+    /// A claim of the bearer token in `Authorization`, as
+    /// `<claim>:<value>`. The token's signature isn't checked here, so put
+    /// this layer inside the one that authenticates.
+    JwtClaim(String),
+    /// A shared [`Extractor`], for keys that mean the same as in
+    /// Guardian's other middleware. It sees the route as axum's
+    /// `MatchedPath`.
+    Extractor(Extractor),
+    /// Whatever `f` returns for the request.
+    Custom(Arc<KeyFn>),
+}
+
+impl Key {
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(f))
+    }
+
+    fn of(&self, req: &Request) -> Option<String> {
+        match self {
+            Self::ClientIp => {
+                let ConnectInfo(addr) = req.extensions().get::<ConnectInfo<SocketAddr>>()?;
+                Some(format!("ip:{}", addr.ip()))
+            }
+            Self::Header(name) => {
+                let value = req.headers().get(name)?.to_str().ok()?;
+                Some(format!("{}:{}", name, value))
+            }
+            Self::JwtClaim(claim) => Extractor::jwt_claim(claim.as_str()).extract(&parts(req)),
+            Self::Extractor(extractor) => extractor.extract(&parts(req)),
+            Self::Custom(f) => f(req),
+        }
+    }
+}
+
+/// `req` as the shared extractors see it.
+fn parts(req: &Request) -> HttpParts<'_, Body> {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str);
+    HttpParts::new(req).with_peer(peer).with_route(route)
+}
+
+/// Where decisions come from.
+#[async_trait]
+trait Limiter: Send + Sync {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String>;
+}
+
+struct Remote<A>(A);
+
+#[async_trait]
+impl<A: GuardianApi> Limiter for Remote<A> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        self.0
+            .check_limit_detailed(key, cost)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend + 'static> Limiter for RateLimiter<B> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        let (result, bucket) = self
+            .check_limit_detailed(key, cost.into())
+            .await
+            .map_err(|e| e.to_string())?;
+        let retry_after = match result {
+            LimitResult::Allowed => Duration::ZERO,
+            LimitResult::Denied { retry_after } => retry_after,
+        };
+        Ok(LimitCheckResult {
+            allowed: matches!(result, LimitResult::Allowed),
+            retry_after_seconds: retry_after.as_secs_f64().ceil() as u32,
+            remaining_tokens: bucket.map_or(0, |bucket| bucket.remaining),
+            limit: bucket.map_or(0, |bucket| bucket.capacity),
+        })
+    }
+}
+
+/// Rate limits the routes it's applied to; see the [crate docs](crate).
+///
+/// Apply it to a whole `Router` with `layer`, or to single routes with
+/// `route_layer`, each with its own [scope](Self::with_scope) and
+/// [cost](Self::with_cost). Layers stack, so a route under two is checked
+/// against both buckets.
+#[derive(Clone)]
+pub struct GuardianLayer {
+    limiter: Arc<dyn Limiter>,
+    key: Key,
+    scope: Option<String>,
+    cost: u32,
+    fail_open: bool,
+}
+
+impl GuardianLayer {
+    /// Decide through a Guardian service: a
+    /// [`GuardianClient`](guardian_client::GuardianClient), or anything
+    /// else implementing [`GuardianApi`].
+    pub fn new(api: impl GuardianApi + 'static) -> Self {
+        Self::with_limiter(Remote(api))
+    }
+
+    /// Decide in this process, with a `guardian-core` limiter, for apps
+    /// that run no Guardian service. Give each scope a limiter of its own
+    /// for different limits per route.
+    pub fn embedded<B: StorageBackend + 'static>(limiter: RateLimiter<B>) -> Self {
+        Self::with_limiter(limiter)
+    }
+
+    fn with_limiter(limiter: impl Limiter + 'static) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            key: Key::ClientIp,
+            scope: None,
+            cost: 1,
+            fail_open: true,
+        }
+    }
+
+    /// What requests are limited by; [`Key::ClientIp`] unless set.
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Prefix keys with `scope:`, so these routes get buckets (and the
+    /// service's limit rules) of their own.
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Tokens each request costs; 1 unless set.
+    pub fn with_cost(mut self, cost: u32) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    /// Whether to let requests through when the limiter can't decide (the
+    /// default), or answer them with `503 Service Unavailable`.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    fn key_of(&self, req: &Request) -> Option<String> {
+        let key = self.key.of(req)?;
+        Some(match &self.scope {
+            Some(scope) => format!("{}:{}", scope, key),
+            None => key,
+        })
+    }
+}
+
+impl<S> Layer<S> for GuardianLayer {
+    type Service = GuardianService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GuardianService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// A service that checks each request with Guardian before its handler.
+#[derive(Clone)]
+pub struct GuardianService<S> {
+    inner: S,
+    layer: GuardianLayer,
+}
+
+impl<S> Service<Request> for GuardianService<S>
+where
+    S: Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // Keep the service that was polled ready for this request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+        Box::pin(async move {
+            let Some(key) = layer.key_of(&req) else {
+                return inner.call(req).await;
+            };
+            let decision = match layer.limiter.check(&key, layer.cost).await {
+                Ok(decision) => decision,
+                Err(_) if layer.fail_open => return inner.call(req).await,
+                Err(_) => return Ok(StatusCode::SERVICE_UNAVAILABLE.into_response()),
+            };
+            let mut response = if decision.allowed {
+                inner.call(req).await?
+            } else {
+                (StatusCode::TOO_MANY_REQUESTS, "rate limited").into_response()
+            };
+            for (name, value) in decision.to_headers() {
+                if let Ok(value) = HeaderValue::try_from(value) {
+                    response
+                        .headers_mut()
+                        .insert(HeaderName::from_static(name), value);
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use axum::Router;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use guardian_client::{ClientError, MockGuardianClient};
+    use tower::ServiceExt;
+
+    fn app(limit: GuardianLayer) -> Router {
+        Router::new()
+            .route("/", get(|| async { "hello" }))
+            .route(
+                "/search",
+                get(|| async { "results" })
+                    .route_layer(limit.clone().with_scope("search").with_cost(5)),
+            )
+            .layer(limit)
+    }
+
+    fn request(uri: &str, header: Option<(&str, &str)>) -> Request {
+        let mut builder = Request::builder().uri(uri);
+        if let Some((name, value)) = header {
+            builder = builder.header(name, value);
+        }
+        let mut req = builder.body(Body::empty()).unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 4000))));
+        req
+    }
+
+    #[tokio::test]
+    async fn test_limits_by_ip_per_route() {
+        let mock = MockGuardianClient::new();
+        let app = app(GuardianLayer::new(mock.clone()));
+
+        let response = app.clone().oneshot(request("/", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+
+        mock.deny("search:ip:10.0.0.1", 4);
+        let response = app.oneshot(request("/search", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "4");
+
+        let calls: Vec<_> = mock
+            .calls()
+            .into_iter()
+            .map(|call| (call.client_id, call.cost))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                ("ip:10.0.0.1".to_string(), 1),
+                ("ip:10.0.0.1".to_string(), 1),
+                ("search:ip:10.0.0.1".to_string(), 5),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_header_and_jwt_claim_keys() {
+        let mock = MockGuardianClient::new();
+        let by_header = GuardianLayer::new(mock.clone())
+            .with_key(Key::Header(HeaderName::from_static("x-api-key")));
+        let response = app(by_header)
+            .oneshot(request("/", Some(("x-api-key", "k1"))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let payload = URL_SAFE_NO_PAD.encode(r#"{"sub":"user-7","org":42}"#);
+        let bearer = format!("Bearer e30.{}.sig", payload);
+        for claim in ["sub", "org"] {
+            let by_claim = GuardianLayer::new(mock.clone()).with_key(Key::JwtClaim(claim.into()));
+            app(by_claim)
+                .oneshot(request("/", Some(("authorization", &bearer))))
+                .await
+                .unwrap();
+        }
+        // No token: not checked.
+        let by_claim = GuardianLayer::new(mock.clone()).with_key(Key::JwtClaim("sub".into()));
+        app(by_claim).oneshot(request("/", None)).await.unwrap();
+
+        let keys: Vec<_> = mock
+            .calls()
+            .into_iter()
+            .map(|call| call.client_id)
+            .collect();
+        assert_eq!(keys, ["x-api-key:k1", "sub:user-7", "org:42"]);
+    }
+
+    #[tokio::test]
+    async fn test_shared_extractor_keys() {
+        let mock = MockGuardianClient::new();
+        let proxies = TrustedProxies::new(["10.0.0.0/8"]).unwrap();
+        let key = Extractor::route().and(Extractor::client_ip(proxies));
+        let limit = GuardianLayer::new(mock.clone()).with_key(Key::Extractor(key));
+        let app: Router = Router::new()
+            .route("/users/:id", get(|| async { "user" }))
+            .route_layer(limit);
+
+        let forwarded = request("/users/7", Some(("x-forwarded-for", "203.0.113.9")));
+        let response = app.clone().oneshot(forwarded).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        app.oneshot(request("/users/8", None)).await.unwrap();
+
+        let keys: Vec<_> = mock
+            .calls()
+            .into_iter()
+            .map(|call| call.client_id)
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "route:/users/:id:ip:203.0.113.9",
+                "route:/users/:id:ip:10.0.0.1"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fails_open_unless_told_otherwise() {
+        let mock = MockGuardianClient::new();
+        mock.push_response("ip:10.0.0.1", Err(ClientError::DeadlineExceeded));
+        mock.push_response("ip:10.0.0.1", Err(ClientError::DeadlineExceeded));
+        let response = app(GuardianLayer::new(mock.clone()))
+            .oneshot(request("/", None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let closed = GuardianLayer::new(mock).with_fail_open(false);
+        let response = app(closed).oneshot(request("/", None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,52 @@
+// BatchingBackend in front of a store 1ms away (a MemoryBackend behind a
+// ChaosBackend's latency), against checking that store directly.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use guardian_core::{
+    BatchingBackend, ChaosBackend, ChaosConfig, MemoryBackend, StorageBackend, TokenBucketConfig,
+};
+use std::time::Duration;
+
+/// A store with a 1ms round trip, which never runs dry.
+fn remote() -> ChaosBackend<MemoryBackend> {
+    let memory = MemoryBackend::new(TokenBucketConfig {
+        capacity: u64::MAX / 2,
+        refill_rate: 0,
+        refill_interval: Duration::from_secs(1),
+    });
+    ChaosBackend::new(
+        memory,
+        ChaosConfig {
+            latency: Duration::from_millis(1),
+            ..ChaosConfig::default()
+        },
+    )
+}
+
+fn batching(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("batching");
+    group.throughput(Throughput::Elements(1));
+    // Each unbatched check waits out the round trip.
+    group.sample_size(20);
+
+    let direct = remote();
+    group.bench_function("direct", |b| {
+        b.to_async(&runtime).iter(|| direct.take_token("user:1", 1));
+    });
+    for batch_size in [10, 100] {
+        let batched = BatchingBackend::new(remote(), batch_size);
+        group.bench_with_input(
+            BenchmarkId::new("batch_size", batch_size),
+            &batched,
+            |b, batched| {
+                b.to_async(&runtime)
+                    .iter(|| batched.take_token("user:1", 1));
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, batching);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+// MemoryBackend as the number of keys it holds grows: the same checks,
+// spread over more buckets in its map.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use guardian_core::{MemoryBackend, StorageBackend, TokenBucketConfig};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+fn config() -> TokenBucketConfig {
+    TokenBucketConfig {
+        capacity: u64::MAX / 2,
+        refill_rate: 0,
+        refill_interval: Duration::from_secs(1),
+    }
+}
+
+fn key_cardinality(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("memory_backend/keys");
+    group.throughput(Throughput::Elements(1));
+    for keys in [1, 1_000, 100_000] {
+        let names: Vec<String> = (0..keys).map(|i| format!("user:{}", i)).collect();
+        let backend = MemoryBackend::new(config());
+        // Every bucket exists before timing starts.
+        runtime.block_on(async {
+            for name in &names {
+                backend.take_token(name, 1).await.unwrap();
+            }
+        });
+        let next = AtomicUsize::new(0);
+        group.bench_with_input(BenchmarkId::from_parameter(keys), &names, |b, names| {
+            b.to_async(&runtime).iter(|| {
+                let name = &names[next.fetch_add(1, Ordering::Relaxed) % names.len()];
+                backend.take_token(name, 1)
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, key_cardinality);
+criterion_main!(benches);
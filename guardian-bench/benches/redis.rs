@@ -0,0 +1,44 @@
+// Round trips to a real Redis: the bucket script on every check, and the
+// read-only usage lookup. Skipped when Redis can't be reached.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use guardian_core::{StorageBackend, TokenBucketConfig};
+use guardian_redis::RedisBackend;
+use std::time::Duration;
+
+const URL_ENV: &str = "GUARDIAN_BENCH_REDIS_URL";
+
+fn round_trips(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let url = std::env::var(URL_ENV).unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string());
+    let config = TokenBucketConfig {
+        capacity: u64::MAX / 2,
+        refill_rate: 0,
+        refill_interval: Duration::from_secs(1),
+    };
+    let backend = match runtime.block_on(RedisBackend::new(&url, config)) {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("skipping Redis benchmarks, {} unreachable: {}", url, e);
+            return;
+        }
+    };
+
+    let mut group = c.benchmark_group("redis");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("take_token", |b| {
+        b.to_async(&runtime)
+            .iter(|| backend.take_token("guardian-bench:user:1", 1));
+    });
+    group.bench_function("get_usage", |b| {
+        b.to_async(&runtime)
+            .iter(|| backend.get_usage("guardian-bench:user:1"));
+    });
+    group.finish();
+    runtime
+        .block_on(backend.reset("guardian-bench:user:1"))
+        .ok();
+}
+
+criterion_group!(benches, round_trips);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+// TokenBucket under contention: threads taking from one bucket at once, all
+// racing on its compare-and-swap and refill lock.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use guardian_core::{TokenBucket, TokenBucketConfig};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A bucket that never runs dry, so every take succeeds.
+fn bottomless() -> TokenBucket {
+    TokenBucket::new(TokenBucketConfig {
+        capacity: u64::MAX / 2,
+        refill_rate: 0,
+        refill_interval: Duration::from_secs(1),
+    })
+}
+
+fn contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("token_bucket/contention");
+    group.throughput(Throughput::Elements(1));
+    for threads in [1, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                // Time for one thread's takes while `threads` take at once.
+                b.iter_custom(|iters| {
+                    let bucket = Arc::new(bottomless());
+                    let start = Arc::new(Barrier::new(threads + 1));
+                    let workers: Vec<_> = (0..threads)
+                        .map(|_| {
+                            let bucket = Arc::clone(&bucket);
+                            let start = Arc::clone(&start);
+                            thread::spawn(move || {
+                                start.wait();
+                                for _ in 0..iters {
+                                    bucket.try_consume(1).unwrap();
+                                }
+                            })
+                        })
+                        .collect();
+                    start.wait();
+                    let started = Instant::now();
+                    for worker in workers {
+                        worker.join().unwrap();
+                    }
+                    started.elapsed()
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, contention);
+criterion_main!(benches);
@@ -0,0 +1,358 @@
+// Load generation: checks sent to a running service at a fixed rate, over
+// a set of keys and costs, with the latency and outcome of each recorded.
+//
+// Requests are sent on schedule whether or not earlier ones have been
+// answered, and each one's latency is measured from when it was due rather
+// than when it went out, so a service falling behind shows up in the
+// percentiles instead of quietly lowering the rate.
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use guardian_client::proto::rate_limiter_client::RateLimiterClient;
+use guardian_client::proto::CheckLimitRequest;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::hash::BuildHasher;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::{Instant, MissedTickBehavior};
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request};
+
+#[derive(Args, Debug)]
+pub struct LoadArgs {
+    /// Service address.
+    #[arg(long, env = "GUARDIAN_ADDR", default_value = "http://127.0.0.1:50051")]
+    addr: String,
+    /// API key sent as `x-api-key`, for services that require authentication.
+    #[arg(long, env = "GUARDIAN_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+    /// Namespace (tenant) to check in; empty for the default namespace.
+    #[arg(long, env = "GUARDIAN_NAMESPACE", default_value = "")]
+    namespace: String,
+    /// Checks sent per second.
+    #[arg(long, default_value_t = 1000)]
+    qps: u32,
+    /// Seconds to send for.
+    #[arg(long, default_value_t = 30)]
+    duration: u64,
+    /// Distinct keys, each check picking one at random.
+    #[arg(long, default_value_t = 10_000)]
+    keys: u64,
+    /// Prefix of every key, which is followed by the key's number.
+    #[arg(long, default_value = "bench:")]
+    key_prefix: String,
+    /// Tokens each check asks for: a number (`1`), a range picked from
+    /// evenly (`1-10`), or costs with weights (`1:90,20:10`).
+    #[arg(long, default_value = "1")]
+    cost: CostDistribution,
+    /// gRPC connections to spread checks over.
+    #[arg(long, default_value_t = 4)]
+    connections: usize,
+    /// Checks awaiting an answer at once, beyond which sending waits.
+    #[arg(long, default_value_t = 10_000)]
+    max_in_flight: usize,
+    /// Print the report as JSON.
+    #[arg(long)]
+    json: bool,
+}
+
+/// How many tokens checks ask for.
+#[derive(Debug, Clone, PartialEq)]
+enum CostDistribution {
+    Fixed(u32),
+    /// Evenly between the two, both included.
+    Range(u32, u32),
+    /// Each cost with its weight.
+    Weighted(Vec<(u32, u32)>),
+}
+
+impl FromStr for CostDistribution {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let number = |text: &str| {
+            text.trim()
+                .parse::<u32>()
+                .map_err(|_| format!("'{}' is not a number", text.trim()))
+        };
+        if text.contains(':') {
+            let weighted = text
+                .split(',')
+                .map(|entry| {
+                    let (cost, weight) = entry
+                        .split_once(':')
+                        .ok_or_else(|| format!("'{}' is not cost:weight", entry))?;
+                    Ok((number(cost)?, number(weight)?))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            if weighted.iter().all(|&(_, weight)| weight == 0) {
+                return Err("every weight is 0".to_string());
+            }
+            return Ok(CostDistribution::Weighted(weighted));
+        }
+        match text.split_once('-') {
+            Some((low, high)) => {
+                let (low, high) = (number(low)?, number(high)?);
+                if low > high {
+                    return Err(format!("{} is above {}", low, high));
+                }
+                Ok(CostDistribution::Range(low, high))
+            }
+            None => Ok(CostDistribution::Fixed(number(text)?)),
+        }
+    }
+}
+
+impl CostDistribution {
+    fn sample(&self, rng: &mut Rng) -> u32 {
+        match self {
+            CostDistribution::Fixed(cost) => *cost,
+            CostDistribution::Range(low, high) => low + rng.below(u64::from(high - low) + 1) as u32,
+            CostDistribution::Weighted(weighted) => {
+                let total: u64 = weighted.iter().map(|&(_, weight)| u64::from(weight)).sum();
+                let mut pick = rng.below(total);
+                for &(cost, weight) in weighted {
+                    if pick < u64::from(weight) {
+                        return cost;
+                    }
+                    pick -= u64::from(weight);
+                }
+                unreachable!("pick is below the total weight")
+            }
+        }
+    }
+}
+
+/// splitmix64, seeded at random: plenty for picking keys and costs.
+struct Rng(u64);
+
+impl Rng {
+    fn new() -> Self {
+        Self(std::collections::hash_map::RandomState::new().hash_one(()))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Below `n`, which must be positive.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+/// What happened to one check.
+enum Outcome {
+    Allowed,
+    Denied,
+    Failed(Code),
+}
+
+/// The run as a whole. Latencies are in microseconds.
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    sent: u64,
+    seconds: f64,
+    /// Checks answered per second.
+    achieved_qps: f64,
+    allowed: u64,
+    denied: u64,
+    /// Failed checks by gRPC status code.
+    errors: BTreeMap<String, u64>,
+    error_rate: f64,
+    latency_us: Percentiles,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Percentiles {
+    p50: u64,
+    p90: u64,
+    p99: u64,
+    p999: u64,
+    max: u64,
+}
+
+impl Percentiles {
+    fn of(latencies: &mut [u64]) -> Self {
+        if latencies.is_empty() {
+            return Self::default();
+        }
+        latencies.sort_unstable();
+        let at =
+            |p: f64| latencies[((latencies.len() as f64 * p) as usize).min(latencies.len() - 1)];
+        Self {
+            p50: at(0.50),
+            p90: at(0.90),
+            p99: at(0.99),
+            p999: at(0.999),
+            max: latencies[latencies.len() - 1],
+        }
+    }
+}
+
+pub async fn run(args: LoadArgs) -> Result<()> {
+    if args.qps == 0 || args.keys == 0 || args.connections == 0 || args.max_in_flight == 0 {
+        bail!("--qps, --keys, --connections and --max-in-flight must be positive");
+    }
+    let api_key = args
+        .api_key
+        .as_deref()
+        .map(|key| MetadataValue::try_from(key).context("API key is not valid header text"))
+        .transpose()?;
+    let endpoint = Endpoint::from_shared(args.addr.clone()).context("invalid service address")?;
+    let mut channels = Vec::with_capacity(args.connections);
+    for _ in 0..args.connections {
+        // Each connect makes a connection of its own.
+        let channel = endpoint
+            .connect()
+            .await
+            .with_context(|| format!("cannot connect to {}", args.addr))?;
+        channels.push(channel);
+    }
+
+    let report = generate(&args, channels, api_key).await;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+async fn generate(
+    args: &LoadArgs,
+    channels: Vec<Channel>,
+    api_key: Option<MetadataValue<tonic::metadata::Ascii>>,
+) -> Report {
+    let interval = Duration::from_secs(1) / args.qps;
+    let total = args.duration * u64::from(args.qps);
+    let in_flight = Arc::new(Semaphore::new(args.max_in_flight));
+    let (outcomes, mut received) = mpsc::unbounded_channel();
+    let mut rng = Rng::new();
+    let mut ticks = tokio::time::interval(Duration::from_millis(1));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let started = Instant::now();
+    let mut sent = 0;
+    while sent < total {
+        ticks.tick().await;
+        // Everything due by now, however late the tick.
+        let due = (started.elapsed().as_nanos() / interval.as_nanos().max(1)) as u64;
+        while sent < due.min(total) {
+            let scheduled = started + Duration::from_nanos(interval.as_nanos() as u64 * sent);
+            let permit = Arc::clone(&in_flight).acquire_owned().await.unwrap();
+            let channel = channels[sent as usize % channels.len()].clone();
+            let request = CheckLimitRequest {
+                client_id: format!("{}{}", args.key_prefix, rng.below(args.keys)),
+                cost: args.cost.sample(&mut rng),
+                namespace: args.namespace.clone(),
+                ..Default::default()
+            };
+            let api_key = api_key.clone();
+            let outcomes = outcomes.clone();
+            tokio::spawn(async move {
+                let mut request = Request::new(request);
+                if let Some(key) = api_key {
+                    request.metadata_mut().insert("x-api-key", key);
+                }
+                let outcome = match RateLimiterClient::new(channel).check_limit(request).await {
+                    Ok(response) if response.get_ref().allowed => Outcome::Allowed,
+                    Ok(_) => Outcome::Denied,
+                    Err(status) => Outcome::Failed(status.code()),
+                };
+                let _ = outcomes.send((outcome, scheduled.elapsed()));
+                drop(permit);
+            });
+            sent += 1;
+        }
+    }
+    drop(outcomes);
+
+    let mut report = Report {
+        sent,
+        ..Report::default()
+    };
+    let mut latencies = Vec::with_capacity(sent as usize);
+    while let Some((outcome, latency)) = received.recv().await {
+        latencies.push(latency.as_micros() as u64);
+        match outcome {
+            Outcome::Allowed => report.allowed += 1,
+            Outcome::Denied => report.denied += 1,
+            Outcome::Failed(code) => {
+                *report.errors.entry(format!("{:?}", code)).or_default() += 1;
+            }
+        }
+    }
+    report.seconds = started.elapsed().as_secs_f64();
+    report.achieved_qps = latencies.len() as f64 / report.seconds;
+    report.error_rate = report.errors.values().sum::<u64>() as f64 / sent.max(1) as f64;
+    report.latency_us = Percentiles::of(&mut latencies);
+    report
+}
+
+fn print_report(report: &Report) {
+    println!(
+        "sent {} checks in {:.1}s ({:.0}/s answered)",
+        report.sent, report.seconds, report.achieved_qps
+    );
+    println!("allowed {}  denied {}", report.allowed, report.denied);
+    println!("errors {:.2}%", report.error_rate * 100.0);
+    for (code, count) in &report.errors {
+        println!("  {:<20} {}", code, count);
+    }
+    let latency = &report.latency_us;
+    println!(
+        "latency (us)  p50 {}  p90 {}  p99 {}  p99.9 {}  max {}",
+        latency.p50, latency.p90, latency.p99, latency.p999, latency.max
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_and_samples_cost_distributions() {
+        assert_eq!("5".parse(), Ok(CostDistribution::Fixed(5)));
+        assert_eq!("1-10".parse(), Ok(CostDistribution::Range(1, 10)));
+        assert_eq!(
+            "1:90, 20:10".parse(),
+            Ok(CostDistribution::Weighted(vec![(1, 90), (20, 10)]))
+        );
+        assert!("10-1".parse::<CostDistribution>().is_err());
+        assert!("1:0,2:0".parse::<CostDistribution>().is_err());
+        assert!("cheap".parse::<CostDistribution>().is_err());
+
+        let mut rng = Rng::new();
+        let range = CostDistribution::Range(3, 4);
+        let weighted = CostDistribution::Weighted(vec![(1, 0), (7, 1)]);
+        for _ in 0..100 {
+            assert!((3..=4).contains(&range.sample(&mut rng)));
+            assert_eq!(weighted.sample(&mut rng), 7);
+        }
+    }
+
+    #[test]
+    fn test_percentiles_of_latencies() {
+        let mut latencies: Vec<u64> = (1..=1000).rev().collect();
+        assert_eq!(
+            Percentiles::of(&mut latencies),
+            Percentiles {
+                p50: 501,
+                p90: 901,
+                p99: 991,
+                p999: 1000,
+                max: 1000,
+            }
+        );
+        assert_eq!(Percentiles::of(&mut []), Percentiles::default());
+    }
+}
@@ -0,0 +1,262 @@
+//! `guardian-bench`: turns the results of `cargo bench -p guardian-bench`
+//! into one JSON summary per run, and compares two summaries so a run can
+//! be checked against a saved baseline. It also puts a running service
+//! under load, for capacity planning.
+//!
+//! ```text
+//! cargo bench -p guardian-bench
+//! cargo run -p guardian-bench -- summarize > bench.json
+//! cargo run -p guardian-bench -- compare baseline.json bench.json --threshold 10
+//! guardian-bench load --addr http://guardian:50051 --qps 5000 --keys 100000 --cost 1-5
+//! ```
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+mod load;
+
+#[derive(Parser)]
+#[command(version, about = "Summarize and compare Guardian benchmark runs")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the latest result of every benchmark criterion has recorded,
+    /// as JSON.
+    Summarize {
+        /// Criterion's output directory. Defaults to `criterion` under
+        /// `$CARGO_TARGET_DIR`, or under `target`.
+        dir: Option<PathBuf>,
+    },
+    /// Compare two summaries by median time, failing if any benchmark got
+    /// slower by more than the threshold.
+    Compare {
+        baseline: PathBuf,
+        current: PathBuf,
+        /// Slowdown, in percent, that counts as a regression.
+        #[arg(long, default_value_t = 10.0)]
+        threshold: f64,
+    },
+    /// Send checks to a running service at a steady rate, and report how
+    /// they were answered and how long that took.
+    Load(load::LoadArgs),
+}
+
+/// One run: each benchmark's times by its full id (`group/function/input`).
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Summary {
+    benchmarks: BTreeMap<String, Times>,
+}
+
+/// Nanoseconds per iteration.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Times {
+    mean_ns: f64,
+    median_ns: f64,
+    std_dev_ns: f64,
+}
+
+/// The parts of criterion's `benchmark.json` read here.
+#[derive(Deserialize)]
+struct Benchmark {
+    full_id: String,
+}
+
+/// The parts of criterion's `estimates.json` read here.
+#[derive(Deserialize)]
+struct Estimates {
+    mean: Estimate,
+    median: Estimate,
+    std_dev: Estimate,
+}
+
+#[derive(Deserialize)]
+struct Estimate {
+    point_estimate: f64,
+}
+
+/// Every benchmark under `dir`, from the `new` results criterion keeps for
+/// each one's latest run.
+fn summarize(dir: &Path) -> Result<Summary> {
+    let mut summary = Summary::default();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = fs::read_dir(&dir).with_context(|| format!("reading {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            // Reports hold criterion's HTML, not results.
+            if !path.is_dir() || path.ends_with("report") {
+                continue;
+            }
+            let latest = path.join("new");
+            if latest.join("benchmark.json").is_file() {
+                let benchmark: Benchmark = read_json(&latest.join("benchmark.json"))?;
+                let estimates: Estimates = read_json(&latest.join("estimates.json"))?;
+                summary.benchmarks.insert(
+                    benchmark.full_id,
+                    Times {
+                        mean_ns: estimates.mean.point_estimate,
+                        median_ns: estimates.median.point_estimate,
+                        std_dev_ns: estimates.std_dev.point_estimate,
+                    },
+                );
+            } else {
+                pending.push(path);
+            }
+        }
+    }
+    Ok(summary)
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
+    let text = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+}
+
+/// How one benchmark's median moved between two runs.
+#[derive(Debug, PartialEq)]
+struct Change {
+    id: String,
+    baseline_ns: f64,
+    current_ns: f64,
+    /// Percent slower (negative when faster).
+    percent: f64,
+}
+
+/// The benchmarks both runs have, in id order.
+fn compare(baseline: &Summary, current: &Summary) -> Vec<Change> {
+    current
+        .benchmarks
+        .iter()
+        .filter_map(|(id, now)| {
+            let before = baseline.benchmarks.get(id)?;
+            Some(Change {
+                id: id.clone(),
+                baseline_ns: before.median_ns,
+                current_ns: now.median_ns,
+                percent: (now.median_ns / before.median_ns - 1.0) * 100.0,
+            })
+        })
+        .collect()
+}
+
+fn main() -> Result<()> {
+    match Cli::parse().command {
+        Command::Summarize { dir } => {
+            let dir = dir.unwrap_or_else(|| {
+                let target =
+                    std::env::var_os("CARGO_TARGET_DIR").unwrap_or_else(|| "target".into());
+                PathBuf::from(target).join("criterion")
+            });
+            let summary = summarize(&dir)?;
+            if summary.benchmarks.is_empty() {
+                bail!("no benchmark results under {}", dir.display());
+            }
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        }
+        Command::Compare {
+            baseline,
+            current,
+            threshold,
+        } => {
+            let baseline: Summary = read_json(&baseline)?;
+            let current: Summary = read_json(&current)?;
+            let mut regressions = 0;
+            for change in compare(&baseline, &current) {
+                let regressed = change.percent > threshold;
+                regressions += regressed as usize;
+                println!(
+                    "{:<40} {:>12.1} ns -> {:>12.1} ns  {:>+7.1}%{}",
+                    change.id,
+                    change.baseline_ns,
+                    change.current_ns,
+                    change.percent,
+                    if regressed { "  REGRESSED" } else { "" }
+                );
+            }
+            for id in current.benchmarks.keys() {
+                if !baseline.benchmarks.contains_key(id) {
+                    println!("{:<40} new", id);
+                }
+            }
+            if regressions > 0 {
+                bail!(
+                    "{} benchmark(s) slower by more than {}%",
+                    regressions,
+                    threshold
+                );
+            }
+        }
+        Command::Load(args) => tokio::runtime::Runtime::new()?.block_on(load::run(args))?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn times(median_ns: f64) -> Times {
+        Times {
+            mean_ns: median_ns,
+            median_ns,
+            std_dev_ns: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_summarizes_latest_results_and_compares_medians() {
+        let dir = std::env::temp_dir().join(format!("guardian-bench-{}", std::process::id()));
+        let latest = dir
+            .join("batching")
+            .join("batch_size")
+            .join("10")
+            .join("new");
+        fs::create_dir_all(&latest).unwrap();
+        fs::create_dir_all(dir.join("report")).unwrap();
+        fs::write(
+            latest.join("benchmark.json"),
+            r#"{"group_id": "batching", "full_id": "batching/batch_size/10"}"#,
+        )
+        .unwrap();
+        fs::write(
+            latest.join("estimates.json"),
+            r#"{"mean": {"point_estimate": 110.0}, "median": {"point_estimate": 100.0},
+                "std_dev": {"point_estimate": 5.0}}"#,
+        )
+        .unwrap();
+        let current = summarize(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            current.benchmarks["batching/batch_size/10"],
+            Times {
+                mean_ns: 110.0,
+                median_ns: 100.0,
+                std_dev_ns: 5.0,
+            }
+        );
+
+        let baseline = Summary {
+            benchmarks: BTreeMap::from([
+                ("batching/batch_size/10".to_string(), times(80.0)),
+                ("batching/direct".to_string(), times(1000.0)),
+            ]),
+        };
+        assert_eq!(
+            compare(&baseline, &current),
+            [Change {
+                id: "batching/batch_size/10".to_string(),
+                baseline_ns: 80.0,
+                current_ns: 100.0,
+                percent: 25.0,
+            }]
+        );
+    }
+}
@@ -0,0 +1,471 @@
+//! `guardian-cli`: routine operations against a running Guardian service,
+//! so operators don't need to hand-write grpcurl requests.
+//!
+//! ```text
+//! guardian-cli check user:42 --cost 5
+//! guardian-cli --admin-token "$TOKEN" top --by denials
+//! guardian-cli --admin-token "$TOKEN" set-limit 'api:*' --capacity 100 --refill-rate 10
+//! guardian-cli watch user:42 user:43 --below 10
+//! ```
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use guardian_client::proto::{
+    rate_limiter_client::RateLimiterClient, AccessList, Algorithm, CheckLimitRequest,
+    GetDecisionTracesRequest, GetStatsRequest, GetUsageRequest, InspectKeyRequest, KeyRanking,
+    LimitStatus, ListKeysRequest, RateLimitConfig, ResetLimitRequest, SetLimitConfigRequest,
+    StreamLimitRequest, TopKeysRequest,
+};
+use tokio_stream::StreamExt;
+use tonic::metadata::MetadataValue;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Status};
+
+/// Exit status for a denied `check`, so scripts can tell it from errors.
+const DENIED_EXIT_CODE: i32 = 2;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "guardian-cli",
+    version,
+    about = "Admin tool for the Guardian rate limiter"
+)]
+struct Cli {
+    /// Service address.
+    #[arg(long, env = "GUARDIAN_ADDR", default_value = "http://127.0.0.1:50051")]
+    addr: String,
+
+    /// Token for admin operations (reset, inspect, list-keys, top, set-limit).
+    #[arg(
+        long,
+        env = "GUARDIAN_ADMIN_TOKEN",
+        default_value = "",
+        hide_env_values = true
+    )]
+    admin_token: String,
+
+    /// API key sent as `x-api-key`, for services that require authentication.
+    #[arg(long, env = "GUARDIAN_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+
+    /// Namespace (tenant) to act in; empty for the default namespace.
+    #[arg(long, env = "GUARDIAN_NAMESPACE", default_value = "")]
+    namespace: String,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Spend tokens for a client; exits with status 2 when denied.
+    Check {
+        client_id: String,
+        #[arg(long, default_value_t = 1)]
+        cost: u32,
+        /// Tier whose limits apply instead of the client's assigned one.
+        #[arg(long, default_value = "")]
+        tier: String,
+    },
+    /// Show a client's token usage and bucket limits.
+    Usage { client_id: String },
+    /// Refill a client's bucket.
+    Reset { client_id: String },
+    /// Show everything known about a client's bucket: the rule limiting it,
+    /// tokens left, recent decisions and any allow/deny list entry.
+    Inspect { client_id: String },
+    /// List keys holding rate-limit state.
+    ListKeys {
+        /// Key pattern with `*` wildcards.
+        #[arg(default_value = "")]
+        pattern: String,
+        /// Stop after this many keys; 0 lists them all.
+        #[arg(long, default_value_t = 0)]
+        limit: usize,
+    },
+    /// Busiest or most-throttled keys over the recent window.
+    Top {
+        #[arg(long, default_value_t = 10)]
+        limit: u32,
+        #[arg(long, value_enum, default_value_t = Ranking::Usage)]
+        by: Ranking,
+    },
+    /// The node's check totals, backend health and cache hit rate, with
+    /// latency percentiles of storage backend calls over the last minute
+    /// or two.
+    Stats,
+    /// Recent traces kept by the decision recorder, newest first.
+    Traces {
+        /// Only traces of this client.
+        #[arg(default_value = "")]
+        client_id: String,
+        /// Stop after this many traces; 0 shows all that are kept.
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+    },
+    /// Create or replace the limit rule for a key pattern.
+    SetLimit {
+        /// Key pattern with `*` wildcards, or `default`.
+        pattern: String,
+        #[arg(long)]
+        capacity: u64,
+        /// Tokens added per refill interval.
+        #[arg(long)]
+        refill_rate: u64,
+        /// Refill interval in seconds.
+        #[arg(long, default_value_t = 1)]
+        interval: u32,
+        /// Log and count denials without enforcing them.
+        #[arg(long)]
+        shadow: bool,
+    },
+    /// Follow clients' limit status until interrupted.
+    Watch {
+        #[arg(required = true)]
+        client_ids: Vec<String>,
+        /// Seconds between heartbeats re-sending each key's state; 0 uses
+        /// the server default.
+        #[arg(long, default_value_t = 0)]
+        interval: u32,
+        /// Only show updates while a key has fewer tokens than this.
+        #[arg(long, default_value_t = 0)]
+        below: u64,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Ranking {
+    Usage,
+    Denials,
+}
+
+type Client = RateLimiterClient<InterceptedService<Channel, ApiKey>>;
+
+/// Adds the `x-api-key` header to every call when a key is set.
+#[derive(Clone)]
+struct ApiKey(Option<MetadataValue<tonic::metadata::Ascii>>);
+
+impl tonic::service::Interceptor for ApiKey {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(key) = &self.0 {
+            request.metadata_mut().insert("x-api-key", key.clone());
+        }
+        Ok(request)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let api_key = cli
+        .api_key
+        .as_deref()
+        .map(|key| key.parse().context("API key is not valid header text"))
+        .transpose()?;
+    let channel = Endpoint::from_shared(cli.addr.clone())
+        .context("invalid service address")?
+        .connect()
+        .await
+        .with_context(|| format!("cannot connect to {}", cli.addr))?;
+    let mut client = RateLimiterClient::with_interceptor(channel, ApiKey(api_key));
+
+    let code = run(&mut client, &cli).await?;
+    std::process::exit(code);
+}
+
+async fn run(client: &mut Client, cli: &Cli) -> Result<i32> {
+    let namespace = cli.namespace.clone();
+    let admin_token = cli.admin_token.clone();
+    match &cli.command {
+        Command::Check {
+            client_id,
+            cost,
+            tier,
+        } => {
+            let response = client
+                .check_limit(CheckLimitRequest {
+                    client_id: client_id.clone(),
+                    cost: *cost,
+                    override_config: None,
+                    tier: tier.clone(),
+                    namespace,
+                    refundable: false,
+                })
+                .await?
+                .into_inner();
+            if response.allowed {
+                println!("allowed remaining={}", response.remaining_tokens);
+            } else {
+                println!(
+                    "denied retry_after={}s remaining={}",
+                    response.retry_after_seconds, response.remaining_tokens
+                );
+                return Ok(DENIED_EXIT_CODE);
+            }
+        }
+        Command::Usage { client_id } => {
+            let usage = client
+                .get_usage(GetUsageRequest {
+                    client_id: client_id.clone(),
+                    namespace,
+                })
+                .await?
+                .into_inner();
+            println!("used={}", usage.used_tokens);
+            println!("capacity={}", usage.total_capacity);
+            println!("refill_rate={}", usage.refill_rate);
+            println!("last_refill={}", usage.last_refill_timestamp);
+        }
+        Command::Inspect { client_id } => {
+            let key = client
+                .inspect_key(InspectKeyRequest {
+                    client_id: client_id.clone(),
+                    admin_token,
+                    namespace,
+                })
+                .await?
+                .into_inner();
+            println!("rule={}", key.rule);
+            if let Some(config) = &key.config {
+                println!("algorithm={}", config.algorithm().as_str_name());
+                println!("capacity={}", config.capacity);
+                println!(
+                    "refill_rate={}/{}s",
+                    config.refill_rate, config.refill_interval_seconds
+                );
+                if config.shadow {
+                    println!("shadow=true");
+                }
+            }
+            println!("tokens={}", key.tokens);
+            println!("last_refill={}", key.last_refill_timestamp);
+            println!("last_seen={}", key.last_seen_timestamp);
+            for window in &key.recent {
+                println!(
+                    "last_{}s: allowed={} denied={} consumed={}",
+                    window.window_seconds, window.allowed, window.denied, window.tokens_consumed
+                );
+            }
+            if let Some(entry) = &key.access {
+                let list = match entry.list() {
+                    AccessList::Allow => "allow",
+                    _ => "deny",
+                };
+                println!("access={} expires_at={} reason={}", list, entry.expires_at, entry.reason);
+            }
+            println!("node={} backend={}", key.node_id, key.backend);
+        }
+        Command::Reset { client_id } => {
+            let response = client
+                .reset_limit(ResetLimitRequest {
+                    client_id: client_id.clone(),
+                    admin_token,
+                    namespace,
+                })
+                .await?
+                .into_inner();
+            if !response.success {
+                bail!("{}", response.message);
+            }
+            println!("{}", response.message);
+        }
+        Command::ListKeys { pattern, limit } => {
+            let mut printed = 0;
+            let mut page_token = String::new();
+            loop {
+                let page = client
+                    .list_keys(ListKeysRequest {
+                        pattern: pattern.clone(),
+                        page_size: 0,
+                        page_token,
+                        admin_token: admin_token.clone(),
+                        namespace: namespace.clone(),
+                    })
+                    .await?
+                    .into_inner();
+                for key in page.keys {
+                    if *limit != 0 && printed == *limit {
+                        return Ok(0);
+                    }
+                    println!("{}", key);
+                    printed += 1;
+                }
+                if page.next_page_token.is_empty() {
+                    break;
+                }
+                page_token = page.next_page_token;
+            }
+        }
+        Command::Top { limit, by } => {
+            let ranking = match by {
+                Ranking::Usage => KeyRanking::Usage,
+                Ranking::Denials => KeyRanking::Denials,
+            };
+            let top = client
+                .top_keys(TopKeysRequest {
+                    limit: *limit,
+                    ranking: ranking as i32,
+                    admin_token,
+                    namespace,
+                })
+                .await?
+                .into_inner();
+            let width = top
+                .keys
+                .iter()
+                .map(|k| k.client_id.len())
+                .max()
+                .unwrap_or(0)
+                .max(9);
+            println!(
+                "{:<width$}  {:>10}  {:>10}  {:>10}",
+                "CLIENT_ID", "ALLOWED", "DENIED", "TOKENS"
+            );
+            for key in top.keys {
+                println!(
+                    "{:<width$}  {:>10}  {:>10}  {:>10}",
+                    key.client_id, key.allowed, key.denied, key.tokens_consumed
+                );
+            }
+        }
+        Command::Stats => {
+            let stats = client
+                .get_stats(GetStatsRequest { admin_token })
+                .await?
+                .into_inner();
+            println!("uptime={}s", stats.uptime_seconds);
+            println!(
+                "checks={} allowed={} denied={} errors={}",
+                stats.checks, stats.allowed, stats.denied, stats.errors
+            );
+            println!(
+                "allow_rate={:.3} deny_rate={:.3}",
+                stats.allow_rate, stats.deny_rate
+            );
+            println!("active_keys={}", stats.active_keys);
+            println!("backend_healthy={}", stats.backend_healthy);
+            println!(
+                "cache_hits={} cache_misses={} cache_hit_rate={:.3}",
+                stats.cache_hits, stats.cache_misses, stats.cache_hit_rate
+            );
+            println!();
+            println!(
+                "{:<14}  {:<10}  {:>10}  {:>8}  {:>9}  {:>9}  {:>9}",
+                "BACKEND", "OPERATION", "CALLS", "ERRORS", "P50_MS", "P95_MS", "P99_MS"
+            );
+            for op in stats.operations {
+                println!(
+                    "{:<14}  {:<10}  {:>10}  {:>8}  {:>9.3}  {:>9.3}  {:>9.3}",
+                    op.backend, op.operation, op.calls, op.errors, op.p50_ms, op.p95_ms, op.p99_ms
+                );
+            }
+        }
+        Command::Traces { client_id, limit } => {
+            let traces = client
+                .get_decision_traces(GetDecisionTracesRequest {
+                    client_id: client_id.clone(),
+                    limit: *limit,
+                    admin_token,
+                    namespace,
+                })
+                .await?
+                .into_inner();
+            for trace in traces.traces {
+                let tokens = if trace.has_bucket {
+                    format!("{}->{}", trace.tokens_before, trace.tokens_after)
+                } else {
+                    "-".to_string()
+                };
+                println!(
+                    "{} {} cost={} allowed={} reason={} rule={} tokens={} latency={}us{}",
+                    trace.timestamp_ms,
+                    trace.client_id,
+                    trace.cost,
+                    trace.allowed,
+                    trace.reason,
+                    trace.rule,
+                    tokens,
+                    trace.latency_us,
+                    if trace.watched { " watched" } else { "" }
+                );
+            }
+        }
+        Command::SetLimit {
+            pattern,
+            capacity,
+            refill_rate,
+            interval,
+            shadow,
+        } => {
+            let response = client
+                .set_limit_config(SetLimitConfigRequest {
+                    pattern: pattern.clone(),
+                    config: Some(RateLimitConfig {
+                        capacity: *capacity,
+                        refill_rate: *refill_rate,
+                        refill_interval_seconds: *interval,
+                        algorithm: Algorithm::TokenBucket as i32,
+                        shadow: *shadow,
+                    }),
+                    admin_token,
+                    namespace,
+                })
+                .await?
+                .into_inner();
+            if !response.success {
+                bail!("{}", response.message);
+            }
+            println!("{}", response.message);
+        }
+        Command::Watch {
+            client_ids,
+            interval,
+            below,
+        } => {
+            let mut updates = client
+                .stream_limit_status(StreamLimitRequest {
+                    client_id: String::new(),
+                    namespace,
+                    client_ids: client_ids.clone(),
+                    interval_seconds: *interval,
+                    below_tokens: *below,
+                })
+                .await?
+                .into_inner();
+            while let Some(update) = updates.next().await {
+                let update = update?;
+                let status = match update.status() {
+                    LimitStatus::Healthy => "healthy",
+                    LimitStatus::Throttled => "throttled",
+                    LimitStatus::Exhausted => "exhausted",
+                    LimitStatus::Unspecified => "unknown",
+                };
+                println!(
+                    "{} {} {} remaining={}",
+                    update.timestamp, update.client_id, status, update.remaining_tokens
+                );
+            }
+        }
+    }
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition_is_valid() {
+        Cli::command().debug_assert();
+        let cli = Cli::parse_from(["guardian-cli", "top", "--by", "denials", "--limit", "5"]);
+        assert!(matches!(
+            cli.command,
+            Command::Top {
+                limit: 5,
+                by: Ranking::Denials
+            }
+        ));
+    }
+}
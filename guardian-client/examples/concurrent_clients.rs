@@ -1,20 +1,19 @@
 use guardian_client::GuardianClient;
-use std::sync::Arc;
-use tokio::sync::Mutex;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("🛡️  Guardian Concurrent Clients Example\n");
 
     
+    // One client is shared by every task: clones share the connection,
+    // and calls are multiplexed over it without any locking.
     let client = GuardianClient::connect("http://localhost:50051").await?;
-    let client = Arc::new(Mutex::new(client));
 
     let mut handles = vec![];
 
     
     for user_id in 0..10 {
-        let client = Arc::clone(&client);
+        let client = client.clone();
         
         let handle = tokio::spawn(async move {
             let client_id = format!("user_{}", user_id);
@@ -23,13 +22,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             
             for _ in 0..20 {
-                let mut client = client.lock().await;
                 match client.check_limit(&client_id, 1).await {
                     Ok(true) => allowed += 1,
                     Ok(false) => denied += 1,
                     Err(e) => eprintln!("Error for {}: {}", client_id, e),
                 }
-                drop(client); // Release lock
                 
                 tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
             }
@@ -5,7 +5,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("🛡️  Guardian Client Example\n");
 
     println!("Connecting to Guardian service at localhost:50051...");
-    let mut client = GuardianClient::connect("http://localhost:50051").await?;
+    // Reset (example 5) is an admin operation; use the service's admin_token.
+    let admin_token = std::env::var("GUARDIAN_ADMIN_TOKEN").unwrap_or_default();
+    let client = GuardianClient::connect("http://localhost:50051")
+        .await?
+        .with_admin_token(admin_token);
     println!("✅ Connected!\n");
 
 
@@ -0,0 +1,82 @@
+// Key affinity: checks of a key go to the instance a consistent-hash ring
+// over the balanced addresses assigns it to, the same ring an instance
+// with `backends.affinity` builds to pick the keys it decides from memory.
+// While that instance isn't serving, its keys are balanced like any other
+// call and checked on the shared backup instead.
+
+use std::collections::HashMap;
+
+use guardian_core::HashRing;
+use tonic::transport::{Channel, Endpoint};
+
+use crate::balance::Instance;
+use crate::in_process::scoped;
+
+pub(crate) struct Affinity {
+    ring: HashRing,
+    instances: HashMap<String, Instance>,
+}
+
+impl Affinity {
+    /// A ring over `endpoints` by `host:port`, each with its instance, in
+    /// the same order.
+    pub(crate) fn new(endpoints: &[Endpoint], instances: Vec<Instance>) -> Self {
+        let instances: HashMap<String, Instance> = endpoints
+            .iter()
+            .filter_map(|endpoint| Some(endpoint.uri().authority()?.to_string()))
+            .zip(instances)
+            .collect();
+        Self {
+            ring: HashRing::new(instances.keys().cloned()),
+            instances,
+        }
+    }
+
+    /// The channel to the instance owning `client_id` in `namespace`,
+    /// while it is serving.
+    pub(crate) fn route(&self, namespace: &str, client_id: &str) -> Option<Channel> {
+        let owner = &self.instances[self.ring.owner(&scoped(namespace, client_id))?];
+        owner.serving().then(|| owner.channel.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance;
+    use guardian_proto::rate_limiter_server::SERVICE_NAME;
+    use std::time::Duration;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    #[tokio::test]
+    async fn test_routes_keys_to_their_serving_owner() {
+        let (mut reporter, health) = tonic_health::server::health_reporter();
+        reporter
+            .set_service_status(SERVICE_NAME, tonic_health::ServingStatus::Serving)
+            .await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let up = listener.local_addr().unwrap().to_string();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(health)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        // Nothing listens on port 1.
+        let down = "127.0.0.1:1".to_string();
+        let endpoints: Vec<Endpoint> = [&down, &up]
+            .iter()
+            .map(|addr| Endpoint::from_shared(format!("http://{}", addr)).unwrap())
+            .collect();
+        let interval = Duration::from_millis(50);
+        let (_, instances) = balance::channel(endpoints.clone(), interval).await.unwrap();
+        let affinity = Affinity::new(&endpoints, instances);
+
+        let ring = HashRing::new([down.clone(), up.clone()]);
+        for i in 0..100 {
+            let client_id = format!("user{}", i);
+            let owner = ring.owner(&scoped("team-a", &client_id)).unwrap();
+            let routed = affinity.route("team-a", &client_id);
+            assert_eq!(routed.is_some(), owner == up, "{}", client_id);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Pacing for RabbitMQ consumers built on lapin (`amqp` feature): each
+//! delivery is handed out only once its Guardian bucket has the tokens,
+//! and while one waits the channel's prefetch is cut to a single message,
+//! so the broker sends the backlog to other workers instead of this one.
+
+use std::time::Duration;
+
+use lapin::message::Delivery;
+use lapin::options::BasicQosOptions;
+use lapin::types::{AMQPValue, FieldTable};
+use lapin::{Channel, Consumer};
+use tokio_stream::StreamExt;
+
+use crate::api::GuardianApi;
+
+/// What a delivery's bucket is chosen by. Deliveries without one (no such
+/// header) are handed out unchecked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaceKey {
+    /// `amqp:<queue>`, the queue being consumed.
+    Queue,
+    /// `amqp:<routing key>`.
+    RoutingKey,
+    /// A header's string value, as `<name>:<value>`, such as a tenant id.
+    Header(String),
+}
+
+impl PaceKey {
+    fn of(&self, queue: &str, routing_key: &str, headers: Option<&FieldTable>) -> Option<String> {
+        match self {
+            Self::Queue => Some(format!("amqp:{}", queue)),
+            Self::RoutingKey => Some(format!("amqp:{}", routing_key)),
+            Self::Header(name) => {
+                let value = match headers?.inner().get(name.as_str())? {
+                    AMQPValue::LongString(value) => std::str::from_utf8(value.as_bytes()).ok()?,
+                    AMQPValue::ShortString(value) => value.as_str(),
+                    _ => return None,
+                };
+                Some(format!("{}:{}", name, value))
+            }
+        }
+    }
+}
+
+type CostFn = dyn Fn(&Delivery) -> u32 + Send + Sync;
+
+/// A lapin `Consumer` whose [`next`](Self::next) paces deliveries through
+/// Guardian, so every worker sharing a key shares one limit.
+///
+/// It sets the channel's prefetch (as a channel-wide `basic.qos`, so the
+/// change reaches a consumer already running) and should be the only one
+/// doing so on that channel.
+pub struct ThrottledConsumer<A> {
+    channel: Channel,
+    consumer: Consumer,
+    api: A,
+    key: PaceKey,
+    cost: Box<CostFn>,
+    prefetch: u16,
+    fail_open: bool,
+    throttled: bool,
+}
+
+impl<A: GuardianApi> ThrottledConsumer<A> {
+    /// Pace `consumer`, which consumes on `channel`, by its queue, a token
+    /// a delivery. `prefetch` is the prefetch to run at while not
+    /// throttled.
+    pub fn new(channel: Channel, consumer: Consumer, api: A, prefetch: u16) -> Self {
+        Self {
+            channel,
+            consumer,
+            api,
+            key: PaceKey::Queue,
+            cost: Box::new(|_| 1),
+            prefetch,
+            fail_open: true,
+            throttled: false,
+        }
+    }
+
+    /// What deliveries are paced by; [`PaceKey::Queue`] unless set.
+    pub fn with_key(mut self, key: PaceKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Tokens each delivery costs, such as its payload size in KiB.
+    pub fn with_cost(mut self, cost: impl Fn(&Delivery) -> u32 + Send + Sync + 'static) -> Self {
+        self.cost = Box::new(cost);
+        self
+    }
+
+    /// Whether to hand deliveries out when Guardian can't decide (the
+    /// default), or hold them and ask again a second later.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// The next delivery, once its bucket has the tokens; `None` once the
+    /// consumer is cancelled. Ack it as usual when it's processed.
+    pub async fn next(&mut self) -> Option<lapin::Result<Delivery>> {
+        let delivery = match self.consumer.next().await? {
+            Ok(delivery) => delivery,
+            Err(e) => return Some(Err(e)),
+        };
+        while let Some(wait) = self.admit(&delivery).await {
+            if !self.throttled {
+                if let Err(e) = self.set_prefetch(1).await {
+                    return Some(Err(e));
+                }
+                self.throttled = true;
+            }
+            tokio::time::sleep(wait).await;
+        }
+        if self.throttled {
+            if let Err(e) = self.set_prefetch(self.prefetch).await {
+                return Some(Err(e));
+            }
+            self.throttled = false;
+        }
+        Some(Ok(delivery))
+    }
+
+    async fn set_prefetch(&self, prefetch: u16) -> lapin::Result<()> {
+        let options = BasicQosOptions { global: true };
+        self.channel.basic_qos(prefetch, options).await
+    }
+
+    /// `None` if `delivery` may be handed out, or how long to hold it.
+    async fn admit(&self, delivery: &Delivery) -> Option<Duration> {
+        let key = self.key.of(
+            self.consumer.queue().as_str(),
+            delivery.routing_key.as_str(),
+            delivery.properties.headers().as_ref(),
+        )?;
+        match self
+            .api
+            .check_limit_detailed(&key, (self.cost)(delivery))
+            .await
+        {
+            Ok(decision) if decision.allowed => None,
+            Ok(decision) => Some(Duration::from_secs(
+                decision.retry_after_seconds.max(1).into(),
+            )),
+            Err(_) if self.fail_open => None,
+            Err(_) => Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lapin::types::{LongString, ShortString};
+
+    #[test]
+    fn test_pace_keys() {
+        let mut headers = FieldTable::default();
+        headers.insert(
+            ShortString::from("tenant"),
+            AMQPValue::LongString(LongString::from("acme")),
+        );
+        headers.insert(ShortString::from("attempt"), AMQPValue::LongInt(2));
+
+        let keys: Vec<_> = [
+            PaceKey::Queue,
+            PaceKey::RoutingKey,
+            PaceKey::Header("tenant".to_string()),
+            PaceKey::Header("attempt".to_string()),
+            PaceKey::Header("region".to_string()),
+        ]
+        .iter()
+        .map(|key| key.of("emails", "send.welcome", Some(&headers)))
+        .collect();
+        assert_eq!(
+            keys,
+            [
+                Some("amqp:emails".to_string()),
+                Some("amqp:send.welcome".to_string()),
+                Some("tenant:acme".to_string()),
+                None,
+                None,
+            ]
+        );
+    }
+}
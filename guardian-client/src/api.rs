@@ -0,0 +1,52 @@
+use async_trait::async_trait;
+
+use crate::client::{GuardianClient, LimitCheckResult};
+use crate::error::Result;
+
+/// The calls application code usually makes against Guardian, as a trait,
+/// so code that takes `&dyn GuardianApi` (or `impl GuardianApi`) can be
+/// handed a [`GuardianClient`] in production and, with the `test-util`
+/// feature, a `MockGuardianClient` in tests.
+#[async_trait]
+pub trait GuardianApi: Send + Sync {
+    async fn check_limit_detailed(&self, client_id: &str, cost: u32) -> Result<LimitCheckResult>;
+
+    async fn check_limit(&self, client_id: &str, cost: u32) -> Result<bool> {
+        Ok(self.check_limit_detailed(client_id, cost).await?.allowed)
+    }
+
+    async fn check_limit_for_tier(&self, client_id: &str, tier: &str, cost: u32) -> Result<bool>;
+
+    async fn check_limits(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>>;
+
+    async fn get_usage(&self, client_id: &str) -> Result<u64>;
+
+    async fn reset_limit(&self, client_id: &str) -> Result<()>;
+}
+
+#[async_trait]
+impl GuardianApi for GuardianClient {
+    async fn check_limit_detailed(&self, client_id: &str, cost: u32) -> Result<LimitCheckResult> {
+        GuardianClient::check_limit_detailed(self, client_id, cost).await
+    }
+
+    async fn check_limit(&self, client_id: &str, cost: u32) -> Result<bool> {
+        GuardianClient::check_limit(self, client_id, cost).await
+    }
+
+    async fn check_limit_for_tier(&self, client_id: &str, tier: &str, cost: u32) -> Result<bool> {
+        GuardianClient::check_limit_for_tier(self, client_id, tier, cost).await
+    }
+
+    async fn check_limits(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>> {
+        GuardianClient::check_limits(self, checks).await
+    }
+
+    async fn get_usage(&self, client_id: &str) -> Result<u64> {
+        GuardianClient::get_usage(self, client_id).await
+    }
+
+    async fn reset_limit(&self, client_id: &str) -> Result<()> {
+        GuardianClient::reset_limit(self, client_id).await
+    }
+}
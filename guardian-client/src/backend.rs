@@ -0,0 +1,259 @@
+use async_trait::async_trait;
+use guardian_core::{Lease, RateLimitError, StorageBackend};
+use std::time::{Duration, UNIX_EPOCH};
+use tonic::transport::Channel;
+use tonic::Request;
+
+use crate::error::ClientError;
+use crate::proto::{
+    rate_limiter_client::RateLimiterClient, AcquireLeaseRequest, CheckLimitRequest,
+    GetUsageRequest, ListLeasesRequest, RenewLeaseRequest, ResetLimitRequest, ReturnLeaseRequest,
+};
+
+/// `StorageBackend` that defers every decision to another Guardian service.
+///
+/// Lets an edge `RateLimiter` (or a `BatchingBackend` or `LeasingBackend`
+/// in front of this one) use a regional aggregator as its source of truth,
+/// for hierarchical or federated deployments.
+///
+/// Refunds aren't supported: the upstream service only gives tokens back
+/// against the grant of the check that took them, which
+/// `StorageBackend::refund` has no way to name.
+#[derive(Clone)]
+pub struct RemoteGuardianBackend {
+    inner: RateLimiterClient<Channel>,
+    admin_token: String,
+    namespace: String,
+}
+
+impl RemoteGuardianBackend {
+    /// Connect to the upstream Guardian service at the given endpoint.
+    pub async fn connect<D>(dst: D) -> Result<Self, ClientError>
+    where
+        D: TryInto<tonic::transport::Endpoint>,
+        D::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let endpoint = dst.try_into().map_err(|e| {
+            ClientError::ConnectionError(format!("Invalid endpoint: {:?}", e.into()))
+        })?;
+
+        let channel = endpoint
+            .connect()
+            .await
+            .map_err(|e| ClientError::ConnectionError(e.to_string()))?;
+
+        Ok(Self::from_channel(channel))
+    }
+
+    /// Connect to the upstream Guardian service on a Unix domain socket.
+    #[cfg(unix)]
+    pub async fn connect_unix(path: impl AsRef<std::path::Path>) -> Result<Self, ClientError> {
+        Ok(Self::from_channel(
+            crate::client::unix_channel(path.as_ref()).await?,
+        ))
+    }
+
+    /// Use an already configured channel (TLS, timeouts, lazy connect).
+    pub fn from_channel(channel: Channel) -> Self {
+        Self {
+            inner: RateLimiterClient::new(channel),
+            admin_token: String::new(),
+            namespace: String::new(),
+        }
+    }
+
+    /// Admin token sent with `reset` and `leases` calls.
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = token.into();
+        self
+    }
+
+    /// Upstream namespace the keys are kept in.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+}
+
+#[async_trait]
+impl StorageBackend for RemoteGuardianBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        let cost = u32::try_from(cost).map_err(|_| {
+            RateLimitError::ConfigError(format!("cost {} exceeds the remote API limit", cost))
+        })?;
+
+        let request = Request::new(CheckLimitRequest {
+            client_id: key.to_string(),
+            cost,
+            override_config: None,
+            tier: String::new(),
+            namespace: self.namespace.clone(),
+            refundable: false,
+        });
+
+        let response = self
+            .inner
+            .clone()
+            .check_limit(request)
+            .await
+            .map_err(remote_error)?;
+
+        Ok(response.into_inner().allowed)
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let request = Request::new(GetUsageRequest {
+            client_id: key.to_string(),
+            namespace: self.namespace.clone(),
+        });
+
+        let response = self
+            .inner
+            .clone()
+            .get_usage(request)
+            .await
+            .map_err(remote_error)?;
+
+        Ok(response.into_inner().used_tokens)
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let request = Request::new(ResetLimitRequest {
+            client_id: key.to_string(),
+            admin_token: self.admin_token.clone(),
+            namespace: self.namespace.clone(),
+        });
+
+        let response = self
+            .inner
+            .clone()
+            .reset_limit(request)
+            .await
+            .map_err(remote_error)?
+            .into_inner();
+
+        if response.success {
+            Ok(())
+        } else {
+            Err(RateLimitError::StorageError(format!(
+                "Remote reset failed: {}",
+                response.message
+            )))
+        }
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        let request = Request::new(AcquireLeaseRequest {
+            client_id: key.to_string(),
+            namespace: self.namespace.clone(),
+            tier: String::new(),
+            lease_id: id,
+            tokens,
+            ttl_ms: ttl_millis(ttl),
+        });
+
+        let response = self
+            .inner
+            .clone()
+            .acquire_lease(request)
+            .await
+            .map_err(remote_error)?
+            .into_inner();
+
+        Ok(response
+            .granted
+            .then(|| lease(id, response.tokens, response.expires_at_ms)))
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        let request = Request::new(RenewLeaseRequest {
+            client_id: key.to_string(),
+            namespace: self.namespace.clone(),
+            tier: String::new(),
+            lease_id: id,
+            ttl_ms: ttl_millis(ttl),
+        });
+
+        let response = self
+            .inner
+            .clone()
+            .renew_lease(request)
+            .await
+            .map_err(remote_error)?
+            .into_inner();
+
+        Ok(response
+            .renewed
+            .then(|| lease(id, response.tokens, response.expires_at_ms)))
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        let request = Request::new(ReturnLeaseRequest {
+            client_id: key.to_string(),
+            namespace: self.namespace.clone(),
+            tier: String::new(),
+            lease_id: id,
+            unused,
+        });
+
+        let response = self
+            .inner
+            .clone()
+            .return_lease(request)
+            .await
+            .map_err(remote_error)?;
+
+        Ok(response.into_inner().returned)
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        let request = Request::new(ListLeasesRequest {
+            client_id: key.to_string(),
+            admin_token: self.admin_token.clone(),
+            namespace: self.namespace.clone(),
+            tier: String::new(),
+        });
+
+        let response = self
+            .inner
+            .clone()
+            .list_leases(request)
+            .await
+            .map_err(remote_error)?;
+
+        Ok(response
+            .into_inner()
+            .leases
+            .into_iter()
+            .map(|l| lease(l.lease_id, l.tokens, l.expires_at_ms))
+            .collect())
+    }
+}
+
+/// `ttl` for the wire; never 0, which would ask for the server's default.
+fn ttl_millis(ttl: Duration) -> u32 {
+    u32::try_from(ttl.as_millis()).unwrap_or(u32::MAX).max(1)
+}
+
+fn lease(id: u64, tokens: u64, expires_at_ms: i64) -> Lease {
+    Lease {
+        id,
+        tokens,
+        expires_at: UNIX_EPOCH + Duration::from_millis(expires_at_ms.max(0) as u64),
+    }
+}
+
+fn remote_error(status: tonic::Status) -> RateLimitError {
+    RateLimitError::StorageError(format!("Remote Guardian error: {}", status))
+}
@@ -0,0 +1,188 @@
+// Client-side load balancing over several service instances. Calls are
+// spread by tonic's balancer over the instances currently in its set, and
+// a background probe per instance takes it out of the set while its
+// grpc.health.v1 status isn't SERVING (down, or draining for a restart)
+// and puts it back once it is. Each instance's own channel and latest
+// health are kept too, for hedged checks aimed at a particular instance.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use guardian_proto::rate_limiter_server::SERVICE_NAME;
+use tokio::sync::mpsc::Sender;
+use tokio::task::JoinSet;
+use tonic::transport::{Channel, Endpoint};
+use tonic_health::pb::health_check_response::ServingStatus;
+use tonic_health::pb::health_client::HealthClient;
+use tonic_health::pb::HealthCheckRequest;
+use tower_discover::discover::Change;
+
+use crate::error::{ClientError, Result};
+
+/// How often each instance's health is checked by default.
+pub const DEFAULT_HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// One of the balanced instances, on its own.
+#[derive(Clone)]
+pub(crate) struct Instance {
+    pub(crate) channel: Channel,
+    serving: Arc<AtomicBool>,
+}
+
+impl Instance {
+    /// Whether the instance answered its latest probe as SERVING.
+    pub(crate) fn serving(&self) -> bool {
+        self.serving.load(Ordering::Relaxed)
+    }
+}
+
+/// A channel balanced over `endpoints`, and each instance on its own, in
+/// the order given. Only instances that answer their first probe as
+/// SERVING start in the set; it is an error if none do.
+pub(crate) async fn channel(
+    endpoints: Vec<Endpoint>,
+    interval: Duration,
+) -> Result<(Channel, Vec<Instance>)> {
+    if endpoints.is_empty() {
+        return Err(ClientError::ConfigError(
+            "no endpoints to balance over".to_string(),
+        ));
+    }
+    let total = endpoints.len();
+    let (channel, changes) = Channel::balance_channel(total);
+
+    let mut first = JoinSet::new();
+    for (key, endpoint) in endpoints.into_iter().enumerate() {
+        first.spawn(async move {
+            let channel = endpoint.connect_lazy();
+            let mut health = HealthClient::new(channel.clone());
+            let serving = probe(&mut health, interval).await;
+            (key, endpoint, channel, health, serving)
+        });
+    }
+    let mut serving = 0;
+    let mut instances = Vec::with_capacity(total);
+    while let Some(done) = first.join_next().await {
+        let Ok((key, endpoint, channel, health, up)) = done else {
+            continue;
+        };
+        if up {
+            serving += 1;
+            let _ = changes.send(Change::Insert(key, endpoint.clone())).await;
+        }
+        let instance = Instance {
+            channel,
+            serving: Arc::new(AtomicBool::new(up)),
+        };
+        instances.push((key, instance.clone()));
+        tokio::spawn(watch(key, endpoint, health, instance, interval, changes.clone()));
+    }
+    if serving == 0 {
+        return Err(ClientError::ConnectionError(format!(
+            "none of the {} endpoints is serving",
+            total
+        )));
+    }
+    instances.sort_by_key(|(key, _)| *key);
+    Ok((channel, instances.into_iter().map(|(_, instance)| instance).collect()))
+}
+
+/// Probe one instance every `interval`, adding it to or removing it from
+/// the balancer as its health changes. Ends once the balancer is gone.
+async fn watch(
+    key: usize,
+    endpoint: Endpoint,
+    mut health: HealthClient<Channel>,
+    instance: Instance,
+    interval: Duration,
+    changes: Sender<Change<usize, Endpoint>>,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if changes.is_closed() {
+            return;
+        }
+        let up = probe(&mut health, interval).await;
+        if up == instance.serving.swap(up, Ordering::Relaxed) {
+            continue;
+        }
+        let change = if up {
+            Change::Insert(key, endpoint.clone())
+        } else {
+            Change::Remove(key)
+        };
+        if changes.send(change).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Whether the instance reports the rate limiter as SERVING within
+/// `timeout`.
+pub(crate) async fn probe(health: &mut HealthClient<Channel>, timeout: Duration) -> bool {
+    let request = HealthCheckRequest {
+        service: SERVICE_NAME.to_string(),
+    };
+    match tokio::time::timeout(timeout, health.check(request)).await {
+        Ok(Ok(response)) => response.into_inner().status() == ServingStatus::Serving,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    #[tokio::test]
+    async fn test_routes_only_to_serving_endpoints() {
+        let interval = Duration::from_millis(50);
+        let empty = channel(Vec::new(), interval).await;
+        assert!(matches!(empty, Err(ClientError::ConfigError(_))));
+        // Nothing listens on port 1.
+        let down = Endpoint::from_static("http://127.0.0.1:1");
+        let none_serving = channel(vec![down.clone(), down.clone()], interval).await;
+        assert!(matches!(none_serving, Err(ClientError::ConnectionError(_))));
+
+        let (mut reporter, health) = tonic_health::server::health_reporter();
+        reporter
+            .set_service_status(SERVICE_NAME, tonic_health::ServingStatus::Serving)
+            .await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let up =
+            Endpoint::from_shared(format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(health)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let (balanced, instances) = channel(vec![down, up], interval).await.unwrap();
+        assert!(!instances[0].serving() && instances[1].serving());
+        let balanced = HealthClient::new(balanced);
+        let check = || {
+            let mut client = balanced.clone();
+            async move {
+                let request = HealthCheckRequest {
+                    service: SERVICE_NAME.to_string(),
+                };
+                tokio::time::timeout(Duration::from_millis(200), client.check(request)).await
+            }
+        };
+        assert!(check().await.unwrap().is_ok());
+
+        // Draining: no endpoint is left to take the call.
+        reporter
+            .set_service_status(SERVICE_NAME, tonic_health::ServingStatus::NotServing)
+            .await;
+        tokio::time::sleep(interval * 3).await;
+        assert!(check().await.is_err());
+
+        reporter
+            .set_service_status(SERVICE_NAME, tonic_health::ServingStatus::Serving)
+            .await;
+        tokio::time::sleep(interval * 3).await;
+        assert!(check().await.unwrap().is_ok());
+    }
+}
@@ -0,0 +1,416 @@
+// A synchronous client for applications without an async runtime of their
+// own: CLIs, thread-per-request servers, FFI hosts. It wraps the async
+// `GuardianClient` and a small Tokio runtime that runs its calls and
+// background work (health probes, refunds, status subscriptions).
+
+// Every method returns the async client's `Result`.
+#![allow(clippy::result_large_err)]
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use guardian_core::TokenBucketConfig;
+use tokio::runtime::Runtime;
+use tokio::sync::watch;
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::Endpoint;
+
+use crate::builder::GuardianClientBuilder;
+use crate::client::LimitCheckResult;
+use crate::config::Fallback;
+use crate::denials::DenialCache;
+use crate::error::{ClientError, Result};
+use crate::health::ChannelHealth;
+use crate::observer::ClientObserver;
+use crate::proto::{KeyActivity, KeyRanking, LimitStatusUpdate, RateLimitConfig};
+use crate::retry::ReconnectPolicy;
+
+/// Blocking counterpart of [`crate::GuardianClient`], with the same
+/// methods minus the `async`.
+///
+/// The client owns a runtime with one worker thread, shared by its
+/// clones. Its methods must not be called from inside an async runtime,
+/// where blocking would stall other tasks; Tokio panics if they are.
+///
+/// # Examples
+///
+/// ```no_run
+/// use guardian_client::blocking::GuardianClient;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let client = GuardianClient::connect("http://localhost:50051")?;
+///     if client.check_limit("user123", 1)? {
+///         println!("Request allowed!");
+///     }
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct GuardianClient {
+    inner: crate::GuardianClient,
+    runtime: Arc<Runtime>,
+}
+
+impl GuardianClient {
+    /// Connect to a Guardian service at the given endpoint.
+    pub fn connect<D>(dst: D) -> Result<Self>
+    where
+        D: TryInto<Endpoint>,
+        D::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        Self::connect_with(crate::GuardianClient::builder(), dst)
+    }
+
+    /// Connect to `dst` with the settings of `builder`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::blocking;
+    /// # use std::time::Duration;
+    /// # fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let builder = guardian_client::GuardianClient::builder()
+    ///     .api_key("checkout-service-key")
+    ///     .timeout(Duration::from_millis(50));
+    /// let client = blocking::GuardianClient::connect_with(builder, "http://localhost:50051")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connect_with<D>(builder: GuardianClientBuilder, dst: D) -> Result<Self>
+    where
+        D: TryInto<Endpoint>,
+        D::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(builder.connect(dst))?;
+        Ok(Self::new(inner, runtime))
+    }
+
+    /// See [`crate::GuardianClient::connect_balanced`].
+    pub fn connect_balanced<D>(endpoints: &[D]) -> Result<Self>
+    where
+        D: TryInto<Endpoint> + Clone,
+        D::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::GuardianClient::connect_balanced(endpoints))?;
+        Ok(Self::new(inner, runtime))
+    }
+
+    /// See [`crate::GuardianClient::connect_unix`].
+    #[cfg(unix)]
+    pub fn connect_unix(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::GuardianClient::connect_unix(path))?;
+        Ok(Self::new(inner, runtime))
+    }
+
+    /// See [`crate::GuardianClient::from_env`].
+    pub fn from_env() -> Result<Self> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::GuardianClient::from_env())?;
+        Ok(Self::new(inner, runtime))
+    }
+
+    /// See [`crate::GuardianClient::from_config`].
+    pub fn from_config(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::GuardianClient::from_config(path))?;
+        Ok(Self::new(inner, runtime))
+    }
+
+    /// See [`crate::GuardianClient::in_process`]. The emulated service
+    /// runs on the client's runtime.
+    pub fn in_process(config: TokenBucketConfig) -> Result<Self> {
+        let runtime = runtime()?;
+        let inner = {
+            let _entered = runtime.enter();
+            crate::GuardianClient::in_process(config)
+        };
+        Ok(Self::new(inner, runtime))
+    }
+
+    fn new(inner: crate::GuardianClient, runtime: Runtime) -> Self {
+        Self {
+            inner,
+            runtime: Arc::new(runtime),
+        }
+    }
+
+    /// See [`crate::GuardianClient::with_admin_token`].
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.inner = self.inner.with_admin_token(token);
+        self
+    }
+
+    /// See [`crate::GuardianClient::with_namespace`].
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.inner = self.inner.with_namespace(namespace);
+        self
+    }
+
+    /// See [`crate::GuardianClient::with_reconnect`].
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.inner = self.inner.with_reconnect(policy);
+        self
+    }
+
+    /// See [`crate::GuardianClient::with_timeout`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.inner = self.inner.with_timeout(timeout);
+        self
+    }
+
+    /// See [`crate::GuardianClient::with_denial_cache`].
+    pub fn with_denial_cache(mut self) -> Self {
+        self.inner = self.inner.with_denial_cache();
+        self
+    }
+
+    /// See [`crate::GuardianClient::with_shadow_mode`].
+    pub fn with_shadow_mode(mut self) -> Self {
+        self.inner = self.inner.with_shadow_mode();
+        self
+    }
+
+    /// See [`crate::GuardianClient::with_fallback`].
+    pub fn with_fallback(mut self, fallback: Fallback) -> Self {
+        self.inner = self.inner.with_fallback(fallback);
+        self
+    }
+
+    /// See [`crate::GuardianClient::with_trace_context`].
+    #[cfg(feature = "opentelemetry")]
+    pub fn with_trace_context(mut self) -> Self {
+        self.inner = self.inner.with_trace_context();
+        self
+    }
+
+    /// See [`crate::GuardianClient::with_coalescing`].
+    pub fn with_coalescing(mut self, window: Duration) -> Self {
+        self.inner = self.inner.with_coalescing(window);
+        self
+    }
+
+    /// See [`crate::GuardianClient::with_observer`].
+    pub fn with_observer(mut self, observer: Arc<dyn ClientObserver>) -> Self {
+        self.inner = self.inner.with_observer(observer);
+        self
+    }
+
+    pub fn denial_cache(&self) -> Option<&DenialCache> {
+        self.inner.denial_cache()
+    }
+
+    pub fn health(&self) -> ChannelHealth {
+        self.inner.health()
+    }
+
+    pub fn check_limit(&self, client_id: &str, cost: u32) -> Result<bool> {
+        self.runtime
+            .block_on(self.inner.check_limit(client_id, cost))
+    }
+
+    pub fn check_limit_with_deadline(
+        &self,
+        client_id: &str,
+        cost: u32,
+        deadline: Instant,
+    ) -> Result<bool> {
+        self.runtime.block_on(
+            self.inner
+                .check_limit_with_deadline(client_id, cost, deadline),
+        )
+    }
+
+    pub fn check_limit_for_tier(&self, client_id: &str, tier: &str, cost: u32) -> Result<bool> {
+        self.runtime
+            .block_on(self.inner.check_limit_for_tier(client_id, tier, cost))
+    }
+
+    pub fn check_limit_detailed(&self, client_id: &str, cost: u32) -> Result<LimitCheckResult> {
+        self.runtime
+            .block_on(self.inner.check_limit_detailed(client_id, cost))
+    }
+
+    /// See [`crate::GuardianClient::acquire`]; the calling thread sleeps
+    /// while it waits.
+    pub fn acquire(
+        &self,
+        client_id: &str,
+        cost: u32,
+        max_wait: Duration,
+    ) -> Result<LimitCheckResult> {
+        self.runtime
+            .block_on(self.inner.acquire(client_id, cost, max_wait))
+    }
+
+    /// See [`crate::GuardianClient::try_acquire`].
+    pub fn try_acquire(&self, client_id: &str, cost: u32) -> Result<Option<Permit>> {
+        let permit = self
+            .runtime
+            .block_on(self.inner.try_acquire(client_id, cost))?;
+        Ok(permit.map(|permit| Permit {
+            inner: Some(permit),
+            runtime: Arc::clone(&self.runtime),
+        }))
+    }
+
+    pub fn check_limits(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>> {
+        self.runtime.block_on(self.inner.check_limits(checks))
+    }
+
+    pub fn check_limit_batch(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>> {
+        self.runtime.block_on(self.inner.check_limit_batch(checks))
+    }
+
+    pub fn check_all_or_nothing(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>> {
+        self.runtime.block_on(self.inner.check_all_or_nothing(checks))
+    }
+
+    /// See [`crate::GuardianClient::subscribe_status`]. Each call to
+    /// `next` blocks until the next update.
+    pub fn subscribe_status(&self, client_id: &str) -> StatusUpdates {
+        let _runtime = self.runtime.enter();
+        StatusUpdates {
+            updates: Box::pin(self.inner.subscribe_status(client_id)),
+            runtime: Arc::clone(&self.runtime),
+        }
+    }
+
+    pub fn get_usage(&self, client_id: &str) -> Result<u64> {
+        self.runtime.block_on(self.inner.get_usage(client_id))
+    }
+
+    /// See [`crate::GuardianClient::watch_usage`]. The receiver's
+    /// `borrow` and `has_changed` don't block.
+    pub fn watch_usage(&self, client_id: &str, interval: Duration) -> watch::Receiver<u64> {
+        let _runtime = self.runtime.enter();
+        self.inner.watch_usage(client_id, interval)
+    }
+
+    pub fn reset_limit(&self, client_id: &str) -> Result<()> {
+        self.runtime.block_on(self.inner.reset_limit(client_id))
+    }
+
+    pub fn bulk_reset(&self, pattern: &str) -> Result<usize> {
+        self.runtime.block_on(self.inner.bulk_reset(pattern))
+    }
+
+    pub fn set_limit_config(&self, pattern: &str, config: RateLimitConfig) -> Result<()> {
+        self.runtime.block_on(self.inner.set_limit_config(pattern, config))
+    }
+
+    pub fn delete_limit_config(&self, pattern: &str) -> Result<()> {
+        self.runtime.block_on(self.inner.delete_limit_config(pattern))
+    }
+
+    pub fn list_keys(&self, pattern: &str) -> Result<Vec<String>> {
+        self.runtime.block_on(self.inner.list_keys(pattern))
+    }
+
+    pub fn top_keys(&self, limit: u32, ranking: KeyRanking) -> Result<Vec<KeyActivity>> {
+        self.runtime.block_on(self.inner.top_keys(limit, ranking))
+    }
+
+    pub fn ban(&self, client_id: &str, ttl: Option<Duration>, reason: &str) -> Result<()> {
+        self.runtime.block_on(self.inner.ban(client_id, ttl, reason))
+    }
+
+    pub fn unban(&self, client_id: &str) -> Result<bool> {
+        self.runtime.block_on(self.inner.unban(client_id))
+    }
+
+    /// Run `f` only if the rate limit allows.
+    pub fn with_rate_limit<F, T>(&self, client_id: &str, cost: u32, f: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        if self.check_limit(client_id, cost)? {
+            f()
+        } else {
+            Err(ClientError::RateLimited)
+        }
+    }
+}
+
+/// Blocking counterpart of [`crate::Permit`]: dropped without
+/// [`commit`](Self::commit), it refunds its tokens in the background.
+#[must_use = "dropping a Permit refunds its tokens; call commit() to keep them spent"]
+#[derive(Debug)]
+pub struct Permit {
+    inner: Option<crate::Permit>,
+    runtime: Arc<Runtime>,
+}
+
+impl Permit {
+    pub fn client_id(&self) -> &str {
+        self.inner.as_ref().map_or("", |permit| permit.client_id())
+    }
+
+    pub fn cost(&self) -> u32 {
+        self.inner.as_ref().map_or(0, |permit| permit.cost())
+    }
+
+    /// Keep the tokens spent.
+    pub fn commit(mut self) {
+        if let Some(permit) = self.inner.take() {
+            permit.commit();
+        }
+    }
+
+    /// See [`crate::Permit::refund`].
+    pub fn refund(mut self) -> Result<()> {
+        match self.inner.take() {
+            Some(permit) => self.runtime.block_on(permit.refund()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        // The async permit refunds on whichever runtime it is dropped in.
+        let _runtime = self.runtime.enter();
+        drop(self.inner.take());
+    }
+}
+
+/// Limit status updates from
+/// [`GuardianClient::subscribe_status`]; see
+/// [`crate::GuardianClient::subscribe_status`].
+pub struct StatusUpdates {
+    updates: std::pin::Pin<Box<dyn Stream<Item = Result<LimitStatusUpdate>> + Send>>,
+    runtime: Arc<Runtime>,
+}
+
+impl Iterator for StatusUpdates {
+    type Item = Result<LimitStatusUpdate>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.updates.next())
+    }
+}
+
+/// The runtime behind a blocking client: one worker, so background tasks
+/// keep running between calls.
+fn runtime() -> Result<Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("guardian-client")
+        .enable_all()
+        .build()
+        .map_err(|e| ClientError::ConfigError(format!("failed to start a runtime: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks_without_a_runtime_of_its_own() {
+        // Nothing listens on port 1.
+        let refused = GuardianClient::connect("http://127.0.0.1:1");
+        assert!(matches!(refused, Err(ClientError::ConnectionError(_))));
+    }
+}
@@ -0,0 +1,433 @@
+use std::time::Duration;
+
+use tonic::metadata::{AsciiMetadataKey, AsciiMetadataValue, MetadataMap};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+
+use crate::affinity::Affinity;
+use crate::balance;
+use crate::client::GuardianClient;
+use crate::config::Fallback;
+use crate::credentials::{
+    CallCredentials, Credentials, Outbound, API_KEY_HEADER, AUTHORIZATION_HEADER,
+};
+use crate::error::{ClientError, Result};
+use crate::hedge::Hedge;
+use crate::proxy::Proxy;
+use crate::retry::ReconnectPolicy;
+
+/// Configures a [`GuardianClient`] before connecting it. Created with
+/// [`GuardianClient::builder`].
+///
+/// Settings are checked when connecting, so a bad header value or user
+/// agent surfaces as [`ClientError::ConfigError`] from `connect`.
+///
+/// # Examples
+///
+/// ```no_run
+/// # use guardian_client::{Certificate, ClientTlsConfig, GuardianClient};
+/// # use std::time::Duration;
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let ca = Certificate::from_pem(std::fs::read("/etc/guardian/ca.pem")?);
+/// let client = GuardianClient::builder()
+///     .tls(ClientTlsConfig::new().ca_certificate(ca))
+///     .api_key("checkout-service-key")
+///     .connect_timeout(Duration::from_secs(1))
+///     .timeout(Duration::from_millis(50))
+///     .connect("https://guardian.internal:50051")
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct GuardianClientBuilder {
+    tls: Option<ClientTlsConfig>,
+    metadata: Vec<(String, String)>,
+    credentials: Credentials,
+    admin_token: Option<String>,
+    namespace: Option<String>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    keepalive_timeout: Option<Duration>,
+    tcp_keepalive: Option<Duration>,
+    user_agent: Option<String>,
+    concurrency_limit: Option<usize>,
+    reconnect: Option<ReconnectPolicy>,
+    health_check_interval: Option<Duration>,
+    health_probe_interval: Option<Duration>,
+    hedge_after: Option<Duration>,
+    key_affinity: bool,
+    coalesce_window: Option<Duration>,
+    denial_cache: bool,
+    shadow_mode: bool,
+    fallback: Fallback,
+    proxy: Option<String>,
+    #[cfg(feature = "opentelemetry")]
+    trace_context: bool,
+}
+
+impl GuardianClientBuilder {
+    /// Connect over TLS; the endpoint passed to `connect` should use
+    /// `https://`. Add a client identity to the config for mutual TLS.
+    pub fn tls(mut self, config: ClientTlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// API key sent with every call, for services with `auth.api_keys`.
+    pub fn api_key(self, key: impl Into<String>) -> Self {
+        self.metadata(API_KEY_HEADER, key)
+    }
+
+    /// JWT sent with every call as `authorization: Bearer <token>`. For a
+    /// token that is refreshed while the client runs, use
+    /// [`credentials`](Self::credentials).
+    pub fn bearer_token(self, token: impl AsRef<str>) -> Self {
+        self.metadata(AUTHORIZATION_HEADER, format!("Bearer {}", token.as_ref()))
+    }
+
+    /// Add credentials to each call as it is sent, after the headers set
+    /// here; see [`CallCredentials`].
+    pub fn credentials(mut self, credentials: impl CallCredentials) -> Self {
+        self.credentials = Credentials::new(credentials);
+        self
+    }
+
+    /// Any other ASCII header to send with every call.
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.push((key.into(), value.into()));
+        self
+    }
+
+    /// See [`GuardianClient::with_admin_token`].
+    pub fn admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = Some(token.into());
+        self
+    }
+
+    /// The tenant to act for; see [`GuardianClient::with_namespace`].
+    /// Callers whose API key or token is bound to a tenant are kept to its
+    /// namespace by the service and needn't set this.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Give up on establishing a connection after `timeout`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// See [`GuardianClient::with_timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Ping the service every `interval` over HTTP/2, even while idle, so
+    /// dead connections are noticed before a call is sent on one.
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Close the connection when a keepalive ping goes unanswered for
+    /// `timeout` (20 seconds by default).
+    pub fn keepalive_timeout(mut self, timeout: Duration) -> Self {
+        self.keepalive_timeout = Some(timeout);
+        self
+    }
+
+    /// Enable TCP keepalive probes after `idle` without traffic.
+    pub fn tcp_keepalive(mut self, idle: Duration) -> Self {
+        self.tcp_keepalive = Some(idle);
+        self
+    }
+
+    /// Prefix for the `user-agent` header, to tell callers apart in
+    /// service logs.
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Calls allowed in flight on the channel at once; more wait for a
+    /// slot. Unlimited by default.
+    pub fn concurrency_limit(mut self, limit: usize) -> Self {
+        self.concurrency_limit = Some(limit);
+        self
+    }
+
+    /// Reach the service through a proxy: `http://host:port` for an HTTP
+    /// proxy (which is asked to CONNECT), or `socks5://host:port`. Either
+    /// may carry `user:password@` credentials before the host. TLS to the
+    /// service still runs end to end. Applies to
+    /// [`connect`](Self::connect) only.
+    pub fn proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    /// See [`GuardianClient::with_reconnect`].
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// See [`GuardianClient::with_denial_cache`].
+    pub fn denial_cache(mut self) -> Self {
+        self.denial_cache = true;
+        self
+    }
+
+    /// See [`GuardianClient::with_shadow_mode`].
+    pub fn shadow_mode(mut self) -> Self {
+        self.shadow_mode = true;
+        self
+    }
+
+    /// See [`GuardianClient::with_fallback`].
+    pub fn fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// See [`GuardianClient::with_trace_context`].
+    #[cfg(feature = "opentelemetry")]
+    pub fn trace_context(mut self) -> Self {
+        self.trace_context = true;
+        self
+    }
+
+    /// See [`GuardianClient::with_coalescing`].
+    pub fn coalesce_window(mut self, window: Duration) -> Self {
+        self.coalesce_window = Some(window);
+        self
+    }
+
+    /// How often [`connect_balanced`](Self::connect_balanced) checks each
+    /// instance's health; every 5 seconds by default.
+    pub fn health_check_interval(mut self, interval: Duration) -> Self {
+        self.health_check_interval = Some(interval);
+        self
+    }
+
+    /// Ask the service's grpc.health.v1 status every `interval` in the
+    /// background, so [`GuardianClient::health`] stays current while few
+    /// calls are made. The prober stops once the client and its clones
+    /// are dropped.
+    pub fn health_probe_interval(mut self, interval: Duration) -> Self {
+        self.health_probe_interval = Some(interval);
+        self
+    }
+
+    /// With [`connect_balanced`](Self::connect_balanced) over two or more
+    /// instances, send a check that hasn't been answered after `after` to
+    /// a second instance as well, and take whichever answer comes first.
+    /// This bounds the latency one slow instance adds, at the cost of the
+    /// extra calls. Set it around the check's usual p95 latency so few
+    /// checks are hedged.
+    ///
+    /// Both instances may take the tokens; if the slower answer allows the
+    /// check too, its tokens are refunded.
+    pub fn hedge_after(mut self, after: Duration) -> Self {
+        self.hedge_after = Some(after);
+        self
+    }
+
+    /// With [`connect_balanced`](Self::connect_balanced), send each key's
+    /// checks, refunds and resets to the instance that owns it on a
+    /// consistent-hash ring over the endpoints' `host:port`, for services
+    /// whose `backends.affinity` lists the same addresses. While the owner
+    /// isn't serving its keys are balanced like other calls. Checks sent
+    /// to their owner aren't hedged.
+    pub fn key_affinity(mut self) -> Self {
+        self.key_affinity = true;
+        self
+    }
+
+    /// Connect to the Guardian service at `dst`.
+    pub async fn connect<D>(self, dst: D) -> Result<GuardianClient>
+    where
+        D: TryInto<Endpoint>,
+        D::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let metadata = self.metadata_map()?;
+        let endpoint = self.endpoint(dst)?;
+        let channel = match &self.proxy {
+            Some(proxy) => Proxy::parse(proxy)?.connect(endpoint).await?,
+            None => endpoint
+                .connect()
+                .await
+                .map_err(|e| ClientError::ConnectionError(e.to_string()))?,
+        };
+        Ok(self.finish(channel, metadata))
+    }
+
+    /// Connect to several instances of the Guardian service and spread
+    /// calls over those that are healthy. Each instance's grpc.health.v1
+    /// status is checked in the background; one that stops serving gets
+    /// no calls until it is back. Fails if none is serving to begin with.
+    #[allow(clippy::result_large_err)]
+    pub async fn connect_balanced<D>(self, endpoints: &[D]) -> Result<GuardianClient>
+    where
+        D: TryInto<Endpoint> + Clone,
+        D::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        if self.proxy.is_some() {
+            return Err(ClientError::ConfigError(
+                "a proxy can't be used with connect_balanced".to_string(),
+            ));
+        }
+        let metadata = self.metadata_map()?;
+        let endpoints = endpoints
+            .iter()
+            .map(|dst| self.endpoint(dst.clone()))
+            .collect::<Result<Vec<_>>>()?;
+        let interval = self
+            .health_check_interval
+            .unwrap_or(balance::DEFAULT_HEALTH_INTERVAL);
+        let hedge_after = self.hedge_after;
+        let key_affinity = self.key_affinity;
+        let (channel, instances) = balance::channel(endpoints.clone(), interval).await?;
+        let mut client = self.finish(channel, metadata);
+        if key_affinity {
+            client = client.with_affinity(Affinity::new(&endpoints, instances.clone()));
+        }
+        Ok(match hedge_after {
+            Some(after) => client.with_hedge(Hedge::new(after, instances)),
+            None => client,
+        })
+    }
+
+    /// Connect to the Guardian service on a Unix domain socket. TLS and
+    /// proxy settings don't apply there.
+    #[cfg(unix)]
+    pub async fn connect_unix(self, path: impl AsRef<std::path::Path>) -> Result<GuardianClient> {
+        let metadata = self.metadata_map()?;
+        let endpoint = self.configure(Endpoint::from_static("http://localhost"))?;
+        let channel = crate::client::unix_channel_on(endpoint, path.as_ref()).await?;
+        Ok(self.finish(channel, metadata))
+    }
+
+    /// `dst` with every setting applied.
+    #[allow(clippy::result_large_err)]
+    fn endpoint<D>(&self, dst: D) -> Result<Endpoint>
+    where
+        D: TryInto<Endpoint>,
+        D::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let endpoint = dst.try_into().map_err(|e| {
+            ClientError::ConnectionError(format!("Invalid endpoint: {:?}", e.into()))
+        })?;
+        let mut endpoint = self.configure(endpoint)?;
+        if let Some(tls) = self.tls.clone() {
+            endpoint = endpoint
+                .tls_config(tls)
+                .map_err(|e| ClientError::ConfigError(format!("invalid TLS config: {}", e)))?;
+        }
+        Ok(endpoint)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn configure(&self, mut endpoint: Endpoint) -> Result<Endpoint> {
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(interval) = self.keepalive_interval {
+            endpoint = endpoint
+                .http2_keep_alive_interval(interval)
+                .keep_alive_while_idle(true);
+        }
+        if let Some(timeout) = self.keepalive_timeout {
+            endpoint = endpoint.keep_alive_timeout(timeout);
+        }
+        endpoint = endpoint.tcp_keepalive(self.tcp_keepalive);
+        if let Some(limit) = self.concurrency_limit {
+            endpoint = endpoint.concurrency_limit(limit);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            endpoint = endpoint
+                .user_agent(user_agent.as_str())
+                .map_err(|e| ClientError::ConfigError(format!("invalid user agent: {}", e)))?;
+        }
+        Ok(endpoint)
+    }
+
+    /// The headers to send with every call.
+    #[allow(clippy::result_large_err)]
+    fn metadata_map(&self) -> Result<MetadataMap> {
+        let mut metadata = MetadataMap::new();
+        for (key, value) in &self.metadata {
+            let name: AsciiMetadataKey = key
+                .parse()
+                .map_err(|_| ClientError::ConfigError(format!("invalid metadata key '{}'", key)))?;
+            let value: AsciiMetadataValue = value.parse().map_err(|_| {
+                ClientError::ConfigError(format!("invalid value for metadata '{}'", key))
+            })?;
+            metadata.insert(name, value);
+        }
+        Ok(metadata)
+    }
+
+    fn finish(self, channel: Channel, metadata: MetadataMap) -> GuardianClient {
+        #[cfg_attr(not(feature = "opentelemetry"), allow(unused_mut))]
+        let mut outbound = Outbound::new(metadata, self.credentials);
+        #[cfg(feature = "opentelemetry")]
+        if self.trace_context {
+            outbound = outbound.with_trace_context();
+        }
+        let mut client = GuardianClient::from_channel(channel.clone())
+            .with_outbound(outbound)
+            .with_fallback(self.fallback)
+            .connected(channel, self.health_probe_interval);
+        if let Some(token) = self.admin_token {
+            client = client.with_admin_token(token);
+        }
+        if let Some(namespace) = self.namespace {
+            client = client.with_namespace(namespace);
+        }
+        if let Some(timeout) = self.timeout {
+            client = client.with_timeout(timeout);
+        }
+        if let Some(policy) = self.reconnect {
+            client = client.with_reconnect(policy);
+        }
+        if self.denial_cache {
+            client = client.with_denial_cache();
+        }
+        if let Some(window) = self.coalesce_window {
+            client = client.with_coalescing(window);
+        }
+        if self.shadow_mode {
+            client = client.with_shadow_mode();
+        }
+        client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rejects_bad_settings_before_connecting() {
+        // Nothing listens on port 1; settings errors must come first.
+        let connect = |builder: GuardianClientBuilder| builder.connect("http://127.0.0.1:1");
+
+        let bad_header = GuardianClient::builder().metadata("not a header", "x");
+        assert!(matches!(connect(bad_header).await, Err(ClientError::ConfigError(_))));
+        let bad_token = GuardianClient::builder().bearer_token("line\nbreak");
+        assert!(matches!(connect(bad_token).await, Err(ClientError::ConfigError(_))));
+        let bad_agent = GuardianClient::builder().user_agent("\u{7f}");
+        assert!(matches!(connect(bad_agent).await, Err(ClientError::ConfigError(_))));
+
+        let fine = GuardianClient::builder()
+            .api_key("key")
+            .connect_timeout(Duration::from_millis(100))
+            .keepalive_interval(Duration::from_secs(30))
+            .concurrency_limit(64);
+        assert!(matches!(connect(fine).await, Err(ClientError::ConnectionError(_))));
+    }
+}
@@ -1,15 +1,64 @@
-use tonic::transport::Channel;
-use tonic::Request;
+use std::borrow::Cow;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use guardian_core::{DecisionReason, TokenBucketConfig};
+use tonic::transport::{Channel, Endpoint};
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+use tokio_stream::Stream;
+use tonic::{Code, Request, Response, Status};
+
+use crate::affinity::Affinity;
+use crate::builder::GuardianClientBuilder;
+use crate::coalesce::{self, Coalescer};
+use crate::config::{ClientConfig, Fallback};
+use crate::credentials::Outbound;
+pub use crate::decision::LimitCheckResult;
+use crate::denials::DenialCache;
 use crate::error::{ClientError, Result};
+use crate::health::{ChannelHealth, HealthState};
+use crate::hedge::{self, Hedge};
+use crate::observer::{AllOrNothing, Answer, CallOutcome, ClientObserver, Rpc, Shadow};
+use crate::permit::Permit;
+use crate::pipeline::CheckPipeline;
 use crate::proto::{
     rate_limiter_client::RateLimiterClient,
-    CheckLimitRequest, GetUsageRequest, ResetLimitRequest,
+    AccessList, CheckLimitBatchRequest, CheckLimitRequest, CheckLimitResponse,
+    DeleteLimitConfigRequest, GetUsageRequest, KeyActivity, KeyRanking, LimitStatusUpdate,
+    ListKeysRequest, RateLimitConfig, RefundTokensRequest, RemoveAccessEntryRequest,
+    ResetLimitRequest, SetAccessEntryRequest, SetLimitConfigRequest, StreamLimitRequest,
+    TopKeysRequest,
 };
+use crate::retry::{self, ReconnectPolicy};
 
 /// Guardian rate limiter client
+///
+/// Calls made while the service is unreachable are retried as the channel
+/// reconnects, following the [`ReconnectPolicy`] (by default, up to 5
+/// retries over about a second and a half). A timeout set with
+/// [`with_timeout`](Self::with_timeout) bounds each call, retries included.
+///
+/// Every method takes `&self`, so one client can serve many tasks at once,
+/// behind an `Arc` or cloned: clones share the underlying connection.
+#[derive(Clone)]
 pub struct GuardianClient {
     inner: RateLimiterClient<Channel>,
+    admin_token: String,
+    namespace: String,
+    reconnect: ReconnectPolicy,
+    timeout: Option<Duration>,
+    /// Sent with every call (API key, bearer token, custom headers).
+    outbound: Outbound,
+    denials: Option<Arc<DenialCache>>,
+    observer: Option<Arc<dyn ClientObserver>>,
+    hedge: Option<Hedge>,
+    affinity: Option<Arc<Affinity>>,
+    health: HealthState,
+    coalescer: Option<Arc<Coalescer>>,
+    shadow: bool,
+    fallback: Fallback,
 }
 
 impl GuardianClient {
@@ -26,21 +75,274 @@ impl GuardianClient {
     /// ```
     pub async fn connect<D>(dst: D) -> Result<Self>
     where
-        D: TryInto<tonic::transport::Endpoint>,
+        D: TryInto<Endpoint>,
         D::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
     {
-        let endpoint = dst.try_into().map_err(|e| {
-            ClientError::ConnectionError(format!("Invalid endpoint: {:?}", e.into()))
-        })?;
+        Self::builder().connect(dst).await
+    }
 
-        let channel = endpoint
-            .connect()
-            .await
-            .map_err(|e| ClientError::ConnectionError(e.to_string()))?;
+    /// Connect to several instances of the Guardian service, spreading
+    /// calls over the healthy ones; see
+    /// [`GuardianClientBuilder::connect_balanced`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client =
+    ///     GuardianClient::connect_balanced(&["http://g1:50051", "http://g2:50051"]).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn connect_balanced<D>(endpoints: &[D]) -> Result<Self>
+    where
+        D: TryInto<Endpoint> + Clone,
+        D::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        Self::builder().connect_balanced(endpoints).await
+    }
 
-        Ok(Self {
+    /// Connect as the environment says: the file named by
+    /// `GUARDIAN_CLIENT_CONFIG`, if set, and `GUARDIAN_*` variables such as
+    /// `GUARDIAN_ENDPOINTS` and `GUARDIAN_API_KEY`; see [`ClientConfig`].
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// // GUARDIAN_ENDPOINTS=http://guardian:50051 GUARDIAN_FALLBACK=allow
+    /// let client = GuardianClient::from_env().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_env() -> Result<Self> {
+        ClientConfig::from_env()?.connect().await
+    }
+
+    /// Connect with the settings in the YAML or TOML file at `path`, which
+    /// `GUARDIAN_*` environment variables override; see [`ClientConfig`].
+    pub async fn from_config(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        ClientConfig::load(path)?.connect().await
+    }
+
+    /// A client of a Guardian service emulated in this process, with
+    /// guardian-core's in-memory backend limiting every key by `config`.
+    /// For development and integration tests without a running service:
+    /// checks, batches, pipelines, usage, resets and refunds behave as
+    /// they would against one. Tiers, admin RPCs and status subscriptions
+    /// aren't emulated and fail with `Unimplemented`.
+    ///
+    /// Each call starts a separate service, and clones of the client share
+    /// it. Must be called from within a Tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # use guardian_core::TokenBucketConfig;
+    /// # use std::time::Duration;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = GuardianClient::in_process(TokenBucketConfig {
+    ///     capacity: 10,
+    ///     refill_rate: 1,
+    ///     refill_interval: Duration::from_secs(1),
+    /// });
+    /// assert!(client.check_limit("user123", 1).await?);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn in_process(config: TokenBucketConfig) -> Self {
+        Self::from_channel(crate::in_process::channel(config))
+    }
+
+    /// Configure TLS, credentials, timeouts and transport settings before
+    /// connecting; see [`GuardianClientBuilder`].
+    pub fn builder() -> GuardianClientBuilder {
+        GuardianClientBuilder::default()
+    }
+
+    /// Use an already configured channel.
+    pub fn from_channel(channel: Channel) -> Self {
+        Self {
             inner: RateLimiterClient::new(channel),
-        })
+            admin_token: String::new(),
+            namespace: String::new(),
+            reconnect: ReconnectPolicy::default(),
+            timeout: None,
+            outbound: Outbound::default(),
+            denials: None,
+            observer: None,
+            hedge: None,
+            affinity: None,
+            health: HealthState::default(),
+            coalescer: None,
+            shadow: false,
+            fallback: Fallback::Error,
+        }
+    }
+
+    /// Connect to a Guardian service on a Unix domain socket, such as a
+    /// sidecar on the same host.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = GuardianClient::connect_unix("/run/guardian/guardian.sock").await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(unix)]
+    pub async fn connect_unix(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        Self::builder().connect_unix(path).await
+    }
+
+    /// Token sent with admin operations such as [`reset_limit`](Self::reset_limit);
+    /// the service rejects them without a valid one.
+    pub fn with_admin_token(mut self, token: impl Into<String>) -> Self {
+        self.admin_token = token.into();
+        self
+    }
+
+    /// Namespace (tenant) that every call's keys belong to. Unset uses the
+    /// shared default namespace, or the caller's own tenant when the
+    /// service binds its credentials to one.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// How calls are retried while the service is unreachable; see
+    /// [`ReconnectPolicy::disabled`] to fail them at once instead.
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+
+    /// Give up on any call that takes longer than `timeout`, with
+    /// [`ClientError::DeadlineExceeded`]. Calls wait indefinitely by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Answer checks locally while the service's `retry_after` for an
+    /// earlier denial of the same key hasn't passed; see [`DenialCache`].
+    /// Clones of the client share the cache.
+    pub fn with_denial_cache(mut self) -> Self {
+        self.denials = Some(Arc::default());
+        self
+    }
+
+    /// Coalesce checks of the same key made within `window` of each other
+    /// into one call for their total cost, and answer each from it. This
+    /// cuts the calls hot keys make, at the price of up to `window` added
+    /// to each check; a millisecond is often enough. Checks allowed
+    /// together share the bucket's `remaining_tokens`. If the total is
+    /// denied, the earliest checks that fit in what was left are still
+    /// allowed, with a second call. Clones of the client share the window.
+    pub fn with_coalescing(mut self, window: Duration) -> Self {
+        self.coalescer = Some(Arc::new(Coalescer::new(window)));
+        self
+    }
+
+    /// Shadow mode: checks still go to the service, but every one is
+    /// allowed. Each denial the service (or the denial cache) gives is
+    /// reported to the observer's
+    /// [`on_shadow_denial`](ClientObserver::on_shadow_denial) instead, and
+    /// calls are reported with their real outcome. Use it to see what a
+    /// limit would do before enforcing it.
+    pub fn with_shadow_mode(mut self) -> Self {
+        self.shadow = true;
+        self
+    }
+
+    /// What single checks (`check_limit` and its variants, and `acquire`)
+    /// return when the service couldn't decide them; by default the
+    /// error. Other errors, such as rejected credentials, are returned
+    /// either way.
+    pub fn with_fallback(mut self, fallback: Fallback) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// The denials remembered, if the cache is on.
+    pub fn denial_cache(&self) -> Option<&DenialCache> {
+        self.denials.as_deref()
+    }
+
+    /// Report each call's outcome, latency and retries to `observer`; see
+    /// [`ClientObserver`]. Clones of the client report to it too.
+    pub fn with_observer(mut self, observer: Arc<dyn ClientObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// The state of the link to the service, as of the latest call or
+    /// probe; see [`ChannelHealth`]. While it isn't `Ready`, an
+    /// application may rather fail open at once than wait for a check to
+    /// time out. Keepalive pings and a background prober (see
+    /// [`GuardianClientBuilder::health_probe_interval`]) keep it current
+    /// between calls.
+    pub fn health(&self) -> ChannelHealth {
+        self.health.get()
+    }
+
+    /// Send the caller's W3C trace context (`traceparent`, `tracestate`
+    /// and `baggage`) with every call, taken from the current `tracing`
+    /// span, or OpenTelemetry's current context when the span has none.
+    /// The service parents its spans to it, so limiter calls appear in
+    /// the caller's traces.
+    #[cfg(feature = "opentelemetry")]
+    pub fn with_trace_context(mut self) -> Self {
+        self.outbound = self.outbound.with_trace_context();
+        self
+    }
+
+    pub(crate) fn with_outbound(mut self, outbound: Outbound) -> Self {
+        self.outbound = outbound;
+        self
+    }
+
+    pub(crate) fn with_hedge(mut self, hedge: Hedge) -> Self {
+        self.hedge = Some(hedge);
+        self
+    }
+
+    pub(crate) fn with_affinity(mut self, affinity: Affinity) -> Self {
+        self.affinity = Some(Arc::new(affinity));
+        self
+    }
+
+    /// This client, aimed at the instance owning `client_id` if calls have
+    /// key affinity and that instance is serving. Calls aimed at their
+    /// owner aren't hedged.
+    fn routed(&self, client_id: &str) -> Cow<'_, Self> {
+        let owner = self
+            .affinity
+            .as_ref()
+            .and_then(|affinity| affinity.route(&self.namespace, client_id));
+        match owner {
+            Some(channel) => Cow::Owned(Self {
+                inner: RateLimiterClient::new(channel),
+                hedge: None,
+                ..self.clone()
+            }),
+            None => Cow::Borrowed(self),
+        }
+    }
+
+    /// Mark the link as up, having just connected over `channel`, and
+    /// probe it every `probe_interval` if set.
+    pub(crate) fn connected(self, channel: Channel, probe_interval: Option<Duration>) -> Self {
+        self.health.set(ChannelHealth::Ready);
+        if let Some(interval) = probe_interval {
+            self.health.probe_every(channel, interval);
+        }
+        self
     }
 
     /// Check if a request should be allowed for the given client
@@ -60,7 +362,7 @@ impl GuardianClient {
     ///
     /// ```no_run
     /// # use guardian_client::GuardianClient;
-    /// # async fn example(mut client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
     /// if client.check_limit("user123", 1).await? {
     ///     // Process request
     ///     println!("Request allowed");
@@ -71,44 +373,715 @@ impl GuardianClient {
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn check_limit(&mut self, client_id: &str, cost: u32) -> Result<bool> {
-        let request = Request::new(CheckLimitRequest {
+    pub async fn check_limit(&self, client_id: &str, cost: u32) -> Result<bool> {
+        Ok(self.decide(client_id, "", cost, None).await?.allowed)
+    }
+
+    /// [`check_limit`](Self::check_limit), giving up at `deadline` instead
+    /// of after the client's default timeout.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::{ClientError, GuardianClient};
+    /// # use std::time::{Duration, Instant};
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// // Whatever is left of the caller's own 50 ms budget.
+    /// let deadline = Instant::now() + Duration::from_millis(50);
+    /// match client.check_limit_with_deadline("user123", 1, deadline).await {
+    ///     Ok(allowed) => println!("allowed: {}", allowed),
+    ///     Err(ClientError::DeadlineExceeded) => println!("no answer in time; failing open"),
+    ///     Err(e) => return Err(e.into()),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn check_limit_with_deadline(
+        &self,
+        client_id: &str,
+        cost: u32,
+        deadline: Instant,
+    ) -> Result<bool> {
+        let result = self.decide(client_id, "", cost, Some(deadline)).await?;
+        Ok(result.allowed)
+    }
+
+    /// Check a request against a named client tier (e.g. `"premium"`)
+    /// instead of the tier, if any, the service assigns to `client_id`.
+    pub async fn check_limit_for_tier(
+        &self,
+        client_id: &str,
+        tier: &str,
+        cost: u32,
+    ) -> Result<bool> {
+        Ok(self.decide(client_id, tier, cost, None).await?.allowed)
+    }
+
+    pub async fn check_limit_detailed(
+        &self,
+        client_id: &str,
+        cost: u32,
+    ) -> Result<LimitCheckResult> {
+        self.decide(client_id, "", cost, None).await
+    }
+
+    /// [`check`](Self::check) as the caller sees it, allowed in shadow
+    /// mode.
+    async fn decide(
+        &self,
+        client_id: &str,
+        tier: &str,
+        cost: u32,
+        deadline: Option<Instant>,
+    ) -> Result<LimitCheckResult> {
+        match self.check(client_id, tier, cost, deadline).await {
+            Ok(result) => Ok(self.shadowed(client_id, result)),
+            Err(e) => self.fallback.apply(e),
+        }
+    }
+
+    fn shadowed(&self, client_id: &str, result: LimitCheckResult) -> LimitCheckResult {
+        if self.shadow {
+            Shadow(self.observer.clone()).pass(client_id, result)
+        } else {
+            result
+        }
+    }
+
+    /// One check, answered from the denial cache when it can be. Without
+    /// a `deadline` the client's default timeout applies.
+    async fn check(
+        &self,
+        client_id: &str,
+        tier: &str,
+        cost: u32,
+        deadline: Option<Instant>,
+    ) -> Result<LimitCheckResult> {
+        if let Some(result) = self.cached_denial(client_id, tier, cost) {
+            return Ok(result);
+        }
+        let result = match &self.coalescer {
+            Some(coalescer) => {
+                self.ask_coalesced(coalescer, client_id, tier, cost, deadline)
+                    .await?
+            }
+            None => self.ask(client_id, tier, cost, deadline).await?,
+        };
+        self.remember(client_id, tier, cost, &result);
+        Ok(result)
+    }
+
+    /// One check, straight from the service.
+    async fn ask(
+        &self,
+        client_id: &str,
+        tier: &str,
+        cost: u32,
+        deadline: Option<Instant>,
+    ) -> Result<LimitCheckResult> {
+        let request = CheckLimitRequest {
             client_id: client_id.to_string(),
             cost,
             override_config: None,
-        });
+            tier: tier.to_string(),
+            namespace: self.namespace.clone(),
+            refundable: false,
+        };
+        let rpc = |mut inner: RateLimiterClient<Channel>, request| async move {
+            inner.check_limit(request).await
+        };
+        let client = self.routed(client_id);
+        if let Some((hedge, instances)) = client
+            .hedge
+            .as_ref()
+            .and_then(|hedge| Some((hedge, hedge.pick()?)))
+        {
+            let deadline = deadline.or_else(|| self.timeout.map(|t| Instant::now() + t));
+            let resp = self.hedged_check(hedge.after, instances, deadline, request).await?;
+            return Ok(resp.into());
+        }
+        let resp = match deadline {
+            Some(deadline) => client.call_until(Some(deadline), request, rpc).await?,
+            None => client.call(request, rpc).await?,
+        };
 
-        let response = self
-            .inner
-            .check_limit(request)
+        Ok(resp.into())
+    }
+
+    /// One check, sharing a call with other checks of the key made within
+    /// the coalescing window. The first of them starts the window.
+    async fn ask_coalesced(
+        &self,
+        coalescer: &Arc<Coalescer>,
+        client_id: &str,
+        tier: &str,
+        cost: u32,
+        deadline: Option<Instant>,
+    ) -> Result<LimitCheckResult> {
+        let key = (
+            self.namespace.clone(),
+            tier.to_string(),
+            client_id.to_string(),
+        );
+        let Some((opened, answer)) = coalescer.join(&key, cost) else {
+            return self.ask(client_id, tier, cost, deadline).await;
+        };
+        if opened {
+            let client = self.clone();
+            let coalescer = Arc::clone(coalescer);
+            let deadline = deadline.or_else(|| self.timeout.map(|t| Instant::now() + t));
+            tokio::spawn(async move {
+                tokio::time::sleep(coalescer.window).await;
+                client.settle(&coalescer, key, deadline).await;
+            });
+        }
+        let answer = async {
+            match answer.await {
+                Ok(result) => result,
+                Err(_) => Err(ClientError::ConnectionError(
+                    "coalesced check was dropped".to_string(),
+                )),
+            }
+        };
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), answer)
+                .await
+                .map_err(|_| ClientError::DeadlineExceeded)?,
+            None => answer.await,
+        }
+    }
+
+    /// Answer the checks coalesced under `key` with one call for their
+    /// total cost. If that is denied with tokens left in the bucket, the
+    /// earliest checks that fit get a second call of their own.
+    async fn settle(&self, coalescer: &Coalescer, key: coalesce::Key, deadline: Option<Instant>) {
+        let (_, tier, client_id) = &key;
+        let (mut waiters, total) = coalescer.close(&key);
+        let denied = match self.ask(client_id, tier, total, deadline).await {
+            Ok(result) if !result.allowed && waiters.len() > 1 => result,
+            result => {
+                for waiter in waiters {
+                    waiter.answer(result.clone());
+                }
+                return;
+            }
+        };
+        let fit = coalesce::affordable(waiters.iter().map(|w| w.cost), denied.remaining_tokens);
+        let rest = waiters.split_off(fit);
+        if !waiters.is_empty() {
+            let cost = waiters.iter().map(|w| w.cost).sum();
+            let result = self.ask(client_id, tier, cost, deadline).await;
+            for waiter in waiters {
+                waiter.answer(result.clone());
+            }
+        }
+        for waiter in rest {
+            waiter.answer(Ok(denied.clone()));
+        }
+    }
+
+    /// `request` to the first of `instances`, and to the second as well if
+    /// it hasn't been answered after `after`. A check the slower instance
+    /// allowed too is refunded on that instance, which need not share
+    /// storage with the other.
+    async fn hedged_check(
+        &self,
+        after: Duration,
+        (first, second): (Channel, Channel),
+        deadline: Option<Instant>,
+        request: CheckLimitRequest,
+    ) -> Result<CheckLimitResponse> {
+        // The attempt that loses the race is refunded by its grant.
+        let request = CheckLimitRequest {
+            refundable: true,
+            ..request
+        };
+        let attempt = |channel: Channel| {
+            let client = Self {
+                inner: RateLimiterClient::new(channel),
+                ..self.clone()
+            };
+            let request = request.clone();
+            async move {
+                let rpc = |mut inner: RateLimiterClient<Channel>, request| async move {
+                    inner.check_limit(request).await
+                };
+                let response = client.send_until(deadline, request, rpc).await?;
+                Ok((response, client))
+            }
+        };
+        let on_hedge = || {
+            if let Some(observer) = &self.observer {
+                observer.on_hedge(CheckLimitRequest::NAME);
+            }
+        };
+        let refund = RefundTokensRequest {
+            client_id: request.client_id.clone(),
+            cost: 0,
+            tier: request.tier.clone(),
+            namespace: request.namespace.clone(),
+            grant_id: String::new(),
+        };
+        let wasted = move |(late, on): (CheckLimitResponse, Self)| {
+            if late.allowed {
+                let refund = RefundTokensRequest {
+                    grant_id: late.grant_id,
+                    ..refund
+                };
+                tokio::spawn(async move {
+                    let rpc = |mut inner: RateLimiterClient<Channel>, request| async move {
+                        inner.refund_tokens(request).await
+                    };
+                    let _ = on.send_until(None, refund, rpc).await;
+                });
+            }
+        };
+
+        let started = Instant::now();
+        let result = hedge::race(after, attempt(first), || attempt(second), on_hedge, wasted)
             .await
-            .map_err(|e| ClientError::RpcError(e))?;
+            .map(|(response, _)| response);
+        if let Some(observer) = &self.observer {
+            let outcome = result.as_ref().map_or(CallOutcome::Failed, Answer::outcome);
+            observer.on_call(CheckLimitRequest::NAME, outcome, started.elapsed());
+        }
+        result
+    }
 
-        Ok(response.into_inner().allowed)
+    fn cached_denial(&self, client_id: &str, tier: &str, cost: u32) -> Option<LimitCheckResult> {
+        let denials = self.denials.as_ref()?;
+        let denied = denials.lookup(&self.namespace, tier, client_id, cost, Instant::now())?;
+        if let Some(observer) = &self.observer {
+            observer.on_cached_denial();
+        }
+        Some(denied)
     }
-    pub async fn check_limit_detailed(
-        &mut self,
+
+    fn remember(&self, client_id: &str, tier: &str, cost: u32, result: &LimitCheckResult) {
+        if let Some(denials) = &self.denials {
+            let now = Instant::now();
+            denials.record(&self.namespace, tier, client_id, cost, result, now);
+        }
+    }
+
+    /// Wait until `cost` tokens of `client_id` can be had, for at most
+    /// `max_wait`. A denied check is tried again once the service's
+    /// `retry_after` has passed, with some jitter. Returns
+    /// [`ClientError::RateLimited`] as soon as the next try would come
+    /// after `max_wait`, rather than waiting for nothing.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::{ClientError, GuardianClient};
+    /// # use std::time::Duration;
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// match client.acquire("export-job", 10, Duration::from_secs(5)).await {
+    ///     Ok(_) => println!("running the export"),
+    ///     Err(ClientError::RateLimited) => println!("still limited after 5 seconds"),
+    ///     Err(e) => return Err(e.into()),
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn acquire(
+        &self,
         client_id: &str,
         cost: u32,
+        max_wait: Duration,
     ) -> Result<LimitCheckResult> {
-        let request = Request::new(CheckLimitRequest {
+        let deadline = Instant::now() + max_wait;
+        let mut attempt = 0;
+        loop {
+            let result = self.decide(client_id, "", cost, Some(deadline)).await?;
+            if result.allowed {
+                return Ok(result);
+            }
+            let wait = retry::acquire_wait(result.retry_after_seconds, attempt);
+            if Instant::now() + wait > deadline {
+                return Err(ClientError::RateLimited);
+            }
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+
+    /// Take `cost` tokens of `client_id` if they are available now, as a
+    /// [`Permit`] that gives them back when dropped without
+    /// [`commit`](Permit::commit). `None` if the check was denied.
+    ///
+    /// The check is never coalesced or hedged, since the refund has to
+    /// redeem the grant of the one call that took the tokens.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # async fn upload() -> Result<(), std::io::Error> { Ok(()) }
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// let Some(permit) = client.try_acquire("user123", 5).await? else {
+    ///     return Ok(()); // rate limited
+    /// };
+    /// // If the upload fails, `?` drops the permit and the 5 tokens are refunded.
+    /// upload().await?;
+    /// permit.commit();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn try_acquire(&self, client_id: &str, cost: u32) -> Result<Option<Permit>> {
+        let (result, grant_id) = match self.cached_denial(client_id, "", cost) {
+            Some(result) => (result, String::new()),
+            None => {
+                let request = CheckLimitRequest {
+                    client_id: client_id.to_string(),
+                    cost,
+                    override_config: None,
+                    tier: String::new(),
+                    namespace: self.namespace.clone(),
+                    refundable: true,
+                };
+                let response = self
+                    .routed(client_id)
+                    .call(request, |mut inner, request| async move {
+                        inner.check_limit(request).await
+                    })
+                    .await?;
+                let grant_id = response.grant_id.clone();
+                let result = LimitCheckResult::from(response);
+                self.remember(client_id, "", cost, &result);
+                (result, grant_id)
+            }
+        };
+        if result.allowed {
+            return Ok(Some(Permit::new(self.clone(), client_id, cost, grant_id)));
+        }
+        if self.shadow {
+            self.shadowed(client_id, result);
+            // Shadow mode took no tokens, so there's no grant to redeem.
+            let unpaid = Permit::new(self.clone(), client_id, cost, String::new());
+            return Ok(Some(unpaid));
+        }
+        Ok(None)
+    }
+
+    /// Give back up to `cost` tokens that the check which returned
+    /// `grant_id` took from `client_id`. The service redeems each grant
+    /// once, and the bucket never goes above its capacity.
+    pub(crate) async fn refund(&self, client_id: &str, cost: u32, grant_id: &str) -> Result<()> {
+        let request = RefundTokensRequest {
             client_id: client_id.to_string(),
             cost,
-            override_config: None,
-        });
+            tier: String::new(),
+            namespace: self.namespace.clone(),
+            grant_id: grant_id.to_string(),
+        };
 
         let response = self
-            .inner
-            .check_limit(request)
-            .await
-            .map_err(|e| ClientError::RpcError(e))?;
+            .routed(client_id)
+            .call(request, |mut inner, request| async move {
+                inner.refund_tokens(request).await
+            })
+            .await?;
 
-        let resp = response.into_inner();
-        Ok(LimitCheckResult {
-            allowed: resp.allowed,
-            retry_after_seconds: resp.retry_after_seconds,
-            remaining_tokens: resp.remaining_tokens,
-        })
+        if response.success {
+            if let Some(denials) = &self.denials {
+                denials.forget(&self.namespace, client_id);
+            }
+            Ok(())
+        } else {
+            Err(ClientError::RefundFailed(response.message))
+        }
+    }
+
+    /// Check several `(client_id, cost)` entries, such as the user, IP and
+    /// route of one request, in one round trip
+    ///
+    /// Uses the batch RPC; against a service without it, the checks are
+    /// sent as concurrent single checks instead. Entries the denial cache
+    /// can answer aren't sent at all. Results line up with `checks`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// let results = client
+    ///     .check_limits(&[("user:123", 1), ("ip:10.0.0.1", 1), ("route:/search", 5)])
+    ///     .await?;
+    /// if results.iter().all(|r| r.allowed) {
+    ///     println!("Request allowed");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn check_limits(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>> {
+        let mut results: Vec<_> = checks
+            .iter()
+            .map(|(client_id, cost)| self.cached_denial(client_id, "", *cost))
+            .collect();
+        let pending: Vec<(usize, (&str, u32))> = checks
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(i, _)| results[*i].is_none())
+            .collect();
+        if pending.is_empty() {
+            return Ok(checks
+                .iter()
+                .zip(results.into_iter().flatten())
+                .map(|((client_id, _), result)| self.shadowed(client_id, result))
+                .collect());
+        }
+
+        let sent: Vec<(&str, u32)> = pending.iter().map(|(_, check)| *check).collect();
+        let answers = match self.batch(&sent).await {
+            Err(ClientError::RpcError(status)) if status.code() == Code::Unimplemented => {
+                self.check_each(&sent).await?
+            }
+            answers => answers?,
+        };
+        for ((i, (client_id, cost)), result) in pending.into_iter().zip(answers) {
+            self.remember(client_id, "", cost, &result);
+            results[i] = Some(result);
+        }
+        Ok(checks
+            .iter()
+            .zip(results.into_iter().flatten())
+            .map(|((client_id, _), result)| self.shadowed(client_id, result))
+            .collect())
+    }
+
+    /// `checks` as concurrent single checks, for services without the
+    /// batch RPC.
+    async fn check_each(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>> {
+        let mut tasks = tokio::task::JoinSet::new();
+        for (i, (client_id, cost)) in checks.iter().enumerate() {
+            let client = self.clone();
+            let client_id = client_id.to_string();
+            let cost = *cost;
+            tasks.spawn(async move { (i, client.ask(&client_id, "", cost, None).await) });
+        }
+        let mut results = vec![None; checks.len()];
+        while let Some(done) = tasks.join_next().await {
+            let (i, result) = done.map_err(|e| ClientError::ConnectionError(e.to_string()))?;
+            results[i] = Some(result?);
+        }
+        Ok(results.into_iter().flatten().collect())
+    }
+
+    /// Check several `(client_id, cost)` entries in one round trip
+    ///
+    /// Entries are decided independently and in order; results line up
+    /// with `checks`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// let results = client
+    ///     .check_limit_batch(&[("user:123", 1), ("ip:10.0.0.1", 1), ("route:/search", 5)])
+    ///     .await?;
+    /// let allowed = results.iter().all(|r| r.allowed);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn check_limit_batch(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>> {
+        let results = self.batch(checks).await?;
+        Ok(checks
+            .iter()
+            .zip(results)
+            .map(|((client_id, _), result)| self.shadowed(client_id, result))
+            .collect())
+    }
+
+    async fn batch(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>> {
+        let request = CheckLimitBatchRequest {
+            checks: checks
+                .iter()
+                .map(|(client_id, cost)| CheckLimitRequest {
+                    client_id: client_id.to_string(),
+                    cost: *cost,
+                    override_config: None,
+                    tier: String::new(),
+                    namespace: self.namespace.clone(),
+                    refundable: false,
+                })
+                .collect(),
+        };
+
+        let response = self
+            .call(request, |mut inner, request| async move {
+                inner.check_limit_batch(request).await
+            })
+            .await?;
+
+        Ok(response.results.into_iter().map(Into::into).collect())
+    }
+
+    /// Check several `(client_id, cost)` entries all or nothing: either
+    /// every entry had the tokens and they were all taken, or none were
+    ///
+    /// For an operation that spends several quotas at once, such as a
+    /// user's and their organisation's. The service takes every entry's
+    /// tokens in one step of its backend, or none of them: when any entry
+    /// is over its limit, every result comes back denied, with the time
+    /// until the slowest bucket has refilled, and no concurrent check ever
+    /// sees some entries charged and the rest not. A service too old for
+    /// this returns [`ClientError::RpcError`] with `Unimplemented` rather
+    /// than checking the entries one by one.
+    ///
+    /// # Limits
+    ///
+    /// The service's backend has to take several keys in one step: its
+    /// in-memory backend and Redis with scripting can, as long as every
+    /// entry is kept in the same one. Otherwise, and when the entries fall
+    /// in different tiers, the call fails with `FailedPrecondition` and
+    /// nothing is charged.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// let results = client
+    ///     .check_all_or_nothing(&[("user:123", 1), ("org:acme", 1)])
+    ///     .await?;
+    /// if results[0].allowed {
+    ///     println!("Both quotas spent");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn check_all_or_nothing(
+        &self,
+        checks: &[(&str, u32)],
+    ) -> Result<Vec<LimitCheckResult>> {
+        let cached = checks
+            .iter()
+            .enumerate()
+            .find_map(|(i, (client_id, cost))| {
+                Some((i, self.cached_denial(client_id, "", *cost)?))
+            });
+        let results: Vec<LimitCheckResult> = match cached {
+            Some((denied_at, denied)) => checks
+                .iter()
+                .enumerate()
+                .map(|(i, _)| {
+                    if i == denied_at {
+                        denied.clone()
+                    } else {
+                        CheckLimitResponse::default().into()
+                    }
+                })
+                .collect(),
+            None => {
+                let responses = self.all_or_nothing(checks).await?;
+                checks
+                    .iter()
+                    .zip(responses)
+                    .map(|((client_id, cost), response)| {
+                        // An entry denied only because another was short
+                        // still has room in its own bucket.
+                        let own = response.reason != DecisionReason::AllOrNothing.as_str();
+                        let result = response.into();
+                        if own {
+                            self.remember(client_id, "", *cost, &result);
+                        }
+                        result
+                    })
+                    .collect()
+            }
+        };
+        Ok(checks
+            .iter()
+            .zip(results)
+            .map(|((client_id, _), result)| self.shadowed(client_id, result))
+            .collect())
+    }
+
+    async fn all_or_nothing(&self, checks: &[(&str, u32)]) -> Result<Vec<CheckLimitResponse>> {
+        let request = CheckLimitBatchRequest {
+            checks: checks
+                .iter()
+                .map(|(client_id, cost)| CheckLimitRequest {
+                    client_id: client_id.to_string(),
+                    cost: *cost,
+                    override_config: None,
+                    tier: String::new(),
+                    namespace: self.namespace.clone(),
+                    refundable: false,
+                })
+                .collect(),
+        };
+
+        let response = self
+            .call(AllOrNothing(request), |mut inner, request| async move {
+                inner.check_limit_all(request.map(|all| all.0)).await
+            })
+            .await?;
+
+        Ok(response.results)
+    }
+
+    /// Open a pipelined check stream for high-throughput callers
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// let pipeline = std::sync::Arc::new(client.check_pipeline().await?);
+    /// let result = pipeline.check("user123", 1).await?;
+    /// println!("Allowed: {}", result.allowed);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn check_pipeline(&self) -> Result<CheckPipeline> {
+        CheckPipeline::open(
+            self.inner.clone(),
+            self.namespace.clone(),
+            self.outbound.metadata()?,
+            self.shadow.then(|| Shadow(self.observer.clone())),
+        )
+        .await
+    }
+
+    /// Follow `client_id`'s limit status as the service reports it: its
+    /// state now, then updates as it changes noticeably, and at least every
+    /// 30 seconds. The subscription reconnects after the service restarts
+    /// or the connection drops, as the [`ReconnectPolicy`] allows; an
+    /// error it can't get past is the stream's last item.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # use guardian_client::proto::LimitStatus;
+    /// # use tokio_stream::StreamExt;
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut updates = std::pin::pin!(client.subscribe_status("user123"));
+    /// while let Some(update) = updates.next().await {
+    ///     if update?.status() != LimitStatus::Healthy {
+    ///         println!("user123 is close to its limit; slowing down");
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn subscribe_status(&self, client_id: &str) -> impl Stream<Item = Result<LimitStatusUpdate>> {
+        let request = StreamLimitRequest {
+            client_id: client_id.to_string(),
+            namespace: self.namespace.clone(),
+            ..StreamLimitRequest::default()
+        };
+        crate::subscription::subscribe(
+            self.inner.clone(),
+            request,
+            self.outbound.clone(),
+            self.reconnect,
+            self.observer.clone(),
+        )
     }
 
     /// Get current usage statistics for a client
@@ -117,64 +1090,289 @@ impl GuardianClient {
     ///
     /// ```no_run
     /// # use guardian_client::GuardianClient;
-    /// # async fn example(mut client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
     /// let usage = client.get_usage("user123").await?;
     /// println!("Used tokens: {}", usage);
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn get_usage(&mut self, client_id: &str) -> Result<u64> {
-        let request = Request::new(GetUsageRequest {
+    pub async fn get_usage(&self, client_id: &str) -> Result<u64> {
+        let request = GetUsageRequest {
             client_id: client_id.to_string(),
-        });
+            namespace: self.namespace.clone(),
+        };
 
         let response = self
-            .inner
-            .get_usage(request)
-            .await
-            .map_err(|e| ClientError::RpcError(e))?;
+            .call(request, |mut inner, request| async move {
+                inner.get_usage(request).await
+            })
+            .await?;
 
-        Ok(response.into_inner().used_tokens)
+        Ok(response.used_tokens)
+    }
+
+    /// [`get_usage`](Self::get_usage) of `client_id`, polled every
+    /// `interval` in the background, for dashboards and gauges that only
+    /// want the latest value. Receivers are notified when the usage
+    /// changes; it reads 0 until the first answer, and a failed poll keeps
+    /// the last value. Polling stops once every receiver is dropped.
+    ///
+    /// Must be called within a Tokio runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use guardian_client::GuardianClient;
+    /// # async fn example(client: GuardianClient) {
+    /// let mut usage = client.watch_usage("user123", Duration::from_secs(5));
+    /// while usage.changed().await.is_ok() {
+    ///     println!("user123 has used {} tokens", *usage.borrow());
+    /// }
+    /// # }
+    /// ```
+    pub fn watch_usage(&self, client_id: &str, interval: Duration) -> watch::Receiver<u64> {
+        let (sender, receiver) = watch::channel(0);
+        let client = self.clone();
+        let client_id = client_id.to_string();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    _ = sender.closed() => return,
+                    _ = ticks.tick() => {}
+                }
+                if let Ok(used) = client.get_usage(&client_id).await {
+                    sender.send_if_modified(|current| std::mem::replace(current, used) != used);
+                }
+            }
+        });
+        receiver
     }
 
     /// Reset the rate limit for a specific client (admin operation)
     ///
+    /// Requires an admin token set with [`with_admin_token`](Self::with_admin_token).
+    ///
     /// # Examples
     ///
     /// ```no_run
     /// # use guardian_client::GuardianClient;
-    /// # async fn example(mut client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = GuardianClient::connect("http://localhost:50051")
+    ///     .await?
+    ///     .with_admin_token("admin-secret");
     /// client.reset_limit("user123").await?;
     /// println!("Limit reset successfully");
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn reset_limit(&mut self, client_id: &str) -> Result<()> {
-        let request = Request::new(ResetLimitRequest {
+    pub async fn reset_limit(&self, client_id: &str) -> Result<()> {
+        let request = ResetLimitRequest {
             client_id: client_id.to_string(),
-            admin_token: String::new(), // Add token if needed
-        });
+            admin_token: self.admin_token.clone(),
+            namespace: self.namespace.clone(),
+        };
 
         let response = self
-            .inner
-            .reset_limit(request)
-            .await
-            .map_err(|e| ClientError::RpcError(e))?;
+            .routed(client_id)
+            .call(request, |mut inner, request| async move {
+                inner.reset_limit(request).await
+            })
+            .await?;
 
-        if response.into_inner().success {
+        if response.success {
+            if let Some(denials) = &self.denials {
+                denials.forget(&self.namespace, client_id);
+            }
             Ok(())
         } else {
             Err(ClientError::ResetFailed)
         }
     }
 
+    /// Reset every key matching `pattern` (`*` wildcards; empty matches
+    /// all), returning how many were reset (admin operation)
+    ///
+    /// Keys are listed first and reset one call at a time, so keys created
+    /// meanwhile may be missed.
+    pub async fn bulk_reset(&self, pattern: &str) -> Result<usize> {
+        let keys = self.list_keys(pattern).await?;
+        for key in &keys {
+            self.reset_limit(key).await?;
+        }
+        Ok(keys.len())
+    }
+
+    /// Create or replace the limit rule for a key pattern; `"default"`
+    /// sets the namespace's default limit (admin operation)
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// use guardian_client::proto::{Algorithm, RateLimitConfig};
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// client
+    ///     .set_limit_config(
+    ///         "api:*",
+    ///         RateLimitConfig {
+    ///             capacity: 100,
+    ///             refill_rate: 10,
+    ///             refill_interval_seconds: 1,
+    ///             algorithm: Algorithm::TokenBucket as i32,
+    ///             shadow: false,
+    ///         },
+    ///     )
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn set_limit_config(&self, pattern: &str, config: RateLimitConfig) -> Result<()> {
+        let request = SetLimitConfigRequest {
+            pattern: pattern.to_string(),
+            config: Some(config),
+            admin_token: self.admin_token.clone(),
+            namespace: self.namespace.clone(),
+        };
+
+        let response = self
+            .call(request, |mut inner, request| async move {
+                inner.set_limit_config(request).await
+            })
+            .await?;
+
+        if response.success {
+            Ok(())
+        } else {
+            Err(ClientError::AdminFailed(response.message))
+        }
+    }
+
+    /// Remove the limit rule for a key pattern, so its keys fall back to
+    /// the default (admin operation)
+    pub async fn delete_limit_config(&self, pattern: &str) -> Result<()> {
+        let request = DeleteLimitConfigRequest {
+            pattern: pattern.to_string(),
+            admin_token: self.admin_token.clone(),
+            namespace: self.namespace.clone(),
+        };
+
+        let response = self
+            .call(request, |mut inner, request| async move {
+                inner.delete_limit_config(request).await
+            })
+            .await?;
+
+        if response.success {
+            Ok(())
+        } else {
+            Err(ClientError::AdminFailed(response.message))
+        }
+    }
+
+    /// Keys holding rate-limit state that match `pattern` (`*` wildcards;
+    /// empty matches all), fetched page by page (admin operation)
+    pub async fn list_keys(&self, pattern: &str) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut page_token = String::new();
+        loop {
+            let request = ListKeysRequest {
+                pattern: pattern.to_string(),
+                page_size: 0,
+                page_token,
+                admin_token: self.admin_token.clone(),
+                namespace: self.namespace.clone(),
+            };
+            let page = self
+                .call(request, |mut inner, request| async move {
+                    inner.list_keys(request).await
+                })
+                .await?;
+            keys.extend(page.keys);
+            if page.next_page_token.is_empty() {
+                return Ok(keys);
+            }
+            page_token = page.next_page_token;
+        }
+    }
+
+    /// The `limit` busiest or most-throttled keys over the recent window
+    /// (admin operation)
+    pub async fn top_keys(&self, limit: u32, ranking: KeyRanking) -> Result<Vec<KeyActivity>> {
+        let request = TopKeysRequest {
+            limit,
+            ranking: ranking as i32,
+            admin_token: self.admin_token.clone(),
+            namespace: self.namespace.clone(),
+        };
+
+        let response = self
+            .call(request, |mut inner, request| async move {
+                inner.top_keys(request).await
+            })
+            .await?;
+
+        Ok(response.keys)
+    }
+
+    /// Deny every check of `client_id` until [`unban`](Self::unban), or
+    /// until `ttl` passes if given; `reason` is shown when listing entries
+    /// (admin operation)
+    pub async fn ban(&self, client_id: &str, ttl: Option<Duration>, reason: &str) -> Result<()> {
+        let request = SetAccessEntryRequest {
+            client_id: client_id.to_string(),
+            list: AccessList::Deny as i32,
+            ttl_seconds: ttl.map_or(0, |ttl| ttl.as_secs().clamp(1, u32::MAX as u64) as u32),
+            reason: reason.to_string(),
+            admin_token: self.admin_token.clone(),
+            namespace: self.namespace.clone(),
+        };
+
+        let response = self
+            .call(request, |mut inner, request| async move {
+                inner.set_access_entry(request).await
+            })
+            .await?;
+
+        if response.success {
+            Ok(())
+        } else {
+            Err(ClientError::AdminFailed(response.message))
+        }
+    }
+
+    /// Take `client_id` off the allow or deny list, returning false if it
+    /// was on neither (admin operation)
+    pub async fn unban(&self, client_id: &str) -> Result<bool> {
+        let request = RemoveAccessEntryRequest {
+            client_id: client_id.to_string(),
+            admin_token: self.admin_token.clone(),
+            namespace: self.namespace.clone(),
+        };
+
+        let response = self
+            .call(request, |mut inner, request| async move {
+                inner.remove_access_entry(request).await
+            })
+            .await?;
+
+        if response.success {
+            if let Some(denials) = &self.denials {
+                denials.forget(&self.namespace, client_id);
+            }
+        }
+        Ok(response.success)
+    }
+
     /// Execute a function only if rate limit allows
     ///
     /// # Examples
     ///
     /// ```no_run
     /// # use guardian_client::GuardianClient;
-    /// # async fn example(mut client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
     /// let result = client.with_rate_limit("user123", 1, async {
     ///     // Your protected operation here
     ///     println!("Executing protected operation");
@@ -184,7 +1382,7 @@ impl GuardianClient {
     /// # }
     /// ```
     pub async fn with_rate_limit<F, T>(
-        &mut self,
+        &self,
         client_id: &str,
         cost: u32,
         f: F,
@@ -198,11 +1396,260 @@ impl GuardianClient {
             Err(ClientError::RateLimited)
         }
     }
+
+    /// Send `request` with `rpc` under the default timeout, retrying it
+    /// while the service is unreachable.
+    async fn call<M, R, F, Fut>(&self, request: M, rpc: F) -> Result<R>
+    where
+        M: Clone + Rpc,
+        R: Answer,
+        F: Fn(RateLimiterClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = std::result::Result<Response<R>, Status>>,
+    {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.call_until(deadline, request, rpc).await
+    }
+
+    /// `call`, giving up at `deadline`. The service is told the time left
+    /// (as `grpc-timeout`) so it can drop work nobody is waiting for.
+    async fn call_until<M, R, F, Fut>(
+        &self,
+        deadline: Option<Instant>,
+        request: M,
+        rpc: F,
+    ) -> Result<R>
+    where
+        M: Clone + Rpc,
+        R: Answer,
+        F: Fn(RateLimiterClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = std::result::Result<Response<R>, Status>>,
+    {
+        let started = Instant::now();
+        let result = self.send_until(deadline, request, rpc).await;
+        if let Some(observer) = &self.observer {
+            let outcome = result.as_ref().map_or(CallOutcome::Failed, R::outcome);
+            observer.on_call(M::NAME, outcome, started.elapsed());
+        }
+        result
+    }
+
+    async fn send_until<M, R, F, Fut>(
+        &self,
+        deadline: Option<Instant>,
+        request: M,
+        rpc: F,
+    ) -> Result<R>
+    where
+        M: Clone + Rpc,
+        F: Fn(RateLimiterClient<Channel>, Request<M>) -> Fut,
+        Fut: Future<Output = std::result::Result<Response<R>, Status>>,
+    {
+        let metadata = self.outbound.metadata()?;
+        let attempts = self.reconnect.run(
+            || {
+                let mut request = Request::new(request.clone());
+                *request.metadata_mut() = metadata.clone();
+                if let Some(deadline) = deadline {
+                    request.set_timeout(deadline.saturating_duration_since(Instant::now()));
+                }
+                rpc(self.inner.clone(), request)
+            },
+            |code| {
+                self.health.set(ChannelHealth::Connecting);
+                if let Some(observer) = &self.observer {
+                    observer.on_retry(M::NAME, code);
+                }
+            },
+        );
+        let result = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline.into(), attempts).await {
+                // The channel enforces `grpc-timeout` too, cancelling the
+                // call if it runs out first.
+                Ok(Err(status))
+                    if status.code() == Code::Cancelled && Instant::now() >= deadline =>
+                {
+                    Err(ClientError::DeadlineExceeded)
+                }
+                Ok(result) => result.map_err(ClientError::from),
+                Err(_) => Err(ClientError::DeadlineExceeded),
+            },
+            None => attempts.await.map_err(ClientError::from),
+        };
+        self.health.record(&result);
+        result.map(Response::into_inner)
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct LimitCheckResult {
-    pub allowed: bool,
-    pub retry_after_seconds: u32,
-    pub remaining_tokens: u64,
+/// Channel whose connections all go to the socket at `path`; the URI is a
+/// placeholder that only sets the `:authority` header.
+#[cfg(unix)]
+pub(crate) async fn unix_channel(path: &std::path::Path) -> Result<Channel> {
+    unix_channel_on(Endpoint::from_static("http://localhost"), path).await
+}
+
+/// [`unix_channel`] with the transport settings of `endpoint`.
+#[cfg(unix)]
+pub(crate) async fn unix_channel_on(endpoint: Endpoint, path: &std::path::Path) -> Result<Channel> {
+    let path = path.to_path_buf();
+    endpoint
+        .connect_with_connector(tower::service_fn(move |_: tonic::transport::Uri| {
+            let path = path.clone();
+            async move {
+                let stream = tokio::net::UnixStream::connect(path).await?;
+                Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(stream))
+            }
+        }))
+        .await
+        .map_err(|e| ClientError::ConnectionError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deadline_cuts_reconnect_attempts_short() {
+        // Nothing listens on port 1, so every attempt is UNAVAILABLE.
+        let channel = tonic::transport::Endpoint::from_static("http://127.0.0.1:1").connect_lazy();
+        let client = GuardianClient::from_channel(channel)
+            .with_reconnect(ReconnectPolicy {
+                max_retries: u32::MAX,
+                ..ReconnectPolicy::default()
+            })
+            .with_timeout(Duration::from_millis(100));
+
+        let started = Instant::now();
+        let result = client.check_limit("user1", 1).await;
+        assert!(matches!(result, Err(ClientError::DeadlineExceeded)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let result = client.check_limit_with_deadline("user1", 1, deadline).await;
+        assert!(matches!(result, Err(ClientError::DeadlineExceeded)));
+        assert!(Instant::now() < deadline + Duration::from_millis(50));
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        calls: std::sync::Mutex<Vec<(&'static str, CallOutcome)>>,
+        retries: std::sync::atomic::AtomicU32,
+    }
+
+    impl ClientObserver for Recorder {
+        fn on_call(&self, rpc: &'static str, outcome: CallOutcome, _latency: Duration) {
+            self.calls.lock().unwrap().push((rpc, outcome));
+        }
+
+        fn on_retry(&self, _rpc: &'static str, code: Code) {
+            assert_eq!(code, Code::Unavailable);
+            self.retries.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observer_sees_calls_and_retries() {
+        let channel = tonic::transport::Endpoint::from_static("http://127.0.0.1:1").connect_lazy();
+        let recorder = Arc::new(Recorder::default());
+        let client = GuardianClient::from_channel(channel)
+            .with_reconnect(ReconnectPolicy {
+                max_retries: 2,
+                initial_backoff: Duration::from_millis(1),
+                ..ReconnectPolicy::default()
+            })
+            .with_observer(Arc::clone(&recorder) as Arc<dyn ClientObserver>);
+
+        assert!(client.check_limit("user1", 1).await.is_err());
+        assert!(client.get_usage("user1").await.is_err());
+        assert_eq!(
+            *recorder.calls.lock().unwrap(),
+            [("CheckLimit", CallOutcome::Failed), ("GetUsage", CallOutcome::Failed)]
+        );
+        assert_eq!(recorder.retries.load(std::sync::atomic::Ordering::Relaxed), 4);
+    }
+
+    #[derive(Default)]
+    struct ShadowDenials(std::sync::Mutex<Vec<String>>);
+
+    impl ClientObserver for ShadowDenials {
+        fn on_call(&self, _rpc: &'static str, _outcome: CallOutcome, _latency: Duration) {}
+
+        fn on_shadow_denial(&self, client_id: &str, result: &LimitCheckResult) {
+            assert!(!result.allowed);
+            self.0.lock().unwrap().push(client_id.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shadow_mode_reports_denials_and_allows() {
+        let channel = tonic::transport::Endpoint::from_static("http://127.0.0.1:1").connect_lazy();
+        let denials = Arc::new(ShadowDenials::default());
+        let client = GuardianClient::from_channel(channel)
+            .with_denial_cache()
+            .with_shadow_mode()
+            .with_observer(Arc::clone(&denials) as Arc<dyn ClientObserver>);
+        // A denial the cache remembers is answered without the service.
+        let denied = LimitCheckResult {
+            allowed: false,
+            retry_after_seconds: 60,
+            remaining_tokens: 0,
+            limit: 10,
+        };
+        let cache = client.denial_cache().unwrap();
+        cache.record("", "", "user1", 1, &denied, Instant::now());
+
+        let result = client.check_limit_detailed("user1", 1).await.unwrap();
+        assert!(result.allowed && result.retry_after_seconds == 0);
+        assert!(client.check_limits(&[("user1", 1)]).await.unwrap()[0].allowed);
+        // Nothing was taken, so there is nothing to refund.
+        let permit = client.try_acquire("user1", 1).await.unwrap().unwrap();
+        assert!(format!("{:?}", permit).contains("committed: true"));
+        assert_eq!(*denials.0.lock().unwrap(), ["user1", "user1", "user1"]);
+    }
+
+    #[tokio::test]
+    async fn test_watch_usage_follows_the_key() {
+        let client = GuardianClient::in_process(TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(3600),
+        });
+        client.check_limit("user1", 2).await.unwrap();
+
+        let mut usage = client.watch_usage("user1", Duration::from_millis(10));
+        usage.changed().await.unwrap();
+        assert_eq!(*usage.borrow_and_update(), 2);
+
+        client.check_limit("user1", 3).await.unwrap();
+        usage.changed().await.unwrap();
+        assert_eq!(*usage.borrow_and_update(), 5);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shared_client_checks_from_many_tasks() {
+        let client = Arc::new(GuardianClient::in_process(TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(3600),
+        }));
+
+        let tasks: Vec<_> = (0..20)
+            .map(|task| {
+                // Half the tasks share the Arc, half hold their own clone.
+                if task % 2 == 0 {
+                    let client = Arc::clone(&client);
+                    tokio::spawn(async move { client.check_limit("user1", 1).await.unwrap() })
+                } else {
+                    let client = GuardianClient::clone(&client);
+                    tokio::spawn(async move { client.check_limit("user1", 1).await.unwrap() })
+                }
+            })
+            .collect();
+        let mut allowed = 0;
+        for task in tasks {
+            allowed += usize::from(task.await.unwrap());
+        }
+
+        assert_eq!(allowed, 10);
+        assert_eq!(client.get_usage("user1").await.unwrap(), 10);
+    }
 }
@@ -0,0 +1,117 @@
+// Coalescing of hot keys' checks: checks of one key that arrive within a
+// short window share a single CheckLimit call for their total cost, and
+// its answer goes to each of them. A shared call is all-or-nothing, so
+// when the total is denied while the bucket still had tokens, a second
+// call takes what the earliest checks need and only the rest are denied.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::client::LimitCheckResult;
+use crate::error::Result;
+
+/// Namespace, tier and client ID.
+pub(crate) type Key = (String, String, String);
+
+/// Checks waiting for the window to close, by key.
+#[derive(Debug)]
+pub(crate) struct Coalescer {
+    pub(crate) window: Duration,
+    open: Mutex<HashMap<Key, Batch>>,
+}
+
+#[derive(Debug, Default)]
+struct Batch {
+    total: u32,
+    waiters: Vec<Waiter>,
+}
+
+/// One check in a batch.
+#[derive(Debug)]
+pub(crate) struct Waiter {
+    pub(crate) cost: u32,
+    reply: oneshot::Sender<Result<LimitCheckResult>>,
+}
+
+impl Waiter {
+    pub(crate) fn answer(self, result: Result<LimitCheckResult>) {
+        // The check may have given up waiting.
+        let _ = self.reply.send(result);
+    }
+}
+
+impl Coalescer {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            open: Mutex::default(),
+        }
+    }
+
+    /// Add a check of `cost` to the open batch for `key`, opening one if
+    /// there is none; `true` with the answer's receiver when it did, in
+    /// which case the caller closes the batch after the window. `None`
+    /// when the batch's total would overflow, for a check to make alone.
+    pub(crate) fn join(
+        &self,
+        key: &Key,
+        cost: u32,
+    ) -> Option<(bool, oneshot::Receiver<Result<LimitCheckResult>>)> {
+        let mut open = self.open.lock().unwrap();
+        let opened = !open.contains_key(key);
+        let batch = open.entry(key.clone()).or_default();
+        batch.total = batch.total.checked_add(cost)?;
+        let (reply, answer) = oneshot::channel();
+        batch.waiters.push(Waiter { cost, reply });
+        Some((opened, answer))
+    }
+
+    /// Take the batch for `key`, its checks in the order they came, and
+    /// their total cost.
+    pub(crate) fn close(&self, key: &Key) -> (Vec<Waiter>, u32) {
+        let batch = self.open.lock().unwrap().remove(key).unwrap_or_default();
+        (batch.waiters, batch.total)
+    }
+}
+
+/// How many of `costs`, taken in order, fit in `remaining` tokens.
+pub(crate) fn affordable(costs: impl IntoIterator<Item = u32>, remaining: u64) -> usize {
+    let mut left = remaining;
+    costs
+        .into_iter()
+        .take_while(|&cost| match left.checked_sub(cost.into()) {
+            Some(rest) => {
+                left = rest;
+                true
+            }
+            None => false,
+        })
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batches_by_key_and_serves_the_earliest_checks_first() {
+        let coalescer = Coalescer::new(Duration::from_millis(1));
+        let key = |id: &str| (String::new(), String::new(), id.to_string());
+        assert!(coalescer.join(&key("a"), 2).unwrap().0);
+        assert!(!coalescer.join(&key("a"), 3).unwrap().0);
+        assert!(coalescer.join(&key("b"), 1).unwrap().0);
+        assert!(coalescer.join(&key("a"), u32::MAX).is_none());
+
+        let (waiters, total) = coalescer.close(&key("a"));
+        assert_eq!(total, 5);
+        assert_eq!(waiters.iter().map(|w| w.cost).collect::<Vec<_>>(), [2, 3]);
+        assert!(coalescer.join(&key("a"), 1).unwrap().0);
+
+        assert_eq!(affordable([2, 3, 1], 4), 1);
+        assert_eq!(affordable([2, 3, 1], 5), 2);
+        assert_eq!(affordable([2, 3], 0), 0);
+    }
+}
@@ -0,0 +1,273 @@
+// Client settings kept outside the code: a small YAML or TOML file and
+// GUARDIAN_* environment variables, read the same way by every service
+// that calls Guardian. Environment variables win over the file, so one
+// file can be shared and overridden per deployment.
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use ::config::{Config, Environment, File};
+use serde::Deserialize;
+
+use crate::builder::GuardianClientBuilder;
+use crate::client::GuardianClient;
+use crate::decision::LimitCheckResult;
+use crate::error::{ClientError, Result};
+use crate::retry::ReconnectPolicy;
+use crate::{Certificate, ClientTlsConfig, Identity};
+
+/// Environment variable naming a config file for
+/// [`GuardianClient::from_env`].
+pub const CLIENT_CONFIG_ENV: &str = "GUARDIAN_CLIENT_CONFIG";
+
+/// Prefix of the environment variables overriding each setting, such as
+/// `GUARDIAN_API_KEY` for `api_key`.
+pub const ENV_PREFIX: &str = "GUARDIAN";
+
+/// What a check returns when the service couldn't decide it: it was
+/// unreachable, didn't answer in time, or shed the call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fallback {
+    /// Return the error, for the caller to decide.
+    #[default]
+    Error,
+    /// Allow the check (fail open).
+    Allow,
+    /// Deny the check (fail closed).
+    Deny,
+}
+
+impl Fallback {
+    /// The answer to a check that failed with `error`.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn apply(self, error: ClientError) -> Result<LimitCheckResult> {
+        let allowed = match self {
+            Self::Allow if error.is_unavailable() => true,
+            Self::Deny if error.is_unavailable() => false,
+            _ => return Err(error),
+        };
+        Ok(LimitCheckResult {
+            allowed,
+            retry_after_seconds: 0,
+            remaining_tokens: 0,
+            limit: 0,
+        })
+    }
+}
+
+/// Everything needed to connect a [`GuardianClient`].
+///
+/// ```yaml
+/// endpoints: ["https://guardian-1.internal:50051", "https://guardian-2.internal:50051"]
+/// api_key: checkout-service-key
+/// tls_ca: /etc/guardian/ca.pem
+/// timeout_ms: 50
+/// fallback: allow
+/// ```
+///
+/// Each setting can also be given as `GUARDIAN_<NAME>`, e.g.
+/// `GUARDIAN_ENDPOINTS=http://a:50051,http://b:50051` or
+/// `GUARDIAN_FALLBACK=deny`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// Service addresses. Several are balanced between; a single
+    /// `unix:<path>` connects to a Unix domain socket.
+    pub endpoints: Vec<String>,
+    pub api_key: Option<String>,
+    pub bearer_token: Option<String>,
+    pub admin_token: Option<String>,
+    pub namespace: Option<String>,
+    /// PEM file of the CA to verify the service with.
+    pub tls_ca: Option<PathBuf>,
+    /// PEM files of a client certificate and its key, for mutual TLS.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    /// Name to verify the service's certificate against, when not the
+    /// endpoint's host.
+    pub tls_domain: Option<String>,
+    /// `http://` or `socks5://` proxy to connect through; see
+    /// [`GuardianClientBuilder::proxy`].
+    pub proxy: Option<String>,
+    pub connect_timeout_ms: Option<u64>,
+    /// Budget for each call, retries included.
+    pub timeout_ms: Option<u64>,
+    /// Retries while the service is unreachable; 0 disables them.
+    pub max_retries: Option<u32>,
+    pub fallback: Fallback,
+}
+
+impl ClientConfig {
+    /// Settings from the file named by [`CLIENT_CONFIG_ENV`], if set, and
+    /// `GUARDIAN_*` environment variables.
+    #[allow(clippy::result_large_err)]
+    pub fn from_env() -> Result<Self> {
+        let file = std::env::var_os(CLIENT_CONFIG_ENV).map(PathBuf::from);
+        Self::read(file.as_deref())
+    }
+
+    /// Settings from the YAML or TOML file at `path` (picked by its
+    /// extension), with `GUARDIAN_*` environment variables on top.
+    #[allow(clippy::result_large_err)]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        Self::read(Some(path.as_ref()))
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn read(file: Option<&Path>) -> Result<Self> {
+        let mut builder = Config::builder();
+        if let Some(file) = file {
+            builder = builder.add_source(File::from(file));
+        }
+        builder
+            .add_source(
+                Environment::with_prefix(ENV_PREFIX)
+                    .try_parsing(true)
+                    .list_separator(",")
+                    .with_list_parse_key("endpoints"),
+            )
+            .build()
+            .and_then(Config::try_deserialize)
+            .map_err(|e| ClientError::ConfigError(format!("invalid client config: {}", e)))
+    }
+
+    /// A builder with these settings applied, reading the TLS files.
+    #[allow(clippy::result_large_err)]
+    pub fn builder(&self) -> Result<GuardianClientBuilder> {
+        let mut builder = GuardianClient::builder().fallback(self.fallback);
+        if let Some(tls) = self.tls()? {
+            builder = builder.tls(tls);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy);
+        }
+        if let Some(key) = &self.api_key {
+            builder = builder.api_key(key);
+        }
+        if let Some(token) = &self.bearer_token {
+            builder = builder.bearer_token(token);
+        }
+        if let Some(token) = &self.admin_token {
+            builder = builder.admin_token(token);
+        }
+        if let Some(namespace) = &self.namespace {
+            builder = builder.namespace(namespace);
+        }
+        if let Some(ms) = self.connect_timeout_ms {
+            builder = builder.connect_timeout(Duration::from_millis(ms));
+        }
+        if let Some(ms) = self.timeout_ms {
+            builder = builder.timeout(Duration::from_millis(ms));
+        }
+        if let Some(max_retries) = self.max_retries {
+            builder = builder.reconnect(ReconnectPolicy {
+                max_retries,
+                ..ReconnectPolicy::default()
+            });
+        }
+        Ok(builder)
+    }
+
+    /// Connect to the configured endpoints.
+    pub async fn connect(&self) -> Result<GuardianClient> {
+        let builder = self.builder()?;
+        match self.endpoints.as_slice() {
+            [] => Err(ClientError::ConfigError(format!(
+                "no endpoints configured; set `endpoints` or {}_ENDPOINTS",
+                ENV_PREFIX
+            ))),
+            #[cfg(unix)]
+            [endpoint] if endpoint.starts_with("unix:") => {
+                builder.connect_unix(&endpoint["unix:".len()..]).await
+            }
+            [endpoint] => builder.connect(endpoint.clone()).await,
+            endpoints => builder.connect_balanced(endpoints).await,
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn tls(&self) -> Result<Option<ClientTlsConfig>> {
+        if self.tls_ca.is_none() && self.tls_cert.is_none() && self.tls_domain.is_none() {
+            return Ok(None);
+        }
+        let mut tls = ClientTlsConfig::new();
+        if let Some(ca) = &self.tls_ca {
+            tls = tls.ca_certificate(Certificate::from_pem(read_pem(ca)?));
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => {
+                tls = tls.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+            }
+            (None, None) => {}
+            _ => {
+                return Err(ClientError::ConfigError(
+                    "tls_cert and tls_key must be given together".to_string(),
+                ))
+            }
+        }
+        if let Some(domain) = &self.tls_domain {
+            tls = tls.domain_name(domain);
+        }
+        Ok(Some(tls))
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn read_pem(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path)
+        .map_err(|e| ClientError::ConfigError(format!("cannot read {}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_environment_overrides_the_file() {
+        let path =
+            std::env::temp_dir().join(format!("guardian-client-{}.yaml", std::process::id()));
+        std::fs::write(
+            &path,
+            "endpoints: [\"http://a:50051\"]\napi_key: from-file\ntimeout_ms: 50\nfallback: allow\n",
+        )
+        .unwrap();
+        std::env::set_var("GUARDIAN_ENDPOINTS", "http://b:50051,http://c:50051");
+        std::env::set_var("GUARDIAN_FALLBACK", "deny");
+        let config = ClientConfig::load(&path);
+        std::env::remove_var("GUARDIAN_ENDPOINTS");
+        std::env::remove_var("GUARDIAN_FALLBACK");
+        std::fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.endpoints, ["http://b:50051", "http://c:50051"]);
+        assert_eq!(config.api_key.as_deref(), Some("from-file"));
+        assert_eq!(config.timeout_ms, Some(50));
+        assert_eq!(config.fallback, Fallback::Deny);
+
+        let half_identity = ClientConfig {
+            tls_cert: Some(path),
+            ..ClientConfig::default()
+        };
+        assert!(matches!(
+            half_identity.builder(),
+            Err(ClientError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn test_fallback_only_covers_undecided_checks() {
+        let allowed = Fallback::Allow
+            .apply(ClientError::DeadlineExceeded)
+            .unwrap();
+        assert!(allowed.allowed);
+        let denied = Fallback::Deny.apply(ClientError::ConnectionError("refused".into()));
+        assert!(!denied.unwrap().allowed);
+        assert!(Fallback::Allow
+            .apply(ClientError::PermissionDenied("bad key".into()))
+            .is_err());
+        assert!(Fallback::Error
+            .apply(ClientError::DeadlineExceeded)
+            .is_err());
+    }
+}
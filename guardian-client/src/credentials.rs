@@ -0,0 +1,174 @@
+// Credentials sent with every call. Static ones (an API key, a long-lived
+// token) are headers set on the builder; `CallCredentials` covers those
+// that change while the client is in use, like a JWT that is refreshed
+// before it expires.
+
+// Only the clients use the internals, and wasm32 has none without `grpc-web`.
+#![cfg_attr(all(target_arch = "wasm32", not(feature = "grpc-web")), allow(dead_code))]
+
+use std::fmt;
+use std::sync::Arc;
+
+use tonic::metadata::MetadataMap;
+
+use crate::error::{ClientError, Result};
+
+/// Why credentials couldn't be added to a call.
+pub type CredentialsError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Header the service reads API keys from (see its `auth.api_keys`).
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Header the service reads bearer JWTs from, as `Bearer <token>`.
+pub const AUTHORIZATION_HEADER: &str = "authorization";
+
+/// Adds credentials to each call as it is sent, after the builder's fixed
+/// headers. Runs on every attempt, so it should be cheap: keep the current
+/// token somewhere shared and refresh it in the background rather than
+/// fetching it here.
+///
+/// Closures taking `&mut MetadataMap` implement it. An error fails the
+/// call without sending it, as [`ClientError::CredentialsError`].
+///
+/// # Examples
+///
+/// ```no_run
+/// # use guardian_client::GuardianClient;
+/// # use std::sync::{Arc, RwLock};
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// // Kept fresh by a task elsewhere.
+/// let token = Arc::new(RwLock::new(String::from("eyJ...")));
+/// let current = Arc::clone(&token);
+/// let client = GuardianClient::builder()
+///     .credentials(move |metadata: &mut tonic::metadata::MetadataMap| {
+///         let value = format!("Bearer {}", current.read().unwrap()).parse()?;
+///         metadata.insert("authorization", value);
+///         Ok(())
+///     })
+///     .connect("http://localhost:50051")
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait CallCredentials: Send + Sync + 'static {
+    fn apply(&self, metadata: &mut MetadataMap) -> std::result::Result<(), CredentialsError>;
+}
+
+impl<F> CallCredentials for F
+where
+    F: Fn(&mut MetadataMap) -> std::result::Result<(), CredentialsError> + Send + Sync + 'static,
+{
+    fn apply(&self, metadata: &mut MetadataMap) -> std::result::Result<(), CredentialsError> {
+        self(metadata)
+    }
+}
+
+/// A shared [`CallCredentials`], if one was set.
+#[derive(Clone, Default)]
+pub(crate) struct Credentials(Option<Arc<dyn CallCredentials>>);
+
+impl Credentials {
+    pub(crate) fn new(credentials: impl CallCredentials) -> Self {
+        Self(Some(Arc::new(credentials)))
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Some(..)" } else { "None" })
+    }
+}
+
+/// The metadata a client sends with every call.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Outbound {
+    fixed: MetadataMap,
+    credentials: Credentials,
+    #[cfg(all(feature = "opentelemetry", not(target_arch = "wasm32")))]
+    trace_context: bool,
+}
+
+impl Outbound {
+    pub(crate) fn new(fixed: MetadataMap, credentials: Credentials) -> Self {
+        Self {
+            fixed,
+            credentials,
+            #[cfg(all(feature = "opentelemetry", not(target_arch = "wasm32")))]
+            trace_context: false,
+        }
+    }
+
+    /// Also send the current trace context with every call.
+    #[cfg(all(feature = "opentelemetry", not(target_arch = "wasm32")))]
+    pub(crate) fn with_trace_context(mut self) -> Self {
+        self.trace_context = true;
+        self
+    }
+
+    /// Send `value` as `key` with every call from now on.
+    #[cfg(all(target_arch = "wasm32", feature = "grpc-web"))]
+    pub(crate) fn insert(&mut self, key: &'static str, value: tonic::metadata::AsciiMetadataValue) {
+        self.fixed.insert(key, value);
+    }
+
+    #[cfg(all(target_arch = "wasm32", feature = "grpc-web"))]
+    pub(crate) fn set_credentials(&mut self, credentials: Credentials) {
+        self.credentials = credentials;
+    }
+
+    /// Metadata for the next call.
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn metadata(&self) -> Result<MetadataMap> {
+        let mut metadata = self.fixed.clone();
+        if let Some(credentials) = &self.credentials.0 {
+            credentials
+                .apply(&mut metadata)
+                .map_err(|e| ClientError::CredentialsError(e.to_string()))?;
+        }
+        #[cfg(all(feature = "opentelemetry", not(target_arch = "wasm32")))]
+        if self.trace_context {
+            crate::trace::inject(&mut metadata);
+        }
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_credentials_apply_to_every_call() {
+        let mut fixed = MetadataMap::new();
+        fixed.insert(API_KEY_HEADER, "key".parse().unwrap());
+        fixed.insert(AUTHORIZATION_HEADER, "Bearer stale".parse().unwrap());
+        let calls = Arc::new(AtomicU32::new(0));
+        let counted = Arc::clone(&calls);
+        let outbound = Outbound::new(
+            fixed,
+            Credentials::new(move |metadata: &mut MetadataMap| {
+                let n = counted.fetch_add(1, Ordering::Relaxed);
+                let token = format!("Bearer token-{}", n).parse().unwrap();
+                metadata.insert(AUTHORIZATION_HEADER, token);
+                Ok(())
+            }),
+        );
+
+        let first = outbound.metadata().unwrap();
+        assert_eq!(first.get(API_KEY_HEADER).unwrap(), "key");
+        assert_eq!(first.get(AUTHORIZATION_HEADER).unwrap(), "Bearer token-0");
+        let second = outbound.metadata().unwrap();
+        assert_eq!(second.get(AUTHORIZATION_HEADER).unwrap(), "Bearer token-1");
+
+        let failing = Outbound::new(
+            MetadataMap::new(),
+            Credentials::new(|_: &mut MetadataMap| Err("no token yet".into())),
+        );
+        assert!(matches!(
+            failing.metadata(),
+            Err(ClientError::CredentialsError(_))
+        ));
+        assert!(Outbound::default().metadata().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::proto::CheckLimitResponse;
+
+/// The service's answer to one check.
+#[derive(Debug, Clone)]
+pub struct LimitCheckResult {
+    pub allowed: bool,
+    pub retry_after_seconds: u32,
+    pub remaining_tokens: u64,
+    /// Capacity of the bucket that decided; 0 if the service didn't say.
+    pub limit: u64,
+}
+
+impl LimitCheckResult {
+    /// Rate-limit headers for an HTTP response relaying this decision to
+    /// the end user, as lowercase `(name, value)` pairs ready for any HTTP
+    /// library: the IETF `ratelimit-limit`, `ratelimit-remaining` and
+    /// `ratelimit-reset`, the legacy `x-ratelimit-*` equivalents, and
+    /// `retry-after` on a denial.
+    ///
+    /// Reset values are seconds from now (not a timestamp) until a denied
+    /// request may be retried, 0 when allowed. The limit headers are left
+    /// out when the limit isn't known.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use guardian_client::GuardianClient;
+    /// # async fn example(client: GuardianClient) -> Result<(), Box<dyn std::error::Error>> {
+    /// let decision = client.check_limit_detailed("user123", 1).await?;
+    /// for (name, value) in decision.to_headers() {
+    ///     println!("{}: {}", name, value);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_headers(&self) -> Vec<(&'static str, String)> {
+        let reset = self.retry_after_seconds.to_string();
+        let remaining = self.remaining_tokens.to_string();
+        let mut headers = Vec::with_capacity(7);
+        if self.limit > 0 {
+            headers.push(("ratelimit-limit", self.limit.to_string()));
+        }
+        headers.push(("ratelimit-remaining", remaining.clone()));
+        headers.push(("ratelimit-reset", reset.clone()));
+        if self.limit > 0 {
+            headers.push(("x-ratelimit-limit", self.limit.to_string()));
+        }
+        headers.push(("x-ratelimit-remaining", remaining));
+        headers.push(("x-ratelimit-reset", reset.clone()));
+        if !self.allowed {
+            headers.push(("retry-after", reset));
+        }
+        headers
+    }
+}
+
+impl From<CheckLimitResponse> for LimitCheckResult {
+    fn from(response: CheckLimitResponse) -> Self {
+        Self {
+            allowed: response.allowed,
+            retry_after_seconds: response.retry_after_seconds,
+            remaining_tokens: response.remaining_tokens,
+            limit: response.limit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_headers() {
+        let denied = LimitCheckResult {
+            allowed: false,
+            retry_after_seconds: 3,
+            remaining_tokens: 0,
+            limit: 100,
+        };
+        assert_eq!(
+            denied.to_headers(),
+            [
+                ("ratelimit-limit", "100".to_string()),
+                ("ratelimit-remaining", "0".to_string()),
+                ("ratelimit-reset", "3".to_string()),
+                ("x-ratelimit-limit", "100".to_string()),
+                ("x-ratelimit-remaining", "0".to_string()),
+                ("x-ratelimit-reset", "3".to_string()),
+                ("retry-after", "3".to_string()),
+            ]
+        );
+
+        let allowed = LimitCheckResult {
+            allowed: true,
+            retry_after_seconds: 0,
+            remaining_tokens: 7,
+            limit: 0,
+        };
+        let names: Vec<_> = allowed
+            .to_headers()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "ratelimit-remaining",
+                "ratelimit-reset",
+                "x-ratelimit-remaining",
+                "x-ratelimit-reset"
+            ]
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::client::LimitCheckResult;
+
+/// Expired entries are swept once the cache holds this many keys.
+const SWEEP_AT: usize = 10_000;
+
+/// Denials remembered by a [`GuardianClient`](crate::GuardianClient) with
+/// [`with_denial_cache`](crate::GuardianClient::with_denial_cache). When
+/// the service denies a check and says when to retry, further checks of
+/// the key at that cost or more are denied locally until then, so a
+/// client being throttled doesn't cost a round trip per request.
+///
+/// A denial only speaks for costs at least as large as the one denied; a
+/// cheaper check still goes to the service. Resetting a key through the
+/// client forgets its denials.
+#[derive(Debug, Default)]
+pub struct DenialCache {
+    denied: Mutex<HashMap<Key, Denial>>,
+}
+
+/// Namespace, tier and client ID.
+type Key = (String, String, String);
+
+#[derive(Debug, Clone, Copy)]
+struct Denial {
+    cost: u32,
+    until: Instant,
+    limit: u64,
+}
+
+impl DenialCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The local answer for a check, if a recorded denial still covers it.
+    pub(crate) fn lookup(
+        &self,
+        namespace: &str,
+        tier: &str,
+        client_id: &str,
+        cost: u32,
+        now: Instant,
+    ) -> Option<LimitCheckResult> {
+        let key = (
+            namespace.to_string(),
+            tier.to_string(),
+            client_id.to_string(),
+        );
+        let denied = self.denied.lock().unwrap();
+        let denial = denied
+            .get(&key)
+            .filter(|d| d.until > now && cost >= d.cost)?;
+        let left = denial.until - now;
+        Some(LimitCheckResult {
+            allowed: false,
+            retry_after_seconds: left.as_secs_f64().ceil() as u32,
+            remaining_tokens: 0,
+            limit: denial.limit,
+        })
+    }
+
+    /// Remember `result` if it is a denial that says when to retry; an
+    /// allowed check clears what was remembered for the key.
+    pub(crate) fn record(
+        &self,
+        namespace: &str,
+        tier: &str,
+        client_id: &str,
+        cost: u32,
+        result: &LimitCheckResult,
+        now: Instant,
+    ) {
+        let key = (
+            namespace.to_string(),
+            tier.to_string(),
+            client_id.to_string(),
+        );
+        let mut denied = self.denied.lock().unwrap();
+        if result.allowed {
+            denied.remove(&key);
+            return;
+        }
+        if result.retry_after_seconds == 0 {
+            return;
+        }
+        if denied.len() >= SWEEP_AT {
+            denied.retain(|_, d| d.until > now);
+        }
+        let denial = Denial {
+            cost,
+            until: now + Duration::from_secs(u64::from(result.retry_after_seconds)),
+            limit: result.limit,
+        };
+        denied
+            .entry(key)
+            .and_modify(|d| {
+                if d.until <= now || cost < d.cost {
+                    *d = denial;
+                }
+            })
+            .or_insert(denial);
+    }
+
+    /// Forget every denial of `client_id` in `namespace`, whatever the tier.
+    pub(crate) fn forget(&self, namespace: &str, client_id: &str) {
+        self.denied
+            .lock()
+            .unwrap()
+            .retain(|(ns, _, id), _| !(ns == namespace && id == client_id));
+    }
+
+    /// Keys with a denial on record, including expired ones not yet swept.
+    pub fn len(&self) -> usize {
+        self.denied.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(allowed: bool, retry_after_seconds: u32) -> LimitCheckResult {
+        LimitCheckResult {
+            allowed,
+            retry_after_seconds,
+            remaining_tokens: 0,
+            limit: 10,
+        }
+    }
+
+    #[test]
+    fn test_short_circuits_until_retry_after() {
+        let cache = DenialCache::new();
+        let now = Instant::now();
+        cache.record("", "", "user1", 5, &result(false, 2), now);
+        // No retry time, nothing to go on.
+        cache.record("", "", "user2", 1, &result(false, 0), now);
+        assert_eq!(cache.len(), 1);
+
+        let later = now + Duration::from_millis(500);
+        let local = cache.lookup("", "", "user1", 5, later).unwrap();
+        assert!(!local.allowed);
+        assert_eq!(local.retry_after_seconds, 2);
+        assert_eq!(local.limit, 10);
+        assert!(cache.lookup("", "", "user1", 8, later).is_some());
+        // Cheaper checks, other tiers and namespaces still ask the service.
+        assert!(cache.lookup("", "", "user1", 1, later).is_none());
+        assert!(cache.lookup("", "gold", "user1", 5, later).is_none());
+        assert!(cache.lookup("billing", "", "user1", 5, later).is_none());
+        assert!(cache
+            .lookup("", "", "user1", 5, now + Duration::from_secs(2))
+            .is_none());
+
+        // A cheaper denial widens what is covered.
+        cache.record("", "", "user1", 1, &result(false, 1), later);
+        assert!(cache.lookup("", "", "user1", 1, later).is_some());
+
+        cache.record("", "", "user1", 1, &result(true, 0), later);
+        assert!(cache.lookup("", "", "user1", 5, later).is_none());
+
+        cache.record("", "", "user3", 1, &result(false, 9), now);
+        cache.record("", "gold", "user3", 1, &result(false, 9), now);
+        cache.forget("", "user3");
+        assert!(cache.is_empty());
+    }
+}
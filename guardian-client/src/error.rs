@@ -1,13 +1,50 @@
+use std::time::Duration;
+
 use thiserror::Error;
-use tonic::Status;
+use tonic::{Code, Status};
+
+/// Metadata key in which the service suggests, in seconds, when to try
+/// again after shedding a call.
+pub const RETRY_AFTER_HEADER: &str = "retry-after";
 
-#[derive(Error, Debug)]
+/// Calls that fail with a gRPC status come back as the variant for its
+/// code where the client has one (`UNAVAILABLE`, `PERMISSION_DENIED` and
+/// `UNAUTHENTICATED`, `INVALID_ARGUMENT`, `RESOURCE_EXHAUSTED`,
+/// `DEADLINE_EXCEEDED`), and as [`RpcError`](Self::RpcError) otherwise.
+#[derive(Error, Debug, Clone)]
 pub enum ClientError {
     #[error("Connection error: {0}")]
     ConnectionError(String),
 
     #[error("RPC error: {0}")]
-    RpcError(#[from] Status),
+    RpcError(Status),
+
+    /// The service couldn't be reached, even after the client's retries.
+    #[error("Service unavailable: {message}")]
+    Unavailable {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+
+    /// The credentials were missing or rejected, or don't allow the call.
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    /// The service rejected the request itself, such as an unknown tier
+    /// or a namespace the caller may not use.
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// The service shed the call to protect itself; not a rate-limit
+    /// decision about the client.
+    #[error("Server overloaded: {message}")]
+    ServerOverloaded {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+
+    #[error("Deadline exceeded before the service answered")]
+    DeadlineExceeded,
 
     #[error("Rate limited - request denied")]
     RateLimited,
@@ -15,8 +52,104 @@ pub enum ClientError {
     #[error("Failed to reset limit")]
     ResetFailed,
 
+    #[error("Failed to refund tokens: {0}")]
+    RefundFailed(String),
+
+    #[error("Admin operation failed: {0}")]
+    AdminFailed(String),
+
+    #[error("Could not add credentials to the call: {0}")]
+    CredentialsError(String),
+
     #[error("Invalid configuration: {0}")]
     ConfigError(String),
 }
 
+impl ClientError {
+    /// How long the service asked callers to wait before trying again,
+    /// if it said.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Self::Unavailable { retry_after, .. } | Self::ServerOverloaded { retry_after, .. } => {
+                *retry_after
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the service couldn't decide the call: it was unreachable,
+    /// didn't answer in time, or shed the call.
+    pub fn is_unavailable(&self) -> bool {
+        matches!(
+            self,
+            Self::ConnectionError(_)
+                | Self::Unavailable { .. }
+                | Self::ServerOverloaded { .. }
+                | Self::DeadlineExceeded
+        )
+    }
+}
+
+impl From<Status> for ClientError {
+    fn from(status: Status) -> Self {
+        let message = status.message().to_string();
+        match status.code() {
+            Code::Unavailable => Self::Unavailable {
+                retry_after: retry_after(&status),
+                message,
+            },
+            Code::PermissionDenied | Code::Unauthenticated => Self::PermissionDenied(message),
+            Code::InvalidArgument => Self::InvalidArgument(message),
+            Code::ResourceExhausted => Self::ServerOverloaded {
+                retry_after: retry_after(&status),
+                message,
+            },
+            Code::DeadlineExceeded => Self::DeadlineExceeded,
+            _ => Self::RpcError(status),
+        }
+    }
+}
+
+/// The `retry-after` seconds sent with `status`, if any.
+fn retry_after(status: &Status) -> Option<Duration> {
+    let value = status.metadata().get(RETRY_AFTER_HEADER)?.to_str().ok()?;
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
 pub type Result<T> = std::result::Result<T, ClientError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maps_statuses_to_kinds() {
+        let mut shed = Status::resource_exhausted("server overloaded: too many requests");
+        shed.metadata_mut()
+            .insert(RETRY_AFTER_HEADER, "2".parse().unwrap());
+        let shed = ClientError::from(shed);
+        assert!(matches!(shed, ClientError::ServerOverloaded { .. }));
+        assert_eq!(shed.retry_after(), Some(Duration::from_secs(2)));
+
+        let down = ClientError::from(Status::unavailable("connection refused"));
+        assert!(matches!(down, ClientError::Unavailable { .. }));
+        assert_eq!(down.retry_after(), None);
+
+        assert!(matches!(
+            ClientError::from(Status::unauthenticated("Unknown API key")),
+            ClientError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            ClientError::from(Status::invalid_argument("unknown tier")),
+            ClientError::InvalidArgument(message) if message == "unknown tier"
+        ));
+        assert!(matches!(
+            ClientError::from(Status::deadline_exceeded("slow")),
+            ClientError::DeadlineExceeded
+        ));
+        assert!(matches!(
+            ClientError::from(Status::unimplemented("no batch")),
+            ClientError::RpcError(status) if status.code() == Code::Unimplemented
+        ));
+    }
+}
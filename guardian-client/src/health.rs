@@ -0,0 +1,151 @@
+// What the client knows about its link to the service, so applications
+// can fail open before a check times out on a degraded link. Every call
+// updates it: an answer, even an error status, means the service is
+// there; a retry means the channel is reconnecting; a call that never
+// reached the service means it isn't. A background prober can ask the
+// service's grpc.health.v1 status between calls too, for clients that
+// call rarely.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tonic::transport::Channel;
+use tonic_health::pb::health_client::HealthClient;
+
+use crate::balance;
+use crate::error::ClientError;
+
+/// The state of a client's link to the service; see
+/// [`GuardianClient::health`](crate::GuardianClient::health).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelHealth {
+    /// Nothing heard from the service yet, or a call is being retried
+    /// while the channel reconnects.
+    Connecting,
+    /// The service answered the latest call or probe.
+    Ready,
+    /// The latest call or probe didn't reach the service in time, or the
+    /// service said it isn't serving.
+    Failure,
+}
+
+impl ChannelHealth {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Connecting => "connecting",
+            Self::Ready => "ready",
+            Self::Failure => "failure",
+        }
+    }
+
+    fn from_u8(n: u8) -> Self {
+        match n {
+            1 => Self::Ready,
+            2 => Self::Failure,
+            _ => Self::Connecting,
+        }
+    }
+}
+
+/// The latest [`ChannelHealth`], shared by a client and its clones.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HealthState(Arc<AtomicU8>);
+
+impl HealthState {
+    pub(crate) fn get(&self) -> ChannelHealth {
+        ChannelHealth::from_u8(self.0.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn set(&self, health: ChannelHealth) {
+        self.0.store(health as u8, Ordering::Relaxed);
+    }
+
+    /// Note how a call ended. Errors raised before anything was sent,
+    /// such as credentials that couldn't be fetched, say nothing about
+    /// the link and are ignored.
+    pub(crate) fn record<T>(&self, result: &Result<T, ClientError>) {
+        match result {
+            Ok(_) => self.set(ChannelHealth::Ready),
+            Err(
+                ClientError::ConnectionError(_)
+                | ClientError::Unavailable { .. }
+                | ClientError::DeadlineExceeded,
+            ) => self.set(ChannelHealth::Failure),
+            Err(ClientError::CredentialsError(_) | ClientError::ConfigError(_)) => {}
+            Err(_) => self.set(ChannelHealth::Ready),
+        }
+    }
+
+    /// Probe the service over `channel` every `interval` and note the
+    /// answer, until the last client sharing this state is dropped.
+    pub(crate) fn probe_every(&self, channel: Channel, interval: Duration) {
+        let state = Arc::downgrade(&self.0);
+        tokio::spawn(async move {
+            let mut health = HealthClient::new(channel);
+            loop {
+                tokio::time::sleep(interval).await;
+                if state.strong_count() == 0 {
+                    return;
+                }
+                let serving = balance::probe(&mut health, interval).await;
+                let Some(state) = state.upgrade() else {
+                    return;
+                };
+                HealthState(state).set(if serving {
+                    ChannelHealth::Ready
+                } else {
+                    ChannelHealth::Failure
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_proto::rate_limiter_server::SERVICE_NAME;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Endpoint;
+
+    #[test]
+    fn test_calls_that_reach_the_service_mean_ready() {
+        let state = HealthState::default();
+        assert_eq!(state.get(), ChannelHealth::Connecting);
+        state.record(&Err::<(), _>(ClientError::DeadlineExceeded));
+        assert_eq!(state.get(), ChannelHealth::Failure);
+        state.record(&Err::<(), _>(ClientError::CredentialsError("expired".into())));
+        assert_eq!(state.get(), ChannelHealth::Failure);
+        state.record(&Err::<(), _>(ClientError::PermissionDenied("no".into())));
+        assert_eq!(state.get(), ChannelHealth::Ready);
+    }
+
+    #[tokio::test]
+    async fn test_prober_follows_the_services_health() {
+        let (mut reporter, health) = tonic_health::server::health_reporter();
+        reporter
+            .set_service_status(SERVICE_NAME, tonic_health::ServingStatus::Serving)
+            .await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint =
+            Endpoint::from_shared(format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(health)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let interval = Duration::from_millis(50);
+        let state = HealthState::default();
+        state.probe_every(endpoint.connect_lazy(), interval);
+        tokio::time::sleep(interval * 3).await;
+        assert_eq!(state.get(), ChannelHealth::Ready);
+
+        reporter
+            .set_service_status(SERVICE_NAME, tonic_health::ServingStatus::NotServing)
+            .await;
+        tokio::time::sleep(interval * 3).await;
+        assert_eq!(state.get(), ChannelHealth::Failure);
+    }
+}
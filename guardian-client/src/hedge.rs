@@ -0,0 +1,163 @@
+// Hedged checks: a check that one instance is slow to answer is sent to a
+// second instance too, and the first answer wins. Both calls run as tasks
+// of their own so the slower one can finish after the caller has moved
+// on, and whatever it took can be given back.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::{JoinError, JoinHandle};
+use tonic::transport::Channel;
+
+use crate::balance::Instance;
+use crate::error::{ClientError, Result};
+
+/// Where and when to hedge.
+#[derive(Clone)]
+pub(crate) struct Hedge {
+    pub(crate) after: Duration,
+    instances: Arc<[Instance]>,
+    next: Arc<AtomicUsize>,
+}
+
+impl Hedge {
+    pub(crate) fn new(after: Duration, instances: Vec<Instance>) -> Self {
+        Self {
+            after,
+            instances: instances.into(),
+            next: Arc::default(),
+        }
+    }
+
+    /// Two different serving instances for the next check, taken in turn;
+    /// `None` while fewer than two are serving.
+    pub(crate) fn pick(&self) -> Option<(Channel, Channel)> {
+        let serving: Vec<&Instance> = self.instances.iter().filter(|i| i.serving()).collect();
+        if serving.len() < 2 {
+            return None;
+        }
+        let first = self.next.fetch_add(1, Ordering::Relaxed) % serving.len();
+        let second = (first + 1) % serving.len();
+        Some((
+            serving[first].channel.clone(),
+            serving[second].channel.clone(),
+        ))
+    }
+}
+
+/// Run `first`; if it hasn't finished after `after`, start `second()` as
+/// well (telling `on_hedge`). The first success is returned, or if both
+/// fail, the later failure. A success that loses the race goes to
+/// `wasted` once it arrives.
+pub(crate) async fn race<T, A, B>(
+    after: Duration,
+    first: A,
+    second: impl FnOnce() -> B,
+    on_hedge: impl FnOnce(),
+    wasted: impl FnOnce(T) + Send + 'static,
+) -> Result<T>
+where
+    T: Send + 'static,
+    A: Future<Output = Result<T>> + Send + 'static,
+    B: Future<Output = Result<T>> + Send + 'static,
+{
+    let mut first = tokio::spawn(first);
+    tokio::select! {
+        done = &mut first => return joined(done),
+        _ = tokio::time::sleep(after) => {}
+    }
+    on_hedge();
+    let mut second = tokio::spawn(second());
+    let (result, other): (_, JoinHandle<Result<T>>) = tokio::select! {
+        done = &mut first => (joined(done), second),
+        done = &mut second => (joined(done), first),
+    };
+    match result {
+        Ok(value) => {
+            tokio::spawn(async move {
+                if let Ok(Ok(late)) = other.await {
+                    wasted(late);
+                }
+            });
+            Ok(value)
+        }
+        Err(_) => joined(other.await),
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn joined<T>(done: std::result::Result<Result<T>, JoinError>) -> Result<T> {
+    done.map_err(|e| ClientError::ConnectionError(e.to_string()))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    async fn answer(after_ms: u64, value: Result<u32>) -> Result<u32> {
+        tokio::time::sleep(Duration::from_millis(after_ms)).await;
+        value
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_first_answer_wins_and_the_loser_is_handed_back() {
+        let after = Duration::from_millis(10);
+        let hedged = Arc::new(AtomicUsize::new(0));
+        let wasted = Arc::new(Mutex::new(Vec::new()));
+        let hedge = || {
+            let hedged = Arc::clone(&hedged);
+            move || {
+                hedged.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        let waste = || {
+            let wasted = Arc::clone(&wasted);
+            move |late| wasted.lock().unwrap().push(late)
+        };
+
+        // Fast enough: no hedge.
+        let fast = race(
+            after,
+            answer(5, Ok(1)),
+            || answer(0, Ok(2)),
+            hedge(),
+            waste(),
+        );
+        assert_eq!(fast.await.unwrap(), 1);
+        assert_eq!(hedged.load(Ordering::Relaxed), 0);
+
+        // Slow first instance: the hedge answers, the late answer is wasted.
+        let slow = race(
+            after,
+            answer(100, Ok(1)),
+            || answer(5, Ok(2)),
+            hedge(),
+            waste(),
+        );
+        assert_eq!(slow.await.unwrap(), 2);
+        assert_eq!(hedged.load(Ordering::Relaxed), 1);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(*wasted.lock().unwrap(), [1]);
+
+        // A failure doesn't win over an answer still coming.
+        let failing = race(
+            after,
+            answer(20, Err(ClientError::RateLimited)),
+            || answer(50, Ok(3)),
+            hedge(),
+            waste(),
+        );
+        assert_eq!(failing.await.unwrap(), 3);
+        let both = race(
+            after,
+            answer(20, Err(ClientError::RateLimited)),
+            || answer(5, Err(ClientError::DeadlineExceeded)),
+            hedge(),
+            waste(),
+        );
+        assert!(matches!(both.await, Err(ClientError::RateLimited)));
+    }
+}
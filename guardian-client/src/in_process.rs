@@ -0,0 +1,567 @@
+// A Guardian service emulated inside the process, for development and
+// tests that shouldn't need a running server. It answers the client's own
+// RPCs from a guardian-core `MemoryBackend` with one limit for every key,
+// over in-memory connections, so the client behaves exactly as it would
+// against the real service: retries, denial cache, permits and all.
+// Tiers, the admin RPCs and status streams aren't emulated.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use guardian_core::{
+    BucketSnapshot, DecisionReason, GrantLedger, LimitResult, MemoryBackend, RateLimiter,
+    TokenBucketConfig,
+};
+use hyper_util::rt::TokioIo;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::{Channel, Endpoint, Server, Uri};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::proto::rate_limiter_server::{RateLimiter as RateLimiterRpc, RateLimiterServer};
+use crate::proto::*;
+
+/// Bytes buffered each way on an in-memory connection.
+const BUFFER: usize = 64 * 1024;
+
+/// Lifetime of a lease whose request leaves it 0, as in the service.
+const DEFAULT_LEASE_TTL: Duration = Duration::from_secs(10);
+
+/// A channel to a fresh emulated service limiting every key by `config`.
+/// The service runs as a task on the current runtime until the channel
+/// and its clones are dropped.
+pub(crate) fn channel(config: TokenBucketConfig) -> Channel {
+    let emulator = Emulator(
+        Arc::new(RateLimiter::new(MemoryBackend::new(config), false)),
+        Arc::default(),
+    );
+    let (connections, incoming) = mpsc::unbounded_channel();
+    tokio::spawn(
+        Server::builder()
+            .add_service(RateLimiterServer::new(emulator))
+            .serve_with_incoming(UnboundedReceiverStream::new(incoming)),
+    );
+    Endpoint::from_static("http://in-process").connect_with_connector_lazy(tower::service_fn(
+        move |_: Uri| {
+            let (client, server) = tokio::io::duplex(BUFFER);
+            let sent = connections
+                .send(Ok::<_, std::io::Error>(server))
+                .map_err(|_| std::io::Error::other("in-process service stopped"));
+            async move { sent.map(|()| TokioIo::new(client)) }
+        },
+    ))
+}
+
+/// The limiter, and the grants of the checks it allowed.
+struct Emulator(Arc<RateLimiter<MemoryBackend>>, Arc<GrantLedger>);
+
+/// The backend key for `client_id` in `namespace`, as the service has it.
+pub(crate) fn scoped(namespace: &str, client_id: &str) -> String {
+    if namespace.is_empty() {
+        client_id.to_string()
+    } else {
+        format!("{}/{}", namespace, client_id)
+    }
+}
+
+fn internal(what: &str, e: impl std::fmt::Display) -> Status {
+    Status::internal(format!("{}: {}", what, e))
+}
+
+fn lease_ttl(ttl_ms: u32) -> Duration {
+    match ttl_ms {
+        0 => DEFAULT_LEASE_TTL,
+        ms => Duration::from_millis(ms.into()),
+    }
+}
+
+fn unix_millis(at: SystemTime) -> i64 {
+    at.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+impl Emulator {
+    /// The answer to one check.
+    async fn decide(&self, req: &CheckLimitRequest) -> Result<CheckLimitResponse, Status> {
+        let key = Self::check_key(req)?;
+        let started = Instant::now();
+        let decided = self
+            .0
+            .check_limit_explained(&key, req.cost.max(1).into())
+            .await
+            .map_err(|e| internal("Rate limiter error", e))?;
+        Ok(self.respond(req, &key, decided, started))
+    }
+
+    /// The backend key a check names, checked as the service checks it.
+    #[allow(clippy::result_large_err)]
+    fn check_key(req: &CheckLimitRequest) -> Result<String, Status> {
+        if !req.tier.is_empty() {
+            return Err(Status::invalid_argument(format!(
+                "unknown tier '{}'",
+                req.tier
+            )));
+        }
+        Ok(scoped(&req.namespace, &req.client_id))
+    }
+
+    /// The response to `req` once the limiter has decided it.
+    fn respond(
+        &self,
+        req: &CheckLimitRequest,
+        key: &str,
+        (result, bucket, reason): (LimitResult, Option<BucketSnapshot>, DecisionReason),
+        started: Instant,
+    ) -> CheckLimitResponse {
+        let cost = req.cost.max(1).into();
+        let (allowed, retry_after_seconds) = match result {
+            LimitResult::Allowed => (true, 0),
+            LimitResult::Denied { retry_after } => (false, retry_after.as_secs_f64().ceil() as u32),
+        };
+        let grant_id = (allowed && req.refundable)
+            .then(|| self.1.issue(key, cost))
+            .flatten()
+            .unwrap_or_default();
+        CheckLimitResponse {
+            allowed,
+            retry_after_seconds,
+            remaining_tokens: bucket.map_or(0, |bucket| bucket.remaining),
+            limit: bucket.map_or(0, |bucket| bucket.capacity),
+            reason: reason.to_string(),
+            metadata: Some(LimitMetadata {
+                node_id: "in-process".to_string(),
+                from_cache: false,
+                latency_us: started.elapsed().as_micros() as u64,
+                is_global: false,
+            }),
+            grant_id,
+        }
+    }
+
+    /// The backend key a lease RPC names, checked as the service checks it.
+    #[allow(clippy::result_large_err)]
+    fn lease_key(
+        namespace: &str,
+        client_id: &str,
+        tier: &str,
+        lease_id: u64,
+    ) -> Result<String, Status> {
+        if !tier.is_empty() {
+            return Err(Status::invalid_argument(format!("unknown tier '{}'", tier)));
+        }
+        if lease_id == 0 {
+            return Err(Status::invalid_argument("lease_id is required"));
+        }
+        Ok(scoped(namespace, client_id))
+    }
+
+    /// Give back what the check that returned `req.grant_id` took, as the
+    /// service does: once, and never more than it was charged.
+    async fn refund(&self, req: &RefundTokensRequest) -> Result<RefundTokensResponse, Status> {
+        let key = scoped(&req.namespace, &req.client_id);
+        let tokens = self
+            .1
+            .redeem(&req.grant_id, &key, req.cost.into())
+            .ok_or_else(|| Status::permission_denied("Unknown, expired or already used grant"))?;
+        Ok(match self.0.refund(&key, tokens).await {
+            Ok(()) => RefundTokensResponse {
+                success: true,
+                message: format!("Refunded {} tokens", tokens),
+            },
+            Err(e) => RefundTokensResponse {
+                success: false,
+                message: format!("Failed to refund tokens: {}", e),
+            },
+        })
+    }
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl RateLimiterRpc for Emulator {
+    type StreamLimitStatusStream = ResponseStream<LimitStatusUpdate>;
+    type CheckLimitStreamStream = ResponseStream<CheckLimitStreamResponse>;
+
+    async fn check_limit(
+        &self,
+        request: Request<CheckLimitRequest>,
+    ) -> Result<Response<CheckLimitResponse>, Status> {
+        self.decide(request.get_ref()).await.map(Response::new)
+    }
+
+    async fn check_limit_batch(
+        &self,
+        request: Request<CheckLimitBatchRequest>,
+    ) -> Result<Response<CheckLimitBatchResponse>, Status> {
+        let mut results = Vec::new();
+        for check in &request.get_ref().checks {
+            results.push(self.decide(check).await?);
+        }
+        Ok(Response::new(CheckLimitBatchResponse { results }))
+    }
+
+    async fn check_limit_all(
+        &self,
+        request: Request<CheckLimitBatchRequest>,
+    ) -> Result<Response<CheckLimitBatchResponse>, Status> {
+        let checks = &request.get_ref().checks;
+        let keys = checks
+            .iter()
+            .map(Self::check_key)
+            .collect::<Result<Vec<_>, _>>()?;
+        let entries: Vec<(&str, u64)> = keys
+            .iter()
+            .zip(checks)
+            .map(|(key, check)| (key.as_str(), check.cost.max(1).into()))
+            .collect();
+        let started = Instant::now();
+        let decided = self
+            .0
+            .check_all(&entries)
+            .await
+            .map_err(|e| internal("Rate limiter error", e))?;
+        let results = checks
+            .iter()
+            .zip(&keys)
+            .zip(decided)
+            .map(|((check, key), decided)| self.respond(check, key, decided, started))
+            .collect();
+        Ok(Response::new(CheckLimitBatchResponse { results }))
+    }
+
+    async fn check_limit_stream(
+        &self,
+        request: Request<Streaming<CheckLimitStreamRequest>>,
+    ) -> Result<Response<Self::CheckLimitStreamStream>, Status> {
+        let mut inbound = request.into_inner();
+        let emulator = Emulator(Arc::clone(&self.0), Arc::clone(&self.1));
+        let (answers, outbound) = mpsc::channel(64);
+        tokio::spawn(async move {
+            while let Some(message) = inbound.next().await {
+                // As in the service, a failed check is answered under its
+                // request id; only a broken inbound stream ends the call.
+                let answer = match message {
+                    Ok(message) => {
+                        let check = message.check.unwrap_or_default();
+                        let (result, error) = match emulator.decide(&check).await {
+                            Ok(result) => (Some(result), None),
+                            Err(status) => (
+                                None,
+                                Some(CheckLimitStreamError {
+                                    code: status.code() as i32,
+                                    message: status.message().to_string(),
+                                }),
+                            ),
+                        };
+                        Ok(CheckLimitStreamResponse {
+                            request_id: message.request_id,
+                            result,
+                            error,
+                        })
+                    }
+                    Err(status) => Err(status),
+                };
+                let failed = answer.is_err();
+                if answers.send(answer).await.is_err() || failed {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(outbound))))
+    }
+
+    async fn get_usage(
+        &self,
+        request: Request<GetUsageRequest>,
+    ) -> Result<Response<GetUsageResponse>, Status> {
+        let req = request.into_inner();
+        let key = scoped(&req.namespace, &req.client_id);
+        let used_tokens = self
+            .0
+            .get_usage(&key)
+            .await
+            .map_err(|e| internal("Failed to get usage", e))?;
+        let bucket = self
+            .0
+            .inspect(&key)
+            .await
+            .map_err(|e| internal("Failed to get usage", e))?;
+        let last_refill = bucket.and_then(|bucket| bucket.last_refill);
+        Ok(Response::new(GetUsageResponse {
+            used_tokens,
+            total_capacity: bucket.map_or(0, |bucket| bucket.capacity),
+            refill_rate: bucket.map_or(0, |bucket| bucket.refill_rate),
+            last_refill_timestamp: last_refill
+                .and_then(|at| at.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_secs() as i64),
+        }))
+    }
+
+    async fn reset_limit(
+        &self,
+        request: Request<ResetLimitRequest>,
+    ) -> Result<Response<ResetLimitResponse>, Status> {
+        let req = request.into_inner();
+        let reset = self.0.reset(&scoped(&req.namespace, &req.client_id)).await;
+        Ok(Response::new(match reset {
+            Ok(()) => ResetLimitResponse {
+                success: true,
+                message: "Rate limit reset successfully".to_string(),
+            },
+            Err(e) => ResetLimitResponse {
+                success: false,
+                message: format!("Failed to reset limit: {}", e),
+            },
+        }))
+    }
+
+    async fn refund_tokens(
+        &self,
+        request: Request<RefundTokensRequest>,
+    ) -> Result<Response<RefundTokensResponse>, Status> {
+        self.refund(request.get_ref()).await.map(Response::new)
+    }
+
+    async fn acquire_lease(
+        &self,
+        request: Request<AcquireLeaseRequest>,
+    ) -> Result<Response<AcquireLeaseResponse>, Status> {
+        let req = request.into_inner();
+        let key = Self::lease_key(&req.namespace, &req.client_id, &req.tier, req.lease_id)?;
+        if req.tokens == 0 {
+            return Err(Status::invalid_argument("tokens must be positive"));
+        }
+        let lease = self
+            .0
+            .acquire_lease(&key, req.lease_id, req.tokens, lease_ttl(req.ttl_ms))
+            .await
+            .map_err(|e| internal("Failed to acquire lease", e))?;
+        Ok(Response::new(match lease {
+            Some(lease) => AcquireLeaseResponse {
+                granted: true,
+                tokens: lease.tokens,
+                expires_at_ms: unix_millis(lease.expires_at),
+            },
+            None => AcquireLeaseResponse::default(),
+        }))
+    }
+
+    async fn renew_lease(
+        &self,
+        request: Request<RenewLeaseRequest>,
+    ) -> Result<Response<RenewLeaseResponse>, Status> {
+        let req = request.into_inner();
+        let key = Self::lease_key(&req.namespace, &req.client_id, &req.tier, req.lease_id)?;
+        let lease = self
+            .0
+            .renew_lease(&key, req.lease_id, lease_ttl(req.ttl_ms))
+            .await
+            .map_err(|e| internal("Failed to renew lease", e))?;
+        Ok(Response::new(match lease {
+            Some(lease) => RenewLeaseResponse {
+                renewed: true,
+                tokens: lease.tokens,
+                expires_at_ms: unix_millis(lease.expires_at),
+            },
+            None => RenewLeaseResponse::default(),
+        }))
+    }
+
+    async fn return_lease(
+        &self,
+        request: Request<ReturnLeaseRequest>,
+    ) -> Result<Response<ReturnLeaseResponse>, Status> {
+        let req = request.into_inner();
+        let key = Self::lease_key(&req.namespace, &req.client_id, &req.tier, req.lease_id)?;
+        let returned = self
+            .0
+            .return_lease(&key, req.lease_id, req.unused)
+            .await
+            .map_err(|e| internal("Failed to return lease", e))?;
+        Ok(Response::new(ReturnLeaseResponse { returned }))
+    }
+
+    async fn stream_limit_status(
+        &self,
+        _request: Request<StreamLimitRequest>,
+    ) -> Result<Response<Self::StreamLimitStatusStream>, Status> {
+        Err(not_emulated("StreamLimitStatus"))
+    }
+
+    async fn set_limit_config(
+        &self,
+        _request: Request<SetLimitConfigRequest>,
+    ) -> Result<Response<SetLimitConfigResponse>, Status> {
+        Err(not_emulated("SetLimitConfig"))
+    }
+
+    async fn get_limit_config(
+        &self,
+        _request: Request<GetLimitConfigRequest>,
+    ) -> Result<Response<GetLimitConfigResponse>, Status> {
+        Err(not_emulated("GetLimitConfig"))
+    }
+
+    async fn delete_limit_config(
+        &self,
+        _request: Request<DeleteLimitConfigRequest>,
+    ) -> Result<Response<DeleteLimitConfigResponse>, Status> {
+        Err(not_emulated("DeleteLimitConfig"))
+    }
+
+    async fn list_keys(
+        &self,
+        _request: Request<ListKeysRequest>,
+    ) -> Result<Response<ListKeysResponse>, Status> {
+        Err(not_emulated("ListKeys"))
+    }
+
+    async fn top_keys(
+        &self,
+        _request: Request<TopKeysRequest>,
+    ) -> Result<Response<TopKeysResponse>, Status> {
+        Err(not_emulated("TopKeys"))
+    }
+
+    async fn get_usage_report(
+        &self,
+        _request: Request<GetUsageReportRequest>,
+    ) -> Result<Response<GetUsageReportResponse>, Status> {
+        Err(not_emulated("GetUsageReport"))
+    }
+
+    async fn set_access_entry(
+        &self,
+        _request: Request<SetAccessEntryRequest>,
+    ) -> Result<Response<SetAccessEntryResponse>, Status> {
+        Err(not_emulated("SetAccessEntry"))
+    }
+
+    async fn remove_access_entry(
+        &self,
+        _request: Request<RemoveAccessEntryRequest>,
+    ) -> Result<Response<RemoveAccessEntryResponse>, Status> {
+        Err(not_emulated("RemoveAccessEntry"))
+    }
+
+    async fn list_access_entries(
+        &self,
+        _request: Request<ListAccessEntriesRequest>,
+    ) -> Result<Response<ListAccessEntriesResponse>, Status> {
+        Err(not_emulated("ListAccessEntries"))
+    }
+
+    async fn inspect_key(
+        &self,
+        _request: Request<InspectKeyRequest>,
+    ) -> Result<Response<InspectKeyResponse>, Status> {
+        Err(not_emulated("InspectKey"))
+    }
+
+    async fn list_leases(
+        &self,
+        _request: Request<ListLeasesRequest>,
+    ) -> Result<Response<ListLeasesResponse>, Status> {
+        Err(not_emulated("ListLeases"))
+    }
+
+    async fn get_stats(
+        &self,
+        _request: Request<GetStatsRequest>,
+    ) -> Result<Response<GetStatsResponse>, Status> {
+        Err(not_emulated("GetStats"))
+    }
+
+    async fn get_decision_traces(
+        &self,
+        _request: Request<GetDecisionTracesRequest>,
+    ) -> Result<Response<GetDecisionTracesResponse>, Status> {
+        Err(not_emulated("GetDecisionTraces"))
+    }
+}
+
+fn not_emulated(rpc: &str) -> Status {
+    Status::unimplemented(format!("{} isn't available in process", rpc))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::GuardianClient;
+    use guardian_core::{LeaseConfig, LeasingBackend, StorageBackend, TokenBucketConfig};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_emulates_the_service() {
+        let client = GuardianClient::in_process(TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(3600),
+        });
+        assert!(client.check_limit("user1", 3).await.unwrap());
+        assert!(!client.check_limit("user1", 3).await.unwrap());
+        assert_eq!(client.get_usage("user1").await.unwrap(), 3);
+
+        // Namespaces get buckets of their own.
+        let tenant = client.clone().with_namespace("acme");
+        assert!(tenant.check_limit("user1", 5).await.unwrap());
+
+        // user2 had room, so its denial isn't cached.
+        let cached = client.clone().with_denial_cache();
+        let all = cached
+            .check_all_or_nothing(&[("user2", 2), ("user1", 3)])
+            .await
+            .unwrap();
+        assert!(all.iter().all(|result| !result.allowed && result.retry_after_seconds > 0));
+        assert!(cached.check_limit("user2", 5).await.unwrap());
+
+        // A permit gives back what its check took, once.
+        let permit = client.try_acquire("user5", 4).await.unwrap().unwrap();
+        permit.refund().await.unwrap();
+        assert_eq!(client.get_usage("user5").await.unwrap(), 0);
+        let permit = client.try_acquire("user5", 4).await.unwrap().unwrap();
+        permit.commit();
+        assert_eq!(client.get_usage("user5").await.unwrap(), 4);
+
+        let pipeline = client.check_pipeline().await.unwrap();
+        assert!(pipeline.check("user3", 5).await.unwrap().allowed);
+        assert!(!pipeline.check("user3", 1).await.unwrap().allowed);
+
+        assert!(client
+            .check_limit_for_tier("user4", "premium", 1)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_leases_over_the_wire() {
+        let remote =
+            crate::RemoteGuardianBackend::from_channel(super::channel(TokenBucketConfig {
+                capacity: 10,
+                refill_rate: 1,
+                refill_interval: Duration::from_secs(3600),
+            }));
+        let leasing = LeasingBackend::new(
+            remote.clone(),
+            LeaseConfig {
+                size: 4,
+                ttl: Duration::from_secs(10),
+            },
+        );
+        for _ in 0..3 {
+            assert!(leasing.take_token("user1", 1).await.unwrap());
+        }
+        assert_eq!(remote.get_usage("user1").await.unwrap(), 4);
+
+        leasing.flush().await.unwrap();
+        assert_eq!(remote.get_usage("user1").await.unwrap(), 3);
+        assert!(remote
+            .renew_lease("user1", 1, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,193 @@
+//! Gating for background job workers, whatever runs them (apalis, a
+//! hand-rolled poller, ...). Fetching from a queue waits on the queue's
+//! bucket, so a fleet of workers pulls no faster than the queue's limit.
+//! Running a job is charged to its tenant's bucket, and a job over the
+//! limit comes back as [`Deferred`], naming when to schedule it again
+//! rather than letting the framework retry it straight away.
+
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+use crate::api::GuardianApi;
+
+/// What the gate needs to know about a job.
+pub trait Job {
+    /// The queue the job came from.
+    fn queue(&self) -> &str;
+
+    /// Who the job's work is for. Jobs without a tenant run unchecked.
+    fn tenant(&self) -> Option<&str> {
+        None
+    }
+
+    /// Tokens the job costs; 1 unless overridden.
+    fn cost(&self) -> u32 {
+        1
+    }
+}
+
+/// A job put off because its tenant is over its limit. Schedule it again
+/// at [`run_at`](Self::run_at).
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("job deferred by {key}; run again in {}s", .retry_after.as_secs())]
+pub struct Deferred {
+    /// The bucket that was empty, `jobs:<queue>:<tenant>`.
+    pub key: String,
+    pub retry_after: Duration,
+}
+
+impl Deferred {
+    pub fn run_at(&self) -> SystemTime {
+        SystemTime::now() + self.retry_after
+    }
+}
+
+/// Paces a worker's fetches per queue (`jobs:<queue>`) and its jobs per
+/// tenant (`jobs:<queue>:<tenant>`).
+pub struct JobGate<A> {
+    api: A,
+    fail_open: bool,
+}
+
+impl<A: GuardianApi> JobGate<A> {
+    pub fn new(api: A) -> Self {
+        Self {
+            api,
+            fail_open: true,
+        }
+    }
+
+    /// Whether to go ahead when Guardian can't decide (the default), or
+    /// wait a second and ask again when fetching, and defer jobs by a
+    /// second.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Wait until `queue`'s bucket has a token, then fetch with `fetch`.
+    pub async fn fetch<F, Fut>(&self, queue: &str, fetch: F) -> Fut::Output
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future,
+    {
+        let key = format!("jobs:{}", queue);
+        while let Err(wait) = self.check(&key, 1).await {
+            tokio::time::sleep(wait).await;
+        }
+        fetch().await
+    }
+
+    /// Charge `job` to its tenant's bucket, or say when it may run.
+    pub async fn admit(&self, job: &impl Job) -> Result<(), Deferred> {
+        let Some(tenant) = job.tenant() else {
+            return Ok(());
+        };
+        let key = format!("jobs:{}:{}", job.queue(), tenant);
+        self.check(&key, job.cost())
+            .await
+            .map_err(|retry_after| Deferred { key, retry_after })
+    }
+
+    /// Run `job` with `work` if its tenant's bucket allows, or defer it.
+    pub async fn run<J, F, Fut>(&self, job: &J, work: F) -> Result<Fut::Output, Deferred>
+    where
+        J: Job,
+        F: FnOnce() -> Fut,
+        Fut: Future,
+    {
+        self.admit(job).await?;
+        Ok(work().await)
+    }
+
+    /// `Err` with how long to wait if `key` is over its limit.
+    async fn check(&self, key: &str, cost: u32) -> Result<(), Duration> {
+        match self.api.check_limit_detailed(key, cost).await {
+            Ok(decision) if decision.allowed => Ok(()),
+            Ok(decision) => Err(Duration::from_secs(
+                decision.retry_after_seconds.max(1).into(),
+            )),
+            Err(_) if self.fail_open => Ok(()),
+            Err(_) => Err(Duration::from_secs(1)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::MockGuardianClient;
+
+    struct Email {
+        tenant: Option<&'static str>,
+    }
+
+    impl Job for Email {
+        fn queue(&self) -> &str {
+            "email"
+        }
+
+        fn tenant(&self) -> Option<&str> {
+            self.tenant
+        }
+
+        fn cost(&self) -> u32 {
+            2
+        }
+    }
+
+    #[tokio::test]
+    async fn test_defers_jobs_over_their_tenants_limit() {
+        let mock = MockGuardianClient::new();
+        mock.deny("jobs:email:acme", 45);
+        let gate = JobGate::new(mock.clone());
+
+        let deferred = gate
+            .run(
+                &Email {
+                    tenant: Some("acme"),
+                },
+                || async { unreachable!() },
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(deferred.key, "jobs:email:acme");
+        assert_eq!(deferred.retry_after, Duration::from_secs(45));
+        assert!(deferred.run_at() > SystemTime::now() + Duration::from_secs(40));
+
+        let ran = gate
+            .run(
+                &Email {
+                    tenant: Some("globex"),
+                },
+                || async { "sent" },
+            )
+            .await;
+        assert_eq!(ran, Ok("sent"));
+        assert_eq!(mock.calls_for("jobs:email:globex")[0].cost, 2);
+        // No tenant, no check.
+        gate.admit(&Email { tenant: None }).await.unwrap();
+        assert_eq!(mock.calls().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fetch_waits_for_the_queues_bucket() {
+        let mock = MockGuardianClient::new();
+        mock.push_response(
+            "jobs:email",
+            Ok(crate::LimitCheckResult {
+                allowed: false,
+                retry_after_seconds: 3,
+                remaining_tokens: 0,
+                limit: 10,
+            }),
+        );
+        let gate = JobGate::new(mock.clone());
+
+        let started = tokio::time::Instant::now();
+        let job = gate.fetch("email", || async { Some("job-1") }).await;
+        assert_eq!(job, Some("job-1"));
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+        assert_eq!(mock.calls_for("jobs:email").len(), 2);
+    }
+}
@@ -0,0 +1,241 @@
+//! Pacing for Kafka consumers (`kafka` feature): messages are handed out
+//! only as fast as their Guardian buckets allow, and a partition whose
+//! bucket is empty is paused at the broker until it refills.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use rdkafka::consumer::{Consumer, ConsumerContext, DefaultConsumerContext, StreamConsumer};
+use rdkafka::error::KafkaResult;
+use rdkafka::message::{Headers, Message, OwnedMessage};
+use rdkafka::TopicPartitionList;
+use tokio::time::Instant;
+
+use crate::api::GuardianApi;
+
+/// What a message's bucket is chosen by. Messages without one (no such
+/// header, no message key) are handed out unchecked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaceKey {
+    /// `kafka:<topic>:<partition>`.
+    Partition,
+    /// `kafka:<topic>`, one limit over all of a topic's partitions.
+    Topic,
+    /// A header's value, as `<name>:<value>`, such as a tenant id.
+    Header(String),
+    /// The message key, as `key:<key>`, when it is UTF-8.
+    MessageKey,
+}
+
+impl PaceKey {
+    fn of(&self, message: &OwnedMessage) -> Option<String> {
+        match self {
+            Self::Partition => Some(format!("kafka:{}:{}", message.topic(), message.partition())),
+            Self::Topic => Some(format!("kafka:{}", message.topic())),
+            Self::Header(name) => {
+                let header = message.headers()?.iter().find(|h| h.key == name)?;
+                let value = std::str::from_utf8(header.value?).ok()?;
+                Some(format!("{}:{}", name, value))
+            }
+            Self::MessageKey => {
+                let key = std::str::from_utf8(message.key()?).ok()?;
+                Some(format!("key:{}", key))
+            }
+        }
+    }
+}
+
+type CostFn = dyn Fn(&OwnedMessage) -> u32 + Send + Sync;
+
+/// Messages of a paused partition, in offset order, waiting for tokens.
+struct Held {
+    messages: VecDeque<OwnedMessage>,
+    retry_at: Instant,
+}
+
+/// A `StreamConsumer` whose [`recv`](Self::recv) paces messages through
+/// Guardian.
+///
+/// A denied message is held, and its partition paused, until its bucket
+/// has the tokens; messages of other partitions keep flowing meanwhile.
+/// Order within a partition is kept.
+pub struct ThrottledConsumer<A, C: ConsumerContext + 'static = DefaultConsumerContext> {
+    consumer: StreamConsumer<C>,
+    api: A,
+    key: PaceKey,
+    cost: Box<CostFn>,
+    fail_open: bool,
+    held: HashMap<(String, i32), Held>,
+}
+
+impl<A: GuardianApi, C: ConsumerContext + 'static> ThrottledConsumer<A, C> {
+    /// Pace `consumer`'s messages by their partition, a token each.
+    pub fn new(consumer: StreamConsumer<C>, api: A) -> Self {
+        Self {
+            consumer,
+            api,
+            key: PaceKey::Partition,
+            cost: Box::new(|_| 1),
+            fail_open: true,
+            held: HashMap::new(),
+        }
+    }
+
+    /// What messages are paced by; [`PaceKey::Partition`] unless set.
+    pub fn with_key(mut self, key: PaceKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Tokens each message costs, such as its payload size in KiB.
+    pub fn with_cost(
+        mut self,
+        cost: impl Fn(&OwnedMessage) -> u32 + Send + Sync + 'static,
+    ) -> Self {
+        self.cost = Box::new(cost);
+        self
+    }
+
+    /// Whether to hand messages out when Guardian can't decide (the
+    /// default), or hold them and ask again a second later.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// The wrapped consumer, for committing offsets and subscribing.
+    pub fn consumer(&self) -> &StreamConsumer<C> {
+        &self.consumer
+    }
+
+    /// The next message its bucket has tokens for.
+    pub async fn recv(&mut self) -> KafkaResult<OwnedMessage> {
+        loop {
+            if let Some(message) = self.release_due().await? {
+                return Ok(message);
+            }
+            let retry_at = self.held.values().map(|held| held.retry_at).min();
+            let received = tokio::select! {
+                received = self.consumer.recv() => Some(received.map(|m| m.detach())),
+                _ = tokio::time::sleep_until(retry_at.unwrap_or_else(Instant::now)),
+                    if retry_at.is_some() => None,
+            };
+            let Some(message) = received.transpose()? else {
+                continue;
+            };
+            let partition = (message.topic().to_string(), message.partition());
+            if let Some(held) = self.held.get_mut(&partition) {
+                // Fetched before the pause took effect.
+                held.messages.push_back(message);
+                continue;
+            }
+            let Some(wait) = self.admit(&message).await else {
+                return Ok(message);
+            };
+            self.consumer.pause(&partition_list(&partition))?;
+            self.held.insert(
+                partition,
+                Held {
+                    messages: VecDeque::from([message]),
+                    retry_at: Instant::now() + wait,
+                },
+            );
+        }
+    }
+
+    /// The first held message that is due and now has its tokens, resuming
+    /// its partition once nothing of it is held.
+    async fn release_due(&mut self) -> KafkaResult<Option<OwnedMessage>> {
+        let now = Instant::now();
+        let due: Vec<_> = self
+            .held
+            .iter()
+            .filter(|(_, held)| held.retry_at <= now)
+            .map(|(partition, _)| partition.clone())
+            .collect();
+        for partition in due {
+            let Some(message) = self.held[&partition].messages.front() else {
+                continue;
+            };
+            if let Some(wait) = self.admit(message).await {
+                self.held.get_mut(&partition).unwrap().retry_at = now + wait;
+                continue;
+            }
+            let held = self.held.get_mut(&partition).unwrap();
+            let message = held.messages.pop_front();
+            if held.messages.is_empty() {
+                self.held.remove(&partition);
+                self.consumer.resume(&partition_list(&partition))?;
+            }
+            return Ok(message);
+        }
+        Ok(None)
+    }
+
+    /// `None` if `message` may be handed out, or how long to hold it.
+    async fn admit(&self, message: &OwnedMessage) -> Option<Duration> {
+        let key = self.key.of(message)?;
+        match self
+            .api
+            .check_limit_detailed(&key, (self.cost)(message))
+            .await
+        {
+            Ok(decision) if decision.allowed => None,
+            Ok(decision) => Some(Duration::from_secs(
+                decision.retry_after_seconds.max(1).into(),
+            )),
+            Err(_) if self.fail_open => None,
+            Err(_) => Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+fn partition_list((topic, partition): &(String, i32)) -> TopicPartitionList {
+    let mut list = TopicPartitionList::new();
+    list.add_partition(topic, *partition);
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::message::{Header, OwnedHeaders, Timestamp};
+
+    #[test]
+    fn test_pace_keys() {
+        let headers = OwnedHeaders::new().insert(Header {
+            key: "tenant",
+            value: Some("acme"),
+        });
+        let message = OwnedMessage::new(
+            None,
+            Some(b"order-7".to_vec()),
+            "orders".to_string(),
+            Timestamp::NotAvailable,
+            3,
+            42,
+            Some(headers),
+        );
+
+        let keys: Vec<_> = [
+            PaceKey::Partition,
+            PaceKey::Topic,
+            PaceKey::Header("tenant".to_string()),
+            PaceKey::Header("region".to_string()),
+            PaceKey::MessageKey,
+        ]
+        .iter()
+        .map(|key| key.of(&message))
+        .collect();
+        assert_eq!(
+            keys,
+            [
+                Some("kafka:orders:3".to_string()),
+                Some("kafka:orders".to_string()),
+                Some("tenant:acme".to_string()),
+                None,
+                Some("key:order-7".to_string()),
+            ]
+        );
+    }
+}
@@ -9,7 +9,7 @@
 //!
 //! #[tokio::main]
 //! async fn main() -> Result<(), Box<dyn std::error::Error>> {
-//!     let mut client = GuardianClient::connect("http://localhost:50051").await?;
+//!     let client = GuardianClient::connect("http://localhost:50051").await?;
 //!     
 //!     let allowed = client.check_limit("user123", 1).await?;
 //!     if allowed {
@@ -21,15 +21,103 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! On wasm32 only the `web` client is available (with the `grpc-web`
+//! feature), along with the types it shares with the native client.
 
+#[cfg(not(target_arch = "wasm32"))]
+mod affinity;
+#[cfg(all(feature = "amqp", not(target_arch = "wasm32")))]
+pub mod amqp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod api;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod backend;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod balance;
+#[cfg(all(feature = "blocking", not(target_arch = "wasm32")))]
+pub mod blocking;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod builder;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod client;
+#[cfg(not(target_arch = "wasm32"))]
+mod coalesce;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod config;
+pub mod credentials;
+pub mod decision;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod denials;
 pub mod error;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod health;
+#[cfg(not(target_arch = "wasm32"))]
+mod in_process;
+#[cfg(not(target_arch = "wasm32"))]
+mod hedge;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod jobs;
+#[cfg(all(feature = "kafka", not(target_arch = "wasm32")))]
+pub mod kafka;
+#[cfg(all(feature = "test-util", not(target_arch = "wasm32")))]
+pub mod mock;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod observer;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod permit;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pipeline;
+#[cfg(not(target_arch = "wasm32"))]
+mod proxy;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod retry;
+#[cfg(all(feature = "sqlx", not(target_arch = "wasm32")))]
+pub mod sql;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod subscription;
+#[cfg(all(feature = "opentelemetry", not(target_arch = "wasm32")))]
+mod trace;
+#[cfg(all(feature = "grpc-web", target_arch = "wasm32"))]
+pub mod web;
+#[cfg(all(feature = "websocket", not(target_arch = "wasm32")))]
+pub mod websocket;
 
 // Re-exports
+pub use credentials::{CallCredentials, CredentialsError};
+pub use decision::LimitCheckResult;
+pub use error::{ClientError, Result, RETRY_AFTER_HEADER};
+#[cfg(all(feature = "grpc-web", target_arch = "wasm32"))]
+pub use web::WebGuardianClient;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use api::GuardianApi;
+#[cfg(not(target_arch = "wasm32"))]
+pub use backend::RemoteGuardianBackend;
+#[cfg(not(target_arch = "wasm32"))]
+pub use builder::GuardianClientBuilder;
+#[cfg(not(target_arch = "wasm32"))]
 pub use client::GuardianClient;
-pub use error::{ClientError, Result};
+#[cfg(not(target_arch = "wasm32"))]
+pub use config::{ClientConfig, Fallback};
+#[cfg(not(target_arch = "wasm32"))]
+pub use denials::DenialCache;
+#[cfg(not(target_arch = "wasm32"))]
+pub use health::ChannelHealth;
+#[cfg(all(feature = "test-util", not(target_arch = "wasm32")))]
+pub use mock::{MockCall, MockGuardianClient};
+#[cfg(all(feature = "metrics", not(target_arch = "wasm32")))]
+pub use observer::MetricsObserver;
+#[cfg(not(target_arch = "wasm32"))]
+pub use observer::{CallOutcome, ClientObserver};
+#[cfg(not(target_arch = "wasm32"))]
+pub use permit::Permit;
+#[cfg(not(target_arch = "wasm32"))]
+pub use pipeline::CheckPipeline;
+#[cfg(not(target_arch = "wasm32"))]
+pub use retry::{ReconnectPolicy, DEFAULT_RETRY_CODES};
+#[cfg(not(target_arch = "wasm32"))]
+pub use tonic::transport::{Certificate, ClientTlsConfig, Identity};
 
-// Include generated protobuf code
-pub mod proto {
-    tonic::include_proto!("guardian");
-}
\ No newline at end of file
+/// The generated gRPC types, from the `guardian-proto` crate.
+pub use guardian_proto as proto;
\ No newline at end of file
@@ -0,0 +1,233 @@
+// An in-process stand-in for the service, for unit testing code written
+// against `GuardianApi` without running Guardian.
+
+// Programmed responses are the client's own `Result`.
+#![allow(clippy::result_large_err)]
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use crate::api::GuardianApi;
+use crate::client::LimitCheckResult;
+use crate::error::Result;
+
+/// A [`GuardianApi`] that answers from programmed responses and records
+/// every call. Clones share responses and records, so a test can keep one
+/// handle and give another to the code under test.
+///
+/// Checks are answered, in order of precedence, by a response queued for
+/// the key with [`push_response`](Self::push_response), the key's
+/// [`set_response`](Self::set_response), then an allow with nothing
+/// spent. Calls besides checks succeed.
+///
+/// # Examples
+///
+/// ```
+/// # use guardian_client::{GuardianApi, MockGuardianClient};
+/// # async fn handle(api: &dyn GuardianApi) -> u16 {
+/// #     if api.check_limit("user:1", 1).await.unwrap() { 200 } else { 429 }
+/// # }
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let mock = MockGuardianClient::new();
+/// mock.deny("user:1", 30);
+/// assert_eq!(handle(&mock).await, 429);
+/// assert_eq!(mock.calls()[0].client_id, "user:1");
+/// # }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MockGuardianClient {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    responses: HashMap<String, LimitCheckResult>,
+    queued: HashMap<String, VecDeque<Result<LimitCheckResult>>>,
+    calls: Vec<MockCall>,
+}
+
+/// One call made to a [`MockGuardianClient`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockCall {
+    /// The method called, such as `"check_limit"`.
+    pub method: &'static str,
+    pub client_id: String,
+    /// Empty unless the call named one.
+    pub tier: String,
+    /// 0 for calls without a cost.
+    pub cost: u32,
+}
+
+impl MockGuardianClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answer every check of `client_id` with `result` from now on.
+    pub fn set_response(&self, client_id: &str, result: LimitCheckResult) {
+        let mut state = self.state.lock().unwrap();
+        state.responses.insert(client_id.to_string(), result);
+    }
+
+    /// Allow every check of `client_id`.
+    pub fn allow(&self, client_id: &str) {
+        self.set_response(client_id, decision(true, 0));
+    }
+
+    /// Deny every check of `client_id`, with `retry_after_seconds`.
+    pub fn deny(&self, client_id: &str, retry_after_seconds: u32) {
+        self.set_response(client_id, decision(false, retry_after_seconds));
+    }
+
+    /// Answer the next check of `client_id` with `response`, after any
+    /// queued before it. An `Err` plays a failed call.
+    pub fn push_response(&self, client_id: &str, response: Result<LimitCheckResult>) {
+        let mut state = self.state.lock().unwrap();
+        let queue = state.queued.entry(client_id.to_string()).or_default();
+        queue.push_back(response);
+    }
+
+    /// Every call made so far, oldest first.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// The calls made for `client_id`.
+    pub fn calls_for(&self, client_id: &str) -> Vec<MockCall> {
+        let state = self.state.lock().unwrap();
+        let calls = state
+            .calls
+            .iter()
+            .filter(|call| call.client_id == client_id);
+        calls.cloned().collect()
+    }
+
+    /// Forget the calls recorded so far; responses are kept.
+    pub fn clear_calls(&self) {
+        self.state.lock().unwrap().calls.clear();
+    }
+
+    fn record(&self, method: &'static str, client_id: &str, tier: &str, cost: u32) {
+        self.state.lock().unwrap().calls.push(MockCall {
+            method,
+            client_id: client_id.to_string(),
+            tier: tier.to_string(),
+            cost,
+        });
+    }
+
+    fn answer(&self, client_id: &str) -> Result<LimitCheckResult> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(response) = state
+            .queued
+            .get_mut(client_id)
+            .and_then(VecDeque::pop_front)
+        {
+            return response;
+        }
+        Ok(state
+            .responses
+            .get(client_id)
+            .cloned()
+            .unwrap_or_else(|| decision(true, 0)))
+    }
+}
+
+fn decision(allowed: bool, retry_after_seconds: u32) -> LimitCheckResult {
+    LimitCheckResult {
+        allowed,
+        retry_after_seconds,
+        remaining_tokens: 0,
+        limit: 0,
+    }
+}
+
+#[async_trait]
+impl GuardianApi for MockGuardianClient {
+    async fn check_limit_detailed(&self, client_id: &str, cost: u32) -> Result<LimitCheckResult> {
+        self.record("check_limit", client_id, "", cost);
+        self.answer(client_id)
+    }
+
+    async fn check_limit_for_tier(&self, client_id: &str, tier: &str, cost: u32) -> Result<bool> {
+        self.record("check_limit", client_id, tier, cost);
+        Ok(self.answer(client_id)?.allowed)
+    }
+
+    async fn check_limits(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>> {
+        checks
+            .iter()
+            .map(|(client_id, cost)| {
+                self.record("check_limit", client_id, "", *cost);
+                self.answer(client_id)
+            })
+            .collect()
+    }
+
+    async fn get_usage(&self, client_id: &str) -> Result<u64> {
+        self.record("get_usage", client_id, "", 0);
+        Ok(0)
+    }
+
+    async fn reset_limit(&self, client_id: &str) -> Result<()> {
+        self.record("reset_limit", client_id, "", 0);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ClientError;
+
+    #[tokio::test]
+    async fn test_answers_in_order_of_precedence_and_records_calls() {
+        let mock = MockGuardianClient::new();
+        let api: &dyn GuardianApi = &mock;
+        assert!(api.check_limit("user1", 1).await.unwrap());
+
+        mock.deny("user1", 5);
+        mock.push_response("user1", Ok(decision(true, 0)));
+        mock.push_response("user1", Err(ClientError::DeadlineExceeded));
+        assert!(api.check_limit("user1", 2).await.unwrap());
+        assert!(matches!(
+            api.check_limit("user1", 3).await,
+            Err(ClientError::DeadlineExceeded)
+        ));
+        let denied = api.check_limit_detailed("user1", 4).await.unwrap();
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after_seconds, 5);
+
+        let results = api
+            .check_limits(&[("user1", 1), ("user2", 1)])
+            .await
+            .unwrap();
+        assert!(!results[0].allowed && results[1].allowed);
+        assert!(api.check_limit_for_tier("user2", "gold", 1).await.unwrap());
+
+        let costs: Vec<_> = mock.calls_for("user1").iter().map(|c| c.cost).collect();
+        assert_eq!(costs, [1, 2, 3, 4, 1]);
+        assert_eq!(
+            mock.calls_for("user2"),
+            [
+                MockCall {
+                    method: "check_limit",
+                    client_id: "user2".to_string(),
+                    tier: String::new(),
+                    cost: 1,
+                },
+                MockCall {
+                    method: "check_limit",
+                    client_id: "user2".to_string(),
+                    tier: "gold".to_string(),
+                    cost: 1,
+                },
+            ]
+        );
+        mock.clear_calls();
+        assert!(mock.calls().is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+// Hooks for watching the client's own traffic: what each RPC returned, how
+// long it took, and how often calls were retried, so applications can see
+// what rate limiting costs them. With the `metrics` feature,
+// `MetricsObserver` reports all of it through the `metrics` facade.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tonic::Code;
+
+use crate::client::LimitCheckResult;
+use crate::proto::{
+    CheckLimitBatchRequest, CheckLimitBatchResponse, CheckLimitRequest, CheckLimitResponse,
+    DeleteLimitConfigRequest, DeleteLimitConfigResponse, GetUsageRequest, GetUsageResponse,
+    ListKeysRequest, ListKeysResponse, RefundTokensRequest, RefundTokensResponse,
+    RemoveAccessEntryRequest, RemoveAccessEntryResponse, ResetLimitRequest, ResetLimitResponse,
+    SetAccessEntryRequest, SetAccessEntryResponse, SetLimitConfigRequest, SetLimitConfigResponse,
+    TopKeysRequest, TopKeysResponse,
+};
+
+/// How a call through the client ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallOutcome {
+    /// A check the service allowed.
+    Allowed,
+    /// A check the service denied.
+    Denied,
+    /// Any other call that got an answer.
+    Ok,
+    /// No answer: an error status after any retries, or a deadline.
+    Failed,
+}
+
+impl CallOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allowed => "allowed",
+            Self::Denied => "denied",
+            Self::Ok => "ok",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// Told about every unary call a [`GuardianClient`](crate::GuardianClient)
+/// makes; set with
+/// [`with_observer`](crate::GuardianClient::with_observer). Called on the
+/// caller's task, so implementations should be quick.
+pub trait ClientObserver: Send + Sync {
+    /// `rpc` (such as `"CheckLimit"`) finished after `latency`, retries
+    /// included.
+    fn on_call(&self, rpc: &'static str, outcome: CallOutcome, latency: Duration);
+
+    /// `rpc` failed with `code` and is being tried again. Status stream
+    /// reconnections are reported here too.
+    fn on_retry(&self, _rpc: &'static str, _code: Code) {}
+
+    /// A check was denied from the denial cache without a call.
+    fn on_cached_denial(&self) {}
+
+    /// `rpc` was slow to answer and was sent to a second instance too;
+    /// see [`hedge_after`](crate::GuardianClientBuilder::hedge_after).
+    fn on_hedge(&self, _rpc: &'static str) {}
+
+    /// In shadow mode, `client_id` was denied as `result` says, and let
+    /// through anyway; see
+    /// [`with_shadow_mode`](crate::GuardianClient::with_shadow_mode).
+    fn on_shadow_denial(&self, _client_id: &str, _result: &LimitCheckResult) {}
+}
+
+/// Shadow mode's decisions: denials are reported, then allowed.
+#[derive(Clone)]
+pub(crate) struct Shadow(pub(crate) Option<Arc<dyn ClientObserver>>);
+
+impl Shadow {
+    pub(crate) fn pass(&self, client_id: &str, result: LimitCheckResult) -> LimitCheckResult {
+        if result.allowed {
+            return result;
+        }
+        if let Some(observer) = &self.0 {
+            observer.on_shadow_denial(client_id, &result);
+        }
+        LimitCheckResult {
+            allowed: true,
+            retry_after_seconds: 0,
+            ..result
+        }
+    }
+}
+
+/// Request types the client sends, by RPC name.
+pub(crate) trait Rpc {
+    const NAME: &'static str;
+}
+
+/// Responses, by what they say about the call.
+pub(crate) trait Answer {
+    fn outcome(&self) -> CallOutcome {
+        CallOutcome::Ok
+    }
+}
+
+impl Rpc for CheckLimitRequest {
+    const NAME: &'static str = "CheckLimit";
+}
+
+impl Answer for CheckLimitResponse {
+    fn outcome(&self) -> CallOutcome {
+        if self.allowed {
+            CallOutcome::Allowed
+        } else {
+            CallOutcome::Denied
+        }
+    }
+}
+
+impl Rpc for CheckLimitBatchRequest {
+    const NAME: &'static str = "CheckLimitBatch";
+}
+
+impl Answer for CheckLimitBatchResponse {}
+
+/// A batch sent to CheckLimitAll rather than CheckLimitBatch.
+#[derive(Clone)]
+pub(crate) struct AllOrNothing(pub(crate) CheckLimitBatchRequest);
+
+impl Rpc for AllOrNothing {
+    const NAME: &'static str = "CheckLimitAll";
+}
+
+impl Rpc for GetUsageRequest {
+    const NAME: &'static str = "GetUsage";
+}
+
+impl Answer for GetUsageResponse {}
+
+impl Rpc for ResetLimitRequest {
+    const NAME: &'static str = "ResetLimit";
+}
+
+impl Answer for ResetLimitResponse {}
+
+impl Rpc for RefundTokensRequest {
+    const NAME: &'static str = "RefundTokens";
+}
+
+impl Answer for RefundTokensResponse {}
+
+impl Rpc for SetLimitConfigRequest {
+    const NAME: &'static str = "SetLimitConfig";
+}
+
+impl Answer for SetLimitConfigResponse {}
+
+impl Rpc for DeleteLimitConfigRequest {
+    const NAME: &'static str = "DeleteLimitConfig";
+}
+
+impl Answer for DeleteLimitConfigResponse {}
+
+impl Rpc for ListKeysRequest {
+    const NAME: &'static str = "ListKeys";
+}
+
+impl Answer for ListKeysResponse {}
+
+impl Rpc for TopKeysRequest {
+    const NAME: &'static str = "TopKeys";
+}
+
+impl Answer for TopKeysResponse {}
+
+impl Rpc for SetAccessEntryRequest {
+    const NAME: &'static str = "SetAccessEntry";
+}
+
+impl Answer for SetAccessEntryResponse {}
+
+impl Rpc for RemoveAccessEntryRequest {
+    const NAME: &'static str = "RemoveAccessEntry";
+}
+
+impl Answer for RemoveAccessEntryResponse {}
+
+/// Reports through the [`metrics`](https://docs.rs/metrics) facade, to
+/// whichever recorder the application installed:
+///
+/// - `guardian_client_requests_total` (counter; `rpc`, `outcome`)
+/// - `guardian_client_request_duration_seconds` (histogram; `rpc`)
+/// - `guardian_client_retries_total` (counter; `rpc`, `code`)
+/// - `guardian_client_cached_denials_total` (counter)
+/// - `guardian_client_hedges_total` (counter; `rpc`)
+/// - `guardian_client_shadow_denials_total` (counter)
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MetricsObserver;
+
+#[cfg(feature = "metrics")]
+impl ClientObserver for MetricsObserver {
+    fn on_call(&self, rpc: &'static str, outcome: CallOutcome, latency: Duration) {
+        metrics::counter!(
+            "guardian_client_requests_total",
+            "rpc" => rpc,
+            "outcome" => outcome.as_str()
+        )
+        .increment(1);
+        metrics::histogram!("guardian_client_request_duration_seconds", "rpc" => rpc)
+            .record(latency.as_secs_f64());
+    }
+
+    fn on_retry(&self, rpc: &'static str, code: Code) {
+        metrics::counter!(
+            "guardian_client_retries_total",
+            "rpc" => rpc,
+            "code" => format!("{:?}", code)
+        )
+        .increment(1);
+    }
+
+    fn on_cached_denial(&self) {
+        metrics::counter!("guardian_client_cached_denials_total").increment(1);
+    }
+
+    fn on_hedge(&self, rpc: &'static str) {
+        metrics::counter!("guardian_client_hedges_total", "rpc" => rpc).increment(1);
+    }
+
+    fn on_shadow_denial(&self, _client_id: &str, _result: &LimitCheckResult) {
+        metrics::counter!("guardian_client_shadow_denials_total").increment(1);
+    }
+}
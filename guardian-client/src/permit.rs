@@ -0,0 +1,93 @@
+use crate::client::GuardianClient;
+use crate::error::Result;
+
+/// Tokens taken by [`GuardianClient::try_acquire`], given back unless the
+/// work they paid for goes ahead.
+///
+/// Call [`commit`](Self::commit) once the request has been served. A permit
+/// dropped without that, on an error path, an early return or a cancelled
+/// task, refunds its tokens in the background, so failed work doesn't
+/// count against the client's budget. The refund is best effort: it needs
+/// a Tokio runtime to run on, and is lost if the service can't be reached.
+///
+/// The refund redeems the grant the service returned with the check, so
+/// only the instance that took the tokens gives them back. A permit
+/// without a grant (let through by shadow mode, or from a service that
+/// predates grants) has nothing to give back.
+#[must_use = "dropping a Permit refunds its tokens; call commit() to keep them spent"]
+pub struct Permit {
+    client: GuardianClient,
+    client_id: String,
+    cost: u32,
+    grant_id: String,
+    committed: bool,
+}
+
+impl Permit {
+    pub(crate) fn new(
+        client: GuardianClient,
+        client_id: &str,
+        cost: u32,
+        grant_id: String,
+    ) -> Self {
+        Self {
+            client,
+            client_id: client_id.to_string(),
+            cost,
+            committed: grant_id.is_empty(),
+            grant_id,
+        }
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn cost(&self) -> u32 {
+        self.cost
+    }
+
+    /// Keep the tokens spent.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+
+    /// Give the tokens back now, rather than in the background on drop,
+    /// and find out whether the service took them.
+    pub async fn refund(mut self) -> Result<()> {
+        if std::mem::replace(&mut self.committed, true) {
+            return Ok(());
+        }
+        self.client
+            .refund(&self.client_id, self.cost, &self.grant_id)
+            .await
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let client = self.client.clone();
+        let client_id = std::mem::take(&mut self.client_id);
+        let grant_id = std::mem::take(&mut self.grant_id);
+        let cost = self.cost;
+        runtime.spawn(async move {
+            let _ = client.refund(&client_id, cost, &grant_id).await;
+        });
+    }
+}
+
+impl std::fmt::Debug for Permit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Permit")
+            .field("client_id", &self.client_id)
+            .field("cost", &self.cost)
+            .field("committed", &self.committed)
+            .finish()
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataMap;
+use tonic::transport::Channel;
+use tonic::{Code, Request, Status};
+
+use crate::client::LimitCheckResult;
+use crate::error::{ClientError, Result};
+use crate::observer::Shadow;
+use crate::proto::{
+    rate_limiter_client::RateLimiterClient, CheckLimitRequest, CheckLimitStreamRequest,
+};
+
+/// Checks queued ahead of the server before `check` starts waiting.
+const PIPELINE_DEPTH: usize = 1024;
+
+type Pending = Arc<Mutex<HashMap<u64, oneshot::Sender<Result<LimitCheckResult>>>>>;
+
+/// Pipelined limit checks over one `CheckLimitStream` call.
+///
+/// `check` takes `&self`, so many tasks can share one pipeline and have
+/// checks in flight at once; each response is matched back to its caller by
+/// request id. Created with `GuardianClient::check_pipeline`.
+pub struct CheckPipeline {
+    tx: mpsc::Sender<CheckLimitStreamRequest>,
+    pending: Pending,
+    next_id: AtomicU64,
+    namespace: String,
+    shadow: Option<Shadow>,
+}
+
+impl CheckPipeline {
+    pub(crate) async fn open(
+        mut client: RateLimiterClient<Channel>,
+        namespace: String,
+        metadata: MetadataMap,
+        shadow: Option<Shadow>,
+    ) -> Result<Self> {
+        let (tx, rx) = mpsc::channel(PIPELINE_DEPTH);
+        let mut request = Request::new(ReceiverStream::new(rx));
+        *request.metadata_mut() = metadata;
+        let mut responses = client
+            .check_limit_stream(request)
+            .await
+            .map_err(ClientError::from)?
+            .into_inner();
+
+        let pending: Pending = Arc::default();
+        let dispatch = Arc::clone(&pending);
+        tokio::spawn(async move {
+            let failure = loop {
+                match responses.message().await {
+                    Ok(Some(response)) => {
+                        let waiter = dispatch.lock().unwrap().remove(&response.request_id);
+                        let Some(waiter) = waiter else { continue };
+                        let answer = match (response.result, response.error) {
+                            (Some(result), _) => Ok(LimitCheckResult::from(result)),
+                            (None, Some(error)) => Err(ClientError::from(Status::new(
+                                Code::from(error.code),
+                                error.message,
+                            ))),
+                            (None, None) => Err(ClientError::ConnectionError(
+                                "check stream answered without a result".to_string(),
+                            )),
+                        };
+                        let _ = waiter.send(answer);
+                    }
+                    Ok(None) => break "check stream closed by server".to_string(),
+                    Err(status) => break format!("check stream failed: {}", status),
+                }
+            };
+
+            for (_, waiter) in dispatch.lock().unwrap().drain() {
+                let _ = waiter.send(Err(ClientError::ConnectionError(failure.clone())));
+            }
+        });
+
+        Ok(Self {
+            tx,
+            pending,
+            next_id: AtomicU64::new(0),
+            namespace,
+            shadow,
+        })
+    }
+
+    /// Send one check down the stream and wait for its decision.
+    pub async fn check(&self, client_id: &str, cost: u32) -> Result<LimitCheckResult> {
+        let request_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (waiter, decision) = oneshot::channel();
+        self.pending.lock().unwrap().insert(request_id, waiter);
+
+        let request = CheckLimitStreamRequest {
+            request_id,
+            check: Some(CheckLimitRequest {
+                client_id: client_id.to_string(),
+                cost,
+                override_config: None,
+                tier: String::new(),
+                namespace: self.namespace.clone(),
+                refundable: false,
+            }),
+        };
+        if self.tx.send(request).await.is_err() {
+            self.pending.lock().unwrap().remove(&request_id);
+            return Err(ClientError::ConnectionError(
+                "check stream is closed".to_string(),
+            ));
+        }
+
+        let result = match decision.await {
+            Ok(result) => result?,
+            Err(_) => {
+                return Err(ClientError::ConnectionError(
+                    "check stream is closed".to_string(),
+                ))
+            }
+        };
+        Ok(match &self.shadow {
+            Some(shadow) => shadow.pass(client_id, result),
+            None => result,
+        })
+    }
+}
@@ -0,0 +1,241 @@
+// Connections to the service through an egress proxy: an HTTP proxy that
+// opens a tunnel with CONNECT, or a SOCKS5 proxy. The tunnel is set up on
+// a plain TCP connection to the proxy before the channel uses it, so TLS
+// and HTTP/2 run end to end with the service as they would without one.
+// The service's host name is passed to the proxy to resolve.
+
+use std::io;
+
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+
+use crate::error::{ClientError, Result};
+
+/// Port used when a proxy URL has none, as curl does.
+const DEFAULT_PROXY_PORT: u16 = 1080;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    HttpConnect,
+    Socks5,
+}
+
+/// A proxy parsed from `http://[user:password@]host[:port]` or
+/// `socks5://[user:password@]host[:port]`.
+#[derive(Debug, Clone)]
+pub(crate) struct Proxy {
+    kind: Kind,
+    addr: String,
+    auth: Option<(String, String)>,
+}
+
+impl Proxy {
+    #[allow(clippy::result_large_err)]
+    pub(crate) fn parse(url: &str) -> Result<Self> {
+        let invalid =
+            |why: &str| ClientError::ConfigError(format!("invalid proxy '{}': {}", url, why));
+        let (scheme, rest) = url.split_once("://").ok_or_else(|| invalid("no scheme"))?;
+        let kind = match scheme {
+            "http" => Kind::HttpConnect,
+            "socks5" | "socks5h" => Kind::Socks5,
+            _ => return Err(invalid("scheme must be http or socks5")),
+        };
+        let rest = rest.trim_end_matches('/');
+        let (auth, host) = match rest.rsplit_once('@') {
+            Some((userinfo, host)) => {
+                let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+                (Some((user.to_string(), password.to_string())), host)
+            }
+            None => (None, rest),
+        };
+        let authority: Uri = format!("http://{}", host)
+            .parse()
+            .map_err(|_| invalid("bad host or port"))?;
+        let port = authority.port_u16().unwrap_or(DEFAULT_PROXY_PORT);
+        Ok(Self {
+            kind,
+            addr: format!("{}:{}", authority.host().unwrap_or_default(), port),
+            auth,
+        })
+    }
+
+    /// Connect `endpoint` through the proxy.
+    pub(crate) async fn connect(&self, endpoint: Endpoint) -> Result<Channel> {
+        let proxy = self.clone();
+        endpoint
+            .connect_with_connector(tower::service_fn(move |uri: Uri| {
+                let proxy = proxy.clone();
+                async move { proxy.tunnel(&uri).await.map(TokioIo::new) }
+            }))
+            .await
+            .map_err(|e| ClientError::ConnectionError(e.to_string()))
+    }
+
+    /// A TCP connection to the proxy, tunnelled on to `uri`'s host.
+    async fn tunnel(&self, uri: &Uri) -> io::Result<TcpStream> {
+        let host = uri
+            .host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "endpoint has no host"))?;
+        let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+            Some("https") => 443,
+            _ => 80,
+        });
+        let mut stream = TcpStream::connect(&self.addr).await?;
+        let refused = |e: &dyn std::fmt::Display| {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("proxy {}: {}", self.addr, e),
+            )
+        };
+        match (self.kind, &self.auth) {
+            (Kind::HttpConnect, None) => {
+                async_http_proxy::http_connect_tokio(&mut stream, host, port)
+                    .await
+                    .map_err(|e| refused(&e))?;
+            }
+            (Kind::HttpConnect, Some((user, password))) => {
+                async_http_proxy::http_connect_tokio_with_basic_auth(
+                    &mut stream,
+                    host,
+                    port,
+                    user,
+                    password,
+                )
+                .await
+                .map_err(|e| refused(&e))?;
+            }
+            (Kind::Socks5, auth) => {
+                // IPv6 hosts come bracketed in URIs.
+                let target = (host.trim_start_matches('[').trim_end_matches(']'), port);
+                let socks = match auth {
+                    None => {
+                        tokio_socks::tcp::Socks5Stream::connect_with_socket(stream, target).await
+                    }
+                    Some((user, password)) => {
+                        tokio_socks::tcp::Socks5Stream::connect_with_password_and_socket(
+                            stream, target, user, password,
+                        )
+                        .await
+                    }
+                };
+                stream = socks.map_err(|e| refused(&e))?.into_inner();
+            }
+        }
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_proto::rate_limiter_server::SERVICE_NAME;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+
+    /// A proxy taking one connection, answering its handshake with
+    /// `handshake` and then relaying it to wherever that returned.
+    async fn proxy<F, Fut>(handshake: F) -> String
+    where
+        F: FnOnce(TcpStream) -> Fut + Send + 'static,
+        Fut: std::future::Future<Output = (TcpStream, String)> + Send,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (client, _) = listener.accept().await.unwrap();
+            let (mut client, target) = handshake(client).await;
+            let mut upstream = TcpStream::connect(target).await.unwrap();
+            let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+        });
+        addr
+    }
+
+    async fn http_connect(client: TcpStream) -> (TcpStream, String) {
+        let mut reader = BufReader::new(client);
+        let mut request = String::new();
+        reader.read_line(&mut request).await.unwrap();
+        let mut authorization = false;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            authorization |= line.starts_with("Proxy-Authorization: Basic ");
+            if line == "\r\n" {
+                break;
+            }
+        }
+        assert!(authorization);
+        let target = request.split(' ').nth(1).unwrap().to_string();
+        let mut client = reader.into_inner();
+        client
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .unwrap();
+        (client, target)
+    }
+
+    async fn socks5(mut client: TcpStream) -> (TcpStream, String) {
+        let mut greeting = [0; 3];
+        client.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(greeting, [5, 1, 0]);
+        client.write_all(&[5, 0]).await.unwrap();
+        // CONNECT to a domain name: version, command, reserved, type 3.
+        let mut request = [0; 5];
+        client.read_exact(&mut request).await.unwrap();
+        assert_eq!(request[..4], [5, 1, 0, 3]);
+        let mut host = vec![0; request[4] as usize];
+        client.read_exact(&mut host).await.unwrap();
+        let port = client.read_u16().await.unwrap();
+        client
+            .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        (
+            client,
+            format!("{}:{}", String::from_utf8(host).unwrap(), port),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_tunnels_through_http_and_socks5_proxies() {
+        let (mut reporter, health) = tonic_health::server::health_reporter();
+        reporter
+            .set_service_status(SERVICE_NAME, tonic_health::ServingStatus::Serving)
+            .await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(health)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        // By name, so the proxy has to resolve it.
+        let endpoint = Endpoint::from_shared(format!("http://localhost:{}", port)).unwrap();
+
+        let http = proxy(http_connect).await;
+        let socks = proxy(socks5).await;
+        for url in [
+            format!("http://user:secret@{}", http),
+            format!("socks5://{}", socks),
+        ] {
+            let channel = Proxy::parse(&url)
+                .unwrap()
+                .connect(endpoint.clone())
+                .await
+                .unwrap();
+            let status = HealthClient::new(channel)
+                .check(HealthCheckRequest {
+                    service: SERVICE_NAME.to_string(),
+                })
+                .await
+                .unwrap();
+            assert_eq!(status.into_inner().status, 1);
+        }
+
+        assert!(Proxy::parse("ftp://proxy:21").is_err());
+        assert_eq!(Proxy::parse("socks5://proxy").unwrap().addr, "proxy:1080");
+    }
+}
@@ -0,0 +1,238 @@
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+use tonic::{Code, Status};
+
+/// Statuses retried by default: the service couldn't be reached
+/// (`UNAVAILABLE`, such as while it restarts), or didn't answer in time
+/// (`DEADLINE_EXCEEDED`, such as while its backend is slow).
+pub const DEFAULT_RETRY_CODES: &[Code] = &[Code::Unavailable, Code::DeadlineExceeded];
+
+/// How [`GuardianClient`](crate::GuardianClient) retries calls that fail
+/// for a transient reason. The channel reconnects on its own; this decides
+/// how long a call waits for it before giving up. Other failures, such as
+/// `PERMISSION_DENIED` or `INVALID_ARGUMENT`, are returned at once.
+///
+/// A check whose connection dropped, or that timed out, after the service
+/// received it may be retried, and so counted twice. A call's own timeout
+/// covers its retries, so they never outlast it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Retries after the first attempt; 0 disables reconnection.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each one after.
+    pub initial_backoff: Duration,
+    /// Longest wait between retries, before jitter.
+    pub max_backoff: Duration,
+    /// Up to this fraction of each wait is added at random, so clients
+    /// cut off together don't all retry at the same moment; 0 for none.
+    pub jitter: f64,
+    /// Statuses worth retrying.
+    pub retry_codes: &'static [Code],
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            jitter: 0.25,
+            retry_codes: DEFAULT_RETRY_CODES,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Fail calls as soon as the service is unreachable.
+    pub fn disabled() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    /// Wait before retry number `retry`, counting from 0, without jitter.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+
+    /// [`backoff`](Self::backoff) with jitter added.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        backoff + backoff.mul_f64(jitter() * self.jitter.max(0.0))
+    }
+
+    /// Whether a call that failed with `code` should be tried again.
+    pub fn retries(&self, code: Code) -> bool {
+        self.retry_codes.contains(&code)
+    }
+
+    /// Run `call` until it succeeds, fails for a reason not worth
+    /// retrying, or runs out of retries. `on_retry` hears the code of each
+    /// failure that is retried.
+    pub(crate) async fn run<T, F, Fut>(
+        &self,
+        mut call: F,
+        mut on_retry: impl FnMut(Code),
+    ) -> Result<T, Status>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let mut retry = 0;
+        loop {
+            match call().await {
+                Err(status) if self.retries(status.code()) && retry < self.max_retries => {
+                    on_retry(status.code());
+                    tokio::time::sleep(self.delay(retry)).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// First wait between denied attempts at [`acquire`] when the service
+/// says to retry in under a second; doubled up to a second after that.
+///
+/// [`acquire`]: crate::GuardianClient::acquire
+const ACQUIRE_MIN_WAIT: Duration = Duration::from_millis(50);
+
+/// How long `acquire` waits after its `attempt`th denial: the service's
+/// `retry_after` (whole seconds, rounded down, so 0 means "soon"), plus
+/// up to a quarter more so waiting callers don't all come back at once.
+pub(crate) fn acquire_wait(retry_after_seconds: u32, attempt: u32) -> Duration {
+    let base = match retry_after_seconds {
+        0 => ACQUIRE_MIN_WAIT
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(Duration::from_secs(1)),
+        secs => Duration::from_secs(u64::from(secs)),
+    };
+    base + base.mul_f64(jitter() * 0.25)
+}
+
+/// A number in `[0, 1)`, different for every call.
+fn jitter() -> f64 {
+    let random = std::collections::hash_map::RandomState::new().hash_one(());
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_unavailable_with_backoff() {
+        let policy = ReconnectPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            jitter: 0.0,
+            ..ReconnectPolicy::default()
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(5), Duration::from_millis(300));
+
+        let calls = Cell::new(0);
+        let started = tokio::time::Instant::now();
+        let result = policy
+            .run(
+                || async {
+                    calls.set(calls.get() + 1);
+                    if calls.get() < 3 {
+                        Err(Status::unavailable("connection refused"))
+                    } else {
+                        Ok(calls.get())
+                    }
+                },
+                |code| assert_eq!(code, Code::Unavailable),
+            )
+            .await;
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(started.elapsed(), Duration::from_millis(300));
+
+        // Other failures, and the last unreachable one, are returned as is.
+        calls.set(0);
+        let denied = policy
+            .run(
+                || async {
+                    calls.set(calls.get() + 1);
+                    Err::<(), _>(Status::permission_denied("bad token"))
+                },
+                |_| panic!("retried PERMISSION_DENIED"),
+            )
+            .await;
+        assert_eq!(denied.unwrap_err().code(), Code::PermissionDenied);
+        assert_eq!(calls.get(), 1);
+        let down = ReconnectPolicy::disabled()
+            .run(
+                || async { Err::<(), _>(Status::unavailable("down")) },
+                |_| {},
+            )
+            .await;
+        assert_eq!(down.unwrap_err().code(), Code::Unavailable);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retries_only_the_configured_codes_with_jitter() {
+        let policy = ReconnectPolicy::default();
+        for retry in 0..8 {
+            let delay = policy.delay(retry);
+            let backoff = policy.backoff(retry);
+            assert!(delay >= backoff && delay <= backoff.mul_f64(1.25));
+        }
+
+        let calls = Cell::new(0);
+        let slow = policy
+            .run(
+                || async {
+                    calls.set(calls.get() + 1);
+                    if calls.get() < 2 {
+                        Err(Status::deadline_exceeded("backend timed out"))
+                    } else {
+                        Ok(())
+                    }
+                },
+                |_| {},
+            )
+            .await;
+        assert!(slow.is_ok());
+        assert_eq!(calls.get(), 2);
+
+        let unavailable_only = ReconnectPolicy {
+            retry_codes: &[Code::Unavailable],
+            ..policy
+        };
+        calls.set(0);
+        let slow = unavailable_only
+            .run(
+                || async {
+                    calls.set(calls.get() + 1);
+                    Err::<(), _>(Status::deadline_exceeded("backend timed out"))
+                },
+                |_| {},
+            )
+            .await;
+        assert_eq!(slow.unwrap_err().code(), Code::DeadlineExceeded);
+        assert_eq!(calls.get(), 1);
+        assert!(!policy.retries(Code::InvalidArgument));
+    }
+
+    #[test]
+    fn test_acquire_waits_out_retry_after_with_jitter() {
+        for attempt in 0..3 {
+            let wait = acquire_wait(2, attempt);
+            assert!(wait >= Duration::from_secs(2) && wait <= Duration::from_millis(2500));
+        }
+        let soon = acquire_wait(0, 0);
+        assert!(soon >= Duration::from_millis(50) && soon <= Duration::from_millis(63));
+        assert!(acquire_wait(0, 10) <= Duration::from_millis(1250));
+    }
+}
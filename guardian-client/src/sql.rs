@@ -0,0 +1,428 @@
+//! Per-tenant query throttling for sqlx pools (`sqlx` feature). Each
+//! statement is charged to its tenant's Guardian bucket before it runs,
+//! priced by its kind, so a tenant over its share of database load waits or
+//! is refused while the others carry on.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, BoxStream};
+use futures_util::{StreamExt, TryStreamExt};
+use sqlx::error::{DatabaseError, ErrorKind};
+use sqlx::{Database, Describe, Either, Execute, Executor, Pool};
+
+use crate::api::GuardianApi;
+
+/// What a statement does, judged by its first keyword.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatementKind {
+    /// `SELECT`, `SHOW`, `EXPLAIN`, `VALUES`, and `WITH` queries that
+    /// modify nothing.
+    Read,
+    /// `INSERT`, `UPDATE`, `DELETE`, `MERGE`, `REPLACE`, `COPY`, and `WITH`
+    /// queries containing one of them.
+    Write,
+    /// DDL and privileges: `CREATE`, `ALTER`, `DROP`, `TRUNCATE`, `GRANT`,
+    /// `REVOKE`, ...
+    Schema,
+    /// Anything else, such as `BEGIN`, `COMMIT` or `SET`.
+    Other,
+}
+
+impl StatementKind {
+    pub fn of(sql: &str) -> Self {
+        let mut words = words(sql);
+        let Some(first) = words.next() else {
+            return Self::Other;
+        };
+        match first.to_ascii_uppercase().as_str() {
+            "SELECT" | "SHOW" | "EXPLAIN" | "VALUES" | "TABLE" | "DESCRIBE" | "DESC" => Self::Read,
+            "INSERT" | "UPDATE" | "DELETE" | "MERGE" | "REPLACE" | "UPSERT" | "COPY" => Self::Write,
+            "CREATE" | "ALTER" | "DROP" | "TRUNCATE" | "RENAME" | "COMMENT" | "GRANT"
+            | "REVOKE" => Self::Schema,
+            // A CTE is a write if any part of it is.
+            "WITH" => {
+                let writes = words.any(|word| {
+                    ["INSERT", "UPDATE", "DELETE", "MERGE"]
+                        .iter()
+                        .any(|w| word.eq_ignore_ascii_case(w))
+                });
+                if writes {
+                    Self::Write
+                } else {
+                    Self::Read
+                }
+            }
+            _ => Self::Other,
+        }
+    }
+}
+
+/// `sql`'s words, skipping comments, string literals and quoted
+/// identifiers.
+fn words(sql: &str) -> impl Iterator<Item = &str> {
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut rest = sql;
+    std::iter::from_fn(move || loop {
+        if let Some(after) = rest.strip_prefix("--") {
+            rest = skip_to(after, "\n");
+        } else if let Some(after) = rest.strip_prefix("/*") {
+            rest = skip_to(after, "*/");
+        } else if let Some(after) = rest.strip_prefix('\'') {
+            rest = skip_to(after, "'");
+        } else if let Some(after) = rest.strip_prefix('"') {
+            rest = skip_to(after, "\"");
+        } else {
+            let c = rest.chars().next()?;
+            if !is_word(c) {
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            let end = rest.find(|c| !is_word(c)).unwrap_or(rest.len());
+            let (word, after) = rest.split_at(end);
+            rest = after;
+            return Some(word);
+        }
+    })
+}
+
+/// What follows the first `end` in `rest`, if anything.
+fn skip_to<'a>(rest: &'a str, end: &str) -> &'a str {
+    rest.split_once(end).map_or("", |(_, next)| next)
+}
+
+/// Tokens each kind of statement costs. A cost of 0 runs the statement
+/// unchecked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryCosts {
+    pub read: u32,
+    pub write: u32,
+    pub schema: u32,
+    pub other: u32,
+}
+
+impl Default for QueryCosts {
+    /// Reads 1, writes 5, schema changes 50; transaction control and the
+    /// like are free.
+    fn default() -> Self {
+        Self {
+            read: 1,
+            write: 5,
+            schema: 50,
+            other: 0,
+        }
+    }
+}
+
+impl QueryCosts {
+    pub fn of(&self, kind: StatementKind) -> u32 {
+        match kind {
+            StatementKind::Read => self.read,
+            StatementKind::Write => self.write,
+            StatementKind::Schema => self.schema,
+            StatementKind::Other => self.other,
+        }
+    }
+}
+
+/// A statement refused because its tenant is over its limit. Executors
+/// return it as `sqlx::Error::Database`, from which
+/// `try_downcast_ref::<QueryThrottled>()` gets it back.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("query rate limited for {key}; retry after {}s", .retry_after.as_secs())]
+pub struct QueryThrottled {
+    /// The bucket that was empty, `db:<tenant>`.
+    pub key: String,
+    pub retry_after: Duration,
+}
+
+impl DatabaseError for QueryThrottled {
+    fn message(&self) -> &str {
+        "query rate limited"
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Borrowed("guardian_rate_limited"))
+    }
+
+    fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// A sqlx `Pool` whose statements are charged to per-tenant buckets, keyed
+/// `db:<tenant>`. Run queries on [`tenant`](Self::tenant); the pool itself
+/// stays reachable through [`pool`](Self::pool) for unthrottled work.
+pub struct ThrottledPool<DB: Database, A> {
+    pool: Pool<DB>,
+    api: Arc<A>,
+    costs: QueryCosts,
+    max_wait: Duration,
+    fail_open: bool,
+}
+
+impl<DB: Database, A> Clone for ThrottledPool<DB, A> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            api: Arc::clone(&self.api),
+            costs: self.costs,
+            max_wait: self.max_wait,
+            fail_open: self.fail_open,
+        }
+    }
+}
+
+impl<DB: Database, A: GuardianApi> ThrottledPool<DB, A> {
+    /// Throttle `pool` with the [default costs](QueryCosts::default),
+    /// refusing statements over the limit at once.
+    pub fn new(pool: Pool<DB>, api: A) -> Self {
+        Self {
+            pool,
+            api: Arc::new(api),
+            costs: QueryCosts::default(),
+            max_wait: Duration::ZERO,
+            fail_open: true,
+        }
+    }
+
+    pub fn with_costs(mut self, costs: QueryCosts) -> Self {
+        self.costs = costs;
+        self
+    }
+
+    /// Hold a statement over the limit until its tokens are back, if that
+    /// takes at most `max_wait`, rather than refusing it.
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Whether to run statements when Guardian can't decide (the
+    /// default), or treat them as over the limit.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    pub fn pool(&self) -> &Pool<DB> {
+        &self.pool
+    }
+
+    /// An executor charging `tenant` for the statements it runs:
+    /// `query(..).fetch_all(&pool.tenant("acme"))`.
+    pub fn tenant(&self, tenant: impl Into<String>) -> TenantPool<'_, DB, A> {
+        TenantPool {
+            throttled: self,
+            key: format!("db:{}", tenant.into()),
+        }
+    }
+
+    /// Charge `tenant` for `sql` without running it, for statements run
+    /// elsewhere, such as in a transaction.
+    pub async fn admit(&self, tenant: &str, sql: &str) -> Result<(), QueryThrottled> {
+        self.admit_key(&format!("db:{}", tenant), sql).await
+    }
+
+    async fn admit_key(&self, key: &str, sql: &str) -> Result<(), QueryThrottled> {
+        let cost = self.costs.of(StatementKind::of(sql));
+        if cost == 0 {
+            return Ok(());
+        }
+        let mut waited = Duration::ZERO;
+        loop {
+            let retry_after = match self.api.check_limit_detailed(key, cost).await {
+                Ok(decision) if decision.allowed => return Ok(()),
+                Ok(decision) => Duration::from_secs(decision.retry_after_seconds.max(1).into()),
+                Err(_) if self.fail_open => return Ok(()),
+                Err(_) => Duration::from_secs(1),
+            };
+            if waited + retry_after > self.max_wait {
+                return Err(QueryThrottled {
+                    key: key.to_string(),
+                    retry_after,
+                });
+            }
+            tokio::time::sleep(retry_after).await;
+            waited += retry_after;
+        }
+    }
+}
+
+/// One tenant's view of a [`ThrottledPool`], usable wherever sqlx takes an
+/// executor.
+pub struct TenantPool<'p, DB: Database, A> {
+    throttled: &'p ThrottledPool<DB, A>,
+    key: String,
+}
+
+impl<DB: Database, A> fmt::Debug for TenantPool<'_, DB, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantPool")
+            .field("key", &self.key)
+            .field("pool", &self.throttled.pool)
+            .finish()
+    }
+}
+
+impl<'c, DB, A> Executor<'c> for &'c TenantPool<'_, DB, A>
+where
+    DB: Database,
+    for<'e> &'e Pool<DB>: Executor<'e, Database = DB>,
+    A: GuardianApi,
+{
+    type Database = DB;
+
+    fn fetch_many<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxStream<'e, Result<Either<DB::QueryResult, DB::Row>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, DB>,
+    {
+        let sql = query.sql();
+        stream::once(async move {
+            self.throttled.admit_key(&self.key, sql).await?;
+            Ok::<_, sqlx::Error>(self.throttled.pool.fetch_many(query))
+        })
+        .try_flatten()
+        .boxed()
+    }
+
+    fn fetch_optional<'e, 'q: 'e, E>(
+        self,
+        query: E,
+    ) -> BoxFuture<'e, Result<Option<DB::Row>, sqlx::Error>>
+    where
+        'c: 'e,
+        E: 'q + Execute<'q, DB>,
+    {
+        let sql = query.sql();
+        Box::pin(async move {
+            self.throttled.admit_key(&self.key, sql).await?;
+            self.throttled.pool.fetch_optional(query).await
+        })
+    }
+
+    /// Preparing runs nothing, so it isn't charged.
+    fn prepare_with<'e, 'q: 'e>(
+        self,
+        sql: &'q str,
+        parameters: &'e [DB::TypeInfo],
+    ) -> BoxFuture<'e, Result<DB::Statement<'q>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.throttled.pool.prepare_with(sql, parameters)
+    }
+
+    fn describe<'e, 'q: 'e>(self, sql: &'q str) -> BoxFuture<'e, Result<Describe<DB>, sqlx::Error>>
+    where
+        'c: 'e,
+    {
+        self.throttled.pool.describe(sql)
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::MockGuardianClient;
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[test]
+    fn test_statement_kinds() {
+        let kinds: Vec<_> = [
+            "select * from users",
+            "  -- who\n/* all */ (SELECT 1)",
+            "insert into users values ('select')",
+            "WITH moved AS (DELETE FROM queue RETURNING *) SELECT * FROM moved",
+            "with recent as (select * from orders) select count(*) from recent",
+            "with t as (select 'update' as \"delete\") select * from t",
+            "ALTER TABLE users ADD COLUMN plan text",
+            "BEGIN",
+            "",
+        ]
+        .iter()
+        .map(|sql| StatementKind::of(sql))
+        .collect();
+        assert_eq!(
+            kinds,
+            [
+                StatementKind::Read,
+                StatementKind::Read,
+                StatementKind::Write,
+                StatementKind::Write,
+                StatementKind::Read,
+                StatementKind::Read,
+                StatementKind::Schema,
+                StatementKind::Other,
+                StatementKind::Other,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_charges_tenants_by_statement_kind() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let mock = MockGuardianClient::new();
+        let throttled = ThrottledPool::new(pool, mock.clone());
+        let acme = throttled.tenant("acme");
+
+        sqlx::query("BEGIN").execute(&acme).await.unwrap();
+        sqlx::query("CREATE TABLE t (n INTEGER)")
+            .execute(&acme)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO t VALUES (1)")
+            .execute(&acme)
+            .await
+            .unwrap();
+        let n: i64 = sqlx::query_scalar("SELECT n FROM t")
+            .fetch_one(&acme)
+            .await
+            .unwrap();
+        assert_eq!(n, 1);
+
+        mock.deny("db:acme", 30);
+        let error = sqlx::query("INSERT INTO t VALUES (2)")
+            .execute(&acme)
+            .await
+            .unwrap_err();
+        let throttled_error = error
+            .as_database_error()
+            .and_then(|e| e.try_downcast_ref::<QueryThrottled>())
+            .unwrap();
+        assert_eq!(throttled_error.key, "db:acme");
+        assert_eq!(throttled_error.retry_after, Duration::from_secs(30));
+        // Other tenants are charged to buckets of their own.
+        sqlx::query("SELECT n FROM t")
+            .fetch_all(&throttled.tenant("globex"))
+            .await
+            .unwrap();
+
+        // `BEGIN` is free; the rest cost by kind.
+        let costs: Vec<_> = mock.calls().iter().map(|call| call.cost).collect();
+        assert_eq!(costs, [50, 5, 1, 5, 1]);
+    }
+}
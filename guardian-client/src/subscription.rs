@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Channel;
+use tonic::{Code, Request, Status};
+
+use crate::credentials::Outbound;
+use crate::error::{ClientError, Result};
+use crate::observer::ClientObserver;
+use crate::proto::{rate_limiter_client::RateLimiterClient, LimitStatusUpdate, StreamLimitRequest};
+use crate::retry::ReconnectPolicy;
+
+/// Updates buffered for a subscriber that has fallen behind.
+const BUFFER: usize = 64;
+
+/// Follow `request`'s `StreamLimitStatus` stream, opening it again whenever
+/// it drops: the service restarting ends streams cleanly, a lost
+/// connection fails them. Reconnection backs off as `policy` says, and the
+/// count starts over once an update gets through. An error is the last
+/// item; dropping the stream stops the subscription.
+pub(crate) fn subscribe(
+    client: RateLimiterClient<Channel>,
+    request: StreamLimitRequest,
+    outbound: Outbound,
+    policy: ReconnectPolicy,
+    observer: Option<Arc<dyn ClientObserver>>,
+) -> ReceiverStream<Result<LimitStatusUpdate>> {
+    let (tx, rx) = mpsc::channel(BUFFER);
+    tokio::spawn(async move {
+        let mut client = client;
+        let mut retry = 0;
+        loop {
+            let mut call = Request::new(request.clone());
+            match outbound.metadata() {
+                Ok(metadata) => *call.metadata_mut() = metadata,
+                Err(e) => {
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            }
+            let opened = tokio::select! {
+                _ = tx.closed() => return,
+                opened = client.stream_limit_status(call) => opened,
+            };
+            let failure = match opened {
+                Ok(response) => {
+                    let mut updates = response.into_inner();
+                    loop {
+                        let next = tokio::select! {
+                            _ = tx.closed() => return,
+                            next = updates.message() => next,
+                        };
+                        match next {
+                            Ok(Some(update)) => {
+                                retry = 0;
+                                if tx.send(Ok(update)).await.is_err() {
+                                    return;
+                                }
+                            }
+                            Ok(None) => break None,
+                            Err(status) => break Some(status),
+                        }
+                    }
+                }
+                Err(status) => Some(status),
+            };
+
+            let failure = failure.unwrap_or_else(|| Status::unavailable("status stream ended"));
+            if !reconnects_after(&failure) || retry >= policy.max_retries {
+                let _ = tx.send(Err(ClientError::from(failure))).await;
+                return;
+            }
+            if let Some(observer) = &observer {
+                observer.on_retry("StreamLimitStatus", failure.code());
+            }
+            tokio::select! {
+                _ = tx.closed() => return,
+                _ = tokio::time::sleep(policy.delay(retry)) => {}
+            }
+            retry += 1;
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+/// Whether a stream that failed with `status` is worth opening again. A
+/// connection lost mid-stream surfaces as `UNKNOWN` or `CANCELLED` rather
+/// than `UNAVAILABLE`.
+fn reconnects_after(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        Code::Unavailable | Code::Unknown | Code::Cancelled
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio_stream::StreamExt;
+
+    #[tokio::test]
+    async fn test_gives_up_after_the_reconnect_policy() {
+        // Nothing listens on port 1.
+        let channel = tonic::transport::Endpoint::from_static("http://127.0.0.1:1").connect_lazy();
+        let policy = ReconnectPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+            ..ReconnectPolicy::default()
+        };
+        let request = StreamLimitRequest {
+            client_id: "user1".to_string(),
+            ..StreamLimitRequest::default()
+        };
+        let mut updates = subscribe(
+            RateLimiterClient::new(channel),
+            request,
+            Outbound::default(),
+            policy,
+            None,
+        );
+
+        let last = updates.next().await.unwrap();
+        assert!(matches!(last, Err(ClientError::Unavailable { .. })));
+        assert!(updates.next().await.is_none());
+        assert!(!reconnects_after(&Status::permission_denied("no")));
+    }
+}
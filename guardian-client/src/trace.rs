@@ -0,0 +1,75 @@
+// W3C trace context for outgoing calls. The service continues the trace it
+// finds in a call's `traceparent` metadata (see its telemetry module), so
+// with this the limiter's spans show up inside the caller's trace. The
+// context is taken from the current `tracing` span when the application
+// runs `tracing-opentelemetry`, and from OpenTelemetry's own current
+// context otherwise.
+
+use opentelemetry::propagation::{Injector, TextMapCompositePropagator, TextMapPropagator};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::Context;
+use opentelemetry_sdk::propagation::{BaggagePropagator, TraceContextPropagator};
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Add `traceparent`, `tracestate` and `baggage` for the current context
+/// to `metadata`.
+pub(crate) fn inject(metadata: &mut MetadataMap) {
+    let mut context = tracing::Span::current().context();
+    if !context.span().span_context().is_valid() {
+        context = Context::current();
+    }
+    let propagator = TextMapCompositePropagator::new(vec![
+        Box::new(TraceContextPropagator::new()),
+        Box::new(BaggagePropagator::new()),
+    ]);
+    propagator.inject_context(&context, &mut MetadataInjector(metadata));
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        // Values that aren't valid metadata are dropped, not the call.
+        if let (Ok(key), Ok(value)) = (
+            MetadataKey::from_bytes(key.as_bytes()),
+            MetadataValue::try_from(value),
+        ) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::baggage::BaggageExt;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_injects_the_current_spans_context() {
+        let provider = TracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        tracing::subscriber::with_default(subscriber, || {
+            let mut outside = MetadataMap::new();
+            inject(&mut outside);
+            assert!(outside.get("traceparent").is_none());
+
+            let _baggage =
+                Context::current_with_baggage([KeyValue::new("tenant", "acme")]).attach();
+            let span = tracing::info_span!("handler");
+            let trace_id = span.context().span().span_context().trace_id();
+            let _entered = span.enter();
+            let mut metadata = MetadataMap::new();
+            inject(&mut metadata);
+
+            let traceparent = metadata.get("traceparent").unwrap().to_str().unwrap();
+            assert!(traceparent.contains(&trace_id.to_string()));
+            assert_eq!(metadata.get("baggage").unwrap(), "tenant=acme");
+        });
+    }
+}
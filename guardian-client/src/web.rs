@@ -0,0 +1,155 @@
+// Client for browsers and edge workers (wasm32, `grpc-web` feature).
+// Calls are grpc-web requests made with `fetch`, to a service that has
+// `global.grpc_web` on, so there is no connection of our own to keep:
+// no reconnect policy, balancing or denial cache here, and futures are
+// not `Send`, as nothing on wasm32 is.
+
+use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+use tonic::Request;
+use tonic_web_wasm_client::Client;
+
+use crate::credentials::{
+    CallCredentials, Credentials, Outbound, API_KEY_HEADER, AUTHORIZATION_HEADER,
+};
+use crate::decision::LimitCheckResult;
+use crate::error::{ClientError, Result};
+use crate::proto::{
+    rate_limiter_client::RateLimiterClient, CheckLimitBatchRequest, CheckLimitRequest,
+    GetUsageRequest,
+};
+
+/// Guardian client over grpc-web, for wasm32.
+///
+/// # Examples
+///
+/// ```ignore
+/// use guardian_client::WebGuardianClient;
+///
+/// let client = WebGuardianClient::new("https://guardian.example.com")
+///     .with_api_key("dashboard-key")?;
+/// let usage = client.get_usage("user123").await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct WebGuardianClient {
+    inner: RateLimiterClient<Client>,
+    namespace: String,
+    outbound: Outbound,
+}
+
+impl WebGuardianClient {
+    /// A client for the service at `base_url`, such as
+    /// `https://guardian.example.com`. Nothing is sent until the first call.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            inner: RateLimiterClient::new(Client::new(base_url.into())),
+            namespace: String::new(),
+            outbound: Outbound::new(MetadataMap::new(), Credentials::default()),
+        }
+    }
+
+    /// Check limits in `namespace` rather than the default one.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = namespace.into();
+        self
+    }
+
+    /// API key sent with every call, for services with `auth.api_keys`.
+    pub fn with_api_key(self, key: &str) -> Result<Self> {
+        self.with_header(API_KEY_HEADER, key)
+    }
+
+    /// JWT sent with every call as `authorization: Bearer <token>`.
+    pub fn with_bearer_token(self, token: &str) -> Result<Self> {
+        self.with_header(AUTHORIZATION_HEADER, &format!("Bearer {}", token))
+    }
+
+    /// Add credentials to each call as it is sent; see [`CallCredentials`].
+    pub fn with_credentials(mut self, credentials: impl CallCredentials) -> Self {
+        self.outbound.set_credentials(Credentials::new(credentials));
+        self
+    }
+
+    fn with_header(mut self, key: &'static str, value: &str) -> Result<Self> {
+        let value: AsciiMetadataValue = value.parse().map_err(|_| {
+            ClientError::ConfigError(format!("invalid value for metadata '{}'", key))
+        })?;
+        self.outbound.insert(key, value);
+        Ok(self)
+    }
+
+    /// Whether `client_id` may spend `cost` tokens now.
+    pub async fn check_limit(&self, client_id: &str, cost: u32) -> Result<bool> {
+        Ok(self.check_limit_detailed(client_id, cost).await?.allowed)
+    }
+
+    /// Check a rate limit and return the service's full answer.
+    pub async fn check_limit_detailed(
+        &self,
+        client_id: &str,
+        cost: u32,
+    ) -> Result<LimitCheckResult> {
+        self.check_limit_for_tier(client_id, "", cost).await
+    }
+
+    /// Check a rate limit against one of the service's configured tiers.
+    pub async fn check_limit_for_tier(
+        &self,
+        client_id: &str,
+        tier: &str,
+        cost: u32,
+    ) -> Result<LimitCheckResult> {
+        let request = self.request(self.check_request(client_id, tier, cost))?;
+        let response = self.inner.clone().check_limit(request).await?;
+        Ok(response.into_inner().into())
+    }
+
+    /// Check several `(client_id, cost)` entries in one round trip;
+    /// results line up with `checks`.
+    pub async fn check_limits(&self, checks: &[(&str, u32)]) -> Result<Vec<LimitCheckResult>> {
+        let batch = CheckLimitBatchRequest {
+            checks: checks
+                .iter()
+                .map(|(client_id, cost)| self.check_request(client_id, "", *cost))
+                .collect(),
+        };
+        let response = self
+            .inner
+            .clone()
+            .check_limit_batch(self.request(batch)?)
+            .await?;
+        Ok(response
+            .into_inner()
+            .results
+            .into_iter()
+            .map(Into::into)
+            .collect())
+    }
+
+    /// Tokens `client_id` has used from its bucket.
+    pub async fn get_usage(&self, client_id: &str) -> Result<u64> {
+        let request = GetUsageRequest {
+            client_id: client_id.to_string(),
+            namespace: self.namespace.clone(),
+        };
+        let response = self.inner.clone().get_usage(self.request(request)?).await?;
+        Ok(response.into_inner().used_tokens)
+    }
+
+    fn check_request(&self, client_id: &str, tier: &str, cost: u32) -> CheckLimitRequest {
+        CheckLimitRequest {
+            client_id: client_id.to_string(),
+            cost,
+            override_config: None,
+            tier: tier.to_string(),
+            namespace: self.namespace.clone(),
+            refundable: false,
+        }
+    }
+
+    /// `message` with the metadata every call carries.
+    fn request<T>(&self, message: T) -> Result<Request<T>> {
+        let mut request = Request::new(message);
+        *request.metadata_mut() = self.outbound.metadata()?;
+        Ok(request)
+    }
+}
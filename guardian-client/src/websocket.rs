@@ -0,0 +1,207 @@
+//! Inbound message limits for WebSocket servers on tokio-tungstenite
+//! (`websocket` feature). Every text or binary message a client sends is
+//! charged to its bucket before the server sees it; control frames pass
+//! unchecked, so pings keep the connection alive while it's throttled.
+
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_tungstenite::WebSocketStream;
+
+use crate::api::GuardianApi;
+
+/// What a client over its limit gets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnExceeded {
+    /// Close the connection with this code (and the reason
+    /// `rate limited`): 1008, policy violation, unless set.
+    Close(CloseCode),
+    /// Drop the message and answer with a text frame,
+    /// `{"error":"rate_limited","retry_after":<seconds>}`, leaving the
+    /// connection open.
+    Backoff,
+}
+
+impl Default for OnExceeded {
+    fn default() -> Self {
+        Self::Close(CloseCode::Policy)
+    }
+}
+
+type CostFn = dyn Fn(&Message) -> u32 + Send + Sync;
+
+/// A server-side `WebSocketStream` whose [`next`](Self::next) only hands
+/// out messages within the connection's limit.
+pub struct ThrottledWebSocket<S, A> {
+    ws: WebSocketStream<S>,
+    api: A,
+    key: String,
+    cost: Box<CostFn>,
+    on_exceeded: OnExceeded,
+    fail_open: bool,
+}
+
+impl<S, A> ThrottledWebSocket<S, A>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    A: GuardianApi,
+{
+    /// Limit `ws`'s inbound messages by `key`, a token a message: the
+    /// user's id to share a limit across their connections, or a
+    /// connection id for one each.
+    pub fn new(ws: WebSocketStream<S>, api: A, key: impl Into<String>) -> Self {
+        Self {
+            ws,
+            api,
+            key: key.into(),
+            cost: Box::new(|_| 1),
+            on_exceeded: OnExceeded::default(),
+            fail_open: true,
+        }
+    }
+
+    /// Tokens each message costs, such as its size in KiB.
+    pub fn with_cost(mut self, cost: impl Fn(&Message) -> u32 + Send + Sync + 'static) -> Self {
+        self.cost = Box::new(cost);
+        self
+    }
+
+    pub fn with_on_exceeded(mut self, on_exceeded: OnExceeded) -> Self {
+        self.on_exceeded = on_exceeded;
+        self
+    }
+
+    /// Whether to hand messages out when Guardian can't decide (the
+    /// default), or treat them as over the limit.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// The next message within the limit; `None` once the connection is
+    /// closed, by either side.
+    pub async fn next(&mut self) -> Option<Result<Message, Error>> {
+        loop {
+            let message = match self.ws.next().await? {
+                Ok(message) => message,
+                Err(e) => return Some(Err(e)),
+            };
+            if !matches!(message, Message::Text(_) | Message::Binary(_)) {
+                return Some(Ok(message));
+            }
+            let Some(retry_after) = self.over_limit(&message).await else {
+                return Some(Ok(message));
+            };
+            let sent = match self.on_exceeded {
+                OnExceeded::Close(code) => {
+                    let frame = CloseFrame {
+                        code,
+                        reason: "rate limited".into(),
+                    };
+                    match self.ws.close(Some(frame)).await {
+                        Ok(()) => return None,
+                        Err(e) => Err(e),
+                    }
+                }
+                OnExceeded::Backoff => {
+                    let backoff = format!(
+                        r#"{{"error":"rate_limited","retry_after":{}}}"#,
+                        retry_after.as_secs()
+                    );
+                    self.ws.send(Message::Text(backoff)).await
+                }
+            };
+            if let Err(e) = sent {
+                return Some(Err(e));
+            }
+        }
+    }
+
+    /// Send `message` to the client.
+    pub async fn send(&mut self, message: Message) -> Result<(), Error> {
+        self.ws.send(message).await
+    }
+
+    pub fn into_inner(self) -> WebSocketStream<S> {
+        self.ws
+    }
+
+    /// `None` if `message` is within the limit, or how long until it
+    /// would be.
+    async fn over_limit(&self, message: &Message) -> Option<Duration> {
+        match self
+            .api
+            .check_limit_detailed(&self.key, (self.cost)(message))
+            .await
+        {
+            Ok(decision) if decision.allowed => None,
+            Ok(decision) => Some(Duration::from_secs(
+                decision.retry_after_seconds.max(1).into(),
+            )),
+            Err(_) if self.fail_open => None,
+            Err(_) => Some(Duration::from_secs(1)),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::MockGuardianClient;
+    use tokio::io::DuplexStream;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    async fn pair() -> (WebSocketStream<DuplexStream>, WebSocketStream<DuplexStream>) {
+        let (server, client) = tokio::io::duplex(4096);
+        (
+            WebSocketStream::from_raw_socket(server, Role::Server, None).await,
+            WebSocketStream::from_raw_socket(client, Role::Client, None).await,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_backs_off_then_closes_over_limit() {
+        let mock = MockGuardianClient::new();
+        let (server, mut client) = pair().await;
+        let mut server = ThrottledWebSocket::new(server, mock.clone(), "ws:user:42")
+            .with_on_exceeded(OnExceeded::Backoff);
+
+        client.send(Message::Text("hi".into())).await.unwrap();
+        assert_eq!(
+            server.next().await.unwrap().unwrap(),
+            Message::Text("hi".into())
+        );
+
+        mock.deny("ws:user:42", 5);
+        client.send(Message::Text("spam".into())).await.unwrap();
+        client.send(Message::Ping(Vec::new())).await.unwrap();
+        // The spam is dropped; the ping comes through.
+        assert!(matches!(
+            server.next().await.unwrap().unwrap(),
+            Message::Ping(_)
+        ));
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            Message::Text(r#"{"error":"rate_limited","retry_after":5}"#.into())
+        );
+
+        let mut server = server.with_on_exceeded(OnExceeded::default());
+        client.send(Message::Text("more".into())).await.unwrap();
+        let closing = tokio::spawn(async move { server.next().await });
+        // Past the pong tungstenite answered the ping with.
+        let close = loop {
+            match client.next().await.unwrap().unwrap() {
+                Message::Close(frame) => break frame.unwrap(),
+                _ => continue,
+            }
+        };
+        assert_eq!(close.code, CloseCode::Policy);
+        assert_eq!(close.reason, "rate limited");
+        drop(client);
+        assert!(closing.await.unwrap().is_none());
+    }
+}
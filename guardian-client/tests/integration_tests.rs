@@ -12,7 +12,7 @@ mod tests {
     #[tokio::test]
     #[ignore] 
     async fn test_check_limit() {
-        let mut client = GuardianClient::connect("http://localhost:50051")
+        let client = GuardianClient::connect("http://localhost:50051")
             .await
             .unwrap();
         
@@ -23,7 +23,7 @@ mod tests {
     #[tokio::test]
     #[ignore] 
     async fn test_get_usage() {
-        let mut client = GuardianClient::connect("http://localhost:50051")
+        let client = GuardianClient::connect("http://localhost:50051")
             .await
             .unwrap();
         
@@ -31,4 +31,117 @@ mod tests {
         let usage = client.get_usage("usage_test").await.unwrap();
         assert!(usage > 0);
     }
+
+    #[tokio::test]
+    #[ignore] 
+    async fn test_check_limits() {
+        let client = GuardianClient::connect("http://localhost:50051")
+            .await
+            .unwrap();
+
+        let results = client
+            .check_limits(&[("limits_user", 1), ("limits_ip", 1), ("limits_route", 1)])
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 3);
+    }
+
+    #[tokio::test]
+    #[ignore] 
+    async fn test_dropped_permit_refunds() {
+        let client = GuardianClient::connect("http://localhost:50051")
+            .await
+            .unwrap();
+
+        let permit = client.try_acquire("permit_test", 50).await.unwrap().unwrap();
+        let spent = client.get_usage("permit_test").await.unwrap();
+        drop(permit);
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        assert!(client.get_usage("permit_test").await.unwrap() < spent);
+
+        let permit = client.try_acquire("permit_test", 1).await.unwrap().unwrap();
+        permit.commit();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    #[ignore] 
+    async fn test_unix_socket_connection() {
+        let client = GuardianClient::connect_unix("/tmp/guardian.sock")
+            .await
+            .unwrap();
+
+        assert!(client.check_limit("uds_test", 1).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore] 
+    async fn test_remote_backend() {
+        use guardian_client::RemoteGuardianBackend;
+        use guardian_core::StorageBackend;
+
+        let backend = RemoteGuardianBackend::connect("http://localhost:50051")
+            .await
+            .unwrap();
+
+        assert!(backend.take_token("remote_test", 1).await.is_ok());
+        assert!(backend.get_usage("remote_test").await.is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore] 
+    async fn test_check_pipeline() {
+        let client = GuardianClient::connect("http://localhost:50051")
+            .await
+            .unwrap();
+        let pipeline = std::sync::Arc::new(client.check_pipeline().await.unwrap());
+
+        let checks: Vec<_> = (0..100)
+            .map(|_| {
+                let pipeline = pipeline.clone();
+                tokio::spawn(async move { pipeline.check("pipeline_test", 1).await })
+            })
+            .collect();
+        for check in checks {
+            assert!(check.await.unwrap().is_ok());
+        }
+    }
+
+    #[tokio::test]
+    #[ignore] 
+    async fn test_admin_operations() {
+        use guardian_client::proto::KeyRanking;
+
+        let client = GuardianClient::connect("http://localhost:50051")
+            .await
+            .unwrap()
+            .with_admin_token("admin-secret");
+
+        client.check_limit("admin_test:1", 1).await.unwrap();
+        client.check_limit("admin_test:2", 1).await.unwrap();
+        assert_eq!(client.list_keys("admin_test:*").await.unwrap().len(), 2);
+        assert!(!client.top_keys(10, KeyRanking::Usage).await.unwrap().is_empty());
+        assert_eq!(client.bulk_reset("admin_test:*").await.unwrap(), 2);
+
+        client.ban("admin_test:1", None, "test").await.unwrap();
+        assert!(!client.check_limit("admin_test:1", 1).await.unwrap());
+        assert!(client.unban("admin_test:1").await.unwrap());
+        assert!(!client.unban("admin_test:1").await.unwrap());
+    }
+
+    #[cfg(feature = "blocking")]
+    #[test]
+    #[ignore] 
+    fn test_blocking_client() {
+        use guardian_client::blocking;
+
+        let client = blocking::GuardianClient::connect("http://localhost:50051").unwrap();
+        assert!(client.check_limit("blocking_test", 1).unwrap());
+        assert!(client.get_usage("blocking_test").unwrap() > 0);
+
+        let permit = client.try_acquire("blocking_test", 1).unwrap().unwrap();
+        permit.commit();
+        let mut updates = client.subscribe_status("blocking_test");
+        assert!(updates.next().unwrap().is_ok());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,286 @@
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use guardian_core::{KvBackend, KvEntry, KvStore, RateLimitError, TokenBucketConfig};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// How long one session keeps being used for new writes before a fresh one
+/// is created. Buckets are deleted between `session_ttl` and roughly twice
+/// that after their last write (Consul may delay invalidation up to 2x TTL).
+const SESSION_ROTATION: Duration = Duration::from_secs(60);
+
+/// Token buckets in Consul KV: `KvBackend` over a `ConsulStore`.
+pub type ConsulBackend = KvBackend<ConsulStore>;
+
+struct CurrentSession {
+    id: String,
+    created_at: Instant,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct KvPair {
+    value: Option<String>,
+    modify_index: u64,
+    session: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TxnErrors {
+    errors: Vec<TxnError>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct TxnError {
+    op_index: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SessionCreated {
+    #[serde(rename = "ID")]
+    id: String,
+}
+
+/// `KvStore` on Consul's KV API.
+///
+/// Writes use check-and-set on `ModifyIndex` inside a transaction that also
+/// locks the key with a rotating session created with `Behavior=delete`, so
+/// idle buckets disappear when their session's TTL runs out. Because expiry
+/// rides along with every write, `expire` itself has nothing to do.
+pub struct ConsulStore {
+    http: reqwest::Client,
+    address: String,
+    prefix: String,
+    token: Option<String>,
+    session_ttl: Duration,
+    session: Mutex<Option<CurrentSession>>,
+}
+
+impl ConsulStore {
+    /// `address` is the agent's HTTP address, e.g. `http://127.0.0.1:8500`.
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            address: address.into().trim_end_matches('/').to_string(),
+            prefix: "guardian/buckets/".to_string(),
+            token: None,
+            session_ttl: Duration::from_secs(3600),
+            session: Mutex::new(None),
+        }
+    }
+
+    /// ACL token sent as `X-Consul-Token`.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// TTL of the sessions holding bucket keys (Consul accepts 10s to 24h).
+    pub fn with_session_ttl(mut self, ttl: Duration) -> Self {
+        self.session_ttl = ttl;
+        self
+    }
+
+    /// Convenience for `KvBackend::new(self, config)`.
+    pub fn into_backend(self, config: TokenBucketConfig) -> ConsulBackend {
+        KvBackend::new(self, config)
+    }
+
+    fn bucket_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .http
+            .request(method, format!("{}{}", self.address, path));
+        match &self.token {
+            Some(token) => request.header("X-Consul-Token", token),
+            None => request,
+        }
+    }
+
+    async fn read(&self, key: &str) -> Result<Option<KvPair>, RateLimitError> {
+        let response = self
+            .request(
+                reqwest::Method::GET,
+                &format!("/v1/kv/{}", self.bucket_key(key)),
+            )
+            .send()
+            .await
+            .map_err(|e| consul_error("Consul get error", e))?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let pairs: Vec<KvPair> = response
+            .error_for_status()
+            .map_err(|e| consul_error("Consul get error", e))?
+            .json()
+            .await
+            .map_err(|e| consul_error("Consul get error", e))?;
+        Ok(pairs.into_iter().next())
+    }
+
+    async fn session_id(&self) -> Result<String, RateLimitError> {
+        if let Some(session) = self.session.lock().as_ref() {
+            if session.created_at.elapsed() < SESSION_ROTATION {
+                return Ok(session.id.clone());
+            }
+        }
+
+        let created: SessionCreated = self
+            .request(reqwest::Method::PUT, "/v1/session/create")
+            .json(&json!({
+                "Name": "guardian-buckets",
+                "TTL": format!("{}s", self.session_ttl.as_secs()),
+                "Behavior": "delete",
+                "LockDelay": "0s",
+            }))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| consul_error("Consul session error", e))?
+            .json()
+            .await
+            .map_err(|e| consul_error("Consul session error", e))?;
+
+        *self.session.lock() = Some(CurrentSession {
+            id: created.id.clone(),
+            created_at: Instant::now(),
+        });
+        Ok(created.id)
+    }
+
+    /// Run a transaction. `Ok(None)` on success, `Ok(Some(op))` with the
+    /// index of the first failing operation when Consul rolled it back.
+    async fn txn(&self, ops: Vec<serde_json::Value>) -> Result<Option<usize>, RateLimitError> {
+        let response = self
+            .request(reqwest::Method::PUT, "/v1/txn")
+            .json(&ops)
+            .send()
+            .await
+            .map_err(|e| consul_error("Consul txn error", e))?;
+
+        if response.status() == reqwest::StatusCode::CONFLICT {
+            let errors: TxnErrors = response
+                .json()
+                .await
+                .map_err(|e| consul_error("Consul txn error", e))?;
+            return Ok(Some(errors.errors.first().map_or(0, |e| e.op_index)));
+        }
+        response
+            .error_for_status()
+            .map_err(|e| consul_error("Consul txn error", e))?;
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl KvStore for ConsulStore {
+    async fn get(&self, key: &str) -> Result<Option<KvEntry>, RateLimitError> {
+        let Some(pair) = self.read(key).await? else {
+            return Ok(None);
+        };
+        let value = match pair.value {
+            Some(encoded) => STANDARD.decode(encoded).map_err(|_| {
+                RateLimitError::StorageError("Consul value is not valid base64".to_string())
+            })?,
+            None => Vec::new(),
+        };
+        Ok(Some(KvEntry {
+            value,
+            version: pair.modify_index,
+        }))
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<u64>,
+        value: Vec<u8>,
+    ) -> Result<bool, RateLimitError> {
+        let bucket_key = self.bucket_key(key);
+        let value = STANDARD.encode(value);
+        let session = self.session_id().await?;
+        // Index 0 makes the CAS succeed only if the key doesn't exist yet.
+        let cas = json!({ "KV": {
+            "Verb": "cas", "Key": bucket_key, "Value": value, "Index": expected.unwrap_or(0),
+        }});
+        let lock = json!({ "KV": {
+            "Verb": "lock", "Key": bucket_key, "Value": value, "Session": session,
+        }});
+
+        match self.txn(vec![cas.clone(), lock.clone()]).await? {
+            None => return Ok(true),
+            Some(0) => return Ok(false),
+            Some(_) => {}
+        }
+
+        // The lock failed: the key is still held by a session from before the
+        // last rotation. Hand it over to the current one in the same CAS.
+        let Some(holder) = self.read(key).await?.and_then(|pair| pair.session) else {
+            return Ok(false);
+        };
+        let unlock = json!({ "KV": {
+            "Verb": "unlock", "Key": bucket_key, "Value": value, "Session": holder,
+        }});
+        Ok(self.txn(vec![cas, unlock, lock]).await?.is_none())
+    }
+
+    async fn expire(&self, _key: &str, _ttl: Duration) -> Result<(), RateLimitError> {
+        Ok(())
+    }
+}
+
+fn consul_error(context: &str, e: reqwest::Error) -> RateLimitError {
+    RateLimitError::StorageError(format!("{}: {}", context, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_core::StorageBackend;
+
+    #[test]
+    fn test_parses_kv_pair() {
+        let body = r#"[{"LockIndex":1,"Key":"guardian/buckets/u","Flags":0,
+            "Value":"NjoxNzAw","Session":"abc","CreateIndex":10,"ModifyIndex":12}]"#;
+        let pairs: Vec<KvPair> = serde_json::from_str(body).unwrap();
+        assert_eq!(pairs[0].modify_index, 12);
+        assert_eq!(pairs[0].session.as_deref(), Some("abc"));
+        assert_eq!(
+            STANDARD.decode(pairs[0].value.as_ref().unwrap()).unwrap(),
+            b"6:1700"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires Consul agent
+    async fn test_consul_backend() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        };
+        let backend = ConsulStore::new("http://127.0.0.1:8500").into_backend(config);
+        backend.reset("test_user").await.unwrap();
+
+        assert!(backend.take_token("test_user", 6).await.unwrap());
+        assert!(!backend.take_token("test_user", 6).await.unwrap());
+        assert_eq!(backend.get_usage("test_user").await.unwrap(), 6);
+
+        backend.reset("test_user").await.unwrap();
+    }
+}
@@ -2,12 +2,20 @@
 // File: guardian-core/src/lib.rs
 
 use async_trait::async_trait;
+use futures_core::Stream;
 use parking_lot::RwLock;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
 use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::broadcast;
+use tracing::Instrument;
 
 // ============================================================================
 // ERROR TYPES
@@ -21,6 +29,10 @@ pub enum RateLimitError {
     StorageError(String),
     #[error("Invalid configuration: {0}")]
     ConfigError(String),
+    /// The backend can't do what was asked at all, however often it's
+    /// retried.
+    #[error("Not supported by this backend: {0}")]
+    Unsupported(String),
 }
 
 // ============================================================================
@@ -44,11 +56,29 @@ impl Default for TokenBucketConfig {
     }
 }
 
+/// Where buckets read the time. Everything runs on [`SystemClock`] unless
+/// given another; tests pass one they can move forward by hand (see the
+/// `guardian-test` crate's `MockClock`) to refill buckets without sleeping.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
 pub struct TokenBucket {
     tokens: AtomicU64,
     capacity: u64,
     refill_rate: u64,
     last_refill: RwLock<SystemTime>,
+    clock: Arc<dyn Clock>,
 }
 
 impl TokenBucket {
@@ -58,18 +88,33 @@ impl TokenBucket {
             capacity: config.capacity,
             refill_rate: config.refill_rate,
             last_refill: RwLock::new(SystemTime::now()),
+            clock: Arc::new(SystemClock),
         }
     }
 
+    /// Refill by `clock` rather than the wall clock. The bucket starts full
+    /// at the clock's present.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        *self.last_refill.get_mut() = clock.now();
+        self.clock = clock;
+        self
+    }
+
     pub fn try_consume(&self, cost: u64) -> Result<(), RateLimitError> {
+        self.consume(cost)
+            .map(|_| ())
+            .map_err(|_| RateLimitError::LimitExceeded("Insufficient tokens".to_string()))
+    }
+
+    /// Take `cost` tokens, returning what's left on success and what was
+    /// available on failure.
+    fn consume(&self, cost: u64) -> Result<u64, u64> {
         self.refill();
 
         let mut current = self.tokens.load(Ordering::Acquire);
         loop {
             if current < cost {
-                return Err(RateLimitError::LimitExceeded(
-                    "Insufficient tokens".to_string(),
-                ));
+                return Err(current);
             }
 
             match self.tokens.compare_exchange_weak(
@@ -78,14 +123,14 @@ impl TokenBucket {
                 Ordering::Release,
                 Ordering::Acquire,
             ) {
-                Ok(_) => return Ok(()),
+                Ok(_) => return Ok(current - cost),
                 Err(actual) => current = actual,
             }
         }
     }
 
     fn refill(&self) {
-        let now = SystemTime::now();
+        let now = self.clock.now();
         let mut last = self.last_refill.write();
 
         if let Ok(elapsed) = now.duration_since(*last) {
@@ -105,279 +150,6822 @@ impl TokenBucket {
         self.refill();
         self.tokens.load(Ordering::Acquire)
     }
-}
 
-// ============================================================================
-// STORAGE BACKEND ABSTRACTION
-// ============================================================================
+    /// Put `tokens` back, up to capacity.
+    pub fn refund(&self, tokens: u64) {
+        self.refill();
+        let _ = self
+            .tokens
+            .fetch_update(Ordering::Release, Ordering::Acquire, |current| {
+                Some(current.saturating_add(tokens).min(self.capacity))
+            });
+    }
 
-#[async_trait]
-pub trait StorageBackend: Send + Sync {
-    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError>;
-    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError>;
-    async fn reset(&self, key: &str) -> Result<(), RateLimitError>;
+    /// When tokens were last credited.
+    pub fn last_refill(&self) -> SystemTime {
+        *self.last_refill.read()
+    }
 }
 
 // ============================================================================
-// IN-MEMORY BACKEND (High Performance)
+// PLAIN BUCKET STATE (for backends that persist buckets externally)
 // ============================================================================
 
-pub struct MemoryBackend {
-    buckets: Arc<RwLock<HashMap<String, Arc<TokenBucket>>>>,
-    config: TokenBucketConfig,
+/// Token bucket state as plain data, for backends that store buckets in an
+/// external system and do their own read-modify-write (CAS, transactions).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BucketState {
+    pub tokens: u64,
+    /// Unix time in milliseconds up to which refill has been credited.
+    pub last_refill_ms: u64,
 }
 
-impl MemoryBackend {
-    pub fn new(config: TokenBucketConfig) -> Self {
+impl BucketState {
+    /// A full bucket, as seen for keys that have never been used.
+    pub fn full(config: &TokenBucketConfig, now_ms: u64) -> Self {
         Self {
-            buckets: Arc::new(RwLock::new(HashMap::new())),
-            config,
+            tokens: config.capacity,
+            last_refill_ms: now_ms,
         }
     }
 
-    fn get_or_create_bucket(&self, key: &str) -> Arc<TokenBucket> {
-        let buckets = self.buckets.read();
-        if let Some(bucket) = buckets.get(key) {
-            return Arc::clone(bucket);
+    /// Credit tokens accrued since `last_refill_ms`. Only whole tokens are
+    /// credited; the fractional remainder keeps accruing.
+    pub fn refill(&mut self, config: &TokenBucketConfig, now_ms: u64) {
+        let elapsed_ms = now_ms.saturating_sub(self.last_refill_ms);
+        let tokens_to_add = elapsed_ms.saturating_mul(config.refill_rate) / 1000;
+        if tokens_to_add == 0 {
+            return;
         }
-        drop(buckets);
 
-        let mut buckets = self.buckets.write();
-        buckets
-            .entry(key.to_string())
-            .or_insert_with(|| Arc::new(TokenBucket::new(self.config.clone())))
-            .clone()
+        let refilled = self.tokens.saturating_add(tokens_to_add);
+        if refilled >= config.capacity {
+            self.tokens = config.capacity;
+            self.last_refill_ms = now_ms;
+        } else {
+            self.tokens = refilled;
+            self.last_refill_ms += tokens_to_add * 1000 / config.refill_rate;
+        }
+    }
+
+    /// Refill, then put `tokens` back, up to capacity.
+    pub fn refund(&mut self, tokens: u64, config: &TokenBucketConfig, now_ms: u64) {
+        self.refill(config, now_ms);
+        self.tokens = self.tokens.saturating_add(tokens).min(config.capacity);
+    }
+
+    /// Refill, then consume `cost` tokens if available.
+    pub fn try_consume(&mut self, cost: u64, config: &TokenBucketConfig, now_ms: u64) -> bool {
+        self.refill(config, now_ms);
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            true
+        } else {
+            false
+        }
     }
 }
 
+// ============================================================================
+// STORAGE BACKEND ABSTRACTION
+// ============================================================================
+
 #[async_trait]
-impl StorageBackend for MemoryBackend {
-    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
-        let bucket = self.get_or_create_bucket(key);
-        match bucket.try_consume(cost) {
-            Ok(_) => Ok(true),
-            Err(RateLimitError::LimitExceeded(_)) => Ok(false),
-            Err(e) => Err(e),
-        }
+pub trait StorageBackend: Send + Sync {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError>;
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError>;
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError>;
+
+    /// `take_token`, plus the bucket as the decision left it when the
+    /// backend can report that in the same step.
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        Ok(TokenDecision {
+            allowed: self.take_token(key, cost).await?,
+            bucket: None,
+        })
     }
 
-    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
-        let bucket = self.get_or_create_bucket(key);
-        Ok(self.config.capacity - bucket.available_tokens())
+    /// Take each `(key, cost)` of `entries` in one step, or none of them:
+    /// no other call sees some taken and the rest not. Each decision says
+    /// whether that key's bucket had its cost (all of them, for a key
+    /// listed twice) and carries the bucket as the call left it; tokens
+    /// were taken only if every decision is allowed. Backends that can't
+    /// do this atomically fail with `RateLimitError::Unsupported` rather
+    /// than take keys one at a time.
+    async fn take_all(
+        &self,
+        _entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        Err(take_all_unsupported())
     }
 
-    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
-        let mut buckets = self.buckets.write();
-        buckets.remove(key);
+    /// Give back `tokens` that an allowed request took from `key` but
+    /// didn't use, up to the bucket's capacity.
+    async fn refund(&self, _key: &str, _tokens: u64) -> Result<(), RateLimitError> {
+        Err(RateLimitError::StorageError(
+            "this backend does not support refunds".to_string(),
+        ))
+    }
+
+    /// Debit `tokens` from `key` as lease `id`, for its holder to spend
+    /// locally until it lapses after `ttl`. `None` when the bucket can't
+    /// spare them all. The caller picks `id`, unique among the key's
+    /// leases.
+    async fn acquire_lease(
+        &self,
+        _key: &str,
+        _id: u64,
+        _tokens: u64,
+        _ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        Err(leases_unsupported())
+    }
+
+    /// Extend lease `id` on `key` to `ttl` from now. `None` once it has
+    /// lapsed or the key was reset; its tokens are then gone.
+    async fn renew_lease(
+        &self,
+        _key: &str,
+        _id: u64,
+        _ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        Err(leases_unsupported())
+    }
+
+    /// End lease `id` on `key`, crediting `unused` of its tokens back to
+    /// the bucket, up to what the lease holds. Returns the tokens credited,
+    /// which are none for a lease that has lapsed.
+    async fn return_lease(
+        &self,
+        _key: &str,
+        _id: u64,
+        _unused: u64,
+    ) -> Result<u64, RateLimitError> {
+        Err(leases_unsupported())
+    }
+
+    /// The leases held on `key`, reclaiming lapsed ones on the way: their
+    /// unspent tokens count as spent.
+    async fn leases(&self, _key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        Err(leases_unsupported())
+    }
+
+    /// Record `demand` as `node`'s recent traffic on `key`, kept for `ttl`,
+    /// and return the demand every node has recorded for `key` and not let
+    /// lapse, this one's included. Lets nodes split a limit between them
+    /// (see `SlicedBackend`) through the store they share.
+    async fn exchange_demand(
+        &self,
+        _key: &str,
+        _node: &str,
+        _demand: u64,
+        _ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        Err(RateLimitError::StorageError(
+            "this backend does not share demand between nodes".to_string(),
+        ))
+    }
+
+    /// Take the lock `name` for `holder` until `ttl` from now, or extend it
+    /// if `holder` has it already. `false` while another holder has it.
+    /// Lets the nodes sharing a store elect one of them to run cluster-wide
+    /// jobs.
+    async fn hold_lock(
+        &self,
+        _name: &str,
+        _holder: &str,
+        _ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        Err(locks_unsupported())
+    }
+
+    /// Give up the lock `name`, if `holder` has it.
+    async fn release_lock(&self, _name: &str, _holder: &str) -> Result<(), RateLimitError> {
+        Err(locks_unsupported())
+    }
+
+    /// The bucket for `key` without consuming from it, or `None` when the
+    /// backend can't tell.
+    async fn inspect(&self, _key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        Ok(None)
+    }
+
+    /// Cheap liveness probe of the underlying store (e.g. a PING). Purely
+    /// in-process backends are always healthy.
+    async fn health_check(&self) -> Result<(), RateLimitError> {
         Ok(())
     }
-}
 
-// ============================================================================
-// BATCHING LAYER (Reduces distributed backend calls)
-// ============================================================================
+    /// Write out anything buffered locally. Called once while the process
+    /// shuts down, after in-flight requests have drained.
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        Ok(())
+    }
 
-pub struct BatchingBackend<B: StorageBackend> {
-    backend: Arc<B>,
-    local_cache: Arc<RwLock<HashMap<String, LocalBatch>>>,
-    batch_size: u64,
+    /// Prepare local state for `key` ahead of traffic (e.g. lease tokens
+    /// from the shared store) so its first checks are answered locally.
+    /// Backends that keep no local state have nothing to do.
+    async fn warm_up(&self, _key: &str) -> Result<(), RateLimitError> {
+        Ok(())
+    }
+
+    /// Up to `limit` keys with stored bucket state that match `pattern`
+    /// (`*` wildcards), in key order and strictly after `after`. Pass the
+    /// last key of one page as `after` to fetch the next.
+    async fn list_keys(
+        &self,
+        _pattern: &str,
+        _after: Option<&str>,
+        _limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        Err(RateLimitError::StorageError(
+            "this backend does not support listing keys".to_string(),
+        ))
+    }
+
+    /// The `n` keys with the most recent activity by `ranking`. Only
+    /// backends that track statistics (see `StatsBackend`) support this.
+    async fn top_keys(
+        &self,
+        _n: usize,
+        _ranking: KeyRanking,
+    ) -> Result<Vec<(String, KeyStats)>, RateLimitError> {
+        Err(RateLimitError::StorageError(
+            "this backend does not track key statistics".to_string(),
+        ))
+    }
 }
 
-struct LocalBatch {
-    available: AtomicU64,
-    reserved_until: RwLock<SystemTime>,
+/// A bucket's limits and the tokens left in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BucketSnapshot {
+    pub capacity: u64,
+    pub remaining: u64,
+    /// Tokens added back per second.
+    pub refill_rate: u64,
+    /// When tokens were last credited to the bucket, if the backend stores
+    /// it and the bucket exists.
+    pub last_refill: Option<SystemTime>,
 }
 
-impl<B: StorageBackend> BatchingBackend<B> {
-    pub fn new(backend: B, batch_size: u64) -> Self {
+impl BucketSnapshot {
+    pub fn new(config: &TokenBucketConfig, remaining: u64) -> Self {
         Self {
-            backend: Arc::new(backend),
-            local_cache: Arc::new(RwLock::new(HashMap::new())),
-            batch_size,
+            capacity: config.capacity,
+            remaining,
+            refill_rate: config.refill_rate,
+            last_refill: None,
         }
     }
 
-    async fn reserve_batch(&self, key: &str) -> Result<(), RateLimitError> {
-        // Try to reserve batch_size tokens from backend
-        for _ in 0..self.batch_size {
-            if !self.backend.take_token(key, 1).await? {
-                return Err(RateLimitError::LimitExceeded(key.to_string()));
-            }
-        }
-        Ok(())
+    pub fn with_last_refill(mut self, last_refill: SystemTime) -> Self {
+        self.last_refill = Some(last_refill);
+        self
     }
 }
 
-#[async_trait]
-impl<B: StorageBackend> StorageBackend for BatchingBackend<B> {
-    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
-        // Try local cache first (drop lock before await)
-        {
-            let cache = self.local_cache.read();
-            if let Some(batch) = cache.get(key) {
-                let current = batch.available.load(Ordering::Acquire);
-                if current >= cost {
-                    match batch.available.compare_exchange(
-                        current,
-                        current - cost,
-                        Ordering::Release,
-                        Ordering::Acquire,
-                    ) {
-                        Ok(_) => return Ok(true),
-                        Err(_) => {} // Retry with backend
-                    }
-                }
-            }
-        } // Lock dropped here
+/// Outcome of `StorageBackend::take_token_detailed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenDecision {
+    pub allowed: bool,
+    pub bucket: Option<BucketSnapshot>,
+}
 
-        // Need to reserve a new batch
-        self.reserve_batch(key).await?;
+/// Tokens debited from a bucket for one holder to spend locally, as granted
+/// by `StorageBackend::acquire_lease`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    pub id: u64,
+    pub tokens: u64,
+    /// When the store reclaims it unless renewed.
+    pub expires_at: SystemTime,
+}
 
-        // Reacquire lock after await
-        let mut cache = self.local_cache.write();
-        let batch = cache.entry(key.to_string()).or_insert_with(|| LocalBatch {
-            available: AtomicU64::new(self.batch_size),
-            reserved_until: RwLock::new(SystemTime::now() + Duration::from_secs(60)),
-        });
+fn leases_unsupported() -> RateLimitError {
+    RateLimitError::StorageError("this backend does not support leases".to_string())
+}
 
-        let current = batch.available.load(Ordering::Acquire);
-        if current >= cost {
-            batch.available.store(current - cost, Ordering::Release);
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+fn locks_unsupported() -> RateLimitError {
+    RateLimitError::StorageError("this backend does not support locks".to_string())
+}
+
+fn take_all_unsupported() -> RateLimitError {
+    RateLimitError::Unsupported("this backend can't take from several keys at once".to_string())
+}
+
+/// Recent activity for one key, as reported by `StorageBackend::top_keys`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyStats {
+    pub allowed: u64,
+    pub denied: u64,
+    /// Tokens spent by allowed requests.
+    pub consumed: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyRanking {
+    /// Most tokens consumed.
+    Usage,
+    /// Most denied requests.
+    Denials,
+}
+
+// Shared and boxed backends, e.g. a `RouterBackend` kept by the service for
+// runtime changes while a `RateLimiter` uses it, or backends chosen from
+// configuration at startup.
+
+#[async_trait]
+impl<T: StorageBackend + ?Sized> StorageBackend for Arc<T> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        (**self).take_token(key, cost).await
     }
 
     async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
-        self.backend.get_usage(key).await
+        (**self).get_usage(key).await
     }
 
     async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
-        {
-            let mut cache = self.local_cache.write();
-            cache.remove(key);
-        } // Lock dropped here before await
-        self.backend.reset(key).await
+        (**self).reset(key).await
     }
-}
-
-// ============================================================================
-// RATE LIMITER FACADE
-// ============================================================================
 
-pub struct RateLimiter<B: StorageBackend> {
-    backend: Arc<B>,
-    fail_open: bool,
-}
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        (**self).refund(key, tokens).await
+    }
 
-impl<B: StorageBackend> RateLimiter<B> {
-    pub fn new(backend: B, fail_open: bool) -> Self {
-        Self {
-            backend: Arc::new(backend),
-            fail_open,
-        }
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        (**self).acquire_lease(key, id, tokens, ttl).await
     }
 
-    pub async fn check_limit(
+    async fn renew_lease(
         &self,
-        client_id: &str,
-        cost: u64,
-    ) -> Result<LimitResult, RateLimitError> {
-        match self.backend.take_token(client_id, cost).await {
-            Ok(true) => Ok(LimitResult::Allowed),
-            Ok(false) => Ok(LimitResult::Denied {
-                retry_after: Duration::from_secs(1),
-            }),
-            Err(e) => {
-                if self.fail_open {
-                    eprintln!("Rate limiter error (failing open): {}", e);
-                    Ok(LimitResult::Allowed)
-                } else {
-                    Err(e)
-                }
-            }
-        }
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        (**self).renew_lease(key, id, ttl).await
     }
 
-    pub async fn get_usage(&self, client_id: &str) -> Result<u64, RateLimitError> {
-        self.backend.get_usage(client_id).await
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        (**self).return_lease(key, id, unused).await
     }
-}
 
-#[derive(Debug, PartialEq)]
-pub enum LimitResult {
-    Allowed,
-    Denied { retry_after: Duration },
-}
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        (**self).leases(key).await
+    }
 
-// ============================================================================
-// TESTS
-// ============================================================================
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        (**self).exchange_demand(key, node, demand, ttl).await
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tokio::time::sleep;
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        (**self).hold_lock(name, holder, ttl).await
+    }
 
-    #[test]
-    fn test_token_bucket_basic() {
-        let config = TokenBucketConfig {
-            capacity: 10,
-            refill_rate: 5,
-            refill_interval: Duration::from_secs(1),
-        };
-        let bucket = TokenBucket::new(config);
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        (**self).release_lock(name, holder).await
+    }
 
-        assert!(bucket.try_consume(5).is_ok());
-        assert!(bucket.try_consume(5).is_ok());
-        assert!(bucket.try_consume(1).is_err());
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        (**self).take_token_detailed(key, cost).await
     }
 
-    #[tokio::test]
-    async fn test_memory_backend() {
-        let config = TokenBucketConfig::default();
-        let backend = MemoryBackend::new(config);
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        (**self).take_all(entries).await
+    }
 
-        assert!(backend.take_token("user1", 10).await.unwrap());
-        assert!(backend.take_token("user1", 50).await.unwrap());
-        assert!(!backend.take_token("user1", 50).await.unwrap());
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        (**self).inspect(key).await
     }
 
-    #[tokio::test]
-    async fn test_rate_limiter_fail_open() {
-        let config = TokenBucketConfig {
-            capacity: 5,
-            refill_rate: 1,
-            refill_interval: Duration::from_secs(1),
-        };
-        let backend = MemoryBackend::new(config);
-        let limiter = RateLimiter::new(backend, true);
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        (**self).health_check().await
+    }
 
-        for _ in 0..5 {
-            assert_eq!(
-                limiter.check_limit("user1", 1).await.unwrap(),
-                LimitResult::Allowed
-            );
-        }
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        (**self).flush().await
+    }
 
-        let result = limiter.check_limit("user1", 1).await.unwrap();
-        assert!(matches!(result, LimitResult::Denied { .. }));
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        (**self).warm_up(key).await
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        (**self).list_keys(pattern, after, limit).await
+    }
+
+    async fn top_keys(
+        &self,
+        n: usize,
+        ranking: KeyRanking,
+    ) -> Result<Vec<(String, KeyStats)>, RateLimitError> {
+        (**self).top_keys(n, ranking).await
+    }
+}
+
+#[async_trait]
+impl<T: StorageBackend + ?Sized> StorageBackend for Box<T> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        (**self).take_token(key, cost).await
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        (**self).get_usage(key).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        (**self).reset(key).await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        (**self).refund(key, tokens).await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        (**self).acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        (**self).renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        (**self).return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        (**self).leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        (**self).exchange_demand(key, node, demand, ttl).await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        (**self).hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        (**self).release_lock(name, holder).await
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        (**self).take_token_detailed(key, cost).await
+    }
+
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        (**self).take_all(entries).await
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        (**self).inspect(key).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        (**self).health_check().await
+    }
+
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        (**self).flush().await
+    }
+
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        (**self).warm_up(key).await
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        (**self).list_keys(pattern, after, limit).await
+    }
+
+    async fn top_keys(
+        &self,
+        n: usize,
+        ranking: KeyRanking,
+    ) -> Result<Vec<(String, KeyStats)>, RateLimitError> {
+        (**self).top_keys(n, ranking).await
+    }
+}
+
+// ============================================================================
+// IN-MEMORY BACKEND (High Performance)
+// ============================================================================
+
+pub struct MemoryBackend {
+    buckets: Arc<RwLock<HashMap<String, Arc<TokenBucket>>>>,
+    config: TokenBucketConfig,
+    /// Live leases by key, then by id.
+    leases: parking_lot::Mutex<HashMap<String, HashMap<u64, Lease>>>,
+    /// Recorded demand by key.
+    demands: parking_lot::Mutex<HashMap<String, NodeDemands>>,
+    /// Each lock's holder and when it lapses.
+    locks: parking_lot::Mutex<HashMap<String, (String, Instant)>>,
+    /// Held shared while a check takes from one bucket and exclusively by
+    /// `take_all`, so no check sees part of its entries taken.
+    gate: RwLock<()>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Each node's demand on one key, with when it lapses.
+type NodeDemands = HashMap<String, (u64, Instant)>;
+
+impl MemoryBackend {
+    pub fn new(config: TokenBucketConfig) -> Self {
+        Self {
+            buckets: Arc::new(RwLock::new(HashMap::new())),
+            config,
+            leases: parking_lot::Mutex::default(),
+            demands: parking_lot::Mutex::default(),
+            locks: parking_lot::Mutex::default(),
+            gate: RwLock::new(()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Refill buckets and lapse leases by `clock` rather than the wall
+    /// clock. Demand and locks still lapse in real time.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn get_or_create_bucket(&self, key: &str) -> Arc<TokenBucket> {
+        let buckets = self.buckets.read();
+        if let Some(bucket) = buckets.get(key) {
+            return Arc::clone(bucket);
+        }
+        drop(buckets);
+
+        let mut buckets = self.buckets.write();
+        buckets
+            .entry(key.to_string())
+            .or_insert_with(|| {
+                let bucket = TokenBucket::new(self.config.clone());
+                Arc::new(bucket.with_clock(Arc::clone(&self.clock)))
+            })
+            .clone()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        let _shared = self.gate.read();
+        let bucket = self.get_or_create_bucket(key);
+        match bucket.try_consume(cost) {
+            Ok(_) => Ok(true),
+            Err(RateLimitError::LimitExceeded(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let bucket = self.get_or_create_bucket(key);
+        Ok(self.config.capacity - bucket.available_tokens())
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let _shared = self.gate.read();
+        let (allowed, remaining) = match self.get_or_create_bucket(key).consume(cost) {
+            Ok(remaining) => (true, remaining),
+            Err(available) => (false, available),
+        };
+        Ok(TokenDecision {
+            allowed,
+            bucket: Some(BucketSnapshot::new(&self.config, remaining)),
+        })
+    }
+
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        let _exclusive = self.gate.write();
+        let mut asked: HashMap<&str, u64> = HashMap::new();
+        for &(key, cost) in entries {
+            *asked.entry(key).or_default() += cost;
+        }
+        let buckets: HashMap<&str, Arc<TokenBucket>> = asked
+            .keys()
+            .map(|&key| (key, self.get_or_create_bucket(key)))
+            .collect();
+        let short: Vec<&str> = asked
+            .iter()
+            .filter(|&(key, cost)| buckets[key].available_tokens() < *cost)
+            .map(|(&key, _)| key)
+            .collect();
+        if short.is_empty() {
+            for (key, cost) in &asked {
+                // Nothing else takes while the gate is held, so this can't
+                // come up short.
+                let _ = buckets[key].consume(*cost);
+            }
+        }
+        Ok(entries
+            .iter()
+            .map(|(key, _)| TokenDecision {
+                allowed: !short.contains(key),
+                bucket: Some(BucketSnapshot::new(
+                    &self.config,
+                    buckets[key].available_tokens(),
+                )),
+            })
+            .collect())
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        let bucket = self.get_or_create_bucket(key);
+        let remaining = bucket.available_tokens();
+        Ok(Some(
+            BucketSnapshot::new(&self.config, remaining).with_last_refill(bucket.last_refill()),
+        ))
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let mut buckets = self.buckets.write();
+        buckets.remove(key);
+        self.leases.lock().remove(key);
+        self.demands.lock().remove(key);
+        Ok(())
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.get_or_create_bucket(key).refund(tokens);
+        Ok(())
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        let _shared = self.gate.read();
+        let mut leases = self.leases.lock();
+        let now = self.clock.now();
+        let held = leases.entry(key.to_string()).or_default();
+        held.retain(|_, lease| lease.expires_at > now);
+        if self.get_or_create_bucket(key).consume(tokens).is_err() {
+            return Ok(None);
+        }
+        let lease = Lease {
+            id,
+            tokens,
+            expires_at: now + ttl,
+        };
+        held.insert(id, lease);
+        Ok(Some(lease))
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        let mut leases = self.leases.lock();
+        let now = self.clock.now();
+        let Some(held) = leases.get_mut(key) else {
+            return Ok(None);
+        };
+        match held.get_mut(&id) {
+            Some(lease) if lease.expires_at > now => {
+                lease.expires_at = now + ttl;
+                Ok(Some(*lease))
+            }
+            Some(_) => {
+                held.remove(&id);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        let lease = self
+            .leases
+            .lock()
+            .get_mut(key)
+            .and_then(|held| held.remove(&id));
+        match lease {
+            Some(lease) if lease.expires_at > self.clock.now() => {
+                let credit = unused.min(lease.tokens);
+                self.get_or_create_bucket(key).refund(credit);
+                Ok(credit)
+            }
+            _ => Ok(0),
+        }
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        let mut leases = self.leases.lock();
+        let now = self.clock.now();
+        let Some(held) = leases.get_mut(key) else {
+            return Ok(Vec::new());
+        };
+        held.retain(|_, lease| lease.expires_at > now);
+        let mut live: Vec<Lease> = held.values().copied().collect();
+        if live.is_empty() {
+            leases.remove(key);
+        }
+        live.sort_by_key(|lease| lease.id);
+        Ok(live)
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        let mut demands = self.demands.lock();
+        let now = Instant::now();
+        let recorded = demands.entry(key.to_string()).or_default();
+        recorded.insert(node.to_string(), (demand, now + ttl));
+        recorded.retain(|_, (_, lapses)| *lapses > now);
+        let mut live: Vec<(String, u64)> = recorded
+            .iter()
+            .map(|(node, (demand, _))| (node.clone(), *demand))
+            .collect();
+        live.sort();
+        Ok(live)
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        let mut locks = self.locks.lock();
+        let now = Instant::now();
+        match locks.get(name) {
+            Some((other, lapses)) if other != holder && *lapses > now => Ok(false),
+            _ => {
+                locks.insert(name.to_string(), (holder.to_string(), now + ttl));
+                Ok(true)
+            }
+        }
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        let mut locks = self.locks.lock();
+        if locks.get(name).is_some_and(|(other, _)| other == holder) {
+            locks.remove(name);
+        }
+        Ok(())
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        let buckets = self.buckets.read();
+        Ok(page_of_keys(
+            buckets.keys().map(String::as_str),
+            pattern,
+            after,
+            limit,
+        ))
+    }
+}
+
+/// Select one `list_keys` page from an unordered set of keys.
+pub fn page_of_keys<'a>(
+    keys: impl IntoIterator<Item = &'a str>,
+    pattern: &str,
+    after: Option<&str>,
+    limit: usize,
+) -> Vec<String> {
+    let mut page: Vec<&str> = keys
+        .into_iter()
+        .filter(|key| after.is_none_or(|after| *key > after) && glob_match(pattern, key))
+        .collect();
+    page.sort_unstable();
+    page.dedup();
+    page.truncate(limit);
+    page.into_iter().map(str::to_string).collect()
+}
+
+// ============================================================================
+// BATCHING LAYER (Reduces distributed backend calls)
+// ============================================================================
+
+/// Counters shared by local caching layers, for reporting how many checks
+/// were answered without going to the backend behind them.
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CacheStats {
+    /// `take_token` calls answered locally.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// `take_token` calls that went to the backend.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Count a `take_token` call by whether it was answered locally.
+    pub fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub struct BatchingBackend<B: StorageBackend> {
+    backend: Arc<B>,
+    local_cache: Arc<RwLock<HashMap<String, LocalBatch>>>,
+    batch_size: u64,
+    stats: Arc<CacheStats>,
+}
+
+struct LocalBatch {
+    available: AtomicU64,
+    /// The shared bucket as the last reservation left it.
+    bucket: Option<BucketSnapshot>,
+}
+
+/// `bucket` with the `local` tokens this node holds counted as remaining,
+/// since they are still there for its checks to spend.
+fn with_local(bucket: Option<BucketSnapshot>, local: u64) -> Option<BucketSnapshot> {
+    bucket.map(|bucket| BucketSnapshot {
+        remaining: bucket.remaining.saturating_add(local).min(bucket.capacity),
+        ..bucket
+    })
+}
+
+impl<B: StorageBackend> BatchingBackend<B> {
+    pub fn new(backend: B, batch_size: u64) -> Self {
+        Self {
+            backend: Arc::new(backend),
+            local_cache: Arc::new(RwLock::new(HashMap::new())),
+            batch_size,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Record into `stats`, e.g. to total several backends' hits.
+    pub fn with_stats(mut self, stats: Arc<CacheStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Checks answered from a local batch, and checks that reserved one.
+    pub fn stats(&self) -> &Arc<CacheStats> {
+        &self.stats
+    }
+
+    /// Take `tokens` from the backend in one go, so a bucket that can't
+    /// spare them all keeps them rather than losing the first few. Adds
+    /// them to the key's batch, less `spent`, when they were taken.
+    async fn reserve_batch(
+        &self,
+        key: &str,
+        tokens: u64,
+        spent: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let decision = self.backend.take_token_detailed(key, tokens).await?;
+        let mut cache = self.local_cache.write();
+        if !decision.allowed {
+            let held = cache
+                .get(key)
+                .map_or(0, |batch| batch.available.load(Ordering::Acquire));
+            return Ok(TokenDecision {
+                allowed: false,
+                bucket: with_local(decision.bucket, held),
+            });
+        }
+        let batch = cache.entry(key.to_string()).or_insert_with(|| LocalBatch {
+            available: AtomicU64::new(0),
+            bucket: None,
+        });
+        if decision.bucket.is_some() {
+            batch.bucket = decision.bucket;
+        }
+        let left = tokens - spent;
+        let available = batch.available.fetch_add(left, Ordering::AcqRel) + left;
+        Ok(TokenDecision {
+            allowed: true,
+            bucket: with_local(batch.bucket, available),
+        })
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend> StorageBackend for BatchingBackend<B> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    /// The bucket is the shared one as this node's last reservation left
+    /// it, with the tokens still in the local batch counted as remaining.
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        // Try local cache first (drop lock before await)
+        {
+            let cache = self.local_cache.read();
+            if let Some(batch) = cache.get(key) {
+                let current = batch.available.load(Ordering::Acquire);
+                if current >= cost
+                    && batch
+                        .available
+                        .compare_exchange(
+                            current,
+                            current - cost,
+                            Ordering::Release,
+                            Ordering::Acquire,
+                        )
+                        .is_ok()
+                {
+                    self.stats.record(true);
+                    return Ok(TokenDecision {
+                        allowed: true,
+                        bucket: with_local(batch.bucket, current - cost),
+                    });
+                } // Otherwise retry with backend
+            }
+        } // Lock dropped here
+
+        // Need to reserve a new batch, spending `cost` of it. Other checks
+        // may have reserved too by the time it lands.
+        self.stats.record(false);
+        let reserved = self.batch_size.max(cost);
+        self.reserve_batch(key, reserved, cost).await
+    }
+
+    /// Goes straight to the shared buckets; tokens in this node's batches
+    /// stay there for its single checks.
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        self.backend.take_all(entries).await
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        self.backend.get_usage(key).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        {
+            let mut cache = self.local_cache.write();
+            cache.remove(key);
+        } // Lock dropped here before await
+        self.backend.reset(key).await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.backend.refund(key, tokens).await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backend.acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backend.renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.backend.return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.backend.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.backend.exchange_demand(key, node, demand, ttl).await
+    }
+
+    /// The shared bucket; tokens already reserved into local batches count
+    /// as spent.
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.backend.inspect(key).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.backend.health_check().await
+    }
+
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        self.backend.flush().await
+    }
+
+    /// Reserve a batch for `key` unless one is already held. A bucket too
+    /// low to spare a batch is left for the first check to settle.
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        if self.local_cache.read().contains_key(key) {
+            return Ok(());
+        }
+        self.reserve_batch(key, self.batch_size, 0).await.map(drop)
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.backend.hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.backend.release_lock(name, holder).await
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.backend.list_keys(pattern, after, limit).await
+    }
+}
+
+// ============================================================================
+// LEASING LAYER (token blocks the shared store records and reclaims)
+// ============================================================================
+
+#[derive(Debug, Clone)]
+pub struct LeaseConfig {
+    /// Tokens leased per round trip to the shared backend.
+    pub size: u64,
+    /// How long a lease lasts without being renewed. Leases in use are
+    /// renewed once past half of it.
+    pub ttl: Duration,
+}
+
+impl Default for LeaseConfig {
+    fn default() -> Self {
+        Self {
+            size: 10,
+            ttl: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Spends tokens locally from leases on a shared backend (typically
+/// `RedisBackend`), one lease per key at a time.
+///
+/// Unlike `BatchingBackend`, the store records each lease with its expiry:
+/// leftovers are returned to the bucket when a lease is replaced and on
+/// `flush`, and a holder that disappears forfeits its lease only until
+/// the store reclaims it. So at any moment a key's bucket is short of what
+/// it would be without leasing by at most `size` tokens per node, and
+/// `StorageBackend::leases` shows by how much.
+pub struct LeasingBackend<B: StorageBackend> {
+    backend: B,
+    config: LeaseConfig,
+    held: parking_lot::Mutex<HashMap<String, HeldLease>>,
+    ids: std::collections::hash_map::RandomState,
+    next_id: AtomicU64,
+    stats: Arc<CacheStats>,
+    clock: Arc<dyn Clock>,
+}
+
+struct HeldLease {
+    id: u64,
+    available: u64,
+    /// The shared bucket as it was just after the lease was taken.
+    bucket: Option<BucketSnapshot>,
+    acquired: SystemTime,
+    /// Stop spending then, a little before the store reclaims it, in case
+    /// the clocks differ.
+    expires: SystemTime,
+}
+
+impl<B: StorageBackend> LeasingBackend<B> {
+    pub fn new(backend: B, config: LeaseConfig) -> Self {
+        Self {
+            backend,
+            config,
+            held: parking_lot::Mutex::default(),
+            ids: std::collections::hash_map::RandomState::new(),
+            next_id: AtomicU64::new(0),
+            stats: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Record into `stats`, e.g. to total several backends' hits.
+    pub fn with_stats(mut self, stats: Arc<CacheStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Time held leases by `clock` rather than the wall clock; give it the
+    /// store's clock when that is not the wall clock either.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Checks answered from a lease, and checks that went to the backend.
+    pub fn stats(&self) -> &Arc<CacheStats> {
+        &self.stats
+    }
+
+    /// Random per process, so nodes' ids don't collide.
+    fn lease_id(&self) -> u64 {
+        use std::hash::BuildHasher;
+        self.ids
+            .hash_one(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Lease at least `cost` tokens for `key` and spend `cost` of them,
+    /// returning whatever is left of the lease it replaces.
+    ///
+    /// The shared bucket is read once per lease, so checks answered from
+    /// it can report how much is left without a round trip of their own.
+    async fn lease(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        let id = self.lease_id();
+        let size = self.config.size.max(cost);
+        let Some(lease) = self
+            .backend
+            .acquire_lease(key, id, size, self.config.ttl)
+            .await?
+        else {
+            return Ok(false);
+        };
+        let bucket = match self.backend.inspect(key).await {
+            Ok(bucket) => bucket,
+            Err(e) => {
+                tracing::debug!(error = %e, "leased bucket inspect failed");
+                None
+            }
+        };
+        let now = self.clock.now();
+        let held = HeldLease {
+            id,
+            available: lease.tokens - cost,
+            bucket,
+            acquired: now,
+            expires: now + self.config.ttl * 9 / 10,
+        };
+        let replaced = self.held.lock().insert(key.to_string(), held);
+        if let Some(replaced) = replaced {
+            self.give_back(key, replaced).await?;
+        }
+        Ok(true)
+    }
+
+    /// The shared bucket with what is left of `key`'s lease counted as
+    /// remaining.
+    fn held_bucket(&self, key: &str) -> Option<BucketSnapshot> {
+        let held = self.held.lock();
+        let lease = held.get(key)?;
+        with_local(lease.bucket, lease.available)
+    }
+
+    async fn give_back(&self, key: &str, lease: HeldLease) -> Result<(), RateLimitError> {
+        if self.clock.now() < lease.expires {
+            self.backend
+                .return_lease(key, lease.id, lease.available)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend> StorageBackend for LeasingBackend<B> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    /// Checks answered from a lease report the shared bucket as it was when
+    /// the lease was taken, with the lease's unspent tokens as remaining.
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let local = match self.held.lock().get_mut(key) {
+            Some(lease) if lease.available >= cost && self.clock.now() < lease.expires => {
+                lease.available -= cost;
+                let held_for = self.clock.now().duration_since(lease.acquired);
+                let half_life = held_for.unwrap_or_default() > self.config.ttl / 2;
+                let bucket = with_local(lease.bucket, lease.available);
+                Some((half_life.then_some(lease.id), bucket))
+            }
+            _ => None,
+        };
+        let Some((renew, bucket)) = local else {
+            self.stats.record(false);
+            // What's left of a lease too small for `cost` goes back first.
+            let spent = self.held.lock().remove(key);
+            if let Some(spent) = spent {
+                self.give_back(key, spent).await?;
+            }
+            if self.lease(key, cost).await? {
+                return Ok(TokenDecision {
+                    allowed: true,
+                    bucket: self.held_bucket(key),
+                });
+            }
+            // Too few tokens left for a lease; the last ones are spent
+            // straight from the bucket.
+            return self.backend.take_token_detailed(key, cost).await;
+        };
+        self.stats.record(true);
+        if let Some(id) = renew {
+            let renewed = self.backend.renew_lease(key, id, self.config.ttl).await?;
+            let mut held = self.held.lock();
+            if let Some(lease) = held.get_mut(key).filter(|lease| lease.id == id) {
+                match renewed {
+                    Some(_) => {
+                        let now = self.clock.now();
+                        lease.acquired = now;
+                        lease.expires = now + self.config.ttl * 9 / 10;
+                    }
+                    // Reclaimed, or the key was reset.
+                    None => {
+                        held.remove(key);
+                    }
+                }
+            }
+        }
+        Ok(TokenDecision {
+            allowed: true,
+            bucket,
+        })
+    }
+
+    /// Goes straight to the shared buckets; tokens in this node's leases
+    /// stay there for its single checks.
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        self.backend.take_all(entries).await
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        self.backend.get_usage(key).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.held.lock().remove(key);
+        self.backend.reset(key).await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.backend.refund(key, tokens).await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backend.acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backend.renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.backend.return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.backend.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.backend.exchange_demand(key, node, demand, ttl).await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.backend.hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.backend.release_lock(name, holder).await
+    }
+
+    /// The shared bucket; tokens held in leases count as spent.
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.backend.inspect(key).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.backend.health_check().await
+    }
+
+    /// Return every lease's leftovers, so tokens a stopping node held
+    /// aren't lost until their leases lapse.
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        let held: Vec<(String, HeldLease)> = self.held.lock().drain().collect();
+        for (key, lease) in held {
+            self.give_back(&key, lease).await?;
+        }
+        self.backend.flush().await
+    }
+
+    /// Lease tokens for `key` unless a lease is already held.
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        if self.held.lock().contains_key(key) {
+            return Ok(());
+        }
+        self.lease(key, 0).await.map(|_| ())
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.backend.list_keys(pattern, after, limit).await
+    }
+}
+
+// ============================================================================
+// SLICING LAYER (a global limit split between nodes by their traffic)
+// ============================================================================
+
+#[derive(Debug, Clone)]
+pub struct SliceConfig {
+    /// How often a key's slices are resized to the nodes' recent traffic.
+    /// A node that stops reporting a key gives up its slice after three.
+    pub rebalance_interval: Duration,
+    /// Least share of a key's limit any reporting node gets, so a node
+    /// whose traffic picks up isn't stuck with nothing until the next
+    /// round.
+    pub min_share: f64,
+}
+
+impl Default for SliceConfig {
+    fn default() -> Self {
+        Self {
+            rebalance_interval: Duration::from_secs(5),
+            min_share: 0.05,
+        }
+    }
+}
+
+/// How a limit is divided between regions, before each region's part is
+/// sliced between its nodes.
+pub enum RegionShares {
+    /// Fixed weights by region name: a region gets its weight over the sum
+    /// of them all.
+    Static(HashMap<String, f64>),
+    /// By each region's recent traffic, which its nodes report through
+    /// `exchange_demand` on a backend every region reaches: a global Redis,
+    /// or a `GossipBackend` spanning the regions. A region that stops
+    /// reporting a key gives up its part like a node gives up its slice.
+    Demand(Arc<dyn StorageBackend>),
+}
+
+struct Region {
+    name: String,
+    shares: RegionShares,
+    /// Regions in the last successful exchange, to split a new key between
+    /// while the others can't be reached.
+    seen: AtomicU64,
+}
+
+/// Splits every key's limit into per-node slices, each enforced by its
+/// node alone and sized by the share of the key's traffic the node saw
+/// lately.
+///
+/// A key's first check on a node, and its first check after every
+/// `rebalance_interval`, reports the tokens the node was asked for since
+/// its last report through `StorageBackend::exchange_demand` on the shared
+/// backend (typically `RedisBackend`), and resizes the node's slice to its
+/// share of everyone's demand. No other check leaves the node. Nodes
+/// resize at different moments, so their slices can add up to a little
+/// more than the limit until each has had its turn.
+///
+/// In an active-active deployment across regions,
+/// [`with_region`](Self::with_region) first divides the limit between the
+/// regions, and `backend` is shared by the region's nodes only. A region
+/// that can't reach the exchange between regions keeps its last share;
+/// over gossip, a partitioned region takes over the parts of those it no
+/// longer hears from, erring towards allowing.
+pub struct SlicedBackend<B: StorageBackend> {
+    backend: B,
+    limit: TokenBucketConfig,
+    node: String,
+    config: SliceConfig,
+    region: Option<Region>,
+    slices: parking_lot::Mutex<HashMap<String, Slice>>,
+}
+
+/// Slices hold thousandths of a token, so that small shares of slow limits
+/// still refill.
+const MILLI: u64 = 1000;
+
+struct Slice {
+    state: BucketState,
+    /// This node's part of the limit, in thousandths of a token.
+    config: TokenBucketConfig,
+    share: f64,
+    /// The region's part of the limit, which `share` is a part of.
+    region_share: f64,
+    /// Tokens asked for since the last report.
+    demand: u64,
+    reported: Instant,
+    reporting: bool,
+}
+
+impl Slice {
+    fn new(limit: &TokenBucketConfig, share: f64, region_share: f64, now_ms: u64) -> Self {
+        let config = sliced(limit, share);
+        Self {
+            state: BucketState::full(&config, now_ms),
+            config,
+            share,
+            region_share,
+            demand: 0,
+            reported: Instant::now(),
+            reporting: false,
+        }
+    }
+
+    /// Resize to `share` of `limit`, keeping the same fraction of the slice
+    /// full.
+    fn resize(&mut self, limit: &TokenBucketConfig, share: f64, region_share: f64, now_ms: u64) {
+        let config = sliced(limit, share);
+        self.state.refill(&self.config, now_ms);
+        self.state.tokens = (self.state.tokens as u128 * config.capacity as u128
+            / self.config.capacity as u128) as u64;
+        self.config = config;
+        self.share = share;
+        self.region_share = region_share;
+    }
+
+    fn snapshot(&self) -> BucketSnapshot {
+        BucketSnapshot {
+            capacity: self.config.capacity / MILLI,
+            remaining: self.state.tokens / MILLI,
+            refill_rate: self.config.refill_rate / MILLI,
+            last_refill: None,
+        }
+    }
+}
+
+fn sliced(limit: &TokenBucketConfig, share: f64) -> TokenBucketConfig {
+    let scale = |n: u64| ((n * MILLI) as f64 * share).round() as u64;
+    TokenBucketConfig {
+        capacity: scale(limit.capacity).max(1),
+        refill_rate: match limit.refill_rate {
+            0 => 0,
+            rate => scale(rate).max(1),
+        },
+        refill_interval: limit.refill_interval,
+    }
+}
+
+/// `node`'s share given every node's `demands`: each node weighs its part
+/// of the total, but no less than `min_share`. With no demand anywhere the
+/// nodes share equally.
+fn share_of(node: &str, demands: &[(String, u64)], min_share: f64) -> f64 {
+    let total: u64 = demands.iter().map(|(_, demand)| demand).sum();
+    let weight = |demand: u64| match total {
+        0 => 1.0,
+        total => (demand as f64 / total as f64).max(min_share),
+    };
+    let all: f64 = demands.iter().map(|(_, demand)| weight(*demand)).sum();
+    let own = demands
+        .iter()
+        .find(|(name, _)| name == node)
+        .map_or(0, |(_, demand)| *demand);
+    if all > 0.0 {
+        (weight(own) / all).min(1.0)
+    } else {
+        1.0
+    }
+}
+
+impl<B: StorageBackend> SlicedBackend<B> {
+    /// Enforce this node's slices of `limit`, the limit across every node,
+    /// naming this one `node` in the shared `backend`.
+    pub fn new(
+        backend: B,
+        limit: TokenBucketConfig,
+        node: impl Into<String>,
+        config: SliceConfig,
+    ) -> Self {
+        Self {
+            backend,
+            limit,
+            node: node.into(),
+            config,
+            region: None,
+            slices: parking_lot::Mutex::default(),
+        }
+    }
+
+    /// Slice only `region`'s part of each limit, divided between regions
+    /// by `shares`, between the nodes sharing `backend`.
+    pub fn with_region(mut self, region: impl Into<String>, shares: RegionShares) -> Self {
+        self.region = Some(Region {
+            name: region.into(),
+            shares,
+            seen: AtomicU64::new(1),
+        });
+        self
+    }
+
+    /// This node's share of `key`'s limit, once it has checked the key.
+    pub fn share(&self, key: &str) -> Option<f64> {
+        self.slices.lock().get(key).map(|slice| slice.share)
+    }
+
+    /// Report `key`'s demand and resize its slice now, instead of at its
+    /// first check after the interval.
+    pub async fn rebalance(&self, key: &str) -> Result<(), RateLimitError> {
+        let demand = match self.slices.lock().get_mut(key) {
+            Some(slice) => {
+                slice.reporting = true;
+                std::mem::take(&mut slice.demand)
+            }
+            None => 0,
+        };
+        self.report(key, demand).await
+    }
+
+    async fn report(&self, key: &str, demand: u64) -> Result<(), RateLimitError> {
+        let ttl = self.config.rebalance_interval * 3;
+        let demands = self
+            .backend
+            .exchange_demand(key, &self.node, demand, ttl)
+            .await;
+        let region_share = match &demands {
+            Ok(demands) => self.region_share(key, demands, ttl).await,
+            Err(_) => 1.0,
+        };
+        let now_ms = unix_millis();
+        let mut slices = self.slices.lock();
+        match (demands, slices.get_mut(key)) {
+            (Ok(demands), Some(slice)) => {
+                let share = share_of(&self.node, &demands, self.config.min_share) * region_share;
+                slice.resize(&self.limit, share, region_share, now_ms);
+                slice.reported = Instant::now();
+                slice.reporting = false;
+            }
+            (Ok(demands), None) => {
+                let share = share_of(&self.node, &demands, self.config.min_share) * region_share;
+                let slice = Slice::new(&self.limit, share, region_share, now_ms);
+                slices.insert(key.to_string(), slice);
+            }
+            // The slice stays as it is until the next round.
+            (Err(e), Some(slice)) => {
+                tracing::debug!(error = %e, "slice rebalance failed");
+                slice.demand += demand;
+                slice.reported = Instant::now();
+                slice.reporting = false;
+            }
+            (Err(e), None) => return Err(e),
+        }
+        Ok(())
+    }
+
+    /// This region's share of `key`'s limit, given the demand of its nodes.
+    async fn region_share(&self, key: &str, demands: &[(String, u64)], ttl: Duration) -> f64 {
+        let Some(region) = &self.region else {
+            return 1.0;
+        };
+        match &region.shares {
+            RegionShares::Static(weights) => {
+                let total: f64 = weights.values().sum();
+                let own = weights.get(&region.name).copied().unwrap_or(0.0);
+                if total > 0.0 {
+                    own / total
+                } else {
+                    1.0
+                }
+            }
+            RegionShares::Demand(regions) => {
+                let demand = demands.iter().map(|(_, demand)| demand).sum();
+                match regions
+                    .exchange_demand(key, &region.name, demand, ttl)
+                    .await
+                {
+                    Ok(regions) => {
+                        region
+                            .seen
+                            .store(regions.len().max(1) as u64, Ordering::Relaxed);
+                        share_of(&region.name, &regions, self.config.min_share)
+                    }
+                    // Keep the last share, or split a new key evenly.
+                    Err(e) => {
+                        tracing::debug!(error = %e, region = %region.name, "region exchange failed");
+                        match self.slices.lock().get(key) {
+                            Some(slice) => slice.region_share,
+                            None => 1.0 / region.seen.load(Ordering::Relaxed) as f64,
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend> StorageBackend for SlicedBackend<B> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let due = match self.slices.lock().get_mut(key) {
+            // Until told otherwise, this check is all of the node's demand.
+            None => Some(cost),
+            Some(slice)
+                if !slice.reporting
+                    && slice.reported.elapsed() >= self.config.rebalance_interval =>
+            {
+                slice.reporting = true;
+                Some(std::mem::take(&mut slice.demand))
+            }
+            Some(_) => None,
+        };
+        if let Some(demand) = due {
+            self.report(key, demand).await?;
+        }
+
+        let mut slices = self.slices.lock();
+        let slice = slices.get_mut(key).ok_or_else(|| {
+            RateLimitError::StorageError(format!("'{}' was reset during the check", key))
+        })?;
+        slice.demand += cost;
+        let allowed =
+            slice
+                .state
+                .try_consume(cost.saturating_mul(MILLI), &slice.config, unix_millis());
+        Ok(TokenDecision {
+            allowed,
+            bucket: Some(slice.snapshot()),
+        })
+    }
+
+    /// Tokens used of this node's slice.
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        Ok(self.inspect(key).await?.map_or(0, |bucket| {
+            bucket.capacity.saturating_sub(bucket.remaining)
+        }))
+    }
+
+    /// This node's slice.
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        let mut slices = self.slices.lock();
+        Ok(slices.get_mut(key).map(|slice| {
+            slice.state.refill(&slice.config, unix_millis());
+            slice.snapshot()
+        }))
+    }
+
+    /// Refills this node's slice and drops the key's demand in the shared
+    /// backend; other nodes keep their slices as they are.
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.slices.lock().remove(key);
+        self.backend.reset(key).await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        if let Some(slice) = self.slices.lock().get_mut(key) {
+            slice
+                .state
+                .refund(tokens.saturating_mul(MILLI), &slice.config, unix_millis());
+        }
+        Ok(())
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.backend.health_check().await
+    }
+
+    /// Size the key's slice ahead of its first check.
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        if self.slices.lock().contains_key(key) {
+            return Ok(());
+        }
+        self.report(key, 0).await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.backend.hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.backend.release_lock(name, holder).await
+    }
+
+    /// Keys this node holds a slice of.
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        let slices = self.slices.lock();
+        Ok(page_of_keys(
+            slices.keys().map(String::as_str),
+            pattern,
+            after,
+            limit,
+        ))
+    }
+}
+
+// ============================================================================
+// ESTIMATING BACKEND (local decisions against shared rate estimates)
+// ============================================================================
+
+/// How [`EstimatingBackend`] measures and shares each key's rate.
+#[derive(Debug, Clone)]
+pub struct EstimateConfig {
+    /// Window a node's rate on a key is measured over. Each node shares
+    /// its last window's count once per window.
+    pub window: Duration,
+}
+
+impl Default for EstimateConfig {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Enforces each limit's refill rate across nodes approximately, with no
+/// coordination on the path of a check.
+///
+/// Every node counts the tokens asked for on each key over a sliding
+/// window, and once per window shares its last count with the other nodes
+/// through `StorageBackend::exchange_demand` on the shared backend,
+/// in the background. A check adds the node's own count to the others'
+/// last ones to estimate the rate across every node. Below the limit's
+/// refill rate it is allowed; above it, it is allowed at random with the
+/// probability that brings the admitted rate down to the limit. Capacity
+/// plays no part, so the mode suits steady traffic better than bursts, and
+/// limits that never refill allow nothing.
+pub struct EstimatingBackend<B: StorageBackend> {
+    backend: Arc<B>,
+    limit: TokenBucketConfig,
+    node: Arc<str>,
+    config: EstimateConfig,
+    estimates: Arc<parking_lot::Mutex<HashMap<String, Estimate>>>,
+}
+
+struct Estimate {
+    started: Instant,
+    /// Tokens asked for here in the current window and the one before.
+    current: u64,
+    previous: u64,
+    /// Tokens the other nodes asked for in their last full windows.
+    others: u64,
+    exchanging: bool,
+}
+
+impl Estimate {
+    /// Start a new window if the current one is over, returning the count
+    /// of the window that ended.
+    fn roll(&mut self, window: Duration) -> Option<u64> {
+        let elapsed = self.started.elapsed();
+        if elapsed < window {
+            return None;
+        }
+        // A window with no checks at all in between counts as empty.
+        self.previous = if elapsed < window * 2 {
+            self.current
+        } else {
+            0
+        };
+        self.current = 0;
+        self.started = Instant::now();
+        Some(self.previous)
+    }
+
+    /// Tokens asked for across every node over the last window, counting
+    /// the part of the previous window still inside it.
+    fn global(&self, window: Duration) -> f64 {
+        let through = (self.started.elapsed().as_secs_f64() / window.as_secs_f64()).min(1.0);
+        self.previous as f64 * (1.0 - through) + self.current as f64 + self.others as f64
+    }
+}
+
+impl<B: StorageBackend + 'static> EstimatingBackend<B> {
+    /// Enforce `limit` across every node, naming this one `node` in the
+    /// shared `backend`.
+    pub fn new(
+        backend: B,
+        limit: TokenBucketConfig,
+        node: impl Into<String>,
+        config: EstimateConfig,
+    ) -> Self {
+        Self {
+            backend: Arc::new(backend),
+            limit,
+            node: node.into().into(),
+            config,
+            estimates: Arc::new(parking_lot::Mutex::default()),
+        }
+    }
+
+    /// Tokens the limit grants per window across every node.
+    fn allowance(&self) -> f64 {
+        let per_second =
+            self.limit.refill_rate as f64 / self.limit.refill_interval.as_secs_f64().max(1e-3);
+        per_second * self.config.window.as_secs_f64()
+    }
+
+    /// Share `demand` as this node's count on `key`, and keep the others'.
+    fn spawn_exchange(&self, key: String, demand: u64) {
+        let backend = Arc::clone(&self.backend);
+        let estimates = Arc::clone(&self.estimates);
+        let node = Arc::clone(&self.node);
+        let ttl = self.config.window * 3;
+        tokio::spawn(async move {
+            let demands = backend.exchange_demand(&key, &node, demand, ttl).await;
+            let mut estimates = estimates.lock();
+            let Some(estimate) = estimates.get_mut(&key) else {
+                return;
+            };
+            estimate.exchanging = false;
+            match demands {
+                Ok(demands) => {
+                    estimate.others = demands
+                        .iter()
+                        .filter(|(name, _)| **name != *node)
+                        .map(|(_, demand)| demand)
+                        .sum();
+                }
+                Err(e) => tracing::debug!(error = %e, "rate estimate exchange failed"),
+            }
+        });
+    }
+}
+
+/// A number in `[0, 1)`, different for every call.
+fn uniform() -> f64 {
+    use std::hash::BuildHasher;
+    let random = std::collections::hash_map::RandomState::new().hash_one(());
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[async_trait]
+impl<B: StorageBackend + 'static> StorageBackend for EstimatingBackend<B> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    /// The bucket is the limit, with what the estimate leaves of this
+    /// window's allowance across every node as remaining.
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let allowance = self.allowance();
+        let mut estimates = self.estimates.lock();
+        let (estimate, due) = match estimates.get_mut(key) {
+            Some(estimate) => {
+                let ended = estimate.roll(self.config.window);
+                (estimate, ended)
+            }
+            // Learn the other nodes' counts from the start.
+            None => (
+                estimates.entry(key.to_string()).or_insert(Estimate {
+                    started: Instant::now(),
+                    current: 0,
+                    previous: 0,
+                    others: 0,
+                    exchanging: false,
+                }),
+                Some(0),
+            ),
+        };
+        estimate.current += cost;
+        let global = estimate.global(self.config.window);
+        let due = due.filter(|_| !estimate.exchanging);
+        if due.is_some() {
+            estimate.exchanging = true;
+        }
+        drop(estimates);
+        if let Some(demand) = due {
+            self.spawn_exchange(key.to_string(), demand);
+        }
+        let remaining = ((allowance - global).max(0.0) as u64).min(self.limit.capacity);
+        Ok(TokenDecision {
+            allowed: global <= allowance || uniform() < allowance / global,
+            bucket: Some(BucketSnapshot::new(&self.limit, remaining)),
+        })
+    }
+
+    /// Tokens asked for on `key` across every node over the last window,
+    /// as estimated here.
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let estimates = self.estimates.lock();
+        Ok(estimates.get(key).map_or(0, |estimate| {
+            estimate.global(self.config.window).round() as u64
+        }))
+    }
+
+    /// Forgets this node's count, and the key's shared counts; other nodes
+    /// keep theirs until their next exchange.
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.estimates.lock().remove(key);
+        self.backend.reset(key).await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        if let Some(estimate) = self.estimates.lock().get_mut(key) {
+            estimate.current = estimate.current.saturating_sub(tokens);
+        }
+        Ok(())
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backend.acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backend.renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.backend.return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.backend.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.backend.exchange_demand(key, node, demand, ttl).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.backend.health_check().await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.backend.hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.backend.release_lock(name, holder).await
+    }
+
+    /// Keys this node has an estimate for.
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        let estimates = self.estimates.lock();
+        Ok(page_of_keys(
+            estimates.keys().map(String::as_str),
+            pattern,
+            after,
+            limit,
+        ))
+    }
+}
+
+// ============================================================================
+// TIERED BACKEND (local slices leased from a shared backend)
+// ============================================================================
+
+#[derive(Debug, Clone)]
+pub struct TieredConfig {
+    /// Tokens leased from the shared backend per round trip.
+    pub slice_size: u64,
+    /// Leased tokens unused after this long are dropped, bounding how far
+    /// the local view can lag the shared one (after a reset, say).
+    pub max_staleness: Duration,
+    /// Start leasing the next slice in the background once fewer than this
+    /// many local tokens remain.
+    pub prefetch_below: u64,
+}
+
+impl Default for TieredConfig {
+    fn default() -> Self {
+        Self {
+            slice_size: 10,
+            max_staleness: Duration::from_secs(1),
+            prefetch_below: 2,
+        }
+    }
+}
+
+/// Memory L1 in front of a shared L2 (typically `RedisBackend`).
+///
+/// Tokens are debited from L2 in slices before they are handed out locally,
+/// so the tier never admits more than L2 would have; the cost is that
+/// unused leased tokens (at most one slice per key and node, for at most
+/// `max_staleness`) are lost rather than returned. Slices are topped up
+/// asynchronously so hot keys rarely wait on L2, and stale ones are swept
+/// out at most once per `max_staleness`.
+pub struct TieredBackend<B: StorageBackend> {
+    l2: Arc<B>,
+    slices: Arc<parking_lot::Mutex<HashMap<String, LocalSlice>>>,
+    swept_at: parking_lot::Mutex<Instant>,
+    config: TieredConfig,
+}
+
+struct LocalSlice {
+    available: u64,
+    leased_at: Instant,
+    prefetching: bool,
+    /// L2's bucket as the last lease left it.
+    bucket: Option<BucketSnapshot>,
+}
+
+impl<B: StorageBackend + 'static> TieredBackend<B> {
+    pub fn new(l2: B, config: TieredConfig) -> Self {
+        Self {
+            l2: Arc::new(l2),
+            slices: Arc::new(parking_lot::Mutex::new(HashMap::new())),
+            swept_at: parking_lot::Mutex::new(Instant::now()),
+            config,
+        }
+    }
+
+    /// Consume from the local slice if it is fresh and large enough,
+    /// returning L2's bucket with the slice's tokens counted as remaining.
+    fn take_local(&self, key: &str, cost: u64) -> Option<Option<BucketSnapshot>> {
+        let mut slices = self.slices.lock();
+        let slice = slices.get_mut(key)?;
+
+        if slice.leased_at.elapsed() > self.config.max_staleness {
+            if !slice.prefetching {
+                slices.remove(key);
+            }
+            return None;
+        }
+        if slice.available < cost {
+            return None;
+        }
+
+        slice.available -= cost;
+        if slice.available < self.config.prefetch_below && !slice.prefetching {
+            slice.prefetching = true;
+            self.spawn_prefetch(key.to_string());
+        }
+        Some(with_local(slice.bucket, slice.available))
+    }
+
+    fn spawn_prefetch(&self, key: String) {
+        let l2 = Arc::clone(&self.l2);
+        let slices = Arc::clone(&self.slices);
+        let slice_size = self.config.slice_size;
+        let max_staleness = self.config.max_staleness;
+
+        tokio::spawn(async move {
+            let leased = match l2.take_token_detailed(&key, slice_size).await {
+                Ok(decision) if decision.allowed => Some(decision.bucket),
+                _ => None,
+            };
+            {
+                let mut slices = slices.lock();
+                if let Some(slice) = slices.get_mut(&key) {
+                    slice.prefetching = false;
+                    if let Some(bucket) = leased {
+                        // What is left of a stale slice has expired; the new
+                        // lease must not carry it forward.
+                        if slice.leased_at.elapsed() > max_staleness {
+                            slice.available = 0;
+                        }
+                        slice.available += slice_size;
+                        slice.leased_at = Instant::now();
+                        slice.bucket = bucket.or(slice.bucket);
+                    }
+                    return;
+                }
+            }
+            // The slice was reset while the lease was in flight, so nothing
+            // local will spend it; hand it back to L2.
+            if leased.is_some() {
+                let _ = l2.refund(&key, slice_size).await;
+            }
+        });
+    }
+
+    /// Add `tokens` leased from L2, which `bucket` was left with, to
+    /// `key`'s slice, returning L2's bucket with the slice's tokens counted
+    /// as remaining.
+    fn add_local(
+        &self,
+        key: &str,
+        tokens: u64,
+        bucket: Option<BucketSnapshot>,
+    ) -> Option<BucketSnapshot> {
+        let mut slices = self.slices.lock();
+        self.sweep(&mut slices);
+        let slice = slices.entry(key.to_string()).or_insert(LocalSlice {
+            available: 0,
+            leased_at: Instant::now(),
+            prefetching: false,
+            bucket: None,
+        });
+        slice.available += tokens;
+        slice.leased_at = Instant::now();
+        slice.bucket = bucket.or(slice.bucket);
+        with_local(slice.bucket, slice.available)
+    }
+
+    /// Drop stale slices of keys no longer being checked, which would
+    /// otherwise stay in the map for good. Slices with a prefetch in flight
+    /// are kept for it to land in.
+    fn sweep(&self, slices: &mut HashMap<String, LocalSlice>) {
+        let mut swept_at = self.swept_at.lock();
+        if swept_at.elapsed() <= self.config.max_staleness {
+            return;
+        }
+        *swept_at = Instant::now();
+        let max_staleness = self.config.max_staleness;
+        slices.retain(|_, slice| {
+            slice.prefetching || slice.leased_at.elapsed() <= max_staleness
+        });
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend + 'static> StorageBackend for TieredBackend<B> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    /// The bucket is L2's as the last lease left it, with the local
+    /// slice's tokens counted as remaining.
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        if let Some(bucket) = self.take_local(key, cost) {
+            return Ok(TokenDecision {
+                allowed: true,
+                bucket,
+            });
+        }
+
+        let lease = self.config.slice_size.max(cost);
+        let decision = self.l2.take_token_detailed(key, lease).await?;
+        if decision.allowed {
+            return Ok(TokenDecision {
+                allowed: true,
+                bucket: self.add_local(key, lease - cost, decision.bucket),
+            });
+        }
+
+        // Not enough left in L2 for a whole slice; settle this request
+        // exactly so the last tokens of a bucket are still usable.
+        if lease > cost {
+            return self.l2.take_token_detailed(key, cost).await;
+        }
+        Ok(decision)
+    }
+
+    /// Goes straight to L2; tokens in local slices stay there for single
+    /// checks.
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        self.l2.take_all(entries).await
+    }
+
+    /// Usage as seen by L2, which includes tokens leased but not yet used.
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        self.l2.get_usage(key).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.slices.lock().remove(key);
+        self.l2.reset(key).await
+    }
+
+    /// Refunds go to L2, where the leased slice was taken from.
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.l2.refund(key, tokens).await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.l2.acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.l2.renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.l2.return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.l2.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.l2.exchange_demand(key, node, demand, ttl).await
+    }
+
+    /// The L2 bucket, which counts leased tokens as spent.
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.l2.inspect(key).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.l2.health_check().await
+    }
+
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        self.l2.flush().await
+    }
+
+    /// Lease a first slice for `key` unless one is already held.
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        if self.slices.lock().contains_key(key) {
+            return Ok(());
+        }
+        let decision = self
+            .l2
+            .take_token_detailed(key, self.config.slice_size)
+            .await?;
+        if decision.allowed {
+            self.add_local(key, self.config.slice_size, decision.bucket);
+        }
+        Ok(())
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.l2.list_keys(pattern, after, limit).await
+    }
+}
+
+// ============================================================================
+// KEY AFFINITY (consistent-hash ownership of keys)
+// ============================================================================
+
+/// Points each node gets on a [`HashRing`], enough to spread keys between
+/// a handful of nodes within a few percent of evenly.
+pub const RING_POINTS: usize = 128;
+
+/// Assigns every key to one of a set of nodes by consistent hashing, so a
+/// node joining or leaving only moves the keys it gains or loses. Rings
+/// built from the same node names agree on every key's owner, whatever
+/// order the names were given in.
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    nodes: Vec<String>,
+    /// Each point's position and the index of its node, by position.
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    pub fn new<I, S>(nodes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut nodes: Vec<String> = nodes.into_iter().map(Into::into).collect();
+        nodes.sort();
+        nodes.dedup();
+        let mut points = Vec::with_capacity(nodes.len() * RING_POINTS);
+        for (index, node) in nodes.iter().enumerate() {
+            for point in 0..RING_POINTS {
+                points.push((ring_hash(&format!("{}#{}", node, point)), index));
+            }
+        }
+        points.sort_unstable();
+        Self { nodes, points }
+    }
+
+    /// The nodes, sorted.
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    /// The node owning `key`: the one with the first point at or after the
+    /// key's own position, wrapping around. `None` on an empty ring.
+    pub fn owner(&self, key: &str) -> Option<&str> {
+        let hash = ring_hash(key);
+        let at = self.points.partition_point(|(point, _)| *point < hash);
+        let (_, index) = self.points.get(at).or_else(|| self.points.first())?;
+        Some(&self.nodes[*index])
+    }
+}
+
+/// FNV-1a, finished with MurmurHash3's mix so keys that differ only in
+/// their last characters land far apart.
+fn ring_hash(key: &str) -> u64 {
+    let mut hash = fnv1a(key);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Decides the keys a [`HashRing`] gives this node from buckets in memory,
+/// and writes what they spend to `backup` (typically `RedisBackend`), so
+/// another node can carry on from there when a key changes owner.
+///
+/// With every check of a key sent to its owner, by
+/// `GuardianClientBuilder::key_affinity` or a proxy hashing keys the same
+/// way, checks run at memory speed and are still exact across the cluster:
+/// one node decides each key. A key's first check on its owner reads the
+/// bucket from the backup, and its spending is written back on its first
+/// check after every `sync_interval`, and on `flush`. Checks of keys other
+/// nodes own go to the backup, which is slower, and blind to what the
+/// owner spent since it last wrote back.
+pub struct AffinityBackend<B: StorageBackend> {
+    backup: B,
+    config: TokenBucketConfig,
+    ring: HashRing,
+    node: String,
+    sync_interval: Duration,
+    owned: parking_lot::Mutex<HashMap<String, Owned>>,
+}
+
+struct Owned {
+    state: BucketState,
+    /// Tokens spent since the last write to the backup.
+    unsynced: u64,
+    synced: Instant,
+    syncing: bool,
+}
+
+impl<B: StorageBackend> AffinityBackend<B> {
+    /// Decide the keys `ring` gives `node`, with buckets sized by `config`.
+    pub fn new(
+        backup: B,
+        config: TokenBucketConfig,
+        ring: HashRing,
+        node: impl Into<String>,
+        sync_interval: Duration,
+    ) -> Self {
+        Self {
+            backup,
+            config,
+            ring,
+            node: node.into(),
+            sync_interval,
+            owned: parking_lot::Mutex::default(),
+        }
+    }
+
+    /// Whether this node decides `key`.
+    pub fn owns(&self, key: &str) -> bool {
+        self.ring.owner(key) == Some(self.node.as_str())
+    }
+
+    /// Read `key`'s bucket from the backup unless it is already held.
+    async fn load(&self, key: &str) -> Result<(), RateLimitError> {
+        if self.owned.lock().contains_key(key) {
+            return Ok(());
+        }
+        let remaining = self
+            .backup
+            .inspect(key)
+            .await?
+            .map_or(self.config.capacity, |bucket| {
+                bucket.remaining.min(self.config.capacity)
+            });
+        self.owned
+            .lock()
+            .entry(key.to_string())
+            .or_insert_with(|| Owned {
+                state: BucketState {
+                    tokens: remaining,
+                    last_refill_ms: unix_millis(),
+                },
+                unsynced: 0,
+                synced: Instant::now(),
+                syncing: false,
+            });
+        Ok(())
+    }
+
+    /// Take `spent` tokens from the backup's bucket, or what it has left.
+    /// Tokens that can't be written are kept for the next attempt.
+    async fn write_back(&self, key: &str, spent: u64) {
+        let written = match self.backup.take_token_detailed(key, spent).await {
+            Ok(decision) if !decision.allowed => match decision.bucket {
+                Some(bucket) if bucket.remaining > 0 => self
+                    .backup
+                    .take_token(key, bucket.remaining)
+                    .await
+                    .map(drop),
+                _ => Ok(()),
+            },
+            result => result.map(drop),
+        };
+        if let Some(owned) = self.owned.lock().get_mut(key) {
+            owned.syncing = false;
+            owned.synced = Instant::now();
+            if let Err(e) = &written {
+                tracing::debug!(error = %e, "affinity write-back failed");
+                owned.unsynced += spent;
+            }
+        }
+    }
+
+    fn snapshot(&self, key: &str) -> Option<BucketSnapshot> {
+        let mut owned = self.owned.lock();
+        let owned = owned.get_mut(key)?;
+        owned.state.refill(&self.config, unix_millis());
+        Some(BucketSnapshot::new(&self.config, owned.state.tokens))
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend> StorageBackend for AffinityBackend<B> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        if !self.owns(key) {
+            return self.backup.take_token_detailed(key, cost).await;
+        }
+        self.load(key).await?;
+
+        let (decision, due) = {
+            let mut owned = self.owned.lock();
+            let owned = owned.get_mut(key).ok_or_else(|| {
+                RateLimitError::StorageError(format!("'{}' was reset during the check", key))
+            })?;
+            let allowed = owned.state.try_consume(cost, &self.config, unix_millis());
+            if allowed {
+                owned.unsynced += cost;
+            }
+            let due = !owned.syncing
+                && owned.unsynced > 0
+                && owned.synced.elapsed() >= self.sync_interval;
+            if due {
+                owned.syncing = true;
+            }
+            let decision = TokenDecision {
+                allowed,
+                bucket: Some(BucketSnapshot::new(&self.config, owned.state.tokens)),
+            };
+            (decision, due.then(|| std::mem::take(&mut owned.unsynced)))
+        };
+        if let Some(spent) = due {
+            self.write_back(key, spent).await;
+        }
+        Ok(decision)
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        Ok(self
+            .inspect(key)
+            .await?
+            .map_or(0, |bucket| bucket.capacity.saturating_sub(bucket.remaining)))
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        if !self.owns(key) {
+            return self.backup.inspect(key).await;
+        }
+        self.load(key).await?;
+        Ok(self.snapshot(key))
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.owned.lock().remove(key);
+        self.backup.reset(key).await
+    }
+
+    /// Refunds come out of the spending not yet written back first, and
+    /// only the rest goes to the backup.
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        let rest = match self.owned.lock().get_mut(key) {
+            Some(owned) => {
+                owned.state.refund(tokens, &self.config, unix_millis());
+                let unwritten = owned.unsynced.min(tokens);
+                owned.unsynced -= unwritten;
+                tokens - unwritten
+            }
+            None => tokens,
+        };
+        if rest == 0 {
+            return Ok(());
+        }
+        self.backup.refund(key, rest).await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backup.acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backup.renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.backup.return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.backup.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.backup.exchange_demand(key, node, demand, ttl).await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.backup.hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.backup.release_lock(name, holder).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.backup.health_check().await
+    }
+
+    /// Write every key's spending back, then flush the backup.
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        let due: Vec<(String, u64)> = self
+            .owned
+            .lock()
+            .iter_mut()
+            .filter(|(_, owned)| !owned.syncing && owned.unsynced > 0)
+            .map(|(key, owned)| {
+                owned.syncing = true;
+                (key.clone(), std::mem::take(&mut owned.unsynced))
+            })
+            .collect();
+        for (key, spent) in due {
+            self.write_back(&key, spent).await;
+        }
+        self.backup.flush().await
+    }
+
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        if self.owns(key) {
+            self.load(key).await
+        } else {
+            self.backup.warm_up(key).await
+        }
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.backup.list_keys(pattern, after, limit).await
+    }
+}
+
+// ============================================================================
+// ROUTER BACKEND (per-key-pattern backend selection)
+// ============================================================================
+
+/// Dispatches each key to a child backend chosen by pattern, so one
+/// `RateLimiter` can mix durability levels (e.g. `internal:*` in memory,
+/// `api:*` on a Redis cluster).
+///
+/// Patterns are matched in the order they were added; `*` matches any run
+/// of characters. Keys matching no pattern go to the default backend.
+///
+/// Routes can also be changed while the router is serving traffic.
+pub struct RouterBackend {
+    routes: RwLock<Vec<(String, Arc<dyn StorageBackend>)>>,
+    default: RwLock<Arc<dyn StorageBackend>>,
+    events: Option<EventBus>,
+}
+
+impl RouterBackend {
+    pub fn new(default: impl StorageBackend + 'static) -> Self {
+        Self {
+            routes: RwLock::new(Vec::new()),
+            default: RwLock::new(Arc::new(default)),
+            events: None,
+        }
+    }
+
+    /// Publish runtime route changes to `events`.
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    fn publish(&self, event: LimiterEvent) {
+        if let Some(events) = &self.events {
+            events.publish(event);
+        }
+    }
+
+    /// Send keys matching `pattern` to `backend`.
+    pub fn route(self, pattern: impl Into<String>, backend: impl StorageBackend + 'static) -> Self {
+        self.routes.write().push((pattern.into(), Arc::new(backend)));
+        self
+    }
+
+    /// Add or replace the route for `pattern` at runtime. A new pattern is
+    /// placed ahead of any shorter ones, so specific rules win over broad
+    /// ones.
+    pub fn set_route(&self, pattern: impl Into<String>, backend: impl StorageBackend + 'static) {
+        let pattern = pattern.into();
+        let backend: Arc<dyn StorageBackend> = Arc::new(backend);
+        {
+            let mut routes = self.routes.write();
+            if let Some(route) = routes.iter_mut().find(|(p, _)| *p == pattern) {
+                route.1 = backend;
+            } else {
+                let position = routes
+                    .iter()
+                    .position(|(p, _)| p.len() < pattern.len())
+                    .unwrap_or(routes.len());
+                routes.insert(position, (pattern.clone(), backend));
+            }
+        }
+        self.publish(LimiterEvent::RouteSet { pattern });
+    }
+
+    /// Remove the route for `pattern`, returning whether it existed.
+    pub fn remove_route(&self, pattern: &str) -> bool {
+        let removed = {
+            let mut routes = self.routes.write();
+            let before = routes.len();
+            routes.retain(|(p, _)| p != pattern);
+            routes.len() != before
+        };
+        if removed {
+            self.publish(LimiterEvent::RouteRemoved {
+                pattern: pattern.to_string(),
+            });
+        }
+        removed
+    }
+
+    pub fn set_default(&self, backend: impl StorageBackend + 'static) {
+        *self.default.write() = Arc::new(backend);
+        self.publish(LimiterEvent::DefaultSet);
+    }
+
+    /// The first route pattern `key` matches, or `None` when it falls
+    /// through to the default backend.
+    pub fn pattern_for(&self, key: &str) -> Option<String> {
+        self.routes
+            .read()
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, key))
+            .map(|(pattern, _)| pattern.clone())
+    }
+
+    /// Every child backend, default first.
+    fn all_backends(&self) -> Vec<Arc<dyn StorageBackend>> {
+        let mut backends = vec![Arc::clone(&self.default.read())];
+        backends.extend(self.routes.read().iter().map(|(_, backend)| Arc::clone(backend)));
+        backends
+    }
+
+    fn backend_for(&self, key: &str) -> Arc<dyn StorageBackend> {
+        self.routes
+            .read()
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, key))
+            .map_or_else(
+                || Arc::clone(&self.default.read()),
+                |(_, backend)| Arc::clone(backend),
+            )
+    }
+}
+
+#[async_trait]
+impl StorageBackend for RouterBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        self.backend_for(key).take_token(key, cost).await
+    }
+
+    /// Only when every key routes to the same backend; keys kept in
+    /// different ones can't be taken in one step.
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        let mut backends = entries.iter().map(|(key, _)| self.backend_for(key));
+        let Some(backend) = backends.next() else {
+            return Ok(Vec::new());
+        };
+        if backends.any(|other| !Arc::ptr_eq(&other, &backend)) {
+            return Err(RateLimitError::Unsupported(
+                "the keys are routed to different backends".to_string(),
+            ));
+        }
+        backend.take_all(entries).await
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        self.backend_for(key).get_usage(key).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.backend_for(key).reset(key).await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.backend_for(key).refund(key, tokens).await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backend_for(key)
+            .acquire_lease(key, id, tokens, ttl)
+            .await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backend_for(key).renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.backend_for(key).return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.backend_for(key).leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.backend_for(key)
+            .exchange_demand(key, node, demand, ttl)
+            .await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.backend_for(name).hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.backend_for(name).release_lock(name, holder).await
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        self.backend_for(key).take_token_detailed(key, cost).await
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.backend_for(key).inspect(key).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        for backend in self.all_backends() {
+            backend.health_check().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        for backend in self.all_backends() {
+            backend.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        self.backend_for(key).warm_up(key).await
+    }
+
+    /// Merges the pages of every child backend. Children may share a store
+    /// (e.g. one Redis per rule), so duplicates are dropped.
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        let mut keys = Vec::new();
+        for backend in self.all_backends() {
+            keys.extend(backend.list_keys(pattern, after, limit).await?);
+        }
+        Ok(page_of_keys(keys.iter().map(String::as_str), pattern, after, limit))
+    }
+}
+
+/// Match `key` against a pattern where `*` stands for any (possibly empty)
+/// run of characters.
+pub fn glob_match(pattern: &str, key: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = key.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` at all: exact match.
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+// ============================================================================
+// MIRROR BACKEND (dual writes for backend migrations)
+// ============================================================================
+
+/// Counters describing how a mirrored secondary compares to the primary.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MirrorStats {
+    pub decisions: u64,
+    /// `take_token` calls where the secondary allowed and the primary
+    /// denied, or the other way round.
+    pub decision_mismatches: u64,
+    pub usage_mismatches: u64,
+    pub secondary_errors: u64,
+}
+
+/// Sends every operation to both a primary and a secondary backend but
+/// only ever answers from the primary, counting where they disagree.
+///
+/// Run this while migrating (standalone Redis to cluster, say) until the
+/// mismatch rate is acceptable, then swap the secondary in as primary.
+/// Secondary errors are counted and otherwise ignored.
+pub struct MirrorBackend<P: StorageBackend, S: StorageBackend> {
+    primary: P,
+    secondary: S,
+    decisions: AtomicU64,
+    decision_mismatches: AtomicU64,
+    usage_mismatches: AtomicU64,
+    secondary_errors: AtomicU64,
+}
+
+impl<P: StorageBackend, S: StorageBackend> MirrorBackend<P, S> {
+    pub fn new(primary: P, secondary: S) -> Self {
+        Self {
+            primary,
+            secondary,
+            decisions: AtomicU64::new(0),
+            decision_mismatches: AtomicU64::new(0),
+            usage_mismatches: AtomicU64::new(0),
+            secondary_errors: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> MirrorStats {
+        MirrorStats {
+            decisions: self.decisions.load(Ordering::Relaxed),
+            decision_mismatches: self.decision_mismatches.load(Ordering::Relaxed),
+            usage_mismatches: self.usage_mismatches.load(Ordering::Relaxed),
+            secondary_errors: self.secondary_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Compare a secondary result with the primary's, returning the
+    /// primary's untouched.
+    fn compare<T: PartialEq>(
+        &self,
+        primary: Result<T, RateLimitError>,
+        secondary: Result<T, RateLimitError>,
+        mismatches: &AtomicU64,
+    ) -> Result<T, RateLimitError> {
+        match (&primary, secondary) {
+            (_, Err(_)) => {
+                self.secondary_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            (Ok(p), Ok(s)) if *p != s => {
+                mismatches.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+        primary
+    }
+}
+
+#[async_trait]
+impl<P: StorageBackend, S: StorageBackend> StorageBackend for MirrorBackend<P, S> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        let (primary, secondary) = tokio::join!(
+            self.primary.take_token(key, cost),
+            self.secondary.take_token(key, cost)
+        );
+        self.decisions.fetch_add(1, Ordering::Relaxed);
+        self.compare(primary, secondary, &self.decision_mismatches)
+    }
+
+    /// The primary's decision and bucket; only the decisions are compared.
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let (primary, secondary) = tokio::join!(
+            self.primary.take_token_detailed(key, cost),
+            self.secondary.take_token_detailed(key, cost)
+        );
+        self.decisions.fetch_add(1, Ordering::Relaxed);
+        match (&primary, secondary) {
+            (_, Err(_)) => {
+                self.secondary_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            (Ok(p), Ok(s)) if p.allowed != s.allowed => {
+                self.decision_mismatches.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+        primary
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let (primary, secondary) =
+            tokio::join!(self.primary.get_usage(key), self.secondary.get_usage(key));
+        self.compare(primary, secondary, &self.usage_mismatches)
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let (primary, secondary) = tokio::join!(self.primary.reset(key), self.secondary.reset(key));
+        if secondary.is_err() {
+            self.secondary_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        primary
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        let (primary, secondary) = tokio::join!(
+            self.primary.refund(key, tokens),
+            self.secondary.refund(key, tokens)
+        );
+        if secondary.is_err() {
+            self.secondary_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        primary
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.primary.acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.primary.renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.primary.return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.primary.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.primary.exchange_demand(key, node, demand, ttl).await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.primary.hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.primary.release_lock(name, holder).await
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.primary.inspect(key).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.primary.health_check().await
+    }
+
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        let (primary, secondary) = tokio::join!(self.primary.flush(), self.secondary.flush());
+        if secondary.is_err() {
+            self.secondary_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        primary
+    }
+
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        let (primary, secondary) =
+            tokio::join!(self.primary.warm_up(key), self.secondary.warm_up(key));
+        if secondary.is_err() {
+            self.secondary_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        primary
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.primary.list_keys(pattern, after, limit).await
+    }
+}
+
+// ============================================================================
+// FAILOVER BACKEND (degraded local decisions while the primary is down)
+// ============================================================================
+
+/// Counters shared by every [`FailoverBackend`] built with them, for
+/// reporting how often and for how long decisions were made locally.
+#[derive(Debug, Default)]
+pub struct FailoverStats {
+    failovers: AtomicU64,
+    recoveries: AtomicU64,
+    degraded: AtomicU64,
+    degraded_decisions: AtomicU64,
+    degraded_millis: AtomicU64,
+    reconciled_tokens: AtomicU64,
+}
+
+impl FailoverStats {
+    /// Times a primary was given up on.
+    pub fn failovers(&self) -> u64 {
+        self.failovers.load(Ordering::Relaxed)
+    }
+
+    /// Times a primary was returned to after a failover.
+    pub fn recoveries(&self) -> u64 {
+        self.recoveries.load(Ordering::Relaxed)
+    }
+
+    /// Backends currently answering from their fallback.
+    pub fn degraded(&self) -> u64 {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// `take_token` calls answered by a fallback.
+    pub fn degraded_decisions(&self) -> u64 {
+        self.degraded_decisions.load(Ordering::Relaxed)
+    }
+
+    /// Total time spent degraded, over windows that have ended.
+    pub fn degraded_time(&self) -> Duration {
+        Duration::from_millis(self.degraded_millis.load(Ordering::Relaxed))
+    }
+
+    /// Tokens spent on a fallback and charged to the primary on recovery.
+    pub fn reconciled_tokens(&self) -> u64 {
+        self.reconciled_tokens.load(Ordering::Relaxed)
+    }
+}
+
+/// What happened to a key on the fallback that the primary hasn't seen.
+#[derive(Debug, Default, Clone, Copy)]
+struct Pending {
+    reset: bool,
+    spent: u64,
+}
+
+struct DegradedWindow {
+    since: Instant,
+    last_probe: Instant,
+    pending: HashMap<String, Pending>,
+}
+
+/// Answers from `primary` until it fails with a storage error, then from
+/// `fallback` (typically an in-process `MemoryBackend` with tighter limits)
+/// until the primary is healthy again.
+///
+/// Recovery is noticed by `health_check`, and by the first call after each
+/// retry interval while degraded. On recovery the tokens allowed by the
+/// fallback are charged to the primary, as far as its buckets allow, so a
+/// key can't double its budget across an outage; the fallback's buckets
+/// are then reset for the next one.
+pub struct FailoverBackend<P: StorageBackend, F: StorageBackend> {
+    primary: P,
+    fallback: F,
+    retry_interval: Duration,
+    degraded: parking_lot::Mutex<Option<DegradedWindow>>,
+    stats: Arc<FailoverStats>,
+    events: Option<EventBus>,
+}
+
+impl<P: StorageBackend, F: StorageBackend> FailoverBackend<P, F> {
+    pub fn new(primary: P, fallback: F) -> Self {
+        Self {
+            primary,
+            fallback,
+            retry_interval: Duration::from_secs(5),
+            degraded: parking_lot::Mutex::new(None),
+            stats: Arc::default(),
+            events: None,
+        }
+    }
+
+    /// Publish switches to the fallback and back to `events`.
+    pub fn with_events(mut self, events: EventBus) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Record into `stats`, e.g. to total several backends' failovers.
+    pub fn with_stats(mut self, stats: Arc<FailoverStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// How long to wait between attempts to get back to the primary while
+    /// degraded (default 5 seconds).
+    pub fn with_retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+
+    pub fn stats(&self) -> &Arc<FailoverStats> {
+        &self.stats
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.lock().is_some()
+    }
+
+    /// Whether calls should go to the fallback. Every retry interval one
+    /// caller probes the primary first, and may find it recovered.
+    async fn use_fallback(&self) -> bool {
+        let probe = match &mut *self.degraded.lock() {
+            None => return false,
+            Some(window) if window.last_probe.elapsed() >= self.retry_interval => {
+                window.last_probe = Instant::now();
+                true
+            }
+            Some(_) => false,
+        };
+        if probe && self.primary.health_check().await.is_ok() {
+            self.recover().await;
+        }
+        self.is_degraded()
+    }
+
+    /// Switch to the fallback after `error`, unless already there.
+    fn fail_over(&self, error: &RateLimitError) {
+        let mut degraded = self.degraded.lock();
+        if degraded.is_none() {
+            tracing::warn!(error = %error, "primary backend failed; serving from the fallback");
+            let now = Instant::now();
+            *degraded = Some(DegradedWindow {
+                since: now,
+                last_probe: now,
+                pending: HashMap::new(),
+            });
+            self.stats.failovers.fetch_add(1, Ordering::Relaxed);
+            self.stats.degraded.fetch_add(1, Ordering::Relaxed);
+            if let Some(events) = &self.events {
+                events.publish(LimiterEvent::FailedOver {
+                    error: error.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Note what the fallback did to `key`, for `recover` to replay.
+    fn record(&self, key: &str, update: impl FnOnce(&mut Pending)) {
+        if let Some(window) = &mut *self.degraded.lock() {
+            update(window.pending.entry(key.to_string()).or_default());
+        }
+    }
+
+    /// Return to the primary, replaying what happened on the fallback.
+    /// Decisions made while this runs are answered by the primary.
+    async fn recover(&self) {
+        let Some(window) = self.degraded.lock().take() else {
+            return;
+        };
+        let mut reconciled = 0;
+        for (key, pending) in &window.pending {
+            let replayed = async {
+                if pending.reset {
+                    self.primary.reset(key).await?;
+                }
+                reconciled += self.charge(key, pending.spent).await?;
+                self.fallback.reset(key).await
+            };
+            if let Err(e) = replayed.await {
+                tracing::warn!(%key, error = %e, "failed to reconcile key after failover");
+            }
+        }
+        let elapsed = window.since.elapsed();
+        self.stats.recoveries.fetch_add(1, Ordering::Relaxed);
+        self.stats.degraded.fetch_sub(1, Ordering::Relaxed);
+        self.stats
+            .degraded_millis
+            .fetch_add(elapsed.as_millis() as u64, Ordering::Relaxed);
+        self.stats
+            .reconciled_tokens
+            .fetch_add(reconciled, Ordering::Relaxed);
+        tracing::info!(
+            degraded_secs = elapsed.as_secs_f64(),
+            keys = window.pending.len(),
+            reconciled,
+            "primary backend recovered"
+        );
+        if let Some(events) = &self.events {
+            events.publish(LimiterEvent::Recovered {
+                degraded_for: elapsed,
+                reconciled_tokens: reconciled,
+            });
+        }
+    }
+
+    /// Take `spent` tokens from the primary's bucket for `key`, or all it
+    /// has left when that's fewer. Returns the tokens taken.
+    async fn charge(&self, key: &str, spent: u64) -> Result<u64, RateLimitError> {
+        if spent == 0 {
+            return Ok(0);
+        }
+        if self.primary.take_token(key, spent).await? {
+            return Ok(spent);
+        }
+        let remaining = match self.primary.inspect(key).await? {
+            Some(bucket) => bucket.remaining.min(spent),
+            None => return Ok(0),
+        };
+        if remaining > 0 && self.primary.take_token(key, remaining).await? {
+            return Ok(remaining);
+        }
+        Ok(0)
+    }
+
+    /// Run `operation` on the primary, or on the fallback when degraded or
+    /// when the primary fails with a storage error.
+    async fn either<'a, T, Fut>(
+        &'a self,
+        operation: impl Fn(&'a (dyn StorageBackend + 'a)) -> Fut,
+    ) -> Result<T, RateLimitError>
+    where
+        Fut: std::future::Future<Output = Result<T, RateLimitError>> + 'a,
+    {
+        if !self.use_fallback().await {
+            match operation(&self.primary).await {
+                Err(e @ RateLimitError::StorageError(_)) => self.fail_over(&e),
+                result => return result,
+            }
+        }
+        operation(&self.fallback).await
+    }
+}
+
+#[async_trait]
+impl<P: StorageBackend, F: StorageBackend> StorageBackend for FailoverBackend<P, F> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        if !self.use_fallback().await {
+            match self.primary.take_token_detailed(key, cost).await {
+                Err(e @ RateLimitError::StorageError(_)) => self.fail_over(&e),
+                result => return result,
+            }
+        }
+        let decision = self.fallback.take_token_detailed(key, cost).await?;
+        self.stats
+            .degraded_decisions
+            .fetch_add(1, Ordering::Relaxed);
+        if decision.allowed {
+            self.record(key, |pending| pending.spent += cost);
+        }
+        Ok(decision)
+    }
+
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        if !self.use_fallback().await {
+            match self.primary.take_all(entries).await {
+                Err(e @ RateLimitError::StorageError(_)) => self.fail_over(&e),
+                result => return result,
+            }
+        }
+        let decisions = self.fallback.take_all(entries).await?;
+        self.stats
+            .degraded_decisions
+            .fetch_add(1, Ordering::Relaxed);
+        if decisions.iter().all(|decision| decision.allowed) {
+            for &(key, cost) in entries {
+                self.record(key, |pending| pending.spent += cost);
+            }
+        }
+        Ok(decisions)
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        self.either(|backend| backend.get_usage(key)).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.either(|backend| backend.reset(key)).await?;
+        if self.is_degraded() {
+            self.record(key, |pending| {
+                *pending = Pending {
+                    reset: true,
+                    spent: 0,
+                }
+            });
+        } else {
+            // The fallback may still hold this key's bucket from an
+            // outage whose reconciliation failed.
+            let _ = self.fallback.reset(key).await;
+        }
+        Ok(())
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.either(|backend| backend.refund(key, tokens)).await?;
+        self.record(key, |pending| pending.spent = pending.spent.saturating_sub(tokens));
+        Ok(())
+    }
+
+    /// Leases are kept by the primary alone; the fallback decides without
+    /// them.
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.primary.acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.primary.renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.primary.return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.primary.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.primary.exchange_demand(key, node, demand, ttl).await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.primary.hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.primary.release_lock(name, holder).await
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.either(|backend| backend.inspect(key)).await
+    }
+
+    /// Healthy while either backend is. Probing the primary is also how
+    /// failures are noticed before traffic hits them, and recoveries
+    /// after.
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        match self.primary.health_check().await {
+            Ok(()) => {
+                self.recover().await;
+                Ok(())
+            }
+            Err(e) => {
+                self.fail_over(&e);
+                self.fallback.health_check().await
+            }
+        }
+    }
+
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        let (primary, _) = tokio::join!(self.primary.flush(), self.fallback.flush());
+        primary
+    }
+
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        self.either(|backend| backend.warm_up(key)).await
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.either(|backend| backend.list_keys(pattern, after, limit))
+            .await
+    }
+}
+
+// ============================================================================
+// CHAOS BACKEND (injected latency and failures for resilience testing)
+// ============================================================================
+
+/// What a [`ChaosBackend`] does to the calls passing through it. The
+/// default disturbs nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChaosConfig {
+    /// Added to every call before it reaches the backend.
+    pub latency: Duration,
+    /// Up to this much more latency, picked at random per call.
+    pub jitter: Duration,
+    /// Share of calls, from 0 to 1, failed without reaching the backend.
+    pub error_rate: f64,
+    /// Share of calls, from 0 to 1, that the backend carries out but that
+    /// fail anyway, as when a store applies a write and the reply is lost.
+    pub partial_failure_rate: f64,
+}
+
+/// Calls a [`ChaosBackend`] has seen and disturbed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    pub calls: u64,
+    pub injected_errors: u64,
+    pub partial_failures: u64,
+}
+
+/// Delays and fails calls to `inner` as configured, to watch fail-open,
+/// failover and circuit breaking respond to a misbehaving store in
+/// integration tests and game days. Injected failures are storage errors,
+/// like those of a store that can't be reached.
+///
+/// The disturbance can be changed while traffic flows with
+/// [`set_config`](Self::set_config), and stopped by setting the default.
+pub struct ChaosBackend<B: StorageBackend> {
+    inner: B,
+    config: RwLock<ChaosConfig>,
+    calls: AtomicU64,
+    injected_errors: AtomicU64,
+    partial_failures: AtomicU64,
+}
+
+impl<B: StorageBackend> ChaosBackend<B> {
+    pub fn new(inner: B, config: ChaosConfig) -> Self {
+        Self {
+            inner,
+            config: RwLock::new(config),
+            calls: AtomicU64::new(0),
+            injected_errors: AtomicU64::new(0),
+            partial_failures: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> ChaosConfig {
+        self.config.read().clone()
+    }
+
+    /// Disturb calls made from now on by `config` instead.
+    pub fn set_config(&self, config: ChaosConfig) {
+        *self.config.write() = config;
+    }
+
+    pub fn stats(&self) -> ChaosStats {
+        ChaosStats {
+            calls: self.calls.load(Ordering::Relaxed),
+            injected_errors: self.injected_errors.load(Ordering::Relaxed),
+            partial_failures: self.partial_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Run `call` on the backend, disturbed as configured.
+    async fn disturb<T>(
+        &self,
+        call: impl Future<Output = Result<T, RateLimitError>>,
+    ) -> Result<T, RateLimitError> {
+        let config = self.config();
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let delay = config.latency + config.jitter.mul_f64(uniform());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        if uniform() < config.error_rate {
+            self.injected_errors.fetch_add(1, Ordering::Relaxed);
+            return Err(RateLimitError::StorageError(
+                "chaos: injected error".to_string(),
+            ));
+        }
+        let result = call.await;
+        if result.is_ok() && uniform() < config.partial_failure_rate {
+            self.partial_failures.fetch_add(1, Ordering::Relaxed);
+            return Err(RateLimitError::StorageError(
+                "chaos: reply lost after the backend answered".to_string(),
+            ));
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend> StorageBackend for ChaosBackend<B> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        self.disturb(self.inner.take_token(key, cost)).await
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        self.disturb(self.inner.take_token_detailed(key, cost))
+            .await
+    }
+
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        self.disturb(self.inner.take_all(entries)).await
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        self.disturb(self.inner.get_usage(key)).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.disturb(self.inner.reset(key)).await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.disturb(self.inner.refund(key, tokens)).await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.disturb(self.inner.acquire_lease(key, id, tokens, ttl))
+            .await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.disturb(self.inner.renew_lease(key, id, ttl)).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.disturb(self.inner.return_lease(key, id, unused)).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.disturb(self.inner.leases(key)).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.disturb(self.inner.exchange_demand(key, node, demand, ttl))
+            .await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.disturb(self.inner.hold_lock(name, holder, ttl)).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.disturb(self.inner.release_lock(name, holder)).await
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.disturb(self.inner.inspect(key)).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.disturb(self.inner.health_check()).await
+    }
+
+    // Shutdown isn't disturbed: losing buffered state there proves nothing.
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        self.inner.flush().await
+    }
+
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        self.disturb(self.inner.warm_up(key)).await
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.disturb(self.inner.list_keys(pattern, after, limit))
+            .await
+    }
+
+    async fn top_keys(
+        &self,
+        n: usize,
+        ranking: KeyRanking,
+    ) -> Result<Vec<(String, KeyStats)>, RateLimitError> {
+        self.disturb(self.inner.top_keys(n, ranking)).await
+    }
+}
+// ============================================================================
+// KEY STATISTICS (per-key counters for operator visibility)
+// ============================================================================
+
+/// Counts allowed and denied requests per key over a rolling window, for
+/// `StorageBackend::top_keys`. Decisions are delegated unchanged.
+///
+/// Counts cover the current window plus the one before it, so "recent"
+/// means between one and two windows back.
+pub struct StatsBackend<B: StorageBackend> {
+    inner: B,
+    window: Duration,
+    stats: parking_lot::Mutex<HashMap<String, WindowedStats>>,
+}
+
+struct WindowedStats {
+    started: Instant,
+    current: KeyStats,
+    previous: KeyStats,
+}
+
+impl WindowedStats {
+    /// Roll the windows forward to `now`; returns false once both are empty.
+    fn advance(&mut self, now: Instant, window: Duration) -> bool {
+        let elapsed = now.duration_since(self.started);
+        if elapsed >= window {
+            self.previous = if elapsed < window * 2 {
+                self.current
+            } else {
+                KeyStats::default()
+            };
+            self.current = KeyStats::default();
+            self.started = now;
+        }
+        self.current != KeyStats::default() || self.previous != KeyStats::default()
+    }
+
+    fn recent(&self) -> KeyStats {
+        KeyStats {
+            allowed: self.current.allowed + self.previous.allowed,
+            denied: self.current.denied + self.previous.denied,
+            consumed: self.current.consumed + self.previous.consumed,
+        }
+    }
+}
+
+impl<B: StorageBackend> StatsBackend<B> {
+    pub fn new(inner: B, window: Duration) -> Self {
+        Self {
+            inner,
+            window,
+            stats: parking_lot::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keys with any activity in the last one to two windows.
+    pub fn active_keys(&self) -> usize {
+        let now = Instant::now();
+        let mut stats = self.stats.lock();
+        stats.retain(|_, entry| entry.advance(now, self.window));
+        stats.len()
+    }
+
+    fn record(&self, key: &str, cost: u64, allowed: bool) {
+        let now = Instant::now();
+        let mut stats = self.stats.lock();
+        let entry = stats
+            .entry(key.to_string())
+            .or_insert_with(|| WindowedStats {
+                started: now,
+                current: KeyStats::default(),
+                previous: KeyStats::default(),
+            });
+        entry.advance(now, self.window);
+
+        if allowed {
+            entry.current.allowed += 1;
+            entry.current.consumed += cost;
+        } else {
+            entry.current.denied += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend> StorageBackend for StatsBackend<B> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        let allowed = self.inner.take_token(key, cost).await?;
+        self.record(key, cost, allowed);
+        Ok(allowed)
+    }
+
+    /// Every entry counts as allowed only when they all were.
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        let decisions = self.inner.take_all(entries).await?;
+        let taken = decisions.iter().all(|decision| decision.allowed);
+        for &(key, cost) in entries {
+            self.record(key, cost, taken);
+        }
+        Ok(decisions)
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        self.inner.get_usage(key).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.inner.reset(key).await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.inner.refund(key, tokens).await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.inner.acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.inner.renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.inner.return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.inner.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.inner.exchange_demand(key, node, demand, ttl).await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.inner.hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.inner.release_lock(name, holder).await
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let decision = self.inner.take_token_detailed(key, cost).await?;
+        self.record(key, cost, decision.allowed);
+        Ok(decision)
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.inner.inspect(key).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.inner.health_check().await
+    }
+
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        self.inner.flush().await
+    }
+
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        self.inner.warm_up(key).await
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.inner.list_keys(pattern, after, limit).await
+    }
+
+    async fn top_keys(
+        &self,
+        n: usize,
+        ranking: KeyRanking,
+    ) -> Result<Vec<(String, KeyStats)>, RateLimitError> {
+        let now = Instant::now();
+        let mut stats = self.stats.lock();
+        // Ranking visits every key anyway, so idle ones are dropped here.
+        stats.retain(|_, entry| entry.advance(now, self.window));
+
+        let mut ranked: Vec<(String, KeyStats)> = stats
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.recent()))
+            .collect();
+        ranked.sort_by(|(a_key, a), (b_key, b)| {
+            let (a_score, b_score) = match ranking {
+                KeyRanking::Usage => (a.consumed, b.consumed),
+                KeyRanking::Denials => (a.denied, b.denied),
+            };
+            b_score.cmp(&a_score).then_with(|| a_key.cmp(b_key))
+        });
+        ranked.truncate(n);
+        Ok(ranked)
+    }
+}
+
+// ============================================================================
+// KEY-VALUE ADAPTER (token buckets on any store with compare-and-set)
+// ============================================================================
+
+/// A stored value with the version the store assigned to it (a revision,
+/// modify index, or counter; only equality matters).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvEntry {
+    pub value: Vec<u8>,
+    pub version: u64,
+}
+
+/// Minimal storage contract for `KvBackend`. Implement this to put token
+/// buckets in a store Guardian has no dedicated backend for.
+#[async_trait]
+pub trait KvStore: Send + Sync {
+    async fn get(&self, key: &str) -> Result<Option<KvEntry>, RateLimitError>;
+
+    /// Write `value` only if the key is still at `expected` (`None` means the
+    /// key must not exist). Returns `Ok(false)` when the condition fails.
+    async fn compare_and_set(
+        &self,
+        key: &str,
+        expected: Option<u64>,
+        value: Vec<u8>,
+    ) -> Result<bool, RateLimitError>;
+
+    /// Let the key be dropped after `ttl` without further writes. Stores
+    /// without expiry can make this a no-op.
+    async fn expire(&self, key: &str, ttl: Duration) -> Result<(), RateLimitError>;
+}
+
+/// Token-bucket algorithm over a `KvStore`, using optimistic
+/// read/compare-and-set retries.
+pub struct KvBackend<S: KvStore> {
+    store: S,
+    config: TokenBucketConfig,
+    ttl: Duration,
+}
+
+const KV_MAX_CAS_RETRIES: usize = 16;
+
+impl<S: KvStore> KvBackend<S> {
+    pub fn new(store: S, config: TokenBucketConfig) -> Self {
+        Self {
+            store,
+            config,
+            ttl: Duration::from_secs(3600),
+        }
+    }
+
+    /// How long idle buckets are kept (default one hour).
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    async fn load(&self, key: &str) -> Result<Option<(BucketState, u64)>, RateLimitError> {
+        match self.store.get(key).await? {
+            Some(entry) => Ok(Some((decode_bucket(&entry.value)?, entry.version))),
+            None => Ok(None),
+        }
+    }
+
+    /// Write `state` with CAS retries, recomputing it from the current
+    /// stored state each attempt. `update` returns `None` to skip the write.
+    async fn update<F>(&self, key: &str, mut update: F) -> Result<bool, RateLimitError>
+    where
+        F: FnMut(Option<BucketState>, u64) -> Option<BucketState> + Send,
+    {
+        for _ in 0..KV_MAX_CAS_RETRIES {
+            let now_ms = unix_millis();
+            let loaded = self.load(key).await?;
+            let version = loaded.as_ref().map(|(_, version)| *version);
+
+            let Some(state) = update(loaded.map(|(state, _)| state), now_ms) else {
+                return Ok(false);
+            };
+
+            if self
+                .store
+                .compare_and_set(key, version, encode_bucket(&state))
+                .await?
+            {
+                self.store.expire(key, self.ttl).await?;
+                return Ok(true);
+            }
+        }
+
+        Err(RateLimitError::StorageError(format!(
+            "compare-and-set lost {} times for key: {}",
+            KV_MAX_CAS_RETRIES, key
+        )))
+    }
+}
+
+#[async_trait]
+impl<S: KvStore> StorageBackend for KvBackend<S> {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let config = &self.config;
+        let mut remaining = 0;
+        let allowed = self
+            .update(key, |state, now_ms| {
+                let mut state = state.unwrap_or_else(|| BucketState::full(config, now_ms));
+                let allowed = state.try_consume(cost, config, now_ms);
+                remaining = state.tokens;
+                allowed.then_some(state)
+            })
+            .await?;
+        Ok(TokenDecision {
+            allowed,
+            bucket: Some(BucketSnapshot::new(config, remaining)),
+        })
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        match self.load(key).await? {
+            Some((mut state, _)) => {
+                state.refill(&self.config, unix_millis());
+                Ok(self.config.capacity.saturating_sub(state.tokens))
+            }
+            None => Ok(0),
+        }
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        Ok(Some(match self.load(key).await? {
+            Some((mut state, _)) => {
+                state.refill(&self.config, unix_millis());
+                BucketSnapshot::new(&self.config, state.tokens).with_last_refill(
+                    SystemTime::UNIX_EPOCH + Duration::from_millis(state.last_refill_ms),
+                )
+            }
+            None => BucketSnapshot::new(&self.config, self.config.capacity),
+        }))
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let config = &self.config;
+        self.update(key, |_, now_ms| Some(BucketState::full(config, now_ms)))
+            .await?;
+        Ok(())
+    }
+
+    /// A key with no stored bucket is full already; nothing is written.
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        let config = &self.config;
+        self.update(key, |state, now_ms| {
+            let mut state = state?;
+            state.refund(tokens, config, now_ms);
+            Some(state)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// The lock is stored at `name` as `<expiry in Unix ms>:<holder>`, and
+    /// taken or extended with one compare-and-set: losing a race to
+    /// another holder counts as not holding it.
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        let now_ms = unix_millis();
+        let entry = self.store.get(name).await?;
+        if let Some(entry) = &entry {
+            let (expires_ms, current) = decode_lock(&entry.value)?;
+            if current != holder && expires_ms > now_ms {
+                return Ok(false);
+            }
+        }
+        let value = format!("{}:{}", now_ms + ttl.as_millis() as u64, holder).into_bytes();
+        let version = entry.map(|entry| entry.version);
+        if !self.store.compare_and_set(name, version, value).await? {
+            return Ok(false);
+        }
+        self.store.expire(name, ttl).await?;
+        Ok(true)
+    }
+
+    /// Stores can't be asked to delete, so the lock is left lapsed.
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        let Some(entry) = self.store.get(name).await? else {
+            return Ok(());
+        };
+        if decode_lock(&entry.value)?.1 == holder {
+            let released = format!("0:{}", holder).into_bytes();
+            self.store
+                .compare_and_set(name, Some(entry.version), released)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn decode_lock(value: &[u8]) -> Result<(u64, &str), RateLimitError> {
+    let invalid = || RateLimitError::StorageError("stored lock value is malformed".to_string());
+
+    let text = std::str::from_utf8(value).map_err(|_| invalid())?;
+    let (expires_ms, holder) = text.split_once(':').ok_or_else(invalid)?;
+    Ok((expires_ms.parse().map_err(|_| invalid())?, holder))
+}
+
+fn encode_bucket(state: &BucketState) -> Vec<u8> {
+    format!("{}:{}", state.tokens, state.last_refill_ms).into_bytes()
+}
+
+fn decode_bucket(value: &[u8]) -> Result<BucketState, RateLimitError> {
+    let invalid = || RateLimitError::StorageError("stored bucket value is malformed".to_string());
+
+    let text = std::str::from_utf8(value).map_err(|_| invalid())?;
+    let (tokens, last_refill_ms) = text.split_once(':').ok_or_else(invalid)?;
+    Ok(BucketState {
+        tokens: tokens.parse().map_err(|_| invalid())?,
+        last_refill_ms: last_refill_ms.parse().map_err(|_| invalid())?,
+    })
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+// ============================================================================
+// RATE LIMITER FACADE
+// ============================================================================
+
+/// A short, stable stand-in for `key` in traces (FNV-1a, as hex), so a
+/// slow check can be followed across services without logging keys that
+/// may hold user ids or addresses.
+pub fn key_hash(key: &str) -> String {
+    format!("{:016x}", fnv1a(key))
+}
+
+fn fnv1a(key: &str) -> u64 {
+    key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Why a request was decided the way it was, as a stable code for logs,
+/// responses and alerting rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecisionReason {
+    /// The key's bucket had the tokens.
+    WithinLimit,
+    /// The key's bucket was short of tokens.
+    BucketExhausted,
+    /// The key's bucket had the tokens, but another key checked with it
+    /// all-or-nothing was short, so neither took any.
+    AllOrNothing,
+    /// A rule decided without consulting the bucket, such as an allow or
+    /// deny list entry.
+    RuleMatched,
+    /// The key is denied until a deadline, whatever its bucket holds.
+    PenaltyBox,
+    /// The bucket was exhausted, but the rule governing it is in shadow
+    /// mode, so the request went through.
+    ShadowRule,
+    /// The backend failed and the request was let through.
+    BackendErrorFailOpen,
+    /// The backend failed and the check failed with it.
+    BackendErrorFailClosed,
+}
+
+impl DecisionReason {
+    /// The reason's code, e.g. `bucket_exhausted`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DecisionReason::WithinLimit => "within_limit",
+            DecisionReason::BucketExhausted => "bucket_exhausted",
+            DecisionReason::AllOrNothing => "all_or_nothing",
+            DecisionReason::RuleMatched => "rule_matched",
+            DecisionReason::PenaltyBox => "penalty_box",
+            DecisionReason::ShadowRule => "shadow_rule",
+            DecisionReason::BackendErrorFailOpen => "backend_error_fail_open",
+            DecisionReason::BackendErrorFailClosed => "backend_error_fail_closed",
+        }
+    }
+}
+
+impl std::fmt::Display for DecisionReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One decision and its reason, as seen by [`Observer::on_event`].
+#[derive(Debug, Clone, Copy)]
+pub struct DecisionEvent<'a> {
+    pub key: &'a str,
+    pub cost: u64,
+    /// Whether the request went through; false when the check failed.
+    pub allowed: bool,
+    pub reason: DecisionReason,
+}
+
+/// Hook for reacting to a `RateLimiter`'s decisions as they happen. Called
+/// inline on the request path, so implementations must not block.
+pub trait Observer: Send + Sync {
+    /// `bucket` is the key's state after the decision, when the backend
+    /// reports it.
+    fn on_decision(&self, key: &str, result: &LimitResult, bucket: Option<&BucketSnapshot>);
+
+    /// The key's bucket was restored to full.
+    fn on_reset(&self, _key: &str) {}
+
+    /// A check of `cost` tokens from `key` was decided in `latency`, the
+    /// backend's time included. Called after `on_decision`.
+    fn on_check(&self, _key: &str, _cost: u64, _result: &LimitResult, _latency: Duration) {}
+
+    /// The backend failed to decide for `key`. The check then fails open,
+    /// or returns the error.
+    fn on_backend_error(&self, _key: &str, _error: &RateLimitError) {}
+
+    /// Every decision with its reason, fail-closed errors included, plus
+    /// those made around the limiter and reported with
+    /// [`RateLimiter::observe`].
+    fn on_event(&self, _event: &DecisionEvent<'_>) {}
+}
+
+/// Reports every decision through the [`metrics`](https://docs.rs/metrics)
+/// facade, under the names the Guardian service exports, so the same
+/// dashboards work for embedded limiters:
+///
+/// - `guardian_decisions_total` (counter; `decision`)
+/// - `guardian_tokens_consumed_total` (counter)
+/// - `guardian_backend_latency_seconds` (histogram; `operation`)
+/// - `guardian_backend_errors_total` (counter; `operation`)
+/// - `guardian_buckets` (gauge): distinct keys decided for
+///
+/// With the `metrics` feature, every `RateLimiter` starts with one.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default)]
+pub struct MetricsObserver {
+    keys: dashmap::DashSet<String>,
+}
+
+#[cfg(feature = "metrics")]
+impl Observer for MetricsObserver {
+    fn on_decision(&self, key: &str, _result: &LimitResult, _bucket: Option<&BucketSnapshot>) {
+        if !self.keys.contains(key) && self.keys.insert(key.to_string()) {
+            metrics::gauge!("guardian_buckets").set(self.keys.len() as f64);
+        }
+    }
+
+    fn on_check(&self, _key: &str, cost: u64, result: &LimitResult, latency: Duration) {
+        let decision = match result {
+            LimitResult::Allowed => {
+                metrics::counter!("guardian_tokens_consumed_total").increment(cost);
+                "allowed"
+            }
+            LimitResult::Denied { .. } => "denied",
+        };
+        metrics::counter!("guardian_decisions_total", "decision" => decision).increment(1);
+        metrics::histogram!("guardian_backend_latency_seconds", "operation" => "take_token")
+            .record(latency.as_secs_f64());
+    }
+
+    fn on_backend_error(&self, _key: &str, _error: &RateLimitError) {
+        metrics::counter!("guardian_backend_errors_total", "operation" => "take_token")
+            .increment(1);
+    }
+}
+
+/// Something that happened to a limiter or its backends, as published on
+/// an [`EventBus`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimiterEvent {
+    /// A check was denied, or failed with the backend while failing
+    /// closed.
+    Denied {
+        key: String,
+        cost: u64,
+        reason: DecisionReason,
+    },
+    /// A key's bucket was restored to full.
+    Reset { key: String },
+    /// A `RouterBackend` route was added or replaced while serving.
+    RouteSet { pattern: String },
+    /// A `RouterBackend` route was removed.
+    RouteRemoved { pattern: String },
+    /// A `RouterBackend`'s default backend was replaced.
+    DefaultSet,
+    /// A `FailoverBackend` gave up on its primary after `error`.
+    FailedOver { error: String },
+    /// A `FailoverBackend` returned to its primary, charging it the
+    /// tokens spent on the fallback.
+    Recovered {
+        degraded_for: Duration,
+        reconciled_tokens: u64,
+    },
+}
+
+/// Broadcast channel of [`LimiterEvent`]s, for alerting and logging
+/// pipelines of your own. Clones share the channel, so one bus can collect
+/// from a `RateLimiter` (see [`RateLimiter::with_events`]) and the
+/// `RouterBackend` and `FailoverBackend` under it.
+///
+/// Publishing never waits on subscribers. Events published while nobody
+/// is subscribed are dropped, and a subscriber that falls more than the
+/// bus's capacity behind loses the oldest, finding out how many from
+/// `RecvError::Lagged`.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<LimiterEvent>,
+}
+
+impl EventBus {
+    /// A bus holding up to `capacity` events for each subscriber.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self { sender }
+    }
+
+    /// Events published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<LimiterEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish(&self, event: LimiterEvent) {
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Default for EventBus {
+    /// Room for 1024 events per subscriber.
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+impl Observer for EventBus {
+    fn on_decision(&self, _key: &str, _result: &LimitResult, _bucket: Option<&BucketSnapshot>) {}
+
+    fn on_reset(&self, key: &str) {
+        self.publish(LimiterEvent::Reset {
+            key: key.to_string(),
+        });
+    }
+
+    fn on_event(&self, event: &DecisionEvent<'_>) {
+        if !event.allowed {
+            self.publish(LimiterEvent::Denied {
+                key: event.key.to_string(),
+                cost: event.cost,
+                reason: event.reason,
+            });
+        }
+    }
+}
+
+pub struct RateLimiter<B: StorageBackend> {
+    backend: Arc<B>,
+    fail_open: bool,
+    observers: Vec<Arc<dyn Observer>>,
+}
+
+impl<B: StorageBackend> RateLimiter<B> {
+    pub fn new(backend: B, fail_open: bool) -> Self {
+        #[cfg(feature = "metrics")]
+        let observers: Vec<Arc<dyn Observer>> = vec![Arc::new(MetricsObserver::default())];
+        #[cfg(not(feature = "metrics"))]
+        let observers = Vec::new();
+        Self {
+            backend: Arc::new(backend),
+            fail_open,
+            observers,
+        }
+    }
+
+    pub fn with_observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Publish denials and resets to `events`.
+    pub fn with_events(self, events: EventBus) -> Self {
+        self.with_observer(Arc::new(events))
+    }
+
+    pub async fn check_limit(
+        &self,
+        client_id: &str,
+        cost: u64,
+    ) -> Result<LimitResult, RateLimitError> {
+        let (result, _) = self.check_limit_detailed(client_id, cost).await?;
+        Ok(result)
+    }
+
+    /// `check_limit`, plus the bucket after the decision when the backend
+    /// reports it. Requests let through by failing open have no bucket.
+    pub async fn check_limit_detailed(
+        &self,
+        client_id: &str,
+        cost: u64,
+    ) -> Result<(LimitResult, Option<BucketSnapshot>), RateLimitError> {
+        let (result, bucket, _) = self.check_limit_explained(client_id, cost).await?;
+        Ok((result, bucket))
+    }
+
+    /// `check_limit_detailed`, plus why the request was decided that way.
+    pub async fn check_limit_explained(
+        &self,
+        client_id: &str,
+        cost: u64,
+    ) -> Result<(LimitResult, Option<BucketSnapshot>, DecisionReason), RateLimitError> {
+        let span = tracing::debug_span!(
+            "check_limit",
+            key_hash = %key_hash(client_id),
+            cost,
+            decision = tracing::field::Empty,
+            reason = tracing::field::Empty,
+            latency_us = tracing::field::Empty,
+        );
+        let started = Instant::now();
+        let decided = self.decide(client_id, cost).instrument(span.clone()).await;
+        let latency = started.elapsed();
+        span.record("latency_us", latency.as_micros() as u64);
+        let (result, bucket, reason) = match decided {
+            Ok(decided) => decided,
+            Err(e) => {
+                span.record("decision", "error");
+                span.record("reason", DecisionReason::BackendErrorFailClosed.as_str());
+                self.observe(&DecisionEvent {
+                    key: client_id,
+                    cost,
+                    allowed: false,
+                    reason: DecisionReason::BackendErrorFailClosed,
+                });
+                return Err(e);
+            }
+        };
+        span.record(
+            "decision",
+            match result {
+                LimitResult::Allowed => "allowed",
+                LimitResult::Denied { .. } => "denied",
+            },
+        );
+        span.record("reason", reason.as_str());
+        let event = DecisionEvent {
+            key: client_id,
+            cost,
+            allowed: result == LimitResult::Allowed,
+            reason,
+        };
+        for observer in &self.observers {
+            observer.on_decision(client_id, &result, bucket.as_ref());
+            observer.on_check(client_id, cost, &result, latency);
+            observer.on_event(&event);
+        }
+        Ok((result, bucket, reason))
+    }
+
+    /// Check `(client_id, cost)` pairs together: each takes its tokens or
+    /// none does, in one step of the backend (see
+    /// `StorageBackend::take_all`). Observers hear every entry's final
+    /// outcome, so one whose bucket had room is still reported denied when
+    /// another's didn't. Backends that can't take several keys at once fail
+    /// with `RateLimitError::Unsupported`, even when failing open.
+    pub async fn check_all(
+        &self,
+        checks: &[(&str, u64)],
+    ) -> Result<Vec<(LimitResult, Option<BucketSnapshot>, DecisionReason)>, RateLimitError> {
+        let span = tracing::debug_span!("check_all", entries = checks.len());
+        let started = Instant::now();
+        let taken = self.backend.take_all(checks).instrument(span).await;
+        let latency = started.elapsed();
+        let decided = match taken {
+            Ok(decisions) => all_or_nothing(checks, decisions),
+            Err(e @ RateLimitError::Unsupported(_)) => return Err(e),
+            Err(e) => {
+                for (client_id, cost) in checks {
+                    for observer in &self.observers {
+                        observer.on_backend_error(client_id, &e);
+                    }
+                    if !self.fail_open {
+                        self.observe(&DecisionEvent {
+                            key: client_id,
+                            cost: *cost,
+                            allowed: false,
+                            reason: DecisionReason::BackendErrorFailClosed,
+                        });
+                    }
+                }
+                if !self.fail_open {
+                    return Err(e);
+                }
+                tracing::warn!(error = %e, "rate limiter error, failing open");
+                checks
+                    .iter()
+                    .map(|_| {
+                        (
+                            LimitResult::Allowed,
+                            None,
+                            DecisionReason::BackendErrorFailOpen,
+                        )
+                    })
+                    .collect()
+            }
+        };
+        for (&(client_id, cost), (result, bucket, reason)) in checks.iter().zip(&decided) {
+            let event = DecisionEvent {
+                key: client_id,
+                cost,
+                allowed: *result == LimitResult::Allowed,
+                reason: *reason,
+            };
+            for observer in &self.observers {
+                observer.on_decision(client_id, result, bucket.as_ref());
+                observer.on_check(client_id, cost, result, latency);
+                observer.on_event(&event);
+            }
+        }
+        Ok(decided)
+    }
+
+    /// Report a decision made without the limiter (an allow list entry,
+    /// say) to its observers, so their events cover every request.
+    pub fn observe(&self, event: &DecisionEvent<'_>) {
+        for observer in &self.observers {
+            observer.on_event(event);
+        }
+    }
+
+    async fn decide(
+        &self,
+        client_id: &str,
+        cost: u64,
+    ) -> Result<(LimitResult, Option<BucketSnapshot>, DecisionReason), RateLimitError> {
+        match self.backend.take_token_detailed(client_id, cost).await {
+            Ok(decision) if decision.allowed => Ok((
+                LimitResult::Allowed,
+                decision.bucket,
+                DecisionReason::WithinLimit,
+            )),
+            Ok(decision) => Ok((
+                LimitResult::Denied {
+                    retry_after: retry_after(cost, decision.bucket.as_ref()),
+                },
+                decision.bucket,
+                DecisionReason::BucketExhausted,
+            )),
+            Err(e) => {
+                for observer in &self.observers {
+                    observer.on_backend_error(client_id, &e);
+                }
+                if self.fail_open {
+                    tracing::warn!(error = %e, key = client_id, "rate limiter error, failing open");
+                    Ok((
+                        LimitResult::Allowed,
+                        None,
+                        DecisionReason::BackendErrorFailOpen,
+                    ))
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    pub async fn get_usage(&self, client_id: &str) -> Result<u64, RateLimitError> {
+        self.backend.get_usage(client_id).await
+    }
+
+    pub async fn inspect(&self, client_id: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.backend.inspect(client_id).await
+    }
+
+    /// Restore a client's full allowance. Errors are returned even when
+    /// failing open, since an admin asked for this explicitly.
+    pub async fn reset(&self, client_id: &str) -> Result<(), RateLimitError> {
+        self.backend.reset(client_id).await?;
+        for observer in &self.observers {
+            observer.on_reset(client_id);
+        }
+        Ok(())
+    }
+
+    /// Give back tokens an allowed check took but the request didn't use.
+    pub async fn refund(&self, client_id: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.backend.refund(client_id, tokens).await
+    }
+
+    /// Lease `tokens` for another node to spend until `ttl` passes; see
+    /// `StorageBackend::acquire_lease`.
+    pub async fn acquire_lease(
+        &self,
+        client_id: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backend.acquire_lease(client_id, id, tokens, ttl).await
+    }
+
+    pub async fn renew_lease(
+        &self,
+        client_id: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.backend.renew_lease(client_id, id, ttl).await
+    }
+
+    pub async fn return_lease(
+        &self,
+        client_id: &str,
+        id: u64,
+        unused: u64,
+    ) -> Result<u64, RateLimitError> {
+        self.backend.return_lease(client_id, id, unused).await
+    }
+
+    pub async fn leases(&self, client_id: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.backend.leases(client_id).await
+    }
+
+    pub async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.backend.health_check().await
+    }
+
+    pub async fn flush(&self) -> Result<(), RateLimitError> {
+        self.backend.flush().await
+    }
+
+    pub async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.backend.list_keys(pattern, after, limit).await
+    }
+
+    pub async fn top_keys(
+        &self,
+        n: usize,
+        ranking: KeyRanking,
+    ) -> Result<Vec<(String, KeyStats)>, RateLimitError> {
+        self.backend.top_keys(n, ranking).await
+    }
+}
+
+/// The outcome of each entry of a `take_all`. When any was short, all are
+/// denied until the slowest of those has refilled.
+fn all_or_nothing(
+    checks: &[(&str, u64)],
+    decisions: Vec<TokenDecision>,
+) -> Vec<(LimitResult, Option<BucketSnapshot>, DecisionReason)> {
+    let wait = checks
+        .iter()
+        .zip(&decisions)
+        .filter(|(_, decision)| !decision.allowed)
+        .map(|((_, cost), decision)| retry_after(*cost, decision.bucket.as_ref()))
+        .max();
+    decisions
+        .into_iter()
+        .map(|decision| match wait {
+            None => (
+                LimitResult::Allowed,
+                decision.bucket,
+                DecisionReason::WithinLimit,
+            ),
+            Some(retry_after) => (
+                LimitResult::Denied { retry_after },
+                decision.bucket,
+                if decision.allowed {
+                    DecisionReason::AllOrNothing
+                } else {
+                    DecisionReason::BucketExhausted
+                },
+            ),
+        })
+        .collect()
+}
+
+/// How long until `bucket` has refilled enough for `cost`. A second when
+/// the backend didn't report the bucket, or it never refills.
+fn retry_after(cost: u64, bucket: Option<&BucketSnapshot>) -> Duration {
+    match bucket {
+        Some(bucket) if bucket.refill_rate > 0 => {
+            let missing = cost.saturating_sub(bucket.remaining).max(1);
+            Duration::from_secs_f64(missing as f64 / bucket.refill_rate as f64)
+        }
+        _ => Duration::from_secs(1),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LimitResult {
+    Allowed,
+    Denied { retry_after: Duration },
+}
+
+// ============================================================================
+// REFUND GRANTS (the right to give back what a check took)
+// ============================================================================
+
+/// How long a grant can be redeemed by default.
+const DEFAULT_GRANT_TTL: Duration = Duration::from_secs(300);
+
+/// Live grants a ledger holds at most by default.
+const DEFAULT_GRANT_CAPACITY: usize = 100_000;
+
+/// Grants for the tokens allowed checks took, so a refund can only give
+/// back what a check actually charged.
+///
+/// [`issue`](Self::issue) records a charge under a random id to hand to
+/// the caller; [`redeem`](Self::redeem) accepts that id once, for the same
+/// key, and never for more tokens than were charged. Grants lapse after
+/// their ttl, and while the ledger is full of live ones no more are issued.
+/// Grants live in memory, so only the process that issued one can redeem
+/// it.
+pub struct GrantLedger {
+    grants: parking_lot::Mutex<Grants>,
+    ttl: Duration,
+    capacity: usize,
+    clock: Arc<dyn Clock>,
+}
+
+/// Live grants by id, and their ids in the order they lapse, so lapsed
+/// ones are dropped without looking at the rest.
+#[derive(Default)]
+struct Grants {
+    by_id: HashMap<String, Grant>,
+    by_expiry: BTreeSet<(SystemTime, String)>,
+}
+
+impl Grants {
+    fn lapse(&mut self, now: SystemTime) {
+        while let Some(first) = self.by_expiry.first() {
+            if first.0 > now {
+                break;
+            }
+            if let Some((_, id)) = self.by_expiry.pop_first() {
+                self.by_id.remove(&id);
+            }
+        }
+    }
+}
+
+struct Grant {
+    key: String,
+    tokens: u64,
+    expires_at: SystemTime,
+}
+
+impl GrantLedger {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            grants: parking_lot::Mutex::default(),
+            ttl,
+            capacity,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// A grant for `tokens` taken from `key`, or `None` if the ledger is
+    /// full.
+    pub fn issue(&self, key: &str, tokens: u64) -> Option<String> {
+        let now = self.clock.now();
+        let mut grants = self.grants.lock();
+        grants.lapse(now);
+        if grants.by_id.len() >= self.capacity {
+            return None;
+        }
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let expires_at = now + self.ttl;
+        grants.by_expiry.insert((expires_at, id.clone()));
+        grants.by_id.insert(
+            id.clone(),
+            Grant {
+                key: key.to_string(),
+                tokens,
+                expires_at,
+            },
+        );
+        Some(id)
+    }
+
+    /// Use up grant `id` on `key`, returning how many tokens to give back:
+    /// `tokens`, or all the grant's if that is 0, and never more than it
+    /// was issued for. `None` for an unknown, lapsed or already redeemed
+    /// grant, or one issued for another key.
+    pub fn redeem(&self, id: &str, key: &str, tokens: u64) -> Option<u64> {
+        let mut grants = self.grants.lock();
+        if grants.by_id.get(id)?.key != key {
+            return None;
+        }
+        let grant = grants.by_id.remove(id)?;
+        grants.by_expiry.remove(&(grant.expires_at, id.to_string()));
+        if grant.expires_at <= self.clock.now() {
+            return None;
+        }
+        Some(match tokens {
+            0 => grant.tokens,
+            tokens => tokens.min(grant.tokens),
+        })
+    }
+}
+
+impl Default for GrantLedger {
+    fn default() -> Self {
+        Self::new(DEFAULT_GRANT_TTL, DEFAULT_GRANT_CAPACITY)
+    }
+}
+
+// ============================================================================
+// STREAM THROTTLING (pacing consumers by a bucket)
+// ============================================================================
+
+/// What a [`Throttled`] stream does with an item its key has no tokens for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleMode {
+    /// Hold it until the tokens are there, pacing the stream.
+    Delay,
+    /// Skip it and move on to the next item.
+    Drop,
+}
+
+/// Rate limiting for any `Stream`, such as a queue consumer or an event
+/// feed.
+pub trait ThrottledStreamExt: Stream + Sized {
+    /// Take `cost_fn(&item)` tokens from `key` for each item before
+    /// yielding it, delaying items until their tokens are there (see
+    /// [`Throttled::with_mode`] to drop them instead).
+    fn guardian_throttle<B, F>(
+        self,
+        limiter: Arc<RateLimiter<B>>,
+        key: impl Into<String>,
+        cost_fn: F,
+    ) -> Throttled<Self, B, F>
+    where
+        B: StorageBackend + 'static,
+        F: FnMut(&Self::Item) -> u64,
+    {
+        Throttled {
+            stream: Box::pin(self),
+            limiter,
+            key: key.into().into(),
+            cost_fn,
+            mode: ThrottleMode::Delay,
+            pending: None,
+        }
+    }
+}
+
+impl<S: Stream> ThrottledStreamExt for S {}
+
+type Admission<T> = Pin<Box<dyn Future<Output = Option<T>> + Send>>;
+
+/// The stream returned by [`ThrottledStreamExt::guardian_throttle`].
+///
+/// An item costing more than the bucket holds is dropped in either mode,
+/// since waiting would never admit it. While a fail-closed limiter's
+/// backend is down, items are treated as denied.
+pub struct Throttled<S: Stream, B: StorageBackend, F> {
+    stream: Pin<Box<S>>,
+    limiter: Arc<RateLimiter<B>>,
+    key: Arc<str>,
+    cost_fn: F,
+    mode: ThrottleMode,
+    /// The item being admitted.
+    pending: Option<Admission<S::Item>>,
+}
+
+// Nothing is pinned in place: the stream and pending admission are boxed,
+// and the cost function is only ever called through `&mut`.
+impl<S: Stream, B: StorageBackend, F> Unpin for Throttled<S, B, F> {}
+
+impl<S: Stream, B: StorageBackend, F> Throttled<S, B, F> {
+    pub fn with_mode(mut self, mode: ThrottleMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+
+impl<S, B, F> Stream for Throttled<S, B, F>
+where
+    S: Stream,
+    S::Item: Send + 'static,
+    B: StorageBackend + 'static,
+    F: FnMut(&S::Item) -> u64,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(pending) = &mut this.pending {
+                let admitted = ready!(pending.as_mut().poll(cx));
+                this.pending = None;
+                match admitted {
+                    Some(item) => return Poll::Ready(Some(item)),
+                    None => continue,
+                }
+            }
+            let Some(item) = ready!(this.stream.as_mut().poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+            let cost = (this.cost_fn)(&item);
+            this.pending = Some(Box::pin(admit(
+                Arc::clone(&this.limiter),
+                Arc::clone(&this.key),
+                cost,
+                this.mode,
+                item,
+            )));
+        }
+    }
+}
+
+/// `item`, once `key` has `cost` tokens for it; `None` if it was dropped.
+async fn admit<B: StorageBackend, T>(
+    limiter: Arc<RateLimiter<B>>,
+    key: Arc<str>,
+    cost: u64,
+    mode: ThrottleMode,
+    item: T,
+) -> Option<T> {
+    loop {
+        let retry_after = match limiter.check_limit_detailed(&key, cost).await {
+            Ok((LimitResult::Allowed, _)) => return Some(item),
+            Ok((_, Some(bucket))) if cost > bucket.capacity => {
+                tracing::warn!(key = &*key, cost, "item costs more than its bucket");
+                return None;
+            }
+            Ok((LimitResult::Denied { retry_after }, _)) => retry_after,
+            Err(e) => {
+                tracing::warn!(error = %e, key = &*key, "rate limiter error while throttling");
+                Duration::from_secs(1)
+            }
+        };
+        if mode == ThrottleMode::Drop {
+            return None;
+        }
+        tokio::time::sleep(retry_after).await;
+    }
+}
+
+// ============================================================================
+// BANDWIDTH LIMITING (AsyncRead / AsyncWrite charged per byte)
+// ============================================================================
+
+/// Bytes asked for at once when the wrapper isn't told otherwise.
+pub const DEFAULT_CHUNK_SIZE: u64 = 16 * 1024;
+
+/// Tokens taken for a stream and not yet spent on bytes.
+struct ByteBudget<B: StorageBackend> {
+    limiter: Arc<RateLimiter<B>>,
+    key: Arc<str>,
+    chunk: u64,
+    credit: u64,
+    pending: Option<Pin<Box<dyn Future<Output = io::Result<u64>> + Send>>>,
+}
+
+impl<B: StorageBackend + 'static> ByteBudget<B> {
+    fn new(limiter: Arc<RateLimiter<B>>, key: String) -> Self {
+        Self {
+            limiter,
+            key: key.into(),
+            chunk: DEFAULT_CHUNK_SIZE,
+            credit: 0,
+            pending: None,
+        }
+    }
+
+    /// How many of `wanted` bytes may go now, taking another chunk's tokens
+    /// when the credit is spent.
+    fn poll_credit(&mut self, cx: &mut Context<'_>, wanted: usize) -> Poll<io::Result<usize>> {
+        if wanted == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        if self.credit == 0 {
+            let cost = self.chunk.min(wanted as u64);
+            let pending = self.pending.get_or_insert_with(|| {
+                Box::pin(take_bytes(
+                    Arc::clone(&self.limiter),
+                    Arc::clone(&self.key),
+                    cost,
+                ))
+            });
+            let granted = ready!(pending.as_mut().poll(cx));
+            self.pending = None;
+            let granted = granted?;
+            // Less than asked means the chunk didn't fit the bucket.
+            if granted < cost {
+                self.chunk = granted;
+            }
+            self.credit = granted;
+        }
+        Poll::Ready(Ok(self.credit.min(wanted as u64) as usize))
+    }
+
+    fn spend(&mut self, bytes: usize) {
+        self.credit -= bytes as u64;
+    }
+}
+
+/// Take `cost` tokens from `key`, or as many as its bucket holds when that
+/// is fewer, waiting for them to refill.
+async fn take_bytes<B: StorageBackend>(
+    limiter: Arc<RateLimiter<B>>,
+    key: Arc<str>,
+    mut cost: u64,
+) -> io::Result<u64> {
+    loop {
+        let (result, bucket) = limiter
+            .check_limit_detailed(&key, cost)
+            .await
+            .map_err(io::Error::other)?;
+        let retry_after = match result {
+            LimitResult::Allowed => return Ok(cost),
+            LimitResult::Denied { retry_after } => retry_after,
+        };
+        let wait = match bucket {
+            Some(bucket) if cost > bucket.capacity => {
+                cost = bucket.capacity.max(1);
+                continue;
+            }
+            // Sleep until the missing tokens have refilled, rather than a
+            // whole retry period, so transfers flow evenly.
+            Some(bucket) if bucket.refill_rate > 0 => Duration::from_secs_f64(
+                cost.saturating_sub(bucket.remaining) as f64 / bucket.refill_rate as f64,
+            )
+            .min(retry_after),
+            _ => retry_after,
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// An `AsyncRead` that takes a token from `key` for each byte read, so
+/// every reader sharing the key shares one bandwidth cap.
+///
+/// Tokens are taken a chunk at a time, ahead of the reads they pay for;
+/// a stream that ends mid-chunk leaves the rest unspent.
+pub struct ThrottledRead<R, B: StorageBackend> {
+    inner: R,
+    budget: ByteBudget<B>,
+}
+
+impl<R, B: StorageBackend + 'static> ThrottledRead<R, B> {
+    pub fn new(inner: R, limiter: Arc<RateLimiter<B>>, key: impl Into<String>) -> Self {
+        Self {
+            inner,
+            budget: ByteBudget::new(limiter, key.into()),
+        }
+    }
+
+    /// Bytes paid for per call to the limiter; [`DEFAULT_CHUNK_SIZE`]
+    /// unless set. Smaller chunks pace more smoothly for more calls.
+    pub fn with_chunk_size(mut self, bytes: u64) -> Self {
+        self.budget.chunk = bytes.max(1);
+        self
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R, B> AsyncRead for ThrottledRead<R, B>
+where
+    R: AsyncRead + Unpin,
+    B: StorageBackend + 'static,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let allowed = ready!(this.budget.poll_credit(cx, buf.remaining()))?;
+        let mut limited = buf.take(allowed);
+        let filled = limited.filled().as_ptr();
+        ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+        assert_eq!(filled, limited.filled().as_ptr(), "reader swapped buffers");
+        let read = limited.filled().len();
+        // SAFETY: the reader initialized `read` bytes of `buf`'s unfilled
+        // part, which `limited` views.
+        unsafe { buf.assume_init(read) };
+        buf.advance(read);
+        this.budget.spend(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// An `AsyncWrite` that takes a token from `key` for each byte written;
+/// the writing side of [`ThrottledRead`].
+pub struct ThrottledWrite<W, B: StorageBackend> {
+    inner: W,
+    budget: ByteBudget<B>,
+}
+
+impl<W, B: StorageBackend + 'static> ThrottledWrite<W, B> {
+    pub fn new(inner: W, limiter: Arc<RateLimiter<B>>, key: impl Into<String>) -> Self {
+        Self {
+            inner,
+            budget: ByteBudget::new(limiter, key.into()),
+        }
+    }
+
+    /// Bytes paid for per call to the limiter; [`DEFAULT_CHUNK_SIZE`]
+    /// unless set.
+    pub fn with_chunk_size(mut self, bytes: u64) -> Self {
+        self.budget.chunk = bytes.max(1);
+        self
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W, B> AsyncWrite for ThrottledWrite<W, B>
+where
+    W: AsyncWrite + Unpin,
+    B: StorageBackend + 'static,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let allowed = ready!(this.budget.poll_credit(cx, buf.len()))?;
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..allowed]))?;
+        this.budget.spend(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket_basic() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 5,
+            refill_interval: Duration::from_secs(1),
+        };
+        let bucket = TokenBucket::new(config);
+
+        assert!(bucket.try_consume(5).is_ok());
+        assert!(bucket.try_consume(5).is_ok());
+        assert!(bucket.try_consume(1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_detailed_check_reports_remaining_tokens() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(60),
+        };
+        let limiter = RateLimiter::new(MemoryBackend::new(config), false);
+
+        let (result, bucket) = limiter.check_limit_detailed("user1", 4).await.unwrap();
+        assert_eq!(result, LimitResult::Allowed);
+        let bucket = bucket.unwrap();
+        assert_eq!(
+            (bucket.capacity, bucket.remaining, bucket.refill_rate),
+            (10, 6, 1)
+        );
+
+        let (result, bucket) = limiter.check_limit_detailed("user1", 7).await.unwrap();
+        assert!(matches!(result, LimitResult::Denied { .. }));
+        assert_eq!(bucket.unwrap().remaining, 6);
+        assert_eq!(
+            limiter.inspect("user1").await.unwrap().unwrap().remaining,
+            6
+        );
+    }
+
+    #[tokio::test]
+    async fn test_observers_see_decisions_and_resets() {
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Vec<String>>);
+
+        impl Observer for Recorder {
+            fn on_decision(
+                &self,
+                key: &str,
+                result: &LimitResult,
+                bucket: Option<&BucketSnapshot>,
+            ) {
+                let remaining = bucket.map(|bucket| bucket.remaining);
+                self.0.lock().unwrap().push(format!(
+                    "{}:{:?}:{:?}",
+                    key,
+                    result == &LimitResult::Allowed,
+                    remaining
+                ));
+            }
+
+            fn on_reset(&self, key: &str) {
+                self.0.lock().unwrap().push(format!("{}:reset", key));
+            }
+        }
+
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(60),
+        };
+        let recorder = Arc::new(Recorder::default());
+        let limiter = RateLimiter::new(MemoryBackend::new(config), false)
+            .with_observer(Arc::clone(&recorder) as Arc<dyn Observer>);
+
+        limiter.check_limit("user1", 3).await.unwrap();
+        limiter.check_limit("user1", 3).await.unwrap();
+        limiter.reset("user1").await.unwrap();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec!["user1:true:Some(2)", "user1:false:Some(2)", "user1:reset"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_observers_see_costs_and_backend_errors() {
+        #[derive(Default)]
+        struct Recorder(std::sync::Mutex<Vec<String>>);
+
+        impl Observer for Recorder {
+            fn on_decision(&self, _: &str, _: &LimitResult, _: Option<&BucketSnapshot>) {}
+
+            fn on_check(&self, key: &str, cost: u64, result: &LimitResult, _: Duration) {
+                let allowed = result == &LimitResult::Allowed;
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}:{}:{}", key, cost, allowed));
+            }
+
+            fn on_backend_error(&self, key: &str, _: &RateLimitError) {
+                self.0.lock().unwrap().push(format!("{}:error", key));
+            }
+        }
+
+        let outage = Outage {
+            inner: MemoryBackend::new(TokenBucketConfig::default()),
+            down: std::sync::atomic::AtomicBool::new(false),
+        };
+        let recorder = Arc::new(Recorder::default());
+        let limiter = RateLimiter::new(outage, true)
+            .with_observer(Arc::clone(&recorder) as Arc<dyn Observer>);
+
+        limiter.check_limit("user1", 3).await.unwrap();
+        limiter.backend.down.store(true, Ordering::Relaxed);
+        // Failed open: the error, then the decision it became.
+        limiter.check_limit("user1", 2).await.unwrap();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec!["user1:3:true", "user1:error", "user1:2:true"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_events_carry_decision_reasons() {
+        #[derive(Default)]
+        struct Reasons(std::sync::Mutex<Vec<&'static str>>);
+
+        impl Observer for Reasons {
+            fn on_decision(&self, _: &str, _: &LimitResult, _: Option<&BucketSnapshot>) {}
+
+            fn on_event(&self, event: &DecisionEvent<'_>) {
+                self.0.lock().unwrap().push(event.reason.as_str());
+            }
+        }
+
+        let config = TokenBucketConfig {
+            capacity: 5,
+            ..TokenBucketConfig::default()
+        };
+        let outage = Outage {
+            inner: MemoryBackend::new(config),
+            down: std::sync::atomic::AtomicBool::new(false),
+        };
+        let reasons = Arc::new(Reasons::default());
+        let limiter = RateLimiter::new(outage, false)
+            .with_observer(Arc::clone(&reasons) as Arc<dyn Observer>);
+
+        let (_, _, reason) = limiter.check_limit_explained("user1", 5).await.unwrap();
+        assert_eq!(reason, DecisionReason::WithinLimit);
+        limiter.check_limit("user1", 1).await.unwrap();
+        limiter.backend.down.store(true, Ordering::Relaxed);
+        assert!(limiter.check_limit("user1", 1).await.is_err());
+        limiter.observe(&DecisionEvent {
+            key: "user2",
+            cost: 1,
+            allowed: true,
+            reason: DecisionReason::RuleMatched,
+        });
+        assert_eq!(
+            *reasons.0.lock().unwrap(),
+            vec![
+                "within_limit",
+                "bucket_exhausted",
+                "backend_error_fail_closed",
+                "rule_matched"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend() {
+        let config = TokenBucketConfig::default();
+        let backend = MemoryBackend::new(config);
+
+        assert!(backend.take_token("user1", 10).await.unwrap());
+        assert!(backend.take_token("user1", 50).await.unwrap());
+        assert!(!backend.take_token("user1", 50).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_memory_take_all_takes_every_key_or_none() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(60),
+        };
+        let backend = MemoryBackend::new(config);
+        let allowed = |decisions: Vec<TokenDecision>| -> Vec<bool> {
+            decisions.iter().map(|decision| decision.allowed).collect()
+        };
+
+        let denied = backend.take_all(&[("a", 4), ("b", 11)]).await.unwrap();
+        assert_eq!(allowed(denied), vec![true, false]);
+        assert_eq!(backend.get_usage("a").await.unwrap(), 0);
+
+        let taken = backend
+            .take_all(&[("a", 4), ("a", 4), ("b", 2)])
+            .await
+            .unwrap();
+        assert_eq!(taken[2].bucket.unwrap().remaining, 8);
+        assert_eq!(allowed(taken), vec![true, true, true]);
+        assert_eq!(backend.get_usage("a").await.unwrap(), 8);
+
+        // A key listed twice needs both costs at once.
+        let denied = backend.take_all(&[("a", 2), ("a", 1)]).await.unwrap();
+        assert_eq!(allowed(denied), vec![false, false]);
+        assert_eq!(backend.get_usage("a").await.unwrap(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_check_all_reports_final_outcomes() {
+        #[derive(Default)]
+        struct Events(std::sync::Mutex<Vec<String>>);
+
+        impl Observer for Events {
+            fn on_decision(&self, _: &str, _: &LimitResult, _: Option<&BucketSnapshot>) {}
+
+            fn on_event(&self, event: &DecisionEvent<'_>) {
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}:{}:{}", event.key, event.allowed, event.reason));
+            }
+        }
+
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(60),
+        };
+        let router = RouterBackend::new(MemoryBackend::new(config.clone()))
+            .route("internal:*", MemoryBackend::new(config));
+        let events = Arc::new(Events::default());
+        let limiter =
+            RateLimiter::new(router, true).with_observer(Arc::clone(&events) as Arc<dyn Observer>);
+
+        let decided = limiter.check_all(&[("a", 2), ("b", 6)]).await.unwrap();
+        // The whole check waits for the short bucket.
+        assert_eq!(
+            decided[0].0,
+            LimitResult::Denied {
+                retry_after: Duration::from_secs(1)
+            }
+        );
+        assert_eq!(decided[0].2, DecisionReason::AllOrNothing);
+        assert_eq!(decided[1].2, DecisionReason::BucketExhausted);
+        limiter.check_all(&[("a", 2), ("b", 5)]).await.unwrap();
+        assert_eq!(limiter.get_usage("a").await.unwrap(), 2);
+
+        // Keys in different backends can't be taken in one step, and that
+        // isn't failed open.
+        let split = limiter.check_all(&[("a", 1), ("internal:job", 1)]).await;
+        assert!(matches!(split, Err(RateLimitError::Unsupported(_))));
+        assert_eq!(
+            *events.0.lock().unwrap(),
+            vec![
+                "a:false:all_or_nothing",
+                "b:false:bucket_exhausted",
+                "a:true:within_limit",
+                "b:true:within_limit",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_fail_open() {
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        };
+        let backend = MemoryBackend::new(config);
+        let limiter = RateLimiter::new(backend, true);
+
+        for _ in 0..5 {
+            assert_eq!(
+                limiter.check_limit("user1", 1).await.unwrap(),
+                LimitResult::Allowed
+            );
+        }
+
+        let result = limiter.check_limit("user1", 1).await.unwrap();
+        assert!(matches!(result, LimitResult::Denied { .. }));
+
+        limiter.reset("user1").await.unwrap();
+        assert_eq!(
+            limiter.check_limit("user1", 5).await.unwrap(),
+            LimitResult::Allowed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_denials_retry_once_the_deficit_refills() {
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 2,
+            refill_interval: Duration::from_secs(1),
+        };
+        let limiter = RateLimiter::new(MemoryBackend::new(config.clone()), false);
+        assert_eq!(
+            limiter.check_limit("user1", 5).await.unwrap(),
+            LimitResult::Allowed
+        );
+
+        // 4 tokens short at 2 a second.
+        let denied = limiter.check_limit("user1", 4).await.unwrap();
+        assert_eq!(
+            denied,
+            LimitResult::Denied {
+                retry_after: Duration::from_secs(2)
+            }
+        );
+
+        /// Reports no bucket with its decisions.
+        struct Opaque(MemoryBackend);
+
+        #[async_trait]
+        impl StorageBackend for Opaque {
+            async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+                self.0.take_token(key, cost).await
+            }
+            async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+                self.0.get_usage(key).await
+            }
+            async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+                self.0.reset(key).await
+            }
+        }
+
+        // Without a bucket there is nothing to go on but a second.
+        let limiter = RateLimiter::new(Opaque(MemoryBackend::new(config)), false);
+        assert_eq!(
+            limiter.check_limit("user1", 5).await.unwrap(),
+            LimitResult::Allowed
+        );
+        assert_eq!(
+            limiter.check_limit("user1", 4).await.unwrap(),
+            LimitResult::Denied {
+                retry_after: Duration::from_secs(1)
+            }
+        );
+    }
+
+    #[test]
+    fn test_bucket_state_keeps_fractional_refill() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 4,
+            refill_interval: Duration::from_secs(1),
+        };
+        let mut state = BucketState {
+            tokens: 0,
+            last_refill_ms: 0,
+        };
+
+        // 300ms at 4 tokens/sec is 1.2 tokens: one credited, 0.2 carried.
+        assert!(state.try_consume(1, &config, 300));
+        assert!(!state.try_consume(1, &config, 450));
+        assert!(state.try_consume(1, &config, 500));
+
+        state.refill(&config, 60_000);
+        assert_eq!(state.tokens, 10);
+        assert_eq!(state.last_refill_ms, 60_000);
+    }
+
+    #[tokio::test]
+    async fn test_tiered_backend_never_exceeds_l2() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        };
+        let tiered = TieredBackend::new(
+            MemoryBackend::new(config),
+            TieredConfig {
+                slice_size: 4,
+                max_staleness: Duration::from_secs(60),
+                prefetch_below: 0,
+            },
+        );
+
+        assert!(tiered.take_token("user1", 1).await.unwrap());
+        assert_eq!(tiered.get_usage("user1").await.unwrap(), 4);
+
+        let mut allowed = 1;
+        while tiered.take_token("user1", 1).await.unwrap() {
+            allowed += 1;
+        }
+        assert_eq!(allowed, 10);
+    }
+
+    #[tokio::test]
+    async fn test_local_layers_report_the_bucket() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 0,
+            refill_interval: Duration::from_secs(1),
+        };
+        let memory = || MemoryBackend::new(config.clone());
+        let layers: Vec<(&str, Box<dyn StorageBackend>)> = vec![
+            ("batching", Box::new(BatchingBackend::new(memory(), 4))),
+            (
+                "leasing",
+                Box::new(LeasingBackend::new(
+                    memory(),
+                    LeaseConfig {
+                        size: 4,
+                        ttl: Duration::from_secs(60),
+                    },
+                )),
+            ),
+            (
+                "tiered",
+                Box::new(TieredBackend::new(
+                    memory(),
+                    TieredConfig {
+                        slice_size: 4,
+                        max_staleness: Duration::from_secs(60),
+                        prefetch_below: 0,
+                    },
+                )),
+            ),
+            (
+                "affinity",
+                Box::new(AffinityBackend::new(
+                    memory(),
+                    config.clone(),
+                    HashRing::new(["a:50051"]),
+                    "a:50051",
+                    Duration::from_secs(60),
+                )),
+            ),
+        ];
+        for (name, layer) in layers {
+            // Two local batches' worth, each check seeing the tokens held
+            // locally as well as the shared bucket's.
+            for taken in 1..=8 {
+                let decision = layer.take_token_detailed("user1", 1).await.unwrap();
+                assert!(decision.allowed, "{} check {}", name, taken);
+                let bucket = decision
+                    .bucket
+                    .unwrap_or_else(|| panic!("{} has no bucket", name));
+                assert_eq!(
+                    (bucket.capacity, bucket.remaining),
+                    (10, 10 - taken),
+                    "{} check {}",
+                    name,
+                    taken
+                );
+            }
+        }
+
+        // Estimates have no bucket of their own; the limit stands in.
+        let estimating = EstimatingBackend::new(
+            memory(),
+            TokenBucketConfig {
+                refill_rate: 10,
+                ..config
+            },
+            "a",
+            EstimateConfig::default(),
+        );
+        let decision = estimating.take_token_detailed("user1", 1).await.unwrap();
+        let bucket = decision.bucket.unwrap();
+        assert_eq!((bucket.capacity, bucket.refill_rate), (10, 10));
+        assert!(bucket.remaining < 10);
+    }
+
+    /// Delays every `take_token` on the wrapped backend.
+    struct SlowTakes {
+        inner: MemoryBackend,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl StorageBackend for SlowTakes {
+        async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+            tokio::time::sleep(self.delay).await;
+            self.inner.take_token(key, cost).await
+        }
+
+        async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+            self.inner.get_usage(key).await
+        }
+
+        async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+            self.inner.reset(key).await
+        }
+
+        async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+            self.inner.refund(key, tokens).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tiered_backend_sweeps_stale_slices() {
+        let tiered = TieredBackend::new(
+            MemoryBackend::new(TokenBucketConfig::default()),
+            TieredConfig {
+                slice_size: 4,
+                max_staleness: Duration::from_millis(20),
+                prefetch_below: 0,
+            },
+        );
+        assert!(tiered.take_token("user1", 1).await.unwrap());
+        assert!(tiered.take_token("user2", 1).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        assert!(tiered.take_token("user3", 1).await.unwrap());
+        let slices = tiered.slices.lock();
+        assert_eq!(slices.keys().collect::<Vec<_>>(), ["user3"]);
+    }
+
+    #[tokio::test]
+    async fn test_tiered_prefetch_drops_stale_tokens() {
+        let tiered = TieredBackend::new(
+            SlowTakes {
+                inner: MemoryBackend::new(TokenBucketConfig::default()),
+                delay: Duration::from_millis(100),
+            },
+            TieredConfig {
+                slice_size: 4,
+                max_staleness: Duration::from_millis(30),
+                prefetch_below: 4,
+            },
+        );
+        assert!(tiered.take_token("user1", 1).await.unwrap());
+        // Dips below prefetch_below, starting a prefetch that lands after the
+        // slice's remaining 2 tokens went stale.
+        assert!(tiered.take_token("user1", 1).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let slices = tiered.slices.lock();
+        let slice = &slices["user1"];
+        assert!(!slice.prefetching);
+        assert_eq!(slice.available, 4);
     }
 
     #[tokio::test]
-    async fn test_token_refill() {
+    async fn test_tiered_reset_refunds_prefetch_in_flight() {
+        let tiered = TieredBackend::new(
+            SlowTakes {
+                inner: MemoryBackend::new(TokenBucketConfig::default()),
+                delay: Duration::from_millis(50),
+            },
+            TieredConfig {
+                slice_size: 4,
+                max_staleness: Duration::from_secs(60),
+                prefetch_below: 4,
+            },
+        );
+        assert!(tiered.take_token("user1", 1).await.unwrap());
+        assert!(tiered.take_token("user1", 1).await.unwrap());
+        tiered.reset("user1").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        // The prefetch leased its slice after the reset, with nowhere to put it.
+        assert_eq!(tiered.get_usage("user1").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_warm_up_leases_ahead_of_traffic() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(60),
+        };
+        let tiered = TieredBackend::new(
+            MemoryBackend::new(config.clone()),
+            TieredConfig {
+                slice_size: 4,
+                max_staleness: Duration::from_secs(60),
+                prefetch_below: 0,
+            },
+        );
+        tiered.warm_up("user1").await.unwrap();
+        tiered.warm_up("user1").await.unwrap();
+        assert_eq!(tiered.get_usage("user1").await.unwrap(), 4);
+        assert!(tiered.take_token("user1", 4).await.unwrap());
+        assert_eq!(tiered.get_usage("user1").await.unwrap(), 4);
+
+        let batching = BatchingBackend::new(MemoryBackend::new(config), 3);
+        batching.warm_up("user1").await.unwrap();
+        assert_eq!(batching.get_usage("user1").await.unwrap(), 3);
+        assert!(batching.take_token("user1", 3).await.unwrap());
+        assert_eq!(batching.get_usage("user1").await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_batching_counts_cache_hits() {
+        let stats = Arc::new(CacheStats::default());
+        let batching = BatchingBackend::new(MemoryBackend::new(TokenBucketConfig::default()), 5)
+            .with_stats(Arc::clone(&stats));
+        for _ in 0..5 {
+            assert!(batching.take_token("user1", 1).await.unwrap());
+        }
+        // One batch reserved, then spent locally.
+        assert_eq!((stats.hits(), stats.misses()), (4, 1));
+        // The next batch tops the spent one up.
+        for _ in 0..5 {
+            assert!(batching.take_token("user1", 1).await.unwrap());
+        }
+        assert_eq!((stats.hits(), stats.misses()), (8, 2));
+        assert_eq!(batching.get_usage("user1").await.unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_memory_leases_lapse_and_return() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 0,
+            refill_interval: Duration::from_secs(1),
+        };
+        let backend = MemoryBackend::new(config);
+        let ttl = Duration::from_secs(60);
+        assert!(backend.acquire_lease("user1", 1, 4, ttl).await.unwrap().is_some());
+        assert!(backend.acquire_lease("user1", 2, 4, ttl).await.unwrap().is_some());
+        assert_eq!(backend.acquire_lease("user1", 3, 4, ttl).await.unwrap(), None);
+        assert_eq!(backend.get_usage("user1").await.unwrap(), 8);
+
+        assert_eq!(backend.return_lease("user1", 1, 3).await.unwrap(), 3);
+        assert_eq!(backend.return_lease("user1", 1, 3).await.unwrap(), 0);
+        assert_eq!(backend.get_usage("user1").await.unwrap(), 5);
+
+        // A lapsed lease can't be renewed or returned, and is reclaimed.
+        backend.leases.lock().get_mut("user1").unwrap().get_mut(&2).unwrap().expires_at =
+            SystemTime::now();
+        assert_eq!(backend.renew_lease("user1", 2, ttl).await.unwrap(), None);
+        assert_eq!(backend.return_lease("user1", 2, 4).await.unwrap(), 0);
+        let lease = backend.acquire_lease("user1", 4, 2, ttl).await.unwrap().unwrap();
+        assert_eq!(backend.leases("user1").await.unwrap(), [lease]);
+        backend.reset("user1").await.unwrap();
+        assert!(backend.leases("user1").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_leasing_spends_locally_and_returns_leftovers() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 0,
+            refill_interval: Duration::from_secs(1),
+        };
+        let shared = Arc::new(MemoryBackend::new(config));
+        let lease = LeaseConfig {
+            size: 4,
+            ttl: Duration::from_secs(60),
+        };
+        let a = LeasingBackend::new(Arc::clone(&shared), lease.clone());
+        let b = LeasingBackend::new(Arc::clone(&shared), lease);
+
+        assert!(a.take_token("user1", 1).await.unwrap());
+        assert!(b.take_token("user1", 1).await.unwrap());
+        assert_eq!(shared.leases("user1").await.unwrap().len(), 2);
+        assert_eq!(shared.get_usage("user1").await.unwrap(), 8);
+        // `a` spends the rest of its lease, then leases the last two tokens
+        // one by one, too few for a lease.
+        for _ in 0..3 {
+            assert!(a.take_token("user1", 1).await.unwrap());
+        }
+        assert!(a.take_token("user1", 1).await.unwrap());
+        assert!(a.take_token("user1", 1).await.unwrap());
+        assert!(!a.take_token("user1", 1).await.unwrap());
+        assert_eq!((a.stats().hits(), a.stats().misses()), (3, 4));
+
+        // What `b` didn't spend goes back when it stops.
+        b.flush().await.unwrap();
+        assert!(shared.leases("user1").await.unwrap().is_empty());
+        assert_eq!(shared.get_usage("user1").await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_slices_follow_each_nodes_traffic() {
+        let limit = TokenBucketConfig {
+            capacity: 100,
+            refill_rate: 0,
+            refill_interval: Duration::from_secs(1),
+        };
+        let shared = Arc::new(MemoryBackend::new(limit.clone()));
+        let config = SliceConfig {
+            rebalance_interval: Duration::from_secs(3600),
+            min_share: 0.1,
+        };
+        let a = SlicedBackend::new(Arc::clone(&shared), limit.clone(), "a", config.clone());
+        let b = SlicedBackend::new(Arc::clone(&shared), limit, "b", config);
+
+        // Alone, `a` starts with the whole limit; `b` then finds `a`.
+        assert!(a.take_token("user1", 1).await.unwrap());
+        assert_eq!(a.share("user1"), Some(1.0));
+        assert!(b.take_token("user1", 1).await.unwrap());
+        assert_eq!(b.share("user1"), Some(0.5));
+
+        // `a` gets busy and takes the larger slice; quiet `b` keeps its
+        // minimum.
+        for _ in 0..39 {
+            assert!(a.take_token("user1", 1).await.unwrap());
+        }
+        a.rebalance("user1").await.unwrap();
+        b.rebalance("user1").await.unwrap();
+        let (share_a, share_b) = (a.share("user1").unwrap(), b.share("user1").unwrap());
+        assert!(share_a > 0.9 && share_b < 0.1, "{} {}", share_a, share_b);
+        assert!((share_a + share_b - 1.0).abs() < 1e-9);
+        assert_eq!(b.inspect("user1").await.unwrap().unwrap().capacity, 9);
+
+        // A reset drops the key's demand, so `b` has the limit to itself.
+        b.reset("user1").await.unwrap();
+        b.rebalance("user1").await.unwrap();
+        assert_eq!(b.share("user1"), Some(1.0));
+        assert_eq!(b.get_usage("user1").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_regions_divide_the_limit_before_their_nodes() {
+        let limit = TokenBucketConfig {
+            capacity: 100,
+            refill_rate: 0,
+            refill_interval: Duration::from_secs(1),
+        };
+        let config = SliceConfig {
+            rebalance_interval: Duration::from_secs(3600),
+            min_share: 0.1,
+        };
+        let global: Arc<dyn StorageBackend> = Arc::new(MemoryBackend::new(limit.clone()));
+        let node = |region: &str| {
+            let local = MemoryBackend::new(limit.clone());
+            SlicedBackend::new(local, limit.clone(), "node-0", config.clone())
+                .with_region(region, RegionShares::Demand(Arc::clone(&global)))
+        };
+        let (eu, us) = (node("eu-west"), node("us-east"));
+
+        // The busier region gets the larger part.
+        assert!(eu.take_token("user1", 1).await.unwrap());
+        assert!(us.take_token("user1", 1).await.unwrap());
+        for _ in 0..29 {
+            assert!(eu.take_token("user1", 1).await.unwrap());
+        }
+        eu.rebalance("user1").await.unwrap();
+        us.rebalance("user1").await.unwrap();
+        let (share_eu, share_us) = (eu.share("user1").unwrap(), us.share("user1").unwrap());
+        assert!(share_eu > 0.8 && share_us < 0.2, "{} {}", share_eu, share_us);
+        assert!((share_eu + share_us - 1.0).abs() < 1e-9);
+
+        // Fixed weights need no exchange between regions.
+        let weights = HashMap::from([("eu-west".to_string(), 3.0), ("us-east".to_string(), 1.0)]);
+        let fixed = SlicedBackend::new(MemoryBackend::new(limit.clone()), limit, "node-0", config)
+            .with_region("us-east", RegionShares::Static(weights));
+        assert!(fixed.take_token("user1", 1).await.unwrap());
+        assert_eq!(fixed.share("user1"), Some(0.25));
+        assert_eq!(fixed.inspect("user1").await.unwrap().unwrap().capacity, 25);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("api:*", "api:user1"));
+        assert!(glob_match("api:*", "api:"));
+        assert!(!glob_match("api:*", "internal:user1"));
+        assert!(glob_match("*:admin", "tenant:admin"));
+        assert!(glob_match("t*:*:read", "tenant:42:read"));
+        assert!(!glob_match("t*:*:read", "tenant:42:write"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+    }
+
+    #[test]
+    fn test_key_hash_is_fnv1a() {
+        assert_eq!(key_hash(""), "cbf29ce484222325");
+        assert_eq!(key_hash("a"), "af63dc4c8601ec8c");
+        assert_eq!(key_hash("api:user1").len(), 16);
+    }
+
+    #[test]
+    fn test_hash_ring_moves_only_the_keys_a_node_gains() {
+        let ring = HashRing::new(["b:50051", "a:50051", "c:50051"]);
+        assert_eq!(ring.nodes(), ["a:50051", "b:50051", "c:50051"]);
+        assert!(HashRing::new(Vec::<String>::new()).owner("user1").is_none());
+
+        let keys: Vec<String> = (0..3000).map(|i| format!("user{}", i)).collect();
+        let mut counts = HashMap::new();
+        for key in &keys {
+            *counts.entry(ring.owner(key).unwrap()).or_insert(0) += 1;
+        }
+        assert!(counts.values().all(|count| (700..1300).contains(count)));
+
+        let grown = HashRing::new(["a:50051", "b:50051", "c:50051", "d:50051"]);
+        for key in &keys {
+            let owner = grown.owner(key).unwrap();
+            assert!(owner == "d:50051" || owner == ring.owner(key).unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_estimating_backend_scales_to_the_global_rate() {
+        // 100 tokens a second: 5 per 50 ms window across both nodes.
+        let limit = TokenBucketConfig {
+            capacity: 100,
+            refill_rate: 100,
+            refill_interval: Duration::from_secs(1),
+        };
+        let shared = Arc::new(MemoryBackend::new(limit.clone()));
+        let config = EstimateConfig {
+            window: Duration::from_millis(50),
+        };
+        let a = EstimatingBackend::new(Arc::clone(&shared), limit.clone(), "a", config.clone());
+        let b = EstimatingBackend::new(Arc::clone(&shared), limit, "b", config);
+        async fn admitted(backend: &impl StorageBackend, checks: usize) -> usize {
+            let mut admitted = 0;
+            for _ in 0..checks {
+                admitted += backend.take_token("user1", 1).await.unwrap() as usize;
+            }
+            admitted
+        }
+
+        // Alone, a node allows its share and then a falling fraction.
+        let alone = admitted(&a, 1000).await;
+        assert!((5..100).contains(&alone), "{}", alone);
+
+        // Once a has shared its window, b counts it against the limit.
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        admitted(&a, 1).await;
+        admitted(&b, 1).await;
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(admitted(&b, 100).await < 20);
+        assert!(b.get_usage("user1").await.unwrap() >= 1000);
+    }
+
+    #[tokio::test]
+    async fn test_affinity_backend_decides_owned_keys_in_memory() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 0,
+            refill_interval: Duration::from_secs(1),
+        };
+        let backup = Arc::new(MemoryBackend::new(config.clone()));
+        let ring = HashRing::new(["a", "b"]);
+        let key = (0..)
+            .map(|i| format!("user{}", i))
+            .find(|key| ring.owner(key) == Some("a"))
+            .unwrap();
+        backup.take_token(&key, 3).await.unwrap();
+
+        let sync = Duration::from_secs(3600);
+        let affinity = |node: &str| {
+            let backup = Arc::clone(&backup);
+            AffinityBackend::new(backup, config.clone(), ring.clone(), node, sync)
+        };
+        let owner = affinity("a");
+        assert!(owner.owns(&key));
+        // Carries on from the backup's bucket, then decides alone.
+        assert!(owner.take_token(&key, 7).await.unwrap());
+        assert!(!owner.take_token(&key, 1).await.unwrap());
+        assert_eq!(backup.inspect(&key).await.unwrap().unwrap().remaining, 7);
+
+        owner.flush().await.unwrap();
+        assert_eq!(backup.inspect(&key).await.unwrap().unwrap().remaining, 0);
+
+        // Another node takes over from what was written back.
+        let other = affinity("b");
+        assert!(!other.owns(&key));
+        assert!(!other.take_token(&key, 1).await.unwrap());
+        let next = affinity("a");
+        assert!(!next.take_token(&key, 1).await.unwrap());
+
+        owner.refund(&key, 2).await.unwrap();
+        assert_eq!(backup.inspect(&key).await.unwrap().unwrap().remaining, 2);
+        assert!(owner.take_token(&key, 2).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_router_backend_dispatches_by_pattern() {
+        let small = TokenBucketConfig {
+            capacity: 1,
+            ..TokenBucketConfig::default()
+        };
+        let router = RouterBackend::new(MemoryBackend::new(TokenBucketConfig::default()))
+            .route("internal:*", MemoryBackend::new(small));
+
+        assert!(router.take_token("internal:job", 1).await.unwrap());
+        assert!(!router.take_token("internal:job", 1).await.unwrap());
+        assert!(router.take_token("api:user1", 50).await.unwrap());
+        assert_eq!(router.get_usage("api:user1").await.unwrap(), 50);
+
+        // A more specific rule added at runtime wins over the broad one.
+        router.set_route(
+            "internal:batch:*",
+            MemoryBackend::new(TokenBucketConfig::default()),
+        );
+        assert!(router
+            .take_token("internal:batch:nightly", 50)
+            .await
+            .unwrap());
+        assert_eq!(
+            router.pattern_for("internal:batch:nightly").as_deref(),
+            Some("internal:batch:*")
+        );
+        assert_eq!(router.pattern_for("api:user1"), None);
+        assert!(router.remove_route("internal:*"));
+        assert!(router.take_token("internal:job", 50).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_list_keys_pages_in_key_order() {
+        let router = RouterBackend::new(MemoryBackend::new(TokenBucketConfig::default()))
+            .route("api:*", MemoryBackend::new(TokenBucketConfig::default()));
+        for key in ["api:c", "api:a", "web:b", "api:b"] {
+            router.take_token(key, 1).await.unwrap();
+        }
+
+        let first = router.list_keys("api:*", None, 2).await.unwrap();
+        assert_eq!(first, vec!["api:a", "api:b"]);
+        let second = router.list_keys("api:*", Some("api:b"), 2).await.unwrap();
+        assert_eq!(second, vec!["api:c"]);
+        assert_eq!(router.list_keys("*", None, 10).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_stats_backend_ranks_recent_activity() {
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(60),
+        };
+        let backend = StatsBackend::new(MemoryBackend::new(config), Duration::from_secs(60));
+
+        backend.take_token("heavy", 5).await.unwrap();
+        for _ in 0..3 {
+            backend.take_token("heavy", 1).await.unwrap();
+        }
+        backend.take_token("light", 1).await.unwrap();
+
+        let by_usage = backend.top_keys(1, KeyRanking::Usage).await.unwrap();
+        assert_eq!(by_usage[0].0, "heavy");
+        assert_eq!(by_usage[0].1.consumed, 5);
+
+        let by_denials = backend.top_keys(10, KeyRanking::Denials).await.unwrap();
+        assert_eq!(by_denials[0].1.denied, 3);
+        assert_eq!(by_denials[1].0, "light");
+        assert_eq!(backend.active_keys(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_backend_counts_disagreements() {
+        let primary = MemoryBackend::new(TokenBucketConfig::default());
+        let secondary = MemoryBackend::new(TokenBucketConfig {
+            capacity: 5,
+            ..TokenBucketConfig::default()
+        });
+        let mirror = MirrorBackend::new(primary, secondary);
+
+        assert!(mirror.take_token("user1", 3).await.unwrap());
+        // Secondary only has 2 tokens left; the primary still decides.
+        assert!(mirror.take_token("user1", 3).await.unwrap());
+        assert_eq!(mirror.get_usage("user1").await.unwrap(), 6);
+
+        let stats = mirror.stats();
+        assert_eq!(stats.decisions, 2);
+        assert_eq!(stats.decision_mismatches, 1);
+        assert_eq!(stats.usage_mismatches, 1);
+        assert_eq!(stats.secondary_errors, 0);
+
+        // The primary's bucket comes through, not the trait's fallback.
+        let decision = mirror.take_token_detailed("user1", 3).await.unwrap();
+        assert!(decision.allowed);
+        assert_eq!(decision.bucket.unwrap().remaining, 91);
+        let stats = mirror.stats();
+        assert_eq!((stats.decisions, stats.decision_mismatches), (3, 2));
+    }
+
+    /// A `MemoryBackend` that fails every call while `down` is set.
+    struct Outage {
+        inner: MemoryBackend,
+        down: std::sync::atomic::AtomicBool,
+    }
+
+    impl Outage {
+        fn check(&self) -> Result<(), RateLimitError> {
+            if self.down.load(Ordering::Relaxed) {
+                return Err(RateLimitError::StorageError(
+                    "connection refused".to_string(),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl StorageBackend for Outage {
+        async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+            self.check()?;
+            self.inner.take_token(key, cost).await
+        }
+
+        async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+            self.check()?;
+            self.inner.get_usage(key).await
+        }
+
+        async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+            self.check()?;
+            self.inner.reset(key).await
+        }
+
+        async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+            self.check()?;
+            self.inner.inspect(key).await
+        }
+
+        async fn health_check(&self) -> Result<(), RateLimitError> {
+            self.check()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failover_backend_degrades_and_reconciles() {
+        let primary = Arc::new(Outage {
+            inner: MemoryBackend::new(TokenBucketConfig {
+                capacity: 10,
+                refill_rate: 1,
+                refill_interval: Duration::from_secs(1),
+            }),
+            down: Default::default(),
+        });
+        let fallback = MemoryBackend::new(TokenBucketConfig {
+            capacity: 4,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        });
+        let failover = FailoverBackend::new(Arc::clone(&primary), fallback)
+            .with_retry_interval(Duration::from_secs(3600));
+
+        assert!(failover.take_token("user1", 2).await.unwrap());
+        primary.down.store(true, Ordering::Relaxed);
+
+        // The outage is absorbed by the fallback's smaller bucket.
+        for _ in 0..4 {
+            assert!(failover.take_token("user1", 1).await.unwrap());
+        }
+        assert!(!failover.take_token("user1", 1).await.unwrap());
+        assert!(failover.is_degraded());
+        assert!(failover.health_check().await.is_ok());
+
+        primary.down.store(false, Ordering::Relaxed);
+        failover.health_check().await.unwrap();
+        assert!(!failover.is_degraded());
+        assert_eq!(primary.get_usage("user1").await.unwrap(), 6);
+
+        let stats = failover.stats();
+        assert_eq!(stats.failovers(), 1);
+        assert_eq!(stats.recoveries(), 1);
+        assert_eq!(stats.degraded(), 0);
+        assert_eq!(stats.degraded_decisions(), 5);
+        assert_eq!(stats.reconciled_tokens(), 4);
+
+        // The fallback starts the next outage with full buckets.
+        primary.down.store(true, Ordering::Relaxed);
+        assert_eq!(failover.get_usage("user1").await.unwrap(), 0);
+        assert_eq!(stats.failovers(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_chaos_backend_delays_and_fails_calls() {
+        let memory = Arc::new(MemoryBackend::new(TokenBucketConfig::default()));
+        let chaos = Arc::new(ChaosBackend::new(
+            Arc::clone(&memory),
+            ChaosConfig {
+                latency: Duration::from_millis(200),
+                ..ChaosConfig::default()
+            },
+        ));
+        let started = tokio::time::Instant::now();
+        assert!(chaos.take_token("user1", 1).await.unwrap());
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        // Failed calls never reach the backend...
+        chaos.set_config(ChaosConfig {
+            error_rate: 1.0,
+            ..ChaosConfig::default()
+        });
+        assert!(chaos.take_token("user1", 1).await.is_err());
+        assert_eq!(memory.get_usage("user1").await.unwrap(), 1);
+
+        // ...partial failures do, and a fail-open limiter lets both through.
+        chaos.set_config(ChaosConfig {
+            partial_failure_rate: 1.0,
+            ..ChaosConfig::default()
+        });
+        let limiter = RateLimiter::new(Arc::clone(&chaos), true);
+        let result = limiter.check_limit("user1", 1).await.unwrap();
+        assert!(matches!(result, LimitResult::Allowed));
+        assert_eq!(memory.get_usage("user1").await.unwrap(), 2);
+
+        chaos.set_config(ChaosConfig::default());
+        assert!(chaos.take_token("user1", 1).await.unwrap());
+        assert_eq!(
+            chaos.stats(),
+            ChaosStats {
+                calls: 4,
+                injected_errors: 1,
+                partial_failures: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_event_bus_collects_from_limiter_and_backends() {
+        let events = EventBus::new(16);
+        let mut received = events.subscribe();
+        let small = TokenBucketConfig {
+            capacity: 1,
+            ..TokenBucketConfig::default()
+        };
+        let primary = Arc::new(Outage {
+            inner: MemoryBackend::new(small.clone()),
+            down: Default::default(),
+        });
+        let fallback = MemoryBackend::new(small.clone());
+        let failover =
+            FailoverBackend::new(Arc::clone(&primary), fallback).with_events(events.clone());
+        let router = Arc::new(RouterBackend::new(failover).with_events(events.clone()));
+        let limiter = RateLimiter::new(Arc::clone(&router), false).with_events(events.clone());
+
+        limiter.check_limit("user1", 1).await.unwrap();
+        limiter.check_limit("user1", 1).await.unwrap();
+        limiter.reset("user1").await.unwrap();
+        router.set_route("api:*", MemoryBackend::new(small));
+        assert!(router.remove_route("api:*"));
+        primary.down.store(true, Ordering::Relaxed);
+        router.health_check().await.unwrap();
+        primary.down.store(false, Ordering::Relaxed);
+        router.health_check().await.unwrap();
+
+        let mut seen = Vec::new();
+        while let Ok(event) = received.try_recv() {
+            seen.push(event);
+        }
+        assert_eq!(seen.len(), 6);
+        assert_eq!(
+            seen[..4],
+            [
+                LimiterEvent::Denied {
+                    key: "user1".to_string(),
+                    cost: 1,
+                    reason: DecisionReason::BucketExhausted,
+                },
+                LimiterEvent::Reset {
+                    key: "user1".to_string()
+                },
+                LimiterEvent::RouteSet {
+                    pattern: "api:*".to_string()
+                },
+                LimiterEvent::RouteRemoved {
+                    pattern: "api:*".to_string()
+                },
+            ]
+        );
+        assert!(matches!(seen[4], LimiterEvent::FailedOver { .. }));
+        assert!(matches!(
+            seen[5],
+            LimiterEvent::Recovered {
+                reconciled_tokens: 0,
+                ..
+            }
+        ));
+    }
+
+    /// HashMap store whose versions are a global write counter.
+    #[derive(Default)]
+    struct MapStore {
+        entries: parking_lot::Mutex<(u64, HashMap<String, KvEntry>)>,
+    }
+
+    #[async_trait]
+    impl KvStore for MapStore {
+        async fn get(&self, key: &str) -> Result<Option<KvEntry>, RateLimitError> {
+            Ok(self.entries.lock().1.get(key).cloned())
+        }
+
+        async fn compare_and_set(
+            &self,
+            key: &str,
+            expected: Option<u64>,
+            value: Vec<u8>,
+        ) -> Result<bool, RateLimitError> {
+            let mut guard = self.entries.lock();
+            let (counter, entries) = &mut *guard;
+            if entries.get(key).map(|e| e.version) != expected {
+                return Ok(false);
+            }
+            *counter += 1;
+            entries.insert(
+                key.to_string(),
+                KvEntry {
+                    value,
+                    version: *counter,
+                },
+            );
+            Ok(true)
+        }
+
+        async fn expire(&self, _key: &str, _ttl: Duration) -> Result<(), RateLimitError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_kv_backend() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        };
+        let backend = KvBackend::new(MapStore::default(), config);
+
+        assert!(backend.take_token("user1", 6).await.unwrap());
+        assert!(!backend.take_token("user1", 6).await.unwrap());
+        assert_eq!(backend.get_usage("user1").await.unwrap(), 6);
+
+        backend.reset("user1").await.unwrap();
+        assert_eq!(backend.get_usage("user1").await.unwrap(), 0);
+        assert!(backend.take_token("user1", 10).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_locks_have_one_holder_at_a_time() {
+        let config = TokenBucketConfig::default();
+        let ttl = Duration::from_secs(60);
+        let backends: [Box<dyn StorageBackend>; 2] = [
+            Box::new(MemoryBackend::new(config.clone())),
+            Box::new(KvBackend::new(MapStore::default(), config)),
+        ];
+        for backend in backends {
+            assert!(backend.hold_lock("leader", "a", ttl).await.unwrap());
+            assert!(!backend.hold_lock("leader", "b", ttl).await.unwrap());
+            // The holder extends it.
+            assert!(backend.hold_lock("leader", "a", ttl).await.unwrap());
+            backend.release_lock("leader", "b").await.unwrap();
+            assert!(!backend.hold_lock("leader", "b", ttl).await.unwrap());
+            backend.release_lock("leader", "a").await.unwrap();
+            assert!(backend.hold_lock("leader", "b", ttl).await.unwrap());
+            // A lapsed lock goes to whoever asks next.
+            assert!(backend.hold_lock("other", "a", Duration::ZERO).await.unwrap());
+            assert!(backend.hold_lock("other", "b", ttl).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refund_returns_tokens_up_to_capacity() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(60),
+        };
+        let memory = MemoryBackend::new(config.clone());
+        let kv = KvBackend::new(MapStore::default(), config);
+        let backends: [&dyn StorageBackend; 2] = [&memory, &kv];
+        for backend in backends {
+            assert!(backend.take_token("user1", 8).await.unwrap());
+            backend.refund("user1", 5).await.unwrap();
+            assert_eq!(backend.get_usage("user1").await.unwrap(), 3);
+            backend.refund("user1", 50).await.unwrap();
+            assert_eq!(backend.get_usage("user1").await.unwrap(), 0);
+            backend.refund("unused", 5).await.unwrap();
+            assert_eq!(backend.get_usage("unused").await.unwrap(), 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_throttled_stream_drops_or_delays_items() {
+        use tokio_stream::StreamExt;
+
+        let config = TokenBucketConfig {
+            capacity: 3,
+            refill_rate: 3,
+            refill_interval: Duration::from_millis(100),
+        };
+        let limiter = Arc::new(RateLimiter::new(MemoryBackend::new(config), false));
+
+        // "huge" costs more than the bucket holds and is dropped outright.
+        let words = ["a", "bb", "huge", "c", "d"];
+        let kept: Vec<_> = tokio_stream::iter(words)
+            .guardian_throttle(Arc::clone(&limiter), "drop", |word| match *word {
+                "huge" => 10,
+                word => word.len() as u64,
+            })
+            .with_mode(ThrottleMode::Drop)
+            .collect()
+            .await;
+        assert_eq!(kept, ["a", "bb"]);
+
+        let started = Instant::now();
+        let paced: Vec<_> = tokio_stream::iter(1..=4)
+            .guardian_throttle(Arc::clone(&limiter), "delay", |_| 1)
+            .collect()
+            .await;
+        assert_eq!(paced, [1, 2, 3, 4]);
+        // The fourth waits for a token to refill, a third of a second.
+        let waited = started.elapsed();
+        assert!(waited >= Duration::from_millis(300) && waited < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_throttled_io_charges_a_token_per_byte() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let config = TokenBucketConfig {
+            capacity: 8,
+            refill_rate: 8,
+            refill_interval: Duration::from_secs(1),
+        };
+        let limiter = Arc::new(RateLimiter::new(MemoryBackend::new(config), false));
+
+        let mut writer =
+            ThrottledWrite::new(Vec::new(), Arc::clone(&limiter), "tenant:w").with_chunk_size(3);
+        writer.write_all(b"hello").await.unwrap();
+        assert_eq!(writer.get_ref(), b"hello");
+        // A chunk of 3, then one for the 2 bytes left.
+        let bucket = limiter.inspect("tenant:w").await.unwrap().unwrap();
+        assert_eq!(bucket.remaining, 3);
+
+        // 12 bytes from a bucket of 8: the last 4 wait for the refill. The
+        // default chunk is bigger than the bucket and shrinks to fit it.
+        let data = [7u8; 12];
+        let mut reader = ThrottledRead::new(&data[..], Arc::clone(&limiter), "tenant:r");
+        let started = Instant::now();
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, data);
+        assert!(started.elapsed() >= Duration::from_millis(400));
+    }
+
+    /// A clock that moves only when told to.
+    struct Stopped(parking_lot::Mutex<SystemTime>);
+
+    impl Clock for Stopped {
+        fn now(&self) -> SystemTime {
+            *self.0.lock()
+        }
+    }
+
+    #[test]
+    fn test_grants_redeem_once_for_at_most_their_charge() {
+        let clock = Arc::new(Stopped(parking_lot::Mutex::new(SystemTime::now())));
+        let ledger =
+            GrantLedger::new(Duration::from_secs(60), 2).with_clock(Arc::clone(&clock) as _);
+
+        let grant = ledger.issue("user1", 5).unwrap();
+        assert_eq!(ledger.redeem(&grant, "user2", 5), None);
+        assert_eq!(ledger.redeem(&grant, "user1", 50), Some(5));
+        assert_eq!(ledger.redeem(&grant, "user1", 5), None);
+
+        let partial = ledger.issue("user1", 5).unwrap();
+        assert_eq!(ledger.redeem(&partial, "user1", 2), Some(2));
+        assert_eq!(ledger.redeem(&partial, "user1", 3), None);
+        assert_eq!(ledger.redeem("made-up", "user1", 1), None);
+
+        // Full of live grants, the ledger issues no more until they lapse.
+        let lapsing = ledger.issue("user1", 1).unwrap();
+        ledger.issue("user1", 1).unwrap();
+        assert!(ledger.issue("user1", 1).is_none());
+        *clock.0.lock() += Duration::from_secs(61);
+        assert_eq!(ledger.redeem(&lapsing, "user1", 0), None);
+        let older = ledger.issue("user1", 1).unwrap();
+
+        // Making room drops the grants that lapsed, oldest first, and
+        // leaves live ones redeemable.
+        *clock.0.lock() += Duration::from_secs(30);
+        let newer = ledger.issue("user1", 1).unwrap();
+        assert!(ledger.issue("user1", 1).is_none());
+        *clock.0.lock() += Duration::from_secs(31);
+        assert!(ledger.issue("user1", 1).is_some());
+        assert_eq!(ledger.redeem(&older, "user1", 0), None);
+        assert_eq!(ledger.redeem(&newer, "user1", 0), Some(1));
+    }
+
+    #[test]
+    fn test_token_refill() {
         let config = TokenBucketConfig {
             capacity: 10,
             refill_rate: 10,
             refill_interval: Duration::from_secs(1),
         };
-        let bucket = TokenBucket::new(config);
+        let clock = Arc::new(Stopped(parking_lot::Mutex::new(SystemTime::now())));
+        let bucket = TokenBucket::new(config).with_clock(Arc::clone(&clock) as _);
 
         bucket.try_consume(10).unwrap();
         assert!(bucket.try_consume(1).is_err());
 
-        sleep(Duration::from_millis(1100)).await;
+        *clock.0.lock() += Duration::from_millis(500);
+        assert!(bucket.try_consume(5).is_ok());
+        assert!(bucket.try_consume(1).is_err());
+        *clock.0.lock() += Duration::from_secs(5);
         assert!(bucket.try_consume(10).is_ok());
     }
+
+    #[tokio::test]
+    async fn test_inspect_reports_when_tokens_were_last_credited() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        };
+        let start = SystemTime::now();
+        let clock = Arc::new(Stopped(parking_lot::Mutex::new(start)));
+        let backend = MemoryBackend::new(config.clone()).with_clock(Arc::clone(&clock) as _);
+
+        assert!(backend.take_token("user1", 5).await.unwrap());
+        // Too soon to credit a token, so the last refill stays put.
+        *clock.0.lock() += Duration::from_millis(500);
+        let bucket = backend.inspect("user1").await.unwrap().unwrap();
+        assert_eq!((bucket.remaining, bucket.last_refill), (5, Some(start)));
+
+        *clock.0.lock() += Duration::from_secs(2);
+        let bucket = backend.inspect("user1").await.unwrap().unwrap();
+        let refilled = start + Duration::from_millis(2500);
+        assert_eq!((bucket.remaining, bucket.last_refill), (7, Some(refilled)));
+
+        // A key the store has never seen has no refill to report.
+        let kv = KvBackend::new(MapStore::default(), config);
+        let bucket = kv.inspect("user1").await.unwrap().unwrap();
+        assert_eq!((bucket.remaining, bucket.last_refill), (10, None));
+        assert!(kv.take_token("user1", 1).await.unwrap());
+        let bucket = kv.inspect("user1").await.unwrap().unwrap();
+        assert!(bucket.last_refill.is_some());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,303 @@
+use async_trait::async_trait;
+use aws_sdk_dynamodb::client::Waiters;
+use aws_sdk_dynamodb::types::{
+    AttributeDefinition, AttributeValue, BillingMode, KeySchemaElement, KeyType,
+    ProvisionedThroughput, ScalarAttributeType, TimeToLiveSpecification,
+};
+use aws_sdk_dynamodb::Client;
+use guardian_core::{BucketState, RateLimitError, StorageBackend, TokenBucketConfig};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const ATTR_KEY: &str = "pk";
+const ATTR_TOKENS: &str = "tokens";
+const ATTR_LAST_REFILL: &str = "last_refill_ms";
+const ATTR_VERSION: &str = "version";
+const ATTR_EXPIRES_AT: &str = "expires_at";
+
+/// Idle buckets are removed by DynamoDB TTL after this long, matching Redis.
+const BUCKET_TTL_SECS: u64 = 3600;
+
+/// Conditional updates retried before reporting contention as an error.
+const MAX_CONDITIONAL_RETRIES: usize = 16;
+
+/// Capacity mode used when `DynamoDbBackend::create_table` creates the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Throughput {
+    /// PAY_PER_REQUEST billing; suits spiky serverless traffic.
+    OnDemand,
+    /// Fixed read/write capacity units.
+    Provisioned {
+        read_capacity_units: i64,
+        write_capacity_units: i64,
+    },
+}
+
+/// Token buckets stored as DynamoDB items, one per key.
+///
+/// Consumption reads the item, refills it locally, and writes it back with a
+/// conditional UpdateItem on a version attribute, retrying on conflict.
+pub struct DynamoDbBackend {
+    client: Client,
+    table: String,
+    config: TokenBucketConfig,
+}
+
+impl DynamoDbBackend {
+    /// Build a client from the standard AWS environment (region, credentials,
+    /// `AWS_ENDPOINT_URL` for DynamoDB Local).
+    pub async fn new(table: impl Into<String>, config: TokenBucketConfig) -> Self {
+        let sdk_config = aws_config::load_from_env().await;
+        Self::with_client(Client::new(&sdk_config), table, config)
+    }
+
+    pub fn with_client(
+        client: Client,
+        table: impl Into<String>,
+        config: TokenBucketConfig,
+    ) -> Self {
+        Self {
+            client,
+            table: table.into(),
+            config,
+        }
+    }
+
+    /// Create the bucket table with the given capacity mode and enable TTL on
+    /// the expiry attribute. Intended for bootstrap scripts and tests; most
+    /// deployments provision the table with infrastructure tooling.
+    pub async fn create_table(&self, throughput: Throughput) -> Result<(), RateLimitError> {
+        let attribute = AttributeDefinition::builder()
+            .attribute_name(ATTR_KEY)
+            .attribute_type(ScalarAttributeType::S)
+            .build()
+            .map_err(config_error)?;
+        let key_schema = KeySchemaElement::builder()
+            .attribute_name(ATTR_KEY)
+            .key_type(KeyType::Hash)
+            .build()
+            .map_err(config_error)?;
+
+        let mut request = self
+            .client
+            .create_table()
+            .table_name(&self.table)
+            .attribute_definitions(attribute)
+            .key_schema(key_schema);
+
+        request = match throughput {
+            Throughput::OnDemand => request.billing_mode(BillingMode::PayPerRequest),
+            Throughput::Provisioned {
+                read_capacity_units,
+                write_capacity_units,
+            } => request
+                .billing_mode(BillingMode::Provisioned)
+                .provisioned_throughput(
+                    ProvisionedThroughput::builder()
+                        .read_capacity_units(read_capacity_units)
+                        .write_capacity_units(write_capacity_units)
+                        .build()
+                        .map_err(config_error)?,
+                ),
+        };
+
+        request
+            .send()
+            .await
+            .map_err(|e| storage_error("DynamoDB create table error", e))?;
+
+        self.client
+            .wait_until_table_exists()
+            .table_name(&self.table)
+            .wait(Duration::from_secs(60))
+            .await
+            .map_err(|e| storage_error("DynamoDB table wait error", e))?;
+
+        self.client
+            .update_time_to_live()
+            .table_name(&self.table)
+            .time_to_live_specification(
+                TimeToLiveSpecification::builder()
+                    .enabled(true)
+                    .attribute_name(ATTR_EXPIRES_AT)
+                    .build()
+                    .map_err(config_error)?,
+            )
+            .send()
+            .await
+            .map_err(|e| storage_error("DynamoDB TTL error", e))?;
+
+        Ok(())
+    }
+
+    /// Strongly consistent read of a bucket and its version.
+    async fn load(&self, key: &str) -> Result<Option<(BucketState, u64)>, RateLimitError> {
+        let output = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .key(ATTR_KEY, AttributeValue::S(key.to_string()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| storage_error("DynamoDB get error", e))?;
+
+        let Some(item) = output.item() else {
+            return Ok(None);
+        };
+
+        let state = BucketState {
+            tokens: number(item, ATTR_TOKENS)?,
+            last_refill_ms: number(item, ATTR_LAST_REFILL)?,
+        };
+        Ok(Some((state, number(item, ATTR_VERSION)?)))
+    }
+
+    fn current_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+}
+
+#[async_trait]
+impl StorageBackend for DynamoDbBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        for _ in 0..MAX_CONDITIONAL_RETRIES {
+            let now_ms = Self::current_millis();
+            let loaded = self.load(key).await?;
+            let (mut state, version) = match loaded {
+                Some((state, version)) => (state, Some(version)),
+                None => (BucketState::full(&self.config, now_ms), None),
+            };
+
+            // Denials only refill, which is recomputed on the next read, so
+            // they don't need to spend a write.
+            if !state.try_consume(cost, &self.config, now_ms) {
+                return Ok(false);
+            }
+
+            let next_version = version.map_or(1, |v| v + 1);
+            let expires_at = now_ms / 1000 + BUCKET_TTL_SECS;
+            let mut update = self
+                .client
+                .update_item()
+                .table_name(&self.table)
+                .key(ATTR_KEY, AttributeValue::S(key.to_string()))
+                .update_expression(
+                    "SET #t = :tokens, #l = :last_refill, #v = :next_version, #e = :expires_at",
+                )
+                .expression_attribute_names("#t", ATTR_TOKENS)
+                .expression_attribute_names("#l", ATTR_LAST_REFILL)
+                .expression_attribute_names("#v", ATTR_VERSION)
+                .expression_attribute_names("#e", ATTR_EXPIRES_AT)
+                .expression_attribute_values(":tokens", AttributeValue::N(state.tokens.to_string()))
+                .expression_attribute_values(
+                    ":last_refill",
+                    AttributeValue::N(state.last_refill_ms.to_string()),
+                )
+                .expression_attribute_values(
+                    ":next_version",
+                    AttributeValue::N(next_version.to_string()),
+                )
+                .expression_attribute_values(
+                    ":expires_at",
+                    AttributeValue::N(expires_at.to_string()),
+                );
+
+            update = match version {
+                Some(version) => update
+                    .condition_expression("#v = :version")
+                    .expression_attribute_values(
+                        ":version",
+                        AttributeValue::N(version.to_string()),
+                    ),
+                None => update.condition_expression(format!("attribute_not_exists({})", ATTR_KEY)),
+            };
+
+            match update.send().await {
+                Ok(_) => return Ok(true),
+                Err(e)
+                    if e.as_service_error()
+                        .is_some_and(|se| se.is_conditional_check_failed_exception()) =>
+                {
+                    // Another writer won the race; re-read and try again.
+                    continue;
+                }
+                Err(e) => return Err(storage_error("DynamoDB update error", e)),
+            }
+        }
+
+        Err(RateLimitError::StorageError(format!(
+            "DynamoDB conditional update lost {} times for key: {}",
+            MAX_CONDITIONAL_RETRIES, key
+        )))
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        match self.load(key).await? {
+            Some((mut state, _)) => {
+                state.refill(&self.config, Self::current_millis());
+                Ok(self.config.capacity.saturating_sub(state.tokens))
+            }
+            None => Ok(0),
+        }
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.client
+            .delete_item()
+            .table_name(&self.table)
+            .key(ATTR_KEY, AttributeValue::S(key.to_string()))
+            .send()
+            .await
+            .map_err(|e| storage_error("DynamoDB delete error", e))?;
+        Ok(())
+    }
+}
+
+fn number(item: &HashMap<String, AttributeValue>, name: &str) -> Result<u64, RateLimitError> {
+    item.get(name)
+        .and_then(|v| v.as_n().ok())
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| {
+            RateLimitError::StorageError(format!(
+                "DynamoDB item missing numeric attribute: {}",
+                name
+            ))
+        })
+}
+
+fn storage_error(context: &str, e: impl std::error::Error) -> RateLimitError {
+    RateLimitError::StorageError(format!("{}: {}", context, e))
+}
+
+fn config_error(e: impl std::error::Error) -> RateLimitError {
+    RateLimitError::ConfigError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore] // Requires DynamoDB Local (AWS_ENDPOINT_URL=http://localhost:8000)
+    async fn test_dynamodb_backend() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        };
+
+        let backend = DynamoDbBackend::new("guardian_test_buckets", config).await;
+        let _ = backend.create_table(Throughput::OnDemand).await;
+        backend.reset("test_user").await.unwrap();
+
+        assert!(backend.take_token("test_user", 6).await.unwrap());
+        assert!(!backend.take_token("test_user", 6).await.unwrap());
+        assert_eq!(backend.get_usage("test_user").await.unwrap(), 6);
+
+        backend.reset("test_user").await.unwrap();
+    }
+}
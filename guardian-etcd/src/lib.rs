@@ -0,0 +1,230 @@
+use async_trait::async_trait;
+use etcd_client::{Client, Compare, CompareOp, PutOptions, Txn, TxnOp};
+use guardian_core::{BucketState, RateLimitError, StorageBackend, TokenBucketConfig};
+use parking_lot::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Idle buckets disappear with their lease after roughly this long.
+const BUCKET_TTL_SECS: i64 = 3600;
+
+/// How long one lease keeps being attached to new writes before a fresh one
+/// is granted. Buckets therefore live between `BUCKET_TTL_SECS` and
+/// `BUCKET_TTL_SECS + LEASE_ROTATION` after their last write, at the cost of
+/// one LeaseGrant per rotation instead of one per write.
+const LEASE_ROTATION: Duration = Duration::from_secs(60);
+
+/// Compare-and-swap transactions retried before reporting contention.
+const MAX_TXN_RETRIES: usize = 16;
+
+struct CurrentLease {
+    id: i64,
+    granted_at: Instant,
+}
+
+/// Token buckets stored as etcd keys under a prefix.
+///
+/// `take_token` reads the bucket, refills it locally, and writes it back in a
+/// transaction guarded by the key's mod revision, so concurrent limiters
+/// never double-spend. Keys are attached to rotating leases for expiry.
+pub struct EtcdBackend {
+    client: Client,
+    config: TokenBucketConfig,
+    prefix: String,
+    lease: Mutex<Option<CurrentLease>>,
+}
+
+impl EtcdBackend {
+    pub async fn new<E: AsRef<str>>(
+        endpoints: &[E],
+        config: TokenBucketConfig,
+    ) -> Result<Self, RateLimitError> {
+        let client = Client::connect(endpoints, None)
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("etcd connection error: {}", e)))?;
+
+        Ok(Self::with_client(client, config, "/guardian/buckets/"))
+    }
+
+    /// Use an existing client (e.g. with TLS or auth configured) and a custom
+    /// key prefix.
+    pub fn with_client(
+        client: Client,
+        config: TokenBucketConfig,
+        prefix: impl Into<String>,
+    ) -> Self {
+        Self {
+            client,
+            config,
+            prefix: prefix.into(),
+            lease: Mutex::new(None),
+        }
+    }
+
+    fn bucket_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// Current bucket state and mod revision, if the key exists.
+    async fn load(&self, bucket_key: &str) -> Result<Option<(BucketState, i64)>, RateLimitError> {
+        let mut client = self.client.clone();
+        let response = client
+            .get(bucket_key, None)
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("etcd get error: {}", e)))?;
+
+        match response.kvs().first() {
+            Some(kv) => Ok(Some((decode_state(kv.value())?, kv.mod_revision()))),
+            None => Ok(None),
+        }
+    }
+
+    async fn lease_id(&self) -> Result<i64, RateLimitError> {
+        if let Some(lease) = self.lease.lock().as_ref() {
+            if lease.granted_at.elapsed() < LEASE_ROTATION {
+                return Ok(lease.id);
+            }
+        }
+
+        let mut client = self.client.clone();
+        let ttl = BUCKET_TTL_SECS + LEASE_ROTATION.as_secs() as i64;
+        let id = client
+            .lease_grant(ttl, None)
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("etcd lease error: {}", e)))?
+            .id();
+
+        *self.lease.lock() = Some(CurrentLease {
+            id,
+            granted_at: Instant::now(),
+        });
+        Ok(id)
+    }
+
+    fn current_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+}
+
+#[async_trait]
+impl StorageBackend for EtcdBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        let bucket_key = self.bucket_key(key);
+
+        for _ in 0..MAX_TXN_RETRIES {
+            let now_ms = Self::current_millis();
+            let (mut state, guard) = match self.load(&bucket_key).await? {
+                Some((state, revision)) => (
+                    state,
+                    Compare::mod_revision(bucket_key.as_str(), CompareOp::Equal, revision),
+                ),
+                None => (
+                    BucketState::full(&self.config, now_ms),
+                    Compare::create_revision(bucket_key.as_str(), CompareOp::Equal, 0),
+                ),
+            };
+
+            // Refill is recomputed from last_refill on every read, so a
+            // denial has nothing worth writing back.
+            if !state.try_consume(cost, &self.config, now_ms) {
+                return Ok(false);
+            }
+
+            let lease = self.lease_id().await?;
+            let txn = Txn::new().when(vec![guard]).and_then(vec![TxnOp::put(
+                bucket_key.as_str(),
+                encode_state(&state),
+                Some(PutOptions::new().with_lease(lease)),
+            )]);
+
+            let mut client = self.client.clone();
+            let response = client
+                .txn(txn)
+                .await
+                .map_err(|e| RateLimitError::StorageError(format!("etcd txn error: {}", e)))?;
+
+            if response.succeeded() {
+                return Ok(true);
+            }
+            // The revision moved underneath us; re-read and retry.
+        }
+
+        Err(RateLimitError::StorageError(format!(
+            "etcd transaction lost {} times for key: {}",
+            MAX_TXN_RETRIES, key
+        )))
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        match self.load(&self.bucket_key(key)).await? {
+            Some((mut state, _)) => {
+                state.refill(&self.config, Self::current_millis());
+                Ok(self.config.capacity.saturating_sub(state.tokens))
+            }
+            None => Ok(0),
+        }
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let mut client = self.client.clone();
+        client
+            .delete(self.bucket_key(key), None)
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("etcd delete error: {}", e)))?;
+        Ok(())
+    }
+}
+
+fn encode_state(state: &BucketState) -> String {
+    format!("{}:{}", state.tokens, state.last_refill_ms)
+}
+
+fn decode_state(value: &[u8]) -> Result<BucketState, RateLimitError> {
+    let invalid = || RateLimitError::StorageError("etcd bucket value is malformed".to_string());
+
+    let text = std::str::from_utf8(value).map_err(|_| invalid())?;
+    let (tokens, last_refill_ms) = text.split_once(':').ok_or_else(invalid)?;
+    Ok(BucketState {
+        tokens: tokens.parse().map_err(|_| invalid())?,
+        last_refill_ms: last_refill_ms.parse().map_err(|_| invalid())?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let state = BucketState {
+            tokens: 42,
+            last_refill_ms: 1_700_000_000_123,
+        };
+        assert_eq!(
+            decode_state(encode_state(&state).as_bytes()).unwrap(),
+            state
+        );
+        assert!(decode_state(b"garbage").is_err());
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires etcd instance
+    async fn test_etcd_backend() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        };
+
+        let backend = EtcdBackend::new(&["localhost:2379"], config).await.unwrap();
+        backend.reset("test_user").await.unwrap();
+
+        assert!(backend.take_token("test_user", 6).await.unwrap());
+        assert!(!backend.take_token("test_user", 6).await.unwrap());
+        assert_eq!(backend.get_usage("test_user").await.unwrap(), 6);
+
+        backend.reset("test_user").await.unwrap();
+    }
+}
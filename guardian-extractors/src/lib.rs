@@ -0,0 +1,357 @@
+//! Extractors that turn a request into the key it's rate limited by,
+//! shared by Guardian's HTTP and gRPC middleware so that a key means the
+//! same thing whichever framework computed it.
+//!
+//! ```
+//! use guardian_extractors::{Extractor, HttpParts, TrustedProxies};
+//!
+//! let proxies = TrustedProxies::new(["10.0.0.0/8"]).unwrap();
+//! // "x-tenant:<id>:ip:<client>", or the client's address alone for
+//! // requests without a tenant.
+//! let key = Extractor::header("x-tenant")
+//!     .and(Extractor::client_ip(proxies.clone()))
+//!     .or(Extractor::client_ip(proxies));
+//!
+//! let req = http::Request::builder()
+//!     .header("x-forwarded-for", "203.0.113.9, 10.1.2.3")
+//!     .header("x-tenant", "acme")
+//!     .body(())
+//!     .unwrap();
+//! let parts = HttpParts::new(&req).with_peer("10.0.0.2".parse().ok());
+//! assert_eq!(key.extract(&parts).as_deref(), Some("x-tenant:acme:ip:203.0.113.9"));
+//! ```
+//!
+//! Middleware crates accept an [`Extractor`] and hand it a
+//! [`RequestParts`] view of their own request type.
+
+mod proxy;
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde_json::Value;
+
+pub use proxy::{InvalidCidr, TrustedProxies};
+
+/// The parts of a request that keys are extracted from.
+pub trait RequestParts {
+    /// Every value of the header `name` that is valid UTF-8, in order.
+    fn header_values(&self, name: &str) -> Vec<&str>;
+
+    /// The first value of the header `name`.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.header_values(name).into_iter().next()
+    }
+
+    /// The address of the connection's other end.
+    fn peer_ip(&self) -> Option<IpAddr>;
+
+    /// The request path, such as `/users/7` or `/pkg.Service/Method`.
+    fn path(&self) -> &str;
+
+    /// The route template the request matched, such as `/users/:id`, when
+    /// the framework knows it.
+    fn route(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// A [`RequestParts`] view of an `http::Request`. The peer is taken from a
+/// `SocketAddr` extension unless [set](Self::with_peer).
+pub struct HttpParts<'a, B> {
+    request: &'a http::Request<B>,
+    peer: Option<IpAddr>,
+    route: Option<&'a str>,
+}
+
+impl<'a, B> HttpParts<'a, B> {
+    pub fn new(request: &'a http::Request<B>) -> Self {
+        Self {
+            request,
+            peer: request
+                .extensions()
+                .get::<SocketAddr>()
+                .map(|addr| addr.ip()),
+            route: None,
+        }
+    }
+
+    pub fn with_peer(mut self, peer: Option<IpAddr>) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    pub fn with_route(mut self, route: Option<&'a str>) -> Self {
+        self.route = route;
+        self
+    }
+}
+
+impl<B> RequestParts for HttpParts<'_, B> {
+    fn header_values(&self, name: &str) -> Vec<&str> {
+        self.request
+            .headers()
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect()
+    }
+
+    fn peer_ip(&self) -> Option<IpAddr> {
+        self.peer
+    }
+
+    fn path(&self) -> &str {
+        self.request.uri().path()
+    }
+
+    fn route(&self) -> Option<&str> {
+        self.route
+    }
+}
+
+type KeyFn = dyn Fn(&dyn RequestParts) -> Option<String> + Send + Sync;
+
+/// How a request's key is found. Requests an extractor finds no key for
+/// (a missing header, no bearer token) are left to the middleware, which
+/// lets them through unchecked.
+#[derive(Clone)]
+pub enum Extractor {
+    /// The client's address, as `ip:<addr>`: the peer's, or behind
+    /// trusted proxies the one they recorded in `Forwarded` or
+    /// `X-Forwarded-For`.
+    ClientIp(TrustedProxies),
+    /// A header's value, as `<name>:<value>`, such as an API key.
+    Header(String),
+    /// A claim of the bearer token in `Authorization`, as
+    /// `<claim>:<value>`. The token's signature isn't checked, so run the
+    /// middleware after authentication.
+    JwtClaim(String),
+    /// The matched route template, as `route:<template>`.
+    Route,
+    /// The gRPC method, as `method:<package.Service>/<Method>`.
+    GrpcMethod,
+    /// Another extractor's key, as `<prefix>:<key>`.
+    Prefixed(String, Box<Extractor>),
+    /// Every extractor's key, joined by `:`; none unless all have one.
+    All(Vec<Extractor>),
+    /// The first key any of the extractors has.
+    First(Vec<Extractor>),
+    /// Whatever `f` returns for the request.
+    Custom(Arc<KeyFn>),
+}
+
+impl Extractor {
+    pub fn client_ip(proxies: TrustedProxies) -> Self {
+        Self::ClientIp(proxies)
+    }
+
+    pub fn header(name: impl Into<String>) -> Self {
+        Self::Header(name.into().to_ascii_lowercase())
+    }
+
+    pub fn jwt_claim(claim: impl Into<String>) -> Self {
+        Self::JwtClaim(claim.into())
+    }
+
+    pub fn route() -> Self {
+        Self::Route
+    }
+
+    pub fn grpc_method() -> Self {
+        Self::GrpcMethod
+    }
+
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(&dyn RequestParts) -> Option<String> + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(f))
+    }
+
+    /// This key, or `other`'s when this one has none.
+    pub fn or(self, other: Extractor) -> Self {
+        match self {
+            Self::First(mut extractors) => {
+                extractors.push(other);
+                Self::First(extractors)
+            }
+            this => Self::First(vec![this, other]),
+        }
+    }
+
+    /// This key and `other`'s together, for a bucket per combination.
+    pub fn and(self, other: Extractor) -> Self {
+        match self {
+            Self::All(mut extractors) => {
+                extractors.push(other);
+                Self::All(extractors)
+            }
+            this => Self::All(vec![this, other]),
+        }
+    }
+
+    /// This key under `prefix:`, giving it buckets (and limit rules) of
+    /// its own.
+    pub fn prefixed(self, prefix: impl Into<String>) -> Self {
+        Self::Prefixed(prefix.into(), Box::new(self))
+    }
+
+    /// The request's key, if it has one.
+    pub fn extract(&self, parts: &dyn RequestParts) -> Option<String> {
+        match self {
+            Self::ClientIp(proxies) => Some(format!("ip:{}", proxies.client(parts)?)),
+            Self::Header(name) => Some(format!("{}:{}", name, parts.header(name)?)),
+            Self::JwtClaim(claim) => Some(format!("{}:{}", claim, jwt_claim(parts, claim)?)),
+            Self::Route => Some(format!("route:{}", parts.route()?)),
+            Self::GrpcMethod => {
+                let method = parts.path().strip_prefix('/')?;
+                method.contains('/').then(|| format!("method:{}", method))
+            }
+            Self::Prefixed(prefix, inner) => Some(format!("{}:{}", prefix, inner.extract(parts)?)),
+            Self::All(extractors) => extractors
+                .iter()
+                .map(|extractor| extractor.extract(parts))
+                .collect::<Option<Vec<_>>>()
+                .map(|keys| keys.join(":")),
+            Self::First(extractors) => extractors
+                .iter()
+                .find_map(|extractor| extractor.extract(parts)),
+            Self::Custom(f) => f(parts),
+        }
+    }
+}
+
+impl fmt::Debug for Extractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ClientIp(proxies) => f.debug_tuple("ClientIp").field(proxies).finish(),
+            Self::Header(name) => f.debug_tuple("Header").field(name).finish(),
+            Self::JwtClaim(claim) => f.debug_tuple("JwtClaim").field(claim).finish(),
+            Self::Route => f.write_str("Route"),
+            Self::GrpcMethod => f.write_str("GrpcMethod"),
+            Self::Prefixed(prefix, inner) => f
+                .debug_tuple("Prefixed")
+                .field(prefix)
+                .field(inner)
+                .finish(),
+            Self::All(extractors) => f.debug_tuple("All").field(extractors).finish(),
+            Self::First(extractors) => f.debug_tuple("First").field(extractors).finish(),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// `claim` from the payload of the request's bearer token.
+fn jwt_claim(parts: &dyn RequestParts, claim: &str) -> Option<String> {
+    let token = parts.header("authorization")?.strip_prefix("Bearer ")?;
+    let payload = URL_SAFE_NO_PAD.decode(token.split('.').nth(1)?).ok()?;
+    let claims: Value = serde_json::from_slice(&payload).ok()?;
+    match claims.get(claim)? {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> http::Request<()> {
+        let mut builder = http::Request::builder().uri("/acme.Reports/Generate");
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    fn client_ip(req: &http::Request<()>, peer: &str) -> Option<String> {
+        let proxies = TrustedProxies::new(["10.0.0.0/8", "fd00::/8"]).unwrap();
+        let parts = HttpParts::new(req).with_peer(peer.parse().ok());
+        Extractor::client_ip(proxies).extract(&parts)
+    }
+
+    #[test]
+    fn test_client_ip_behind_trusted_proxies() {
+        let xff = request(&[("x-forwarded-for", "198.51.100.4, 203.0.113.9, 10.1.2.3")]);
+        // Hops are only believed from a trusted peer, and only back to the
+        // first one a trusted proxy didn't add.
+        assert_eq!(
+            client_ip(&xff, "10.0.0.2").as_deref(),
+            Some("ip:203.0.113.9")
+        );
+        assert_eq!(
+            client_ip(&xff, "192.0.2.1").as_deref(),
+            Some("ip:192.0.2.1")
+        );
+
+        let forwarded = request(&[
+            ("forwarded", r#"for="[2001:db8::17]:4711";proto=https"#),
+            ("forwarded", "for=10.3.3.3"),
+            ("x-forwarded-for", "198.51.100.4"),
+        ]);
+        assert_eq!(
+            client_ip(&forwarded, "fd00::1").as_deref(),
+            Some("ip:2001:db8::17")
+        );
+
+        let internal = request(&[("x-forwarded-for", "10.9.9.9")]);
+        assert_eq!(
+            client_ip(&internal, "10.0.0.2").as_deref(),
+            Some("ip:10.9.9.9")
+        );
+        let hidden = request(&[("forwarded", "for=unknown")]);
+        assert_eq!(
+            client_ip(&hidden, "10.0.0.2").as_deref(),
+            Some("ip:unknown")
+        );
+        assert_eq!(client_ip(&xff, "not a peer"), None);
+    }
+
+    #[test]
+    fn test_extractors_and_combinators() {
+        let payload = URL_SAFE_NO_PAD.encode(r#"{"sub":"user-7","org":42}"#);
+        let bearer = format!("Bearer e30.{}.sig", payload);
+        let req = request(&[("authorization", &bearer), ("X-Api-Key", "k1")]);
+        let parts = HttpParts::new(&req).with_route(Some("/reports/:id"));
+
+        let keys: Vec<_> = [
+            Extractor::header("X-Api-Key"),
+            Extractor::jwt_claim("sub"),
+            Extractor::jwt_claim("org"),
+            Extractor::jwt_claim("email"),
+            Extractor::route(),
+            Extractor::grpc_method(),
+            Extractor::jwt_claim("org")
+                .and(Extractor::route())
+                .prefixed("reports"),
+            Extractor::jwt_claim("email").and(Extractor::route()),
+            Extractor::jwt_claim("email")
+                .or(Extractor::header("x-client"))
+                .or(Extractor::custom(|parts| {
+                    Some(parts.path().len().to_string())
+                })),
+        ]
+        .iter()
+        .map(|extractor| extractor.extract(&parts))
+        .collect();
+        assert_eq!(
+            keys,
+            [
+                Some("x-api-key:k1".to_string()),
+                Some("sub:user-7".to_string()),
+                Some("org:42".to_string()),
+                None,
+                Some("route:/reports/:id".to_string()),
+                Some("method:acme.Reports/Generate".to_string()),
+                Some("reports:org:42:route:/reports/:id".to_string()),
+                None,
+                Some("22".to_string()),
+            ]
+        );
+    }
+}
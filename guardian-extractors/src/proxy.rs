@@ -0,0 +1,197 @@
+//! Finding the client's address behind reverse proxies, from
+//! `Forwarded` (RFC 7239) or `X-Forwarded-For`.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::RequestParts;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid proxy address or CIDR range: {0}")]
+pub struct InvalidCidr(pub String);
+
+/// An address range, `10.0.0.0/8` or `2001:db8::/32`; a bare address is a
+/// range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidCidr(s.to_string());
+        let (network, prefix) = match s.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (s, None),
+        };
+        let network = network
+            .parse::<IpAddr>()
+            .map_err(|_| invalid())?
+            .to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse()
+                .ok()
+                .filter(|&p| p <= max)
+                .ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// The proxies whose forwarding headers are believed. Headers from anyone
+/// else are ignored, since clients can send them too.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies(Vec<Cidr>);
+
+impl TrustedProxies {
+    /// Trust no one: the client is always the peer.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Trust peers in `ranges`, given as addresses or CIDR ranges.
+    pub fn new<I, S>(ranges: I) -> Result<Self, InvalidCidr>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        ranges
+            .into_iter()
+            .map(|range| range.as_ref().trim().parse())
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+
+    /// The client's address: the peer's, unless the peer is trusted, then
+    /// the nearest hop the trusted proxies recorded that isn't one of them.
+    /// A hop that isn't an address (`unknown`, an obfuscated `_name`) is
+    /// returned as recorded.
+    pub(crate) fn client(&self, parts: &dyn RequestParts) -> Option<String> {
+        let peer = parts.peer_ip()?;
+        if !self.contains(peer) {
+            return Some(peer.to_string());
+        }
+        let forwarded = parts.header_values("forwarded");
+        let hops: Vec<&str> = if forwarded.is_empty() {
+            parts
+                .header_values("x-forwarded-for")
+                .into_iter()
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .filter(|hop| !hop.is_empty())
+                .collect()
+        } else {
+            forwarded.into_iter().flat_map(forwarded_for).collect()
+        };
+        let mut client = peer.to_string();
+        for hop in hops.into_iter().rev() {
+            match parse_node(hop) {
+                Some(ip) if self.contains(ip) => client = ip.to_string(),
+                Some(ip) => return Some(ip.to_string()),
+                None => return Some(hop.to_string()),
+            }
+        }
+        Some(client)
+    }
+}
+
+/// The `for=` nodes of a `Forwarded` header value, in order.
+fn forwarded_for(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').filter_map(|element| {
+        element.split(';').find_map(|pair| {
+            let (name, node) = pair.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("for")
+                .then(|| node.trim().trim_matches('"'))
+        })
+    })
+}
+
+/// An address from a forwarding node: `1.2.3.4`, `1.2.3.4:80`, `::1` or
+/// `[::1]:80`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges() {
+        let proxies = TrustedProxies::new(["10.0.0.0/8", "192.168.1.7", "fd00::/8"]).unwrap();
+        assert!(proxies.contains("10.200.3.4".parse().unwrap()));
+        assert!(proxies.contains("::ffff:10.1.1.1".parse().unwrap()));
+        assert!(proxies.contains("192.168.1.7".parse().unwrap()));
+        assert!(!proxies.contains("192.168.1.8".parse().unwrap()));
+        assert!(proxies.contains("fd12::1".parse().unwrap()));
+        assert!(!proxies.contains("2001:db8::1".parse().unwrap()));
+        assert!(TrustedProxies::new(["0.0.0.0/0"])
+            .unwrap()
+            .contains("8.8.8.8".parse().unwrap()));
+
+        assert_eq!(
+            TrustedProxies::new(["10.0.0.0/33"]),
+            Err(InvalidCidr("10.0.0.0/33".to_string()))
+        );
+        assert!(TrustedProxies::new(["proxy.internal"]).is_err());
+    }
+
+    #[test]
+    fn test_forwarded_nodes() {
+        let nodes: Vec<_> =
+            forwarded_for(r#"for=192.0.2.60;proto=http, For="[2001:db8::17]:4711", by=x"#)
+                .collect();
+        assert_eq!(nodes, ["192.0.2.60", "[2001:db8::17]:4711"]);
+        assert_eq!(parse_node(nodes[1]), Some("2001:db8::17".parse().unwrap()));
+        assert_eq!(
+            parse_node("192.0.2.60:80"),
+            Some("192.0.2.60".parse().unwrap())
+        );
+        assert_eq!(parse_node("unknown"), None);
+    }
+}
@@ -0,0 +1,53 @@
+/*
+ * C bindings for the Guardian rate limiter client (guardian-ffi).
+ *
+ * Link against libguardian_ffi (shared or static). Calls block the calling
+ * thread. A client may be shared between threads; each thread has its own
+ * last error.
+ */
+
+#ifndef GUARDIAN_H
+#define GUARDIAN_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* guardian_check_limit results. */
+#define GUARDIAN_ALLOWED 1
+#define GUARDIAN_DENIED 0
+#define GUARDIAN_ERROR (-1)
+
+/* A connected client. */
+typedef struct GuardianClient GuardianClient;
+
+/*
+ * Connect to the service at addr, such as "http://127.0.0.1:50051".
+ * Returns NULL if that fails, with the reason in guardian_last_error().
+ * Free the client with guardian_free().
+ */
+GuardianClient *guardian_connect(const char *addr);
+
+/*
+ * Take cost tokens from client_id's bucket if it has them. Returns
+ * GUARDIAN_ALLOWED, GUARDIAN_DENIED, or GUARDIAN_ERROR when the service
+ * couldn't decide; the caller picks whether to fail open.
+ */
+int guardian_check_limit(const GuardianClient *client, const char *client_id, uint32_t cost);
+
+/* Close client. NULL is ignored. */
+void guardian_free(GuardianClient *client);
+
+/*
+ * Why the last call on this thread failed, or NULL if none has. The string
+ * stays valid until the next failing call on the thread.
+ */
+const char *guardian_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* GUARDIAN_H */
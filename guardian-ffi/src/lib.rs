@@ -0,0 +1,175 @@
+//! C bindings for the Guardian client, so C and C++ services can check
+//! limits without speaking gRPC themselves. The declarations are in
+//! `include/guardian.h`:
+//!
+//! ```c
+//! GuardianClient *client = guardian_connect("http://127.0.0.1:50051");
+//! if (client == NULL) {
+//!     fprintf(stderr, "guardian: %s\n", guardian_last_error());
+//!     return 1;
+//! }
+//! if (guardian_check_limit(client, "user123", 1) == GUARDIAN_DENIED) {
+//!     return 429;
+//! }
+//! guardian_free(client);
+//! ```
+//!
+//! Calls block the calling thread. A client may be shared between
+//! threads; each thread sees its own last error.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use guardian_client::blocking;
+
+/// `guardian_check_limit`: the request may go ahead.
+pub const GUARDIAN_ALLOWED: c_int = 1;
+/// `guardian_check_limit`: the request is over its limit.
+pub const GUARDIAN_DENIED: c_int = 0;
+/// `guardian_check_limit`: no decision; see `guardian_last_error`.
+pub const GUARDIAN_ERROR: c_int = -1;
+
+/// A connected client, opaque to C.
+pub struct GuardianClient(blocking::GuardianClient);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl Into<String>) {
+    // Interior NULs would cut the message short in C, so drop them.
+    let message = message.into().replace('\0', "");
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run `f`, turning a panic into an error instead of unwinding into C.
+fn guarded<T>(on_error: T, f: impl FnOnce() -> Result<T, String>) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(message)) => {
+            set_last_error(message);
+            on_error
+        }
+        Err(_) => {
+            set_last_error("guardian client panicked");
+            on_error
+        }
+    }
+}
+
+/// `ptr` as a `&str`, if it is a non-NULL, UTF-8 C string.
+///
+/// # Safety
+///
+/// A non-NULL `ptr` must point to a NUL-terminated string.
+unsafe fn str_arg<'a>(ptr: *const c_char, name: &str) -> Result<&'a str, String> {
+    if ptr.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+    CStr::from_ptr(ptr)
+        .to_str()
+        .map_err(|_| format!("{} is not valid UTF-8", name))
+}
+
+/// Connect to the service at `addr`, such as `http://127.0.0.1:50051`.
+/// Returns NULL if that fails, with the reason in `guardian_last_error`.
+/// Free the client with `guardian_free`.
+///
+/// # Safety
+///
+/// `addr` must be NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn guardian_connect(addr: *const c_char) -> *mut GuardianClient {
+    guarded(ptr::null_mut(), || {
+        let addr = str_arg(addr, "addr")?;
+        let client =
+            blocking::GuardianClient::connect(addr.to_string()).map_err(|e| e.to_string())?;
+        Ok(Box::into_raw(Box::new(GuardianClient(client))))
+    })
+}
+
+/// Take `cost` tokens from `client_id`'s bucket if it has them.
+/// Returns `GUARDIAN_ALLOWED`, `GUARDIAN_DENIED`, or `GUARDIAN_ERROR` when
+/// the service couldn't decide; the caller picks whether to fail open.
+///
+/// # Safety
+///
+/// `client` must be NULL or a pointer from `guardian_connect` not yet
+/// freed, and `client_id` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn guardian_check_limit(
+    client: *const GuardianClient,
+    client_id: *const c_char,
+    cost: u32,
+) -> c_int {
+    guarded(GUARDIAN_ERROR, || {
+        let client = client.as_ref().ok_or("client is NULL")?;
+        let client_id = str_arg(client_id, "client_id")?;
+        match client.0.check_limit(client_id, cost) {
+            Ok(true) => Ok(GUARDIAN_ALLOWED),
+            Ok(false) => Ok(GUARDIAN_DENIED),
+            Err(e) => Err(e.to_string()),
+        }
+    })
+}
+
+/// Close `client`. NULL is ignored.
+///
+/// # Safety
+///
+/// `client` must be NULL or a pointer from `guardian_connect` not already
+/// freed, and no other thread may be using it.
+#[no_mangle]
+pub unsafe extern "C" fn guardian_free(client: *mut GuardianClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Why the last call on this thread failed, or NULL if none has. The
+/// string stays valid until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn guardian_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        let message = guardian_last_error();
+        assert!(!message.is_null());
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn test_errors_are_reported_not_raised() {
+        unsafe {
+            assert!(guardian_connect(ptr::null()).is_null());
+            assert_eq!(last_error(), "addr is NULL");
+
+            let bad = CString::new("not a url").unwrap();
+            assert!(guardian_connect(bad.as_ptr()).is_null());
+            // Nothing listens on port 1.
+            let down = CString::new("http://127.0.0.1:1").unwrap();
+            assert!(guardian_connect(down.as_ptr()).is_null());
+            assert!(last_error().contains("Connection error"));
+
+            let id = CString::new("user1").unwrap();
+            let checked = guardian_check_limit(ptr::null(), id.as_ptr(), 1);
+            assert_eq!(checked, GUARDIAN_ERROR);
+            assert_eq!(last_error(), "client is NULL");
+
+            guardian_free(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use async_trait::async_trait;
+use foundationdb::options::MutationType;
+use foundationdb::tuple::pack;
+use foundationdb::{Database, FdbBindingError, RetryableTransaction};
+use guardian_core::{BucketState, RateLimitError, StorageBackend, TokenBucketConfig};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Token buckets stored in FoundationDB, one pair of keys per bucket under
+/// the tuple prefix `(prefix, key)`.
+///
+/// Every operation runs in a FoundationDB transaction, so consumption is
+/// strictly serializable across all limiters sharing the cluster: reads of
+/// the bucket add conflict ranges, and a concurrent writer forces a retry
+/// (handled by `Database::run`). The token count is updated with an atomic
+/// ADD of the net change rather than a blind overwrite.
+///
+/// FoundationDB has no key expiry, so buckets live until `reset`.
+pub struct FoundationDbBackend {
+    db: Database,
+    config: TokenBucketConfig,
+    prefix: String,
+}
+
+impl FoundationDbBackend {
+    /// Open the cluster named by `cluster_file` (or the default cluster
+    /// file). The FoundationDB network thread must already be running; keep
+    /// the guard returned by `foundationdb::boot()` alive for the life of
+    /// the process.
+    pub fn new(
+        cluster_file: Option<&str>,
+        config: TokenBucketConfig,
+    ) -> Result<Self, RateLimitError> {
+        let db = Database::new(cluster_file).map_err(|e| {
+            RateLimitError::StorageError(format!("FoundationDB connection error: {}", e))
+        })?;
+        Ok(Self::with_database(db, config, "guardian"))
+    }
+
+    pub fn with_database(
+        db: Database,
+        config: TokenBucketConfig,
+        prefix: impl Into<String>,
+    ) -> Self {
+        Self {
+            db,
+            config,
+            prefix: prefix.into(),
+        }
+    }
+
+    fn keys(&self, key: &str) -> (Vec<u8>, Vec<u8>) {
+        (
+            pack(&(self.prefix.as_str(), key, "tokens")),
+            pack(&(self.prefix.as_str(), key, "last_refill_ms")),
+        )
+    }
+
+    fn current_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+}
+
+/// Read a bucket inside `trx`. Non-snapshot reads, so a concurrent change
+/// to either key makes this transaction conflict and retry.
+async fn load(
+    trx: &RetryableTransaction,
+    tokens_key: &[u8],
+    refill_key: &[u8],
+) -> Result<Option<BucketState>, FdbBindingError> {
+    let tokens = trx.get(tokens_key, false).await?;
+    let last_refill = trx.get(refill_key, false).await?;
+
+    match (tokens, last_refill) {
+        (Some(tokens), Some(last_refill)) => Ok(Some(BucketState {
+            tokens: decode_i64(&tokens)?.max(0) as u64,
+            last_refill_ms: decode_i64(&last_refill)? as u64,
+        })),
+        _ => Ok(None),
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FoundationDbBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        let (tokens_key, refill_key) = self.keys(key);
+        let config = &self.config;
+
+        self.db
+            .run(|trx, _maybe_committed| {
+                let tokens_key = tokens_key.clone();
+                let refill_key = refill_key.clone();
+                async move {
+                    let now_ms = Self::current_millis();
+                    let loaded = load(&trx, &tokens_key, &refill_key).await?;
+                    let before = loaded.as_ref().map_or(0, |state| state.tokens);
+                    let mut state = loaded.unwrap_or_else(|| BucketState::full(config, now_ms));
+
+                    // A denial only refills, which the next read recomputes,
+                    // so it commits as a read-only transaction.
+                    if !state.try_consume(cost, config, now_ms) {
+                        return Ok(false);
+                    }
+
+                    // ADD on a missing key starts from zero, so this also
+                    // initializes new buckets.
+                    let delta = state.tokens as i64 - before as i64;
+                    trx.atomic_op(&tokens_key, &delta.to_le_bytes(), MutationType::Add);
+                    trx.set(&refill_key, &(state.last_refill_ms as i64).to_le_bytes());
+                    Ok(true)
+                }
+            })
+            .await
+            .map_err(fdb_error)
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let (tokens_key, refill_key) = self.keys(key);
+        let config = &self.config;
+
+        self.db
+            .run(|trx, _maybe_committed| {
+                let tokens_key = tokens_key.clone();
+                let refill_key = refill_key.clone();
+                async move {
+                    Ok(match load(&trx, &tokens_key, &refill_key).await? {
+                        Some(mut state) => {
+                            state.refill(config, Self::current_millis());
+                            config.capacity.saturating_sub(state.tokens)
+                        }
+                        None => 0,
+                    })
+                }
+            })
+            .await
+            .map_err(fdb_error)
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let (tokens_key, refill_key) = self.keys(key);
+
+        self.db
+            .run(|trx, _maybe_committed| {
+                let tokens_key = tokens_key.clone();
+                let refill_key = refill_key.clone();
+                async move {
+                    trx.clear(&tokens_key);
+                    trx.clear(&refill_key);
+                    Ok(())
+                }
+            })
+            .await
+            .map_err(fdb_error)
+    }
+}
+
+/// Values are little-endian i64, the encoding FoundationDB's atomic ADD uses.
+fn decode_i64(bytes: &[u8]) -> Result<i64, FdbBindingError> {
+    let bytes: [u8; 8] = bytes.try_into().map_err(|_| {
+        FdbBindingError::CustomError("FoundationDB bucket value is malformed".into())
+    })?;
+    Ok(i64::from_le_bytes(bytes))
+}
+
+fn fdb_error(e: FdbBindingError) -> RateLimitError {
+    RateLimitError::StorageError(format!("FoundationDB error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    #[ignore] // Requires a FoundationDB cluster and libfdb_c
+    async fn test_foundationdb_backend() {
+        let _network = unsafe { foundationdb::boot() };
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        };
+
+        let backend = FoundationDbBackend::new(None, config).unwrap();
+        backend.reset("test_user").await.unwrap();
+
+        assert!(backend.take_token("test_user", 6).await.unwrap());
+        assert!(!backend.take_token("test_user", 6).await.unwrap());
+        assert_eq!(backend.get_usage("test_user").await.unwrap(), 6);
+
+        backend.reset("test_user").await.unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+//! Per-key PN-counters for [`GossipMode::Crdt`](crate::GossipMode::Crdt).
+//! Each node only ever adds to its own entry in a key's counter, and peers
+//! exchange whole counters, merging by taking the larger of each node's
+//! entry. Merges are idempotent and order-independent, so a lost, repeated
+//! or delayed datagram changes nothing once a later one arrives, and nodes
+//! cut off from each other agree again as soon as they can talk.
+//!
+//! Resets can't be expressed as counts: a key's counter carries an epoch,
+//! bumped by a reset, and a counter from a later epoch replaces one from
+//! an earlier.
+//!
+//! Entries that haven't changed for a while are retired everywhere alike,
+//! so counters don't collect the entries of every node that ever took from
+//! a key. A node taking from a key again after its entry was retired starts
+//! a new one, which replaces any copy of the old still going round.
+
+use std::collections::{btree_map, BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+/// One node's entry in a key's counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Count {
+    /// When the node started this entry; a later one replaces it.
+    pub since_ms: u64,
+    pub updated_ms: u64,
+    pub spent: u64,
+    /// Tokens given back; never more than `spent`.
+    pub refunded: u64,
+}
+
+impl Count {
+    fn value(&self) -> u64 {
+        self.spent.saturating_sub(self.refunded)
+    }
+}
+
+/// A key's counter, as exchanged between nodes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    pub epoch: u64,
+    /// Entries by node id.
+    pub counts: BTreeMap<u64, Count>,
+}
+
+impl State {
+    /// Tokens taken by every node, less refunds.
+    fn value(&self) -> u64 {
+        self.counts
+            .values()
+            .map(Count::value)
+            .fold(0, u64::saturating_add)
+    }
+}
+
+/// How the local bucket must change to reflect a key's counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Spent(u64),
+    Refunded(u64),
+}
+
+#[derive(Default)]
+struct KeyCounter {
+    state: State,
+    /// How much of the counter the local bucket reflects.
+    charged: u64,
+}
+
+/// Every key's counter on one node.
+pub struct Counters {
+    id: u64,
+    /// How long an entry lasts without changing.
+    ttl: Duration,
+    keys: HashMap<String, KeyCounter>,
+    /// Keys changed since the counters were last sent.
+    dirty: HashSet<String>,
+}
+
+impl Counters {
+    pub fn new(id: u64, ttl: Duration) -> Self {
+        Self {
+            id,
+            ttl,
+            keys: HashMap::new(),
+            dirty: HashSet::new(),
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.keys.contains_key(key)
+    }
+
+    pub fn is_dirty(&self, key: &str) -> bool {
+        self.dirty.contains(key)
+    }
+
+    /// Count `tokens` this node took from `key`, which its bucket already
+    /// reflects.
+    pub fn spend(&mut self, key: &str, tokens: u64, now_ms: u64) {
+        let counter = self.keys.entry(key.to_string()).or_default();
+        own(counter, self.id, now_ms).spent += tokens;
+        counter.charged += tokens;
+        self.dirty.insert(key.to_string());
+    }
+
+    /// Count up to `tokens` of what this node took from `key` as given
+    /// back, which its bucket already reflects. Refunds beyond what it took
+    /// stay local.
+    pub fn refund(&mut self, key: &str, tokens: u64, now_ms: u64) {
+        let counter = self.keys.entry(key.to_string()).or_default();
+        let count = own(counter, self.id, now_ms);
+        let refunded = tokens.min(count.value());
+        count.refunded += refunded;
+        counter.charged = counter.charged.saturating_sub(refunded);
+        self.dirty.insert(key.to_string());
+    }
+
+    /// Start `key` over, here and, once sent, on every peer.
+    pub fn reset(&mut self, key: &str) {
+        let counter = self.keys.entry(key.to_string()).or_default();
+        let epoch = counter.state.epoch + 1;
+        *counter = KeyCounter::default();
+        counter.state.epoch = epoch;
+        self.dirty.insert(key.to_string());
+    }
+
+    /// Merge a peer's counter for `key`. Returns whether it reset the key,
+    /// whose bucket is then full again.
+    pub fn merge(&mut self, key: String, remote: State, now_ms: u64) -> bool {
+        let expired = now_ms.saturating_sub(self.ttl.as_millis() as u64);
+        let live = |count: &Count| count.updated_ms >= expired;
+        if !self.keys.contains_key(&key) && remote.epoch == 0 && !remote.counts.values().any(live) {
+            return false;
+        }
+        let counter = self.keys.entry(key.clone()).or_default();
+        if remote.epoch < counter.state.epoch {
+            // Tell the peer about the later epoch.
+            self.dirty.insert(key);
+            return false;
+        }
+        let reset = remote.epoch > counter.state.epoch;
+        if reset {
+            *counter = KeyCounter::default();
+            counter.state.epoch = remote.epoch;
+        }
+        let mut changed = reset;
+        for (node, theirs) in remote.counts {
+            match counter.state.counts.entry(node) {
+                // Retired here, or about to be.
+                btree_map::Entry::Vacant(_) if !live(&theirs) => {}
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(theirs);
+                    changed = true;
+                }
+                btree_map::Entry::Occupied(mut entry) => {
+                    let ours = entry.get_mut();
+                    if theirs.since_ms > ours.since_ms {
+                        // What the old entry charged stays charged.
+                        counter.charged = counter.charged.saturating_sub(ours.value());
+                        *ours = theirs;
+                        changed = true;
+                    } else if theirs.since_ms == ours.since_ms
+                        && (theirs.spent > ours.spent || theirs.refunded > ours.refunded)
+                    {
+                        ours.spent = ours.spent.max(theirs.spent);
+                        ours.refunded = ours.refunded.max(theirs.refunded);
+                        ours.updated_ms = ours.updated_ms.max(theirs.updated_ms);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if changed {
+            // Pass it on, for peers the sender can't reach.
+            self.dirty.insert(key);
+        }
+        reset
+    }
+
+    /// What the bucket for `key` hasn't been charged or credited with yet,
+    /// counted from now on as applied.
+    pub fn settle(&mut self, key: &str) -> Option<Change> {
+        let counter = self.keys.get_mut(key)?;
+        let value = counter.state.value();
+        let change = if value > counter.charged {
+            Change::Spent(value - counter.charged)
+        } else if value < counter.charged {
+            Change::Refunded(counter.charged - value)
+        } else {
+            return None;
+        };
+        counter.charged = value;
+        Some(change)
+    }
+
+    /// The counters changed since the last call, or all of them.
+    pub fn drain(&mut self, all: bool) -> Vec<(String, State)> {
+        let dirty = std::mem::take(&mut self.dirty);
+        let keys = &self.keys;
+        let state = |key: &String| Some((key.clone(), keys.get(key)?.state.clone()));
+        if all {
+            keys.keys().filter_map(state).collect()
+        } else {
+            dirty.iter().filter_map(state).collect()
+        }
+    }
+
+    /// Retire entries that outlived the ttl, without giving back what they
+    /// charged, then drop counters left without entries unless `in_use` or
+    /// not yet sent.
+    pub fn prune(&mut self, now_ms: u64, in_use: impl Fn(&str) -> bool) {
+        let expired = now_ms.saturating_sub(self.ttl.as_millis() as u64);
+        let dirty = &self.dirty;
+        self.keys.retain(|key, counter| {
+            counter.state.counts.retain(|_, count| {
+                let live = count.updated_ms >= expired;
+                if !live {
+                    counter.charged = counter.charged.saturating_sub(count.value());
+                }
+                live
+            });
+            !counter.state.counts.is_empty() || in_use(key) || dirty.contains(key)
+        });
+    }
+}
+
+/// This node's entry in `counter`, marked as updated.
+fn own(counter: &mut KeyCounter, id: u64, now_ms: u64) -> &mut Count {
+    let count = counter.state.counts.entry(id).or_insert(Count {
+        since_ms: now_ms,
+        updated_ms: now_ms,
+        spent: 0,
+        refunded: 0,
+    });
+    count.updated_ms = now_ms;
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    /// Merge everything `from` has into `to`.
+    fn sync(from: &mut Counters, to: &mut Counters, now_ms: u64) {
+        for (key, state) in from.drain(true) {
+            to.merge(key, state, now_ms);
+        }
+    }
+
+    #[test]
+    fn test_merges_converge_whatever_the_order() {
+        let (mut a, mut b, mut c) = (
+            Counters::new(1, TTL),
+            Counters::new(2, TTL),
+            Counters::new(3, TTL),
+        );
+        a.spend("user1", 4, 1_000);
+        b.spend("user1", 3, 1_000);
+        c.spend("user1", 2, 1_000);
+        c.refund("user1", 1, 1_000);
+
+        // `a` and `c` never talk directly, and `b` hears from `a` twice.
+        sync(&mut a, &mut b, 1_000);
+        sync(&mut c, &mut b, 1_000);
+        sync(&mut a, &mut b, 1_000);
+        sync(&mut b, &mut a, 1_000);
+        sync(&mut b, &mut c, 1_000);
+        for counters in [&a, &b, &c] {
+            assert_eq!(counters.keys["user1"].state.value(), 8);
+        }
+        // Each bucket is charged only what other nodes took.
+        assert_eq!(a.settle("user1"), Some(Change::Spent(4)));
+        assert_eq!(c.settle("user1"), Some(Change::Spent(7)));
+        assert_eq!(c.settle("user1"), None);
+    }
+
+    #[test]
+    fn test_later_epochs_win() {
+        let (mut a, mut b) = (Counters::new(1, TTL), Counters::new(2, TTL));
+        a.spend("user1", 5, 1_000);
+        sync(&mut a, &mut b, 1_000);
+        b.reset("user1");
+        b.spend("user1", 1, 1_000);
+
+        let (key, state) = b.drain(false).remove(0);
+        assert!(a.merge(key, state, 1_000));
+        assert_eq!(a.settle("user1"), Some(Change::Spent(1)));
+
+        // A counter from before the reset is answered with the current one.
+        assert!(!b.merge("user1".to_string(), State::default(), 1_000));
+        assert!(b.is_dirty("user1"));
+    }
+
+    #[test]
+    fn test_retired_entries_are_not_charged_again() {
+        let (mut a, mut b) = (Counters::new(1, TTL), Counters::new(2, TTL));
+        a.spend("user1", 5, 1_000);
+        sync(&mut a, &mut b, 1_000);
+        assert_eq!(b.settle("user1"), Some(Change::Spent(5)));
+        let old = a.drain(true);
+        b.drain(false);
+
+        b.prune(70_000, |_| false);
+        assert!(!b.contains("user1"));
+        // A copy of the retired entry comes round again.
+        for (key, state) in old {
+            b.merge(key, state, 70_000);
+        }
+        assert!(!b.contains("user1"));
+
+        // `a` starts a new entry, which replaces the old one.
+        a.prune(70_000, |_| true);
+        a.spend("user1", 2, 70_000);
+        sync(&mut a, &mut b, 70_000);
+        assert_eq!(b.settle("user1"), Some(Change::Spent(2)));
+    }
+}
@@ -0,0 +1,761 @@
+//! Approximate global rate limiting without a shared datastore. Every
+//! Guardian node keeps its own copy of each bucket, and tells its peers
+//! over UDP how many tokens it took from which keys; each node takes what
+//! its peers report out of its own copy. A key's budget is thus shared by
+//! the whole cluster, give or take what was spent within the last gossip
+//! interval (and anything lost in transit, which only ever errs towards
+//! allowing).
+//!
+//! ```no_run
+//! use guardian_core::{RateLimiter, TokenBucketConfig};
+//! use guardian_gossip::{GossipBackend, GossipConfig, GossipNode};
+//!
+//! # async fn example() -> std::io::Result<()> {
+//! let node = GossipNode::start(GossipConfig {
+//!     bind: "0.0.0.0:7946".to_string(),
+//!     peers: vec!["guardian-2:7946".to_string(), "guardian-3:7946".to_string()],
+//!     ..GossipConfig::default()
+//! })
+//! .await?;
+//! let limiter = RateLimiter::new(GossipBackend::new(node, TokenBucketConfig::default()), false);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every node sends to every peer, which suits clusters of up to a few
+//! dozen nodes. Peers are named by address and re-resolved periodically,
+//! so a DNS name covering a changing set of hosts (a headless Kubernetes
+//! service, say) can stand for all of them.
+//!
+//! Between regions, where links drop packets and partitions happen,
+//! [`GossipMode::Crdt`] exchanges each key's whole PN-counter instead of
+//! what changed. A lost datagram is made up for by the next, and regions
+//! cut off from each other keep limiting on their own, each charging the
+//! others' spending to its buckets once they reconnect.
+//!
+//! Nodes also pass on the demand reported through
+//! `StorageBackend::exchange_demand`, so that a `SlicedBackend` can split
+//! limits between regions over gossip rather than a global store.
+
+use async_trait::async_trait;
+use guardian_core::{
+    page_of_keys, BucketSnapshot, BucketState, Clock, RateLimitError, StorageBackend, SystemClock,
+    TokenBucketConfig, TokenDecision,
+};
+use parking_lot::Mutex;
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+mod crdt;
+mod wire;
+
+use crdt::{Change, Counters};
+use wire::{Delta, Demand};
+
+/// How often peer addresses are looked up again.
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often full, idle buckets are dropped.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often every counter is sent in CRDT mode, not just changed ones.
+const FULL_SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tokens peers spent on a key this node hasn't used are held against its
+/// first use for this long. In CRDT mode, how long a node's entry in a
+/// key's counter lasts after it last took from the key.
+const UNCLAIMED_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct GossipConfig {
+    /// UDP address peers send to, e.g. `0.0.0.0:7946`.
+    pub bind: String,
+    /// Peers' gossip addresses as `host:port`. A name resolving to several
+    /// addresses names them all, and may include this node: what it sends
+    /// itself is ignored.
+    pub peers: Vec<String>,
+    /// How often local spending is sent to the peers (default 100ms). The
+    /// cluster can overshoot a limit by what the other nodes spend in one
+    /// interval.
+    pub interval: Duration,
+    pub mode: GossipMode,
+}
+
+/// What nodes tell each other.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GossipMode {
+    /// Tokens taken since the last interval. Cheap, but a lost datagram's
+    /// spending is never charged to the peers it was meant for.
+    #[default]
+    Deltas,
+    /// Each changed key's counter of what every node took from it, and
+    /// every counter every few seconds. Lost datagrams and partitions only
+    /// delay charging the other nodes' spending, and nodes that can't
+    /// reach each other directly hear of it through the ones between. For
+    /// nodes in different regions.
+    Crdt,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:7946".to_string(),
+            peers: Vec::new(),
+            interval: Duration::from_millis(100),
+            mode: GossipMode::Deltas,
+        }
+    }
+}
+
+/// Counters describing a node's exchanges with its peers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GossipStats {
+    pub datagrams_sent: u64,
+    pub datagrams_received: u64,
+    /// Datagrams that weren't gossip, or came from a different version.
+    pub datagrams_rejected: u64,
+    pub send_errors: u64,
+}
+
+struct Bucket {
+    state: BucketState,
+    config: TokenBucketConfig,
+}
+
+#[derive(Default)]
+struct Table {
+    buckets: HashMap<String, Bucket>,
+    /// Tokens peers spent on keys without a local bucket, and when last.
+    unclaimed: HashMap<String, (u64, Instant)>,
+    /// Local changes not yet sent.
+    outgoing: HashMap<String, Delta>,
+    /// Every key's counter, in CRDT mode.
+    counters: Option<Counters>,
+    /// Each key's demand by node, reported here or by peers, and when it
+    /// lapses.
+    demands: HashMap<String, HashMap<String, (u64, Instant)>>,
+    /// Demand reported here not yet sent.
+    outgoing_demands: Vec<(String, Demand)>,
+    stats: GossipStats,
+}
+
+impl Table {
+    /// The bucket for `key`, charged with anything peers spent on it before
+    /// it was first used here.
+    fn bucket(&mut self, key: &str, config: &TokenBucketConfig, now_ms: u64) -> &mut BucketState {
+        let bucket = match self.buckets.entry(key.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut state = BucketState::full(config, now_ms);
+                if let Some((spent, _)) = self.unclaimed.remove(key) {
+                    state.tokens = state.tokens.saturating_sub(spent);
+                }
+                entry.insert(Bucket {
+                    state,
+                    config: config.clone(),
+                })
+            }
+        };
+        // A replaced limit rule applies from the next refill.
+        bucket.config = config.clone();
+        bucket.state.refill(config, now_ms);
+        match self.counters.as_mut().and_then(|c| c.settle(key)) {
+            Some(Change::Spent(tokens)) => {
+                bucket.state.tokens = bucket.state.tokens.saturating_sub(tokens)
+            }
+            Some(Change::Refunded(tokens)) => bucket.state.refund(tokens, config, now_ms),
+            None => {}
+        }
+        &mut bucket.state
+    }
+
+    fn apply_remote(&mut self, key: String, delta: Delta, now_ms: u64) {
+        match self.buckets.get_mut(&key) {
+            Some(bucket) => {
+                if delta.reset {
+                    bucket.state = BucketState::full(&bucket.config, now_ms);
+                }
+                bucket.state.refill(&bucket.config, now_ms);
+                bucket.state.tokens = bucket.state.tokens.saturating_sub(delta.spent);
+            }
+            None => {
+                let unclaimed = self.unclaimed.entry(key).or_insert((0, Instant::now()));
+                if delta.reset {
+                    unclaimed.0 = 0;
+                }
+                unclaimed.0 = unclaimed.0.saturating_add(delta.spent);
+                unclaimed.1 = Instant::now();
+            }
+        }
+    }
+
+    fn apply_demand(&mut self, key: String, demand: Demand) {
+        let expires = Instant::now() + Duration::from_millis(demand.ttl_ms);
+        self.demands
+            .entry(key)
+            .or_default()
+            .insert(demand.node, (demand.demand, expires));
+    }
+
+    fn apply_state(&mut self, key: String, state: crdt::State, now_ms: u64) {
+        let Some(counters) = &mut self.counters else {
+            return;
+        };
+        if counters.merge(key.clone(), state, now_ms) {
+            self.buckets.remove(&key);
+        }
+    }
+
+    /// Drop buckets that have refilled and have nothing left to send, as
+    /// a full bucket is what an absent one stands for, and stale debts and
+    /// counter entries.
+    fn prune(&mut self, now_ms: u64) {
+        let outgoing = &self.outgoing;
+        let counters = &self.counters;
+        self.buckets.retain(|key, bucket| {
+            bucket.state.refill(&bucket.config, now_ms);
+            bucket.state.tokens < bucket.config.capacity
+                || outgoing.contains_key(key)
+                || counters.as_ref().is_some_and(|c| c.is_dirty(key))
+        });
+        self.unclaimed
+            .retain(|_, (_, at)| at.elapsed() < UNCLAIMED_TTL);
+        let now = Instant::now();
+        self.demands.retain(|_, nodes| {
+            nodes.retain(|_, (_, expires)| *expires > now);
+            !nodes.is_empty()
+        });
+        if let Some(counters) = &mut self.counters {
+            counters.prune(now_ms, |key| self.buckets.contains_key(key));
+        }
+    }
+}
+
+/// What carries datagrams between nodes: a UDP socket, or anything else
+/// that loses and reorders them no worse, such as a simulated network.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+    async fn send_to(&self, datagram: &[u8], peer: SocketAddr) -> io::Result<()>;
+    /// Wait for the next datagram and copy it into `buffer`, returning its
+    /// length.
+    async fn recv(&self, buffer: &mut [u8]) -> io::Result<usize>;
+}
+
+#[async_trait]
+impl Transport for UdpSocket {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    async fn send_to(&self, datagram: &[u8], peer: SocketAddr) -> io::Result<()> {
+        UdpSocket::send_to(self, datagram, peer).await.map(drop)
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        UdpSocket::recv(self, buffer).await
+    }
+}
+
+/// One node's side of the gossip: the socket, the local copy of every
+/// bucket, and the tasks exchanging deltas with peers. Shared by every
+/// [`GossipBackend`] on the node; the tasks stop when it is dropped.
+pub struct GossipNode {
+    id: u64,
+    local_addr: SocketAddr,
+    table: Mutex<Table>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl GossipNode {
+    /// Bind the gossip socket and start exchanging deltas with the peers.
+    pub async fn start(config: GossipConfig) -> io::Result<Arc<Self>> {
+        let socket = UdpSocket::bind(&config.bind).await?;
+        Self::start_with(config, Arc::new(socket), Arc::new(SystemClock))
+    }
+
+    /// Start exchanging deltas over `transport` instead of a socket bound
+    /// to `config.bind`, refilling buckets by `clock`. Lets a test run a
+    /// whole cluster in one process, on a network and clock it controls.
+    pub fn start_with(
+        config: GossipConfig,
+        transport: Arc<dyn Transport>,
+        clock: Arc<dyn Clock>,
+    ) -> io::Result<Arc<Self>> {
+        // Random per process, so a restarted node isn't mistaken for its
+        // former self.
+        let id = RandomState::new().hash_one(std::process::id());
+        let table = Table {
+            counters: (config.mode == GossipMode::Crdt).then(|| Counters::new(id, UNCLAIMED_TTL)),
+            ..Table::default()
+        };
+        let node = Arc::new(Self {
+            id,
+            local_addr: transport.local_addr()?,
+            table: Mutex::new(table),
+            tasks: Mutex::default(),
+            clock,
+        });
+        let receiving = tokio::spawn(receive(Arc::downgrade(&node), Arc::clone(&transport)));
+        let sending = tokio::spawn(send(Arc::downgrade(&node), transport, config));
+        node.tasks.lock().extend([receiving, sending]);
+        Ok(node)
+    }
+
+    /// The address the gossip socket is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn stats(&self) -> GossipStats {
+        self.table.lock().stats
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+
+    /// Take the changes made since the last call, as datagrams. In CRDT
+    /// mode, `all` takes every counter.
+    fn drain(&self, all: bool) -> Vec<Vec<u8>> {
+        let mut table = self.table.lock();
+        let demands = std::mem::take(&mut table.outgoing_demands);
+        let mut datagrams = if let Some(counters) = &mut table.counters {
+            let states = counters.drain(all);
+            drop(table);
+            wire::encode_states(
+                self.id,
+                states.iter().map(|(key, state)| (key.as_str(), state)),
+            )
+        } else {
+            let outgoing = std::mem::take(&mut table.outgoing);
+            drop(table);
+            wire::encode(
+                self.id,
+                outgoing.iter().map(|(key, delta)| (key.as_str(), *delta)),
+            )
+        };
+        datagrams.extend(wire::encode_demands(
+            self.id,
+            demands.iter().map(|(key, demand)| (key.as_str(), demand)),
+        ));
+        datagrams
+    }
+}
+
+impl Drop for GossipNode {
+    fn drop(&mut self) {
+        for task in self.tasks.get_mut().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+async fn receive(node: Weak<GossipNode>, socket: Arc<dyn Transport>) {
+    let mut buffer = vec![0; wire::MAX_DATAGRAM];
+    loop {
+        let len = match socket.recv(&mut buffer).await {
+            Ok(len) => len,
+            Err(e) => {
+                // Typically an ICMP unreachable from a peer that is down.
+                tracing::debug!(error = %e, "gossip receive failed");
+                continue;
+            }
+        };
+        let Some(node) = node.upgrade() else {
+            return;
+        };
+        let now_ms = node.now_ms();
+        let mut table = node.table.lock();
+        if let Some((sender, entries)) = wire::decode_demands(&buffer[..len]) {
+            if sender != node.id {
+                table.stats.datagrams_received += 1;
+                for (key, demand) in entries {
+                    table.apply_demand(key, demand);
+                }
+            }
+            continue;
+        }
+        if table.counters.is_some() {
+            match wire::decode_states(&buffer[..len]) {
+                Some((sender, _)) if sender == node.id => {}
+                Some((_, entries)) => {
+                    table.stats.datagrams_received += 1;
+                    for (key, state) in entries {
+                        table.apply_state(key, state, now_ms);
+                    }
+                }
+                None => table.stats.datagrams_rejected += 1,
+            }
+            continue;
+        }
+        match wire::decode(&buffer[..len]) {
+            Some((sender, _)) if sender == node.id => {}
+            Some((_, entries)) => {
+                table.stats.datagrams_received += 1;
+                for (key, delta) in entries {
+                    table.apply_remote(key, delta, now_ms);
+                }
+            }
+            None => table.stats.datagrams_rejected += 1,
+        }
+    }
+}
+
+async fn send(node: Weak<GossipNode>, socket: Arc<dyn Transport>, config: GossipConfig) {
+    let mut peers = Vec::new();
+    let mut resolved_at: Option<Instant> = None;
+    let mut pruned_at = Instant::now();
+    let mut synced_at = Instant::now();
+    let mut interval = tokio::time::interval(config.interval.max(Duration::from_millis(1)));
+    loop {
+        interval.tick().await;
+        let Some(node) = node.upgrade() else {
+            return;
+        };
+        if resolved_at.is_none_or(|at| at.elapsed() >= RESOLVE_INTERVAL) {
+            peers = resolve(&config.peers, node.local_addr).await;
+            resolved_at = Some(Instant::now());
+        }
+        let all = synced_at.elapsed() >= FULL_SYNC_INTERVAL;
+        if all {
+            synced_at = Instant::now();
+        }
+        let datagrams = node.drain(all);
+        let (mut sent, mut errors) = (0, 0);
+        for datagram in &datagrams {
+            for peer in &peers {
+                match socket.send_to(datagram, *peer).await {
+                    Ok(()) => sent += 1,
+                    Err(e) => {
+                        tracing::debug!(error = %e, %peer, "gossip send failed");
+                        errors += 1;
+                    }
+                }
+            }
+        }
+        let mut table = node.table.lock();
+        table.stats.datagrams_sent += sent;
+        table.stats.send_errors += errors;
+        if pruned_at.elapsed() >= PRUNE_INTERVAL {
+            table.prune(node.now_ms());
+            pruned_at = Instant::now();
+        }
+    }
+}
+
+/// Every address `peers` name, other than the socket's own.
+async fn resolve(peers: &[String], local: SocketAddr) -> Vec<SocketAddr> {
+    let mut addrs = Vec::new();
+    for peer in peers {
+        match tokio::net::lookup_host(peer.as_str()).await {
+            Ok(found) => addrs.extend(found.filter(|addr| *addr != local)),
+            Err(e) => tracing::warn!(%peer, error = %e, "failed to resolve gossip peer"),
+        }
+    }
+    addrs.sort_unstable();
+    addrs.dedup();
+    addrs
+}
+
+/// Token buckets sized by one config, kept on a [`GossipNode`] and shared
+/// with its peers.
+///
+/// Several backends can share a node (one per limit rule, say), as long as
+/// they don't handle the same keys: the node knows each key's bucket, not
+/// which backend it belongs to.
+pub struct GossipBackend {
+    node: Arc<GossipNode>,
+    config: TokenBucketConfig,
+}
+
+impl GossipBackend {
+    pub fn new(node: Arc<GossipNode>, config: TokenBucketConfig) -> Self {
+        Self { node, config }
+    }
+
+    pub fn node(&self) -> &Arc<GossipNode> {
+        &self.node
+    }
+}
+
+#[async_trait]
+impl StorageBackend for GossipBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let mut table = self.node.table.lock();
+        let now_ms = self.node.now_ms();
+        let bucket = table.bucket(key, &self.config, now_ms);
+        let allowed = bucket.try_consume(cost, &self.config, now_ms);
+        let remaining = bucket.tokens;
+        if allowed {
+            match &mut table.counters {
+                Some(counters) => counters.spend(key, cost, now_ms),
+                None => table.outgoing.entry(key.to_string()).or_default().spent += cost,
+            }
+        }
+        Ok(TokenDecision {
+            allowed,
+            bucket: Some(BucketSnapshot::new(&self.config, remaining)),
+        })
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let remaining = self
+            .inspect(key)
+            .await?
+            .map_or(self.config.capacity, |b| b.remaining);
+        Ok(self.config.capacity.saturating_sub(remaining))
+    }
+
+    /// Reset here and on every peer.
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let mut table = self.node.table.lock();
+        table.buckets.remove(key);
+        table.unclaimed.remove(key);
+        if let Some(counters) = &mut table.counters {
+            counters.reset(key);
+            return Ok(());
+        }
+        table.outgoing.insert(
+            key.to_string(),
+            Delta {
+                reset: true,
+                spent: 0,
+            },
+        );
+        Ok(())
+    }
+
+    /// Tokens already reported to peers stay spent there, but in CRDT
+    /// mode.
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        let mut table = self.node.table.lock();
+        let now_ms = self.node.now_ms();
+        table
+            .bucket(key, &self.config, now_ms)
+            .refund(tokens, &self.config, now_ms);
+        if let Some(counters) = &mut table.counters {
+            counters.refund(key, tokens, now_ms);
+        } else if let Some(delta) = table.outgoing.get_mut(key) {
+            delta.spent = delta.spent.saturating_sub(tokens);
+        }
+        Ok(())
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        let mut table = self.node.table.lock();
+        let counted = table.counters.as_ref().is_some_and(|c| c.contains(key));
+        if !table.buckets.contains_key(key) && !table.unclaimed.contains_key(key) && !counted {
+            return Ok(None);
+        }
+        let remaining = table.bucket(key, &self.config, self.node.now_ms()).tokens;
+        Ok(Some(BucketSnapshot::new(&self.config, remaining)))
+    }
+
+    /// Sent to the peers with the next gossip; what this returns is the
+    /// demand heard from them so far.
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        let demand = Demand {
+            node: node.to_string(),
+            demand,
+            ttl_ms: ttl.as_millis() as u64,
+        };
+        let mut table = self.node.table.lock();
+        table.apply_demand(key.to_string(), demand.clone());
+        table.outgoing_demands.push((key.to_string(), demand));
+        let now = Instant::now();
+        let mut demands: Vec<(String, u64)> = table.demands[key]
+            .iter()
+            .filter(|(_, (_, expires))| *expires > now)
+            .map(|(node, (demand, _))| (node.clone(), *demand))
+            .collect();
+        demands.sort();
+        Ok(demands)
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        let table = self.node.table.lock();
+        Ok(page_of_keys(
+            table.buckets.keys().map(String::as_str),
+            pattern,
+            after,
+            limit,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(capacity: u64) -> TokenBucketConfig {
+        TokenBucketConfig {
+            capacity,
+            refill_rate: 0,
+            refill_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Two nodes gossiping with each other every 10ms.
+    async fn pair(mode: GossipMode) -> (Arc<GossipNode>, Arc<GossipNode>) {
+        let free = || {
+            let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.local_addr().unwrap().to_string()
+        };
+        let (a, b) = (free(), free());
+        let start = |bind: &str, peer: &str| {
+            GossipNode::start(GossipConfig {
+                bind: bind.to_string(),
+                peers: vec![peer.to_string()],
+                interval: Duration::from_millis(10),
+                mode,
+            })
+        };
+        (start(&a, &b).await.unwrap(), start(&b, &a).await.unwrap())
+    }
+
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    #[tokio::test]
+    async fn test_peers_share_a_budget() {
+        let (a, b) = pair(GossipMode::Deltas).await;
+        let on_a = GossipBackend::new(Arc::clone(&a), config(10));
+        let on_b = GossipBackend::new(Arc::clone(&b), config(10));
+
+        assert!(on_a.take_token("user1", 6).await.unwrap());
+        settle().await;
+        // Spent on `a` before `b` ever saw the key.
+        assert_eq!(on_b.get_usage("user1").await.unwrap(), 6);
+        assert!(!on_b.take_token("user1", 5).await.unwrap());
+        assert!(on_b.take_token("user1", 4).await.unwrap());
+        assert!(a.stats().datagrams_sent > 0);
+        assert!(b.stats().datagrams_received > 0);
+    }
+
+    #[tokio::test]
+    async fn test_crdt_peers_share_refunds_and_resets() {
+        let (a, b) = pair(GossipMode::Crdt).await;
+        let on_a = GossipBackend::new(Arc::clone(&a), config(10));
+        let on_b = GossipBackend::new(Arc::clone(&b), config(10));
+
+        assert!(on_a.take_token("user1", 6).await.unwrap());
+        assert!(on_b.take_token("user1", 2).await.unwrap());
+        settle().await;
+        assert_eq!(on_a.get_usage("user1").await.unwrap(), 8);
+        assert_eq!(on_b.get_usage("user1").await.unwrap(), 8);
+
+        on_a.refund("user1", 5).await.unwrap();
+        settle().await;
+        assert_eq!(on_b.get_usage("user1").await.unwrap(), 3);
+
+        on_b.reset("user1").await.unwrap();
+        settle().await;
+        assert_eq!(on_a.get_usage("user1").await.unwrap(), 0);
+        assert!(on_a.take_token("user1", 10).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_refund_and_reset() {
+        let node = GossipNode::start(GossipConfig {
+            bind: "127.0.0.1:0".to_string(),
+            ..GossipConfig::default()
+        })
+        .await
+        .unwrap();
+        let backend = GossipBackend::new(node, config(10));
+        assert_eq!(backend.inspect("user1").await.unwrap(), None);
+        assert!(backend.take_token("user1", 8).await.unwrap());
+        backend.refund("user1", 3).await.unwrap();
+        assert_eq!(backend.get_usage("user1").await.unwrap(), 5);
+        assert_eq!(
+            backend.list_keys("user*", None, 10).await.unwrap(),
+            ["user1"]
+        );
+
+        backend.reset("user1").await.unwrap();
+        assert_eq!(backend.get_usage("user1").await.unwrap(), 0);
+        let delta = backend.node().table.lock().outgoing["user1"];
+        assert!(delta.reset);
+    }
+
+    #[tokio::test]
+    async fn test_peers_exchange_demand() {
+        let (a, b) = pair(GossipMode::Crdt).await;
+        let on_a = GossipBackend::new(Arc::clone(&a), config(10));
+        let on_b = GossipBackend::new(Arc::clone(&b), config(10));
+        let ttl = Duration::from_secs(15);
+
+        assert_eq!(
+            on_a.exchange_demand("user1", "eu-west", 30, ttl)
+                .await
+                .unwrap(),
+            [("eu-west".to_string(), 30)]
+        );
+        settle().await;
+        assert_eq!(
+            on_b.exchange_demand("user1", "us-east", 10, ttl)
+                .await
+                .unwrap(),
+            [("eu-west".to_string(), 30), ("us-east".to_string(), 10)]
+        );
+    }
+
+    #[test]
+    fn test_prunes_full_buckets_and_stale_debts() {
+        let mut table = Table::default();
+        let refilling = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1000,
+            refill_interval: Duration::from_secs(1),
+        };
+        table.bucket("idle", &refilling, 0).tokens = 0;
+        table.bucket("held", &config(10), 0).tokens = 4;
+        table.apply_remote(
+            "elsewhere".to_string(),
+            Delta {
+                reset: false,
+                spent: 3,
+            },
+            0,
+        );
+        table.unclaimed.get_mut("elsewhere").unwrap().1 -= UNCLAIMED_TTL;
+
+        table.prune(10_000);
+        assert_eq!(table.buckets.keys().collect::<Vec<_>>(), ["held"]);
+        assert!(table.unclaimed.is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+//! The datagram formats. Each datagram carries one sender's changes to any
+//! number of keys, all big-endian:
+//!
+//! ```text
+//! magic "GG" | version u8 | sender u64 | entries u16
+//! entry: flags u8 (bit 0: reset) | spent u64 | key length u16 | key
+//! ```
+//!
+//! or, in CRDT mode, whole counters:
+//!
+//! ```text
+//! magic "GC" | version u8 | sender u64 | entries u16
+//! entry: epoch u64 | counts u16 | count* | key length u16 | key
+//! count: node u64 | since_ms u64 | updated_ms u64 | spent u64 | refunded u64
+//! ```
+//!
+//! or, in either mode, demand reported for slicing limits:
+//!
+//! ```text
+//! magic "GD" | version u8 | sender u64 | entries u16
+//! entry: ttl_ms u64 | demand u64 | node length u16 | node | key length u16 | key
+//! ```
+
+use crate::crdt::{Count, State};
+
+/// Largest datagram sent, keeping clear of fragmentation on a 1500-byte
+/// MTU.
+pub const MAX_DATAGRAM: usize = 1432;
+
+const MAGIC: &[u8; 2] = b"GG";
+const STATE_MAGIC: &[u8; 2] = b"GC";
+const DEMAND_MAGIC: &[u8; 2] = b"GD";
+const VERSION: u8 = 1;
+const HEADER: usize = 2 + 1 + 8 + 2;
+const RESET: u8 = 1;
+
+/// What one node did to a key since it last told its peers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Delta {
+    /// The bucket was reset, before any tokens in `spent` were taken.
+    pub reset: bool,
+    pub spent: u64,
+}
+
+/// Pack `deltas` into datagrams of at most [`MAX_DATAGRAM`] bytes. Keys too
+/// long to fit a datagram are skipped.
+pub fn encode<'a>(sender: u64, deltas: impl IntoIterator<Item = (&'a str, Delta)>) -> Vec<Vec<u8>> {
+    let entries = deltas.into_iter().map(|(key, delta)| {
+        let mut body = Vec::with_capacity(1 + 8);
+        body.push(if delta.reset { RESET } else { 0 });
+        body.extend_from_slice(&delta.spent.to_be_bytes());
+        (key, body)
+    });
+    pack(MAGIC, sender, entries)
+}
+
+/// One node's demand on a key, as given to `exchange_demand`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Demand {
+    pub node: String,
+    pub demand: u64,
+    /// How long the demand stands unless reported again.
+    pub ttl_ms: u64,
+}
+
+/// Pack demands into datagrams like [`encode`].
+pub fn encode_demands<'a>(
+    sender: u64,
+    demands: impl IntoIterator<Item = (&'a str, &'a Demand)>,
+) -> Vec<Vec<u8>> {
+    let entries = demands.into_iter().map(|(key, demand)| {
+        // Names too long for a datagram leave it to `pack` to skip.
+        let node = demand.node.as_bytes();
+        let mut body = Vec::with_capacity(8 + 8 + 2 + node.len());
+        body.extend_from_slice(&demand.ttl_ms.to_be_bytes());
+        body.extend_from_slice(&demand.demand.to_be_bytes());
+        body.extend_from_slice(&(node.len() as u16).to_be_bytes());
+        body.extend_from_slice(node);
+        (key, body)
+    });
+    pack(DEMAND_MAGIC, sender, entries)
+}
+
+/// Pack counters into datagrams like [`encode`]. Counters with too many
+/// entries to fit a datagram are skipped too.
+pub fn encode_states<'a>(
+    sender: u64,
+    states: impl IntoIterator<Item = (&'a str, &'a State)>,
+) -> Vec<Vec<u8>> {
+    let entries = states.into_iter().map(|(key, state)| {
+        let mut body = Vec::with_capacity(8 + 2 + state.counts.len() * 40);
+        body.extend_from_slice(&state.epoch.to_be_bytes());
+        body.extend_from_slice(&(state.counts.len().min(u16::MAX as usize) as u16).to_be_bytes());
+        for (node, count) in &state.counts {
+            for n in [
+                *node,
+                count.since_ms,
+                count.updated_ms,
+                count.spent,
+                count.refunded,
+            ] {
+                body.extend_from_slice(&n.to_be_bytes());
+            }
+        }
+        (key, body)
+    });
+    pack(STATE_MAGIC, sender, entries)
+}
+
+/// Pack entries, each a body followed by its key, behind a header.
+fn pack<'a>(
+    magic: &[u8; 2],
+    sender: u64,
+    entries: impl Iterator<Item = (&'a str, Vec<u8>)>,
+) -> Vec<Vec<u8>> {
+    let mut datagrams = Vec::new();
+    let mut datagram = Vec::new();
+    let mut count: u16 = 0;
+    for (key, body) in entries {
+        let len = body.len() + 2 + key.len();
+        if HEADER + len > MAX_DATAGRAM {
+            tracing::warn!(key, "key too long to gossip");
+            continue;
+        }
+        if count == u16::MAX || datagram.len() + len > MAX_DATAGRAM {
+            datagrams.push(finish(std::mem::take(&mut datagram), count));
+            count = 0;
+        }
+        if datagram.is_empty() {
+            datagram.extend_from_slice(magic);
+            datagram.push(VERSION);
+            datagram.extend_from_slice(&sender.to_be_bytes());
+            datagram.extend_from_slice(&[0, 0]);
+        }
+        datagram.extend_from_slice(&body);
+        datagram.extend_from_slice(&(key.len() as u16).to_be_bytes());
+        datagram.extend_from_slice(key.as_bytes());
+        count += 1;
+    }
+    if count > 0 {
+        datagrams.push(finish(datagram, count));
+    }
+    datagrams
+}
+
+fn finish(mut datagram: Vec<u8>, count: u16) -> Vec<u8> {
+    datagram[HEADER - 2..HEADER].copy_from_slice(&count.to_be_bytes());
+    datagram
+}
+
+/// The sender and entries of a datagram, or `None` if it isn't one of ours
+/// or is cut short.
+pub fn decode(datagram: &[u8]) -> Option<(u64, Vec<(String, Delta)>)> {
+    unpack(datagram, MAGIC, |reader| {
+        let flags = reader.take(1)?[0];
+        Some(Delta {
+            reset: flags & RESET != 0,
+            spent: reader.u64()?,
+        })
+    })
+}
+
+/// The sender and counters of a CRDT-mode datagram, as [`decode`].
+pub fn decode_states(datagram: &[u8]) -> Option<(u64, Vec<(String, State)>)> {
+    unpack(datagram, STATE_MAGIC, |reader| {
+        let epoch = reader.u64()?;
+        let mut state = State {
+            epoch,
+            ..State::default()
+        };
+        for _ in 0..reader.u16()? {
+            let node = reader.u64()?;
+            let count = Count {
+                since_ms: reader.u64()?,
+                updated_ms: reader.u64()?,
+                spent: reader.u64()?,
+                refunded: reader.u64()?,
+            };
+            state.counts.insert(node, count);
+        }
+        Some(state)
+    })
+}
+
+/// The sender and demands of a demand datagram, as [`decode`].
+pub fn decode_demands(datagram: &[u8]) -> Option<(u64, Vec<(String, Demand)>)> {
+    unpack(datagram, DEMAND_MAGIC, |reader| {
+        let ttl_ms = reader.u64()?;
+        let demand = reader.u64()?;
+        let len = reader.u16()? as usize;
+        let node = String::from_utf8(reader.take(len)?.to_vec()).ok()?;
+        Some(Demand {
+            node,
+            demand,
+            ttl_ms,
+        })
+    })
+}
+
+fn unpack<T>(
+    datagram: &[u8],
+    magic: &[u8; 2],
+    body: impl Fn(&mut Reader) -> Option<T>,
+) -> Option<(u64, Vec<(String, T)>)> {
+    let mut reader = Reader(datagram);
+    if reader.take(2)? != magic || reader.take(1)?[0] != VERSION {
+        return None;
+    }
+    let sender = reader.u64()?;
+    let count = reader.u16()?;
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let value = body(&mut reader)?;
+        let len = reader.u16()? as usize;
+        let key = String::from_utf8(reader.take(len)?.to_vec()).ok()?;
+        entries.push((key, value));
+    }
+    Some((sender, entries))
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_across_datagrams() {
+        let keys: Vec<String> = (0..200).map(|n| format!("api:user{}", n)).collect();
+        let deltas = keys.iter().enumerate().map(|(n, key)| {
+            let delta = Delta {
+                reset: n % 7 == 0,
+                spent: n as u64,
+            };
+            (key.as_str(), delta)
+        });
+        let datagrams = encode(42, deltas);
+        assert!(datagrams.len() > 1);
+        assert!(datagrams.iter().all(|d| d.len() <= MAX_DATAGRAM));
+
+        let mut decoded = Vec::new();
+        for datagram in &datagrams {
+            let (sender, entries) = decode(datagram).unwrap();
+            assert_eq!(sender, 42);
+            decoded.extend(entries);
+        }
+        assert_eq!(decoded.len(), 200);
+        assert_eq!(decoded[14].0, "api:user14");
+        assert_eq!(
+            decoded[14].1,
+            Delta {
+                reset: true,
+                spent: 14
+            }
+        );
+    }
+
+    #[test]
+    fn test_rejects_foreign_and_truncated_datagrams() {
+        let datagram = encode(1, [("user1", Delta::default())]).remove(0);
+        assert!(decode(&datagram[..datagram.len() - 1]).is_none());
+        assert!(decode(b"GET / HTTP/1.1\r\n").is_none());
+        assert!(encode(1, [("k".repeat(MAX_DATAGRAM).as_str(), Delta::default())]).is_empty());
+        // Neither format is mistaken for the other.
+        assert!(decode_states(&datagram).is_none());
+    }
+
+    #[test]
+    fn test_round_trips_counters() {
+        let count = |n| Count {
+            since_ms: n,
+            updated_ms: n + 1,
+            spent: n + 2,
+            refunded: n + 3,
+        };
+        let state = State {
+            epoch: 3,
+            counts: [(7, count(10)), (9, count(20))].into(),
+        };
+        let datagrams = encode_states(42, [("user1", &state), ("user2", &State::default())]);
+        let (sender, entries) = decode_states(&datagrams[0]).unwrap();
+        assert_eq!(sender, 42);
+        assert_eq!(
+            entries,
+            [
+                ("user1".to_string(), state),
+                ("user2".to_string(), State::default())
+            ]
+        );
+        assert!(decode(&datagrams[0]).is_none());
+    }
+
+    #[test]
+    fn test_round_trips_demands() {
+        let demand = Demand {
+            node: "eu-west".to_string(),
+            demand: 120,
+            ttl_ms: 15_000,
+        };
+        let datagrams = encode_demands(42, [("user1", &demand)]);
+        let (sender, entries) = decode_demands(&datagrams[0]).unwrap();
+        assert_eq!(sender, 42);
+        assert_eq!(entries, [("user1".to_string(), demand)]);
+        assert!(decode(&datagrams[0]).is_none());
+        assert!(decode_states(&datagrams[0]).is_none());
+    }
+}
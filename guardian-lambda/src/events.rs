@@ -0,0 +1,189 @@
+//! The API Gateway payloads Guardian reads and answers with. Only the
+//! fields it needs are typed; the rest of a request is kept in `other`, so
+//! handlers behind the middleware still see the whole event.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use guardian_extractors::RequestParts;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::{json, Map, Value};
+
+/// A request from API Gateway: a proxy integration event or a `REQUEST`
+/// authorizer event, from a REST API or an HTTP API (payload 1.0 or 2.0).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiGatewayRequest {
+    /// `2.0` for HTTP API payloads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// The method a REST API authorizer is asked about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method_arn: Option<String>,
+    /// The route an HTTP API authorizer is asked about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route_arn: Option<String>,
+    /// A REST API's resource template, such as `/users/{id}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource: Option<String>,
+    /// An HTTP API's route, such as `GET /users/{id}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub route_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_path: Option<String>,
+    #[serde(default, deserialize_with = "null_as_empty")]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub request_context: Value,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+fn null_as_empty<'de, D: Deserializer<'de>>(d: D) -> Result<HashMap<String, String>, D::Error> {
+    Ok(Option::deserialize(d)?.unwrap_or_default())
+}
+
+impl RequestParts for ApiGatewayRequest {
+    /// REST APIs keep header names as sent, so they're matched
+    /// ignoring case.
+    fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    fn peer_ip(&self) -> Option<IpAddr> {
+        let context = &self.request_context;
+        context
+            .pointer("/identity/sourceIp")
+            .or_else(|| context.pointer("/http/sourceIp"))?
+            .as_str()?
+            .parse()
+            .ok()
+    }
+
+    fn path(&self) -> &str {
+        self.raw_path
+            .as_deref()
+            .or(self.path.as_deref())
+            .unwrap_or_default()
+    }
+
+    fn route(&self) -> Option<&str> {
+        self.resource.as_deref().or(self.route_key.as_deref())
+    }
+}
+
+/// A proxy integration response.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiGatewayResponse {
+    pub status_code: u16,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub is_base64_encoded: bool,
+}
+
+impl ApiGatewayResponse {
+    pub fn new(status_code: u16, body: impl Into<String>) -> Self {
+        Self {
+            status_code,
+            body: Some(body.into()),
+            ..Self::default()
+        }
+    }
+}
+
+/// What a Lambda authorizer returns. `context` carries the decision's
+/// rate-limit values (`ratelimit_remaining`, `retry_after`, ...) for
+/// mapping into responses as `$context.authorizer.<name>`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum AuthorizerResponse {
+    /// An IAM policy, which REST APIs and HTTP APIs both accept.
+    #[serde(rename_all = "camelCase")]
+    Policy {
+        principal_id: String,
+        policy_document: Value,
+        context: HashMap<String, String>,
+    },
+    /// An HTTP API's simple response, when the authorizer has
+    /// `enableSimpleResponses` set.
+    #[serde(rename_all = "camelCase")]
+    Simple {
+        is_authorized: bool,
+        context: HashMap<String, String>,
+    },
+}
+
+impl AuthorizerResponse {
+    pub(crate) fn policy(
+        principal_id: String,
+        allow: bool,
+        resource: &str,
+        context: HashMap<String, String>,
+    ) -> Self {
+        Self::Policy {
+            principal_id,
+            policy_document: json!({
+                "Version": "2012-10-17",
+                "Statement": [{
+                    "Action": "execute-api:Invoke",
+                    "Effect": if allow { "Allow" } else { "Deny" },
+                    "Resource": resource,
+                }],
+            }),
+            context,
+        }
+    }
+
+    pub fn is_allowed(&self) -> bool {
+        match self {
+            Self::Policy {
+                policy_document, ..
+            } => policy_document.pointer("/Statement/0/Effect") == Some(&json!("Allow")),
+            Self::Simple { is_authorized, .. } => *is_authorized,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_rest_and_http_api_events() {
+        let rest: ApiGatewayRequest = serde_json::from_value(json!({
+            "resource": "/users/{id}",
+            "path": "/users/7",
+            "headers": {"X-Api-Key": "k1"},
+            "requestContext": {"identity": {"sourceIp": "203.0.113.9"}},
+            "body": "{}",
+        }))
+        .unwrap();
+        assert_eq!(rest.header("x-api-key"), Some("k1"));
+        assert_eq!(rest.peer_ip(), Some("203.0.113.9".parse().unwrap()));
+        assert_eq!(RequestParts::path(&rest), "/users/7");
+        assert_eq!(rest.route(), Some("/users/{id}"));
+        assert_eq!(rest.other["body"], "{}");
+
+        let http: ApiGatewayRequest = serde_json::from_value(json!({
+            "version": "2.0",
+            "routeKey": "GET /users/{id}",
+            "rawPath": "/users/7",
+            "headers": null,
+            "requestContext": {"http": {"sourceIp": "2001:db8::1"}},
+        }))
+        .unwrap();
+        assert_eq!(http.header("x-api-key"), None);
+        assert_eq!(http.peer_ip(), Some("2001:db8::1".parse().unwrap()));
+        assert_eq!(http.route(), Some("GET /users/{id}"));
+    }
+}
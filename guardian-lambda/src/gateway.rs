@@ -0,0 +1,151 @@
+//! Checks through Guardian's JSON/HTTP gateway, for functions that would
+//! rather not hold a gRPC connection open between invocations.
+
+use async_trait::async_trait;
+use guardian_client::LimitCheckResult;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::Limiter;
+
+/// A client of the gateway's `POST /v1/check`, served on the service's
+/// `global.http_port`.
+#[derive(Debug, Clone)]
+pub struct GatewayClient {
+    http: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    namespace: Option<String>,
+}
+
+impl GatewayClient {
+    /// A client of the gateway at `url`, such as
+    /// `https://guardian.internal:8080`.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            namespace: None,
+        }
+    }
+
+    /// Send `key` as `x-api-key`, for services that require callers to
+    /// authenticate.
+    pub fn with_api_key(mut self, key: impl Into<String>) -> Self {
+        self.api_key = Some(key.into());
+        self
+    }
+
+    /// Check keys in `namespace` rather than the caller's own.
+    pub fn with_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Make calls with `http`, such as one with a short timeout.
+    pub fn with_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+}
+
+#[derive(Deserialize)]
+struct CheckReply {
+    allowed: bool,
+    remaining: u64,
+    retry_after_seconds: u32,
+}
+
+#[async_trait]
+impl Limiter for GatewayClient {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        let body = json!({
+            "key": key,
+            "cost": cost,
+            "namespace": self.namespace.as_deref().unwrap_or_default(),
+        });
+        let mut request = self.http.post(format!("{}/v1/check", self.url)).json(&body);
+        if let Some(api_key) = &self.api_key {
+            request = request.header("x-api-key", api_key);
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        // 429 is a denial, not a failure.
+        let status = response.status();
+        if !status.is_success() && status != reqwest::StatusCode::TOO_MANY_REQUESTS {
+            return Err(format!("gateway answered {}", status));
+        }
+        let limit = response
+            .headers()
+            .get("ratelimit-limit")
+            .and_then(|value| value.to_str().ok()?.parse().ok())
+            .unwrap_or(0);
+        let reply: CheckReply = response.json().await.map_err(|e| e.to_string())?;
+        Ok(LimitCheckResult {
+            allowed: reply.allowed,
+            retry_after_seconds: reply.retry_after_seconds,
+            remaining_tokens: reply.remaining,
+            limit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A gateway that answers one call with `response`, handing back the
+    /// request it got.
+    async fn gateway(response: String) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let served = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            // Every call has a JSON body, so it's all here at the last `}`.
+            let mut request = Vec::new();
+            while request.last() != Some(&b'}') {
+                let mut chunk = [0; 4096];
+                let read = socket.read(&mut chunk).await.unwrap();
+                assert_ne!(read, 0, "the call ended before its body");
+                request.extend_from_slice(&chunk[..read]);
+            }
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (url, served)
+    }
+
+    #[tokio::test]
+    async fn test_reads_denials_from_the_gateway() {
+        let body = r#"{"allowed":false,"remaining":0,"retry_after_seconds":7}"#;
+        let (url, served) = gateway(format!(
+            "HTTP/1.1 429 Too Many Requests\r\nratelimit-limit: 10\r\n\
+             content-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ))
+        .await;
+
+        let client = GatewayClient::new(format!("{}/", url)).with_api_key("k1");
+        let decision = client.check("ip:203.0.113.9", 2).await.unwrap();
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after_seconds, 7);
+        assert_eq!(decision.limit, 10);
+
+        let request = served.await.unwrap();
+        assert!(request.starts_with("POST /v1/check "));
+        assert!(request.contains("x-api-key: k1"));
+        assert!(request.contains(r#""key":"ip:203.0.113.9""#));
+    }
+
+    #[tokio::test]
+    async fn test_gateway_errors_are_errors() {
+        let (url, _) =
+            gateway("HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n".to_string())
+                .await;
+        let error = GatewayClient::new(url).check("k", 1).await.unwrap_err();
+        assert!(error.contains("503"));
+    }
+}
@@ -0,0 +1,340 @@
+//! Rate limiting for Lambda-based APIs behind API Gateway, checked with a
+//! Guardian service over gRPC or its HTTP gateway, so serverless
+//! endpoints share limits with the rest of the fleet.
+//!
+//! A function can run as a `REQUEST` authorizer, answering with an IAM
+//! policy (or an HTTP API simple response) that denies requests over
+//! their limit:
+//!
+//! ```no_run
+//! use guardian_lambda::{ApiGatewayRequest, GatewayClient, Guardian};
+//! use lambda_runtime::{service_fn, LambdaEvent};
+//!
+//! # async fn example() -> Result<(), lambda_runtime::Error> {
+//! let guardian = Guardian::gateway(GatewayClient::new("https://guardian.internal:8080"));
+//! lambda_runtime::run(service_fn(|event: LambdaEvent<ApiGatewayRequest>| {
+//!     let guardian = guardian.clone();
+//!     async move { Ok::<_, lambda_runtime::Error>(guardian.authorize(&event.payload).await) }
+//! }))
+//! .await
+//! # }
+//! ```
+//!
+//! API Gateway answers an authorizer's denial with 403, and caches
+//! policies unless the authorizer's TTL is 0, which it should be here.
+//! For a proper `429 Too Many Requests`, wrap a proxy integration's
+//! handler in the [`Guardian`] layer instead:
+//!
+//! ```no_run
+//! use guardian_client::GuardianClient;
+//! use guardian_lambda::{ApiGatewayRequest, ApiGatewayResponse, Extractor, Guardian};
+//! use lambda_runtime::tower::Layer;
+//! use lambda_runtime::{service_fn, LambdaEvent};
+//!
+//! # async fn example() -> Result<(), lambda_runtime::Error> {
+//! let client = GuardianClient::connect("http://guardian.internal:50051").await?;
+//! let guardian = Guardian::new(client).with_key(Extractor::header("x-api-key"));
+//! let handler = guardian.layer(service_fn(|_event: LambdaEvent<ApiGatewayRequest>| async {
+//!     Ok::<_, lambda_runtime::Error>(ApiGatewayResponse::new(200, "hello"))
+//! }));
+//! lambda_runtime::run(handler).await
+//! # }
+//! ```
+//!
+//! Responses then carry the decision's `ratelimit-*` headers, and denied
+//! requests never reach the handler.
+
+mod events;
+mod gateway;
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use guardian_client::{GuardianApi, LimitCheckResult};
+use lambda_runtime::tower::{Layer, Service};
+use lambda_runtime::LambdaEvent;
+
+pub use events::{ApiGatewayRequest, ApiGatewayResponse, AuthorizerResponse};
+pub use gateway::GatewayClient;
+pub use guardian_extractors::{Extractor, TrustedProxies};
+
+/// Where decisions come from.
+#[async_trait]
+trait Limiter: Send + Sync {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String>;
+}
+
+struct Remote<A>(A);
+
+#[async_trait]
+impl<A: GuardianApi> Limiter for Remote<A> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        self.0
+            .check_limit_detailed(key, cost)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Checks API Gateway requests with Guardian; see the [crate docs](crate).
+#[derive(Clone)]
+pub struct Guardian {
+    limiter: Arc<dyn Limiter>,
+    key: Extractor,
+    cost: u32,
+    fail_open: bool,
+    simple_responses: bool,
+}
+
+impl Guardian {
+    /// Decide over gRPC: a
+    /// [`GuardianClient`](guardian_client::GuardianClient), or anything
+    /// else implementing [`GuardianApi`].
+    pub fn new(api: impl GuardianApi + 'static) -> Self {
+        Self::with_limiter(Remote(api))
+    }
+
+    /// Decide through the service's HTTP gateway.
+    pub fn gateway(client: GatewayClient) -> Self {
+        Self::with_limiter(client)
+    }
+
+    fn with_limiter(limiter: impl Limiter + 'static) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            key: Extractor::client_ip(TrustedProxies::none()),
+            cost: 1,
+            fail_open: true,
+            simple_responses: false,
+        }
+    }
+
+    /// What requests are limited by; the caller's source IP (`ip:<addr>`)
+    /// unless set.
+    pub fn with_key(mut self, key: Extractor) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Tokens each request costs; 1 unless set.
+    pub fn with_cost(mut self, cost: u32) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    /// Whether to let requests through when Guardian can't decide (the
+    /// default), or deny them: with a `Deny` policy from the authorizer,
+    /// or `503 Service Unavailable` from the layer.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Have [`authorize`](Self::authorize) answer with HTTP API simple
+    /// responses rather than IAM policies.
+    pub fn with_simple_responses(mut self, simple: bool) -> Self {
+        self.simple_responses = simple;
+        self
+    }
+
+    /// The authorizer's answer for `request`. Requests without a key are
+    /// allowed unchecked.
+    pub async fn authorize(&self, request: &ApiGatewayRequest) -> AuthorizerResponse {
+        let key = self.key.extract(request);
+        let (allow, context) = match self.check(key.as_deref()).await {
+            Ok(Some(decision)) => {
+                let context = decision
+                    .to_headers()
+                    .into_iter()
+                    .map(|(name, value)| (name.replace('-', "_"), value))
+                    .collect();
+                (decision.allowed, context)
+            }
+            Ok(None) => (true, HashMap::new()),
+            Err(_) => (false, HashMap::new()),
+        };
+        if self.simple_responses {
+            return AuthorizerResponse::Simple {
+                is_authorized: allow,
+                context,
+            };
+        }
+        let resource = request
+            .method_arn
+            .as_deref()
+            .or(request.route_arn.as_deref())
+            .unwrap_or("*");
+        let principal = key.unwrap_or_else(|| "anonymous".to_string());
+        AuthorizerResponse::policy(principal, allow, resource, context)
+    }
+
+    /// The decision for `key`: `Ok(None)` lets the request through
+    /// unchecked, `Err` denies it because Guardian couldn't decide.
+    async fn check(&self, key: Option<&str>) -> Result<Option<LimitCheckResult>, String> {
+        let Some(key) = key else {
+            return Ok(None);
+        };
+        match self.limiter.check(key, self.cost).await {
+            Ok(decision) => Ok(Some(decision)),
+            Err(_) if self.fail_open => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<S> Layer<S> for Guardian {
+    type Service = GuardianService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GuardianService {
+            inner,
+            guardian: self.clone(),
+        }
+    }
+}
+
+/// A handler that only sees requests within their limit.
+#[derive(Clone)]
+pub struct GuardianService<S> {
+    inner: S,
+    guardian: Guardian,
+}
+
+impl<S> Service<LambdaEvent<ApiGatewayRequest>> for GuardianService<S>
+where
+    S: Service<LambdaEvent<ApiGatewayRequest>, Response = ApiGatewayResponse>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = ApiGatewayResponse;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<ApiGatewayResponse, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, event: LambdaEvent<ApiGatewayRequest>) -> Self::Future {
+        // Keep the service that was polled ready for this request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let guardian = self.guardian.clone();
+        let key = guardian.key.extract(&event.payload);
+        Box::pin(async move {
+            let decision = match guardian.check(key.as_deref()).await {
+                Ok(Some(decision)) => decision,
+                Ok(None) => return inner.call(event).await,
+                Err(_) => return Ok(ApiGatewayResponse::new(503, "rate limiter unavailable")),
+            };
+            let mut response = if decision.allowed {
+                inner.call(event).await?
+            } else {
+                ApiGatewayResponse::new(429, "rate limited")
+            };
+            for (name, value) in decision.to_headers() {
+                response.headers.insert(name.to_string(), value);
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use guardian_client::{ClientError, MockGuardianClient};
+    use lambda_runtime::tower::ServiceExt;
+    use serde_json::json;
+
+    fn request(source_ip: &str) -> ApiGatewayRequest {
+        serde_json::from_value(json!({
+            "methodArn": "arn:aws:execute-api:eu-west-1:123:api/prod/GET/users",
+            "resource": "/users",
+            "path": "/users",
+            "headers": {"X-Api-Key": "k1"},
+            "requestContext": {"identity": {"sourceIp": source_ip}},
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_authorizer_denies_over_limit() {
+        let mock = MockGuardianClient::new();
+        mock.deny("ip:203.0.113.9", 4);
+        let guardian = Guardian::new(mock.clone());
+
+        let allowed = guardian.authorize(&request("198.51.100.1")).await;
+        assert!(allowed.is_allowed());
+        let denied =
+            serde_json::to_value(guardian.authorize(&request("203.0.113.9")).await).unwrap();
+        assert_eq!(denied["principalId"], "ip:203.0.113.9");
+        assert_eq!(denied["policyDocument"]["Statement"][0]["Effect"], "Deny");
+        assert_eq!(
+            denied["policyDocument"]["Statement"][0]["Resource"],
+            "arn:aws:execute-api:eu-west-1:123:api/prod/GET/users"
+        );
+        assert_eq!(denied["context"]["retry_after"], "4");
+
+        let simple = guardian
+            .with_simple_responses(true)
+            .with_key(Extractor::header("x-api-key").prefixed("lambda"))
+            .authorize(&request("203.0.113.9"))
+            .await;
+        assert_eq!(serde_json::to_value(&simple).unwrap()["isAuthorized"], true);
+        assert_eq!(
+            mock.calls().last().unwrap().client_id,
+            "lambda:x-api-key:k1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_layer_answers_429_without_calling_the_handler() {
+        let mock = MockGuardianClient::new();
+        mock.deny("ip:203.0.113.9", 4);
+        let calls = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&calls);
+        let handler = Guardian::new(mock).layer(lambda_runtime::service_fn(
+            move |_event: LambdaEvent<ApiGatewayRequest>| {
+                counter.fetch_add(1, Ordering::Relaxed);
+                async { Ok::<_, Infallible>(ApiGatewayResponse::new(200, "hello")) }
+            },
+        ));
+        let event = |ip| LambdaEvent::new(request(ip), lambda_runtime::Context::default());
+
+        let allowed = handler
+            .clone()
+            .oneshot(event("198.51.100.1"))
+            .await
+            .unwrap();
+        assert_eq!(allowed.status_code, 200);
+        assert!(allowed.headers.contains_key("ratelimit-remaining"));
+        let denied = handler.oneshot(event("203.0.113.9")).await.unwrap();
+        assert_eq!(denied.status_code, 429);
+        assert_eq!(denied.headers["retry-after"], "4");
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fails_open_unless_told_otherwise() {
+        let mock = MockGuardianClient::new();
+        mock.push_response("ip:198.51.100.1", Err(ClientError::DeadlineExceeded));
+        mock.push_response("ip:198.51.100.1", Err(ClientError::DeadlineExceeded));
+
+        let open = Guardian::new(mock.clone());
+        assert!(open.authorize(&request("198.51.100.1")).await.is_allowed());
+        let closed = Guardian::new(mock).with_fail_open(false);
+        assert!(!closed
+            .authorize(&request("198.51.100.1"))
+            .await
+            .is_allowed());
+    }
+}
@@ -0,0 +1,229 @@
+use async_trait::async_trait;
+use guardian_core::{BucketState, RateLimitError, StorageBackend, TokenBucketConfig};
+use mongodb::bson::{doc, DateTime};
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::IndexOptions;
+use mongodb::{Client, Collection, IndexModel};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Idle buckets are removed by the TTL index after this long, matching Redis.
+const BUCKET_TTL: Duration = Duration::from_secs(3600);
+
+/// Conditional updates retried before reporting contention as an error.
+const MAX_CONDITIONAL_RETRIES: usize = 16;
+
+/// MongoDB's duplicate key error code, raised when two limiters create the
+/// same bucket at once.
+const DUPLICATE_KEY: i32 = 11000;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BucketDocument {
+    #[serde(rename = "_id")]
+    key: String,
+    tokens: i64,
+    last_refill_ms: i64,
+    version: i64,
+    expires_at: DateTime,
+}
+
+/// Token buckets stored as MongoDB documents, one per key.
+///
+/// Consumption reads the document, refills it locally, and writes it back
+/// with findAndModify filtered on a version field, retrying on conflict. A
+/// TTL index on `expires_at` removes idle buckets.
+pub struct MongoBackend {
+    collection: Collection<BucketDocument>,
+    config: TokenBucketConfig,
+}
+
+impl MongoBackend {
+    pub async fn new(
+        uri: &str,
+        database: &str,
+        collection: &str,
+        config: TokenBucketConfig,
+    ) -> Result<Self, RateLimitError> {
+        let client = Client::with_uri_str(uri)
+            .await
+            .map_err(|e| storage_error("MongoDB connection error", e))?;
+        let backend = Self::with_client(&client, database, collection, config);
+        backend.ensure_indexes().await?;
+        Ok(backend)
+    }
+
+    /// Use an existing client. Call `ensure_indexes` once if the collection
+    /// wasn't provisioned elsewhere.
+    pub fn with_client(
+        client: &Client,
+        database: &str,
+        collection: &str,
+        config: TokenBucketConfig,
+    ) -> Self {
+        Self {
+            collection: client.database(database).collection(collection),
+            config,
+        }
+    }
+
+    /// Create the TTL index on `expires_at` (a no-op if it already exists).
+    pub async fn ensure_indexes(&self) -> Result<(), RateLimitError> {
+        let index = IndexModel::builder()
+            .keys(doc! { "expires_at": 1 })
+            .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+            .build();
+
+        self.collection
+            .create_index(index)
+            .await
+            .map_err(|e| storage_error("MongoDB index error", e))?;
+        Ok(())
+    }
+
+    async fn load(&self, key: &str) -> Result<Option<BucketDocument>, RateLimitError> {
+        self.collection
+            .find_one(doc! { "_id": key })
+            .await
+            .map_err(|e| storage_error("MongoDB find error", e))
+    }
+
+    fn current_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MongoBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        for _ in 0..MAX_CONDITIONAL_RETRIES {
+            let now_ms = Self::current_millis();
+            let loaded = self.load(key).await?;
+            let (mut state, version) = match &loaded {
+                Some(document) => (
+                    BucketState {
+                        tokens: document.tokens as u64,
+                        last_refill_ms: document.last_refill_ms as u64,
+                    },
+                    Some(document.version),
+                ),
+                None => (BucketState::full(&self.config, now_ms), None),
+            };
+
+            // Denials only refill, which is recomputed on the next read, so
+            // they don't need to spend a write.
+            if !state.try_consume(cost, &self.config, now_ms) {
+                return Ok(false);
+            }
+
+            let expires_at = DateTime::from_millis((now_ms + BUCKET_TTL.as_millis() as u64) as i64);
+
+            let Some(version) = version else {
+                let document = BucketDocument {
+                    key: key.to_string(),
+                    tokens: state.tokens as i64,
+                    last_refill_ms: state.last_refill_ms as i64,
+                    version: 1,
+                    expires_at,
+                };
+                match self.collection.insert_one(document).await {
+                    Ok(_) => return Ok(true),
+                    Err(e) if is_duplicate_key(&e) => continue,
+                    Err(e) => return Err(storage_error("MongoDB insert error", e)),
+                }
+            };
+
+            let updated = self
+                .collection
+                .find_one_and_update(
+                    doc! { "_id": key, "version": version },
+                    doc! {
+                        "$set": {
+                            "tokens": state.tokens as i64,
+                            "last_refill_ms": state.last_refill_ms as i64,
+                            "expires_at": expires_at,
+                        },
+                        "$inc": { "version": 1 },
+                    },
+                )
+                .await
+                .map_err(|e| storage_error("MongoDB update error", e))?;
+
+            if updated.is_some() {
+                return Ok(true);
+            }
+            // Another writer bumped the version; re-read and try again.
+        }
+
+        Err(RateLimitError::StorageError(format!(
+            "MongoDB conditional update lost {} times for key: {}",
+            MAX_CONDITIONAL_RETRIES, key
+        )))
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        match self.load(key).await? {
+            Some(document) => {
+                let mut state = BucketState {
+                    tokens: document.tokens as u64,
+                    last_refill_ms: document.last_refill_ms as u64,
+                };
+                state.refill(&self.config, Self::current_millis());
+                Ok(self.config.capacity.saturating_sub(state.tokens))
+            }
+            None => Ok(0),
+        }
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.collection
+            .delete_one(doc! { "_id": key })
+            .await
+            .map_err(|e| storage_error("MongoDB delete error", e))?;
+        Ok(())
+    }
+}
+
+fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    matches!(
+        e.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(we)) if we.code == DUPLICATE_KEY
+    )
+}
+
+fn storage_error(context: &str, e: impl std::error::Error) -> RateLimitError {
+    RateLimitError::StorageError(format!("{}: {}", context, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore] // Requires MongoDB instance
+    async fn test_mongo_backend() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        };
+
+        let backend = MongoBackend::new(
+            "mongodb://localhost:27017",
+            "guardian_test",
+            "buckets",
+            config,
+        )
+        .await
+        .unwrap();
+        backend.reset("test_user").await.unwrap();
+
+        assert!(backend.take_token("test_user", 6).await.unwrap());
+        assert!(!backend.take_token("test_user", 6).await.unwrap());
+        assert_eq!(backend.get_usage("test_user").await.unwrap(), 6);
+
+        backend.reset("test_user").await.unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+//! Node.js bindings for the Guardian client, built with napi-rs, so
+//! TypeScript services check limits through the same client as Rust ones
+//! instead of reimplementing the protocol.
+//!
+//! ```js
+//! const { GuardianClient } = require('guardian-node');
+//!
+//! const client = await GuardianClient.connect('http://127.0.0.1:50051', {
+//!   apiKey: process.env.GUARDIAN_API_KEY,
+//!   timeoutMs: 50,
+//! });
+//! if (!(await client.checkLimit('user123'))) {
+//!   reply.code(429);
+//! }
+//! ```
+//!
+//! Every call returns a promise. A call that fails rejects with an
+//! `Error` whose message starts with the kind of failure, such as
+//! `UNAVAILABLE: ...` or `PERMISSION_DENIED: ...` (see [`error_kind`]).
+
+use std::time::Duration;
+
+use guardian_client::ClientError;
+use napi::{Error, Result, Status};
+use napi_derive::napi;
+
+/// Settings for [`GuardianClient::connect`], all optional.
+#[napi(object)]
+#[derive(Default)]
+pub struct ConnectOptions {
+    /// Sent as `x-api-key` with every call.
+    pub api_key: Option<String>,
+    /// Sent as `authorization: Bearer <token>` with every call.
+    pub bearer_token: Option<String>,
+    /// Needed for `resetLimit`.
+    pub admin_token: Option<String>,
+    pub namespace: Option<String>,
+    /// Budget for each call, retries included.
+    pub timeout_ms: Option<u32>,
+    pub connect_timeout_ms: Option<u32>,
+    /// Answer checks of keys the service recently denied locally, until
+    /// their retry time has passed.
+    pub denial_cache: Option<bool>,
+}
+
+/// The service's answer to one check.
+#[napi(object)]
+pub struct LimitCheckResult {
+    pub allowed: bool,
+    pub retry_after_seconds: u32,
+    pub remaining_tokens: i64,
+    /// Capacity of the bucket that decided; 0 if the service didn't say.
+    pub limit: i64,
+    /// Rate-limit response headers for relaying the decision, by
+    /// lowercase name.
+    pub headers: std::collections::HashMap<String, String>,
+}
+
+impl From<guardian_client::LimitCheckResult> for LimitCheckResult {
+    fn from(result: guardian_client::LimitCheckResult) -> Self {
+        let headers = result
+            .to_headers()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        Self {
+            allowed: result.allowed,
+            retry_after_seconds: result.retry_after_seconds,
+            remaining_tokens: saturating_i64(result.remaining_tokens),
+            limit: saturating_i64(result.limit),
+            headers,
+        }
+    }
+}
+
+fn saturating_i64(n: u64) -> i64 {
+    i64::try_from(n).unwrap_or(i64::MAX)
+}
+
+/// A Guardian client. Share one across requests: calls are multiplexed
+/// over its connection.
+#[napi]
+pub struct GuardianClient {
+    inner: guardian_client::GuardianClient,
+}
+
+#[napi]
+impl GuardianClient {
+    /// Connect to the service at `addr`, such as `http://127.0.0.1:50051`.
+    #[napi(factory)]
+    pub async fn connect(addr: String, options: Option<ConnectOptions>) -> Result<Self> {
+        let options = options.unwrap_or_default();
+        let mut builder = guardian_client::GuardianClient::builder();
+        if let Some(key) = options.api_key {
+            builder = builder.api_key(key);
+        }
+        if let Some(token) = options.bearer_token {
+            builder = builder.bearer_token(token);
+        }
+        if let Some(token) = options.admin_token {
+            builder = builder.admin_token(token);
+        }
+        if let Some(namespace) = options.namespace {
+            builder = builder.namespace(namespace);
+        }
+        if let Some(ms) = options.timeout_ms {
+            builder = builder.timeout(Duration::from_millis(ms.into()));
+        }
+        if let Some(ms) = options.connect_timeout_ms {
+            builder = builder.connect_timeout(Duration::from_millis(ms.into()));
+        }
+        if options.denial_cache == Some(true) {
+            builder = builder.denial_cache();
+        }
+        let inner = builder.connect(addr).await.map_err(to_js)?;
+        Ok(Self { inner })
+    }
+
+    /// Whether `clientId` may spend `cost` tokens (default 1) now.
+    #[napi]
+    pub async fn check_limit(&self, client_id: String, cost: Option<u32>) -> Result<bool> {
+        self.inner
+            .check_limit(&client_id, cost.unwrap_or(1))
+            .await
+            .map_err(to_js)
+    }
+
+    /// Like `checkLimit`, with the remaining tokens, retry time and
+    /// response headers.
+    #[napi]
+    pub async fn check_limit_detailed(
+        &self,
+        client_id: String,
+        cost: Option<u32>,
+    ) -> Result<LimitCheckResult> {
+        self.inner
+            .check_limit_detailed(&client_id, cost.unwrap_or(1))
+            .await
+            .map(Into::into)
+            .map_err(to_js)
+    }
+
+    /// Check against one of the service's configured tiers.
+    #[napi]
+    pub async fn check_limit_for_tier(
+        &self,
+        client_id: String,
+        tier: String,
+        cost: Option<u32>,
+    ) -> Result<bool> {
+        self.inner
+            .check_limit_for_tier(&client_id, &tier, cost.unwrap_or(1))
+            .await
+            .map_err(to_js)
+    }
+
+    /// Tokens `clientId` has used from its bucket.
+    #[napi]
+    pub async fn get_usage(&self, client_id: String) -> Result<i64> {
+        self.inner
+            .get_usage(&client_id)
+            .await
+            .map(saturating_i64)
+            .map_err(to_js)
+    }
+
+    /// Refill `clientId`'s bucket. Needs `adminToken`.
+    #[napi]
+    pub async fn reset_limit(&self, client_id: String) -> Result<()> {
+        self.inner.reset_limit(&client_id).await.map_err(to_js)
+    }
+}
+
+/// The kind of failure a rejection's message starts with, so callers can
+/// tell a service that is down from bad credentials without matching on
+/// the whole message.
+pub fn error_kind(e: &ClientError) -> &'static str {
+    match e {
+        ClientError::ConnectionError(_) | ClientError::Unavailable { .. } => "UNAVAILABLE",
+        ClientError::PermissionDenied(_) | ClientError::CredentialsError(_) => "PERMISSION_DENIED",
+        ClientError::InvalidArgument(_) | ClientError::ConfigError(_) => "INVALID_ARGUMENT",
+        ClientError::ServerOverloaded { .. } => "OVERLOADED",
+        ClientError::DeadlineExceeded => "DEADLINE_EXCEEDED",
+        ClientError::RateLimited => "RATE_LIMITED",
+        _ => "ERROR",
+    }
+}
+
+fn to_js(e: ClientError) -> Error {
+    Error::new(Status::GenericFailure, format!("{}: {}", error_kind(&e), e))
+}
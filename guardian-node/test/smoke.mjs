@@ -0,0 +1,46 @@
+// Run with `npm test` after `npm run build`. The live test needs a service:
+// `GUARDIAN_ADDR=http://127.0.0.1:50051 npm test`; it is skipped without
+// GUARDIAN_ADDR.
+import assert from 'node:assert/strict';
+import { createRequire } from 'node:module';
+import net from 'node:net';
+import test from 'node:test';
+
+const require = createRequire(import.meta.url);
+const addr = process.env.GUARDIAN_ADDR;
+
+test('checks, usage and errors', { skip: !addr }, async () => {
+  const { GuardianClient } = require('..');
+  const client = await GuardianClient.connect(addr, { timeoutMs: 1000 });
+  const key = `node-smoke-${process.pid}`;
+
+  assert.equal(await client.checkLimit(key), true);
+  const result = await client.checkLimitDetailed(key, 2);
+  assert.equal(result.allowed, true);
+  assert.equal(result.headers['ratelimit-remaining'], String(result.remainingTokens));
+  assert.equal(await client.getUsage(key), 3);
+
+  // No admin token.
+  await assert.rejects(client.resetLimit(key), /^Error: PERMISSION_DENIED/);
+});
+
+test('rejections start with the kind of failure', async () => {
+  const { GuardianClient } = require('..');
+  await assert.rejects(GuardianClient.connect('http://127.0.0.1:1'), /^Error: UNAVAILABLE/);
+  await assert.rejects(GuardianClient.connect('not a uri'), /^Error: UNAVAILABLE/);
+
+  // Accepts connections but never answers.
+  const sockets = [];
+  const silent = net.createServer((socket) => sockets.push(socket)).listen(0, '127.0.0.1');
+  await new Promise((resolve) => silent.on('listening', resolve));
+  try {
+    const client = await GuardianClient.connect(`http://127.0.0.1:${silent.address().port}`, {
+      timeoutMs: 100,
+    });
+    await assert.rejects(client.checkLimit('node-smoke'), /^Error: DEADLINE_EXCEEDED/);
+    await assert.rejects(client.checkLimitDetailed('node-smoke', 2), /^Error: DEADLINE_EXCEEDED/);
+  } finally {
+    sockets.forEach((socket) => socket.destroy());
+    silent.close();
+  }
+});
@@ -0,0 +1,17 @@
+use std::path::PathBuf;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    // The server and `connect` need tonic's transport, which wasm32 lacks.
+    let native = std::env::var("CARGO_CFG_TARGET_ARCH")? != "wasm32";
+    tonic_build::configure()
+        .build_server(native)
+        .build_transport(native)
+        .file_descriptor_set_path(out_dir.join("guardian_descriptor.bin"))
+        .type_attribute(
+            ".guardian",
+            "#[cfg_attr(feature = \"serde\", derive(serde::Serialize, serde::Deserialize))]",
+        )
+        .compile_protos(&["../proto/guardian.proto"], &["../proto"])?;
+    Ok(())
+}
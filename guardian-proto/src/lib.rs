@@ -0,0 +1,60 @@
+//! Generated types for the Guardian gRPC API (`proto/guardian.proto`).
+//!
+//! Messages and enums come from prost; the `rate_limiter_client` and
+//! `rate_limiter_server` modules hold the tonic client and server. Build
+//! your own client or server on these rather than copying the proto file
+//! and a build script. The `serde` feature derives `Serialize` and
+//! `Deserialize` for every message, using the proto field names.
+
+tonic::include_proto!("guardian");
+
+/// Encoded descriptors, e.g. for gRPC server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("guardian_descriptor");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn test_messages_round_trip_through_the_wire_format() {
+        let request = CheckLimitRequest {
+            client_id: "user1".to_string(),
+            cost: 3,
+            tier: "premium".to_string(),
+            ..Default::default()
+        };
+        let decoded = CheckLimitRequest::decode(request.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded, request);
+    }
+
+    #[test]
+    fn test_descriptor_set_describes_the_rate_limiter_service() {
+        let set = prost_types::FileDescriptorSet::decode(FILE_DESCRIPTOR_SET).unwrap();
+        let file = set
+            .file
+            .iter()
+            .find(|file| file.package() == "guardian")
+            .unwrap();
+        assert!(file.service.iter().any(|s| s.name() == "RateLimiter"));
+        assert!(file
+            .message_type
+            .iter()
+            .any(|m| m.name() == "CheckLimitRequest"));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_uses_proto_field_names() {
+        let request = CheckLimitRequest {
+            client_id: "user1".to_string(),
+            cost: 3,
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["client_id"], "user1");
+        assert_eq!(json["cost"], 3);
+        let back: CheckLimitRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(back, request);
+    }
+}
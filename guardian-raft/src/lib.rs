@@ -0,0 +1,444 @@
+//! Exact global rate limiting without an external datastore. A few Guardian
+//! nodes form a Raft group: every take, refund and reset is appended to a
+//! replicated log, and applied to each node's copy of the buckets once a
+//! majority has it on disk. A key's budget is thus enforced exactly across
+//! the group, and survives the loss of any minority of its nodes.
+//!
+//! ```no_run
+//! use guardian_core::{RateLimiter, TokenBucketConfig};
+//! use guardian_raft::{RaftBackend, RaftConfig, RaftNode};
+//!
+//! # async fn example() -> std::io::Result<()> {
+//! let node = RaftNode::start(RaftConfig {
+//!     id: 1,
+//!     bind: "0.0.0.0:7950".to_string(),
+//!     peers: [(2, "guardian-2:7950".to_string()), (3, "guardian-3:7950".to_string())].into(),
+//!     data_dir: "/var/lib/guardian/raft".into(),
+//!     ..RaftConfig::default()
+//! })
+//! .await?;
+//! let limiter = RateLimiter::new(RaftBackend::new(node, TokenBucketConfig::default()), false);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Each decision waits for a round trip to the leader (from any other node)
+//! and one from the leader to a majority, plus an fsync on each, so this
+//! suits groups of three or five nodes on a fast network. Membership is
+//! fixed by the configuration; every node must list the same peers.
+//! Without a majority, decisions fail and the health check with them.
+
+use async_trait::async_trait;
+use guardian_core::{
+    BucketSnapshot, RateLimitError, StorageBackend, TokenBucketConfig, TokenDecision,
+};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+mod node;
+mod state;
+mod storage;
+mod transport;
+mod wire;
+
+use node::{Core, Event, REQUEST_TIMEOUT};
+use storage::Storage;
+use transport::{Link, Peer};
+use wire::{Answer, Command, Outcome, Query, Request, Response};
+
+#[derive(Debug, Clone)]
+pub struct RaftConfig {
+    /// This node's id, unique within the group and never 0.
+    pub id: u64,
+    /// TCP address peers connect to, e.g. `0.0.0.0:7950`.
+    pub bind: String,
+    /// Every other node's id and address as `host:port`.
+    pub peers: BTreeMap<u64, String>,
+    /// Where the log, snapshot and votes are kept.
+    pub data_dir: PathBuf,
+    /// How long followers wait to hear from a leader before electing
+    /// another (default 500ms), randomized up to twice that. The leader
+    /// sends heartbeats five times as often.
+    pub election_timeout: Duration,
+}
+
+impl Default for RaftConfig {
+    fn default() -> Self {
+        Self {
+            id: 1,
+            bind: "0.0.0.0:7950".to_string(),
+            peers: BTreeMap::new(),
+            data_dir: PathBuf::from("raft"),
+            election_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaftRole {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Where a node stands in the group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaftStatus {
+    pub id: u64,
+    pub role: RaftRole,
+    pub term: u64,
+    /// The leader of the current term, once known.
+    pub leader: Option<u64>,
+    pub commit_index: u64,
+    pub applied_index: u64,
+    pub last_index: u64,
+}
+
+/// One node's membership in the Raft group: its listener, its log and its
+/// copy of every bucket. Shared by every [`RaftBackend`] on the node; the
+/// tasks stop when it is dropped.
+pub struct RaftNode {
+    local_addr: SocketAddr,
+    events: mpsc::UnboundedSender<Event>,
+    status: Arc<Mutex<RaftStatus>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl RaftNode {
+    /// Open the data directory, bind the listener and join the group.
+    pub async fn start(config: RaftConfig) -> io::Result<Arc<Self>> {
+        if config.id == 0 || config.peers.contains_key(&config.id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "raft id must be non-zero and not among the peers",
+            ));
+        }
+        let storage = Storage::open(&config.data_dir)?;
+        let listener = TcpListener::bind(&config.bind).await?;
+        let local_addr = listener.local_addr()?;
+        let peers = config
+            .peers
+            .into_iter()
+            .map(|(id, addr)| (id, Arc::new(Peer::new(addr)) as Arc<dyn Link>))
+            .collect();
+        let (events, receiver) = mpsc::unbounded_channel();
+        let status = Arc::new(Mutex::new(RaftStatus {
+            id: config.id,
+            role: RaftRole::Follower,
+            term: 0,
+            leader: None,
+            commit_index: 0,
+            applied_index: 0,
+            last_index: 0,
+        }));
+        let core = Core::new(
+            config.id,
+            peers,
+            storage,
+            config.election_timeout,
+            events.clone(),
+            Arc::clone(&status),
+        )?;
+        let running = tokio::spawn(core.run(receiver));
+        let serving = tokio::spawn(transport::serve(listener, events.clone()));
+        Ok(Arc::new(Self {
+            local_addr,
+            events,
+            status,
+            tasks: Mutex::new(vec![running, serving]),
+        }))
+    }
+
+    /// The address the listener is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub fn status(&self) -> RaftStatus {
+        *self.status.lock()
+    }
+
+    /// Commit `command` through the leader and return what applying it did.
+    async fn propose(&self, command: Command) -> Result<Outcome, RateLimitError> {
+        match self.call(Request::Propose(command)).await? {
+            Response::Proposed(result) => result.map_err(RateLimitError::StorageError),
+            _ => Err(unexpected()),
+        }
+    }
+
+    /// Ask the leader, whose buckets reflect everything committed.
+    async fn query(&self, query: Query) -> Result<Answer, RateLimitError> {
+        match self.call(Request::Query(query)).await? {
+            Response::Answered(result) => result.map_err(RateLimitError::StorageError),
+            _ => Err(unexpected()),
+        }
+    }
+
+    async fn call(&self, request: Request) -> Result<Response, RateLimitError> {
+        let (reply, response) = oneshot::channel();
+        self.events
+            .send(Event::Local(request, reply))
+            .map_err(|_| RateLimitError::StorageError("raft node stopped".to_string()))?;
+        match tokio::time::timeout(REQUEST_TIMEOUT, response).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(RateLimitError::StorageError(
+                "raft request dropped".to_string(),
+            )),
+            Err(_) => Err(RateLimitError::StorageError(
+                "raft request timed out".to_string(),
+            )),
+        }
+    }
+}
+
+impl Drop for RaftNode {
+    fn drop(&mut self) {
+        for task in self.tasks.get_mut().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+fn unexpected() -> RateLimitError {
+    RateLimitError::StorageError("unexpected raft response".to_string())
+}
+
+/// A [`StorageBackend`] whose buckets are replicated by a [`RaftNode`].
+///
+/// Several backends can share a node, as long as they don't handle the same
+/// keys: each entry carries its backend's bucket configuration, and the last
+/// one applied to a key wins.
+pub struct RaftBackend {
+    node: Arc<RaftNode>,
+    config: TokenBucketConfig,
+}
+
+impl RaftBackend {
+    pub fn new(node: Arc<RaftNode>, config: TokenBucketConfig) -> Self {
+        Self { node, config }
+    }
+
+    pub fn node(&self) -> &Arc<RaftNode> {
+        &self.node
+    }
+}
+
+#[async_trait]
+impl StorageBackend for RaftBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let command = Command::Take {
+            key: key.to_string(),
+            cost,
+            config: self.config.clone(),
+        };
+        match self.node.propose(command).await? {
+            Outcome::Taken { allowed, remaining } => Ok(TokenDecision {
+                allowed,
+                bucket: Some(BucketSnapshot::new(&self.config, remaining)),
+            }),
+            Outcome::Done => Err(unexpected()),
+        }
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let remaining = self
+            .inspect(key)
+            .await?
+            .map_or(self.config.capacity, |b| b.remaining);
+        Ok(self.config.capacity.saturating_sub(remaining))
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let command = Command::Reset {
+            key: key.to_string(),
+        };
+        self.node.propose(command).await.map(|_| ())
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        let command = Command::Refund {
+            key: key.to_string(),
+            tokens,
+            config: self.config.clone(),
+        };
+        self.node.propose(command).await.map(|_| ())
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        let query = Query::Inspect {
+            key: key.to_string(),
+        };
+        match self.node.query(query).await? {
+            Answer::Bucket(remaining) => {
+                Ok(remaining.map(|remaining| BucketSnapshot::new(&self.config, remaining)))
+            }
+            Answer::Keys(_) => Err(unexpected()),
+        }
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        let query = Query::ListKeys {
+            pattern: pattern.to_string(),
+            after: after.map(str::to_string),
+            limit: limit as u64,
+        };
+        match self.node.query(query).await? {
+            Answer::Keys(keys) => Ok(keys),
+            Answer::Bucket(_) => Err(unexpected()),
+        }
+    }
+
+    /// Healthy while some node leads the group, as far as this one knows.
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        match self.node.status().leader {
+            Some(_) => Ok(()),
+            None => Err(RateLimitError::StorageError("no raft leader".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn config(capacity: u64) -> TokenBucketConfig {
+        TokenBucketConfig {
+            capacity,
+            refill_rate: 0,
+            refill_interval: Duration::from_secs(1),
+        }
+    }
+
+    fn free() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn data_dir(name: &str, id: u64) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "guardian-raft-{}-{}-{}",
+            name,
+            std::process::id(),
+            id
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn start(name: &str, id: u64, addrs: &BTreeMap<u64, String>) -> Arc<RaftNode> {
+        let mut peers = addrs.clone();
+        let bind = peers.remove(&id).unwrap();
+        RaftNode::start(RaftConfig {
+            id,
+            bind,
+            peers,
+            data_dir: data_dir(name, id),
+            election_timeout: Duration::from_millis(100),
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Wait for one of `nodes` to lead, and return its position.
+    async fn leader(nodes: &[Option<Arc<RaftNode>>]) -> usize {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let leading = nodes.iter().position(|node| {
+                node.as_ref()
+                    .is_some_and(|node| node.status().role == RaftRole::Leader)
+            });
+            if let Some(position) = leading {
+                return position;
+            }
+            assert!(Instant::now() < deadline, "no leader elected");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_group_shares_a_budget_and_survives_its_leader() {
+        let addrs: BTreeMap<u64, String> = (1..=3).map(|id| (id, free())).collect();
+        let mut nodes = Vec::new();
+        for id in 1..=3 {
+            nodes.push(Some(start("group", id, &addrs).await));
+        }
+        let backend = |node: &Option<Arc<RaftNode>>| {
+            RaftBackend::new(Arc::clone(node.as_ref().unwrap()), config(10))
+        };
+
+        let first = leader(&nodes).await;
+        let follower = (first + 1) % 3;
+        assert!(backend(&nodes[first]).take_token("user1", 6).await.unwrap());
+        // Sent on to the leader.
+        let on_follower = backend(&nodes[follower]);
+        assert!(!on_follower.take_token("user1", 5).await.unwrap());
+        on_follower.refund("user1", 2).await.unwrap();
+        assert_eq!(on_follower.get_usage("user1").await.unwrap(), 4);
+        assert_eq!(
+            on_follower.list_keys("user*", None, 10).await.unwrap(),
+            ["user1"]
+        );
+        on_follower.health_check().await.unwrap();
+
+        // The others elect a leader between them, which has every decision.
+        nodes[first] = None;
+        let second = leader(&nodes).await;
+        assert_ne!(second, first);
+        let on_second = backend(&nodes[second]);
+        assert_eq!(on_second.get_usage("user1").await.unwrap(), 4);
+        assert!(on_second.take_token("user1", 6).await.unwrap());
+        on_second.reset("user1").await.unwrap();
+        assert_eq!(on_second.inspect("user1").await.unwrap(), None);
+
+        for id in 1..=3 {
+            let _ = std::fs::remove_dir_all(data_dir("group", id));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_a_lone_node_restarts_with_its_buckets() {
+        let dir = data_dir("lone", 1);
+        let lone = || {
+            RaftNode::start(RaftConfig {
+                bind: "127.0.0.1:0".to_string(),
+                data_dir: dir.clone(),
+                election_timeout: Duration::from_millis(100),
+                ..RaftConfig::default()
+            })
+        };
+        let node = lone().await.unwrap();
+        leader(&[Some(Arc::clone(&node))]).await;
+        let backend = RaftBackend::new(node, config(10));
+        assert!(backend.take_token("user1", 7).await.unwrap());
+        drop(backend);
+
+        // Its log is applied again once it leads.
+        let node = lone().await.unwrap();
+        leader(&[Some(Arc::clone(&node))]).await;
+        let backend = RaftBackend::new(node, config(10));
+        assert_eq!(backend.get_usage("user1").await.unwrap(), 7);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,1131 @@
+//! The consensus core: one task owning the log, the buckets and the node's
+//! role, driven by requests from peers and local backends, by what peers
+//! answer, and by the election and heartbeat timers. It follows the Raft
+//! paper, with leaders stepping down when a majority stops answering
+//! (so a leader cut off from the cluster stops taking proposals) and
+//! proposals on followers sent on to the leader.
+
+use parking_lot::Mutex;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::BuildHasher;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+use crate::state::Buckets;
+use crate::storage::Storage;
+use crate::transport::Link;
+use crate::wire::{Answer, Command, Entry, Query, Request, Response};
+use crate::{RaftRole, RaftStatus};
+
+/// Most entries sent in one append.
+const MAX_APPEND: usize = 512;
+
+/// Applied entries between snapshots.
+const SNAPSHOT_EVERY: u64 = 10_000;
+
+/// How often the leader drops buckets that have refilled.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a proposal or query waits for an answer, here or from the
+/// leader.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub enum Event {
+    /// A request from another node.
+    Request(Request, oneshot::Sender<Response>),
+    /// A proposal or query from this node's backends, sent on to the
+    /// leader if this node isn't it.
+    Local(Request, oneshot::Sender<Response>),
+    /// What a peer answered, or `None` if it couldn't be reached.
+    Replied {
+        peer: u64,
+        sent: Sent,
+        response: Option<Response>,
+    },
+}
+
+/// A request this node sent, as much as handling the answer needs.
+pub enum Sent {
+    Vote { term: u64 },
+    Append { term: u64, prev_index: u64 },
+    Snapshot { term: u64, last_index: u64 },
+}
+
+/// What the leader knows of a follower's log.
+struct Progress {
+    next: u64,
+    matched: u64,
+    inflight: bool,
+    /// Not before this, after failing to reach it, unless a heartbeat is
+    /// due.
+    retry_at: Instant,
+    answered_at: Instant,
+}
+
+enum Role {
+    Follower,
+    Candidate {
+        votes: BTreeSet<u64>,
+    },
+    Leader {
+        progress: BTreeMap<u64, Progress>,
+        heartbeat_at: Instant,
+        pruned_at: Instant,
+    },
+}
+
+pub struct Core {
+    id: u64,
+    peers: BTreeMap<u64, Arc<dyn Link>>,
+    storage: Storage,
+    buckets: Buckets,
+    role: Role,
+    leader: Option<u64>,
+    commit: u64,
+    applied: u64,
+    /// The latest time any entry carries, which new entries never precede.
+    clock_ms: u64,
+    election_timeout: Duration,
+    election_at: Instant,
+    heartbeat_due: bool,
+    /// Appended entries not yet synced.
+    unsynced: bool,
+    /// Proposals waiting for their entries to be applied, by index, with
+    /// the term they were appended in.
+    waiting: BTreeMap<u64, (u64, oneshot::Sender<Response>)>,
+    /// Queries held until the leader has applied an entry of its own term,
+    /// and with it everything earlier leaders committed.
+    reads: Vec<(Query, oneshot::Sender<Response>)>,
+    events: mpsc::UnboundedSender<Event>,
+    status: Arc<Mutex<RaftStatus>>,
+    /// Applied entries between snapshots.
+    snapshot_every: u64,
+    /// State of the xorshift generator that jitters election timeouts.
+    jitter: u64,
+}
+
+impl Core {
+    pub fn new(
+        id: u64,
+        peers: BTreeMap<u64, Arc<dyn Link>>,
+        (storage, snapshot): (Storage, Option<Vec<u8>>),
+        election_timeout: Duration,
+        events: mpsc::UnboundedSender<Event>,
+        status: Arc<Mutex<RaftStatus>>,
+    ) -> io::Result<Self> {
+        let buckets = match snapshot {
+            Some(data) => Buckets::decode(&data).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "raft snapshot is corrupt")
+            })?,
+            None => Buckets::default(),
+        };
+        let applied = storage.snapshot_index;
+        let mut core = Self {
+            id,
+            peers,
+            storage,
+            buckets,
+            role: Role::Follower,
+            leader: None,
+            commit: applied,
+            applied,
+            clock_ms: 0,
+            election_timeout,
+            election_at: Instant::now(),
+            heartbeat_due: false,
+            unsynced: false,
+            waiting: BTreeMap::new(),
+            reads: Vec::new(),
+            events,
+            status,
+            snapshot_every: SNAPSHOT_EVERY,
+            jitter: RandomState::new().hash_one(id) | 1,
+        };
+        // A lone node needn't wait to hear from a leader.
+        if core.peers.is_empty() {
+            core.start_election()?;
+        } else {
+            core.reset_election();
+        }
+        core.publish();
+        Ok(core)
+    }
+
+    pub async fn run(mut self, mut events: mpsc::UnboundedReceiver<Event>) {
+        loop {
+            let deadline = match &self.role {
+                Role::Leader { heartbeat_at, .. } => *heartbeat_at,
+                _ => self.election_at,
+            };
+            let result = tokio::select! {
+                event = events.recv() => match event {
+                    Some(event) => self.handle(event),
+                    None => return,
+                },
+                _ = tokio::time::sleep_until(deadline) => self.tick(),
+            };
+            // Take whatever else arrived meanwhile, to sync it all at once.
+            let result = result.and_then(|()| {
+                while let Ok(event) = events.try_recv() {
+                    self.handle(event)?;
+                }
+                self.flush()
+            });
+            if let Err(e) = result {
+                tracing::error!(error = %e, "raft storage failed");
+            }
+        }
+    }
+
+    fn handle(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::Request(request, reply) => {
+                let response = match request {
+                    Request::Vote {
+                        term,
+                        candidate,
+                        last_index,
+                        last_term,
+                    } => self.vote(term, candidate, last_index, last_term)?,
+                    Request::Append {
+                        term,
+                        leader,
+                        prev_index,
+                        prev_term,
+                        entries,
+                        commit,
+                    } => self.append(term, leader, prev_index, prev_term, entries, commit)?,
+                    Request::Snapshot {
+                        term,
+                        leader,
+                        last_index,
+                        last_term,
+                        data,
+                    } => self.install(term, leader, last_index, last_term, data)?,
+                    // Answered by the leader only; never sent on again.
+                    request if self.is_leader() => return self.local(request, reply),
+                    request => failed(&request, "not the raft leader".to_string()),
+                };
+                let _ = reply.send(response);
+            }
+            Event::Local(request, reply) => return self.local(request, reply),
+            Event::Replied {
+                peer,
+                sent,
+                response,
+            } => return self.replied(peer, sent, response),
+        }
+        Ok(())
+    }
+
+    fn local(&mut self, request: Request, reply: oneshot::Sender<Response>) -> io::Result<()> {
+        if !self.is_leader() {
+            let Some(peer) = self.leader.and_then(|leader| self.peers.get(&leader)) else {
+                let _ = reply.send(failed(&request, "no raft leader".to_string()));
+                return Ok(());
+            };
+            let peer = Arc::clone(peer);
+            tokio::spawn(async move {
+                let response = match peer.call(&request, REQUEST_TIMEOUT).await {
+                    Ok(response) => response,
+                    Err(e) => failed(&request, format!("raft leader: {}", e)),
+                };
+                let _ = reply.send(response);
+            });
+            return Ok(());
+        }
+        match request {
+            Request::Propose(command) => {
+                let index = self.propose(command)?;
+                self.waiting.insert(index, (self.storage.term, reply));
+            }
+            Request::Query(query) => self.reads.push((query, reply)),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Append `command` to the leader's log, returning its index.
+    fn propose(&mut self, command: Command) -> io::Result<u64> {
+        self.clock_ms = self.clock_ms.max(current_millis());
+        self.storage.append(Entry {
+            term: self.storage.term,
+            now_ms: self.clock_ms,
+            command,
+        })?;
+        self.unsynced = true;
+        Ok(self.storage.last_index())
+    }
+
+    fn vote(
+        &mut self,
+        term: u64,
+        candidate: u64,
+        last_index: u64,
+        last_term: u64,
+    ) -> io::Result<Response> {
+        if term > self.storage.term {
+            self.step_down(term, None)?;
+        }
+        let up_to_date =
+            (last_term, last_index) >= (self.storage.last_term(), self.storage.last_index());
+        let granted = term == self.storage.term
+            && up_to_date
+            && self.storage.voted_for.is_none_or(|vote| vote == candidate);
+        if granted {
+            self.storage.save_vote(term, Some(candidate))?;
+            self.reset_election();
+        }
+        Ok(Response::Vote {
+            term: self.storage.term,
+            granted,
+        })
+    }
+
+    fn append(
+        &mut self,
+        term: u64,
+        leader: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+    ) -> io::Result<Response> {
+        if term < self.storage.term {
+            return Ok(Response::Append {
+                term: self.storage.term,
+                success: false,
+                last_index: self.storage.last_index(),
+            });
+        }
+        self.step_down(term, Some(leader))?;
+        let rejected = |last_index| Response::Append {
+            term,
+            success: false,
+            last_index,
+        };
+        if prev_index > self.storage.last_index() {
+            return Ok(rejected(self.storage.last_index()));
+        }
+        // Entries the snapshot covers are committed, so agree.
+        if prev_index > self.storage.snapshot_index {
+            let conflict = self.storage.term_at(prev_index).filter(|t| *t != prev_term);
+            if let Some(conflict) = conflict {
+                // Skip back past every entry of the conflicting term.
+                let mut index = prev_index;
+                while index > self.storage.snapshot_index + 1
+                    && self.storage.term_at(index - 1) == Some(conflict)
+                {
+                    index -= 1;
+                }
+                return Ok(rejected(index - 1));
+            }
+        }
+        let mut index = prev_index;
+        let mut appended = false;
+        for entry in entries {
+            index += 1;
+            if index <= self.storage.snapshot_index {
+                continue;
+            }
+            match self.storage.term_at(index) {
+                Some(existing) if existing == entry.term => continue,
+                Some(_) => self.storage.truncate(index)?,
+                None => {}
+            }
+            self.clock_ms = self.clock_ms.max(entry.now_ms);
+            self.storage.append(entry)?;
+            appended = true;
+        }
+        if appended {
+            self.storage.sync()?;
+        }
+        self.commit = self.commit.max(commit.min(index));
+        Ok(Response::Append {
+            term,
+            success: true,
+            last_index: index,
+        })
+    }
+
+    fn install(
+        &mut self,
+        term: u64,
+        leader: u64,
+        last_index: u64,
+        last_term: u64,
+        data: Vec<u8>,
+    ) -> io::Result<Response> {
+        if term < self.storage.term {
+            return Ok(Response::Snapshot {
+                term: self.storage.term,
+            });
+        }
+        self.step_down(term, Some(leader))?;
+        if last_index > self.applied {
+            match Buckets::decode(&data) {
+                Some(buckets) => {
+                    self.storage.save_snapshot(last_index, last_term, &data)?;
+                    self.buckets = buckets;
+                    self.applied = last_index;
+                    self.commit = self.commit.max(last_index);
+                }
+                None => tracing::warn!(leader, "undecodable raft snapshot"),
+            }
+        }
+        Ok(Response::Snapshot { term })
+    }
+
+    fn replied(&mut self, peer: u64, sent: Sent, response: Option<Response>) -> io::Result<()> {
+        let current = self.storage.term;
+        let responded = match &response {
+            Some(Response::Vote { term, .. })
+            | Some(Response::Append { term, .. })
+            | Some(Response::Snapshot { term }) => Some(*term),
+            _ => None,
+        };
+        if let Some(term) = responded.filter(|term| *term > current) {
+            return self.step_down(term, None);
+        }
+        let now = Instant::now();
+        let majority = self.majority();
+        match (&mut self.role, sent) {
+            (Role::Candidate { votes }, Sent::Vote { term }) if term == current => {
+                if let Some(Response::Vote { granted: true, .. }) = response {
+                    votes.insert(peer);
+                    if votes.len() >= majority {
+                        self.become_leader()?;
+                    }
+                }
+            }
+            (Role::Leader { progress, .. }, Sent::Append { term, prev_index })
+                if term == current =>
+            {
+                let Some(progress) = progress.get_mut(&peer) else {
+                    return Ok(());
+                };
+                progress.inflight = false;
+                match response {
+                    Some(Response::Append {
+                        success,
+                        last_index,
+                        ..
+                    }) => {
+                        progress.answered_at = now;
+                        if success {
+                            progress.matched = progress.matched.max(last_index);
+                            progress.next = progress.matched + 1;
+                        } else {
+                            progress.next = (last_index + 1).min(prev_index).max(1);
+                        }
+                    }
+                    _ => progress.retry_at = now + self.election_timeout / 5,
+                }
+            }
+            (Role::Leader { progress, .. }, Sent::Snapshot { term, last_index })
+                if term == current =>
+            {
+                let Some(progress) = progress.get_mut(&peer) else {
+                    return Ok(());
+                };
+                progress.inflight = false;
+                if response.is_some() {
+                    progress.answered_at = now;
+                    progress.matched = progress.matched.max(last_index);
+                    progress.next = progress.matched + 1;
+                } else {
+                    progress.retry_at = now + self.election_timeout / 5;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let majority = self.majority();
+        let timeout = self.election_timeout;
+        let Role::Leader {
+            progress,
+            heartbeat_at,
+            pruned_at,
+        } = &mut self.role
+        else {
+            if now >= self.election_at {
+                self.start_election()?;
+            }
+            return Ok(());
+        };
+        if now < *heartbeat_at {
+            return Ok(());
+        }
+        let answering = progress
+            .values()
+            .filter(|p| p.answered_at.elapsed() < timeout)
+            .count();
+        if 1 + answering < majority {
+            tracing::warn!(term = self.storage.term, "raft leader lost its majority");
+            let term = self.storage.term;
+            return self.step_down(term, None);
+        }
+        *heartbeat_at = now + timeout / 5;
+        self.heartbeat_due = true;
+        if pruned_at.elapsed() >= PRUNE_INTERVAL {
+            *pruned_at = now;
+            self.propose(Command::Prune)?;
+        }
+        Ok(())
+    }
+
+    fn start_election(&mut self) -> io::Result<()> {
+        let term = self.storage.term + 1;
+        self.storage.save_vote(term, Some(self.id))?;
+        self.role = Role::Candidate {
+            votes: BTreeSet::from([self.id]),
+        };
+        self.leader = None;
+        self.reset_election();
+        if self.majority() == 1 {
+            return self.become_leader();
+        }
+        tracing::debug!(term, "raft election started");
+        let request = Request::Vote {
+            term,
+            candidate: self.id,
+            last_index: self.storage.last_index(),
+            last_term: self.storage.last_term(),
+        };
+        for &peer in self.peers.keys() {
+            self.send(peer, request.clone(), Sent::Vote { term });
+        }
+        Ok(())
+    }
+
+    fn become_leader(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        let next = self.storage.last_index() + 1;
+        let progress = self
+            .peers
+            .keys()
+            .map(|&peer| {
+                let progress = Progress {
+                    next,
+                    matched: 0,
+                    inflight: false,
+                    retry_at: now,
+                    answered_at: now,
+                };
+                (peer, progress)
+            })
+            .collect();
+        self.role = Role::Leader {
+            progress,
+            heartbeat_at: now + self.election_timeout / 5,
+            pruned_at: now,
+        };
+        self.leader = Some(self.id);
+        self.heartbeat_due = true;
+        tracing::info!(term = self.storage.term, "raft leader elected");
+        // Commits what earlier terms left uncommitted.
+        self.propose(Command::Noop)?;
+        Ok(())
+    }
+
+    /// Follow `leader` (or no one yet) in `term`.
+    fn step_down(&mut self, term: u64, leader: Option<u64>) -> io::Result<()> {
+        if term > self.storage.term {
+            self.storage.save_vote(term, None)?;
+        }
+        if !matches!(self.role, Role::Follower) || leader.is_some() {
+            self.reset_election();
+        }
+        self.role = Role::Follower;
+        self.leader = leader;
+        Ok(())
+    }
+
+    /// Sync, send followers what they lack, then commit and apply what a
+    /// majority holds.
+    fn flush(&mut self) -> io::Result<()> {
+        if self.unsynced {
+            self.storage.sync()?;
+            self.unsynced = false;
+        }
+        self.replicate()?;
+        self.advance_commit();
+        self.apply()?;
+        self.answer();
+        if self.applied - self.storage.snapshot_index >= self.snapshot_every {
+            let term = self.storage.term_at(self.applied).unwrap_or_default();
+            self.storage
+                .save_snapshot(self.applied, term, &self.buckets.encode())?;
+        }
+        self.publish();
+        Ok(())
+    }
+
+    fn replicate(&mut self) -> io::Result<()> {
+        let heartbeat = std::mem::take(&mut self.heartbeat_due);
+        let Role::Leader { progress, .. } = &mut self.role else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let term = self.storage.term;
+        let last_index = self.storage.last_index();
+        let mut sends = Vec::new();
+        for (&peer, progress) in progress.iter_mut() {
+            let behind = progress.next <= last_index && now >= progress.retry_at;
+            if progress.inflight || !(heartbeat || behind) {
+                continue;
+            }
+            progress.inflight = true;
+            if progress.next <= self.storage.snapshot_index {
+                let last_index = self.storage.snapshot_index;
+                let request = Request::Snapshot {
+                    term,
+                    leader: self.id,
+                    last_index,
+                    last_term: self.storage.snapshot_term,
+                    data: self.storage.snapshot()?,
+                };
+                sends.push((peer, request, Sent::Snapshot { term, last_index }));
+            } else {
+                let prev_index = progress.next - 1;
+                let request = Request::Append {
+                    term,
+                    leader: self.id,
+                    prev_index,
+                    prev_term: self.storage.term_at(prev_index).unwrap_or_default(),
+                    entries: self.storage.entries_from(progress.next, MAX_APPEND),
+                    commit: self.commit,
+                };
+                sends.push((peer, request, Sent::Append { term, prev_index }));
+            }
+        }
+        for (peer, request, sent) in sends {
+            self.send(peer, request, sent);
+        }
+        Ok(())
+    }
+
+    fn advance_commit(&mut self) {
+        let Role::Leader { progress, .. } = &self.role else {
+            return;
+        };
+        let mut matched: Vec<u64> = progress.values().map(|p| p.matched).collect();
+        matched.push(self.storage.last_index());
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let replicated = matched[self.majority() - 1];
+        // Only entries of its own term are committed by counting replicas.
+        if replicated > self.commit && self.storage.term_at(replicated) == Some(self.storage.term) {
+            self.commit = replicated;
+        }
+    }
+
+    fn apply(&mut self) -> io::Result<()> {
+        while self.applied < self.commit {
+            let index = self.applied + 1;
+            let Some(entry) = self.storage.entry(index) else {
+                break;
+            };
+            let outcome = self.buckets.apply(entry);
+            self.clock_ms = self.clock_ms.max(entry.now_ms);
+            self.applied = index;
+            if let Some((term, reply)) = self.waiting.remove(&index) {
+                let result = if term == entry.term {
+                    Ok(outcome)
+                } else {
+                    Err("lost to a raft leader change".to_string())
+                };
+                let _ = reply.send(Response::Proposed(result));
+            }
+        }
+        Ok(())
+    }
+
+    fn answer(&mut self) {
+        let current =
+            self.is_leader() && self.storage.term_at(self.applied) == Some(self.storage.term);
+        if self.reads.is_empty() || (self.is_leader() && !current) {
+            return;
+        }
+        let now_ms = current_millis();
+        for (query, reply) in self.reads.drain(..) {
+            let answer = match query {
+                _ if !current => Err("lost to a raft leader change".to_string()),
+                Query::Inspect { key } => Ok(Answer::Bucket(self.buckets.tokens(&key, now_ms))),
+                Query::ListKeys {
+                    pattern,
+                    after,
+                    limit,
+                } => Ok(Answer::Keys(self.buckets.keys(
+                    &pattern,
+                    after.as_deref(),
+                    limit as usize,
+                ))),
+            };
+            let _ = reply.send(Response::Answered(answer));
+        }
+    }
+
+    fn send(&self, peer: u64, request: Request, sent: Sent) {
+        let Some(client) = self.peers.get(&peer).cloned() else {
+            return;
+        };
+        let events = self.events.clone();
+        let timeout = self.election_timeout;
+        tokio::spawn(async move {
+            let response = client.call(&request, timeout).await.ok();
+            let _ = events.send(Event::Replied {
+                peer,
+                sent,
+                response,
+            });
+        });
+    }
+
+    fn publish(&self) {
+        *self.status.lock() = RaftStatus {
+            id: self.id,
+            role: match self.role {
+                Role::Follower => RaftRole::Follower,
+                Role::Candidate { .. } => RaftRole::Candidate,
+                Role::Leader { .. } => RaftRole::Leader,
+            },
+            term: self.storage.term,
+            leader: self.leader,
+            commit_index: self.commit,
+            applied_index: self.applied,
+            last_index: self.storage.last_index(),
+        };
+    }
+
+    fn is_leader(&self) -> bool {
+        matches!(self.role, Role::Leader { .. })
+    }
+
+    fn majority(&self) -> usize {
+        let nodes = self.peers.len() + 1;
+        nodes / 2 + 1
+    }
+
+    /// Expect to hear from a leader within one to two election timeouts.
+    fn reset_election(&mut self) {
+        self.jitter ^= self.jitter << 13;
+        self.jitter ^= self.jitter >> 7;
+        self.jitter ^= self.jitter << 17;
+        let jitter = self.jitter % 1000;
+        self.election_at =
+            Instant::now() + self.election_timeout.mul_f64(1.0 + jitter as f64 / 1000.0);
+    }
+}
+
+/// The response to a proposal or query that couldn't be answered.
+fn failed(request: &Request, error: String) -> Response {
+    match request {
+        Request::Query(_) => Response::Answered(Err(error)),
+        _ => Response::Proposed(Err(error)),
+    }
+}
+
+pub fn current_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::Outcome;
+    use async_trait::async_trait;
+    use guardian_core::TokenBucketConfig;
+    use std::path::{Path, PathBuf};
+    use tokio::task::JoinHandle;
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    fn take(key: &str, cost: u64) -> Command {
+        Command::Take {
+            key: key.to_string(),
+            cost,
+            config: TokenBucketConfig {
+                capacity: 10,
+                refill_rate: 0,
+                refill_interval: Duration::from_secs(1),
+            },
+        }
+    }
+
+    fn data_dir(name: &str, id: u64) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "guardian-raft-node-{}-{}-{}",
+            name,
+            std::process::id(),
+            id
+        ))
+    }
+
+    /// Nodes reached in memory, any of which can be cut off from the rest.
+    #[derive(Default)]
+    struct Net {
+        inboxes: Mutex<BTreeMap<u64, mpsc::UnboundedSender<Event>>>,
+        isolated: Mutex<BTreeSet<u64>>,
+    }
+
+    impl Net {
+        fn route(&self, from: u64, to: u64) -> Option<mpsc::UnboundedSender<Event>> {
+            let isolated = self.isolated.lock();
+            if isolated.contains(&from) || isolated.contains(&to) {
+                return None;
+            }
+            self.inboxes.lock().get(&to).cloned()
+        }
+    }
+
+    /// One node's link to another across a [`Net`].
+    struct Wire {
+        from: u64,
+        to: u64,
+        net: Arc<Net>,
+    }
+
+    #[async_trait]
+    impl Link for Wire {
+        async fn call(&self, request: &Request, timeout: Duration) -> io::Result<Response> {
+            let unreachable = || io::Error::new(io::ErrorKind::TimedOut, "unreachable");
+            let inbox = self.net.route(self.from, self.to).ok_or_else(unreachable)?;
+            let (reply, response) = oneshot::channel();
+            let _ = inbox.send(Event::Request(request.clone(), reply));
+            let response = tokio::time::timeout(timeout, response).await;
+            // An answer is lost to a partition made while it was coming.
+            match (response, self.net.route(self.to, self.from)) {
+                (Ok(Ok(response)), Some(_)) => Ok(response),
+                _ => Err(unreachable()),
+            }
+        }
+    }
+
+    /// Node `id` of `size`, keeping its log in `dir`. Its election timeouts
+    /// are jittered from its id, so every run elects the same way.
+    fn core(
+        id: u64,
+        size: u64,
+        net: &Arc<Net>,
+        dir: &Path,
+    ) -> (Core, mpsc::UnboundedReceiver<Event>) {
+        let peers = (1..=size)
+            .filter(|&to| to != id)
+            .map(|to| {
+                let wire = Wire {
+                    from: id,
+                    to,
+                    net: Arc::clone(net),
+                };
+                (to, Arc::new(wire) as Arc<dyn Link>)
+            })
+            .collect();
+        let status = Arc::new(Mutex::new(RaftStatus {
+            id,
+            role: RaftRole::Follower,
+            term: 0,
+            leader: None,
+            commit_index: 0,
+            applied_index: 0,
+            last_index: 0,
+        }));
+        let (events, receiver) = mpsc::unbounded_channel();
+        let storage = Storage::open(dir).unwrap();
+        let mut core = Core::new(id, peers, storage, TIMEOUT, events, status).unwrap();
+        core.jitter = id;
+        core.reset_election();
+        (core, receiver)
+    }
+
+    /// Nodes 1 to `size` on one [`Net`]. Run on paused time, a cluster
+    /// plays out the same way every time.
+    struct Cluster {
+        name: &'static str,
+        size: u64,
+        snapshot_every: u64,
+        net: Arc<Net>,
+        running: BTreeMap<u64, (JoinHandle<()>, Arc<Mutex<RaftStatus>>)>,
+    }
+
+    impl Cluster {
+        fn new(name: &'static str, size: u64, snapshot_every: u64) -> Self {
+            let mut cluster = Self {
+                name,
+                size,
+                snapshot_every,
+                net: Arc::default(),
+                running: BTreeMap::new(),
+            };
+            for id in 1..=size {
+                let _ = std::fs::remove_dir_all(data_dir(name, id));
+                cluster.start(id);
+            }
+            cluster
+        }
+
+        fn dir(&self, id: u64) -> PathBuf {
+            data_dir(self.name, id)
+        }
+
+        /// Start node `id` from whatever its directory holds.
+        fn start(&mut self, id: u64) {
+            let (mut core, receiver) = core(id, self.size, &self.net, &self.dir(id));
+            core.snapshot_every = self.snapshot_every;
+            let status = Arc::clone(&core.status);
+            self.net.inboxes.lock().insert(id, core.events.clone());
+            self.running
+                .insert(id, (tokio::spawn(core.run(receiver)), status));
+        }
+
+        fn stop(&mut self, id: u64) {
+            self.net.inboxes.lock().remove(&id);
+            if let Some((task, _)) = self.running.remove(&id) {
+                task.abort();
+            }
+        }
+
+        fn isolate(&self, id: u64) {
+            self.net.isolated.lock().insert(id);
+        }
+
+        fn heal(&self) {
+            self.net.isolated.lock().clear();
+        }
+
+        fn status(&self, id: u64) -> RaftStatus {
+            *self.running[&id].1.lock()
+        }
+
+        /// What the running nodes that aren't cut off report.
+        fn reachable(&self) -> Vec<RaftStatus> {
+            let isolated = self.net.isolated.lock().clone();
+            self.running
+                .iter()
+                .filter(|(id, _)| !isolated.contains(id))
+                .map(|(_, (_, status))| *status.lock())
+                .collect()
+        }
+
+        /// The leader every reachable node follows, once there is one.
+        async fn leader(&self) -> u64 {
+            for _ in 0..500 {
+                tokio::time::sleep(TIMEOUT / 10).await;
+                let statuses = self.reachable();
+                let mut leaders = statuses.iter().filter(|s| s.role == RaftRole::Leader);
+                let (Some(leader), None) = (leaders.next(), leaders.next()) else {
+                    continue;
+                };
+                if statuses
+                    .iter()
+                    .all(|s| s.leader == Some(leader.id) && s.term == leader.term)
+                {
+                    return leader.id;
+                }
+            }
+            panic!("no leader elected");
+        }
+
+        /// Wait for every reachable node to apply the whole of one log.
+        async fn settle(&self) {
+            for _ in 0..500 {
+                tokio::time::sleep(TIMEOUT / 10).await;
+                let statuses = self.reachable();
+                let first = statuses[0];
+                if statuses.iter().all(|s| {
+                    (s.last_index, s.applied_index) == (first.last_index, first.last_index)
+                }) {
+                    return;
+                }
+            }
+            panic!("logs never converged");
+        }
+
+        /// Hand `request` to node `id` as its own backends would.
+        fn send(&self, id: u64, request: Request) -> oneshot::Receiver<Response> {
+            let (reply, response) = oneshot::channel();
+            let _ = self.net.inboxes.lock()[&id].send(Event::Local(request, reply));
+            response
+        }
+
+        async fn take(&self, id: u64, key: &str, cost: u64) -> Outcome {
+            match self.send(id, Request::Propose(take(key, cost))).await {
+                Ok(Response::Proposed(Ok(outcome))) => outcome,
+                other => panic!("take failed: {:?}", other),
+            }
+        }
+
+        async fn tokens(&self, id: u64, key: &str) -> Option<u64> {
+            let query = Query::Inspect {
+                key: key.to_string(),
+            };
+            match self.send(id, Request::Query(query)).await {
+                Ok(Response::Answered(Ok(Answer::Bucket(tokens)))) => tokens,
+                other => panic!("query failed: {:?}", other),
+            }
+        }
+    }
+
+    impl Drop for Cluster {
+        fn drop(&mut self) {
+            for id in 1..=self.size {
+                self.stop(id);
+                let _ = std::fs::remove_dir_all(self.dir(id));
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_partitioned_leader_steps_down_and_loses_what_it_could_not_commit() {
+        let cluster = Cluster::new("partition", 3, SNAPSHOT_EVERY);
+        let old = cluster.leader().await;
+        let term = cluster.status(old).term;
+        let allowed = Outcome::Taken {
+            allowed: true,
+            remaining: 6,
+        };
+        assert_eq!(cluster.take(old, "user1", 4).await, allowed);
+
+        // Cut off, the leader still appends a proposal, but no majority
+        // hears of it.
+        cluster.isolate(old);
+        let lost = cluster.send(old, Request::Propose(take("user1", 5)));
+        let new = cluster.leader().await;
+        assert_ne!(new, old);
+        assert!(cluster.status(new).term > term);
+        assert_ne!(cluster.status(old).role, RaftRole::Leader);
+        let rest = Outcome::Taken {
+            allowed: true,
+            remaining: 0,
+        };
+        assert_eq!(cluster.take(new, "user1", 6).await, rest);
+
+        // Back in touch, the old leader can't win an election with its
+        // stale log, and gives up the entry it appended alone.
+        cluster.heal();
+        assert_ne!(cluster.leader().await, old);
+        assert_eq!(
+            lost.await.unwrap(),
+            Response::Proposed(Err("lost to a raft leader change".to_string()))
+        );
+        cluster.settle().await;
+        let leader = cluster.leader().await;
+        assert_eq!(cluster.tokens(leader, "user1").await, Some(0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_node_behind_the_leaders_snapshot_installs_it() {
+        let mut cluster = Cluster::new("snapshot", 3, 4);
+        let leader = cluster.leader().await;
+        let behind = leader % 3 + 1;
+        cluster.stop(behind);
+        for remaining in (0..10).rev() {
+            let taken = Outcome::Taken {
+                allowed: true,
+                remaining,
+            };
+            assert_eq!(cluster.take(leader, "user1", 1).await, taken);
+        }
+        let (compacted, _) = Storage::open(&cluster.dir(leader)).unwrap();
+        assert!(compacted.snapshot_index > 4);
+
+        // The entries it lacks are gone from the leader's log, so it is
+        // sent the snapshot, then what came after.
+        cluster.start(behind);
+        cluster.settle().await;
+        cluster.stop(behind);
+        let (storage, snapshot) = Storage::open(&cluster.dir(behind)).unwrap();
+        assert!(storage.snapshot_index > 4);
+        let mut buckets = Buckets::decode(&snapshot.unwrap()).unwrap();
+        for index in storage.snapshot_index + 1..=storage.last_index() {
+            buckets.apply(storage.entry(index).unwrap());
+        }
+        assert_eq!(buckets.tokens("user1", current_millis()), Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_a_follower_truncates_entries_that_conflict_with_the_leader() {
+        let dir = data_dir("conflict", 1);
+        let _ = std::fs::remove_dir_all(&dir);
+        let (mut follower, _events) = core(1, 3, &Arc::default(), &dir);
+        let entries = |term, n| {
+            (0..n)
+                .map(|_| Entry {
+                    term,
+                    now_ms: 0,
+                    command: take("user1", 1),
+                })
+                .collect::<Vec<_>>()
+        };
+        let appended = |response| match response {
+            Response::Append {
+                success,
+                last_index,
+                ..
+            } => (success, last_index),
+            other => panic!("unexpected {:?}", other),
+        };
+
+        // Node 2 leads term 1 and replicates three entries, committing one.
+        let response = follower.append(1, 2, 0, 0, entries(1, 3), 1).unwrap();
+        assert_eq!(appended(response), (true, 3));
+
+        // Node 3 leads term 2 with a log that has entry 1 but not the rest.
+        // Told where it holds term 1, the leader backs up past all of it.
+        let response = follower.append(2, 3, 2, 2, entries(2, 1), 1).unwrap();
+        assert_eq!(appended(response), (false, 0));
+        let response = follower.append(2, 3, 1, 1, entries(2, 2), 1).unwrap();
+        assert_eq!(appended(response), (true, 3));
+        assert_eq!(follower.storage.term_at(2), Some(2));
+        assert_eq!(follower.storage.term_at(3), Some(2));
+
+        // The old leader's late appends are refused.
+        let response = follower.append(1, 2, 3, 1, entries(1, 1), 3).unwrap();
+        assert!(matches!(
+            response,
+            Response::Append {
+                term: 2,
+                success: false,
+                ..
+            }
+        ));
+        drop(follower);
+
+        let (storage, _) = Storage::open(&dir).unwrap();
+        assert_eq!((storage.last_index(), storage.last_term()), (3, 2));
+        assert_eq!(storage.term_at(1), Some(1));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_a_vote_survives_a_restart() {
+        let dir = data_dir("vote", 1);
+        let _ = std::fs::remove_dir_all(&dir);
+        let net = Arc::default();
+        let vote = |node: &mut Core, candidate| match node.vote(5, candidate, 0, 0).unwrap() {
+            Response::Vote { granted, .. } => granted,
+            other => panic!("unexpected {:?}", other),
+        };
+
+        let (mut node, _events) = core(1, 3, &net, &dir);
+        assert!(vote(&mut node, 2));
+        drop(node);
+
+        // Restarted, it still won't vote for anyone else in the same term.
+        let (mut node, _events) = core(1, 3, &net, &dir);
+        assert!(!vote(&mut node, 3));
+        assert!(vote(&mut node, 2));
+        assert_eq!((node.storage.term, node.storage.voted_for), (5, Some(2)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
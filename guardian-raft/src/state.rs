@@ -0,0 +1,171 @@
+//! The replicated state machine: every key's bucket, changed only by
+//! applying committed log entries, in order, at the time each carries.
+
+use guardian_core::{page_of_keys, BucketState, TokenBucketConfig};
+use std::collections::HashMap;
+
+use crate::wire::{Command, Entry, Outcome, Reader, Writer};
+
+struct Bucket {
+    state: BucketState,
+    config: TokenBucketConfig,
+}
+
+#[derive(Default)]
+pub struct Buckets {
+    buckets: HashMap<String, Bucket>,
+}
+
+impl Buckets {
+    pub fn apply(&mut self, entry: &Entry) -> Outcome {
+        let now_ms = entry.now_ms;
+        match &entry.command {
+            Command::Noop => {}
+            Command::Take { key, cost, config } => {
+                let state = self.bucket(key, config, now_ms);
+                let allowed = state.try_consume(*cost, config, now_ms);
+                return Outcome::Taken {
+                    allowed,
+                    remaining: state.tokens,
+                };
+            }
+            Command::Refund {
+                key,
+                tokens,
+                config,
+            } => self
+                .bucket(key, config, now_ms)
+                .refund(*tokens, config, now_ms),
+            Command::Reset { key } => {
+                self.buckets.remove(key);
+            }
+            Command::Prune => self.buckets.retain(|_, bucket| {
+                bucket.state.refill(&bucket.config, now_ms);
+                bucket.state.tokens < bucket.config.capacity
+            }),
+        }
+        Outcome::Done
+    }
+
+    fn bucket(&mut self, key: &str, config: &TokenBucketConfig, now_ms: u64) -> &mut BucketState {
+        let bucket = self
+            .buckets
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket {
+                state: BucketState::full(config, now_ms),
+                config: config.clone(),
+            });
+        // A replaced limit rule applies from the next refill.
+        bucket.config = config.clone();
+        &mut bucket.state
+    }
+
+    /// The tokens left in `key`'s bucket at `now_ms`, if it has one.
+    pub fn tokens(&self, key: &str, now_ms: u64) -> Option<u64> {
+        let bucket = self.buckets.get(key)?;
+        let mut state = bucket.state;
+        state.refill(&bucket.config, now_ms);
+        Some(state.tokens)
+    }
+
+    pub fn keys(&self, pattern: &str, after: Option<&str>, limit: usize) -> Vec<String> {
+        page_of_keys(
+            self.buckets.keys().map(String::as_str),
+            pattern,
+            after,
+            limit,
+        )
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.u64(self.buckets.len() as u64);
+        for (key, bucket) in &self.buckets {
+            w.str(key)
+                .u64(bucket.state.tokens)
+                .u64(bucket.state.last_refill_ms)
+                .config(&bucket.config);
+        }
+        w.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut r = Reader(bytes);
+        let mut buckets = HashMap::new();
+        for _ in 0..r.u64()? {
+            let key = r.string()?;
+            let state = BucketState {
+                tokens: r.u64()?,
+                last_refill_ms: r.u64()?,
+            };
+            buckets.insert(
+                key,
+                Bucket {
+                    state,
+                    config: r.config()?,
+                },
+            );
+        }
+        r.is_empty().then_some(Self { buckets })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn entry(now_ms: u64, command: Command) -> Entry {
+        Entry {
+            term: 1,
+            now_ms,
+            command,
+        }
+    }
+
+    fn take(key: &str, cost: u64) -> Command {
+        Command::Take {
+            key: key.to_string(),
+            cost,
+            config: TokenBucketConfig {
+                capacity: 10,
+                refill_rate: 1,
+                refill_interval: Duration::from_secs(1),
+            },
+        }
+    }
+
+    #[test]
+    fn test_applies_at_the_entries_time_and_survives_a_snapshot() {
+        let mut buckets = Buckets::default();
+        let taken = buckets.apply(&entry(0, take("user1", 8)));
+        assert_eq!(
+            taken,
+            Outcome::Taken {
+                allowed: true,
+                remaining: 2
+            }
+        );
+        assert_eq!(
+            buckets.apply(&entry(1_000, take("user1", 5))),
+            Outcome::Taken {
+                allowed: false,
+                remaining: 3
+            }
+        );
+        buckets.apply(&entry(1_000, take("user2", 1)));
+
+        let mut restored = Buckets::decode(&buckets.encode()).unwrap();
+        assert_eq!(restored.tokens("user1", 1_000), Some(3));
+        assert_eq!(restored.keys("user*", Some("user1"), 10), ["user2"]);
+
+        // By 5s `user2` has refilled and goes.
+        restored.apply(&entry(5_000, Command::Prune));
+        assert_eq!(restored.keys("*", None, 10), ["user1"]);
+        let reset = Command::Reset {
+            key: "user1".to_string(),
+        };
+        restored.apply(&entry(5_000, reset));
+        assert_eq!(restored.tokens("user1", 5_000), None);
+    }
+}
@@ -0,0 +1,341 @@
+//! What a node keeps on disk, in its data directory:
+//!
+//! - `state`: the current term and the vote cast in it;
+//! - `snapshot`: the buckets as of some applied index, and that entry's term;
+//! - `log`: the index of its first entry, then the entries after the
+//!   snapshot, each prefixed with its length.
+//!
+//! `state` and `snapshot` are replaced whole by writing a temporary file and
+//! renaming it over the old one. The log is appended to and cut short in
+//! place, and rewritten the same way when a snapshot makes its head
+//! redundant.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::wire::{Entry, Reader, Writer};
+
+pub struct Storage {
+    dir: PathBuf,
+    pub term: u64,
+    pub voted_for: Option<u64>,
+    /// The last index and term the snapshot covers.
+    pub snapshot_index: u64,
+    pub snapshot_term: u64,
+    /// Entries after the snapshot.
+    entries: Vec<Entry>,
+    /// Where each entry starts in the log file.
+    offsets: Vec<u64>,
+    log: BufWriter<File>,
+    log_len: u64,
+}
+
+impl Storage {
+    /// Open the storage in `dir`, creating it if need be, with the snapshot's
+    /// buckets if there is one.
+    pub fn open(dir: &Path) -> io::Result<(Self, Option<Vec<u8>>)> {
+        fs::create_dir_all(dir)?;
+        let (term, voted_for) = match fs::read(dir.join("state")) {
+            Ok(bytes) => {
+                let mut r = Reader(&bytes);
+                let (term, vote) = r.u64().zip(r.u64()).ok_or_else(|| corrupt("state"))?;
+                (term, (vote != 0).then_some(vote))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (0, None),
+            Err(e) => return Err(e),
+        };
+        let (snapshot_index, snapshot_term, snapshot) = match fs::read(dir.join("snapshot")) {
+            Ok(bytes) => {
+                let mut r = Reader(&bytes);
+                let (index, term) = r.u64().zip(r.u64()).ok_or_else(|| corrupt("snapshot"))?;
+                let data = r.bytes().ok_or_else(|| corrupt("snapshot"))?;
+                (index, term, Some(data.to_vec()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (0, 0, None),
+            Err(e) => return Err(e),
+        };
+
+        let path = dir.join("log");
+        let mut bytes = Vec::new();
+        match File::open(&path) {
+            Ok(mut file) => {
+                file.read_to_end(&mut bytes)?;
+            }
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            Err(_) => {}
+        }
+        let mut entries = Vec::new();
+        let mut offsets = Vec::new();
+        let mut log_len = 0;
+        if bytes.len() >= 8 {
+            let mut r = Reader(&bytes);
+            let mut index = r.u64().ok_or_else(|| corrupt("log"))?;
+            if index > snapshot_index + 1 {
+                return Err(corrupt("log"));
+            }
+            log_len = 8;
+            // A record cut short by a crash ends the log.
+            while let Some(record) = r.bytes() {
+                let entry = Reader(record).entry().ok_or_else(|| corrupt("log"))?;
+                if index > snapshot_index {
+                    entries.push(entry);
+                    offsets.push(log_len);
+                }
+                log_len += 4 + record.len() as u64;
+                index += 1;
+            }
+            if index <= snapshot_index {
+                // The log ends inside the snapshot.
+                entries.clear();
+                offsets.clear();
+                log_len = 0;
+            }
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        file.set_len(log_len)?;
+        let mut storage = Self {
+            dir: dir.to_path_buf(),
+            term,
+            voted_for,
+            snapshot_index,
+            snapshot_term,
+            entries,
+            offsets,
+            log: BufWriter::new(file),
+            log_len,
+        };
+        if log_len == 0 {
+            storage.rewrite_log()?;
+        } else {
+            storage.log.seek(SeekFrom::End(0))?;
+        }
+        Ok((storage, snapshot))
+    }
+
+    pub fn save_vote(&mut self, term: u64, voted_for: Option<u64>) -> io::Result<()> {
+        let mut w = Writer::default();
+        w.u64(term).u64(voted_for.unwrap_or(0));
+        replace(&self.dir.join("state"), &w.0)?;
+        self.term = term;
+        self.voted_for = voted_for;
+        Ok(())
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.snapshot_index + self.entries.len() as u64
+    }
+
+    pub fn last_term(&self) -> u64 {
+        self.entries.last().map_or(self.snapshot_term, |e| e.term)
+    }
+
+    /// The term of the entry at `index`, if the log or snapshot has it.
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.snapshot_index {
+            return Some(self.snapshot_term);
+        }
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    pub fn entry(&self, index: u64) -> Option<&Entry> {
+        let position = index.checked_sub(self.snapshot_index + 1)?;
+        self.entries.get(position as usize)
+    }
+
+    /// Up to `max` entries from `index` on.
+    pub fn entries_from(&self, index: u64, max: usize) -> Vec<Entry> {
+        let start =
+            (index.saturating_sub(self.snapshot_index + 1) as usize).min(self.entries.len());
+        let end = (start + max).min(self.entries.len());
+        self.entries[start..end].to_vec()
+    }
+
+    /// Append `entry`, to be made durable by [`sync`](Self::sync).
+    pub fn append(&mut self, entry: Entry) -> io::Result<()> {
+        let mut w = Writer::default();
+        w.entry(&entry);
+        let mut record = Writer::default();
+        record.bytes(&w.0);
+        self.log.write_all(&record.0)?;
+        self.offsets.push(self.log_len);
+        self.log_len += record.0.len() as u64;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Drop the entries from `index` on.
+    pub fn truncate(&mut self, index: u64) -> io::Result<()> {
+        let Some(position) = index.checked_sub(self.snapshot_index + 1) else {
+            return Ok(());
+        };
+        let position = position as usize;
+        if position >= self.entries.len() {
+            return Ok(());
+        }
+        self.log.flush()?;
+        self.log_len = self.offsets[position];
+        self.log.get_ref().set_len(self.log_len)?;
+        self.log.seek(SeekFrom::Start(self.log_len))?;
+        self.entries.truncate(position);
+        self.offsets.truncate(position);
+        Ok(())
+    }
+
+    /// Write appended entries through to the disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.log.flush()?;
+        self.log.get_ref().sync_data()
+    }
+
+    /// Save `data` as the buckets as of `index`, dropping the entries it
+    /// covers. Entries after `index` are kept if the log's entry at `index`
+    /// has `term`, and otherwise dropped too, as when a leader sends a
+    /// snapshot the log disagrees with.
+    pub fn save_snapshot(&mut self, index: u64, term: u64, data: &[u8]) -> io::Result<()> {
+        let mut w = Writer::default();
+        w.u64(index).u64(term).bytes(data);
+        replace(&self.dir.join("snapshot"), &w.0)?;
+        if self.term_at(index) == Some(term) && index <= self.last_index() {
+            let drop = (index - self.snapshot_index) as usize;
+            self.entries.drain(..drop);
+        } else {
+            self.entries.clear();
+        }
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+        self.rewrite_log()
+    }
+
+    pub fn snapshot(&self) -> io::Result<Vec<u8>> {
+        let bytes = fs::read(self.dir.join("snapshot"))?;
+        let mut r = Reader(&bytes);
+        r.u64().zip(r.u64()).ok_or_else(|| corrupt("snapshot"))?;
+        Ok(r.bytes().ok_or_else(|| corrupt("snapshot"))?.to_vec())
+    }
+
+    fn rewrite_log(&mut self) -> io::Result<()> {
+        let mut w = Writer::default();
+        w.u64(self.snapshot_index + 1);
+        self.offsets.clear();
+        for entry in &self.entries {
+            self.offsets.push(w.0.len() as u64);
+            let mut record = Writer::default();
+            record.entry(entry);
+            w.bytes(&record.0);
+        }
+        let path = self.dir.join("log");
+        replace(&path, &w.0)?;
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        file.seek(SeekFrom::End(0))?;
+        self.log = BufWriter::new(file);
+        self.log_len = w.0.len() as u64;
+        Ok(())
+    }
+}
+
+/// Replace the file at `path` with `bytes`, durably.
+fn replace(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    if let Some(dir) = path.parent() {
+        // Make the rename itself durable; not possible on every platform.
+        let _ = File::open(dir).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+fn corrupt(file: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("raft {} file is corrupt", file),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::Command;
+
+    fn entry(term: u64) -> Entry {
+        Entry {
+            term,
+            now_ms: 0,
+            command: Command::Noop,
+        }
+    }
+
+    fn dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("guardian-raft-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_reopens_what_was_synced() {
+        let dir = dir("reopen");
+        let (mut storage, snapshot) = Storage::open(&dir).unwrap();
+        assert!(snapshot.is_none());
+        storage.save_vote(3, Some(2)).unwrap();
+        for term in [1, 1, 2, 3] {
+            storage.append(entry(term)).unwrap();
+        }
+        storage.truncate(4).unwrap();
+        storage.append(entry(3)).unwrap();
+        storage.sync().unwrap();
+        drop(storage);
+
+        let (mut storage, _) = Storage::open(&dir).unwrap();
+        assert_eq!((storage.term, storage.voted_for), (3, Some(2)));
+        assert_eq!((storage.last_index(), storage.last_term()), (4, 3));
+        assert_eq!(storage.term_at(3), Some(2));
+
+        storage.save_snapshot(2, 1, b"buckets").unwrap();
+        storage.append(entry(4)).unwrap();
+        storage.sync().unwrap();
+        drop(storage);
+
+        let (storage, snapshot) = Storage::open(&dir).unwrap();
+        assert_eq!(snapshot.as_deref(), Some(&b"buckets"[..]));
+        assert_eq!(storage.term_at(2), Some(1));
+        assert_eq!(storage.entry(2).map(|e| e.term), None);
+        assert_eq!((storage.last_index(), storage.last_term()), (5, 4));
+        assert_eq!(storage.entries_from(3, 10).len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_a_torn_record_ends_the_log() {
+        let dir = dir("torn");
+        let (mut storage, _) = Storage::open(&dir).unwrap();
+        storage.append(entry(1)).unwrap();
+        storage.append(entry(1)).unwrap();
+        storage.sync().unwrap();
+        drop(storage);
+        let log = dir.join("log");
+        let len = fs::metadata(&log).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&log)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let (mut storage, _) = Storage::open(&dir).unwrap();
+        assert_eq!(storage.last_index(), 1);
+        storage.append(entry(2)).unwrap();
+        storage.sync().unwrap();
+        drop(storage);
+        let (storage, _) = Storage::open(&dir).unwrap();
+        assert_eq!((storage.last_index(), storage.last_term()), (2, 2));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
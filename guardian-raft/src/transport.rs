@@ -0,0 +1,126 @@
+//! Requests and responses between nodes, one at a time on each TCP
+//! connection. Callers keep a few connections to each peer open, so
+//! heartbeats aren't held up behind proposals waiting to commit.
+
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::node::Event;
+use crate::wire::{Request, Response, MAX_FRAME};
+
+/// Idle connections kept to each peer.
+const IDLE_CONNECTIONS: usize = 8;
+
+/// How a node reaches another.
+#[async_trait]
+pub trait Link: Send + Sync {
+    /// Send `request` and wait up to `timeout` for the response.
+    async fn call(&self, request: &Request, timeout: Duration) -> io::Result<Response>;
+}
+
+/// Another node, reached over TCP.
+pub struct Peer {
+    addr: String,
+    idle: Mutex<Vec<TcpStream>>,
+}
+
+impl Peer {
+    pub fn new(addr: String) -> Self {
+        Self {
+            addr,
+            idle: Mutex::default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Link for Peer {
+    async fn call(&self, request: &Request, timeout: Duration) -> io::Result<Response> {
+        let bytes = request.encode();
+        let exchange = async {
+            let idle = self.idle.lock().pop();
+            let mut stream = match idle {
+                Some(stream) => stream,
+                None => {
+                    let stream = TcpStream::connect(&self.addr).await?;
+                    stream.set_nodelay(true)?;
+                    stream
+                }
+            };
+            write_frame(&mut stream, &bytes).await?;
+            let response = read_frame(&mut stream).await?;
+            let mut idle = self.idle.lock();
+            if idle.len() < IDLE_CONNECTIONS {
+                idle.push(stream);
+            }
+            Response::decode(&response).ok_or_else(|| invalid("undecodable response"))
+        };
+        tokio::time::timeout(timeout, exchange)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "raft peer timed out"))?
+    }
+}
+
+/// Answer every connection's requests by handing them to the node.
+pub async fn serve(listener: TcpListener, events: mpsc::UnboundedSender<Event>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!(error = %e, "raft accept failed");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+        tokio::spawn(connection(stream, events.clone()));
+    }
+}
+
+async fn connection(mut stream: TcpStream, events: mpsc::UnboundedSender<Event>) {
+    loop {
+        let Ok(bytes) = read_frame(&mut stream).await else {
+            return;
+        };
+        let Some(request) = Request::decode(&bytes) else {
+            tracing::debug!("undecodable raft request");
+            return;
+        };
+        let (reply, response) = oneshot::channel();
+        if events.send(Event::Request(request, reply)).is_err() {
+            return;
+        }
+        let Ok(response) = response.await else {
+            return;
+        };
+        if write_frame(&mut stream, &response.encode()).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn write_frame(stream: &mut TcpStream, bytes: &[u8]) -> io::Result<()> {
+    stream
+        .write_all(&(bytes.len() as u32).to_be_bytes())
+        .await?;
+    stream.write_all(bytes).await
+}
+
+async fn read_frame(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let len = stream.read_u32().await? as usize;
+    if len > MAX_FRAME {
+        return Err(invalid("raft frame too large"));
+    }
+    let mut bytes = vec![0; len];
+    stream.read_exact(&mut bytes).await?;
+    Ok(bytes)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,545 @@
+//! The binary encoding of log entries, snapshots and the messages nodes
+//! exchange. Integers are big-endian, strings and byte strings are prefixed
+//! with their length as a u32, and messages travel over TCP as frames, each
+//! a u32 length followed by that many bytes.
+
+use guardian_core::TokenBucketConfig;
+use std::time::Duration;
+
+/// Largest frame accepted, bounding what a snapshot can hold.
+pub const MAX_FRAME: usize = 256 * 1024 * 1024;
+
+/// A change to the buckets, replicated through the log.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Appended by each new leader, to commit what earlier terms left.
+    Noop,
+    Take {
+        key: String,
+        cost: u64,
+        config: TokenBucketConfig,
+    },
+    Refund {
+        key: String,
+        tokens: u64,
+        config: TokenBucketConfig,
+    },
+    Reset {
+        key: String,
+    },
+    /// Drop buckets that have refilled, which a full bucket stands for.
+    Prune,
+}
+
+/// One log entry. Its index is its place in the log.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub term: u64,
+    /// When the leader appended it, which every node applies it at, so
+    /// they all refill buckets alike.
+    pub now_ms: u64,
+    pub command: Command,
+}
+
+/// What applying a command did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    Taken { allowed: bool, remaining: u64 },
+}
+
+/// A query the leader answers from its buckets.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Inspect {
+        key: String,
+    },
+    ListKeys {
+        pattern: String,
+        after: Option<String>,
+        limit: u64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Answer {
+    /// The tokens left in the bucket, if there is one.
+    Bucket(Option<u64>),
+    Keys(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Request {
+    Vote {
+        term: u64,
+        candidate: u64,
+        last_index: u64,
+        last_term: u64,
+    },
+    Append {
+        term: u64,
+        leader: u64,
+        prev_index: u64,
+        prev_term: u64,
+        entries: Vec<Entry>,
+        commit: u64,
+    },
+    Snapshot {
+        term: u64,
+        leader: u64,
+        last_index: u64,
+        last_term: u64,
+        data: Vec<u8>,
+    },
+    /// A command sent on to the leader by a follower.
+    Propose(Command),
+    Query(Query),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    Append {
+        term: u64,
+        success: bool,
+        /// The follower's last index on success; otherwise where its log
+        /// may last agree with the leader's.
+        last_index: u64,
+    },
+    Snapshot {
+        term: u64,
+    },
+    Proposed(Result<Outcome, String>),
+    Answered(Result<Answer, String>),
+}
+
+#[derive(Default)]
+pub struct Writer(pub Vec<u8>);
+
+impl Writer {
+    pub fn u8(&mut self, n: u8) -> &mut Self {
+        self.0.push(n);
+        self
+    }
+
+    pub fn u64(&mut self, n: u64) -> &mut Self {
+        self.0.extend_from_slice(&n.to_be_bytes());
+        self
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        self.0
+            .extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        self.0.extend_from_slice(bytes);
+        self
+    }
+
+    pub fn str(&mut self, s: &str) -> &mut Self {
+        self.bytes(s.as_bytes())
+    }
+
+    pub fn config(&mut self, config: &TokenBucketConfig) -> &mut Self {
+        self.u64(config.capacity)
+            .u64(config.refill_rate)
+            .u64(config.refill_interval.as_millis() as u64)
+    }
+
+    pub fn entry(&mut self, entry: &Entry) -> &mut Self {
+        self.u64(entry.term).u64(entry.now_ms);
+        match &entry.command {
+            Command::Noop => self.u8(0),
+            Command::Take { key, cost, config } => self.u8(1).str(key).u64(*cost).config(config),
+            Command::Refund {
+                key,
+                tokens,
+                config,
+            } => self.u8(2).str(key).u64(*tokens).config(config),
+            Command::Reset { key } => self.u8(3).str(key),
+            Command::Prune => self.u8(4),
+        }
+    }
+
+    fn result<T>(&mut self, result: &Result<T, String>, ok: impl FnOnce(&mut Self, &T)) {
+        match result {
+            Ok(value) => ok(self.u8(0), value),
+            Err(e) => {
+                self.u8(1).str(e);
+            }
+        }
+    }
+}
+
+pub struct Reader<'a>(pub &'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    pub fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = u32::from_be_bytes(self.take(4)?.try_into().ok()?);
+        self.take(len as usize)
+    }
+
+    pub fn string(&mut self) -> Option<String> {
+        String::from_utf8(self.bytes()?.to_vec()).ok()
+    }
+
+    pub fn config(&mut self) -> Option<TokenBucketConfig> {
+        Some(TokenBucketConfig {
+            capacity: self.u64()?,
+            refill_rate: self.u64()?,
+            refill_interval: Duration::from_millis(self.u64()?),
+        })
+    }
+
+    pub fn entry(&mut self) -> Option<Entry> {
+        let term = self.u64()?;
+        let now_ms = self.u64()?;
+        let command = match self.u8()? {
+            0 => Command::Noop,
+            1 => Command::Take {
+                key: self.string()?,
+                cost: self.u64()?,
+                config: self.config()?,
+            },
+            2 => Command::Refund {
+                key: self.string()?,
+                tokens: self.u64()?,
+                config: self.config()?,
+            },
+            3 => Command::Reset {
+                key: self.string()?,
+            },
+            4 => Command::Prune,
+            _ => return None,
+        };
+        Some(Entry {
+            term,
+            now_ms,
+            command,
+        })
+    }
+
+    fn result<T>(&mut self, ok: impl FnOnce(&mut Self) -> Option<T>) -> Option<Result<T, String>> {
+        match self.u8()? {
+            0 => Some(Ok(ok(self)?)),
+            _ => Some(Err(self.string()?)),
+        }
+    }
+}
+
+impl Request {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        match self {
+            Request::Vote {
+                term,
+                candidate,
+                last_index,
+                last_term,
+            } => {
+                w.u8(0)
+                    .u64(*term)
+                    .u64(*candidate)
+                    .u64(*last_index)
+                    .u64(*last_term);
+            }
+            Request::Append {
+                term,
+                leader,
+                prev_index,
+                prev_term,
+                entries,
+                commit,
+            } => {
+                w.u8(1)
+                    .u64(*term)
+                    .u64(*leader)
+                    .u64(*prev_index)
+                    .u64(*prev_term);
+                w.u64(*commit).u64(entries.len() as u64);
+                for entry in entries {
+                    w.entry(entry);
+                }
+            }
+            Request::Snapshot {
+                term,
+                leader,
+                last_index,
+                last_term,
+                data,
+            } => {
+                w.u8(2)
+                    .u64(*term)
+                    .u64(*leader)
+                    .u64(*last_index)
+                    .u64(*last_term);
+                w.bytes(data);
+            }
+            Request::Propose(command) => {
+                // Carried as an entry, with no term or time of its own yet.
+                w.u8(3).entry(&Entry {
+                    term: 0,
+                    now_ms: 0,
+                    command: command.clone(),
+                });
+            }
+            Request::Query(Query::Inspect { key }) => {
+                w.u8(4).str(key);
+            }
+            Request::Query(Query::ListKeys {
+                pattern,
+                after,
+                limit,
+            }) => {
+                w.u8(5).str(pattern).u64(*limit);
+                if let Some(after) = after {
+                    w.str(after);
+                }
+            }
+        }
+        w.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut r = Reader(bytes);
+        let request = match r.u8()? {
+            0 => Request::Vote {
+                term: r.u64()?,
+                candidate: r.u64()?,
+                last_index: r.u64()?,
+                last_term: r.u64()?,
+            },
+            1 => {
+                let (term, leader, prev_index, prev_term) =
+                    (r.u64()?, r.u64()?, r.u64()?, r.u64()?);
+                let commit = r.u64()?;
+                let count = r.u64()?;
+                let entries = (0..count).map(|_| r.entry()).collect::<Option<_>>()?;
+                Request::Append {
+                    term,
+                    leader,
+                    prev_index,
+                    prev_term,
+                    entries,
+                    commit,
+                }
+            }
+            2 => Request::Snapshot {
+                term: r.u64()?,
+                leader: r.u64()?,
+                last_index: r.u64()?,
+                last_term: r.u64()?,
+                data: r.bytes()?.to_vec(),
+            },
+            3 => Request::Propose(r.entry()?.command),
+            4 => Request::Query(Query::Inspect { key: r.string()? }),
+            5 => {
+                let pattern = r.string()?;
+                let limit = r.u64()?;
+                let after = if r.is_empty() {
+                    None
+                } else {
+                    Some(r.string()?)
+                };
+                Request::Query(Query::ListKeys {
+                    pattern,
+                    after,
+                    limit,
+                })
+            }
+            _ => return None,
+        };
+        r.is_empty().then_some(request)
+    }
+}
+
+impl Response {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::default();
+        match self {
+            Response::Vote { term, granted } => {
+                w.u8(0).u64(*term).u8(*granted as u8);
+            }
+            Response::Append {
+                term,
+                success,
+                last_index,
+            } => {
+                w.u8(1).u64(*term).u8(*success as u8).u64(*last_index);
+            }
+            Response::Snapshot { term } => {
+                w.u8(2).u64(*term);
+            }
+            Response::Proposed(result) => {
+                w.u8(3).result(result, |w, outcome| match outcome {
+                    Outcome::Done => {
+                        w.u8(0);
+                    }
+                    Outcome::Taken { allowed, remaining } => {
+                        w.u8(1).u8(*allowed as u8).u64(*remaining);
+                    }
+                });
+            }
+            Response::Answered(result) => {
+                w.u8(4).result(result, |w, answer| match answer {
+                    Answer::Bucket(None) => {
+                        w.u8(0);
+                    }
+                    Answer::Bucket(Some(tokens)) => {
+                        w.u8(1).u64(*tokens);
+                    }
+                    Answer::Keys(keys) => {
+                        w.u8(2).u64(keys.len() as u64);
+                        for key in keys {
+                            w.str(key);
+                        }
+                    }
+                });
+            }
+        }
+        w.0
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let mut r = Reader(bytes);
+        let response = match r.u8()? {
+            0 => Response::Vote {
+                term: r.u64()?,
+                granted: r.u8()? != 0,
+            },
+            1 => Response::Append {
+                term: r.u64()?,
+                success: r.u8()? != 0,
+                last_index: r.u64()?,
+            },
+            2 => Response::Snapshot { term: r.u64()? },
+            3 => Response::Proposed(r.result(|r| match r.u8()? {
+                0 => Some(Outcome::Done),
+                _ => Some(Outcome::Taken {
+                    allowed: r.u8()? != 0,
+                    remaining: r.u64()?,
+                }),
+            })?),
+            4 => Response::Answered(r.result(|r| match r.u8()? {
+                0 => Some(Answer::Bucket(None)),
+                1 => Some(Answer::Bucket(Some(r.u64()?))),
+                _ => {
+                    let count = r.u64()?;
+                    let keys = (0..count).map(|_| r.string()).collect::<Option<_>>()?;
+                    Some(Answer::Keys(keys))
+                }
+            })?),
+            _ => return None,
+        };
+        r.is_empty().then_some(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TokenBucketConfig {
+        TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 2,
+            refill_interval: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn test_requests_round_trip() {
+        let entries = vec![
+            Entry {
+                term: 2,
+                now_ms: 1_000,
+                command: Command::Take {
+                    key: "user1".to_string(),
+                    cost: 3,
+                    config: config(),
+                },
+            },
+            Entry {
+                term: 2,
+                now_ms: 1_001,
+                command: Command::Reset {
+                    key: "user2".to_string(),
+                },
+            },
+        ];
+        let requests = [
+            Request::Append {
+                term: 2,
+                leader: 1,
+                prev_index: 7,
+                prev_term: 1,
+                entries,
+                commit: 6,
+            },
+            Request::Propose(Command::Refund {
+                key: "user1".to_string(),
+                tokens: 1,
+                config: config(),
+            }),
+            Request::Query(Query::ListKeys {
+                pattern: "user*".to_string(),
+                after: Some("user1".to_string()),
+                limit: 10,
+            }),
+            Request::Query(Query::ListKeys {
+                pattern: "*".to_string(),
+                after: None,
+                limit: 5,
+            }),
+        ];
+        for request in requests {
+            let encoded = request.encode();
+            assert_eq!(Request::decode(&encoded).unwrap().encode(), encoded);
+            assert!(Request::decode(&encoded[..encoded.len() - 1]).is_none());
+        }
+    }
+
+    #[test]
+    fn test_responses_round_trip() {
+        let responses = [
+            Response::Vote {
+                term: 3,
+                granted: true,
+            },
+            Response::Proposed(Ok(Outcome::Taken {
+                allowed: false,
+                remaining: 2,
+            })),
+            Response::Proposed(Err("not the leader".to_string())),
+            Response::Answered(Ok(Answer::Keys(vec!["a".to_string(), "b".to_string()]))),
+            Response::Answered(Ok(Answer::Bucket(None))),
+        ];
+        for response in responses {
+            let encoded = response.encode();
+            assert_eq!(Response::decode(&encoded), Some(response));
+            assert_eq!(Response::decode(&encoded[..encoded.len() - 1]), None);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+// Startup probing of Redis-compatible servers (Redis, Valkey, Dragonfly,
+// KeyDB): which one it is, and whether it allows Lua scripting, without
+// which the backend falls back to optimistic transactions.
+
+use guardian_core::RateLimitError;
+use redis::{aio::ConnectionLike, ErrorKind, Script, Value};
+use std::collections::HashMap;
+
+/// Which Redis-compatible server implementation we are talking to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServerFlavor {
+    Redis,
+    Valkey,
+    Dragonfly,
+    KeyDb,
+    Unknown,
+}
+
+/// Features detected on the server at startup. Fields are public so callers
+/// can pin a feature off (or skip detection) via
+/// `RedisBackend::with_capabilities`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    pub flavor: ServerFlavor,
+    pub version: Option<String>,
+    /// EVAL/EVALSHA are permitted.
+    pub scripting: bool,
+    /// SCAN takes a TYPE filter (Redis 6.0 and later). Without it, listing
+    /// keys checks each key's type separately.
+    pub scan_type: bool,
+}
+
+impl ServerCapabilities {
+    /// Conservative capabilities for a plain Redis-protocol server: scripting
+    /// allowed, flavor unknown, no version-dependent commands.
+    pub fn baseline() -> Self {
+        Self {
+            flavor: ServerFlavor::Unknown,
+            version: None,
+            scripting: true,
+            scan_type: false,
+        }
+    }
+
+    /// Probe the server with HELLO, INFO and a trivial script.
+    ///
+    /// Commands the server rejects just switch the matching feature off;
+    /// only transport failures are returned as errors.
+    pub async fn detect<C: ConnectionLike>(conn: &mut C) -> Result<Self, RateLimitError> {
+        // HELLO 2 reports server/version without switching the connection
+        // away from RESP2.
+        let hello: Option<HashMap<String, Value>> =
+            optional(redis::cmd("HELLO").arg(2).query_async(conn).await)?;
+        let info: Option<String> =
+            optional(redis::cmd("INFO").arg("server").query_async(conn).await)?;
+
+        let scripting = optional(
+            Script::new("return 1")
+                .invoke_async::<_, i64>(conn)
+                .await,
+        )?
+        .is_some();
+
+        let mut capabilities = Self::from_replies(hello.as_ref(), info.as_deref().unwrap_or(""));
+        capabilities.scripting = scripting;
+        Ok(capabilities)
+    }
+
+    /// Identify the server from HELLO and INFO replies.
+    fn from_replies(hello: Option<&HashMap<String, Value>>, info: &str) -> Self {
+        let fields = parse_info(info);
+        let hello_field = |name: &str| {
+            hello
+                .and_then(|h| h.get(name))
+                .and_then(|v| redis::from_redis_value::<String>(v).ok())
+        };
+
+        let flavor = if fields.contains_key("dragonfly_version") {
+            ServerFlavor::Dragonfly
+        } else if fields.contains_key("valkey_version")
+            || fields.get("server_name").map(String::as_str) == Some("valkey")
+            || hello_field("server").as_deref() == Some("valkey")
+        {
+            ServerFlavor::Valkey
+        } else if info.contains("# KeyDB")
+            || fields.keys().any(|k| k.starts_with("keydb"))
+            || fields
+                .get("executable")
+                .is_some_and(|exe| exe.to_ascii_lowercase().contains("keydb"))
+        {
+            ServerFlavor::KeyDb
+        } else if fields.contains_key("redis_version") || hello.is_some() {
+            ServerFlavor::Redis
+        } else {
+            ServerFlavor::Unknown
+        };
+
+        let version = match flavor {
+            ServerFlavor::Dragonfly => fields.get("dragonfly_version").cloned(),
+            ServerFlavor::Valkey => fields.get("valkey_version").cloned(),
+            _ => None,
+        }
+        .or_else(|| fields.get("redis_version").cloned())
+        .or_else(|| hello_field("version"));
+
+        // Valkey forked from Redis 7.2 and Dragonfly numbers its own
+        // releases; both have always had SCAN ... TYPE.
+        let scan_type = match flavor {
+            ServerFlavor::Valkey | ServerFlavor::Dragonfly => true,
+            _ => version
+                .as_deref()
+                .and_then(major_minor)
+                .is_some_and(|v| v >= (6, 0)),
+        };
+
+        Self {
+            flavor,
+            version,
+            scripting: true,
+            scan_type,
+        }
+    }
+}
+
+/// The leading `major.minor` of a version string such as `6.2.14`.
+fn major_minor(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().and_then(|p| p.parse().ok()).unwrap_or(0);
+    Some((major, minor))
+}
+
+/// Treat server-side rejections as "feature unavailable" while still
+/// surfacing transport errors.
+fn optional<T>(result: redis::RedisResult<T>) -> Result<Option<T>, RateLimitError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == ErrorKind::IoError => Err(RateLimitError::StorageError(format!(
+            "Redis connection error: {}",
+            e
+        ))),
+        Err(_) => Ok(None),
+    }
+}
+
+fn parse_info(info: &str) -> HashMap<String, String> {
+    info.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(server: &str, version: &str) -> HashMap<String, Value> {
+        let mut reply = HashMap::new();
+        reply.insert(
+            "server".to_string(),
+            Value::BulkString(server.as_bytes().to_vec()),
+        );
+        reply.insert(
+            "version".to_string(),
+            Value::BulkString(version.as_bytes().to_vec()),
+        );
+        reply
+    }
+
+    #[test]
+    fn test_detects_redis_7() {
+        let info = "# Server\r\nredis_version:7.2.4\r\nredis_mode:standalone\r\n";
+        let caps = ServerCapabilities::from_replies(Some(&hello("redis", "7.2.4")), info);
+        assert_eq!(caps.flavor, ServerFlavor::Redis);
+        assert_eq!(caps.version.as_deref(), Some("7.2.4"));
+        assert!(caps.scan_type);
+    }
+
+    #[test]
+    fn test_detects_redis_5_without_hello() {
+        let info = "# Server\r\nredis_version:5.0.14\r\n";
+        let caps = ServerCapabilities::from_replies(None, info);
+        assert_eq!(caps.flavor, ServerFlavor::Redis);
+        assert_eq!(caps.version.as_deref(), Some("5.0.14"));
+        assert!(!caps.scan_type);
+    }
+
+    #[test]
+    fn test_detects_valkey() {
+        let info = "# Server\r\nredis_version:7.2.4\r\nserver_name:valkey\r\nvalkey_version:8.0.1\r\n";
+        let caps = ServerCapabilities::from_replies(Some(&hello("valkey", "8.0.1")), info);
+        assert_eq!(caps.flavor, ServerFlavor::Valkey);
+        assert_eq!(caps.version.as_deref(), Some("8.0.1"));
+    }
+
+    #[test]
+    fn test_detects_dragonfly_despite_its_redis_version() {
+        let info = "# Server\r\nredis_version:7.2.0\r\ndragonfly_version:df-v1.21.2\r\n";
+        let caps = ServerCapabilities::from_replies(Some(&hello("redis", "7.2.0")), info);
+        assert_eq!(caps.flavor, ServerFlavor::Dragonfly);
+        assert_eq!(caps.version.as_deref(), Some("df-v1.21.2"));
+        assert!(caps.scan_type);
+    }
+
+    #[test]
+    fn test_detects_keydb() {
+        let info = "# Server\r\nredis_version:6.3.4\r\nexecutable:/usr/bin/keydb-server\r\n";
+        let caps = ServerCapabilities::from_replies(Some(&hello("redis", "6.3.4")), info);
+        assert_eq!(caps.flavor, ServerFlavor::KeyDb);
+        assert_eq!(caps.version.as_deref(), Some("6.3.4"));
+        assert!(caps.scan_type);
+    }
+
+    #[test]
+    fn test_unknown_version_gets_no_scan_type() {
+        let caps = ServerCapabilities::from_replies(None, "");
+        assert_eq!(caps.flavor, ServerFlavor::Unknown);
+        assert!(!caps.scan_type);
+        assert!(!ServerCapabilities::baseline().scan_type);
+    }
+}
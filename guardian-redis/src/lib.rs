@@ -1,20 +1,127 @@
 
 use async_trait::async_trait;
-use guardian_core::{RateLimitError, StorageBackend, TokenBucketConfig};
-use redis::{aio::ConnectionManager, AsyncCommands, Client, Script};
+use guardian_core::{
+    key_hash, page_of_keys, BucketSnapshot, CacheStats, Lease, RateLimitError, StorageBackend, TokenBucketConfig, TokenDecision,
+};
+use redis::{
+    aio::{ConnectionManager, MultiplexedConnection},
+    AsyncCommands, Client, Script,
+};
+use std::future::Future;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
 
+pub mod capabilities;
+pub mod quota;
+
+pub use capabilities::{ServerCapabilities, ServerFlavor};
+pub use quota::{QuotaConfig, QuotaPeriod, RedisQuotaBackend};
+
+/// TTL applied to every bucket hash, matching the Lua scripts.
+const BUCKET_TTL_SECS: i64 = 3600;
+
+/// Keys requested per SCAN round trip when listing buckets.
+const SCAN_BATCH: usize = 1000;
+
+/// How many times an optimistic transaction is retried when the watched key
+/// changes underneath it before giving up.
+const MAX_TRANSACTION_RETRIES: usize = 16;
+
+/// Run one backend operation in a `redis` span naming the operation and
+/// the key's hash, recording how long the round trips took.
+pub(crate) async fn traced<T>(
+    op: &'static str,
+    key: &str,
+    operation: impl Future<Output = Result<T, RateLimitError>>,
+) -> Result<T, RateLimitError> {
+    let span = tracing::debug_span!(
+        "redis",
+        op,
+        key_hash = %key_hash(key),
+        latency_us = tracing::field::Empty,
+    );
+    let started = Instant::now();
+    let result = operation.instrument(span.clone()).await;
+    span.record("latency_us", started.elapsed().as_micros() as u64);
+    if let Err(e) = &result {
+        tracing::debug!(parent: &span, error = %e, "redis operation failed");
+    }
+    result
+}
+
+/// How `RedisBackend` performs atomic read-modify-write on a bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecutionMode {
+    /// Single round trip Lua scripts via EVALSHA/EVAL.
+    Scripting,
+    /// WATCH/MULTI/EXEC optimistic transactions, for servers that restrict EVAL.
+    Transactions,
+}
 
 pub struct RedisBackend {
+    client: Client,
     connection: Arc<ConnectionManager>,
     config: TokenBucketConfig,
+    mode: ExecutionMode,
+    capabilities: ServerCapabilities,
     take_token_script: Script,
+    take_all_script: Script,
     get_usage_script: Script,
+    refund_script: Script,
+    lease_scripts: LeaseScripts,
+    demand_script: Script,
+    lock_scripts: LockScripts,
+    // WATCH state is per connection, so transactions can't share the
+    // multiplexed manager; dedicated connections are pooled here instead.
+    transaction_pool: parking_lot::Mutex<Vec<MultiplexedConnection>>,
 }
 
 impl RedisBackend {
+    /// Connect and probe the server's capabilities, falling back to
+    /// optimistic transactions when EVAL is disabled.
     pub async fn new(redis_url: &str, config: TokenBucketConfig) -> Result<Self, RateLimitError> {
+        let (client, mut connection) = Self::connect(redis_url).await?;
+        let capabilities = ServerCapabilities::detect(&mut connection).await?;
+        let mode = if capabilities.scripting {
+            ExecutionMode::Scripting
+        } else {
+            ExecutionMode::Transactions
+        };
+        Ok(Self::from_parts(client, connection, config, mode, capabilities))
+    }
+
+    /// Connect using an explicit execution mode, skipping detection.
+    pub async fn with_execution_mode(
+        redis_url: &str,
+        config: TokenBucketConfig,
+        mode: ExecutionMode,
+    ) -> Result<Self, RateLimitError> {
+        let (client, connection) = Self::connect(redis_url).await?;
+        let capabilities = ServerCapabilities {
+            scripting: mode == ExecutionMode::Scripting,
+            ..ServerCapabilities::baseline()
+        };
+        Ok(Self::from_parts(client, connection, config, mode, capabilities))
+    }
+
+    /// Connect with caller-supplied capabilities, skipping detection. Use
+    /// this to switch features off on servers that misreport support.
+    pub async fn with_capabilities(
+        redis_url: &str,
+        config: TokenBucketConfig,
+        capabilities: ServerCapabilities,
+    ) -> Result<Self, RateLimitError> {
+        let (client, connection) = Self::connect(redis_url).await?;
+        let mode = if capabilities.scripting {
+            ExecutionMode::Scripting
+        } else {
+            ExecutionMode::Transactions
+        };
+        Ok(Self::from_parts(client, connection, config, mode, capabilities))
+    }
+
+    async fn connect(redis_url: &str) -> Result<(Client, ConnectionManager), RateLimitError> {
         let client = Client::open(redis_url)
             .map_err(|e| RateLimitError::StorageError(format!("Redis client error: {}", e)))?;
 
@@ -23,14 +130,42 @@ impl RedisBackend {
             .await
             .map_err(|e| RateLimitError::StorageError(format!("Redis connection error: {}", e)))?;
 
-        Ok(Self {
+        Ok((client, connection))
+    }
+
+    fn from_parts(
+        client: Client,
+        connection: ConnectionManager,
+        config: TokenBucketConfig,
+        mode: ExecutionMode,
+        capabilities: ServerCapabilities,
+    ) -> Self {
+        Self {
+            client,
             connection: Arc::new(connection),
             config,
+            mode,
+            capabilities,
             take_token_script: Self::create_take_token_script(),
+            take_all_script: Self::create_take_all_script(),
             get_usage_script: Self::create_get_usage_script(),
-        })
+            refund_script: Self::create_refund_script(),
+            lease_scripts: LeaseScripts::new(),
+            demand_script: Self::create_demand_script(),
+            lock_scripts: LockScripts::new(),
+            transaction_pool: parking_lot::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The execution mode selected at startup.
+    pub fn execution_mode(&self) -> ExecutionMode {
+        self.mode
     }
 
+    /// Server features detected (or supplied) at startup.
+    pub fn capabilities(&self) -> &ServerCapabilities {
+        &self.capabilities
+    }
 
     fn create_take_token_script() -> Script {
         Script::new(
@@ -60,17 +195,69 @@ impl RedisBackend {
                 last_refill = now
             end
             
-            -- Check if we can consume
+            -- Check if we can consume; reply is {allowed, tokens left}
             if tokens >= cost then
                 tokens = tokens - cost
                 redis.call('HMSET', key, 'tokens', tokens, 'last_refill', last_refill)
                 redis.call('EXPIRE', key, 3600)  -- TTL: 1 hour
-                return 1  -- Success
+                return {1, tokens}  -- Success
             else
                 redis.call('HMSET', key, 'tokens', tokens, 'last_refill', last_refill)
                 redis.call('EXPIRE', key, 3600)
-                return 0  -- Denied
+                return {0, tokens}  -- Denied
+            end
+            "#,
+        )
+    }
+
+    /// The take_token script over several keys at once, each with its
+    /// cost in ARGV after the limits and time. Redis runs a script without
+    /// interleaving other commands, so nothing sees part of it done.
+    fn create_take_all_script() -> Script {
+        Script::new(
+            r#"
+            local capacity = tonumber(ARGV[1])
+            local refill_rate = tonumber(ARGV[2])
+            local now = tonumber(ARGV[3])
+
+            -- Refill each bucket once, and total what each key is asked for
+            local tokens = {}
+            local asked = {}
+            for i, key in ipairs(KEYS) do
+                if not tokens[key] then
+                    local bucket = redis.call('HMGET', key, 'tokens', 'last_refill')
+                    local left = tonumber(bucket[1]) or capacity
+                    local last_refill = tonumber(bucket[2]) or now
+                    tokens[key] = math.min(capacity, left + math.floor((now - last_refill) * refill_rate))
+                end
+                asked[key] = (asked[key] or 0) + tonumber(ARGV[3 + i])
+            end
+
+            -- Take from every bucket, or from none when any is short
+            local taken = true
+            for key, cost in pairs(asked) do
+                if tokens[key] < cost then
+                    taken = false
+                end
+            end
+            for key, left in pairs(tokens) do
+                if taken then
+                    tokens[key] = left - asked[key]
+                end
+                redis.call('HMSET', key, 'tokens', tokens[key], 'last_refill', now)
+                redis.call('EXPIRE', key, 3600)
+            end
+
+            -- Reply is {had its cost, tokens left} per entry
+            local reply = {}
+            for i, key in ipairs(KEYS) do
+                local had = 0
+                if taken or tokens[key] >= asked[key] then
+                    had = 1
+                end
+                reply[i] = {had, tokens[key]}
             end
+            return reply
             "#,
         )
     }
@@ -97,21 +284,253 @@ impl RedisBackend {
         )
     }
 
+    /// Refill, then add tokens back up to capacity. A missing bucket is
+    /// full already and is left alone.
+    fn create_refund_script() -> Script {
+        Script::new(
+            r#"
+            local key = KEYS[1]
+            local capacity = tonumber(ARGV[1])
+            local refill_rate = tonumber(ARGV[2])
+            local refund = tonumber(ARGV[3])
+            local now = tonumber(ARGV[4])
+            
+            local bucket = redis.call('HMGET', key, 'tokens', 'last_refill')
+            local tokens = tonumber(bucket[1])
+            if not tokens then
+                return 0
+            end
+            local last_refill = tonumber(bucket[2]) or now
+            
+            local elapsed = now - last_refill
+            local tokens_to_add = math.floor(elapsed * refill_rate)
+            tokens = math.min(capacity, tokens + tokens_to_add + refund)
+            redis.call('HMSET', key, 'tokens', tokens, 'last_refill', now)
+            redis.call('EXPIRE', key, 3600)
+            return tokens
+            "#,
+        )
+    }
+
+    /// Nodes' demand is kept beside the bucket too, in `demand:<node>`
+    /// fields holding `<demand>:<expiry in Unix ms>`.
+    fn create_demand_script() -> Script {
+        Script::new(
+            r#"
+            local key = KEYS[1]
+            local now_ms = tonumber(ARGV[1])
+            redis.call('HSET', key, 'demand:' .. ARGV[2], ARGV[3] .. ':' .. (now_ms + tonumber(ARGV[4])))
+            redis.call('EXPIRE', key, 3600)
+
+            -- Reply is {node, demand} for every node whose demand hasn't lapsed
+            local fields = redis.call('HGETALL', key)
+            local demands = {}
+            for i = 1, #fields, 2 do
+                local name = fields[i]
+                if string.sub(name, 1, 7) == 'demand:' then
+                    local demand, expires = string.match(fields[i + 1], '(%d+):(%d+)')
+                    if tonumber(expires) <= now_ms then
+                        redis.call('HDEL', key, name)
+                    else
+                        table.insert(demands, {string.sub(name, 8), tonumber(demand)})
+                    end
+                end
+            end
+            return demands
+            "#,
+        )
+    }
+
     fn get_current_time() -> f64 {
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs_f64()
     }
-}
 
-#[async_trait]
-impl StorageBackend for RedisBackend {
-    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+    async fn checkout_transaction_connection(
+        &self,
+    ) -> Result<MultiplexedConnection, RateLimitError> {
+        if let Some(conn) = self.transaction_pool.lock().pop() {
+            return Ok(conn);
+        }
+        self.client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("Redis connection error: {}", e)))
+    }
+
+    fn checkin_transaction_connection(&self, conn: MultiplexedConnection) {
+        self.transaction_pool.lock().push(conn);
+    }
+
+    /// Leases, demand and locks have no transaction equivalent; they need
+    /// EVAL.
+    fn require_scripting(&self) -> Result<(), RateLimitError> {
+        if self.mode == ExecutionMode::Transactions {
+            return Err(RateLimitError::StorageError(
+                "Redis leases, demand and locks need Lua scripting, not transactions mode"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// WATCH/MULTI/EXEC equivalent of the take_token Lua script. Returns
+    /// the decision and the tokens left afterwards.
+    async fn take_token_transaction(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<(bool, u64), RateLimitError> {
+        let mut conn = self.checkout_transaction_connection().await?;
+        let map_err = |e: redis::RedisError| {
+            RateLimitError::StorageError(format!("Redis transaction error: {}", e))
+        };
+
+        for _ in 0..MAX_TRANSACTION_RETRIES {
+            redis::cmd("WATCH")
+                .arg(key)
+                .query_async::<()>(&mut conn)
+                .await
+                .map_err(map_err)?;
+
+            let (tokens, last_refill): (Option<u64>, Option<f64>) = conn
+                .hget(key, &["tokens", "last_refill"])
+                .await
+                .map_err(map_err)?;
+
+            let now = Self::get_current_time();
+            let available = refill_tokens(
+                tokens,
+                last_refill,
+                self.config.capacity,
+                self.config.refill_rate,
+                now,
+            );
+            let allowed = available >= cost;
+            let remaining = if allowed { available - cost } else { available };
+
+            let committed: Option<()> = redis::pipe()
+                .atomic()
+                .cmd("HSET")
+                .arg(key)
+                .arg("tokens")
+                .arg(remaining)
+                .arg("last_refill")
+                .arg(now)
+                .ignore()
+                .expire(key, BUCKET_TTL_SECS)
+                .ignore()
+                .query_async(&mut conn)
+                .await
+                .map_err(map_err)?;
+
+            if committed.is_some() {
+                self.checkin_transaction_connection(conn);
+                return Ok((allowed, remaining));
+            }
+            // EXEC returned nil: another writer touched the key, so retry.
+        }
+
+        self.checkin_transaction_connection(conn);
+        Err(RateLimitError::StorageError(format!(
+            "Redis transaction aborted {} times for key: {}",
+            MAX_TRANSACTION_RETRIES, key
+        )))
+    }
+
+    /// WATCH/MULTI/EXEC equivalent of the refund Lua script.
+    async fn refund_transaction(&self, key: &str, refund: u64) -> Result<(), RateLimitError> {
+        let mut conn = self.checkout_transaction_connection().await?;
+        let map_err = |e: redis::RedisError| {
+            RateLimitError::StorageError(format!("Redis transaction error: {}", e))
+        };
+
+        for _ in 0..MAX_TRANSACTION_RETRIES {
+            redis::cmd("WATCH")
+                .arg(key)
+                .query_async::<()>(&mut conn)
+                .await
+                .map_err(map_err)?;
+
+            let (tokens, last_refill): (Option<u64>, Option<f64>) = conn
+                .hget(key, &["tokens", "last_refill"])
+                .await
+                .map_err(map_err)?;
+            if tokens.is_none() {
+                redis::cmd("UNWATCH")
+                    .query_async::<()>(&mut conn)
+                    .await
+                    .map_err(map_err)?;
+                self.checkin_transaction_connection(conn);
+                return Ok(());
+            }
+
+            let now = Self::get_current_time();
+            let available = refill_tokens(
+                tokens,
+                last_refill,
+                self.config.capacity,
+                self.config.refill_rate,
+                now,
+            );
+            let refunded = available.saturating_add(refund).min(self.config.capacity);
+
+            let committed: Option<()> = redis::pipe()
+                .atomic()
+                .cmd("HSET")
+                .arg(key)
+                .arg("tokens")
+                .arg(refunded)
+                .arg("last_refill")
+                .arg(now)
+                .ignore()
+                .expire(key, BUCKET_TTL_SECS)
+                .ignore()
+                .query_async(&mut conn)
+                .await
+                .map_err(map_err)?;
+
+            if committed.is_some() {
+                self.checkin_transaction_connection(conn);
+                return Ok(());
+            }
+        }
+
+        self.checkin_transaction_connection(conn);
+        Err(RateLimitError::StorageError(format!(
+            "Redis transaction aborted {} times for key: {}",
+            MAX_TRANSACTION_RETRIES, key
+        )))
+    }
+
+    async fn get_usage_direct(&self, key: &str) -> Result<u64, RateLimitError> {
+        let mut conn = self.connection.as_ref().clone();
+        let (tokens, last_refill): (Option<u64>, Option<f64>) = conn
+            .hget(key, &["tokens", "last_refill"])
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("Redis get error: {}", e)))?;
+
+        let available = refill_tokens(
+            tokens,
+            last_refill,
+            self.config.capacity,
+            self.config.refill_rate,
+            Self::get_current_time(),
+        );
+        Ok(self.config.capacity - available)
+    }
+
+    async fn take_token_scripted(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<(bool, u64), RateLimitError> {
         let mut conn = self.connection.as_ref().clone();
         let now = Self::get_current_time();
 
-        let result: i32 = self
+        let (allowed, remaining): (i32, u64) = self
             .take_token_script
             .key(key)
             .arg(self.config.capacity)
@@ -124,35 +543,619 @@ impl StorageBackend for RedisBackend {
                 RateLimitError::StorageError(format!("Redis script execution error: {}", e))
             })?;
 
-        Ok(result == 1)
+        Ok((allowed == 1, remaining))
     }
 
-    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+    async fn take_all_scripted(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<(bool, u64)>, RateLimitError> {
         let mut conn = self.connection.as_ref().clone();
-        let now = Self::get_current_time();
-
-        let usage: u64 = self
-            .get_usage_script
-            .key(key)
+        let mut invocation = self.take_all_script.prepare_invoke();
+        invocation
             .arg(self.config.capacity)
             .arg(self.config.refill_rate)
-            .arg(now)
-            .invoke_async(&mut conn)
-            .await
-            .map_err(|e| {
-                RateLimitError::StorageError(format!("Redis script execution error: {}", e))
-            })?;
+            .arg(Self::get_current_time());
+        for &(key, cost) in entries {
+            invocation.key(key).arg(cost);
+        }
+
+        let decisions: Vec<(i32, u64)> = invocation.invoke_async(&mut conn).await.map_err(|e| {
+            RateLimitError::StorageError(format!("Redis script execution error: {}", e))
+        })?;
+        Ok(decisions
+            .into_iter()
+            .map(|(had, remaining)| (had == 1, remaining))
+            .collect())
+    }
+}
+
+/// Leases are recorded in their bucket's hash, as a `lease:<id>` field
+/// holding `<tokens>:<expiry in Unix ms>`, so a reset drops them with the
+/// bucket and they live on the bucket's cluster slot. Lapsed ones are
+/// reclaimed (deleted; their unspent tokens stay spent) whenever the
+/// key's leases are acquired or listed.
+struct LeaseScripts {
+    acquire: Script,
+    renew: Script,
+    give_back: Script,
+    list: Script,
+}
+
+/// Deletes `key`'s lapsed leases and returns the rest as `{id, tokens,
+/// expires_ms}`.
+const RECLAIM_LEASES: &str = r#"
+local function reclaim(key, now_ms)
+    local fields = redis.call('HGETALL', key)
+    local live = {}
+    for i = 1, #fields, 2 do
+        local name = fields[i]
+        if string.sub(name, 1, 6) == 'lease:' then
+            local tokens, expires = string.match(fields[i + 1], '(%d+):(%d+)')
+            if tonumber(expires) <= now_ms then
+                redis.call('HDEL', key, name)
+            else
+                table.insert(live, {string.sub(name, 7), tonumber(tokens), tonumber(expires)})
+            end
+        end
+    end
+    return live
+end
+"#;
+
+impl LeaseScripts {
+    fn new() -> Self {
+        Self {
+            acquire: Script::new(&format!(
+                "{}{}",
+                RECLAIM_LEASES,
+                r#"
+            local key = KEYS[1]
+            local capacity = tonumber(ARGV[1])
+            local refill_rate = tonumber(ARGV[2])
+            local wanted = tonumber(ARGV[3])
+            local now = tonumber(ARGV[4])
+            local now_ms = math.floor(now * 1000)
+            local expires_ms = now_ms + tonumber(ARGV[5])
+            local field = 'lease:' .. ARGV[6]
+
+            reclaim(key, now_ms)
+            local bucket = redis.call('HMGET', key, 'tokens', 'last_refill')
+            local tokens = tonumber(bucket[1]) or capacity
+            local last_refill = tonumber(bucket[2]) or now
+            tokens = math.min(capacity, tokens + math.floor((now - last_refill) * refill_rate))
+
+            -- Reply is {1, expiry} when granted, {0, tokens left} when not
+            if tokens < wanted then
+                redis.call('HMSET', key, 'tokens', tokens, 'last_refill', now)
+                redis.call('EXPIRE', key, 3600)
+                return {0, tokens}
+            end
+            redis.call('HMSET', key, 'tokens', tokens - wanted, 'last_refill', now,
+                field, wanted .. ':' .. expires_ms)
+            redis.call('EXPIRE', key, 3600)
+            return {1, expires_ms}
+            "#
+            )),
+            renew: Script::new(
+                r#"
+            local key = KEYS[1]
+            local now_ms = tonumber(ARGV[1])
+            local expires_ms = now_ms + tonumber(ARGV[2])
+            local field = 'lease:' .. ARGV[3]
+
+            -- Reply is {1, tokens, expiry}, or {0, 0, 0} once lapsed
+            local lease = redis.call('HGET', key, field)
+            if not lease then
+                return {0, 0, 0}
+            end
+            local tokens, expires = string.match(lease, '(%d+):(%d+)')
+            if tonumber(expires) <= now_ms then
+                redis.call('HDEL', key, field)
+                return {0, 0, 0}
+            end
+            redis.call('HSET', key, field, tokens .. ':' .. expires_ms)
+            return {1, tonumber(tokens), expires_ms}
+            "#,
+            ),
+            give_back: Script::new(
+                r#"
+            local key = KEYS[1]
+            local capacity = tonumber(ARGV[1])
+            local refill_rate = tonumber(ARGV[2])
+            local now = tonumber(ARGV[3])
+            local field = 'lease:' .. ARGV[4]
+            local unused = tonumber(ARGV[5])
+
+            -- Reply is the tokens credited back
+            local lease = redis.call('HGET', key, field)
+            if not lease then
+                return 0
+            end
+            redis.call('HDEL', key, field)
+            local leased, expires = string.match(lease, '(%d+):(%d+)')
+            if tonumber(expires) <= math.floor(now * 1000) then
+                return 0
+            end
+            local credit = math.min(unused, tonumber(leased))
+            local bucket = redis.call('HMGET', key, 'tokens', 'last_refill')
+            local tokens = tonumber(bucket[1])
+            if not tokens then
+                return 0
+            end
+            local last_refill = tonumber(bucket[2]) or now
+            local elapsed = now - last_refill
+            tokens = math.min(capacity, tokens + math.floor(elapsed * refill_rate) + credit)
+            redis.call('HMSET', key, 'tokens', tokens, 'last_refill', now)
+            redis.call('EXPIRE', key, 3600)
+            return credit
+            "#,
+            ),
+            list: Script::new(&format!(
+                "{}{}",
+                RECLAIM_LEASES,
+                r#"
+            return reclaim(KEYS[1], tonumber(ARGV[1]))
+            "#
+            )),
+        }
+    }
+}
+
+/// Locks are plain string keys holding their holder's name, expiring with
+/// the lock.
+struct LockScripts {
+    hold: Script,
+    release: Script,
+}
+
+impl LockScripts {
+    fn new() -> Self {
+        Self {
+            hold: Script::new(
+                r#"
+            local holder = redis.call('GET', KEYS[1])
+            if holder and holder ~= ARGV[1] then
+                return 0
+            end
+            redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+            return 1
+            "#,
+            ),
+            release: Script::new(
+                r#"
+            if redis.call('GET', KEYS[1]) == ARGV[1] then
+                redis.call('DEL', KEYS[1])
+            end
+            return 0
+            "#,
+            ),
+        }
+    }
+}
+
+fn lease(id: u64, tokens: u64, expires_ms: u64) -> Lease {
+    Lease {
+        id,
+        tokens,
+        expires_at: UNIX_EPOCH + Duration::from_millis(expires_ms),
+    }
+}
 
-        Ok(usage)
+/// Token count after refilling a stored bucket up to `now`, mirroring the Lua
+/// scripts so both execution modes agree. Missing buckets start full.
+fn refill_tokens(
+    tokens: Option<u64>,
+    last_refill: Option<f64>,
+    capacity: u64,
+    refill_rate: u64,
+    now: f64,
+) -> u64 {
+    match (tokens, last_refill) {
+        (Some(tokens), Some(last_refill)) => {
+            let elapsed = (now - last_refill).max(0.0);
+            let tokens_to_add = (elapsed * refill_rate as f64).floor() as u64;
+            tokens.saturating_add(tokens_to_add).min(capacity)
+        }
+        (Some(tokens), None) => tokens.min(capacity),
+        (None, _) => capacity,
+    }
+}
+
+#[async_trait]
+impl StorageBackend for RedisBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let (allowed, remaining) = if self.mode == ExecutionMode::Transactions {
+            traced(
+                "take_token.transaction",
+                key,
+                self.take_token_transaction(key, cost),
+            )
+            .await?
+        } else {
+            traced(
+                "take_token.script",
+                key,
+                self.take_token_scripted(key, cost),
+            )
+            .await?
+        };
+        Ok(TokenDecision {
+            allowed,
+            bucket: Some(BucketSnapshot::new(&self.config, remaining)),
+        })
+    }
+
+    /// One script over every key, so it needs scripting.
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        if self.mode == ExecutionMode::Transactions {
+            return Err(RateLimitError::Unsupported(
+                "Redis takes several keys at once with Lua scripting, not transactions mode"
+                    .to_string(),
+            ));
+        }
+        let first = entries.first().map_or("", |(key, _)| *key);
+        let decisions = traced("take_all.script", first, self.take_all_scripted(entries)).await?;
+        Ok(decisions
+            .into_iter()
+            .map(|(allowed, remaining)| TokenDecision {
+                allowed,
+                bucket: Some(BucketSnapshot::new(&self.config, remaining)),
+            })
+            .collect())
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        let mut conn = self.connection.as_ref().clone();
+        let (tokens, last_refill): (Option<u64>, Option<f64>) = traced("inspect", key, async {
+            conn.hget(key, &["tokens", "last_refill"])
+                .await
+                .map_err(|e| RateLimitError::StorageError(format!("Redis get error: {}", e)))
+        })
+        .await?;
+
+        let remaining = refill_tokens(
+            tokens,
+            last_refill,
+            self.config.capacity,
+            self.config.refill_rate,
+            Self::get_current_time(),
+        );
+        let bucket = BucketSnapshot::new(&self.config, remaining);
+        Ok(Some(match last_refill {
+            Some(secs) if secs >= 0.0 => {
+                bucket.with_last_refill(UNIX_EPOCH + Duration::from_secs_f64(secs))
+            }
+            _ => bucket,
+        }))
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        if self.mode == ExecutionMode::Transactions {
+            return traced("get_usage.direct", key, self.get_usage_direct(key)).await;
+        }
+
+        let mut conn = self.connection.as_ref().clone();
+        let now = Self::get_current_time();
+
+        traced("get_usage.script", key, async {
+            self.get_usage_script
+                .key(key)
+                .arg(self.config.capacity)
+                .arg(self.config.refill_rate)
+                .arg(now)
+                .invoke_async::<u64>(&mut conn)
+                .await
+                .map_err(|e| {
+                    RateLimitError::StorageError(format!("Redis script execution error: {}", e))
+                })
+        })
+        .await
     }
 
     async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
         let mut conn = self.connection.as_ref().clone();
-        conn.del::<_, ()>(key)
-            .await
-            .map_err(|e| RateLimitError::StorageError(format!("Redis delete error: {}", e)))?;
+        traced("reset", key, async {
+            conn.del::<_, ()>(key)
+                .await
+                .map_err(|e| RateLimitError::StorageError(format!("Redis delete error: {}", e)))
+        })
+        .await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        if self.mode == ExecutionMode::Transactions {
+            return traced(
+                "refund.transaction",
+                key,
+                self.refund_transaction(key, tokens),
+            )
+            .await;
+        }
+
+        let mut conn = self.connection.as_ref().clone();
+        traced("refund.script", key, async {
+            self.refund_script
+                .key(key)
+                .arg(self.config.capacity)
+                .arg(self.config.refill_rate)
+                .arg(tokens)
+                .arg(Self::get_current_time())
+                .invoke_async::<u64>(&mut conn)
+                .await
+                .map_err(|e| {
+                    RateLimitError::StorageError(format!("Redis script execution error: {}", e))
+                })
+        })
+        .await?;
         Ok(())
     }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.require_scripting()?;
+        let mut conn = self.connection.as_ref().clone();
+        let (granted, value): (i32, u64) = traced("acquire_lease.script", key, async {
+            self.lease_scripts
+                .acquire
+                .key(key)
+                .arg(self.config.capacity)
+                .arg(self.config.refill_rate)
+                .arg(tokens)
+                .arg(Self::get_current_time())
+                .arg(ttl.as_millis() as u64)
+                .arg(id)
+                .invoke_async(&mut conn)
+                .await
+                .map_err(|e| {
+                    RateLimitError::StorageError(format!("Redis script execution error: {}", e))
+                })
+        })
+        .await?;
+        Ok((granted == 1).then(|| lease(id, tokens, value)))
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.require_scripting()?;
+        let mut conn = self.connection.as_ref().clone();
+        let (renewed, tokens, expires_ms): (i32, u64, u64) =
+            traced("renew_lease.script", key, async {
+                self.lease_scripts
+                    .renew
+                    .key(key)
+                    .arg((Self::get_current_time() * 1000.0) as u64)
+                    .arg(ttl.as_millis() as u64)
+                    .arg(id)
+                    .invoke_async(&mut conn)
+                    .await
+                    .map_err(|e| {
+                        RateLimitError::StorageError(format!(
+                            "Redis script execution error: {}",
+                            e
+                        ))
+                    })
+            })
+            .await?;
+        Ok((renewed == 1).then(|| lease(id, tokens, expires_ms)))
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.require_scripting()?;
+        let mut conn = self.connection.as_ref().clone();
+        traced("return_lease.script", key, async {
+            self.lease_scripts
+                .give_back
+                .key(key)
+                .arg(self.config.capacity)
+                .arg(self.config.refill_rate)
+                .arg(Self::get_current_time())
+                .arg(id)
+                .arg(unused)
+                .invoke_async::<u64>(&mut conn)
+                .await
+                .map_err(|e| {
+                    RateLimitError::StorageError(format!("Redis script execution error: {}", e))
+                })
+        })
+        .await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.require_scripting()?;
+        let mut conn = self.connection.as_ref().clone();
+        let live: Vec<(String, u64, u64)> = traced("leases.script", key, async {
+            self.lease_scripts
+                .list
+                .key(key)
+                .arg((Self::get_current_time() * 1000.0) as u64)
+                .invoke_async(&mut conn)
+                .await
+                .map_err(|e| {
+                    RateLimitError::StorageError(format!("Redis script execution error: {}", e))
+                })
+        })
+        .await?;
+        Ok(live
+            .into_iter()
+            .filter_map(|(id, tokens, expires_ms)| {
+                Some(lease(id.parse().ok()?, tokens, expires_ms))
+            })
+            .collect())
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.require_scripting()?;
+        let mut conn = self.connection.as_ref().clone();
+        traced("exchange_demand.script", key, async {
+            self.demand_script
+                .key(key)
+                .arg((Self::get_current_time() * 1000.0) as u64)
+                .arg(node)
+                .arg(demand)
+                .arg(ttl.as_millis() as u64)
+                .invoke_async(&mut conn)
+                .await
+                .map_err(|e| {
+                    RateLimitError::StorageError(format!("Redis script execution error: {}", e))
+                })
+        })
+        .await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.require_scripting()?;
+        let mut conn = self.connection.as_ref().clone();
+        traced("hold_lock.script", name, async {
+            self.lock_scripts
+                .hold
+                .key(name)
+                .arg(holder)
+                .arg(ttl.as_millis().max(1) as u64)
+                .invoke_async::<u8>(&mut conn)
+                .await
+                .map(|held| held == 1)
+                .map_err(|e| {
+                    RateLimitError::StorageError(format!("Redis script execution error: {}", e))
+                })
+        })
+        .await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.require_scripting()?;
+        let mut conn = self.connection.as_ref().clone();
+        traced("release_lock.script", name, async {
+            self.lock_scripts
+                .release
+                .key(name)
+                .arg(holder)
+                .invoke_async::<u8>(&mut conn)
+                .await
+                .map(|_| ())
+                .map_err(|e| {
+                    RateLimitError::StorageError(format!("Redis script execution error: {}", e))
+                })
+        })
+        .await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        let mut conn = self.connection.as_ref().clone();
+        redis::cmd("PING")
+            .query_async::<()>(&mut conn)
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("Redis ping error: {}", e)))
+    }
+
+    /// Every page walks the whole keyspace with SCAN (bucket hashes only),
+    /// so this is meant for occasional admin use, not hot paths. Servers
+    /// without `SCAN ... TYPE` get one extra round trip per batch to check
+    /// each key's type.
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        let mut conn = self.connection.as_ref().clone();
+        let mut keys: Vec<String> = Vec::new();
+        let mut cursor: u64 = 0;
+
+        loop {
+            let (next, batch): (u64, Vec<String>) =
+                scan_command(cursor, pattern, &self.capabilities)
+                    .query_async(&mut conn)
+                    .await
+                    .map_err(|e| {
+                        RateLimitError::StorageError(format!("Redis scan error: {}", e))
+                    })?;
+
+            if self.capabilities.scan_type || batch.is_empty() {
+                keys.extend(batch);
+            } else {
+                let mut types = redis::pipe();
+                for key in &batch {
+                    types.cmd("TYPE").arg(key);
+                }
+                let types: Vec<String> = types.query_async(&mut conn).await.map_err(|e| {
+                    RateLimitError::StorageError(format!("Redis type error: {}", e))
+                })?;
+                keys.extend(
+                    batch
+                        .into_iter()
+                        .zip(types)
+                        .filter(|(_, kind)| kind == "hash")
+                        .map(|(key, _)| key),
+                );
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        Ok(page_of_keys(keys.iter().map(String::as_str), pattern, after, limit))
+    }
+}
+
+/// One SCAN page of keys matching `pattern`, filtered to hashes on servers
+/// that can do it themselves.
+fn scan_command(cursor: u64, pattern: &str, capabilities: &ServerCapabilities) -> redis::Cmd {
+    let mut cmd = redis::cmd("SCAN");
+    cmd.arg(cursor)
+        .arg("MATCH")
+        .arg(scan_pattern(pattern))
+        .arg("COUNT")
+        .arg(SCAN_BATCH);
+    if capabilities.scan_type {
+        cmd.arg("TYPE").arg("hash");
+    }
+    cmd
+}
+
+/// Escape Redis glob syntax other than `*`, which means the same thing in
+/// our patterns.
+fn scan_pattern(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if matches!(c, '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
 }
 
 
@@ -160,6 +1163,7 @@ pub struct RedisClusterBackend {
     connection: Arc<redis::cluster_async::ClusterConnection>,
     config: TokenBucketConfig,
     take_token_script: Script,
+    refund_script: Script,
 }
 
 impl RedisClusterBackend {
@@ -179,6 +1183,7 @@ impl RedisClusterBackend {
             connection: Arc::new(connection),
             config,
             take_token_script: RedisBackend::create_take_token_script(),
+            refund_script: RedisBackend::create_refund_script(),
         })
     }
 
@@ -191,34 +1196,49 @@ impl RedisClusterBackend {
 #[async_trait]
 impl StorageBackend for RedisClusterBackend {
     async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
         let hashed_key = self.hash_key(key);
         let mut conn = self.connection.as_ref().clone();
         let now = RedisBackend::get_current_time();
 
-        let result: i32 = self
-            .take_token_script
-            .key(hashed_key)
-            .arg(self.config.capacity)
-            .arg(self.config.refill_rate)
-            .arg(cost)
-            .arg(now)
-            .invoke_async(&mut conn)
-            .await
-            .map_err(|e| {
-                RateLimitError::StorageError(format!("Cluster script execution error: {}", e))
-            })?;
+        let (allowed, remaining): (i32, u64) = traced("cluster.take_token", key, async {
+            self.take_token_script
+                .key(hashed_key)
+                .arg(self.config.capacity)
+                .arg(self.config.refill_rate)
+                .arg(cost)
+                .arg(now)
+                .invoke_async(&mut conn)
+                .await
+                .map_err(|e| {
+                    RateLimitError::StorageError(format!("Cluster script execution error: {}", e))
+                })
+        })
+        .await?;
 
-        Ok(result == 1)
+        Ok(TokenDecision {
+            allowed: allowed == 1,
+            bucket: Some(BucketSnapshot::new(&self.config, remaining)),
+        })
     }
 
     async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
         let hashed_key = self.hash_key(key);
         let mut conn = self.connection.as_ref().clone();
 
-        let bucket: Option<(u64, f64)> = conn
-            .hget(&hashed_key, &["tokens", "last_refill"])
-            .await
-            .map_err(|e| RateLimitError::StorageError(format!("Redis get error: {}", e)))?;
+        let bucket: Option<(u64, f64)> = traced("cluster.get_usage", key, async {
+            conn.hget(&hashed_key, &["tokens", "last_refill"])
+                .await
+                .map_err(|e| RateLimitError::StorageError(format!("Redis get error: {}", e)))
+        })
+        .await?;
 
         match bucket {
             Some((tokens, _)) => Ok(self.config.capacity.saturating_sub(tokens)),
@@ -230,11 +1250,42 @@ impl StorageBackend for RedisClusterBackend {
         let hashed_key = self.hash_key(key);
         let mut conn = self.connection.as_ref().clone();
 
-        conn.del::<_, ()>(hashed_key)
-            .await
-            .map_err(|e| RateLimitError::StorageError(format!("Redis delete error: {}", e)))?;
+        traced("cluster.reset", key, async {
+            conn.del::<_, ()>(hashed_key)
+                .await
+                .map_err(|e| RateLimitError::StorageError(format!("Redis delete error: {}", e)))
+        })
+        .await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        let hashed_key = self.hash_key(key);
+        let mut conn = self.connection.as_ref().clone();
+
+        traced("cluster.refund", key, async {
+            self.refund_script
+                .key(hashed_key)
+                .arg(self.config.capacity)
+                .arg(self.config.refill_rate)
+                .arg(tokens)
+                .arg(RedisBackend::get_current_time())
+                .invoke_async::<u64>(&mut conn)
+                .await
+                .map_err(|e| {
+                    RateLimitError::StorageError(format!("Cluster script execution error: {}", e))
+                })
+        })
+        .await?;
         Ok(())
     }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        let mut conn = self.connection.as_ref().clone();
+        redis::cmd("PING")
+            .query_async::<()>(&mut conn)
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("Cluster ping error: {}", e)))
+    }
 }
 
 
@@ -246,6 +1297,7 @@ pub struct CachedRedisBackend {
     redis: Arc<RedisBackend>,
     cache: Arc<RwLock<HashMap<String, CacheEntry>>>,
     cache_ttl: std::time::Duration,
+    stats: Arc<CacheStats>,
 }
 
 struct CacheEntry {
@@ -259,9 +1311,21 @@ impl CachedRedisBackend {
             redis: Arc::new(redis),
             cache: Arc::new(RwLock::new(HashMap::new())),
             cache_ttl,
+            stats: Arc::default(),
         }
     }
 
+    /// Record into `stats`, e.g. to total several backends' hits.
+    pub fn with_stats(mut self, stats: Arc<CacheStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Checks answered from the cache, and checks that went to Redis.
+    pub fn stats(&self) -> &Arc<CacheStats> {
+        &self.stats
+    }
+
     fn get_cached(&self, key: &str) -> Option<u64> {
         let cache = self.cache.read();
         cache.get(key).and_then(|entry| {
@@ -292,11 +1356,13 @@ impl StorageBackend for CachedRedisBackend {
         if let Some(cached_tokens) = self.get_cached(key) {
             if cached_tokens >= cost {
                 self.set_cache(key, cached_tokens - cost);
+                self.stats.record(true);
                 return Ok(true);
             }
         }
 
         // Fallback to Redis
+        self.stats.record(false);
         let result = self.redis.take_token(key, cost).await?;
         if result {
             // Update cache with estimated remaining tokens
@@ -309,6 +1375,10 @@ impl StorageBackend for CachedRedisBackend {
         self.redis.get_usage(key).await
     }
 
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.redis.inspect(key).await
+    }
+
     async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
         {
             let mut cache = self.cache.write();
@@ -316,6 +1386,101 @@ impl StorageBackend for CachedRedisBackend {
         } // Drop lock before await
         self.redis.reset(key).await
     }
+
+    /// The cached estimate is dropped; the next check reads Redis again.
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.cache.write().remove(key);
+        self.redis.refund(key, tokens).await
+    }
+
+    /// Goes straight to Redis, dropping the keys' cached estimates.
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        {
+            let mut cache = self.cache.write();
+            for (key, _) in entries {
+                cache.remove(*key);
+            }
+        }
+        self.redis.take_all(entries).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.redis.health_check().await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.redis.acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.redis.renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.redis.return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.redis.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.redis.exchange_demand(key, node, demand, ttl).await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.redis.hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.redis.release_lock(name, holder).await
+    }
+
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        self.redis.flush().await
+    }
+
+    /// Seed the cache with the tokens left in Redis.
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        if let Some(bucket) = self.redis.inspect(key).await? {
+            self.set_cache(key, bucket.remaining);
+        }
+        Ok(())
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.redis.list_keys(pattern, after, limit).await
+    }
 }
 
 #[cfg(test)]
@@ -341,4 +1506,210 @@ mod tests {
 
         backend.reset("test_user").await.unwrap();
     }
+
+    #[tokio::test]
+    #[ignore] // Requires Redis instance
+    async fn test_redis_inspect_reports_last_refill() {
+        let config = TokenBucketConfig {
+            capacity: 100,
+            refill_rate: 10,
+            refill_interval: std::time::Duration::from_secs(1),
+        };
+        let backend = RedisBackend::new("redis://127.0.0.1", config)
+            .await
+            .unwrap();
+        backend.reset("inspect_user").await.unwrap();
+
+        let bucket = backend.inspect("inspect_user").await.unwrap().unwrap();
+        assert_eq!((bucket.remaining, bucket.last_refill), (100, None));
+
+        let before = SystemTime::now() - Duration::from_secs(1);
+        assert!(backend.take_token("inspect_user", 10).await.unwrap());
+        let bucket = backend.inspect("inspect_user").await.unwrap().unwrap();
+        let last_refill = bucket.last_refill.unwrap();
+        assert!(last_refill >= before && last_refill <= SystemTime::now());
+
+        backend.reset("inspect_user").await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires Redis instance
+    async fn test_redis_backend_transaction_mode() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(1),
+        };
+
+        let backend = RedisBackend::with_execution_mode(
+            "redis://127.0.0.1",
+            config,
+            ExecutionMode::Transactions,
+        )
+        .await
+        .unwrap();
+        backend.reset("test_tx_user").await.unwrap();
+
+        assert!(backend.take_token("test_tx_user", 6).await.unwrap());
+        assert!(!backend.take_token("test_tx_user", 6).await.unwrap());
+        assert_eq!(backend.get_usage("test_tx_user").await.unwrap(), 6);
+        assert!(matches!(
+            backend.take_all(&[("test_tx_user", 1)]).await,
+            Err(RateLimitError::Unsupported(_))
+        ));
+
+        backend.reset("test_tx_user").await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires Redis instance
+    async fn test_redis_take_all() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 0,
+            refill_interval: std::time::Duration::from_secs(1),
+        };
+        let backend = RedisBackend::new("redis://127.0.0.1", config)
+            .await
+            .unwrap();
+        for key in ["all_a", "all_b"] {
+            backend.reset(key).await.unwrap();
+        }
+        let allowed = |decisions: Vec<TokenDecision>| -> Vec<bool> {
+            decisions.iter().map(|decision| decision.allowed).collect()
+        };
+
+        let denied = backend
+            .take_all(&[("all_a", 4), ("all_b", 11)])
+            .await
+            .unwrap();
+        assert_eq!(allowed(denied), vec![true, false]);
+        assert_eq!(backend.get_usage("all_a").await.unwrap(), 0);
+
+        let taken = backend
+            .take_all(&[("all_a", 4), ("all_a", 4), ("all_b", 2)])
+            .await
+            .unwrap();
+        assert_eq!(taken[2].bucket.unwrap().remaining, 8);
+        assert_eq!(allowed(taken), vec![true, true, true]);
+        assert_eq!(backend.get_usage("all_a").await.unwrap(), 8);
+
+        for key in ["all_a", "all_b"] {
+            backend.reset(key).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires Redis instance
+    async fn test_redis_leases() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 0,
+            refill_interval: std::time::Duration::from_secs(1),
+        };
+
+        let backend = RedisBackend::new("redis://127.0.0.1", config)
+            .await
+            .unwrap();
+        backend.reset("test_lease_user").await.unwrap();
+        let ttl = Duration::from_secs(30);
+
+        let lease = backend.acquire_lease("test_lease_user", 7, 6, ttl).await;
+        assert_eq!(lease.unwrap().unwrap().tokens, 6);
+        assert!(backend
+            .acquire_lease("test_lease_user", 8, 6, ttl)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(backend
+            .renew_lease("test_lease_user", 7, ttl)
+            .await
+            .unwrap()
+            .is_some());
+        assert_eq!(backend.leases("test_lease_user").await.unwrap().len(), 1);
+
+        let returned = backend.return_lease("test_lease_user", 7, 4).await;
+        assert_eq!(returned.unwrap(), 4);
+        assert_eq!(backend.get_usage("test_lease_user").await.unwrap(), 2);
+        assert!(backend.leases("test_lease_user").await.unwrap().is_empty());
+
+        backend.reset("test_lease_user").await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires Redis instance
+    async fn test_redis_demand() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(1),
+        };
+
+        let backend = RedisBackend::new("redis://127.0.0.1", config)
+            .await
+            .unwrap();
+        backend.reset("test_demand_user").await.unwrap();
+        let ttl = Duration::from_secs(30);
+
+        backend
+            .exchange_demand("test_demand_user", "a", 5, ttl)
+            .await
+            .unwrap();
+        let mut demands = backend
+            .exchange_demand("test_demand_user", "b", 2, ttl)
+            .await
+            .unwrap();
+        demands.sort();
+        assert_eq!(demands, [("a".to_string(), 5), ("b".to_string(), 2)]);
+
+        backend.reset("test_demand_user").await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires Redis instance
+    async fn test_redis_locks() {
+        let backend = RedisBackend::new("redis://127.0.0.1", TokenBucketConfig::default())
+            .await
+            .unwrap();
+        let ttl = Duration::from_secs(30);
+        backend.release_lock("test_lock", "a").await.unwrap();
+
+        assert!(backend.hold_lock("test_lock", "a", ttl).await.unwrap());
+        assert!(!backend.hold_lock("test_lock", "b", ttl).await.unwrap());
+        assert!(backend.hold_lock("test_lock", "a", ttl).await.unwrap());
+        backend.release_lock("test_lock", "a").await.unwrap();
+        assert!(backend.hold_lock("test_lock", "b", ttl).await.unwrap());
+
+        backend.release_lock("test_lock", "b").await.unwrap();
+    }
+
+    #[test]
+    fn test_scan_filters_by_type_only_when_the_server_can() {
+        let packed = |scan_type| {
+            let caps = ServerCapabilities {
+                scan_type,
+                ..ServerCapabilities::baseline()
+            };
+            String::from_utf8(scan_command(0, "api:*", &caps).get_packed_command()).unwrap()
+        };
+
+        assert!(packed(true).ends_with("$4\r\nTYPE\r\n$4\r\nhash\r\n"));
+        assert!(!packed(false).contains("TYPE"));
+        assert!(packed(false).contains("api:*"));
+    }
+
+    #[test]
+    fn test_scan_pattern_escapes_redis_globs() {
+        assert_eq!(scan_pattern("api:*"), "api:*");
+        assert_eq!(scan_pattern("a?[b]"), "a\\?\\[b\\]");
+    }
+
+    #[test]
+    fn test_refill_tokens_matches_script() {
+        assert_eq!(refill_tokens(None, None, 100, 10, 50.0), 100);
+        assert_eq!(refill_tokens(Some(0), Some(10.0), 100, 10, 12.5), 25);
+        assert_eq!(refill_tokens(Some(90), Some(10.0), 100, 10, 20.0), 100);
+        // Clock going backwards never removes tokens.
+        assert_eq!(refill_tokens(Some(5), Some(10.0), 100, 10, 9.0), 5);
+    }
 }
\ No newline at end of file
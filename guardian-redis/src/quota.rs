@@ -0,0 +1,279 @@
+// Calendar quotas: fixed daily/monthly allowances that reset at a
+// timezone-local boundary instead of refilling continuously.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use guardian_core::{RateLimitError, StorageBackend};
+use redis::{aio::ConnectionManager, AsyncCommands, Client};
+use std::sync::Arc;
+
+use crate::{traced, RedisBackend};
+
+/// Calendar period a quota counter covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaConfig {
+    /// Tokens allowed per period.
+    pub limit: u64,
+    pub period: QuotaPeriod,
+    /// Timezone whose local midnight starts each period.
+    pub timezone: Tz,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            limit: 10_000,
+            period: QuotaPeriod::Daily,
+            timezone: chrono_tz::UTC,
+        }
+    }
+}
+
+impl QuotaConfig {
+    /// Label for the period containing `now`, used to version counter keys so
+    /// a new period starts from zero even if expiry lags.
+    fn period_label(&self, now: DateTime<Utc>) -> String {
+        let local = now.with_timezone(&self.timezone);
+        match self.period {
+            QuotaPeriod::Daily => local.format("%Y-%m-%d").to_string(),
+            QuotaPeriod::Monthly => local.format("%Y-%m").to_string(),
+        }
+    }
+
+    /// Start of the period following the one containing `now`.
+    fn period_end(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let local = now.with_timezone(&self.timezone).date_naive();
+        let next = match self.period {
+            QuotaPeriod::Daily => local + Duration::days(1),
+            QuotaPeriod::Monthly => {
+                let (year, month) = if local.month() == 12 {
+                    (local.year() + 1, 1)
+                } else {
+                    (local.year(), local.month() + 1)
+                };
+                NaiveDate::from_ymd_opt(year, month, 1).expect("first of month is valid")
+            }
+        };
+        local_midnight(&self.timezone, next)
+    }
+}
+
+/// Midnight at the start of `date` in `tz`. Where a DST jump skips midnight,
+/// the first instant of the day that does exist is used.
+fn local_midnight(tz: &Tz, date: NaiveDate) -> DateTime<Utc> {
+    let mut naive = date.and_hms_opt(0, 0, 0).expect("midnight is valid");
+    loop {
+        if let Some(dt) = tz.from_local_datetime(&naive).earliest() {
+            return dt.with_timezone(&Utc);
+        }
+        naive += Duration::minutes(15);
+    }
+}
+
+/// Daily/monthly quota counters on Redis, kept with plain INCRBY/DECRBY and
+/// EXPIREAT so they also work on servers that disable scripting.
+///
+/// Implements `StorageBackend` so quotas plug into `RateLimiter` exactly like
+/// token buckets, and can share a connection with a `RedisBackend`.
+pub struct RedisQuotaBackend {
+    connection: Arc<ConnectionManager>,
+    config: QuotaConfig,
+}
+
+impl RedisQuotaBackend {
+    pub async fn new(redis_url: &str, config: QuotaConfig) -> Result<Self, RateLimitError> {
+        let client = Client::open(redis_url)
+            .map_err(|e| RateLimitError::StorageError(format!("Redis client error: {}", e)))?;
+
+        let connection = client
+            .get_connection_manager()
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("Redis connection error: {}", e)))?;
+
+        Ok(Self {
+            connection: Arc::new(connection),
+            config,
+        })
+    }
+
+    /// Reuse the connection of an existing token-bucket backend.
+    pub fn from_backend(backend: &RedisBackend, config: QuotaConfig) -> Self {
+        Self {
+            connection: Arc::clone(&backend.connection),
+            config,
+        }
+    }
+
+    fn counter_key(&self, key: &str, now: DateTime<Utc>) -> String {
+        format!("{}:quota:{}", key, self.config.period_label(now))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for RedisQuotaBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        let now = Utc::now();
+        let counter = self.counter_key(key, now);
+        let expires_at = self.config.period_end(now).timestamp();
+        let mut conn = self.connection.as_ref().clone();
+
+        traced("quota.take_token", key, async {
+            let map_err = |e: redis::RedisError| {
+                RateLimitError::StorageError(format!("Redis quota error: {}", e))
+            };
+
+            // INCRBY and EXPIREAT go in one MULTI so a counter never outlives
+            // its period, without needing EVAL.
+            let (used,): (u64,) = redis::pipe()
+                .atomic()
+                .incr(&counter, cost)
+                .expire_at(&counter, expires_at)
+                .ignore()
+                .query_async(&mut conn)
+                .await
+                .map_err(map_err)?;
+            if used <= self.config.limit {
+                return Ok(true);
+            }
+
+            // Over the limit: take the cost back off so a denied request
+            // doesn't count. Until the DECRBY lands, concurrent checks near
+            // the limit can see the counter inflated and be denied too.
+            conn.decr::<_, _, i64>(&counter, cost)
+                .await
+                .map_err(map_err)?;
+            Ok(false)
+        })
+        .await
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let counter = self.counter_key(key, Utc::now());
+        let mut conn = self.connection.as_ref().clone();
+
+        let used: Option<u64> = conn
+            .get(&counter)
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("Redis get error: {}", e)))?;
+        Ok(used.unwrap_or(0))
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let counter = self.counter_key(key, Utc::now());
+        let mut conn = self.connection.as_ref().clone();
+
+        conn.del::<_, ()>(counter)
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("Redis delete error: {}", e)))?;
+        Ok(())
+    }
+
+    /// Takes `tokens` off this period's count, never below zero. A period
+    /// with no counter yet is left at zero.
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        let now = Utc::now();
+        let counter = self.counter_key(key, now);
+        let expires_at = self.config.period_end(now).timestamp();
+        let mut conn = self.connection.as_ref().clone();
+
+        let map_err = |e: redis::RedisError| {
+            RateLimitError::StorageError(format!("Redis quota error: {}", e))
+        };
+
+        let (used,): (i64,) = redis::pipe()
+            .atomic()
+            .decr(&counter, tokens)
+            .expire_at(&counter, expires_at)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .map_err(map_err)?;
+        if used < 0 {
+            // More was refunded than counted; add the shortfall back so the
+            // counter rests at zero.
+            conn.incr::<_, _, i64>(&counter, -used)
+                .await
+                .map_err(map_err)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(y: i32, m: u32, d: u32, h: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn test_daily_boundary_respects_timezone() {
+        let config = QuotaConfig {
+            limit: 10,
+            period: QuotaPeriod::Daily,
+            timezone: chrono_tz::America::New_York,
+        };
+
+        // 02:00 UTC on Jan 15 is still Jan 14 in New York (UTC-5).
+        let now = utc(2025, 1, 15, 2);
+        assert_eq!(config.period_label(now), "2025-01-14");
+        assert_eq!(config.period_end(now), utc(2025, 1, 15, 5));
+    }
+
+    #[test]
+    fn test_monthly_boundary_rolls_over_year() {
+        let config = QuotaConfig {
+            limit: 10,
+            period: QuotaPeriod::Monthly,
+            timezone: chrono_tz::Asia::Tokyo,
+        };
+
+        let now = utc(2025, 12, 20, 12);
+        assert_eq!(config.period_label(now), "2025-12");
+        // Midnight Jan 1 in Tokyo (UTC+9) is 15:00 UTC on Dec 31.
+        assert_eq!(config.period_end(now), utc(2025, 12, 31, 15));
+    }
+
+    #[test]
+    fn test_daily_boundary_across_dst_change() {
+        let config = QuotaConfig {
+            limit: 10,
+            period: QuotaPeriod::Daily,
+            timezone: chrono_tz::America::New_York,
+        };
+
+        // DST starts Mar 9 2025; midnight Mar 10 is EDT (UTC-4).
+        let now = utc(2025, 3, 9, 12);
+        assert_eq!(config.period_end(now), utc(2025, 3, 10, 4));
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires Redis instance
+    async fn test_redis_quota_backend() {
+        let config = QuotaConfig {
+            limit: 10,
+            ..QuotaConfig::default()
+        };
+        let backend = RedisQuotaBackend::new("redis://127.0.0.1", config)
+            .await
+            .unwrap();
+        backend.reset("quota_user").await.unwrap();
+
+        assert!(backend.take_token("quota_user", 7).await.unwrap());
+        assert!(!backend.take_token("quota_user", 7).await.unwrap());
+        assert_eq!(backend.get_usage("quota_user").await.unwrap(), 7);
+        // The denied request took nothing, so the rest still fits.
+        assert!(backend.take_token("quota_user", 3).await.unwrap());
+        assert!(!backend.take_token("quota_user", 1).await.unwrap());
+
+        backend.reset("quota_user").await.unwrap();
+    }
+}
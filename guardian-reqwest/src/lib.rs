@@ -0,0 +1,334 @@
+//! [`reqwest-middleware`](https://docs.rs/reqwest-middleware) middleware
+//! that asks Guardian before each outbound request, so a client app stays
+//! within a third-party API's quota instead of finding out from its 429s.
+//!
+//! ```no_run
+//! use guardian_client::GuardianClient;
+//! use guardian_reqwest::GuardianMiddleware;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let guardian = GuardianClient::connect("http://127.0.0.1:50051").await?;
+//! let http = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+//!     .with(GuardianMiddleware::new(guardian))
+//!     .build();
+//! // Checked against the bucket of "host:api.github.com" first.
+//! let repos = http.get("https://api.github.com/users/octocat/repos").send().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests are keyed by host unless [`with_key`](GuardianMiddleware::with_key)
+//! says otherwise, or a request carries a [`GuardianKey`]. A denied request
+//! waits out the limiter's retry time and is checked again, for up to
+//! [`with_max_wait`](GuardianMiddleware::with_max_wait); after that it fails
+//! with [`ThrottleError::RateLimited`] without being sent.
+
+use std::hash::BuildHasher;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use guardian_client::GuardianApi;
+use guardian_core::{LimitResult, RateLimiter, StorageBackend};
+use http::Extensions;
+use reqwest::{Request, Response};
+use reqwest_middleware::{Middleware, Next};
+use thiserror::Error;
+
+/// How long a request waits for its limit by default.
+pub const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(30);
+
+/// Shortest wait after a denial whose retry time rounds down to zero;
+/// doubled on each further denial, up to a second.
+const MIN_WAIT: Duration = Duration::from_millis(50);
+
+/// Why the middleware didn't send a request.
+#[derive(Error, Debug)]
+pub enum ThrottleError {
+    /// `key` was still over its limit when waiting any longer would have
+    /// passed the maximum wait.
+    #[error("rate limited on '{key}'; next try in {retry_after:?}")]
+    RateLimited { key: String, retry_after: Duration },
+
+    /// The limiter couldn't decide, and the middleware fails closed.
+    #[error("rate limiter unavailable: {0}")]
+    Unavailable(String),
+}
+
+/// Limits a request against this key instead of the one the middleware
+/// would pick. Attach it with `RequestBuilder::with_extension`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuardianKey(pub String);
+
+/// Tokens a request costs, when not 1. Attach it with
+/// `RequestBuilder::with_extension`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuardianCost(pub u32);
+
+/// Where decisions come from.
+#[async_trait]
+trait Limiter: Send + Sync {
+    /// `None` if the request may go; otherwise how long until it might.
+    async fn check(&self, key: &str, cost: u32) -> Result<Option<Duration>, String>;
+}
+
+struct Remote<A>(A);
+
+#[async_trait]
+impl<A: GuardianApi> Limiter for Remote<A> {
+    async fn check(&self, key: &str, cost: u32) -> Result<Option<Duration>, String> {
+        let result = self
+            .0
+            .check_limit_detailed(key, cost)
+            .await
+            .map_err(|e| e.to_string())?;
+        Ok((!result.allowed).then(|| Duration::from_secs(result.retry_after_seconds.into())))
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend + 'static> Limiter for RateLimiter<B> {
+    async fn check(&self, key: &str, cost: u32) -> Result<Option<Duration>, String> {
+        match self.check_limit(key, cost.into()).await {
+            Ok(LimitResult::Allowed) => Ok(None),
+            Ok(LimitResult::Denied { retry_after }) => Ok(Some(retry_after)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+type KeyFn = dyn Fn(&Request) -> Option<String> + Send + Sync;
+
+/// Checks each request with Guardian before sending it; see the
+/// [crate docs](crate).
+#[derive(Clone)]
+pub struct GuardianMiddleware {
+    limiter: Arc<dyn Limiter>,
+    key: Arc<KeyFn>,
+    max_wait: Duration,
+    fail_open: bool,
+}
+
+impl GuardianMiddleware {
+    /// Decide through a Guardian service: a
+    /// [`GuardianClient`](guardian_client::GuardianClient), or anything
+    /// else implementing [`GuardianApi`].
+    pub fn new(api: impl GuardianApi + 'static) -> Self {
+        Self::with_limiter(Remote(api))
+    }
+
+    /// Decide in this process, with a `guardian-core` limiter, for apps
+    /// that run no Guardian service. The limiter's own `fail_open`
+    /// applies to its backend's errors.
+    pub fn embedded<B: StorageBackend + 'static>(limiter: RateLimiter<B>) -> Self {
+        Self::with_limiter(limiter)
+    }
+
+    fn with_limiter(limiter: impl Limiter + 'static) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            key: Arc::new(host_key),
+            max_wait: DEFAULT_MAX_WAIT,
+            fail_open: true,
+        }
+    }
+
+    /// Pick each request's key with `key`, such as one bucket per API
+    /// route. Requests it returns `None` for go out unchecked. A
+    /// [`GuardianKey`] on the request still takes precedence.
+    pub fn with_key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&Request) -> Option<String> + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    /// Longest a request waits for its limit before failing with
+    /// [`ThrottleError::RateLimited`]; [`DEFAULT_MAX_WAIT`] unless set.
+    /// Zero fails denied requests at once.
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Whether to send requests when the limiter can't decide (the
+    /// default), or fail them with [`ThrottleError::Unavailable`].
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Wait until `cost` tokens of `key` are had, or fail.
+    async fn throttle(&self, key: &str, cost: u32) -> Result<(), ThrottleError> {
+        let deadline = Instant::now() + self.max_wait;
+        let mut attempt = 0;
+        loop {
+            let retry_after = match self.limiter.check(key, cost).await {
+                Ok(None) => return Ok(()),
+                Ok(Some(retry_after)) => retry_after,
+                Err(_) if self.fail_open => return Ok(()),
+                Err(e) => return Err(ThrottleError::Unavailable(e)),
+            };
+            let wait = wait_after(retry_after, attempt);
+            if Instant::now() + wait > deadline {
+                return Err(ThrottleError::RateLimited {
+                    key: key.to_string(),
+                    retry_after,
+                });
+            }
+            tokio::time::sleep(wait).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for GuardianMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let key = match extensions.get::<GuardianKey>() {
+            Some(GuardianKey(key)) => Some(key.clone()),
+            None => (self.key)(&req),
+        };
+        if let Some(key) = key {
+            let cost = extensions.get::<GuardianCost>().map_or(1, |cost| cost.0);
+            self.throttle(&key, cost)
+                .await
+                .map_err(reqwest_middleware::Error::middleware)?;
+        }
+        next.run(req, extensions).await
+    }
+}
+
+/// `host:<host>`, for requests to a host.
+fn host_key(req: &Request) -> Option<String> {
+    req.url().host_str().map(|host| format!("host:{}", host))
+}
+
+/// How long to wait after the `attempt`th denial: the limiter's retry
+/// time (or a short backoff when that is zero), plus up to a quarter more
+/// so waiting requests don't all come back at once.
+fn wait_after(retry_after: Duration, attempt: u32) -> Duration {
+    let base = if retry_after.is_zero() {
+        MIN_WAIT
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(Duration::from_secs(1))
+    } else {
+        retry_after
+    };
+    let jitter = std::collections::hash_map::RandomState::new().hash_one(()) >> 11;
+    base + base.mul_f64(jitter as f64 / (1u64 << 53) as f64 * 0.25)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_client::{ClientError, LimitCheckResult, MockGuardianClient};
+
+    fn denied(retry_after_seconds: u32) -> LimitCheckResult {
+        LimitCheckResult {
+            allowed: false,
+            retry_after_seconds,
+            remaining_tokens: 0,
+            limit: 0,
+        }
+    }
+
+    fn client(middleware: GuardianMiddleware) -> reqwest_middleware::ClientWithMiddleware {
+        reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(middleware)
+            .build()
+    }
+
+    /// Nothing listens on port 1, so a request the middleware lets
+    /// through fails to connect.
+    const DOWN: &str = "http://127.0.0.1:1/search";
+
+    fn throttled(result: reqwest_middleware::Result<Response>) -> Option<ThrottleError> {
+        match result {
+            Err(reqwest_middleware::Error::Middleware(e)) => e.downcast().ok(),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_waits_out_denials_then_sends() {
+        let mock = MockGuardianClient::new();
+        mock.push_response("host:127.0.0.1", Ok(denied(0)));
+        mock.push_response("host:127.0.0.1", Ok(denied(0)));
+        let http = client(GuardianMiddleware::new(mock.clone()));
+
+        let started = Instant::now();
+        let sent = http.get(DOWN).send().await;
+        assert!(matches!(sent, Err(reqwest_middleware::Error::Reqwest(_))));
+        assert!(started.elapsed() >= MIN_WAIT * 3);
+        assert_eq!(mock.calls_for("host:127.0.0.1").len(), 3);
+
+        // Past the maximum wait: not sent at all.
+        mock.deny("search", 60);
+        let http =
+            client(GuardianMiddleware::new(mock.clone()).with_key(|_| Some("search".to_string())));
+        let refused = throttled(http.get(DOWN).send().await);
+        assert!(matches!(
+            refused,
+            Some(ThrottleError::RateLimited { key, retry_after })
+                if key == "search" && retry_after == Duration::from_secs(60)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_key_and_cost_override_the_default() {
+        let mock = MockGuardianClient::new();
+        let http = client(GuardianMiddleware::new(mock.clone()));
+        let _ = http
+            .get(DOWN)
+            .with_extension(GuardianKey("github:search".to_string()))
+            .with_extension(GuardianCost(5))
+            .send()
+            .await;
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(
+            (calls[0].client_id.as_str(), calls[0].cost),
+            ("github:search", 5)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_limiter_errors_fail_open_unless_told_otherwise() {
+        let mock = MockGuardianClient::new();
+        mock.push_response("host:127.0.0.1", Err(ClientError::DeadlineExceeded));
+        let open = client(GuardianMiddleware::new(mock.clone()));
+        assert!(throttled(open.get(DOWN).send().await).is_none());
+
+        mock.push_response("host:127.0.0.1", Err(ClientError::DeadlineExceeded));
+        let closed = client(GuardianMiddleware::new(mock.clone()).with_fail_open(false));
+        assert!(matches!(
+            throttled(closed.get(DOWN).send().await),
+            Some(ThrottleError::Unavailable(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_embedded_limiter() {
+        let backend = guardian_core::MemoryBackend::new(guardian_core::TokenBucketConfig {
+            capacity: 1,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        });
+        let http = client(
+            GuardianMiddleware::embedded(RateLimiter::new(backend, true))
+                .with_max_wait(Duration::ZERO),
+        );
+        assert!(throttled(http.get(DOWN).send().await).is_none());
+        assert!(matches!(
+            throttled(http.get(DOWN).send().await),
+            Some(ThrottleError::RateLimited { .. })
+        ));
+    }
+}
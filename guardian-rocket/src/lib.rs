@@ -0,0 +1,394 @@
+//! [Rocket](https://rocket.rs) integration: a fairing that holds the
+//! limiter and adds rate-limit headers to responses, and a [`Limit`]
+//! request guard that checks a route's requests with Guardian.
+//!
+//! ```no_run
+//! use guardian_client::GuardianClient;
+//! use guardian_rocket::{Guardian, Key, Limit, RouteLimit};
+//! use rocket::{get, post, routes, Build, Rocket};
+//!
+//! /// Uploads count against "upload:ip:<addr>" buckets, 10 tokens at a time.
+//! struct Upload;
+//!
+//! impl RouteLimit for Upload {
+//!     const SCOPE: Option<&'static str> = Some("upload");
+//!     const COST: u32 = 10;
+//! }
+//!
+//! #[get("/")]
+//! fn index(_limit: Limit) -> &'static str {
+//!     "hello"
+//! }
+//!
+//! #[post("/upload")]
+//! fn upload(_limit: Limit<Upload>) -> &'static str {
+//!     "stored"
+//! }
+//!
+//! fn rocket(client: GuardianClient) -> Rocket<Build> {
+//!     rocket::build()
+//!         .attach(Guardian::new(client).with_key(Key::ClientIp))
+//!         .mount("/", routes![index, upload])
+//! }
+//! ```
+//!
+//! A denied request fails its guard with `429 Too Many Requests`, so the
+//! handler doesn't run; the response (from the 429 catcher) still gets
+//! `retry-after` and the `ratelimit-*` headers.
+
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use guardian_client::{GuardianApi, LimitCheckResult};
+use guardian_core::{LimitResult, RateLimiter, StorageBackend};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::{Build, Response, Rocket};
+use serde_json::Value;
+
+type KeyFn = dyn Fn(&Request<'_>) -> Option<String> + Send + Sync;
+
+/// What a request is limited by. Requests without one (a missing header,
+/// no bearer token) go through unchecked.
+#[derive(Clone)]
+pub enum Key {
+    /// The client's IP address, as `ip:<addr>`; Rocket takes it from the
+    /// configured `ip_header` (`X-Real-IP` by default) when present.
+    ClientIp,
+    /// A header's value, as `<name>:<value>`, such as an API key.
+    Header(String),
+    /// A claim of the bearer token in `Authorization`, as
+    /// `<claim>:<value>`. The token's signature isn't checked here, so
+    /// authenticate first.
+    JwtClaim(String),
+    /// Whatever `f` returns for the request.
+    Custom(Arc<KeyFn>),
+}
+
+impl Key {
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(&Request<'_>) -> Option<String> + Send + Sync + 'static,
+    {
+        Self::Custom(Arc::new(f))
+    }
+
+    fn of(&self, req: &Request<'_>) -> Option<String> {
+        match self {
+            Self::ClientIp => Some(format!("ip:{}", req.client_ip()?)),
+            Self::Header(name) => {
+                let value = req.headers().get_one(name)?;
+                Some(format!("{}:{}", name.to_ascii_lowercase(), value))
+            }
+            Self::JwtClaim(claim) => {
+                jwt_claim(req, claim).map(|value| format!("{}:{}", claim, value))
+            }
+            Self::Custom(f) => f(req),
+        }
+    }
+}
+
+/// `claim` from the payload of the request's bearer token.
+fn jwt_claim(req: &Request<'_>, claim: &str) -> Option<String> {
+    let token = req
+        .headers()
+        .get_one("Authorization")?
+        .strip_prefix("Bearer ")?;
+    let payload = URL_SAFE_NO_PAD.decode(token.split('.').nth(1)?).ok()?;
+    let claims: Value = serde_json::from_slice(&payload).ok()?;
+    match claims.get(claim)? {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Where decisions come from.
+#[async_trait]
+trait Limiter: Send + Sync {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String>;
+}
+
+struct Remote<A>(A);
+
+#[async_trait]
+impl<A: GuardianApi> Limiter for Remote<A> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        self.0
+            .check_limit_detailed(key, cost)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend + 'static> Limiter for RateLimiter<B> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        let (result, bucket) = self
+            .check_limit_detailed(key, cost.into())
+            .await
+            .map_err(|e| e.to_string())?;
+        let retry_after = match result {
+            LimitResult::Allowed => Duration::ZERO,
+            LimitResult::Denied { retry_after } => retry_after,
+        };
+        Ok(LimitCheckResult {
+            allowed: matches!(result, LimitResult::Allowed),
+            retry_after_seconds: retry_after.as_secs_f64().ceil() as u32,
+            remaining_tokens: bucket.map_or(0, |bucket| bucket.remaining),
+            limit: bucket.map_or(0, |bucket| bucket.capacity),
+        })
+    }
+}
+
+/// The fairing to attach; see the [crate docs](crate).
+#[derive(Clone)]
+pub struct Guardian {
+    limiter: Arc<dyn Limiter>,
+    key: Key,
+    fail_open: bool,
+}
+
+impl Guardian {
+    /// Decide through a Guardian service: a
+    /// [`GuardianClient`](guardian_client::GuardianClient), or anything
+    /// else implementing [`GuardianApi`].
+    pub fn new(api: impl GuardianApi + 'static) -> Self {
+        Self::with_limiter(Remote(api))
+    }
+
+    /// Decide in this process, with a `guardian-core` limiter, for apps
+    /// that run no Guardian service.
+    pub fn embedded<B: StorageBackend + 'static>(limiter: RateLimiter<B>) -> Self {
+        Self::with_limiter(limiter)
+    }
+
+    fn with_limiter(limiter: impl Limiter + 'static) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            key: Key::ClientIp,
+            fail_open: true,
+        }
+    }
+
+    /// What requests are limited by; [`Key::ClientIp`] unless set.
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Whether to let requests through when the limiter can't decide (the
+    /// default), or fail their guard with `503 Service Unavailable`.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+}
+
+/// The latest decision for a request, for its response headers.
+#[derive(Default)]
+struct Decision(Mutex<Option<LimitCheckResult>>);
+
+#[rocket::async_trait]
+impl Fairing for Guardian {
+    fn info(&self) -> Info {
+        Info {
+            name: "Guardian rate limiting",
+            kind: Kind::Ignite | Kind::Response,
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        Ok(rocket.manage(self.clone()))
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let decision = req.local_cache(Decision::default);
+        if let Some(decision) = decision.0.lock().unwrap().as_ref() {
+            for (name, value) in decision.to_headers() {
+                res.set_raw_header(name, value);
+            }
+        }
+    }
+}
+
+/// A route's limit: the scope its keys are prefixed with, giving it
+/// buckets (and service limit rules) of its own, and what each request
+/// costs.
+pub trait RouteLimit: Send + Sync + 'static {
+    const SCOPE: Option<&'static str> = None;
+    const COST: u32 = 1;
+}
+
+/// The defaults: no scope, 1 token a request.
+pub struct Unscoped;
+
+impl RouteLimit for Unscoped {}
+
+/// Why a [`Limit`] guard failed.
+#[derive(Debug)]
+pub enum LimitError {
+    /// The key is over its limit (`429`).
+    RateLimited { key: String, retry_after: Duration },
+    /// The limiter couldn't decide and the fairing fails closed (`503`).
+    Unavailable(String),
+    /// No [`Guardian`] fairing is attached (`500`).
+    NotAttached,
+}
+
+/// A request guard that passes requests within their limit under `S`.
+/// Guarding a route with two (say `Limit` and `Limit<Upload>`) checks its
+/// requests against both buckets.
+pub struct Limit<S: RouteLimit = Unscoped>(PhantomData<S>);
+
+#[rocket::async_trait]
+impl<'r, S: RouteLimit> FromRequest<'r> for Limit<S> {
+    type Error = LimitError;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(guardian) = req.rocket().state::<Guardian>() else {
+            return Outcome::Error((Status::InternalServerError, LimitError::NotAttached));
+        };
+        let Some(key) = guardian.key.of(req) else {
+            return Outcome::Success(Limit(PhantomData));
+        };
+        let key = match S::SCOPE {
+            Some(scope) => format!("{}:{}", scope, key),
+            None => key,
+        };
+        match guardian.limiter.check(&key, S::COST).await {
+            Ok(decision) => {
+                let allowed = decision.allowed;
+                let retry_after = Duration::from_secs(decision.retry_after_seconds.into());
+                *req.local_cache(Decision::default).0.lock().unwrap() = Some(decision);
+                if allowed {
+                    Outcome::Success(Limit(PhantomData))
+                } else {
+                    let error = LimitError::RateLimited { key, retry_after };
+                    Outcome::Error((Status::TooManyRequests, error))
+                }
+            }
+            Err(_) if guardian.fail_open => Outcome::Success(Limit(PhantomData)),
+            Err(e) => Outcome::Error((Status::ServiceUnavailable, LimitError::Unavailable(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_client::{ClientError, MockGuardianClient};
+    use rocket::local::asynchronous::Client;
+    use rocket::{get, routes};
+
+    struct Search;
+
+    impl RouteLimit for Search {
+        const SCOPE: Option<&'static str> = Some("search");
+        const COST: u32 = 5;
+    }
+
+    #[get("/")]
+    fn index(_limit: Limit) -> &'static str {
+        "hello"
+    }
+
+    #[get("/search")]
+    fn search(_limit: Limit<Search>) -> &'static str {
+        "results"
+    }
+
+    async fn client(guardian: Guardian) -> Client {
+        let rocket = rocket::build()
+            .attach(guardian)
+            .mount("/", routes![index, search]);
+        Client::untracked(rocket).await.unwrap()
+    }
+
+    const PEER: &str = "10.0.0.1:4000";
+
+    #[rocket::async_test]
+    async fn test_limits_per_route_and_sets_headers() {
+        let mock = MockGuardianClient::new();
+        let client = client(Guardian::new(mock.clone())).await;
+
+        let response = client
+            .get("/")
+            .remote(PEER.parse().unwrap())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("ratelimit-remaining"), Some("0"));
+
+        mock.deny("search:ip:10.0.0.1", 4);
+        let response = client
+            .get("/search")
+            .remote(PEER.parse().unwrap())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::TooManyRequests);
+        assert_eq!(response.headers().get_one("retry-after"), Some("4"));
+
+        let calls: Vec<_> = mock
+            .calls()
+            .into_iter()
+            .map(|call| (call.client_id, call.cost))
+            .collect();
+        assert_eq!(
+            calls,
+            [
+                ("ip:10.0.0.1".to_string(), 1),
+                ("search:ip:10.0.0.1".to_string(), 5),
+            ]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_header_and_jwt_claim_keys() {
+        let mock = MockGuardianClient::new();
+        let payload = URL_SAFE_NO_PAD.encode(r#"{"sub":"user-7"}"#);
+        let bearer = format!("Bearer e30.{}.sig", payload);
+        for key in [
+            Key::Header("X-Api-Key".to_string()),
+            Key::JwtClaim("sub".to_string()),
+        ] {
+            let client = client(Guardian::new(mock.clone()).with_key(key)).await;
+            client
+                .get("/")
+                .header(rocket::http::Header::new("X-Api-Key", "k1"))
+                .header(rocket::http::Header::new("Authorization", bearer.clone()))
+                .dispatch()
+                .await;
+            // Neither header: not checked.
+            client.get("/").dispatch().await;
+        }
+
+        let keys: Vec<_> = mock
+            .calls()
+            .into_iter()
+            .map(|call| call.client_id)
+            .collect();
+        assert_eq!(keys, ["x-api-key:k1", "sub:user-7"]);
+    }
+
+    #[rocket::async_test]
+    async fn test_fails_open_unless_told_otherwise() {
+        let mock = MockGuardianClient::new();
+        mock.push_response("ip:10.0.0.1", Err(ClientError::DeadlineExceeded));
+        mock.push_response("ip:10.0.0.1", Err(ClientError::DeadlineExceeded));
+        for (fail_open, status) in [(true, Status::Ok), (false, Status::ServiceUnavailable)] {
+            let client = client(Guardian::new(mock.clone()).with_fail_open(fail_open)).await;
+            let response = client
+                .get("/")
+                .remote(PEER.parse().unwrap())
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), status);
+        }
+    }
+}
@@ -1,4 +1,15 @@
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("../proto/guardian.proto")?;
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("envoy_descriptor.bin"))
+        .compile_protos(
+            &[
+                "../proto/envoy/service/ratelimit/v3/rls.proto",
+                "../proto/envoy/service/auth/v3/external_auth.proto",
+            ],
+            &["../proto"],
+        )?;
     Ok(())
 }
\ No newline at end of file
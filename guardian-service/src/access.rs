@@ -0,0 +1,136 @@
+// Allow and deny lists: admin-managed overrides consulted before any limit,
+// for blocking an abusive client or exempting a critical caller on the spot.
+// Entries are kept in this process and can expire on their own.
+
+use guardian_core::DecisionReason;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Always allowed, without spending tokens.
+    Allow,
+    /// Always denied.
+    Deny,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessEntry {
+    pub access: Access,
+    /// `None` keeps the entry until it is removed.
+    pub expires_at: Option<SystemTime>,
+    pub reason: String,
+}
+
+impl AccessEntry {
+    fn is_live(&self, now: SystemTime) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    /// Time left before the entry expires, if it ever does.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.expires_at
+            .map(|expires_at| expires_at.duration_since(SystemTime::now()).unwrap_or_default())
+    }
+
+    /// A deny entry that expires is a penalty box; any other entry is a
+    /// rule that matched.
+    pub fn decision_reason(&self) -> DecisionReason {
+        match (self.access, self.expires_at) {
+            (Access::Deny, Some(_)) => DecisionReason::PenaltyBox,
+            _ => DecisionReason::RuleMatched,
+        }
+    }
+}
+
+/// Entries keyed by backend key (namespace included).
+#[derive(Default)]
+pub struct AccessLists {
+    entries: RwLock<HashMap<String, AccessEntry>>,
+}
+
+impl AccessLists {
+    /// Add or replace the entry for `key`; a `ttl` makes it temporary.
+    pub fn set(&self, key: String, access: Access, ttl: Option<Duration>, reason: String) {
+        let now = SystemTime::now();
+        let mut entries = self.entries.write().unwrap();
+        entries.retain(|_, entry| entry.is_live(now));
+        entries.insert(
+            key,
+            AccessEntry {
+                access,
+                expires_at: ttl.map(|ttl| now + ttl),
+                reason,
+            },
+        );
+    }
+
+    /// Remove the entry for `key`, returning whether a live one existed.
+    pub fn remove(&self, key: &str) -> bool {
+        let removed = self.entries.write().unwrap().remove(key);
+        removed.is_some_and(|entry| entry.is_live(SystemTime::now()))
+    }
+
+    pub fn lookup(&self, key: &str) -> Option<AccessEntry> {
+        let entries = self.entries.read().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| entry.is_live(SystemTime::now()))
+            .cloned()
+    }
+
+    /// Live entries, sorted by key.
+    pub fn entries(&self) -> Vec<(String, AccessEntry)> {
+        let now = SystemTime::now();
+        let mut entries: Vec<_> = self
+            .entries
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| entry.is_live(now))
+            .map(|(key, entry)| (key.clone(), entry.clone()))
+            .collect();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_expire() {
+        let lists = AccessLists::default();
+        lists.set("abuser".to_string(), Access::Deny, None, "spam".to_string());
+        lists.set(
+            "batch".to_string(),
+            Access::Allow,
+            Some(Duration::ZERO),
+            String::new(),
+        );
+
+        assert_eq!(lists.lookup("abuser").unwrap().access, Access::Deny);
+        assert!(lists.lookup("batch").is_none());
+        assert_eq!(lists.entries().len(), 1);
+
+        assert!(!lists.remove("batch"));
+        assert!(lists.remove("abuser"));
+        assert!(lists.lookup("abuser").is_none());
+    }
+
+    #[test]
+    fn test_temporary_denials_are_a_penalty_box() {
+        let lists = AccessLists::default();
+        let hour = Some(Duration::from_secs(3600));
+        lists.set("abuser".to_string(), Access::Deny, hour, String::new());
+        lists.set("banned".to_string(), Access::Deny, None, String::new());
+        lists.set("batch".to_string(), Access::Allow, hour, String::new());
+
+        let reason = |key| lists.lookup(key).unwrap().decision_reason();
+        assert_eq!(reason("abuser"), DecisionReason::PenaltyBox);
+        assert_eq!(reason("banned"), DecisionReason::RuleMatched);
+        assert_eq!(reason("batch"), DecisionReason::RuleMatched);
+    }
+}
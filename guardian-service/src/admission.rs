@@ -0,0 +1,300 @@
+// Admission control: caps on the gRPC traffic the service itself accepts.
+// A request over any cap is answered with RESOURCE_EXHAUSTED before it is
+// decoded, so a spike costs the service almost nothing instead of piling
+// up work behind the backend exactly when callers need fast answers.
+
+use guardian_core::{TokenBucket, TokenBucketConfig};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::body::BoxBody;
+use tonic::codegen::{http, BoxFuture, Context, Poll, Service};
+use tonic::transport::server::{TcpConnectInfo, TlsConnectInfo};
+use tonic::Status;
+use tower::Layer;
+
+use crate::config::AdmissionConfig;
+use crate::metrics::Metrics;
+
+/// Load balancers must still see the instance as alive while it sheds.
+const EXEMPT_PREFIX: &str = "/grpc.health.v1.Health/";
+
+/// Seconds a shed caller is told to wait (as `retry-after`): caps are per
+/// second, or cleared as soon as calls in flight finish.
+const SHED_RETRY_AFTER_SECS: u64 = 1;
+
+pub struct Admission {
+    rate: Option<TokenBucket>,
+    in_flight: Option<Arc<Semaphore>>,
+    per_peer: Option<PeerSlots>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+/// RPCs in flight per peer, for peers with any.
+struct PeerSlots {
+    max: usize,
+    in_flight: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+/// Holds an admitted RPC's concurrency slots until dropped.
+#[must_use]
+pub struct Permit {
+    _global: Option<OwnedSemaphorePermit>,
+    _peer: Option<PeerPermit>,
+}
+
+struct PeerPermit {
+    peer: String,
+    in_flight: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Drop for PeerPermit {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&self.peer) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.peer);
+            }
+        }
+    }
+}
+
+impl Admission {
+    pub fn new(config: AdmissionConfig) -> Self {
+        Self {
+            rate: config.max_rps.map(|max_rps| {
+                TokenBucket::new(TokenBucketConfig {
+                    capacity: max_rps,
+                    refill_rate: max_rps,
+                    refill_interval: Duration::from_secs(1),
+                })
+            }),
+            in_flight: config
+                .max_concurrent
+                .map(|max| Arc::new(Semaphore::new(max))),
+            per_peer: config.max_concurrent_per_peer.map(|max| PeerSlots {
+                max,
+                in_flight: Arc::default(),
+            }),
+            metrics: None,
+        }
+    }
+
+    /// Count shed RPCs in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Admit one RPC from `peer` (unknown peers only face the global caps).
+    #[allow(clippy::result_large_err)]
+    pub fn admit(&self, peer: Option<&str>) -> Result<Permit, Status> {
+        let peer = match (&self.per_peer, peer) {
+            (Some(slots), Some(peer)) => {
+                let mut in_flight = slots.in_flight.lock().unwrap();
+                let count = in_flight.entry(peer.to_string()).or_default();
+                if *count >= slots.max {
+                    return Err(self.shed("peer", "too many concurrent requests from this client"));
+                }
+                *count += 1;
+                Some(PeerPermit {
+                    peer: peer.to_string(),
+                    in_flight: Arc::clone(&slots.in_flight),
+                })
+            }
+            _ => None,
+        };
+        let global = match &self.in_flight {
+            Some(in_flight) => match Arc::clone(in_flight).try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => return Err(self.shed("concurrency", "too many concurrent requests")),
+            },
+            None => None,
+        };
+        if let Some(rate) = &self.rate {
+            if rate.try_consume(1).is_err() {
+                return Err(self.shed("rate", "too many requests per second"));
+            }
+        }
+        Ok(Permit {
+            _global: global,
+            _peer: peer,
+        })
+    }
+
+    fn shed(&self, reason: &str, message: &str) -> Status {
+        if let Some(metrics) = &self.metrics {
+            metrics.record_shed(reason);
+        }
+        let mut status = Status::resource_exhausted(format!("server overloaded: {}", message));
+        status
+            .metadata_mut()
+            .insert("retry-after", SHED_RETRY_AFTER_SECS.into());
+        status
+    }
+}
+
+/// Puts an [`Admission`] in front of every service on a tonic server.
+#[derive(Clone)]
+pub struct AdmissionLayer(Arc<Admission>);
+
+impl AdmissionLayer {
+    pub fn new(admission: Arc<Admission>) -> Self {
+        Self(admission)
+    }
+}
+
+impl<S> Layer<S> for AdmissionLayer {
+    type Service = Admitted<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Admitted {
+            inner,
+            admission: Arc::clone(&self.0),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Admitted<S> {
+    inner: S,
+    admission: Arc<Admission>,
+}
+
+impl<S, B> Service<http::Request<B>> for Admitted<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        if request.uri().path().starts_with(EXEMPT_PREFIX) {
+            return Box::pin(self.inner.call(request));
+        }
+        match self.admission.admit(peer(&request).as_deref()) {
+            Ok(permit) => {
+                let response = self.inner.call(request);
+                Box::pin(async move {
+                    let response = response.await;
+                    drop(permit);
+                    response
+                })
+            }
+            Err(status) => Box::pin(std::future::ready(Ok(status.into_http()))),
+        }
+    }
+}
+
+/// Who sent `request`: the client's IP address, or its user ID on a Unix
+/// socket. Connections from one host share a budget however many it opens.
+fn peer<B>(request: &http::Request<B>) -> Option<String> {
+    let extensions = request.extensions();
+    let tcp = extensions.get::<TcpConnectInfo>().or_else(|| {
+        extensions
+            .get::<TlsConnectInfo<TcpConnectInfo>>()
+            .map(TlsConnectInfo::get_ref)
+    });
+    if let Some(addr) = tcp.and_then(TcpConnectInfo::remote_addr) {
+        return Some(addr.ip().to_string());
+    }
+    #[cfg(unix)]
+    if let Some(info) = extensions.get::<tonic::transport::server::UdsConnectInfo>() {
+        return info.peer_cred.map(|cred| format!("uid:{}", cred.uid()));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_sheds_over_the_concurrency_cap() {
+        let admission = Arc::new(Admission::new(AdmissionConfig {
+            max_concurrent: Some(1),
+            ..AdmissionConfig::default()
+        }));
+        let held = admission.admit(None).unwrap();
+
+        let layer = AdmissionLayer::new(Arc::clone(&admission));
+        let ok = || {
+            tower::service_fn(|_: http::Request<()>| async {
+                Ok::<_, std::convert::Infallible>(http::Response::new(tonic::body::empty_body()))
+            })
+        };
+        let call = |path: &str| http::Request::builder().uri(path).body(()).unwrap();
+
+        let shed = layer
+            .layer(ok())
+            .oneshot(call("/guardian.RateLimiter/CheckLimit"))
+            .await
+            .unwrap();
+        assert_eq!(shed.headers()["grpc-status"], "8");
+        let health = layer
+            .layer(ok())
+            .oneshot(call("/grpc.health.v1.Health/Check"))
+            .await
+            .unwrap();
+        assert!(health.headers().get("grpc-status").is_none());
+
+        drop(held);
+        let admitted = layer
+            .layer(ok())
+            .oneshot(call("/guardian.RateLimiter/CheckLimit"))
+            .await
+            .unwrap();
+        assert!(admitted.headers().get("grpc-status").is_none());
+    }
+
+    #[test]
+    fn test_sheds_over_the_rate_cap() {
+        let admission = Admission::new(AdmissionConfig {
+            max_rps: Some(3),
+            ..AdmissionConfig::default()
+        });
+        for _ in 0..3 {
+            assert!(admission.admit(None).is_ok());
+        }
+        let status = admission.admit(None).err().unwrap();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "1");
+    }
+
+    #[test]
+    fn test_one_peer_cannot_take_every_slot() {
+        let admission = Admission::new(AdmissionConfig {
+            max_concurrent_per_peer: Some(2),
+            ..AdmissionConfig::default()
+        });
+        let greedy: Vec<_> = (0..2)
+            .map(|_| admission.admit(Some("10.0.0.1")).unwrap())
+            .collect();
+        assert!(admission.admit(Some("10.0.0.1")).is_err());
+        let other = admission.admit(Some("10.0.0.2")).unwrap();
+
+        drop(greedy);
+        assert!(admission.admit(Some("10.0.0.1")).is_ok());
+        drop(other);
+        let slots = admission.per_peer.as_ref().unwrap();
+        assert!(slots.in_flight.lock().unwrap().is_empty());
+
+        // Every connection from a host counts against the same budget.
+        let mut request = http::Request::new(());
+        request.extensions_mut().insert(TcpConnectInfo {
+            local_addr: None,
+            remote_addr: Some("10.0.0.1:53124".parse().unwrap()),
+        });
+        assert_eq!(peer(&request).as_deref(), Some("10.0.0.1"));
+    }
+}
@@ -0,0 +1,376 @@
+// Audit trail: a structured record of every denial and reset (who, key,
+// cost, rule, node), written off the request path to stdout, a rotating
+// file or a Kafka topic for compliance and abuse investigations.
+
+use async_trait::async_trait;
+use guardian_core::{RateLimitError, RouterBackend};
+use serde::Serialize;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::auth::Principal;
+use crate::config::{default_node_id, AuditConfig, AuditSinkConfig, DEFAULT_RULE};
+
+/// Records queued for the writer before new ones are dropped.
+const QUEUE_DEPTH: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Denied,
+    Reset,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    /// Unix time in milliseconds.
+    pub timestamp_ms: u64,
+    pub action: AuditAction,
+    /// Authenticated caller, when auth is configured.
+    pub principal: Option<String>,
+    pub namespace: String,
+    pub client_id: String,
+    pub cost: u64,
+    /// Limit rule pattern, `tier:<name>`, or `deny-list`.
+    pub rule: String,
+    pub node: String,
+}
+
+/// Destination for audit records. Writes may buffer until `flush`.
+#[async_trait]
+pub trait AuditSink: Send {
+    async fn write(&mut self, record: &AuditRecord) -> io::Result<()>;
+    async fn flush(&mut self) -> io::Result<()>;
+}
+
+enum Command {
+    Record(AuditRecord),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Queues records for a background writer, so a slow sink never holds up
+/// a decision. When the queue is full, records are dropped and counted.
+pub struct Auditor {
+    tx: mpsc::Sender<Command>,
+    node: String,
+    rules: Option<Arc<RouterBackend>>,
+    dropped: AtomicU64,
+}
+
+impl Auditor {
+    pub async fn from_config(config: &AuditConfig) -> Result<Self, RateLimitError> {
+        let sink: Box<dyn AuditSink> = match &config.sink {
+            AuditSinkConfig::Stdout => Box::new(JsonLines(BufWriter::new(tokio::io::stdout()))),
+            AuditSinkConfig::File {
+                path,
+                max_bytes,
+                max_files,
+            } => Box::new(
+                RotatingFile::open(path.clone(), *max_bytes, *max_files)
+                    .await
+                    .map_err(|e| {
+                        RateLimitError::ConfigError(format!(
+                            "cannot open audit log {}: {}",
+                            path.display(),
+                            e
+                        ))
+                    })?,
+            ),
+            #[cfg(feature = "kafka")]
+            AuditSinkConfig::Kafka { brokers, topic } => {
+                Box::new(kafka::KafkaSink::new(brokers, topic.clone())?)
+            }
+            #[cfg(not(feature = "kafka"))]
+            AuditSinkConfig::Kafka { .. } => {
+                return Err(RateLimitError::ConfigError(
+                    "the Kafka audit sink needs guardian-service built with the `kafka` feature"
+                        .to_string(),
+                ))
+            }
+        };
+        let node = config.node_id.clone().unwrap_or_else(default_node_id);
+        Ok(Self::new(sink, node))
+    }
+
+    /// Start the writer task for `sink`.
+    pub fn new(sink: Box<dyn AuditSink>, node: String) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_DEPTH);
+        tokio::spawn(write_records(sink, rx));
+        Self {
+            tx,
+            node,
+            rules: None,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Name the limit rule in records using `router`'s patterns.
+    pub fn with_rules(mut self, router: Arc<RouterBackend>) -> Self {
+        self.rules = Some(router);
+        self
+    }
+
+    /// The rule pattern that limits `key`.
+    pub fn rule_for(&self, key: &str) -> String {
+        self.rules
+            .as_ref()
+            .and_then(|router| router.pattern_for(key))
+            .unwrap_or_else(|| DEFAULT_RULE.to_string())
+    }
+
+    pub fn record(
+        &self,
+        action: AuditAction,
+        principal: Option<&Principal>,
+        namespace: &str,
+        client_id: &str,
+        cost: u64,
+        rule: String,
+    ) {
+        let record = AuditRecord {
+            timestamp_ms: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            action,
+            principal: principal.map(|p| p.id.clone()),
+            namespace: namespace.to_string(),
+            client_id: client_id.to_string(),
+            cost,
+            rule,
+            node: self.node.clone(),
+        };
+        if self.tx.try_send(Command::Record(record)).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if dropped == 1 || dropped.is_multiple_of(1000) {
+                tracing::warn!(dropped, "audit queue full, dropping records");
+            }
+        }
+    }
+
+    /// Wait until everything queued so far has reached the sink.
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.tx.send(Command::Flush(done)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+/// Write whatever is queued, flushing the sink after each burst.
+async fn write_records(mut sink: Box<dyn AuditSink>, mut rx: mpsc::Receiver<Command>) {
+    while let Some(mut command) = rx.recv().await {
+        let mut waiting = Vec::new();
+        loop {
+            match command {
+                Command::Record(record) => {
+                    if let Err(e) = sink.write(&record).await {
+                        tracing::warn!(error = %e, "failed to write audit record");
+                    }
+                }
+                Command::Flush(done) => waiting.push(done),
+            }
+            match rx.try_recv() {
+                Ok(next) => command = next,
+                Err(_) => break,
+            }
+        }
+        if let Err(e) = sink.flush().await {
+            tracing::warn!(error = %e, "failed to flush audit sink");
+        }
+        for done in waiting {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn json_line(record: &AuditRecord) -> io::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// One JSON object per line on any writer (stdout in practice).
+pub struct JsonLines<W>(pub W);
+
+#[async_trait]
+impl<W: AsyncWrite + Unpin + Send> AuditSink for JsonLines<W> {
+    async fn write(&mut self, record: &AuditRecord) -> io::Result<()> {
+        self.0.write_all(&json_line(record)?).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.0.flush().await
+    }
+}
+
+/// JSON lines in a file that is renamed to `<path>.1` (shifting older
+/// files up, up to `max_files`) once it reaches `max_bytes`.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: BufWriter<tokio::fs::File>,
+    written: u64,
+}
+
+impl RotatingFile {
+    pub async fn open(path: PathBuf, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let file = Self::append(&path).await?;
+        let written = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file: BufWriter::new(file),
+            written,
+        })
+    }
+
+    async fn append(path: &PathBuf) -> io::Result<tokio::fs::File> {
+        tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        name.into()
+    }
+
+    async fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush().await?;
+        if self.max_files == 0 {
+            tokio::fs::remove_file(&self.path).await?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                match tokio::fs::rename(self.rotated(index), self.rotated(index + 1)).await {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            tokio::fs::rename(&self.path, self.rotated(1)).await?;
+        }
+        self.file = BufWriter::new(Self::append(&self.path).await?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuditSink for RotatingFile {
+    async fn write(&mut self, record: &AuditRecord) -> io::Result<()> {
+        let line = json_line(record)?;
+        if self.written > 0 && self.written + line.len() as u64 > self.max_bytes {
+            self.rotate().await?;
+        }
+        self.file.write_all(&line).await?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.file.flush().await
+    }
+}
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use super::{AuditRecord, AuditSink};
+    use async_trait::async_trait;
+    use guardian_core::RateLimitError;
+    use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+    use rdkafka::util::Timeout;
+    use rdkafka::ClientConfig;
+    use std::io;
+    use std::time::Duration;
+
+    /// How long a record may wait in the producer before the send fails.
+    const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// One message per record, keyed by client ID so a client's records
+    /// stay in order within a partition.
+    pub struct KafkaSink {
+        producer: FutureProducer,
+        topic: String,
+    }
+
+    impl KafkaSink {
+        pub fn new(brokers: &str, topic: String) -> Result<Self, RateLimitError> {
+            let producer = ClientConfig::new()
+                .set("bootstrap.servers", brokers)
+                .create()
+                .map_err(|e| RateLimitError::ConfigError(format!("Kafka audit sink: {}", e)))?;
+            Ok(Self { producer, topic })
+        }
+    }
+
+    #[async_trait]
+    impl AuditSink for KafkaSink {
+        async fn write(&mut self, record: &AuditRecord) -> io::Result<()> {
+            let payload = serde_json::to_vec(record)?;
+            let message = FutureRecord::to(&self.topic)
+                .key(&record.client_id)
+                .payload(&payload);
+            self.producer
+                .send(message, SEND_TIMEOUT)
+                .await
+                .map(|_| ())
+                .map_err(|(e, _)| io::Error::other(e))
+        }
+
+        async fn flush(&mut self) -> io::Result<()> {
+            self.producer
+                .flush(Timeout::After(SEND_TIMEOUT))
+                .map_err(io::Error::other)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(client_id: &str) -> AuditRecord {
+        AuditRecord {
+            // Same width as a current timestamp, so line lengths match.
+            timestamp_ms: 1_700_000_000_000,
+            action: AuditAction::Denied,
+            principal: None,
+            namespace: String::new(),
+            client_id: client_id.to_string(),
+            cost: 1,
+            rule: DEFAULT_RULE.to_string(),
+            node: "test".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_sink_rotates() {
+        let dir = std::env::temp_dir().join(format!("guardian-audit-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("audit.log");
+
+        let line_len = json_line(&record("user1")).unwrap().len() as u64;
+        let sink = RotatingFile::open(path.clone(), line_len * 2, 1).await.unwrap();
+        let auditor = Auditor::new(Box::new(sink), "test".to_string());
+        for client_id in ["user1", "user2", "user3"] {
+            auditor.record(AuditAction::Denied, None, "", client_id, 1, "default".to_string());
+        }
+        auditor.flush().await;
+
+        let current = tokio::fs::read_to_string(&path).await.unwrap();
+        let rotated = tokio::fs::read_to_string(dir.join("audit.log.1")).await.unwrap();
+        assert_eq!(rotated.lines().count(), 2);
+        assert!(current.contains("\"client_id\":\"user3\""));
+        assert!(current.contains("\"action\":\"denied\""));
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
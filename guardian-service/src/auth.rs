@@ -0,0 +1,276 @@
+// Caller authentication (API keys or JWTs in request metadata) and admin
+// RPC authorization.
+
+use guardian_core::RateLimitError;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::config::AuthConfig;
+
+/// Metadata key carrying an API key.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// The authenticated caller, stored in request extensions by
+/// `AuthInterceptor` for handlers to read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub id: String,
+    /// Tenant the caller belongs to, for scoping its keys.
+    pub tenant: Option<String>,
+    /// May call admin RPCs without an admin token.
+    pub admin: bool,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    tenant: Option<String>,
+    #[serde(default)]
+    admin: bool,
+}
+
+struct JwtVerifier {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+/// Rejects calls that carry neither a known API key (`x-api-key`) nor a
+/// valid bearer JWT (`authorization: Bearer ...`).
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    api_keys: Arc<HashMap<String, Principal>>,
+    jwt: Option<Arc<JwtVerifier>>,
+}
+
+impl AuthInterceptor {
+    pub fn from_config(config: &AuthConfig) -> Result<Self, RateLimitError> {
+        let api_keys = config
+            .api_keys
+            .iter()
+            .map(|(key, entry)| {
+                let principal = Principal {
+                    id: entry.principal.clone(),
+                    tenant: entry.tenant.clone(),
+                    admin: entry.admin,
+                };
+                (key.clone(), principal)
+            })
+            .collect();
+
+        let jwt = match &config.jwt {
+            Some(jwt) => {
+                let (key, algorithm) = match (&jwt.hmac_secret, &jwt.rsa_public_key_path) {
+                    (Some(secret), None) => (
+                        DecodingKey::from_secret(secret.as_bytes()),
+                        Algorithm::HS256,
+                    ),
+                    (None, Some(path)) => {
+                        let pem = std::fs::read(path).map_err(|e| {
+                            RateLimitError::ConfigError(format!(
+                                "failed to read {}: {}",
+                                path.display(),
+                                e
+                            ))
+                        })?;
+                        let key = DecodingKey::from_rsa_pem(&pem)
+                            .map_err(|e| RateLimitError::ConfigError(format!("JWT key: {}", e)))?;
+                        (key, Algorithm::RS256)
+                    }
+                    _ => {
+                        return Err(RateLimitError::ConfigError(
+                            "auth.jwt needs exactly one of hmac_secret or rsa_public_key_path"
+                                .to_string(),
+                        ))
+                    }
+                };
+
+                let mut validation = Validation::new(algorithm);
+                if let Some(issuer) = &jwt.issuer {
+                    validation.set_issuer(&[issuer]);
+                }
+                match &jwt.audience {
+                    Some(audience) => validation.set_audience(&[audience]),
+                    None => validation.validate_aud = false,
+                }
+                Some(Arc::new(JwtVerifier { key, validation }))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            api_keys: Arc::new(api_keys),
+            jwt,
+        })
+    }
+
+    #[allow(clippy::result_large_err)] // the interceptor has to return Status
+    fn authenticate<T>(&self, request: &Request<T>) -> Result<Principal, Status> {
+        let metadata = request.metadata();
+
+        if let Some(key) = metadata.get(API_KEY_HEADER) {
+            let key = key.to_str().unwrap_or_default();
+            return self
+                .api_keys
+                .get(key)
+                .cloned()
+                .ok_or_else(|| Status::unauthenticated("Unknown API key"));
+        }
+
+        let bearer = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match (bearer, &self.jwt) {
+            (Some(token), Some(jwt)) => {
+                let claims = decode::<Claims>(token, &jwt.key, &jwt.validation)
+                    .map_err(|e| Status::unauthenticated(format!("Invalid token: {}", e)))?
+                    .claims;
+                Ok(Principal {
+                    id: claims.sub,
+                    tenant: claims.tenant,
+                    admin: claims.admin,
+                })
+            }
+            (Some(_), None) => Err(Status::unauthenticated("Bearer tokens are not accepted")),
+            (None, _) => Err(Status::unauthenticated("Missing credentials")),
+        }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let principal = self.authenticate(&request)?;
+        request.extensions_mut().insert(principal);
+        Ok(request)
+    }
+}
+
+/// Decides whether a caller's admin token is accepted. Implement this to
+/// check tokens against a secret store or identity provider instead of a
+/// single shared secret.
+#[tonic::async_trait]
+pub trait AdminTokenValidator: Send + Sync {
+    async fn is_valid(&self, token: &str) -> bool;
+}
+
+/// One shared secret (`global.admin_token` in the config file).
+pub struct StaticToken {
+    secret: String,
+}
+
+impl StaticToken {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl AdminTokenValidator for StaticToken {
+    async fn is_valid(&self, token: &str) -> bool {
+        let expected = self.secret.as_bytes();
+        let given = token.as_bytes();
+        // Compare every byte so the time taken doesn't reveal how much of
+        // the secret a guess got right.
+        !expected.is_empty()
+            && expected.len() == given.len()
+            && expected
+                .iter()
+                .zip(given)
+                .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ApiKeyConfig, JwtConfig};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    fn interceptor() -> AuthInterceptor {
+        let mut config = AuthConfig::default();
+        config.api_keys.insert(
+            "key-a".to_string(),
+            ApiKeyConfig {
+                principal: "service-a".to_string(),
+                tenant: Some("acme".to_string()),
+                admin: false,
+            },
+        );
+        config.jwt = Some(JwtConfig {
+            hmac_secret: Some("jwt-secret".to_string()),
+            rsa_public_key_path: None,
+            issuer: Some("guardian-tests".to_string()),
+            audience: None,
+        });
+        AuthInterceptor::from_config(&config).unwrap()
+    }
+
+    fn with_metadata(header: &'static str, value: &str) -> Request<()> {
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert(header, value.parse().unwrap());
+        request
+    }
+
+    #[test]
+    fn test_api_key_authentication() {
+        let mut auth = interceptor();
+
+        let authed = auth.call(with_metadata(API_KEY_HEADER, "key-a")).unwrap();
+        let principal = authed.extensions().get::<Principal>().unwrap();
+        assert_eq!(principal.id, "service-a");
+        assert_eq!(principal.tenant.as_deref(), Some("acme"));
+
+        let unknown = auth
+            .call(with_metadata(API_KEY_HEADER, "key-b"))
+            .unwrap_err();
+        assert_eq!(unknown.code(), tonic::Code::Unauthenticated);
+        assert!(auth.call(Request::new(())).is_err());
+    }
+
+    #[test]
+    fn test_jwt_authentication() {
+        let mut auth = interceptor();
+        let token = |secret: &str| {
+            let claims = serde_json::json!({
+                "sub": "ops-bot",
+                "iss": "guardian-tests",
+                "admin": true,
+                "exp": 4_102_444_800u64,
+            });
+            encode(
+                &Header::default(),
+                &claims,
+                &EncodingKey::from_secret(secret.as_bytes()),
+            )
+            .unwrap()
+        };
+
+        let bearer = format!("Bearer {}", token("jwt-secret"));
+        let authed = auth.call(with_metadata("authorization", &bearer)).unwrap();
+        let principal = authed.extensions().get::<Principal>().unwrap();
+        assert_eq!(principal.id, "ops-bot");
+        assert!(principal.admin);
+
+        let forged = format!("Bearer {}", token("wrong-secret"));
+        assert!(auth.call(with_metadata("authorization", &forged)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_static_token() {
+        let validator = StaticToken::new("s3cret");
+        assert!(validator.is_valid("s3cret").await);
+        assert!(!validator.is_valid("s3cre").await);
+        assert!(!validator.is_valid("S3cret").await);
+        assert!(!StaticToken::new("").is_valid("").await);
+    }
+}
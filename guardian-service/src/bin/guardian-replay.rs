@@ -0,0 +1,100 @@
+// guardian-replay: run recorded traffic (an access log or the denial audit
+// log, as JSON lines) through a candidate config and report, per key, how
+// many requests it would have allowed and denied.
+//
+//     guardian-replay --config candidate.yaml audit.log [more.log ...]
+//
+// Logs from several instances may be given together; their entries are
+// merged by timestamp. With no log files, entries are read from stdin.
+
+use anyhow::{bail, Context};
+use guardian_service::config::GuardianConfig;
+use guardian_service::replay::{self, Replay};
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: guardian-replay --config <file> [log ...]";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("guardian-replay: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let mut config = None;
+    let mut logs = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.split_once('=') {
+            Some(("--config", path)) => config = Some(PathBuf::from(path)),
+            _ if arg == "--config" => config = args.next().map(PathBuf::from),
+            _ if arg == "--help" || arg == "-h" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with("--") => bail!("unknown option {}\n{}", arg, USAGE),
+            _ => logs.push(PathBuf::from(arg)),
+        }
+    }
+    let Some(config) = config else {
+        bail!("{}", USAGE);
+    };
+    let config = GuardianConfig::load(&config)?;
+
+    let (mut entries, mut unreadable) = (Vec::new(), 0);
+    if logs.is_empty() {
+        (entries, unreadable) = replay::read(std::io::stdin().lock())?;
+    }
+    for path in &logs {
+        let file =
+            std::fs::File::open(path).with_context(|| format!("cannot open {}", path.display()))?;
+        let (read, skipped) = replay::read(BufReader::new(file))
+            .with_context(|| format!("cannot read {}", path.display()))?;
+        entries.extend(read);
+        unreadable += skipped;
+    }
+    if unreadable > 0 {
+        eprintln!("guardian-replay: skipped {} unreadable lines", unreadable);
+    }
+
+    let mut replay = Replay::new(&config);
+    replay.run(entries)?;
+    let report = replay.report();
+    let width = report
+        .iter()
+        .map(|(key, _)| key.len())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    let rule_width = report
+        .iter()
+        .map(|(_, key)| key.rule.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!(
+        "{:<width$}  {:<rule_width$}  {:>10}  {:>10}  {:>10}  {:>10}",
+        "KEY", "RULE", "ALLOWED", "DENIED", "SHADOW", "WAS_DENIED"
+    );
+    let mut totals = (0, 0, 0, 0);
+    for (key, stats) in report {
+        println!(
+            "{:<width$}  {:<rule_width$}  {:>10}  {:>10}  {:>10}  {:>10}",
+            key, stats.rule, stats.allowed, stats.denied, stats.shadow_denied, stats.denied_before
+        );
+        totals.0 += stats.allowed;
+        totals.1 += stats.denied;
+        totals.2 += stats.shadow_denied;
+        totals.3 += stats.denied_before;
+    }
+    println!(
+        "{:<width$}  {:<rule_width$}  {:>10}  {:>10}  {:>10}  {:>10}",
+        "TOTAL", "", totals.0, totals.1, totals.2, totals.3
+    );
+    Ok(())
+}
@@ -0,0 +1,183 @@
+// `--check-config`: validate the configuration without serving and print
+// one line per finding. Lines are sorted, so reports from two revisions of
+// a config diff cleanly in CI.
+
+use guardian_core::{RateLimitError, StorageBackend};
+use std::fmt;
+
+use crate::config::{sorted, Args, GuardianConfig, LimitConfig, DEFAULT_RULE};
+use crate::namespace;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Ok,
+    Warn,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Level::Ok => "ok",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Finding {
+    pub text: String,
+    pub level: Level,
+}
+
+impl Finding {
+    fn new(level: Level, text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            level,
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<5} {}", self.level, self.text)
+    }
+}
+
+/// Check the config named by `args`, print the report and return the
+/// process exit status: 1 if anything would stop the service, else 0.
+pub async fn run(args: Args) -> i32 {
+    let check_backend = args.check_backend;
+    let findings = match GuardianConfig::load_unchecked(args) {
+        Ok(config) => check(&config, check_backend).await,
+        Err(e) => vec![Finding::new(Level::Error, format!("config: {}", message(&e)))],
+    };
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    let count = |level| findings.iter().filter(|f| f.level == level).count();
+    println!("{} error(s), {} warning(s)", count(Level::Error), count(Level::Warn));
+    i32::from(count(Level::Error) > 0)
+}
+
+/// Every finding for `config`, sorted.
+pub async fn check(config: &GuardianConfig, check_backend: bool) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = config
+        .problems()
+        .iter()
+        .map(|problem| Finding::new(Level::Error, message(problem)))
+        .collect();
+
+    for (pattern, limit) in sorted(&config.limits) {
+        if !pattern.is_empty() && limit.validate(pattern).is_ok() {
+            findings.push(rule("limit", pattern, limit));
+        }
+        if pattern == "*" {
+            findings.push(Finding::new(
+                Level::Warn,
+                format!("limit '*': matches every key, so '{}' never applies", DEFAULT_RULE),
+            ));
+        }
+    }
+    for (name, limit) in sorted(&config.namespaces) {
+        let pattern = namespace::default_pattern(name);
+        if config.limits.contains_key(&pattern) {
+            findings.push(Finding::new(
+                Level::Warn,
+                format!("namespace '{}': limit '{}' overrides its default", name, pattern),
+            ));
+        } else if limit.validate(&pattern).is_ok() {
+            findings.push(rule("namespace", name, limit));
+        }
+    }
+    for (tier, limit) in sorted(&config.tiers) {
+        if limit.validate(tier).is_ok() {
+            findings.push(rule("tier", tier, limit));
+        }
+    }
+
+    findings.push(backend(config, check_backend).await);
+    findings.sort();
+    findings
+}
+
+fn rule(kind: &str, name: &str, limit: &LimitConfig) -> Finding {
+    Finding::new(
+        Level::Ok,
+        format!(
+            "{} '{}': capacity={} refill_rate={}/{}s{}",
+            kind,
+            name,
+            limit.capacity,
+            limit.refill_rate,
+            limit.refill_interval_secs,
+            if limit.shadow { " (shadow)" } else { "" }
+        ),
+    )
+}
+
+async fn backend(config: &GuardianConfig, connect: bool) -> Finding {
+    let kind = config.backends.primary.kind();
+    if !connect {
+        return Finding::new(
+            Level::Ok,
+            format!("backend: {} (not contacted; add --check-backend)", kind),
+        );
+    }
+    let reached = match config.build_backend().await {
+        Ok(router) => router.health_check().await,
+        Err(e) => Err(e),
+    };
+    match reached {
+        Ok(()) => Finding::new(Level::Ok, format!("backend: {} reachable", kind)),
+        Err(e) => Finding::new(
+            Level::Error,
+            format!("backend: {} unreachable: {}", kind, message(&e)),
+        ),
+    }
+}
+
+fn message(error: &RateLimitError) -> String {
+    match error {
+        RateLimitError::ConfigError(message) => message.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reports_every_problem_in_order() {
+        let mut config = GuardianConfig::default();
+        let limit = |capacity| LimitConfig {
+            capacity,
+            refill_rate: 1,
+            ..LimitConfig::default()
+        };
+        config.limits.insert("zeta:*".to_string(), limit(0));
+        config.limits.insert("api:*".to_string(), limit(10));
+        config.limits.insert("*".to_string(), limit(5));
+        config.client_tiers.insert("acme".to_string(), "gold".to_string());
+
+        let report: Vec<String> = check(&config, false)
+            .await
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            report,
+            vec![
+                "ok    backend: memory (not contacted; add --check-backend)",
+                "error client 'acme' is assigned to unknown tier 'gold'",
+                "ok    limit '*': capacity=5 refill_rate=1/1s",
+                "warn  limit '*': matches every key, so 'default' never applies",
+                "ok    limit 'api:*': capacity=10 refill_rate=1/1s",
+                "error limit 'zeta:*': capacity and refill_rate must be positive",
+            ]
+        );
+    }
+}
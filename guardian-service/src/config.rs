@@ -0,0 +1,2010 @@
+// Service configuration: a YAML or TOML file selecting the storage backend,
+// the fail-open policy and per-key limit rules, with GUARDIAN__* environment
+// overrides on top.
+
+use ::config::{Config, Environment, File};
+use guardian_core::{
+    AffinityBackend, BatchingBackend, CacheStats, ChaosBackend, ChaosConfig, EstimateConfig,
+    EstimatingBackend, FailoverBackend, FailoverStats, HashRing, LeaseConfig, LeasingBackend,
+    MemoryBackend, RateLimitError, RegionShares, RouterBackend, SliceConfig, SlicedBackend,
+    StorageBackend, TokenBucketConfig,
+};
+use guardian_gossip::{GossipBackend, GossipConfig, GossipNode};
+use guardian_raft::{RaftBackend, RaftConfig, RaftNode};
+use guardian_redis::{CachedRedisBackend, RedisBackend, RedisClusterBackend};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tonic::codec::CompressionEncoding;
+
+use crate::namespace;
+
+/// Environment variable naming the config file when `--config` isn't given.
+pub const CONFIG_ENV: &str = "GUARDIAN_CONFIG";
+
+/// Environment fallbacks for `--backend`, `--redis-url` and `--redis-nodes`.
+pub const BACKEND_ENV: &str = "GUARDIAN_BACKEND";
+pub const REDIS_URL_ENV: &str = "GUARDIAN_REDIS_URL";
+pub const REDIS_NODES_ENV: &str = "GUARDIAN_REDIS_NODES";
+
+/// Redis address used when `--backend redis` is chosen without a URL.
+const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1:6379";
+
+/// This instance's name when the config doesn't give one: `$HOSTNAME`.
+pub fn default_node_id() -> String {
+    std::env::var("HOSTNAME").unwrap_or_else(|_| "guardian".to_string())
+}
+
+/// Name of the limit rule applied to keys no other rule matches.
+pub const DEFAULT_RULE: &str = "default";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct GuardianConfig {
+    pub global: GlobalConfig,
+    pub backends: BackendsConfig,
+    /// Limit rules keyed by key pattern (`*` wildcards, as in
+    /// `RouterBackend`), plus the special `default` rule.
+    pub limits: HashMap<String, LimitConfig>,
+    /// Named client classes (e.g. `free`, `premium`) with their own limits.
+    pub tiers: HashMap<String, LimitConfig>,
+    /// Tier for known client IDs; requests may also name a tier directly.
+    pub client_tiers: HashMap<String, String>,
+    /// Default limit for keys in each namespace. Namespaces not listed here
+    /// fall back to the `default` rule.
+    pub namespaces: HashMap<String, LimitConfig>,
+    /// Require callers to authenticate; unset means anyone may call.
+    pub auth: Option<AuthConfig>,
+    /// Record every denial and reset; unset keeps no audit trail.
+    pub audit: Option<AuditConfig>,
+    pub usage_report: UsageReportConfig,
+    /// Alert endpoints for keys denied too often; unset sends nothing.
+    pub webhooks: Option<WebhookConfig>,
+    /// Hot keys to prepare before serving; unset starts cold.
+    pub warm_start: Option<WarmStartConfig>,
+    /// Keep full traces of some decisions for GetDecisionTraces; unset
+    /// records nothing.
+    pub decision_recorder: Option<DecisionRecorderConfig>,
+    /// Elect one instance to run cluster-wide maintenance jobs; unset runs
+    /// none of them.
+    pub leader: Option<LeaderConfig>,
+    /// Relay admin actions straight to the other instances; unset leaves
+    /// them to the shared store.
+    pub peers: Option<PeersConfig>,
+}
+
+/// The other instances, which resets, access list changes and limit rule
+/// changes made here are relayed to over gRPC.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PeersConfig {
+    /// Their gRPC addresses as `host:port`. A name resolving to several
+    /// addresses names them all, and may include this instance, which then
+    /// applies its own actions twice, to no effect.
+    pub addresses: Vec<String>,
+    /// This instance's name in relayed requests. Defaults to `$HOSTNAME`.
+    #[serde(default)]
+    pub node_id: Option<String>,
+    /// Sent as `x-api-key` to peers that require authentication; it should
+    /// belong to an admin principal without a tenant. Without one, relayed
+    /// actions carry `global.admin_token`.
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default = "default_peer_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_peer_timeout_ms() -> u64 {
+    500
+}
+
+/// Leader election over a lock in the primary store, and the jobs only
+/// the leader runs.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LeaderConfig {
+    /// This instance's name as the lock's holder; must differ between
+    /// instances. Defaults to `$HOSTNAME`.
+    #[serde(default)]
+    pub node_id: Option<String>,
+    #[serde(default = "default_leader_lock")]
+    pub lock: String,
+    /// The leader renews the lock every third of this; another instance
+    /// takes over within this long of the leader going away.
+    #[serde(default = "default_leader_ttl_ms")]
+    pub ttl_ms: u64,
+    /// How often lapsed leases are reclaimed from every key, when
+    /// `backends.lease` is set; 0 never does.
+    #[serde(default = "default_reclaim_leases_secs")]
+    pub reclaim_leases_secs: u64,
+}
+
+fn default_leader_lock() -> String {
+    "guardian:leader".to_string()
+}
+
+fn default_leader_ttl_ms() -> u64 {
+    15_000
+}
+
+fn default_reclaim_leases_secs() -> u64 {
+    60
+}
+
+/// Which decisions the debug recorder traces: an evenly spaced fraction of
+/// all checks, plus every check of the watched keys.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DecisionRecorderConfig {
+    /// Fraction of checks to record; 0 records only watched keys.
+    #[serde(default)]
+    pub sample_ratio: f64,
+    /// Key patterns (`*` wildcards) whose every check is recorded.
+    #[serde(default)]
+    pub watch_keys: Vec<String>,
+    /// Traces kept, the oldest making way for new ones.
+    #[serde(default = "default_recorder_capacity")]
+    pub capacity: usize,
+}
+
+fn default_recorder_capacity() -> usize {
+    1000
+}
+
+/// Keys warmed up at startup (see `StorageBackend::warm_up`), so a fresh
+/// instance doesn't take its first hit on every hot key at once.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WarmStartConfig {
+    /// File with one key per line; blank lines and `#` comments are skipped.
+    #[serde(default)]
+    pub keys_file: Option<PathBuf>,
+    /// Patterns (`*` wildcards) whose stored keys are looked up in the
+    /// backend, e.g. a Redis SCAN.
+    #[serde(default)]
+    pub scan: Vec<String>,
+    #[serde(default = "default_warm_max_keys")]
+    pub max_keys: usize,
+    /// Serving starts after this long even if warm-up hasn't finished.
+    #[serde(default = "default_warm_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_warm_max_keys() -> usize {
+    10_000
+}
+
+fn default_warm_timeout_secs() -> u64 {
+    30
+}
+
+/// POSTs a JSON event to every URL when a key reaches `denial_threshold`
+/// denials within `window_secs`, or is put on the deny list.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    #[serde(default = "default_denial_threshold")]
+    pub denial_threshold: u64,
+    #[serde(default = "default_webhook_window_secs")]
+    pub window_secs: u64,
+    /// Further attempts after a failed delivery, with doubling delays
+    /// starting at `retry_backoff_ms`.
+    #[serde(default = "default_webhook_retries")]
+    pub max_retries: u32,
+    #[serde(default = "default_webhook_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    #[serde(default = "default_webhook_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_denial_threshold() -> u64 {
+    100
+}
+
+fn default_webhook_window_secs() -> u64 {
+    60
+}
+
+fn default_webhook_retries() -> u32 {
+    3
+}
+
+fn default_webhook_backoff_ms() -> u64 {
+    500
+}
+
+fn default_webhook_timeout_ms() -> u64 {
+    5000
+}
+
+/// Time windows GetUsageReport totals over.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct UsageReportConfig {
+    /// Windows reported by default; the longest also bounds what a
+    /// request may ask for.
+    pub windows_secs: Vec<u64>,
+    /// Granularity of the counters; windows are rounded up to it.
+    pub resolution_secs: u64,
+    /// Most keys counted at once; the least recently seen are forgotten
+    /// to make room.
+    pub max_keys: usize,
+}
+
+impl Default for UsageReportConfig {
+    fn default() -> Self {
+        Self {
+            windows_secs: vec![300, 3600],
+            resolution_secs: 60,
+            max_keys: 100_000,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditConfig {
+    pub sink: AuditSinkConfig,
+    /// Node name written into each record; defaults to `$HOSTNAME`.
+    #[serde(default)]
+    pub node_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditSinkConfig {
+    /// JSON lines on standard output.
+    Stdout,
+    /// JSON lines in `path`, rotated to `path.1`, `path.2`, ... once the
+    /// file would pass `max_bytes`.
+    File {
+        path: PathBuf,
+        #[serde(default = "default_audit_max_bytes")]
+        max_bytes: u64,
+        #[serde(default = "default_audit_max_files")]
+        max_files: usize,
+    },
+    /// One JSON message per record; needs the `kafka` build feature.
+    Kafka { brokers: String, topic: String },
+}
+
+fn default_audit_max_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_audit_max_files() -> usize {
+    5
+}
+
+/// Accepted caller credentials.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// API keys (sent as `x-api-key` metadata) and who they belong to.
+    pub api_keys: HashMap<String, ApiKeyConfig>,
+    pub jwt: Option<JwtConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiKeyConfig {
+    pub principal: String,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Allows admin RPCs without an admin token.
+    #[serde(default)]
+    pub admin: bool,
+}
+
+/// Bearer JWTs, signed with either a shared HMAC secret (HS256) or an RSA
+/// key (RS256). The `sub` claim names the caller; optional `tenant` and
+/// `admin` claims mirror `ApiKeyConfig`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct JwtConfig {
+    pub hmac_secret: Option<String>,
+    pub rsa_public_key_path: Option<PathBuf>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GlobalConfig {
+    pub listen_addr: String,
+    pub fail_open: bool,
+    /// Secret required by admin RPCs; they are refused while unset.
+    pub admin_token: Option<String>,
+    /// Minimum level, or any `RUST_LOG`-style filter (`RUST_LOG` itself
+    /// takes precedence when set).
+    pub log_level: String,
+    pub log_format: LogFormat,
+    /// Port for the Prometheus `/metrics` endpoint, on the same address as
+    /// `listen_addr`; 0 turns it off.
+    pub metrics_port: u16,
+    /// Also push metrics to a statsd or DogStatsD agent.
+    pub statsd: Option<StatsdConfig>,
+    /// Port for the JSON/HTTP gateway, on the same address as
+    /// `listen_addr`; unset leaves it off.
+    pub http_port: Option<u16>,
+    /// Serve gRPC over TLS instead of plaintext.
+    pub tls: Option<TlsConfig>,
+    /// Also serve gRPC (without TLS) on this Unix domain socket, for
+    /// sidecar clients on the same host.
+    pub unix_socket: Option<PathBuf>,
+    /// Serve gRPC server reflection so tools like grpcurl work without the
+    /// proto files.
+    pub reflection: bool,
+    /// How long in-flight RPCs get to finish after SIGTERM.
+    pub shutdown_grace_secs: u64,
+    /// Load shedding for the service itself.
+    pub admission: AdmissionConfig,
+    /// Compression, message size and keepalive settings for gRPC.
+    pub grpc: GrpcConfig,
+    /// Export OpenTelemetry traces of RPCs and backend calls.
+    pub tracing: Option<TracingConfig>,
+    /// Also accept grpc-web, over HTTP/1.1 as well as HTTP/2, so browsers
+    /// and edge workers can call the service directly.
+    pub grpc_web: Option<GrpcWebConfig>,
+    /// Also serve Envoy's external authorization API, for meshes that
+    /// allow `ext_authz` filters but not `rate_limit` ones.
+    pub ext_authz: Option<ExtAuthzConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per event, for log pipelines.
+    Json,
+}
+
+/// OTLP/gRPC trace export.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TracingConfig {
+    pub otlp_endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Fraction of new traces to record. Requests arriving with a trace
+    /// context follow the caller's sampling decision instead.
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+fn default_service_name() -> String {
+    "guardian-service".to_string()
+}
+
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+/// Metrics pushed over UDP, for fleets without a Prometheus scrape path.
+/// Counters are sent as the increase since the last push, gauges as they
+/// stand; histograms as their `_count` and `_sum` counters.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StatsdConfig {
+    /// `host:port` of the agent, e.g. `127.0.0.1:8125`.
+    pub address: String,
+    #[serde(default)]
+    pub flavor: StatsdFlavor,
+    /// Put in front of every metric name, e.g. `myteam.`.
+    #[serde(default)]
+    pub prefix: String,
+    /// Tags on every metric, e.g. `env: prod`. DogStatsD only.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    #[serde(default = "default_statsd_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_statsd_interval_secs() -> u64 {
+    10
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsdFlavor {
+    /// Labels become tags: `name:1|c|#rule:api_*`.
+    #[default]
+    DogStatsd,
+    /// Plain statsd has no tags, so label values are appended to the
+    /// name: `name.api_*:1|c`.
+    Statsd,
+}
+
+/// grpc-web callers. Browsers are sent CORS headers for them, allowing the
+/// `x-api-key`, `authorization` and trace context headers.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct GrpcWebConfig {
+    /// Origins pages may call from, like `https://dashboard.example.com`;
+    /// empty allows any.
+    pub allowed_origins: Vec<String>,
+}
+
+/// How `ext_authz` checks become rate limit descriptors. Each descriptor
+/// is checked exactly as the rate limit service checks Envoy's, as the key
+/// `domain:key=value:...`, and the request is denied if any is over its
+/// limit.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ExtAuthzConfig {
+    /// Domain of the descriptors, unless a route's `context_extensions`
+    /// name another as `domain`.
+    pub domain: String,
+    /// Descriptors, each a list of entries built from the request, as in
+    /// the actions of Envoy's `rate_limits`. A descriptor missing an entry
+    /// (the request lacks its header) is skipped.
+    pub descriptors: Vec<Vec<DescriptorAction>>,
+}
+
+/// One descriptor entry, named like Envoy's rate limit actions.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DescriptorAction {
+    /// `remote_address=<address>`, the downstream client's address.
+    RemoteAddress,
+    /// `<descriptor_key>=<value>` of a request header; `:path`, `:method`
+    /// and `:authority` work too.
+    RequestHeaders {
+        header_name: String,
+        descriptor_key: String,
+    },
+    /// `<descriptor_key>=<descriptor_value>`, the same for every request.
+    GenericKey {
+        descriptor_value: String,
+        #[serde(default = "default_generic_key")]
+        descriptor_key: String,
+    },
+}
+
+fn default_generic_key() -> String {
+    "generic_key".to_string()
+}
+
+/// Caps on the gRPC traffic the service takes on. Past them, RPCs are
+/// refused with RESOURCE_EXHAUSTED before any work is done. Health checks
+/// are always admitted.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct AdmissionConfig {
+    /// RPCs accepted per second, with bursts of up to a second's worth.
+    pub max_rps: Option<u64>,
+    /// RPCs handled at once.
+    pub max_concurrent: Option<usize>,
+    /// RPCs handled at once for any one client host (by IP address, or by
+    /// user on the Unix socket), so a single caller can't starve the rest.
+    pub max_concurrent_per_peer: Option<usize>,
+}
+
+/// gRPC transport options. Unset fields keep tonic's defaults: a 4 MiB
+/// limit on received messages, none on sent ones, and no keepalive pings.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct GrpcConfig {
+    /// Encodings accepted on requests and used for responses when the
+    /// client accepts them.
+    pub compression: Vec<Compression>,
+    pub max_recv_message_bytes: Option<usize>,
+    pub max_send_message_bytes: Option<usize>,
+    /// Ping idle HTTP/2 connections this often, so dead peers and
+    /// middleboxes dropping long-lived streams are noticed.
+    pub keepalive_interval_secs: Option<u64>,
+    /// Close a connection whose ping goes unanswered this long.
+    pub keepalive_timeout_secs: Option<u64>,
+    /// TCP keepalive probe interval for accepted connections.
+    pub tcp_keepalive_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl From<Compression> for CompressionEncoding {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Gzip => CompressionEncoding::Gzip,
+            Compression::Zstd => CompressionEncoding::Zstd,
+        }
+    }
+}
+
+/// Server certificate and key; adding `client_ca_path` turns on mutual TLS.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// CA bundle that client certificates must chain to.
+    #[serde(default)]
+    pub client_ca_path: Option<PathBuf>,
+    /// Also accept clients without a certificate; those presenting one are
+    /// still verified.
+    #[serde(default)]
+    pub client_auth_optional: bool,
+    /// How often to check the certificate and key for changes; 0 leaves
+    /// reloading to SIGHUP alone.
+    #[serde(default = "default_tls_reload_interval_secs")]
+    pub reload_interval_secs: u64,
+}
+
+fn default_tls_reload_interval_secs() -> u64 {
+    30
+}
+
+impl Default for GlobalConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: "0.0.0.0:50051".to_string(),
+            fail_open: true,
+            admin_token: None,
+            log_level: "info".to_string(),
+            log_format: LogFormat::default(),
+            metrics_port: 9090,
+            statsd: None,
+            http_port: None,
+            tls: None,
+            unix_socket: None,
+            reflection: true,
+            shutdown_grace_secs: 30,
+            admission: AdmissionConfig::default(),
+            grpc: GrpcConfig::default(),
+            tracing: None,
+            grpc_web: None,
+            ext_authz: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackendsConfig {
+    pub primary: BackendType,
+    /// Local storage that answers while the primary is unreachable. Only
+    /// `Memory` is supported.
+    #[serde(default)]
+    pub fallback: Option<BackendType>,
+    /// Share of each limit's capacity and refill rate the fallback grants.
+    /// Every instance limits on its own while degraded, so this is usually
+    /// well below 1.
+    #[serde(default = "default_fallback_ratio")]
+    pub fallback_ratio: f64,
+    /// Reserve tokens from the primary this many at a time and spend them
+    /// locally. A node may then hold up to a batch per key that no other
+    /// node can spend. Checks answered from a batch report the primary's
+    /// bucket as the batch's reservation left it, plus what is left of it.
+    #[serde(default)]
+    pub batch_size: Option<u64>,
+    /// Answer checks of a Redis primary from a local estimate of each
+    /// bucket, for this long after Redis last allowed the key.
+    #[serde(default)]
+    pub cache_ttl_ms: Option<u64>,
+    /// Lease tokens from a Redis primary and spend them locally, like
+    /// `batch_size` but with each lease recorded in Redis, renewed while in
+    /// use, returned when replaced or at shutdown, and reclaimed once it
+    /// lapses if this node goes away.
+    #[serde(default)]
+    pub lease: Option<LeasingConfig>,
+    /// Split each limit between the instances sharing a Redis primary,
+    /// each deciding alone within a slice sized by its share of the key's
+    /// recent traffic.
+    #[serde(default)]
+    pub slices: Option<SlicingConfig>,
+    /// Decide the keys clients route to this instance from memory, keeping
+    /// a Redis primary only as a backup of what they spend.
+    #[serde(default)]
+    pub affinity: Option<AffinityConfig>,
+    /// Decide every check locally against an estimate of each key's rate
+    /// across the instances sharing a Redis primary, which they exchange
+    /// in the background.
+    #[serde(default)]
+    pub estimates: Option<EstimatesConfig>,
+    /// Delay and fail calls to the primary on purpose, to rehearse how
+    /// fail-open and any fallback behave while the store misbehaves. Never
+    /// meant for serving real traffic.
+    #[serde(default)]
+    pub chaos: Option<FaultInjectionConfig>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LeasingConfig {
+    /// Tokens per lease.
+    #[serde(default = "default_lease_size")]
+    pub size: u64,
+    #[serde(default = "default_lease_ttl_ms")]
+    pub ttl_ms: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SlicingConfig {
+    /// This instance's name among those sharing the limits; must differ
+    /// between them. Defaults to `$HOSTNAME`.
+    #[serde(default)]
+    pub node_id: Option<String>,
+    #[serde(default = "default_rebalance_ms")]
+    pub rebalance_ms: u64,
+    /// Least share of a limit an instance with traffic on the key gets.
+    #[serde(default = "default_min_share")]
+    pub min_share: f64,
+    /// Divide each limit between regions first, and slice only this
+    /// instance's region's part between the instances sharing the primary,
+    /// which then only serves this region.
+    #[serde(default)]
+    pub region: Option<RegionConfig>,
+}
+
+/// This instance's region, and how limits are divided between regions:
+/// by fixed `weights`, or by each region's traffic, reported through an
+/// `exchange` every region reaches.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RegionConfig {
+    pub name: String,
+    /// Weight by region name; a region's part of a limit is its weight over
+    /// their sum.
+    #[serde(default)]
+    pub weights: BTreeMap<String, f64>,
+    /// A `Redis` every region reaches, or `Gossip` between the regions.
+    #[serde(default)]
+    pub exchange: Option<BackendType>,
+}
+
+/// The instances keys are divided between by consistent hashing. Clients
+/// with key affinity hash keys onto the addresses they dial, so `nodes`
+/// must list those, in the same form.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AffinityConfig {
+    /// Every instance, as `host:port`.
+    pub nodes: Vec<String>,
+    /// This instance, as listed in `nodes`.
+    pub node_id: String,
+    /// How often each key's spending is written back to the primary.
+    #[serde(default = "default_affinity_sync_ms")]
+    pub sync_ms: u64,
+}
+
+fn default_affinity_sync_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EstimatesConfig {
+    /// This instance's name among those sharing the limits; must differ
+    /// between them. Defaults to `$HOSTNAME`.
+    #[serde(default)]
+    pub node_id: Option<String>,
+    /// Window each instance's rate is measured over and shared once per.
+    #[serde(default = "default_estimate_window_ms")]
+    pub window_ms: u64,
+}
+
+fn default_estimate_window_ms() -> u64 {
+    EstimateConfig::default().window.as_millis() as u64
+}
+
+/// Faults injected into every call to the primary.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FaultInjectionConfig {
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Up to this much more latency, picked at random per call.
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// Share of calls, from 0 to 1, failed before reaching the primary.
+    #[serde(default)]
+    pub error_rate: f64,
+    /// Share of calls, from 0 to 1, that the primary carries out but that
+    /// fail anyway.
+    #[serde(default)]
+    pub partial_failure_rate: f64,
+}
+
+fn default_rebalance_ms() -> u64 {
+    SliceConfig::default().rebalance_interval.as_millis() as u64
+}
+
+fn default_min_share() -> f64 {
+    SliceConfig::default().min_share
+}
+
+fn default_lease_size() -> u64 {
+    LeaseConfig::default().size
+}
+
+fn default_lease_ttl_ms() -> u64 {
+    LeaseConfig::default().ttl.as_millis() as u64
+}
+
+impl Default for BackendsConfig {
+    fn default() -> Self {
+        Self {
+            primary: BackendType::Memory { cache_size: 10_000 },
+            fallback: None,
+            fallback_ratio: default_fallback_ratio(),
+            batch_size: None,
+            cache_ttl_ms: None,
+            lease: None,
+            slices: None,
+            affinity: None,
+            estimates: None,
+            chaos: None,
+        }
+    }
+}
+
+fn default_fallback_ratio() -> f64 {
+    0.5
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum BackendType {
+    Memory { cache_size: usize },
+    Redis { url: String, pool_size: usize },
+    RedisCluster { nodes: Vec<String> },
+    /// Buckets kept by every instance, which tell each other over UDP what
+    /// they spend, for approximate global limits without Redis.
+    Gossip {
+        /// Address the other instances send to, e.g. `0.0.0.0:7946`.
+        bind: String,
+        /// The other instances' gossip addresses, as `host:port`.
+        #[serde(default)]
+        peers: Vec<String>,
+        #[serde(default = "default_gossip_interval_ms")]
+        interval_ms: u64,
+        #[serde(default)]
+        mode: GossipMode,
+    },
+    /// Buckets replicated between three or five instances with Raft, for
+    /// exact global limits that survive restarts, without Redis.
+    Raft {
+        /// This instance's id in the group; not 0.
+        id: u64,
+        /// Address the other instances connect to, e.g. `0.0.0.0:7950`.
+        bind: String,
+        /// The other instances' addresses as `host:port`, by id.
+        #[serde(default)]
+        peers: BTreeMap<String, String>,
+        /// Where the log and snapshots are kept.
+        data_dir: PathBuf,
+        #[serde(default = "default_election_timeout_ms")]
+        election_timeout_ms: u64,
+    },
+}
+
+fn default_gossip_interval_ms() -> u64 {
+    100
+}
+
+fn default_election_timeout_ms() -> u64 {
+    500
+}
+
+/// What gossiping instances tell each other.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GossipMode {
+    /// The tokens taken since the last interval.
+    #[default]
+    Deltas,
+    /// Each key's counter of what every instance took from it, which
+    /// survives lost datagrams and partitions; for instances in different
+    /// regions.
+    Crdt,
+}
+
+impl BackendType {
+    /// Short name of the storage, as accepted by `--backend` (but for
+    /// `gossip` and `raft`, which need their peers from the config file).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BackendType::Memory { .. } => "memory",
+            BackendType::Redis { .. } => "redis",
+            BackendType::RedisCluster { .. } => "redis-cluster",
+            BackendType::Gossip { .. } => "gossip",
+            BackendType::Raft { .. } => "raft",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LimitConfig {
+    pub capacity: u64,
+    pub refill_rate: u64,
+    #[serde(default = "default_refill_interval_secs")]
+    pub refill_interval_secs: u64,
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    /// Log and count this rule's denials without enforcing them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shadow: bool,
+}
+
+fn default_refill_interval_secs() -> u64 {
+    1
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1000,
+            refill_rate: 100,
+            refill_interval_secs: 1,
+            algorithm: Algorithm::TokenBucket,
+            shadow: false,
+        }
+    }
+}
+
+impl LimitConfig {
+    pub fn validate(&self, pattern: &str) -> Result<(), RateLimitError> {
+        if self.algorithm != Algorithm::TokenBucket {
+            return Err(RateLimitError::ConfigError(format!(
+                "limit '{}': only the token_bucket algorithm is supported",
+                pattern
+            )));
+        }
+        if self.capacity == 0 || self.refill_rate == 0 {
+            return Err(RateLimitError::ConfigError(format!(
+                "limit '{}': capacity and refill_rate must be positive",
+                pattern
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn bucket_config(&self) -> TokenBucketConfig {
+        TokenBucketConfig {
+            capacity: self.capacity,
+            refill_rate: self.refill_rate,
+            refill_interval: Duration::from_secs(self.refill_interval_secs),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    #[default]
+    TokenBucket,
+    SlidingWindow,
+    FixedWindow,
+}
+
+impl GuardianConfig {
+    /// Load from `--config <path>` or `$GUARDIAN_CONFIG`, falling back to
+    /// built-in defaults when neither is set. `--backend`, `--redis-url`
+    /// and `--redis-nodes` (or their environment variables) then replace
+    /// the configured primary backend.
+    pub fn load_from_args() -> Result<Self, RateLimitError> {
+        let config = Self::load_unchecked(Args::from_env())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// `load_from_args` without the validation, for `--check-config` to
+    /// report every problem at once.
+    pub fn load_unchecked(args: Args) -> Result<Self, RateLimitError> {
+        let env = |name| std::env::var(name).ok();
+
+        let mut builder = Config::builder();
+        if let Some(path) = args.config_path() {
+            builder = builder.add_source(File::from(path.as_path()));
+        }
+        let mut config = Self::read_sources(builder)?;
+
+        let selection = BackendSelection {
+            kind: args.backend.or_else(|| env(BACKEND_ENV)),
+            redis_url: args.redis_url.or_else(|| env(REDIS_URL_ENV)),
+            redis_nodes: args.redis_nodes.or_else(|| env(REDIS_NODES_ENV)),
+        };
+        config.backends.primary = selection.apply(config.backends.primary)?;
+        Ok(config)
+    }
+
+    /// Load a YAML or TOML file (chosen by extension).
+    pub fn load(path: &Path) -> Result<Self, RateLimitError> {
+        Self::from_sources(Config::builder().add_source(File::from(path)))
+    }
+
+    fn from_sources(
+        builder: ::config::ConfigBuilder<::config::builder::DefaultState>,
+    ) -> Result<Self, RateLimitError> {
+        let config = Self::read_sources(builder)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn read_sources(
+        builder: ::config::ConfigBuilder<::config::builder::DefaultState>,
+    ) -> Result<Self, RateLimitError> {
+        builder
+            .add_source(Environment::with_prefix("GUARDIAN").separator("__"))
+            .build()
+            .and_then(Config::try_deserialize)
+            .map_err(|e| RateLimitError::ConfigError(e.to_string()))
+    }
+
+    fn validate(&self) -> Result<(), RateLimitError> {
+        match self.problems().into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(()),
+        }
+    }
+
+    /// Everything that would stop the service from starting, in a stable
+    /// order.
+    pub fn problems(&self) -> Vec<RateLimitError> {
+        let mut problems = Vec::new();
+        for (pattern, limit) in sorted(&self.limits) {
+            if pattern.is_empty() {
+                problems.push(RateLimitError::ConfigError(
+                    "limit '': patterns must not be empty".to_string(),
+                ));
+            }
+            problems.extend(limit.validate(pattern).err());
+        }
+        if self.global.admin_token.as_deref() == Some("") {
+            problems.push(RateLimitError::ConfigError(
+                "global.admin_token must not be empty; omit it to disable admin RPCs".to_string(),
+            ));
+        }
+        let admission = self.global.admission;
+        if admission.max_rps == Some(0)
+            || admission.max_concurrent == Some(0)
+            || admission.max_concurrent_per_peer == Some(0)
+        {
+            problems.push(RateLimitError::ConfigError(
+                "global.admission limits must be positive; omit them to disable shedding"
+                    .to_string(),
+            ));
+        }
+        let grpc = &self.global.grpc;
+        let sizes = [grpc.max_recv_message_bytes, grpc.max_send_message_bytes];
+        let periods = [
+            grpc.keepalive_interval_secs,
+            grpc.keepalive_timeout_secs,
+            grpc.tcp_keepalive_secs,
+        ];
+        if sizes.contains(&Some(0)) || periods.contains(&Some(0)) {
+            problems.push(RateLimitError::ConfigError(
+                "global.grpc sizes and intervals must be positive; omit them for the defaults"
+                    .to_string(),
+            ));
+        }
+        let origins = self.global.grpc_web.iter().flat_map(|web| &web.allowed_origins);
+        for origin in origins {
+            if origin.is_empty() || origin.parse::<tonic::codegen::http::HeaderValue>().is_err() {
+                problems.push(RateLimitError::ConfigError(format!(
+                    "global.grpc_web.allowed_origins: invalid origin '{}'",
+                    origin
+                )));
+            }
+        }
+        if let Some(ext_authz) = &self.global.ext_authz {
+            if ext_authz.domain.is_empty()
+                || ext_authz.descriptors.is_empty()
+                || ext_authz.descriptors.iter().any(Vec::is_empty)
+            {
+                problems.push(RateLimitError::ConfigError(
+                    "global.ext_authz needs a domain and descriptors with at least one entry each"
+                        .to_string(),
+                ));
+            }
+        }
+        match &self.backends.fallback {
+            Some(BackendType::Memory { .. }) => {
+                if matches!(
+                    self.backends.primary,
+                    BackendType::Memory { .. } | BackendType::Gossip { .. }
+                ) {
+                    problems.push(RateLimitError::ConfigError(
+                        "backends.fallback only applies to Redis and Raft primaries".to_string(),
+                    ));
+                }
+                let ratio = self.backends.fallback_ratio;
+                if !(ratio > 0.0 && ratio <= 1.0) {
+                    problems.push(RateLimitError::ConfigError(
+                        "backends.fallback_ratio must be above 0 and at most 1".to_string(),
+                    ));
+                }
+            }
+            Some(_) => problems.push(RateLimitError::ConfigError(
+                "backends.fallback must be a Memory backend".to_string(),
+            )),
+            None => {}
+        }
+        if matches!(
+            self.backends.primary,
+            BackendType::Gossip { interval_ms: 0, .. }
+        ) {
+            problems.push(RateLimitError::ConfigError(
+                "backends.primary.interval_ms must be positive".to_string(),
+            ));
+        }
+        if let BackendType::Raft {
+            id,
+            peers,
+            election_timeout_ms,
+            ..
+        } = &self.backends.primary
+        {
+            let distinct = peers
+                .keys()
+                .all(|peer| matches!(peer.parse::<u64>(), Ok(peer) if peer != 0 && peer != *id));
+            if *id == 0 || !distinct {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.primary.id and its peers' ids must be distinct and positive"
+                        .to_string(),
+                ));
+            }
+            if *election_timeout_ms == 0 {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.primary.election_timeout_ms must be positive".to_string(),
+                ));
+            }
+        }
+        if self.backends.batch_size == Some(0) || self.backends.cache_ttl_ms == Some(0) {
+            problems.push(RateLimitError::ConfigError(
+                "backends.batch_size and cache_ttl_ms must be positive".to_string(),
+            ));
+        }
+        if self.backends.cache_ttl_ms.is_some()
+            && !matches!(self.backends.primary, BackendType::Redis { .. })
+        {
+            problems.push(RateLimitError::ConfigError(
+                "backends.cache_ttl_ms only applies to Redis primaries".to_string(),
+            ));
+        }
+        if let Some(lease) = &self.backends.lease {
+            if lease.size == 0 || lease.ttl_ms == 0 {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.lease.size and ttl_ms must be positive".to_string(),
+                ));
+            }
+            if !matches!(self.backends.primary, BackendType::Redis { .. })
+                || self.backends.batch_size.is_some()
+                || self.backends.cache_ttl_ms.is_some()
+            {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.lease only applies to Redis primaries without batch_size or cache_ttl_ms"
+                        .to_string(),
+                ));
+            }
+        }
+        if let Some(slices) = &self.backends.slices {
+            if slices.rebalance_ms == 0 || !(0.0..1.0).contains(&slices.min_share) {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.slices.rebalance_ms must be positive and min_share in [0, 1)"
+                        .to_string(),
+                ));
+            }
+            if !matches!(self.backends.primary, BackendType::Redis { .. })
+                || self.backends.batch_size.is_some()
+                || self.backends.cache_ttl_ms.is_some()
+                || self.backends.lease.is_some()
+            {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.slices only applies to Redis primaries without batch_size, cache_ttl_ms or lease"
+                        .to_string(),
+                ));
+            }
+            if let Some(region) = &slices.region {
+                if region.weights.is_empty() == region.exchange.is_none() {
+                    problems.push(RateLimitError::ConfigError(
+                        "backends.slices.region needs either weights or an exchange".to_string(),
+                    ));
+                }
+                if !region.weights.is_empty()
+                    && (region.weights.values().any(|weight| *weight < 0.0)
+                        || region.weights.get(&region.name).is_none_or(|w| *w <= 0.0))
+                {
+                    problems.push(RateLimitError::ConfigError(format!(
+                        "backends.slices.region.weights must be non-negative, with a positive weight for '{}'",
+                        region.name
+                    )));
+                }
+                if region.exchange.as_ref().is_some_and(|exchange| {
+                    !matches!(
+                        exchange,
+                        BackendType::Redis { .. } | BackendType::Gossip { .. }
+                    )
+                }) {
+                    problems.push(RateLimitError::ConfigError(
+                        "backends.slices.region.exchange must be Redis or Gossip".to_string(),
+                    ));
+                }
+            }
+        }
+        if let Some(affinity) = &self.backends.affinity {
+            if affinity.sync_ms == 0 || !affinity.nodes.contains(&affinity.node_id) {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.affinity.sync_ms must be positive and nodes must list node_id"
+                        .to_string(),
+                ));
+            }
+            if !matches!(self.backends.primary, BackendType::Redis { .. })
+                || self.backends.batch_size.is_some()
+                || self.backends.cache_ttl_ms.is_some()
+                || self.backends.lease.is_some()
+                || self.backends.slices.is_some()
+            {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.affinity only applies to Redis primaries without batch_size, cache_ttl_ms, lease or slices"
+                        .to_string(),
+                ));
+            }
+        }
+        if let Some(estimates) = &self.backends.estimates {
+            if estimates.window_ms == 0 {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.estimates.window_ms must be positive".to_string(),
+                ));
+            }
+            if !matches!(self.backends.primary, BackendType::Redis { .. })
+                || self.backends.batch_size.is_some()
+                || self.backends.cache_ttl_ms.is_some()
+                || self.backends.lease.is_some()
+                || self.backends.slices.is_some()
+                || self.backends.affinity.is_some()
+            {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.estimates only applies to Redis primaries without batch_size, cache_ttl_ms, lease, slices or affinity"
+                        .to_string(),
+                ));
+            }
+        }
+        if let Some(chaos) = &self.backends.chaos {
+            let rates = [chaos.error_rate, chaos.partial_failure_rate];
+            if rates.iter().any(|rate| !(0.0..=1.0).contains(rate)) {
+                problems.push(RateLimitError::ConfigError(
+                    "backends.chaos error_rate and partial_failure_rate must be between 0 and 1"
+                        .to_string(),
+                ));
+            }
+        }
+        if let Some(warm) = &self.warm_start {
+            if warm.max_keys == 0 || warm.timeout_secs == 0 {
+                problems.push(RateLimitError::ConfigError(
+                    "warm_start.max_keys and timeout_secs must be positive".to_string(),
+                ));
+            }
+        }
+        if let Some(leader) = &self.leader {
+            if leader.ttl_ms == 0 || leader.lock.is_empty() {
+                problems.push(RateLimitError::ConfigError(
+                    "leader.ttl_ms must be positive and leader.lock non-empty".to_string(),
+                ));
+            }
+            if !matches!(self.backends.primary, BackendType::Redis { .. }) {
+                problems.push(RateLimitError::ConfigError(
+                    "leader election needs a Redis primary".to_string(),
+                ));
+            }
+        }
+        if let Some(peers) = &self.peers {
+            if peers.addresses.is_empty() || peers.timeout_ms == 0 {
+                problems.push(RateLimitError::ConfigError(
+                    "peers.addresses must not be empty and peers.timeout_ms must be positive"
+                        .to_string(),
+                ));
+            }
+            if self.global.tls.is_some() {
+                problems.push(RateLimitError::ConfigError(
+                    "peers are called over plaintext gRPC, which global.tls turns off".to_string(),
+                ));
+            }
+        }
+        if let Some(recorder) = &self.decision_recorder {
+            if !(0.0..=1.0).contains(&recorder.sample_ratio) || recorder.capacity == 0 {
+                problems.push(RateLimitError::ConfigError(
+                    "decision_recorder.sample_ratio must be between 0 and 1, and capacity positive"
+                        .to_string(),
+                ));
+            }
+        }
+        if let Some(tracing) = &self.global.tracing {
+            if !(0.0..=1.0).contains(&tracing.sample_ratio) {
+                problems.push(RateLimitError::ConfigError(
+                    "global.tracing.sample_ratio must be between 0 and 1".to_string(),
+                ));
+            }
+        }
+        for (tier, limit) in sorted(&self.tiers) {
+            problems.extend(limit.validate(tier).err());
+            if limit.shadow {
+                problems.push(RateLimitError::ConfigError(format!(
+                    "tier '{}': shadow mode is only available on limit rules",
+                    tier
+                )));
+            }
+        }
+        for (name, limit) in sorted(&self.namespaces) {
+            problems.extend(namespace::validate(name).err());
+            problems.extend(limit.validate(&namespace::default_pattern(name)).err());
+        }
+        for (client_id, tier) in sorted(&self.client_tiers) {
+            if !self.tiers.contains_key(tier) {
+                problems.push(RateLimitError::ConfigError(format!(
+                    "client '{}' is assigned to unknown tier '{}'",
+                    client_id, tier
+                )));
+            }
+        }
+        problems
+    }
+
+    /// The rule for keys no pattern matches.
+    pub fn default_limit(&self) -> LimitConfig {
+        self.limits.get(DEFAULT_RULE).cloned().unwrap_or_default()
+    }
+
+    /// Every pattern rule, including one `<namespace>/*` rule per
+    /// namespace default.
+    pub fn pattern_rules(&self) -> HashMap<String, LimitConfig> {
+        let mut rules: HashMap<_, _> = self
+            .namespaces
+            .iter()
+            .map(|(name, limit)| (namespace::default_pattern(name), limit.clone()))
+            .collect();
+        rules.extend(
+            self.limits
+                .iter()
+                .filter(|(pattern, _)| pattern.as_str() != DEFAULT_RULE)
+                .map(|(pattern, limit)| (pattern.clone(), limit.clone())),
+        );
+        rules
+    }
+
+    /// Build the primary backend with one bucket configuration per limit
+    /// rule. Longer (more specific) patterns are tried first.
+    pub async fn build_backend(&self) -> Result<RouterBackend, RateLimitError> {
+        let mut router = RouterBackend::new(
+            build_storage(&self.backends, self.default_limit().bucket_config()).await?,
+        );
+
+        let mut rules: Vec<_> = self.pattern_rules().into_iter().collect();
+        rules.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+
+        for (pattern, limit) in rules {
+            let backend = build_storage(&self.backends, limit.bucket_config()).await?;
+            router = router.route(pattern, backend);
+        }
+        Ok(router)
+    }
+}
+
+/// Map entries sorted by key, so checks report in a stable order.
+pub fn sorted<V>(map: &HashMap<String, V>) -> Vec<(&String, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_by_key(|(key, _)| *key);
+    entries
+}
+
+/// Command-line flags, as `--name value` or `--name=value`, plus the bare
+/// `--check-config` and `--check-backend` switches. Anything else is
+/// ignored.
+#[derive(Debug, Default)]
+pub struct Args {
+    config: Option<String>,
+    backend: Option<String>,
+    redis_url: Option<String>,
+    redis_nodes: Option<String>,
+    /// Validate the config and exit instead of serving.
+    pub check_config: bool,
+    /// With `check_config`, also try to reach the backend.
+    pub check_backend: bool,
+}
+
+impl Args {
+    pub fn from_env() -> Self {
+        Self::parse(std::env::args().skip(1))
+    }
+
+    /// `--config`, or `$GUARDIAN_CONFIG`.
+    pub fn config_path(&self) -> Option<PathBuf> {
+        self.config
+            .clone()
+            .or_else(|| std::env::var(CONFIG_ENV).ok())
+            .map(PathBuf::from)
+    }
+
+    fn parse(mut args: impl Iterator<Item = String>) -> Self {
+        let mut parsed = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--check-config" => parsed.check_config = true,
+                "--check-backend" => parsed.check_backend = true,
+                _ => {}
+            }
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let slot = match name.as_str() {
+                "--config" => &mut parsed.config,
+                "--backend" => &mut parsed.backend,
+                "--redis-url" => &mut parsed.redis_url,
+                "--redis-nodes" => &mut parsed.redis_nodes,
+                _ => continue,
+            };
+            *slot = inline.or_else(|| args.next());
+        }
+        parsed
+    }
+}
+
+/// Backend chosen at startup, on top of whatever the config file says.
+#[derive(Debug, Default)]
+struct BackendSelection {
+    /// `memory`, `redis` or `redis-cluster`.
+    kind: Option<String>,
+    redis_url: Option<String>,
+    /// Comma-separated cluster node URLs.
+    redis_nodes: Option<String>,
+}
+
+impl BackendSelection {
+    /// Switch `current` to the selected type (keeping its settings when it
+    /// already is that type), then apply the Redis addresses given.
+    fn apply(self, current: BackendType) -> Result<BackendType, RateLimitError> {
+        let backend = match (self.kind.as_deref(), current) {
+            (None, current) => current,
+            (Some("memory"), current @ BackendType::Memory { .. }) => current,
+            (Some("memory"), _) => BackendType::Memory { cache_size: 10_000 },
+            (Some("redis"), current @ BackendType::Redis { .. }) => current,
+            (Some("redis"), _) => BackendType::Redis {
+                url: DEFAULT_REDIS_URL.to_string(),
+                pool_size: 10,
+            },
+            (Some("redis-cluster"), current @ BackendType::RedisCluster { .. }) => current,
+            (Some("redis-cluster"), _) => BackendType::RedisCluster { nodes: Vec::new() },
+            (Some(other), _) => {
+                return Err(RateLimitError::ConfigError(format!(
+                    "unknown backend '{}'; expected memory, redis or redis-cluster",
+                    other
+                )))
+            }
+        };
+
+        let backend = match backend {
+            BackendType::Redis { url, pool_size } => BackendType::Redis {
+                url: self.redis_url.unwrap_or(url),
+                pool_size,
+            },
+            BackendType::RedisCluster { nodes } => BackendType::RedisCluster {
+                nodes: match self.redis_nodes {
+                    Some(list) => list.split(',').map(|node| node.trim().to_string()).collect(),
+                    None => nodes,
+                },
+            },
+            memory => memory,
+        };
+        if matches!(&backend, BackendType::RedisCluster { nodes } if nodes.is_empty()) {
+            return Err(RateLimitError::ConfigError(
+                "the redis-cluster backend needs at least one node (--redis-nodes)".to_string(),
+            ));
+        }
+        Ok(backend)
+    }
+}
+
+/// Failovers of every backend built by [`build_storage`], for `/metrics`.
+pub static FAILOVER_STATS: LazyLock<Arc<FailoverStats>> = LazyLock::new(Arc::default);
+
+/// Hits of the batching and caching layers of every backend built by
+/// [`build_storage`], for GetStats.
+pub static CACHE_STATS: LazyLock<Arc<CacheStats>> = LazyLock::new(Arc::default);
+
+/// The gossip node shared by every backend [`build_storage`] builds on
+/// `Gossip`, started with the first.
+static GOSSIP_NODE: OnceCell<Arc<GossipNode>> = OnceCell::const_new();
+
+/// Likewise the Raft node for `Raft`.
+static RAFT_NODE: OnceCell<Arc<RaftNode>> = OnceCell::const_new();
+
+/// One primary backend sized by `config`, with any batching or caching
+/// configured and any chaos injected into its calls, behind a failover to the configured fallback (at
+/// `fallback_ratio` of the limit) if there is one.
+pub async fn build_storage(
+    backends: &BackendsConfig,
+    config: TokenBucketConfig,
+) -> Result<Box<dyn StorageBackend>, RateLimitError> {
+    let mut primary: Box<dyn StorageBackend> = match &backends.primary {
+        BackendType::Memory { .. } => Box::new(MemoryBackend::new(config.clone())),
+        BackendType::Redis { url, .. } => {
+            let redis = RedisBackend::new(url, config.clone()).await?;
+            match (
+                backends.cache_ttl_ms,
+                &backends.lease,
+                &backends.slices,
+                &backends.affinity,
+                &backends.estimates,
+            ) {
+                (Some(ttl), _, _, _, _) => Box::new(
+                    CachedRedisBackend::new(redis, Duration::from_millis(ttl))
+                        .with_stats(Arc::clone(&CACHE_STATS)),
+                ),
+                (None, Some(lease), _, _, _) => {
+                    let lease = LeaseConfig {
+                        size: lease.size,
+                        ttl: Duration::from_millis(lease.ttl_ms),
+                    };
+                    Box::new(LeasingBackend::new(redis, lease).with_stats(Arc::clone(&CACHE_STATS)))
+                }
+                (None, None, Some(slices), _, _) => {
+                    let node = slices.node_id.clone().unwrap_or_else(default_node_id);
+                    let slicing = SliceConfig {
+                        rebalance_interval: Duration::from_millis(slices.rebalance_ms),
+                        min_share: slices.min_share,
+                    };
+                    let sliced = SlicedBackend::new(redis, config.clone(), node, slicing);
+                    match &slices.region {
+                        Some(region) => Box::new(sliced.with_region(
+                            region.name.clone(),
+                            region_shares(region, &config).await?,
+                        )),
+                        None => Box::new(sliced),
+                    }
+                }
+                (None, None, None, Some(affinity), _) => Box::new(AffinityBackend::new(
+                    redis,
+                    config.clone(),
+                    HashRing::new(affinity.nodes.iter().cloned()),
+                    affinity.node_id.clone(),
+                    Duration::from_millis(affinity.sync_ms),
+                )),
+                (None, None, None, None, Some(estimates)) => Box::new(EstimatingBackend::new(
+                    redis,
+                    config.clone(),
+                    estimates.node_id.clone().unwrap_or_else(default_node_id),
+                    EstimateConfig {
+                        window: Duration::from_millis(estimates.window_ms),
+                    },
+                )),
+                (None, None, None, None, None) => Box::new(redis),
+            }
+        }
+        BackendType::RedisCluster { nodes } => {
+            Box::new(RedisClusterBackend::new(nodes.clone(), config.clone()).await?)
+        }
+        BackendType::Gossip {
+            bind,
+            peers,
+            interval_ms,
+            mode,
+        } => {
+            let node = gossip_node(bind, peers, *interval_ms, *mode).await?;
+            Box::new(GossipBackend::new(node, config.clone()))
+        }
+        BackendType::Raft {
+            id,
+            bind,
+            peers,
+            data_dir,
+            election_timeout_ms,
+        } => {
+            let node = RAFT_NODE
+                .get_or_try_init(|| async {
+                    let peers = peers
+                        .iter()
+                        .map(|(peer, addr)| {
+                            let peer = peer.parse().map_err(|_| {
+                                RateLimitError::ConfigError(format!("raft peer id '{}'", peer))
+                            })?;
+                            Ok((peer, addr.clone()))
+                        })
+                        .collect::<Result<_, RateLimitError>>()?;
+                    let raft = RaftConfig {
+                        id: *id,
+                        bind: bind.clone(),
+                        peers,
+                        data_dir: data_dir.clone(),
+                        election_timeout: Duration::from_millis(*election_timeout_ms),
+                    };
+                    RaftNode::start(raft).await.map_err(|e| {
+                        RateLimitError::StorageError(format!("raft on {}: {}", bind, e))
+                    })
+                })
+                .await?;
+            Box::new(RaftBackend::new(Arc::clone(node), config.clone()))
+        }
+    };
+    if let Some(chaos) = &backends.chaos {
+        tracing::warn!(?chaos, "injecting faults into the primary backend");
+        primary = Box::new(ChaosBackend::new(
+            primary,
+            ChaosConfig {
+                latency: Duration::from_millis(chaos.latency_ms),
+                jitter: Duration::from_millis(chaos.jitter_ms),
+                error_rate: chaos.error_rate,
+                partial_failure_rate: chaos.partial_failure_rate,
+            },
+        ));
+    }
+    if let Some(batch_size) = backends.batch_size {
+        primary = Box::new(
+            BatchingBackend::new(primary, batch_size).with_stats(Arc::clone(&CACHE_STATS)),
+        );
+    }
+    if backends.fallback.is_none() {
+        return Ok(primary);
+    }
+    let scale = |n: u64| ((n as f64 * backends.fallback_ratio) as u64).max(1);
+    let fallback = MemoryBackend::new(TokenBucketConfig {
+        capacity: scale(config.capacity),
+        refill_rate: scale(config.refill_rate),
+        ..config
+    });
+    Ok(Box::new(
+        FailoverBackend::new(primary, fallback).with_stats(Arc::clone(&FAILOVER_STATS)),
+    ))
+}
+
+/// The [`GOSSIP_NODE`], started on first use.
+async fn gossip_node(
+    bind: &str,
+    peers: &[String],
+    interval_ms: u64,
+    mode: GossipMode,
+) -> Result<Arc<GossipNode>, RateLimitError> {
+    let node = GOSSIP_NODE
+        .get_or_try_init(|| async {
+            let gossip = GossipConfig {
+                bind: bind.to_string(),
+                peers: peers.to_vec(),
+                interval: Duration::from_millis(interval_ms),
+                mode: match mode {
+                    GossipMode::Deltas => guardian_gossip::GossipMode::Deltas,
+                    GossipMode::Crdt => guardian_gossip::GossipMode::Crdt,
+                },
+            };
+            GossipNode::start(gossip)
+                .await
+                .map_err(|e| RateLimitError::StorageError(format!("gossip on {}: {}", bind, e)))
+        })
+        .await?;
+    Ok(Arc::clone(node))
+}
+
+/// How `region` divides limits sized by `config` between regions.
+async fn region_shares(
+    region: &RegionConfig,
+    config: &TokenBucketConfig,
+) -> Result<RegionShares, RateLimitError> {
+    let exchange: Arc<dyn StorageBackend> = match &region.exchange {
+        None => {
+            let weights = region.weights.clone().into_iter().collect();
+            return Ok(RegionShares::Static(weights));
+        }
+        Some(BackendType::Redis { url, .. }) => {
+            Arc::new(RedisBackend::new(url, config.clone()).await?)
+        }
+        Some(BackendType::Gossip {
+            bind,
+            peers,
+            interval_ms,
+            mode,
+        }) => {
+            let node = gossip_node(bind, peers, *interval_ms, *mode).await?;
+            Arc::new(GossipBackend::new(node, config.clone()))
+        }
+        Some(other) => {
+            return Err(RateLimitError::ConfigError(format!(
+                "regions can't exchange demand over {}",
+                other.kind()
+            )))
+        }
+    };
+    Ok(RegionShares::Demand(exchange))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::config::FileFormat;
+
+    fn parse(contents: &str, format: FileFormat) -> Result<GuardianConfig, RateLimitError> {
+        GuardianConfig::from_sources(Config::builder().add_source(File::from_str(contents, format)))
+    }
+
+    const YAML: &str = r#"
+global:
+  fail_open: false
+  listen_addr: "127.0.0.1:6000"
+
+backends:
+  primary:
+    type: "Memory"
+    cache_size: 10000
+
+limits:
+  default:
+    capacity: 100
+    refill_rate: 10
+  "premium:*":
+    capacity: 1000
+    refill_rate: 100
+    refill_interval_secs: 1
+    algorithm: "token_bucket"
+"#;
+
+    #[test]
+    fn test_parses_yaml() {
+        let config = parse(YAML, FileFormat::Yaml).unwrap();
+        assert!(!config.global.fail_open);
+        assert_eq!(config.global.listen_addr, "127.0.0.1:6000");
+        assert_eq!(config.global.metrics_port, 9090);
+        assert!(config.global.statsd.is_none());
+        assert_eq!(config.global.log_format, LogFormat::Text);
+        assert_eq!(config.default_limit().capacity, 100);
+        assert_eq!(config.limits["premium:*"].capacity, 1000);
+    }
+
+    #[test]
+    fn test_parses_toml() {
+        let toml = r#"
+[global]
+fail_open = true
+log_level = "debug"
+log_format = "json"
+
+[backends.primary]
+type = "Redis"
+url = "redis://localhost:6379"
+pool_size = 10
+
+[limits.default]
+capacity = 50
+refill_rate = 5
+"#;
+        let config = parse(toml, FileFormat::Toml).unwrap();
+        assert!(matches!(config.backends.primary, BackendType::Redis { .. }));
+        assert_eq!(config.global.log_level, "debug");
+        assert_eq!(config.global.log_format, LogFormat::Json);
+        assert_eq!(config.default_limit().refill_rate, 5);
+    }
+
+    #[test]
+    fn test_parses_grpc_transport_options() {
+        let yaml = r#"
+global:
+  grpc:
+    compression: ["gzip", "zstd"]
+    max_recv_message_bytes: 16777216
+    keepalive_interval_secs: 30
+"#;
+        let grpc = parse(yaml, FileFormat::Yaml).unwrap().global.grpc;
+        assert_eq!(grpc.compression, [Compression::Gzip, Compression::Zstd]);
+        assert_eq!(grpc.max_recv_message_bytes, Some(16 * 1024 * 1024));
+        assert_eq!(grpc.max_send_message_bytes, None);
+        assert_eq!(grpc.keepalive_interval_secs, Some(30));
+
+        let zero = "global:\n  grpc:\n    max_send_message_bytes: 0\n";
+        assert!(parse(zero, FileFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_parses_ext_authz_descriptors() {
+        let yaml = r#"
+global:
+  ext_authz:
+    domain: edge
+    descriptors:
+      - [remote_address]
+      - - request_headers: {header_name: x-api-key, descriptor_key: api_key}
+        - generic_key: {descriptor_value: checkout}
+"#;
+        let config = parse(yaml, FileFormat::Yaml).unwrap();
+        let ext_authz = config.global.ext_authz.unwrap();
+        assert_eq!(ext_authz.domain, "edge");
+        assert_eq!(
+            ext_authz.descriptors,
+            [
+                vec![DescriptorAction::RemoteAddress],
+                vec![
+                    DescriptorAction::RequestHeaders {
+                        header_name: "x-api-key".to_string(),
+                        descriptor_key: "api_key".to_string(),
+                    },
+                    DescriptorAction::GenericKey {
+                        descriptor_value: "checkout".to_string(),
+                        descriptor_key: "generic_key".to_string(),
+                    },
+                ],
+            ]
+        );
+
+        let empty = "global:\n  ext_authz:\n    domain: edge\n    descriptors: [[]]\n";
+        assert!(parse(empty, FileFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_rejects_unsupported_algorithm() {
+        let yaml = r#"
+limits:
+  default:
+    capacity: 10
+    refill_rate: 1
+    algorithm: "sliding_window"
+"#;
+        assert!(parse(yaml, FileFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_rejects_client_in_unknown_tier() {
+        let yaml = r#"
+tiers:
+  premium:
+    capacity: 1000
+    refill_rate: 100
+client_tiers:
+  acme: "premium"
+  globex: "gold"
+"#;
+        assert!(parse(yaml, FileFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_validates_fallback_backend() {
+        let memory = r#"{ type: "Memory", cache_size: 100 }"#;
+        let redis = r#"{ type: "Redis", url: "redis://localhost:6379", pool_size: 1 }"#;
+        let yaml = |primary: &str, fallback: &str, ratio: f64| {
+            let backends = format!(
+                "backends:\n  primary: {}\n  fallback: {}\n  fallback_ratio: {}\n",
+                primary, fallback, ratio
+            );
+            parse(&backends, FileFormat::Yaml)
+        };
+        let config = yaml(redis, memory, 0.25).unwrap();
+        assert_eq!(config.backends.fallback_ratio, 0.25);
+
+        assert!(yaml(redis, memory, 0.0).is_err());
+        assert!(yaml(redis, memory, 1.5).is_err());
+        assert!(yaml(redis, redis, 0.5).is_err());
+        assert!(yaml(memory, memory, 0.5).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_builds_gossip_backend() {
+        let yaml = r#"
+backends:
+  primary:
+    type: "Gossip"
+    bind: "127.0.0.1:0"
+    peers: ["guardian-2:7946"]
+limits:
+  default:
+    capacity: 2
+    refill_rate: 1
+"#;
+        let config = parse(yaml, FileFormat::Yaml).unwrap();
+        assert!(matches!(
+            config.backends.primary,
+            BackendType::Gossip {
+                interval_ms: 100,
+                mode: GossipMode::Deltas,
+                ..
+            }
+        ));
+        assert_eq!(config.backends.primary.kind(), "gossip");
+        let backend = config.build_backend().await.unwrap();
+        assert!(backend.take_token("user1", 2).await.unwrap());
+        assert!(!backend.take_token("user1", 1).await.unwrap());
+
+        let fallback = "  fallback: { type: \"Memory\", cache_size: 10 }\nlimits:";
+        let with_fallback = yaml.replacen("limits:", fallback, 1);
+        assert!(parse(&with_fallback, FileFormat::Yaml).is_err());
+
+        let crdt = yaml.replacen("peers:", "mode: crdt\n    peers:", 1);
+        assert!(matches!(
+            parse(&crdt, FileFormat::Yaml).unwrap().backends.primary,
+            BackendType::Gossip {
+                mode: GossipMode::Crdt,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_builds_raft_backend() {
+        let data_dir =
+            std::env::temp_dir().join(format!("guardian-raft-config-{}", std::process::id()));
+        let yaml = format!(
+            r#"
+backends:
+  primary:
+    type: "Raft"
+    id: 1
+    bind: "127.0.0.1:0"
+    data_dir: "{}"
+limits:
+  default:
+    capacity: 2
+    refill_rate: 1
+"#,
+            data_dir.display()
+        );
+        let config = parse(&yaml, FileFormat::Yaml).unwrap();
+        assert!(matches!(
+            config.backends.primary,
+            BackendType::Raft {
+                election_timeout_ms: 500,
+                ..
+            }
+        ));
+        assert_eq!(config.backends.primary.kind(), "raft");
+        // A group of one elects itself.
+        let backend = config.build_backend().await.unwrap();
+        assert!(backend.take_token("user1", 2).await.unwrap());
+        assert!(!backend.take_token("user1", 1).await.unwrap());
+        let _ = std::fs::remove_dir_all(&data_dir);
+
+        let with_peers = yaml.replacen("bind:", "peers: { 2: \"guardian-2:7950\" }\n    bind:", 1);
+        let BackendType::Raft { peers, .. } = parse(&with_peers, FileFormat::Yaml)
+            .unwrap()
+            .backends
+            .primary
+        else {
+            panic!("not a Raft primary");
+        };
+        assert_eq!(peers["2"], "guardian-2:7950");
+        let fallback = "  fallback: { type: \"Memory\", cache_size: 10 }\nlimits:";
+        assert!(parse(&yaml.replacen("limits:", fallback, 1), FileFormat::Yaml).is_ok());
+        assert!(parse(&yaml.replacen("id: 1", "id: 0", 1), FileFormat::Yaml).is_err());
+        assert!(parse(&with_peers.replacen("2:", "1:", 1), FileFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn test_validates_local_caching() {
+        let yaml = |primary: &str, option: &str| {
+            let backends = format!("backends:\n  primary: {}\n  {}\n", primary, option);
+            parse(&backends, FileFormat::Yaml)
+        };
+        let memory = r#"{ type: "Memory", cache_size: 100 }"#;
+        let redis = r#"{ type: "Redis", url: "redis://localhost:6379", pool_size: 1 }"#;
+        let batching = yaml(memory, "batch_size: 50").unwrap();
+        assert_eq!(batching.backends.batch_size, Some(50));
+        let cached = yaml(redis, "cache_ttl_ms: 100").unwrap();
+        assert_eq!(cached.backends.cache_ttl_ms, Some(100));
+
+        let leasing = yaml(redis, "lease: { size: 20 }").unwrap();
+        let lease = leasing.backends.lease.unwrap();
+        assert_eq!((lease.size, lease.ttl_ms), (20, 10_000));
+
+        assert!(yaml(memory, "batch_size: 0").is_err());
+        assert!(yaml(memory, "cache_ttl_ms: 100").is_err());
+        assert!(yaml(memory, "lease: { size: 20 }").is_err());
+        assert!(yaml(redis, "lease: { ttl_ms: 0 }").is_err());
+
+        let sliced = yaml(redis, "slices: { node_id: \"guardian-0\" }").unwrap();
+        let slices = sliced.backends.slices.unwrap();
+        assert_eq!(slices.node_id.as_deref(), Some("guardian-0"));
+        assert_eq!((slices.rebalance_ms, slices.min_share), (5000, 0.05));
+        assert!(yaml(memory, "slices: {}").is_err());
+        assert!(yaml(redis, "slices: { min_share: 1.0 }").is_err());
+
+        let regional = yaml(
+            redis,
+            "slices: { region: { name: \"eu-west\", weights: { eu-west: 2, us-east: 1 } } }",
+        )
+        .unwrap();
+        let region = regional.backends.slices.unwrap().region.unwrap();
+        assert_eq!(region.weights["us-east"], 1.0);
+        let exchanged = "slices: { region: { name: \"eu-west\", exchange: { type: \"Redis\", url: \"redis://global:6379\", pool_size: 4 } } }";
+        assert!(yaml(redis, exchanged).is_ok());
+        assert!(yaml(redis, "slices: { region: { name: \"eu-west\" } }").is_err());
+        assert!(yaml(
+            redis,
+            "slices: { region: { name: \"eu-west\", weights: { us-east: 1 } } }"
+        )
+        .is_err());
+        let memory_exchange = "slices: { region: { name: \"eu-west\", exchange: { type: \"Memory\", cache_size: 10 } } }";
+        assert!(yaml(redis, memory_exchange).is_err());
+
+        let affinity = |node: &str| {
+            format!(
+                "affinity: {{ nodes: [\"guardian-0:50051\", \"guardian-1:50051\"], node_id: \"{}\" }}",
+                node
+            )
+        };
+        let owning = yaml(redis, &affinity("guardian-1:50051")).unwrap();
+        assert_eq!(owning.backends.affinity.unwrap().sync_ms, 1000);
+        assert!(yaml(redis, &affinity("guardian-2:50051")).is_err());
+        assert!(yaml(memory, &affinity("guardian-0:50051")).is_err());
+
+        let estimating = yaml(redis, "estimates: { node_id: \"guardian-0\" }").unwrap();
+        assert_eq!(estimating.backends.estimates.unwrap().window_ms, 1000);
+        assert!(yaml(redis, "estimates: { window_ms: 0 }").is_err());
+        assert!(yaml(redis, "estimates: {}\n  slices: {}").is_err());
+
+        let chaos = yaml(memory, "chaos: { latency_ms: 50, error_rate: 0.1 }").unwrap();
+        assert_eq!(chaos.backends.chaos.unwrap().partial_failure_rate, 0.0);
+        assert!(yaml(memory, "chaos: { error_rate: 1.5 }").is_err());
+    }
+
+    #[test]
+    fn test_validates_leader() {
+        let yaml = |primary: &str, leader: &str| {
+            let config = format!("backends:\n  primary: {}\nleader: {}\n", primary, leader);
+            parse(&config, FileFormat::Yaml)
+        };
+        let redis = r#"{ type: "Redis", url: "redis://localhost:6379", pool_size: 1 }"#;
+        let config = yaml(redis, "{ node_id: \"guardian-0\" }").unwrap();
+        let leader = config.leader.unwrap();
+        assert_eq!(leader.lock, "guardian:leader");
+        assert_eq!((leader.ttl_ms, leader.reclaim_leases_secs), (15_000, 60));
+
+        assert!(yaml(redis, "{ ttl_ms: 0 }").is_err());
+        assert!(yaml(r#"{ type: "Memory", cache_size: 100 }"#, "{}").is_err());
+    }
+
+    #[test]
+    fn test_validates_peers() {
+        let yaml = |peers: &str| parse(&format!("peers: {}\n", peers), FileFormat::Yaml);
+        let config = yaml(r#"{ addresses: ["guardian-headless:50051"] }"#).unwrap();
+        let peers = config.peers.unwrap();
+        assert_eq!(peers.addresses, ["guardian-headless:50051"]);
+        assert_eq!(peers.timeout_ms, 500);
+
+        assert!(yaml("{ addresses: [] }").is_err());
+        assert!(yaml(r#"{ addresses: ["guardian-1:50051"], timeout_ms: 0 }"#).is_err());
+    }
+
+    #[test]
+    fn test_backend_flags_override_config() {
+        let args = Args::parse(
+            ["--config=guardian.yaml", "--backend", "redis", "--verbose"]
+                .into_iter()
+                .map(String::from),
+        );
+        assert_eq!(args.config.as_deref(), Some("guardian.yaml"));
+
+        let selection = BackendSelection {
+            kind: args.backend,
+            redis_url: Some("redis://cache:6379".to_string()),
+            redis_nodes: None,
+        };
+        let backend = selection.apply(BackendType::Memory { cache_size: 10 }).unwrap();
+        assert!(matches!(backend, BackendType::Redis { ref url, .. } if url == "redis://cache:6379"));
+
+        let cluster = BackendSelection {
+            kind: Some("redis-cluster".to_string()),
+            redis_url: None,
+            redis_nodes: Some("redis://a:7000, redis://b:7000".to_string()),
+        };
+        let backend = cluster.apply(BackendType::Memory { cache_size: 10 }).unwrap();
+        assert!(matches!(backend, BackendType::RedisCluster { ref nodes } if nodes.len() == 2));
+
+        let unknown = BackendSelection {
+            kind: Some("mongo".to_string()),
+            ..BackendSelection::default()
+        };
+        assert!(unknown.apply(BackendType::Memory { cache_size: 10 }).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_limit_rules_select_bucket_size() {
+        let config = parse(YAML, FileFormat::Yaml).unwrap();
+        let backend = config.build_backend().await.unwrap();
+
+        assert!(backend.take_token("premium:acme", 500).await.unwrap());
+        assert!(!backend.take_token("free:bob", 500).await.unwrap());
+        assert!(backend.take_token("free:bob", 100).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_get_their_own_default() {
+        let yaml = r#"
+namespaces:
+  billing:
+    capacity: 5
+    refill_rate: 1
+"#;
+        let config = parse(yaml, FileFormat::Yaml).unwrap();
+        let backend = config.build_backend().await.unwrap();
+
+        assert!(!backend.take_token("billing/user:1", 10).await.unwrap());
+        assert!(backend.take_token("user:1", 10).await.unwrap());
+        assert!(parse("namespaces:\n  \"a/b\": {capacity: 1, refill_rate: 1}", FileFormat::Yaml).is_err());
+    }
+}
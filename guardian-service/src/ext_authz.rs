@@ -0,0 +1,333 @@
+//! Envoy's external authorization protocol (`envoy.service.auth.v3`), for
+//! meshes whose policy allows `ext_authz` filters but not `rate_limit`
+//! ones.
+//!
+//! Each request becomes the descriptors listed in `global.ext_authz`,
+//! decided exactly as the rate limit service decides Envoy's own, so the
+//! same limit rules apply in either mode. Allowed requests go on, their
+//! responses getting the `ratelimit-*` headers; denied ones are answered
+//! by Envoy with a 429 and `retry-after`.
+
+use std::cmp::Reverse;
+
+use guardian_core::StorageBackend;
+use guardian_proto::CheckLimitResponse;
+use tonic::{Code, Request, Response, Status};
+
+use crate::auth::Principal;
+use crate::config::{DescriptorAction, ExtAuthzConfig};
+use crate::rls::envoy::config::core::v3::header_value_option::HeaderAppendAction;
+use crate::rls::envoy::config::core::v3::{address, HeaderValue, HeaderValueOption};
+use crate::rls::envoy::extensions::common::ratelimit::v3::rate_limit_descriptor::Entry;
+use crate::rls::envoy::extensions::common::ratelimit::v3::RateLimitDescriptor;
+use crate::rls::envoy::r#type::v3::{HttpStatus, StatusCode};
+use crate::rls::envoy::service::auth::v3::authorization_server::Authorization;
+use crate::rls::envoy::service::auth::v3::check_response::HttpResponse;
+use crate::rls::envoy::service::auth::v3::{
+    AttributeContext, CheckRequest, CheckResponse, DeniedHttpResponse, OkHttpResponse,
+};
+use crate::rls::{google, RlsService};
+use crate::GuardianService;
+
+pub use crate::rls::envoy::service::auth::v3::authorization_server::AuthorizationServer;
+
+/// `Check` on top of the rate limit service.
+pub struct ExtAuthzService<B: StorageBackend + 'static> {
+    rls: RlsService<B>,
+    config: ExtAuthzConfig,
+}
+
+impl<B: StorageBackend + 'static> ExtAuthzService<B> {
+    pub fn new(guardian: GuardianService<B>, config: ExtAuthzConfig) -> Self {
+        Self {
+            rls: RlsService::new(guardian),
+            config,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<B: StorageBackend + 'static> Authorization for ExtAuthzService<B> {
+    async fn check(
+        &self,
+        request: Request<CheckRequest>,
+    ) -> Result<Response<CheckResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let attributes = request.into_inner().attributes.unwrap_or_default();
+        let domain = attributes
+            .context_extensions
+            .get("domain")
+            .unwrap_or(&self.config.domain);
+        let descriptors: Vec<_> = self
+            .config
+            .descriptors
+            .iter()
+            .filter_map(|actions| descriptor(actions, &attributes))
+            .collect();
+
+        let results = self
+            .rls
+            .decide(principal.as_ref(), domain, &descriptors, 1)
+            .await?;
+        Ok(Response::new(check_response(tightest(&results))))
+    }
+}
+
+/// The descriptor `actions` build for a request, unless one of them has
+/// nothing to build from.
+fn descriptor(
+    actions: &[DescriptorAction],
+    attributes: &AttributeContext,
+) -> Option<RateLimitDescriptor> {
+    let entries = actions
+        .iter()
+        .map(|action| entry(action, attributes))
+        .collect::<Option<_>>()?;
+    Some(RateLimitDescriptor {
+        entries,
+        hits_addend: None,
+    })
+}
+
+fn entry(action: &DescriptorAction, attributes: &AttributeContext) -> Option<Entry> {
+    let (key, value) = match action {
+        DescriptorAction::RemoteAddress => {
+            let address = attributes.source.as_ref()?.address.as_ref()?;
+            let Some(address::Address::SocketAddress(socket)) = &address.address else {
+                return None;
+            };
+            ("remote_address".to_string(), socket.address.clone())
+        }
+        DescriptorAction::RequestHeaders {
+            header_name,
+            descriptor_key,
+        } => (
+            descriptor_key.clone(),
+            header(attributes, header_name)?.to_string(),
+        ),
+        DescriptorAction::GenericKey {
+            descriptor_value,
+            descriptor_key,
+        } => (descriptor_key.clone(), descriptor_value.clone()),
+    };
+    Some(Entry { key, value })
+}
+
+/// A request header, with the pseudo-headers Envoy may send only as
+/// fields of their own.
+fn header<'a>(attributes: &'a AttributeContext, name: &str) -> Option<&'a str> {
+    let http = attributes.request.as_ref()?.http.as_ref()?;
+    let name = name.to_ascii_lowercase();
+    if let Some(value) = http.headers.get(&name) {
+        return Some(value);
+    }
+    let value = match name.as_str() {
+        ":path" => &http.path,
+        ":method" => &http.method,
+        ":authority" => &http.host,
+        _ => return None,
+    };
+    (!value.is_empty()).then_some(value.as_str())
+}
+
+/// The decision a response reports: the longest denial, or else the bucket
+/// closest to empty.
+fn tightest(results: &[CheckLimitResponse]) -> Option<&CheckLimitResponse> {
+    results.iter().min_by_key(|result| {
+        (
+            result.allowed,
+            Reverse(result.retry_after_seconds),
+            result.remaining_tokens,
+        )
+    })
+}
+
+fn check_response(decision: Option<&CheckLimitResponse>) -> CheckResponse {
+    let ok = |response_headers_to_add| CheckResponse {
+        status: Some(google::rpc::Status {
+            code: Code::Ok as i32,
+            message: String::new(),
+        }),
+        http_response: Some(HttpResponse::OkResponse(OkHttpResponse {
+            headers: Vec::new(),
+            response_headers_to_add,
+        })),
+    };
+    let Some(decision) = decision else {
+        return ok(Vec::new());
+    };
+
+    let reset = decision.retry_after_seconds.to_string();
+    let mut headers = Vec::with_capacity(4);
+    if decision.limit > 0 {
+        headers.push(header_option("ratelimit-limit", decision.limit.to_string()));
+    }
+    headers.push(header_option(
+        "ratelimit-remaining",
+        decision.remaining_tokens.to_string(),
+    ));
+    headers.push(header_option("ratelimit-reset", reset.clone()));
+    if decision.allowed {
+        return ok(headers);
+    }
+    headers.push(header_option("retry-after", reset));
+    CheckResponse {
+        status: Some(google::rpc::Status {
+            code: Code::ResourceExhausted as i32,
+            message: "rate limited".to_string(),
+        }),
+        http_response: Some(HttpResponse::DeniedResponse(DeniedHttpResponse {
+            status: Some(HttpStatus {
+                code: StatusCode::TooManyRequests as i32,
+            }),
+            headers,
+            body: "rate limited".to_string(),
+        })),
+    }
+}
+
+fn header_option(key: &str, value: String) -> HeaderValueOption {
+    HeaderValueOption {
+        header: Some(HeaderValue {
+            key: key.to_string(),
+            value,
+        }),
+        append_action: HeaderAppendAction::OverwriteIfExistsOrAdd as i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rls::descriptor_key;
+    use crate::rls::envoy::config::core::v3::{Address, SocketAddress};
+    use crate::rls::envoy::service::auth::v3::attribute_context::{self, HttpRequest, Peer};
+    use guardian_core::{MemoryBackend, RateLimiter, TokenBucketConfig};
+
+    fn attributes(source: &str, headers: &[(&str, &str)]) -> AttributeContext {
+        AttributeContext {
+            source: Some(Peer {
+                address: Some(Address {
+                    address: Some(address::Address::SocketAddress(SocketAddress {
+                        address: source.to_string(),
+                        port_specifier: None,
+                    })),
+                }),
+                principal: String::new(),
+            }),
+            request: Some(attribute_context::Request {
+                http: Some(HttpRequest {
+                    method: "POST".to_string(),
+                    path: "/checkout".to_string(),
+                    headers: headers
+                        .iter()
+                        .map(|(name, value)| (name.to_string(), value.to_string()))
+                        .collect(),
+                    ..HttpRequest::default()
+                }),
+            }),
+            context_extensions: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_descriptors_from_request_attributes() {
+        let by_key = [
+            DescriptorAction::RequestHeaders {
+                header_name: "X-Api-Key".to_string(),
+                descriptor_key: "api_key".to_string(),
+            },
+            DescriptorAction::RequestHeaders {
+                header_name: ":path".to_string(),
+                descriptor_key: "path".to_string(),
+            },
+        ];
+        let by_address = [
+            DescriptorAction::RemoteAddress,
+            DescriptorAction::GenericKey {
+                descriptor_value: "checkout".to_string(),
+                descriptor_key: "generic_key".to_string(),
+            },
+        ];
+        let with_key = attributes("10.0.0.1", &[("x-api-key", "k1")]);
+        let key = |actions: &[DescriptorAction], attributes: &AttributeContext| {
+            descriptor(actions, attributes).map(|d| descriptor_key("edge", &d))
+        };
+
+        assert_eq!(
+            key(&by_key, &with_key).as_deref(),
+            Some("edge:api_key=k1:path=/checkout")
+        );
+        assert_eq!(
+            key(&by_address, &with_key).as_deref(),
+            Some("edge:remote_address=10.0.0.1:generic_key=checkout")
+        );
+        // No API key, no descriptor.
+        assert_eq!(key(&by_key, &attributes("10.0.0.1", &[])), None);
+    }
+
+    #[tokio::test]
+    async fn test_denies_with_429_once_over_limit() {
+        let config = TokenBucketConfig {
+            capacity: 1,
+            ..TokenBucketConfig::default()
+        };
+        let guardian = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false));
+        let ext_authz = ExtAuthzService::new(
+            guardian,
+            ExtAuthzConfig {
+                domain: "edge".to_string(),
+                descriptors: vec![vec![DescriptorAction::RemoteAddress]],
+            },
+        );
+        let check = |source: &str| {
+            Request::new(CheckRequest {
+                attributes: Some(attributes(source, &[])),
+            })
+        };
+
+        let allowed = ext_authz
+            .check(check("10.0.0.1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(allowed.status.unwrap().code, Code::Ok as i32);
+        let Some(HttpResponse::OkResponse(ok)) = allowed.http_response else {
+            panic!("expected an OK response");
+        };
+        assert!(ok
+            .response_headers_to_add
+            .iter()
+            .any(|h| h.header.as_ref().unwrap().key == "ratelimit-remaining"));
+
+        let denied = ext_authz
+            .check(check("10.0.0.1"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(denied.status.unwrap().code, Code::ResourceExhausted as i32);
+        let Some(HttpResponse::DeniedResponse(denied)) = denied.http_response else {
+            panic!("expected a denied response");
+        };
+        assert_eq!(
+            denied.status.unwrap().code,
+            StatusCode::TooManyRequests as i32
+        );
+        assert!(denied
+            .headers
+            .iter()
+            .any(|h| h.header.as_ref().unwrap().key == "retry-after"));
+
+        // A route naming its own domain gets buckets of its own.
+        let mut other_domain = check("10.0.0.1");
+        other_domain
+            .get_mut()
+            .attributes
+            .as_mut()
+            .unwrap()
+            .context_extensions
+            .insert("domain".to_string(), "admin".to_string());
+        let allowed = ext_authz.check(other_domain).await.unwrap().into_inner();
+        assert_eq!(allowed.status.unwrap().code, Code::Ok as i32);
+    }
+}
@@ -0,0 +1,350 @@
+//! JSON over HTTP for clients that can't speak gRPC:
+//!
+//! * `POST /v1/check` with `{"key": "...", "cost": 1}`
+//! * `GET /v1/usage/{key}`
+//! * `POST /v1/reset` with `{"key": "...", "admin_token": "..."}`
+//!
+//! Bodies may add a `"namespace"`, and usage lookups a `?namespace=` query.
+//!
+//! Check responses carry the IETF `RateLimit-Limit` / `RateLimit-Remaining`
+//! / `RateLimit-Reset` headers, and denials are `429 Too Many Requests` with `Retry-After`.
+//! Authentication uses the same `x-api-key` or bearer token headers as the
+//! gRPC API.
+
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use guardian_core::StorageBackend;
+use serde::{Deserialize, Serialize};
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Code, Status};
+
+use crate::auth::{AuthInterceptor, Principal};
+use guardian_proto::{
+    CheckLimitRequest, CheckLimitResponse, GetUsageRequest, ResetLimitRequest,
+};
+use crate::GuardianService;
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+pub struct Gateway<B: StorageBackend + 'static> {
+    service: GuardianService<B>,
+    auth: Option<AuthInterceptor>,
+}
+
+impl<B: StorageBackend + 'static> Clone for Gateway<B> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            auth: self.auth.clone(),
+        }
+    }
+}
+
+impl<B: StorageBackend + 'static> Gateway<B> {
+    pub fn new(service: GuardianService<B>, auth: Option<AuthInterceptor>) -> Self {
+        Self { service, auth }
+    }
+
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/v1/check", post(check::<B>))
+            .route("/v1/usage/:key", get(usage::<B>))
+            .route("/v1/reset", post(reset::<B>))
+            .with_state(self)
+    }
+
+    /// Run the caller's headers through the gRPC auth interceptor.
+    #[allow(clippy::result_large_err)]
+    fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>, Status> {
+        let Some(mut auth) = self.auth.clone() else {
+            return Ok(None);
+        };
+        let request = tonic::Request::from_parts(
+            MetadataMap::from_headers(headers.clone()),
+            Default::default(),
+            (),
+        );
+        let request = auth.call(request)?;
+        Ok(request.extensions().get::<Principal>().cloned())
+    }
+}
+
+/// Serve the gateway on `listener` until the service starts shutting down.
+pub async fn serve<B: StorageBackend + 'static>(
+    listener: tokio::net::TcpListener,
+    gateway: Gateway<B>,
+) -> std::io::Result<()> {
+    let service = gateway.service.clone();
+    axum::serve(listener, gateway.router())
+        .with_graceful_shutdown(async move { service.stopping().await })
+        .await
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckBody {
+    key: String,
+    #[serde(default = "default_cost")]
+    cost: u32,
+    #[serde(default)]
+    tier: String,
+    #[serde(default)]
+    namespace: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UsageQuery {
+    #[serde(default)]
+    namespace: String,
+}
+
+fn default_cost() -> u32 {
+    1
+}
+
+#[derive(Debug, Serialize)]
+struct CheckReply {
+    allowed: bool,
+    remaining: u64,
+    retry_after_seconds: u32,
+    /// Why: `within_limit`, `bucket_exhausted`, `rule_matched`, ...
+    reason: String,
+}
+
+#[derive(Debug, Serialize)]
+struct UsageReply {
+    key: String,
+    used_tokens: u64,
+    total_capacity: u64,
+    refill_rate: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResetBody {
+    key: String,
+    #[serde(default)]
+    admin_token: String,
+    #[serde(default)]
+    namespace: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ResetReply {
+    success: bool,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorReply {
+    error: String,
+}
+
+async fn check<B: StorageBackend + 'static>(
+    State(gateway): State<Gateway<B>>,
+    headers: HeaderMap,
+    Json(body): Json<CheckBody>,
+) -> Response {
+    let principal = match gateway.authenticate(&headers) {
+        Ok(principal) => principal,
+        Err(status) => return error_response(status),
+    };
+    let request = CheckLimitRequest {
+        client_id: body.key,
+        cost: body.cost,
+        override_config: None,
+        tier: body.tier,
+        namespace: body.namespace,
+        refundable: false,
+    };
+    match gateway.service.decide(principal.as_ref(), request).await {
+        Ok(decision) => check_response(decision),
+        Err(status) => error_response(status),
+    }
+}
+
+async fn usage<B: StorageBackend + 'static>(
+    State(gateway): State<Gateway<B>>,
+    headers: HeaderMap,
+    Path(key): Path<String>,
+    Query(query): Query<UsageQuery>,
+) -> Response {
+    let principal = match gateway.authenticate(&headers) {
+        Ok(principal) => principal,
+        Err(status) => return error_response(status),
+    };
+    let request = GetUsageRequest {
+        client_id: key.clone(),
+        namespace: query.namespace,
+    };
+    match gateway.service.usage(principal.as_ref(), request).await {
+        Ok(usage) => Json(UsageReply {
+            key,
+            used_tokens: usage.used_tokens,
+            total_capacity: usage.total_capacity,
+            refill_rate: usage.refill_rate,
+        })
+        .into_response(),
+        Err(status) => error_response(status),
+    }
+}
+
+async fn reset<B: StorageBackend + 'static>(
+    State(gateway): State<Gateway<B>>,
+    headers: HeaderMap,
+    Json(body): Json<ResetBody>,
+) -> Response {
+    let principal = match gateway.authenticate(&headers) {
+        Ok(principal) => principal,
+        Err(status) => return error_response(status),
+    };
+    let request = ResetLimitRequest {
+        client_id: body.key,
+        admin_token: body.admin_token,
+        namespace: body.namespace,
+    };
+    match gateway
+        .service
+        .reset(principal.as_ref(), request, false)
+        .await
+    {
+        Ok(reply) => {
+            let status = if reply.success {
+                StatusCode::OK
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            let reply = ResetReply {
+                success: reply.success,
+                message: reply.message,
+            };
+            (status, Json(reply)).into_response()
+        }
+        Err(status) => error_response(status),
+    }
+}
+
+fn check_response(decision: CheckLimitResponse) -> Response {
+    let mut headers = HeaderMap::new();
+    if decision.limit > 0 {
+        headers.insert(RATELIMIT_LIMIT, HeaderValue::from(decision.limit));
+    }
+    headers.insert(
+        RATELIMIT_REMAINING,
+        HeaderValue::from(decision.remaining_tokens),
+    );
+    headers.insert(
+        RATELIMIT_RESET,
+        HeaderValue::from(decision.retry_after_seconds),
+    );
+
+    let status = if decision.allowed {
+        StatusCode::OK
+    } else {
+        headers.insert(
+            axum::http::header::RETRY_AFTER,
+            HeaderValue::from(decision.retry_after_seconds),
+        );
+        StatusCode::TOO_MANY_REQUESTS
+    };
+    let reply = CheckReply {
+        allowed: decision.allowed,
+        remaining: decision.remaining_tokens,
+        retry_after_seconds: decision.retry_after_seconds,
+        reason: decision.reason,
+    };
+    (status, headers, Json(reply)).into_response()
+}
+
+fn error_response(status: Status) -> Response {
+    let code = match status.code() {
+        Code::InvalidArgument => StatusCode::BAD_REQUEST,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let reply = ErrorReply {
+        error: status.message().to_string(),
+    };
+    (code, Json(reply)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use guardian_core::{MemoryBackend, RateLimiter, TokenBucketConfig};
+    use tower::ServiceExt;
+
+    fn check_request(key: &str) -> Request<Body> {
+        Request::post("/v1/check")
+            .header("content-type", "application/json")
+            .body(Body::from(format!(r#"{{"key": "{}"}}"#, key)))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_check_sets_ratelimit_headers_and_429() {
+        let config = TokenBucketConfig {
+            capacity: 1,
+            ..TokenBucketConfig::default()
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false));
+        let router = Gateway::new(service, None).router();
+
+        let allowed = router
+            .clone()
+            .oneshot(check_request("user1"))
+            .await
+            .unwrap();
+        assert_eq!(allowed.status(), StatusCode::OK);
+        assert!(allowed.headers().contains_key(RATELIMIT_REMAINING));
+        assert!(allowed.headers().contains_key(RATELIMIT_LIMIT));
+
+        let denied = router
+            .clone()
+            .oneshot(check_request("user1"))
+            .await
+            .unwrap();
+        assert_eq!(denied.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(denied.headers().contains_key("retry-after"));
+        let body = axum::body::to_bytes(denied.into_body(), 1024)
+            .await
+            .unwrap();
+        let reply: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["allowed"], false);
+        assert_eq!(reply["reason"], "bucket_exhausted");
+    }
+
+    #[tokio::test]
+    async fn test_reset_requires_admin_token() {
+        let service = GuardianService::new(RateLimiter::new(
+            MemoryBackend::new(TokenBucketConfig::default()),
+            false,
+        ))
+        .with_admin_token(Some("secret".to_string()));
+        let router = Gateway::new(service, None).router();
+        let reset = |token: &str| {
+            Request::post("/v1/reset")
+                .header("content-type", "application/json")
+                .body(Body::from(format!(
+                    r#"{{"key": "user1", "admin_token": "{}"}}"#,
+                    token
+                )))
+                .unwrap()
+        };
+
+        let denied = router.clone().oneshot(reset("wrong")).await.unwrap();
+        assert_eq!(denied.status(), StatusCode::FORBIDDEN);
+        let ok = router.clone().oneshot(reset("secret")).await.unwrap();
+        assert_eq!(ok.status(), StatusCode::OK);
+    }
+}
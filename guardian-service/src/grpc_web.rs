@@ -0,0 +1,171 @@
+// grpc-web, so browsers and edge workers can call the service without a
+// proxy. grpc-web requests (HTTP/1.1 or HTTP/2) are translated to gRPC in
+// front of everything else, sheds included, and answered with the CORS
+// headers a page on another origin needs, allowing the credential headers
+// `auth` reads. Plain gRPC passes through untouched.
+
+use tonic::body::BoxBody;
+use tonic::codegen::{http, BoxFuture, Context, Poll, Service};
+use tonic_web::{GrpcWebLayer, GrpcWebService};
+use tower::Layer;
+use tower_http::cors::{AllowOrigin, Cors, CorsLayer};
+
+use crate::auth::API_KEY_HEADER;
+use crate::config::GrpcWebConfig;
+
+/// How long browsers may cache a preflight answer.
+const PREFLIGHT_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Request headers a grpc-web caller may send: the protocol's own,
+/// credentials, and the W3C trace context a traced page propagates.
+const ALLOWED_HEADERS: [&str; 8] = [
+    "content-type",
+    "x-grpc-web",
+    "x-user-agent",
+    "grpc-timeout",
+    API_KEY_HEADER,
+    "authorization",
+    "traceparent",
+    "tracestate",
+];
+
+/// Response headers a browser script may read: the call's status, and
+/// when to retry after a shed.
+const EXPOSED_HEADERS: [&str; 4] = [
+    "grpc-status",
+    "grpc-message",
+    "grpc-status-details-bin",
+    "retry-after",
+];
+
+/// Serves grpc-web on a tonic server when configured; otherwise a no-op.
+#[derive(Clone)]
+pub struct GrpcWebSupport(Option<CorsLayer>);
+
+impl GrpcWebSupport {
+    pub fn new(config: Option<&GrpcWebConfig>) -> Self {
+        Self(config.map(cors))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.0.is_some()
+    }
+}
+
+/// CORS for the origins in `config`, or for any origin if it lists none.
+fn cors(config: &GrpcWebConfig) -> CorsLayer {
+    let origins = if config.allowed_origins.is_empty() {
+        AllowOrigin::mirror_request()
+    } else {
+        // Checked when the config was loaded.
+        AllowOrigin::list(
+            config
+                .allowed_origins
+                .iter()
+                .filter_map(|origin| origin.parse().ok()),
+        )
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_credentials(true)
+        .max_age(PREFLIGHT_MAX_AGE)
+        .allow_headers(ALLOWED_HEADERS.map(http::HeaderName::from_static))
+        .expose_headers(EXPOSED_HEADERS.map(http::HeaderName::from_static))
+}
+
+impl<S> Layer<S> for GrpcWebSupport
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<BoxBody>> + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>> + Send + 'static,
+{
+    type Service = WithGrpcWeb<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        match &self.0 {
+            Some(cors) => WithGrpcWeb::On(Box::new(cors.layer(GrpcWebLayer::new().layer(inner)))),
+            None => WithGrpcWeb::Off(inner),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum WithGrpcWeb<S> {
+    On(Box<Cors<GrpcWebService<S>>>),
+    Off(S),
+}
+
+impl<S> Service<http::Request<BoxBody>> for WithGrpcWeb<S>
+where
+    S: Service<http::Request<BoxBody>, Response = http::Response<BoxBody>> + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn std::error::Error + Send + Sync>> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self {
+            Self::On(web) => web.poll_ready(cx),
+            Self::Off(inner) => inner.poll_ready(cx),
+        }
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        match self {
+            Self::On(web) => Box::pin(web.call(request)),
+            Self::Off(inner) => Box::pin(inner.call(request)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_answers_preflights_from_allowed_origins() {
+        let config = GrpcWebConfig {
+            allowed_origins: vec!["https://dash.example.com".to_string()],
+        };
+        let inner = tower::service_fn(|_: http::Request<BoxBody>| async {
+            Ok::<_, std::convert::Infallible>(http::Response::new(tonic::body::empty_body()))
+        });
+        let service = GrpcWebSupport::new(Some(&config)).layer(inner);
+        let preflight = |origin| {
+            http::Request::builder()
+                .method("OPTIONS")
+                .uri("/guardian.RateLimiter/CheckLimit")
+                .header("origin", origin)
+                .header("access-control-request-method", "POST")
+                .header("access-control-request-headers", "x-api-key,traceparent")
+                .body(tonic::body::empty_body())
+                .unwrap()
+        };
+
+        let allowed = service
+            .clone()
+            .oneshot(preflight("https://dash.example.com"))
+            .await
+            .unwrap();
+        let headers = allowed.headers();
+        assert_eq!(
+            headers["access-control-allow-origin"],
+            "https://dash.example.com"
+        );
+        let allow_headers = headers["access-control-allow-headers"].to_str().unwrap();
+        assert!(allow_headers.contains(API_KEY_HEADER));
+        assert!(allow_headers.contains("traceparent"));
+
+        let refused = service
+            .oneshot(preflight("https://elsewhere.example.com"))
+            .await
+            .unwrap();
+        assert!(!refused
+            .headers()
+            .contains_key("access-control-allow-origin"));
+        assert!(!GrpcWebSupport::new(None).enabled());
+    }
+}
@@ -0,0 +1,91 @@
+// grpc.health.v1 reporting driven by periodic storage backend probes.
+
+use guardian_core::StorageBackend;
+use std::time::Duration;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use guardian_proto::rate_limiter_server::SERVICE_NAME;
+
+/// How often the backend is probed.
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Probe `backend` forever, marking both the Guardian service and the
+/// server as a whole (the empty service name) NOT_SERVING while it fails.
+pub async fn report_backend_health(
+    mut reporter: HealthReporter,
+    backend: impl StorageBackend,
+    interval: Duration,
+) {
+    let mut last = None;
+    loop {
+        let status = match backend.health_check().await {
+            Ok(()) => ServingStatus::Serving,
+            Err(e) => {
+                tracing::warn!(error = %e, "storage backend health check failed");
+                ServingStatus::NotServing
+            }
+        };
+
+        if last != Some(status) {
+            reporter.set_service_status("", status).await;
+            reporter.set_service_status(SERVICE_NAME, status).await;
+            last = Some(status);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_core::TokenBucketConfig;
+    use guardian_test::MockBackend;
+    use std::sync::Arc;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic_health::pb::health_check_response::ServingStatus as Reported;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+
+    #[tokio::test]
+    async fn test_backend_failure_marks_not_serving() {
+        let backend = Arc::new(MockBackend::new(TokenBucketConfig::default()));
+        let (reporter, health) = tonic_health::server::health_reporter();
+        tokio::spawn(report_backend_health(
+            reporter,
+            Arc::clone(&backend),
+            Duration::from_millis(10),
+        ));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(health)
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let client = HealthClient::new(channel);
+
+        let status = || {
+            let mut client = client.clone();
+            async move {
+                let request = HealthCheckRequest {
+                    service: SERVICE_NAME.to_string(),
+                };
+                client.check(request).await.unwrap().into_inner().status
+            }
+        };
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(status().await, Reported::Serving as i32);
+
+        backend.fail_all("down");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(status().await, Reported::NotServing as i32);
+    }
+}
@@ -0,0 +1,239 @@
+// Heavy hitters: the keys spending the most tokens and collecting the most
+// denials, found with the Space-Saving algorithm. Each ranking keeps a
+// fixed number of counters however many keys the service sees, and counts
+// halve every window so a key that quiets down drops out of the top.
+
+use guardian_core::{BucketSnapshot, KeyRanking, KeyStats, LimitResult, Observer};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// A key's counter within one ranking.
+struct Counter {
+    key: Arc<str>,
+    /// Estimated weight: never less than the key's true weight, and more
+    /// by at most the count of the counter it took over.
+    count: u64,
+    /// What was seen of the key while it held a counter.
+    stats: KeyStats,
+}
+
+/// The Space-Saving summary: `capacity` counters, each new key taking
+/// over the smallest once they are all in use.
+struct SpaceSaving {
+    capacity: usize,
+    counters: HashMap<Arc<str>, Counter>,
+    /// Counters ordered by count, for finding the smallest.
+    by_count: BTreeSet<(u64, Arc<str>)>,
+}
+
+impl SpaceSaving {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counters: HashMap::new(),
+            by_count: BTreeSet::new(),
+        }
+    }
+
+    /// Add `weight` to `key`'s count and fold the request into its stats.
+    /// Requests without weight only update keys already counted.
+    fn offer(&mut self, key: &str, weight: u64, update: impl FnOnce(&mut KeyStats)) {
+        if let Some(counter) = self.counters.get_mut(key) {
+            update(&mut counter.stats);
+            if weight > 0 {
+                self.by_count
+                    .remove(&(counter.count, Arc::clone(&counter.key)));
+                counter.count += weight;
+                self.by_count
+                    .insert((counter.count, Arc::clone(&counter.key)));
+            }
+            return;
+        }
+        if weight == 0 || self.capacity == 0 {
+            return;
+        }
+        let inherited = if self.counters.len() < self.capacity {
+            0
+        } else {
+            let (count, evicted) = self.by_count.pop_first().expect("counters are full");
+            self.counters.remove(&evicted);
+            count
+        };
+        let key: Arc<str> = key.into();
+        let mut stats = KeyStats::default();
+        update(&mut stats);
+        self.by_count.insert((inherited + weight, Arc::clone(&key)));
+        self.counters.insert(
+            Arc::clone(&key),
+            Counter {
+                key,
+                count: inherited + weight,
+                stats,
+            },
+        );
+    }
+
+    /// Divide every count by `2^halvings`, dropping counters that reach 0.
+    fn decay(&mut self, halvings: u32) {
+        let shift = |n: u64| n.checked_shr(halvings).unwrap_or(0);
+        self.counters.retain(|_, counter| {
+            counter.count = shift(counter.count);
+            counter.stats = KeyStats {
+                allowed: shift(counter.stats.allowed),
+                denied: shift(counter.stats.denied),
+                consumed: shift(counter.stats.consumed),
+            };
+            counter.count > 0
+        });
+        self.by_count = self
+            .counters
+            .values()
+            .map(|counter| (counter.count, Arc::clone(&counter.key)))
+            .collect();
+    }
+
+    /// Counted keys, heaviest first.
+    fn ranked(&self) -> impl Iterator<Item = &Counter> {
+        self.by_count
+            .iter()
+            .rev()
+            .map(|(_, key)| &self.counters[key])
+    }
+}
+
+struct Rankings {
+    /// Ranked by tokens consumed.
+    usage: SpaceSaving,
+    /// Ranked by denied requests.
+    denials: SpaceSaving,
+    decayed_at: Instant,
+}
+
+/// Limiter observer tracking the heaviest keys for TopKeys and the
+/// `guardian_heavy_hitter_*` metrics.
+pub struct HeavyHitters {
+    rankings: Mutex<Rankings>,
+    window: Duration,
+}
+
+impl HeavyHitters {
+    /// Track up to `capacity` keys per ranking, halving counts every
+    /// `window`.
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        Self {
+            rankings: Mutex::new(Rankings {
+                usage: SpaceSaving::new(capacity),
+                denials: SpaceSaving::new(capacity),
+                decayed_at: Instant::now(),
+            }),
+            window,
+        }
+    }
+
+    fn record(&self, key: &str, cost: u64, allowed: bool) {
+        let update = |stats: &mut KeyStats| {
+            if allowed {
+                stats.allowed += 1;
+                stats.consumed += cost;
+            } else {
+                stats.denied += 1;
+            }
+        };
+        let mut rankings = self.rankings();
+        rankings
+            .usage
+            .offer(key, if allowed { cost } else { 0 }, update);
+        rankings
+            .denials
+            .offer(key, if allowed { 0 } else { 1 }, update);
+    }
+
+    /// The `n` heaviest keys by `ranking`, with their stats. The ranked
+    /// figure (`consumed` or `denied`) is the estimate the ranking is by,
+    /// which may overstate a key by what it inherited on entering the top;
+    /// the others count what was seen of it since.
+    pub fn top(&self, n: usize, ranking: KeyRanking) -> Vec<(String, KeyStats)> {
+        let rankings = self.rankings();
+        let summary = match ranking {
+            KeyRanking::Usage => &rankings.usage,
+            KeyRanking::Denials => &rankings.denials,
+        };
+        summary
+            .ranked()
+            .take(n)
+            .map(|counter| {
+                let stats = match ranking {
+                    KeyRanking::Usage => KeyStats {
+                        consumed: counter.count,
+                        ..counter.stats
+                    },
+                    KeyRanking::Denials => KeyStats {
+                        denied: counter.count,
+                        ..counter.stats
+                    },
+                };
+                (counter.key.to_string(), stats)
+            })
+            .collect()
+    }
+
+    /// The rankings, decayed for any windows passed since last time.
+    fn rankings(&self) -> MutexGuard<'_, Rankings> {
+        let mut rankings = self.rankings.lock().unwrap();
+        let elapsed = rankings.decayed_at.elapsed().as_nanos();
+        let window = self.window.as_nanos().max(1);
+        if elapsed >= window {
+            let halvings = u32::try_from(elapsed / window).unwrap_or(u32::MAX);
+            rankings.usage.decay(halvings);
+            rankings.denials.decay(halvings);
+            // Keep the windows aligned to when tracking started.
+            let into_window = Duration::from_nanos((elapsed % window) as u64);
+            rankings.decayed_at = Instant::now() - into_window;
+        }
+        rankings
+    }
+}
+
+impl Observer for HeavyHitters {
+    fn on_decision(&self, _key: &str, _result: &LimitResult, _bucket: Option<&BucketSnapshot>) {}
+
+    fn on_check(&self, key: &str, cost: u64, result: &LimitResult, _latency: Duration) {
+        self.record(key, cost, *result == LimitResult::Allowed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heavy_keys_survive_a_long_tail() {
+        let hitters = HeavyHitters::new(4, Duration::from_secs(300));
+        for round in 0..100 {
+            hitters.record("api:heavy", 10, true);
+            hitters.record("api:abuser", 1, false);
+            hitters.record(&format!("api:tail-{}", round), 1, true);
+        }
+
+        let usage = hitters.top(2, KeyRanking::Usage);
+        assert_eq!(usage[0].0, "api:heavy");
+        assert_eq!(usage[0].1.consumed, 1000);
+        assert_eq!(usage[0].1.allowed, 100);
+        let denials = hitters.top(1, KeyRanking::Denials);
+        assert_eq!(denials[0].0, "api:abuser");
+        assert_eq!(denials[0].1.denied, 100);
+        // Only the counters' worth of keys is ever held.
+        assert_eq!(hitters.top(10, KeyRanking::Usage).len(), 4);
+    }
+
+    #[test]
+    fn test_counts_halve_every_window() {
+        let hitters = HeavyHitters::new(4, Duration::from_millis(200));
+        hitters.record("api:burst", 8, true);
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(hitters.top(1, KeyRanking::Usage)[0].1.consumed, 4);
+        std::thread::sleep(Duration::from_millis(800));
+        assert!(hitters.top(1, KeyRanking::Usage).is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+// Recent latency of storage backend calls, per backend and operation, for
+// GetStats and the `guardian_backend_latency_quantile_seconds` gauges. The
+// Prometheus histogram counts since startup, which suits `rate()` but can't
+// say how slow Redis is right now; here calls are also counted into fine
+// log-scaled buckets that roll over every window.
+
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Buckets per doubling of latency. Each bucket is 2^(1/4) times as wide as
+/// the one before, so a percentile read off its bucket's upper bound
+/// overstates the true latency by at most 19%.
+const BUCKETS_PER_DOUBLING: f64 = 4.0;
+
+/// Bucket 0 holds calls of up to a microsecond, the last every call over
+/// 2^24µs (about 17s).
+const BUCKETS: usize = 97;
+
+/// Calls to one operation of one backend within a window.
+#[derive(Clone)]
+struct Histogram {
+    buckets: Vec<u64>,
+    errors: u64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS],
+            errors: 0,
+        }
+    }
+
+    fn bucket(latency: Duration) -> usize {
+        let micros = latency.as_secs_f64() * 1e6;
+        if micros <= 1.0 {
+            return 0;
+        }
+        ((micros.log2() * BUCKETS_PER_DOUBLING).ceil() as usize).min(BUCKETS - 1)
+    }
+
+    fn upper_bound(bucket: usize) -> Duration {
+        Duration::from_secs_f64((bucket as f64 / BUCKETS_PER_DOUBLING).exp2() / 1e6)
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+        self.errors += other.errors;
+    }
+
+    fn calls(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// The latency `quantile` (0 to 1) of the calls fall within.
+    fn quantile(&self, quantile: f64) -> Duration {
+        let rank = ((self.calls() as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Self::upper_bound(bucket);
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+/// Percentiles of one backend operation over the recent window.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OperationLatency {
+    pub backend: &'static str,
+    pub operation: &'static str,
+    pub calls: u64,
+    pub errors: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+type Key = (&'static str, &'static str);
+
+struct Windows {
+    current: BTreeMap<Key, Histogram>,
+    previous: BTreeMap<Key, Histogram>,
+    started: Instant,
+}
+
+/// Backend call latencies over the current and previous window.
+pub struct LatencyTracker {
+    windows: Mutex<Windows>,
+    window: Duration,
+}
+
+impl LatencyTracker {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            windows: Mutex::new(Windows {
+                current: BTreeMap::new(),
+                previous: BTreeMap::new(),
+                started: Instant::now(),
+            }),
+            window,
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn record(
+        &self,
+        backend: &'static str,
+        operation: &'static str,
+        latency: Duration,
+        failed: bool,
+    ) {
+        let mut windows = self.windows();
+        let histogram = windows
+            .current
+            .entry((backend, operation))
+            .or_insert_with(Histogram::new);
+        histogram.buckets[Histogram::bucket(latency)] += 1;
+        if failed {
+            histogram.errors += 1;
+        }
+    }
+
+    /// Percentiles of every operation called within the last one to two
+    /// windows, by backend and operation.
+    pub fn recent(&self) -> Vec<OperationLatency> {
+        let windows = self.windows();
+        let mut merged = windows.previous.clone();
+        for (key, histogram) in &windows.current {
+            merged
+                .entry(*key)
+                .or_insert_with(Histogram::new)
+                .merge(histogram);
+        }
+        merged
+            .into_iter()
+            .map(|((backend, operation), histogram)| OperationLatency {
+                backend,
+                operation,
+                calls: histogram.calls(),
+                errors: histogram.errors,
+                p50: histogram.quantile(0.5),
+                p95: histogram.quantile(0.95),
+                p99: histogram.quantile(0.99),
+            })
+            .collect()
+    }
+
+    /// The windows, rolled over if the current one has ended.
+    fn windows(&self) -> MutexGuard<'_, Windows> {
+        let mut windows = self.windows.lock().unwrap();
+        let elapsed = windows.started.elapsed();
+        if elapsed >= self.window {
+            let current = std::mem::take(&mut windows.current);
+            // Calls from before the last full window are too old to report.
+            windows.previous = if elapsed < self.window * 2 {
+                current
+            } else {
+                BTreeMap::new()
+            };
+            windows.started = Instant::now();
+        }
+        windows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles_per_operation() {
+        let tracker = LatencyTracker::new(Duration::from_secs(60));
+        for n in 1..=100 {
+            tracker.record("redis", "take_token", Duration::from_micros(n * 100), false);
+        }
+        tracker.record("redis", "reset", Duration::from_millis(30), true);
+
+        let recent = tracker.recent();
+        let reset = &recent[0];
+        assert_eq!(
+            (reset.operation, reset.calls, reset.errors),
+            ("reset", 1, 1)
+        );
+        assert!(reset.p99 >= Duration::from_millis(30));
+
+        let take = &recent[1];
+        assert_eq!((take.backend, take.calls, take.errors), ("redis", 100, 0));
+        // Within the width of a bucket above the true figures.
+        for (got, want) in [(take.p50, 5_000), (take.p95, 9_500), (take.p99, 9_900)] {
+            let want = Duration::from_micros(want);
+            assert!(
+                got >= want && got <= want.mul_f64(1.19),
+                "{:?} vs {:?}",
+                got,
+                want
+            );
+        }
+    }
+
+    #[test]
+    fn test_old_windows_are_forgotten() {
+        let tracker = LatencyTracker::new(Duration::from_millis(20));
+        tracker.record("memory", "get_usage", Duration::from_micros(3), false);
+        std::thread::sleep(Duration::from_millis(25));
+        // Still counted from the previous window.
+        assert_eq!(tracker.recent()[0].calls, 1);
+        std::thread::sleep(Duration::from_millis(45));
+        assert!(tracker.recent().is_empty());
+    }
+}
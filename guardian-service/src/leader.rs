@@ -0,0 +1,226 @@
+// Leader election: the instances sharing a store compete for a lock in it,
+// and whichever holds the lock runs the cluster-wide maintenance jobs, so
+// each job runs once per interval rather than once per instance. When the
+// leader goes away, another instance takes over within the lock's TTL.
+
+use async_trait::async_trait;
+use guardian_core::{RateLimitError, StorageBackend};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+use crate::config::{default_node_id, LeaderConfig};
+
+/// Keys requested per `list_keys` page by jobs visiting every key.
+const SCAN_PAGE: usize = 1000;
+
+/// Periodic work only the leader does. Leadership can pass on while a run
+/// is under way, so two instances running a job back to back must be
+/// harmless.
+#[async_trait]
+pub trait Job: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn interval(&self) -> Duration;
+    async fn run(&self) -> Result<(), RateLimitError>;
+}
+
+pub struct Election {
+    backend: Arc<dyn StorageBackend>,
+    node: String,
+    lock: String,
+    ttl: Duration,
+    leading: AtomicBool,
+}
+
+impl Election {
+    pub fn new(config: &LeaderConfig, backend: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            backend,
+            node: config.node_id.clone().unwrap_or_else(default_node_id),
+            lock: config.lock.clone(),
+            ttl: Duration::from_millis(config.ttl_ms),
+            leading: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether this instance held the lock at its last attempt.
+    pub fn is_leader(&self) -> bool {
+        self.leading.load(Ordering::Acquire)
+    }
+
+    /// Take the lock, or keep it if this instance has it. A store that
+    /// can't be reached counts as losing it: by the time it answers again
+    /// the lock may have lapsed and gone to another instance.
+    pub async fn campaign(&self) -> bool {
+        let held = match self
+            .backend
+            .hold_lock(&self.lock, &self.node, self.ttl)
+            .await
+        {
+            Ok(held) => held,
+            Err(e) => {
+                tracing::warn!(lock = %self.lock, error = %e, "leader election failed");
+                false
+            }
+        };
+        if self.leading.swap(held, Ordering::AcqRel) != held {
+            tracing::info!(node = %self.node, leading = held, "leadership changed");
+        }
+        held
+    }
+
+    /// Campaign every third of the TTL, and run each job on its interval
+    /// while leading. Abort the tasks before [`resign`](Self::resign)ing.
+    pub fn start(self: &Arc<Self>, jobs: Vec<Box<dyn Job>>) -> Vec<JoinHandle<()>> {
+        let election = Arc::clone(self);
+        let mut tasks = vec![tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(election.ttl / 3);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                election.campaign().await;
+            }
+        })];
+        for job in jobs {
+            let election = Arc::clone(self);
+            tasks.push(tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(job.interval());
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                // The first tick is immediate, before any campaign.
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    if !election.is_leader() {
+                        continue;
+                    }
+                    if let Err(e) = job.run().await {
+                        tracing::warn!(job = job.name(), error = %e, "leader job failed");
+                    }
+                }
+            }));
+        }
+        tasks
+    }
+
+    /// Give the lock up, so another instance takes over without waiting
+    /// for it to lapse.
+    pub async fn resign(&self) {
+        if !self.leading.swap(false, Ordering::AcqRel) {
+            return;
+        }
+        if let Err(e) = self.backend.release_lock(&self.lock, &self.node).await {
+            tracing::warn!(lock = %self.lock, error = %e, "failed to release leadership");
+        }
+    }
+}
+
+/// Reclaims lapsed leases on every key. The store otherwise reclaims a
+/// key's leases only when the key is leased from again, so tokens leased by
+/// an instance that died stay spent on keys nobody uses any more until the
+/// bucket expires.
+pub struct ReclaimLeases {
+    backend: Arc<dyn StorageBackend>,
+    interval: Duration,
+}
+
+impl ReclaimLeases {
+    pub fn new(backend: Arc<dyn StorageBackend>, interval: Duration) -> Self {
+        Self { backend, interval }
+    }
+}
+
+#[async_trait]
+impl Job for ReclaimLeases {
+    fn name(&self) -> &'static str {
+        "reclaim_leases"
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    async fn run(&self) -> Result<(), RateLimitError> {
+        let mut after: Option<String> = None;
+        let mut visited = 0;
+        loop {
+            let page = self
+                .backend
+                .list_keys("*", after.as_deref(), SCAN_PAGE)
+                .await?;
+            for key in &page {
+                // Listing a key's leases drops the lapsed ones.
+                if let Err(e) = self.backend.leases(key).await {
+                    tracing::debug!(%key, error = %e, "leases not reclaimed");
+                }
+            }
+            visited += page.len();
+            if page.len() < SCAN_PAGE {
+                break;
+            }
+            after = page.last().cloned();
+        }
+        tracing::debug!(keys = visited, "reclaimed lapsed leases");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_core::{MemoryBackend, TokenBucketConfig};
+
+    fn config(node: &str) -> LeaderConfig {
+        LeaderConfig {
+            node_id: Some(node.to_string()),
+            lock: "guardian:leader".to_string(),
+            ttl_ms: 60_000,
+            reclaim_leases_secs: 60,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_one_leader_until_it_resigns() {
+        let shared: Arc<dyn StorageBackend> =
+            Arc::new(MemoryBackend::new(TokenBucketConfig::default()));
+        let a = Election::new(&config("a"), Arc::clone(&shared));
+        let b = Election::new(&config("b"), shared);
+
+        assert!(a.campaign().await);
+        assert!(!b.campaign().await);
+        assert!(a.campaign().await);
+        assert!(a.is_leader() && !b.is_leader());
+
+        a.resign().await;
+        assert!(!a.is_leader());
+        assert!(b.campaign().await);
+        assert!(!a.campaign().await);
+    }
+
+    #[tokio::test]
+    async fn test_reclaiming_keeps_live_leases() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 0,
+            refill_interval: Duration::from_secs(1),
+        };
+        let memory = Arc::new(MemoryBackend::new(config));
+        for key in ["user1", "user2"] {
+            memory
+                .acquire_lease(key, 1, 4, Duration::ZERO)
+                .await
+                .unwrap();
+        }
+        let live = memory
+            .acquire_lease("user2", 2, 4, Duration::from_secs(60))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let job = ReclaimLeases::new(Arc::clone(&memory) as _, Duration::from_secs(60));
+        job.run().await.unwrap();
+        assert!(memory.leases("user1").await.unwrap().is_empty());
+        assert_eq!(memory.leases("user2").await.unwrap(), [live]);
+    }
+}
@@ -0,0 +1,8 @@
+// Parts of the service shared by its binaries: `guardian-service` itself
+// and `guardian-replay`, which evaluates a config against recorded traffic
+// without serving anything.
+
+pub mod auth;
+pub mod config;
+pub mod namespace;
+pub mod replay;
@@ -0,0 +1,277 @@
+// Runtime limit rules: the admin RPCs change rules on the live RouterBackend
+// and persist them next to the rate-limit state, so they are re-applied when
+// the service restarts.
+
+use guardian_core::{RateLimitError, RouterBackend};
+use redis::aio::ConnectionManager;
+use redis::cluster_async::ClusterConnection;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::config::{
+    build_storage, BackendType, BackendsConfig, GuardianConfig, LimitConfig, DEFAULT_RULE,
+};
+
+/// Redis hash holding persisted rules (field = pattern, value = JSON).
+const LIMITS_KEY: &str = "guardian:limits";
+
+/// Where runtime rule changes are persisted.
+#[tonic::async_trait]
+pub trait LimitStore: Send + Sync {
+    async fn load(&self) -> Result<HashMap<String, LimitConfig>, RateLimitError>;
+    async fn save(&self, pattern: &str, limit: &LimitConfig) -> Result<(), RateLimitError>;
+    async fn delete(&self, pattern: &str) -> Result<(), RateLimitError>;
+}
+
+/// For the memory backend: bucket state doesn't survive a restart either,
+/// so rules live only as long as the process.
+pub struct EphemeralLimitStore;
+
+#[tonic::async_trait]
+impl LimitStore for EphemeralLimitStore {
+    async fn load(&self) -> Result<HashMap<String, LimitConfig>, RateLimitError> {
+        Ok(HashMap::new())
+    }
+
+    async fn save(&self, _pattern: &str, _limit: &LimitConfig) -> Result<(), RateLimitError> {
+        Ok(())
+    }
+
+    async fn delete(&self, _pattern: &str) -> Result<(), RateLimitError> {
+        Ok(())
+    }
+}
+
+enum RedisConnection {
+    Single(ConnectionManager),
+    Cluster(ClusterConnection),
+}
+
+/// Rules kept in a Redis hash on the same deployment as the buckets.
+pub struct RedisLimitStore {
+    connection: RedisConnection,
+}
+
+impl RedisLimitStore {
+    pub async fn connect(backend: &BackendType) -> Result<Self, RateLimitError> {
+        let connection = match backend {
+            BackendType::Redis { url, .. } => RedisConnection::Single(
+                redis::Client::open(url.as_str())
+                    .map_err(redis_error)?
+                    .get_connection_manager()
+                    .await
+                    .map_err(redis_error)?,
+            ),
+            BackendType::RedisCluster { nodes } => RedisConnection::Cluster(
+                redis::cluster::ClusterClient::new(nodes.clone())
+                    .map_err(redis_error)?
+                    .get_async_connection()
+                    .await
+                    .map_err(redis_error)?,
+            ),
+            BackendType::Memory { .. } | BackendType::Gossip { .. } | BackendType::Raft { .. } => {
+                return Err(RateLimitError::ConfigError(format!(
+                    "the {} backend has nowhere to persist limit rules",
+                    backend.kind()
+                )))
+            }
+        };
+        Ok(Self { connection })
+    }
+
+    async fn query<T: redis::FromRedisValue>(&self, cmd: &redis::Cmd) -> Result<T, RateLimitError> {
+        match &self.connection {
+            RedisConnection::Single(conn) => cmd.query_async(&mut conn.clone()).await,
+            RedisConnection::Cluster(conn) => cmd.query_async(&mut conn.clone()).await,
+        }
+        .map_err(redis_error)
+    }
+}
+
+#[tonic::async_trait]
+impl LimitStore for RedisLimitStore {
+    async fn load(&self) -> Result<HashMap<String, LimitConfig>, RateLimitError> {
+        let raw: HashMap<String, String> =
+            self.query(redis::cmd("HGETALL").arg(LIMITS_KEY)).await?;
+        raw.into_iter()
+            .map(|(pattern, json)| {
+                serde_json::from_str(&json)
+                    .map(|limit| (pattern.clone(), limit))
+                    .map_err(|e| {
+                        RateLimitError::ConfigError(format!("stored limit '{}': {}", pattern, e))
+                    })
+            })
+            .collect()
+    }
+
+    async fn save(&self, pattern: &str, limit: &LimitConfig) -> Result<(), RateLimitError> {
+        let json =
+            serde_json::to_string(limit).map_err(|e| RateLimitError::ConfigError(e.to_string()))?;
+        self.query(redis::cmd("HSET").arg(LIMITS_KEY).arg(pattern).arg(json))
+            .await
+    }
+
+    async fn delete(&self, pattern: &str) -> Result<(), RateLimitError> {
+        self.query(redis::cmd("HDEL").arg(LIMITS_KEY).arg(pattern))
+            .await
+    }
+}
+
+fn redis_error(e: redis::RedisError) -> RateLimitError {
+    RateLimitError::StorageError(format!("Redis limit store error: {}", e))
+}
+
+/// Owns the rule table behind the admin RPCs.
+pub struct LimitAdmin {
+    router: Arc<RouterBackend>,
+    backends: BackendsConfig,
+    rules: Mutex<HashMap<String, LimitConfig>>,
+    store: Box<dyn LimitStore>,
+}
+
+impl LimitAdmin {
+    /// Start from the rules in `config`, then re-apply any persisted at
+    /// runtime (which win over the file for the same pattern).
+    pub async fn new(
+        router: Arc<RouterBackend>,
+        config: &GuardianConfig,
+    ) -> Result<Self, RateLimitError> {
+        let store: Box<dyn LimitStore> = match config.backends.primary {
+            BackendType::Memory { .. } | BackendType::Gossip { .. } | BackendType::Raft { .. } => {
+                Box::new(EphemeralLimitStore)
+            }
+            ref backend => Box::new(RedisLimitStore::connect(backend).await?),
+        };
+        Self::with_store(router, config, store).await
+    }
+
+    pub async fn with_store(
+        router: Arc<RouterBackend>,
+        config: &GuardianConfig,
+        store: Box<dyn LimitStore>,
+    ) -> Result<Self, RateLimitError> {
+        let mut rules = config.pattern_rules();
+        rules.insert(DEFAULT_RULE.to_string(), config.default_limit());
+
+        let admin = Self {
+            router,
+            backends: config.backends.clone(),
+            rules: Mutex::new(rules),
+            store,
+        };
+        for (pattern, limit) in admin.store.load().await? {
+            admin.apply(&pattern, &limit).await?;
+        }
+        Ok(admin)
+    }
+
+    /// All rules, or just the one for `pattern`.
+    pub fn rules(&self, pattern: Option<&str>) -> Vec<(String, LimitConfig)> {
+        let rules = self.rules.lock().unwrap();
+        let mut selected: Vec<_> = rules
+            .iter()
+            .filter(|(p, _)| pattern.is_none_or(|wanted| p.as_str() == wanted))
+            .map(|(p, limit)| (p.clone(), limit.clone()))
+            .collect();
+        selected.sort_by(|(a, _), (b, _)| a.cmp(b));
+        selected
+    }
+
+    /// The rule governing `key`: its pattern (or `default`) and limits.
+    pub fn rule_for(&self, key: &str) -> (String, LimitConfig) {
+        let pattern = self
+            .router
+            .pattern_for(key)
+            .unwrap_or_else(|| DEFAULT_RULE.to_string());
+        let limit = self.rules.lock().unwrap().get(&pattern).cloned();
+        (pattern, limit.unwrap_or_default())
+    }
+
+    /// The rule governing `key`, if that rule is in shadow mode.
+    pub fn shadow_rule(&self, key: &str) -> Option<String> {
+        let (pattern, limit) = self.rule_for(key);
+        limit.shadow.then_some(pattern)
+    }
+
+    /// Create or replace a rule. Buckets for the pattern start over under
+    /// the new limits on the memory backend; on Redis they keep their state.
+    pub async fn set(&self, pattern: &str, limit: LimitConfig) -> Result<(), RateLimitError> {
+        limit.validate(pattern)?;
+        self.apply(pattern, &limit).await?;
+        self.store.save(pattern, &limit).await
+    }
+
+    /// Remove a rule, returning whether it existed. The default rule can be
+    /// changed but not removed.
+    pub async fn delete(&self, pattern: &str) -> Result<bool, RateLimitError> {
+        if pattern == DEFAULT_RULE {
+            return Err(RateLimitError::ConfigError(
+                "the default rule cannot be deleted".to_string(),
+            ));
+        }
+
+        let existed = self.rules.lock().unwrap().remove(pattern).is_some();
+        self.router.remove_route(pattern);
+        self.store.delete(pattern).await?;
+        Ok(existed)
+    }
+
+    async fn apply(&self, pattern: &str, limit: &LimitConfig) -> Result<(), RateLimitError> {
+        let backend = build_storage(&self.backends, limit.bucket_config()).await?;
+        if pattern == DEFAULT_RULE {
+            self.router.set_default(backend);
+        } else {
+            self.router.set_route(pattern, backend);
+        }
+        self.rules
+            .lock()
+            .unwrap()
+            .insert(pattern.to_string(), limit.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_core::StorageBackend;
+
+    #[tokio::test]
+    async fn test_set_and_delete_rules_on_live_router() {
+        let config = GuardianConfig::default();
+        let router = Arc::new(config.build_backend().await.unwrap());
+        let admin = LimitAdmin::new(Arc::clone(&router), &config).await.unwrap();
+
+        let small = LimitConfig {
+            capacity: 2,
+            refill_rate: 1,
+            ..LimitConfig::default()
+        };
+        admin.set("trial:*", small).await.unwrap();
+        assert_eq!(admin.rules(Some("trial:*"))[0].1.capacity, 2);
+        assert!(router.take_token("trial:bob", 2).await.unwrap());
+        assert!(!router.take_token("trial:bob", 1).await.unwrap());
+
+        assert!(admin.delete("trial:*").await.unwrap());
+        assert!(router.take_token("trial:bob", 100).await.unwrap());
+        assert!(admin.delete(DEFAULT_RULE).await.is_err());
+        assert_eq!(admin.rules(None).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reports_shadow_rules() {
+        let config = GuardianConfig::default();
+        let router = Arc::new(config.build_backend().await.unwrap());
+        let admin = LimitAdmin::new(Arc::clone(&router), &config).await.unwrap();
+
+        let shadow = LimitConfig {
+            shadow: true,
+            ..LimitConfig::default()
+        };
+        admin.set("beta:*", shadow).await.unwrap();
+        admin.set("api:*", LimitConfig::default()).await.unwrap();
+        assert_eq!(admin.shadow_rule("beta:alice").as_deref(), Some("beta:*"));
+        assert_eq!(admin.shadow_rule("api:alice"), None);
+        assert_eq!(admin.shadow_rule("web:alice"), None);
+    }
+}
@@ -0,0 +1,71 @@
+//! Log output for the service: `global.log_level` / `log_format`, plus the
+//! OpenTelemetry exporter when `global.tracing` is set.
+
+use guardian_core::RateLimitError;
+use opentelemetry_sdk::trace::TracerProvider;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter, Layer};
+
+use crate::config::{GlobalConfig, LogFormat};
+use crate::telemetry;
+
+/// Install the global subscriber. Returns the trace exporter, if any, so it
+/// can be flushed on shutdown.
+pub fn init(config: &GlobalConfig) -> Result<Option<TracerProvider>, RateLimitError> {
+    let filter = filter(std::env::var("RUST_LOG").ok(), &config.log_level)?;
+    let output = match config.log_format {
+        LogFormat::Text => fmt::layer().boxed(),
+        LogFormat::Json => fmt::layer().json().boxed(),
+    };
+    let provider = config
+        .tracing
+        .as_ref()
+        .map(telemetry::provider)
+        .transpose()?;
+
+    // The level only filters log output; sampled traces keep every span.
+    tracing_subscriber::registry()
+        .with(output.with_filter(filter))
+        .with(provider.as_ref().map(telemetry::layer))
+        .try_init()
+        .map_err(|e| RateLimitError::ConfigError(format!("logging already initialized: {}", e)))?;
+
+    Ok(provider)
+}
+
+/// A non-empty `RUST_LOG` wins over the configured level.
+fn filter(rust_log: Option<String>, log_level: &str) -> Result<EnvFilter, RateLimitError> {
+    match rust_log {
+        Some(filter) if !filter.is_empty() => EnvFilter::try_new(filter),
+        _ => EnvFilter::try_new(log_level),
+    }
+    .map_err(|e| RateLimitError::ConfigError(format!("invalid log level: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::level_filters::LevelFilter;
+
+    #[test]
+    fn test_rust_log_overrides_configured_level() {
+        let level = |rust_log: Option<&str>, log_level| {
+            filter(rust_log.map(str::to_string), log_level)
+                .unwrap()
+                .max_level_hint()
+        };
+        assert_eq!(level(None, "warn"), Some(LevelFilter::WARN));
+        assert_eq!(level(Some(""), "warn"), Some(LevelFilter::WARN));
+        assert_eq!(level(Some("debug"), "warn"), Some(LevelFilter::DEBUG));
+        assert_eq!(
+            level(None, "guardian_service=trace,info"),
+            Some(LevelFilter::TRACE)
+        );
+
+        assert!(matches!(
+            filter(None, "guardian_service=loud"),
+            Err(RateLimitError::ConfigError(_))
+        ));
+    }
+}
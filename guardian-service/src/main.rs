@@ -1,231 +1,2214 @@
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::server::{Router, TcpIncoming};
+use tower::layer::util::{Identity, Stack};
 use tonic::{transport::Server, Request, Response, Status};
 use guardian_core::{
-    LimitResult, MemoryBackend, RateLimiter, StorageBackend, TokenBucketConfig,
+    DecisionEvent, DecisionReason, GrantLedger, KeyRanking, Lease, LimitResult, RateLimitError,
+    RateLimiter, StatsBackend, StorageBackend,
 };
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::watch;
+use futures_util::stream::FuturesOrdered;
 use tokio_stream::Stream;
 use std::pin::Pin;
+use tonic_health::pb::health_server::{Health, HealthServer};
+use tonic_health::ServingStatus;
+use tracing::Instrument;
 
+mod access;
+mod admission;
+mod audit;
+mod check;
+mod ext_authz;
+mod gateway;
+mod grpc_web;
+mod health;
+mod heavy_hitters;
+mod latency;
+mod leader;
+mod limits;
+mod logging;
+mod metrics;
+mod peers;
+mod recorder;
+mod reflection;
+mod rls;
+mod shutdown;
+mod statsd;
+mod status;
+mod telemetry;
+mod tiers;
+mod tls;
+mod usage_report;
+mod warm;
+mod webhooks;
 
-pub mod guardian_proto {
-    tonic::include_proto!("guardian");
-}
+use guardian_service::{auth, config, namespace};
+
+use crate::access::{Access, AccessEntry, AccessLists};
+use crate::admission::{Admission, AdmissionLayer};
+use crate::audit::{AuditAction, Auditor};
+use crate::auth::{AdminTokenValidator, AuthInterceptor, Principal, StaticToken};
+use crate::config::{
+    Algorithm, DecisionRecorderConfig, GuardianConfig, LimitConfig, UsageReportConfig,
+    DEFAULT_RULE,
+};
+use crate::ext_authz::{AuthorizationServer, ExtAuthzService};
+use crate::gateway::Gateway;
+use crate::grpc_web::GrpcWebSupport;
+use crate::heavy_hitters::HeavyHitters;
+use crate::leader::{Election, Job, ReclaimLeases};
+use crate::limits::LimitAdmin;
+use crate::metrics::{Decision, MeteredBackend, Metrics};
+use crate::peers::{PeerAction, PeerMesh};
+use crate::recorder::{DecisionRecorder, DecisionTrace};
+use crate::rls::{RateLimitServiceServer, RlsService};
+use crate::status::{KeyWatch, StatusHub, HEARTBEAT_INTERVAL, MAX_STREAM_KEYS};
+use crate::tiers::{TierLimiter, Tiers};
+use crate::usage_report::UsageCounters;
+use crate::webhooks::Notifier;
 
 use guardian_proto::{
-    rate_limiter_server::{RateLimiter as RateLimiterTrait, RateLimiterServer},
-    CheckLimitRequest, CheckLimitResponse, GetUsageRequest, GetUsageResponse,
-    ResetLimitRequest, ResetLimitResponse,
+    rate_limiter_server::{self, RateLimiter as RateLimiterTrait, RateLimiterServer},
+    AccessList, AcquireLeaseRequest, AcquireLeaseResponse, BackendOperationStats, CheckLimitBatchRequest, CheckLimitBatchResponse,
+    CheckLimitRequest, CheckLimitResponse, CheckLimitStreamError, CheckLimitStreamRequest,
+    CheckLimitStreamResponse,
+    DeleteLimitConfigRequest, DeleteLimitConfigResponse, GetDecisionTracesRequest,
+    GetDecisionTracesResponse, GetLimitConfigRequest,
+    GetLimitConfigResponse, GetStatsRequest, GetStatsResponse, GetUsageReportRequest,
+    GetUsageReportResponse, GetUsageRequest, GetUsageResponse, InspectKeyRequest, InspectKeyResponse, KeyActivity, KeyUsageReport,
+    LimitRule, ListAccessEntriesRequest, ListAccessEntriesResponse, ListKeysRequest, ListKeysResponse,
+    ListLeasesRequest, ListLeasesResponse, RateLimitConfig, RefundTokensRequest, RefundTokensResponse,
+    RemoveAccessEntryRequest, RemoveAccessEntryResponse, RenewLeaseRequest, RenewLeaseResponse,
+    ResetLimitRequest, ResetLimitResponse, ReturnLeaseRequest, ReturnLeaseResponse, SetAccessEntryRequest, SetAccessEntryResponse, SetLimitConfigRequest,
+    SetLimitConfigResponse, TokenLease, TopKeysRequest, TopKeysResponse, UsageWindow,
 };
 
+/// Page size for ListKeys when the caller doesn't pick one, and the cap.
+const DEFAULT_PAGE_SIZE: usize = 100;
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// Entries returned by TopKeys when the caller doesn't pick a limit, and the cap.
+const DEFAULT_TOP_KEYS: usize = 10;
+const MAX_TOP_KEYS: usize = 1000;
+
+/// Lifetime of a lease when the caller doesn't pick one.
+const DEFAULT_LEASE_TTL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Retry hint given to clients on the deny list without an expiry, so
+/// that lifting the block reaches well-behaved callers soon.
+const PERMANENT_DENY_RETRY_SECS: u32 = 60;
+
+/// Rule named in audit records and decision traces for clients on the
+/// deny list.
+const DENY_LIST_RULE: &str = "deny-list";
+
+/// Rule named in decision traces for clients on the allow list.
+const ALLOW_LIST_RULE: &str = "allow-list";
+
+/// How far back the active keys gauge looks (between one and two of
+/// these), and how often heavy hitters' counts halve.
+const STATS_WINDOW: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Keys tracked per heavy-hitter ranking, enough to answer any TopKeys.
+const HEAVY_HITTERS: usize = MAX_TOP_KEYS;
+
+/// Checks from one CheckLimitStream decided at once. Further requests wait
+/// in the transport until the oldest check is answered.
+const STREAM_WINDOW: usize = 256;
+
 
 
 pub struct GuardianService<B: StorageBackend + 'static> {
-    limiter: Arc<RwLock<RateLimiter<B>>>,
+    limiter: Arc<RateLimiter<B>>,
+    admin: Option<Arc<LimitAdmin>>,
+    admin_auth: Option<Arc<dyn AdminTokenValidator>>,
+    tiers: Arc<Tiers>,
+    access: Arc<AccessLists>,
+    metrics: Option<Arc<Metrics>>,
+    status: Arc<StatusHub>,
+    heavy_hitters: Arc<HeavyHitters>,
+    audit: Option<Arc<Auditor>>,
+    usage_report: Arc<UsageCounters>,
+    webhooks: Option<Arc<Notifier>>,
+    recorder: Option<Arc<DecisionRecorder>>,
+    peers: Option<Arc<PeerMesh>>,
+    grants: Arc<GrantLedger>,
+    active_keys: Option<Arc<dyn Fn() -> usize + Send + Sync>>,
+    node: Arc<Node>,
+    started: std::time::Instant,
+    shutdown: Arc<watch::Sender<bool>>,
+}
+
+/// How InspectKey and checks describe where they were answered.
+#[derive(Debug, Default)]
+struct Node {
+    id: String,
+    backend: String,
+}
+
+impl<B: StorageBackend + 'static> Clone for GuardianService<B> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: Arc::clone(&self.limiter),
+            admin: self.admin.clone(),
+            admin_auth: self.admin_auth.clone(),
+            tiers: Arc::clone(&self.tiers),
+            access: Arc::clone(&self.access),
+            metrics: self.metrics.clone(),
+            status: Arc::clone(&self.status),
+            heavy_hitters: Arc::clone(&self.heavy_hitters),
+            audit: self.audit.clone(),
+            usage_report: Arc::clone(&self.usage_report),
+            webhooks: self.webhooks.clone(),
+            recorder: self.recorder.clone(),
+            peers: self.peers.clone(),
+            grants: Arc::clone(&self.grants),
+            active_keys: self.active_keys.clone(),
+            node: Arc::clone(&self.node),
+            started: self.started,
+            shutdown: Arc::clone(&self.shutdown),
+        }
+    }
 }
 
 impl<B: StorageBackend + 'static> GuardianService<B> {
     pub fn new(limiter: RateLimiter<B>) -> Self {
+        let status = Arc::new(StatusHub::default());
+        let heavy_hitters = Arc::new(HeavyHitters::new(HEAVY_HITTERS, STATS_WINDOW));
+        let limiter = limiter
+            .with_observer(Arc::clone(&status) as _)
+            .with_observer(Arc::clone(&heavy_hitters) as _);
         Self {
-            limiter: Arc::new(RwLock::new(limiter)),
+            limiter: Arc::new(limiter),
+            admin: None,
+            admin_auth: None,
+            tiers: Arc::new(Tiers::default()),
+            access: Arc::default(),
+            metrics: None,
+            status,
+            heavy_hitters,
+            audit: None,
+            usage_report: Arc::new(UsageCounters::new(&UsageReportConfig::default())),
+            webhooks: None,
+            recorder: None,
+            peers: None,
+            grants: Arc::default(),
+            active_keys: None,
+            node: Arc::default(),
+            started: std::time::Instant::now(),
+            shutdown: Arc::new(watch::channel(false).0),
         }
     }
-}
 
-#[tonic::async_trait]
-impl<B: StorageBackend + 'static> RateLimiterTrait for GuardianService<B> {
-    type StreamLimitStatusStream = Pin<Box<dyn Stream<Item = Result<guardian_proto::LimitStatusUpdate, Status>> + Send>>;
+    /// Ask open streams to finish so connections can drain.
+    pub fn begin_shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
 
-    async fn check_limit(
+    /// Resolves once `begin_shutdown` has been called.
+    pub async fn stopping(&self) {
+        let mut shutdown = self.shutdown.subscribe();
+        let _ = shutdown.wait_for(|stopping| *stopping).await;
+    }
+
+    /// Flush every backend the service writes to, and the audit trail.
+    pub async fn flush(&self) -> Result<(), guardian_core::RateLimitError> {
+        if let Some(audit) = &self.audit {
+            audit.flush().await;
+        }
+        self.limiter.flush().await?;
+        self.tiers.flush().await
+    }
+
+    /// Count every decision returned to callers in `metrics`.
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Apply per-tier limits to clients that request or are assigned a tier.
+    pub fn with_tiers(mut self, tiers: Tiers) -> Self {
+        let tiers = tiers
+            .with_observer(Arc::clone(&self.status) as _)
+            .with_observer(Arc::clone(&self.heavy_hitters) as _);
+        self.tiers = Arc::new(tiers);
+        self
+    }
+
+    /// The keys consuming the most tokens and drawing the most denials,
+    /// across the default limiter and every tier.
+    pub fn heavy_hitters(&self) -> Arc<HeavyHitters> {
+        Arc::clone(&self.heavy_hitters)
+    }
+
+    /// Write a record of every denial and reset to `audit`.
+    pub fn with_audit(mut self, audit: Arc<Auditor>) -> Self {
+        self.audit = Some(audit);
+        self
+    }
+
+    /// Count decisions for GetUsageReport over `config`'s windows.
+    pub fn with_usage_report(mut self, config: &UsageReportConfig) -> Self {
+        self.usage_report = Arc::new(UsageCounters::new(config));
+        self
+    }
+
+    /// Alert `webhooks` when keys are denied too often or deny-listed.
+    pub fn with_webhooks(mut self, webhooks: Arc<Notifier>) -> Self {
+        self.webhooks = Some(webhooks);
+        self
+    }
+
+    /// Trace the checks `config` samples or watches, for GetDecisionTraces.
+    pub fn with_decision_recorder(mut self, config: &DecisionRecorderConfig) -> Self {
+        self.recorder = Some(Arc::new(DecisionRecorder::new(config)));
+        self
+    }
+
+    /// Relay resets, access list changes and limit rule changes made here
+    /// to `peers`.
+    pub fn with_peers(mut self, peers: Arc<PeerMesh>) -> Self {
+        self.peers = Some(peers);
+        self
+    }
+
+    /// Send an action that succeeded here on to the peers, unless a peer
+    /// sent it here.
+    fn relay(&self, relayed: bool, action: PeerAction) {
+        if let (false, Some(peers)) = (relayed, &self.peers) {
+            peers.tell(action);
+        }
+    }
+
+    /// Count the keys holding state with `active_keys`, for GetStats.
+    pub fn with_active_keys(
+        mut self,
+        active_keys: impl Fn() -> usize + Send + Sync + 'static,
+    ) -> Self {
+        self.active_keys = Some(Arc::new(active_keys));
+        self
+    }
+
+    /// Name this instance `node_id` in InspectKey responses and check
+    /// metadata. InspectKey also reports the `backend` buckets are kept in.
+    pub fn with_node(mut self, node_id: String, backend: &str) -> Self {
+        self.node = Arc::new(Node {
+            id: node_id,
+            backend: backend.to_string(),
+        });
+        self
+    }
+
+    fn audit(
         &self,
-        request: Request<CheckLimitRequest>,
-    ) -> Result<Response<CheckLimitResponse>, Status> {
-        let req = request.into_inner();
-        let client_id = req.client_id;
+        action: AuditAction,
+        principal: Option<&Principal>,
+        namespace: &str,
+        client_id: &str,
+        cost: u64,
+        rule: impl FnOnce(&Auditor) -> String,
+    ) {
+        if let Some(audit) = &self.audit {
+            audit.record(action, principal, namespace, client_id, cost, rule(audit));
+        }
+    }
+
+    /// The tier or limit rule that governs `key`, for audit records.
+    fn rule_for(&self, audit: &Auditor, key: &str, client_id: &str, requested_tier: &str) -> String {
+        match self.tiers.tier_for(client_id, requested_tier) {
+            Some(tier) => format!("tier:{}", tier),
+            None => audit.rule_for(key),
+        }
+    }
+
+    /// The tier or limit rule that governs `key`, as InspectKey names it.
+    fn governing_rule(&self, key: &str, client_id: &str, requested_tier: &str) -> String {
+        match (self.tiers.tier_for(client_id, requested_tier), &self.admin) {
+            (Some(tier), _) => format!("tier:{}", tier),
+            (None, Some(admin)) => admin.rule_for(key).0,
+            (None, None) => DEFAULT_RULE.to_string(),
+        }
+    }
+
+    /// The decision recorder, if it samples or watches this check of
+    /// `key`, and whether it watches it.
+    fn recording(&self, key: &str) -> Option<(&DecisionRecorder, bool)> {
+        let recorder = self.recorder.as_deref()?;
+        Some((recorder, recorder.wants(key)?))
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn tier_limiter(&self, client_id: &str, requested: &str) -> Result<Option<&TierLimiter>, Status> {
+        self.tiers
+            .limiter_for(client_id, requested)
+            .map_err(|e| Status::invalid_argument(e.to_string()))
+    }
+
+    /// Secret expected by the admin RPCs. Without one (or a validator)
+    /// they refuse every caller.
+    pub fn with_admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_auth = token.map(|token| Arc::new(StaticToken::new(token)) as _);
+        self
+    }
+
+    /// Check admin tokens with a custom validator instead of a shared secret.
+    pub fn with_admin_validator(mut self, validator: impl AdminTokenValidator + 'static) -> Self {
+        self.admin_auth = Some(Arc::new(validator));
+        self
+    }
+
+    /// Serve the limit-config admin RPCs from `admin`.
+    pub fn with_limit_admin(mut self, admin: Arc<LimitAdmin>) -> Self {
+        self.admin = Some(admin);
+        self
+    }
+
+    /// Admin RPCs are allowed for admin principals (see `AuthInterceptor`)
+    /// or with a valid admin token.
+    async fn authorize_admin(&self, principal: Option<&Principal>, token: &str) -> Result<(), Status> {
+        if principal.is_some_and(|p| p.admin) {
+            return Ok(());
+        }
+        match &self.admin_auth {
+            Some(validator) if validator.is_valid(token).await => Ok(()),
+            Some(_) => Err(Status::permission_denied("Invalid admin token")),
+            None => Err(Status::permission_denied("No admin token is configured")),
+        }
+    }
+
+    async fn limit_admin(
+        &self,
+        principal: Option<&Principal>,
+        token: &str,
+    ) -> Result<&LimitAdmin, Status> {
+        let admin = self
+            .admin
+            .as_deref()
+            .ok_or_else(|| Status::unimplemented("Limit administration is not enabled"))?;
+        self.authorize_admin(principal, token).await?;
+        Ok(admin)
+    }
+
+    async fn decide(
+        &self,
+        principal: Option<&Principal>,
+        req: CheckLimitRequest,
+    ) -> Result<CheckLimitResponse, Status> {
+        let namespace = namespace::resolve(principal, &req.namespace)?;
+        let key = namespace::client_key(&namespace, &req.client_id)?;
         let cost = req.cost.max(1) as u64;
+        let started = std::time::Instant::now();
+        if let Some(entry) = self.access.lookup(&key) {
+            // Allow-listed requests spend no tokens.
+            let allowed = entry.access == Access::Allow;
+            if let Some((recorder, watched)) = self.recording(&key) {
+                let rule = if allowed { ALLOW_LIST_RULE } else { DENY_LIST_RULE };
+                recorder.record(DecisionTrace {
+                    at: std::time::SystemTime::now(),
+                    key: key.clone(),
+                    cost,
+                    allowed,
+                    reason: entry.decision_reason(),
+                    rule: rule.to_string(),
+                    tokens: None,
+                    latency: started.elapsed(),
+                    watched,
+                });
+            }
+            self.usage_report.record(&key, if allowed { 0 } else { cost }, allowed);
+            if !allowed {
+                self.audit(AuditAction::Denied, principal, &namespace, &req.client_id, cost, |_| {
+                    DENY_LIST_RULE.to_string()
+                });
+            }
+            self.limiter.observe(&DecisionEvent {
+                key: &key,
+                cost,
+                allowed,
+                reason: entry.decision_reason(),
+            });
+            return Ok(self.access_decision(&entry, started));
+        }
 
-        let limiter = self.limiter.read().await;
-        match limiter.check_limit(&client_id, cost).await {
-            Ok(LimitResult::Allowed) => Ok(Response::new(CheckLimitResponse {
+        let tier = self.tier_limiter(&req.client_id, &req.tier)?;
+        let explained = match tier {
+            Some(tier) => tier.check_limit_explained(&key, cost).await,
+            None => self.limiter.check_limit_explained(&key, cost).await,
+        };
+        let (mut result, bucket, mut reason) = match explained {
+            Ok((result, bucket, reason)) => (Ok(result), bucket, reason),
+            Err(e) => (Err(e), None, DecisionReason::BackendErrorFailClosed),
+        };
+        let charged = matches!(result, Ok(LimitResult::Allowed));
+        if let (None, Ok(LimitResult::Denied { retry_after })) = (tier, &result) {
+            if let Some(rule) = self.shadow_rule(&key) {
+                tracing::info!(
+                    %rule,
+                    %key,
+                    cost,
+                    retry_after_secs = retry_after.as_secs(),
+                    "shadow rule would have denied the request"
+                );
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_shadow_denial(&rule);
+                }
+                result = Ok(LimitResult::Allowed);
+                reason = DecisionReason::ShadowRule;
+            }
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.record_decision(Decision::of(&result));
+        }
+        if let Ok(result) = &result {
+            self.usage_report.record(&key, cost, *result == LimitResult::Allowed);
+        }
+        if let Some((recorder, watched)) = self.recording(&key) {
+            recorder.record(DecisionTrace {
+                at: std::time::SystemTime::now(),
+                key: key.clone(),
+                cost,
+                allowed: matches!(result, Ok(LimitResult::Allowed)),
+                reason,
+                rule: self.governing_rule(&key, &req.client_id, &req.tier),
+                // A check that took tokens had them on top of what's left.
+                tokens: bucket.map(|bucket| {
+                    let taken = if charged { cost } else { 0 };
+                    (bucket.remaining + taken, bucket.remaining)
+                }),
+                latency: started.elapsed(),
+                watched,
+            });
+        }
+        if let Ok(LimitResult::Denied { .. }) = &result {
+            if let Some(webhooks) = &self.webhooks {
+                webhooks.record_denial(&namespace, &req.client_id, &key);
+            }
+            self.audit(AuditAction::Denied, principal, &namespace, &req.client_id, cost, |audit| {
+                self.rule_for(audit, &key, &req.client_id, &req.tier)
+            });
+        }
+        let remaining_tokens = bucket.map_or(0, |bucket| bucket.remaining);
+        let limit = bucket.map_or(0, |bucket| bucket.capacity);
+        let grant_id = (charged && req.refundable)
+            .then(|| self.grants.issue(&grant_key(&req.tier, &key), cost))
+            .flatten();
+        match result {
+            Ok(LimitResult::Allowed) => Ok(CheckLimitResponse {
                 allowed: true,
                 retry_after_seconds: 0,
-                remaining_tokens: 0, // Could be enhanced to return actual remaining
-                metadata: Some(guardian_proto::LimitMetadata {
-                    node_id: "primary".to_string(),
-                    from_cache: false,
-                    latency_us: 100,
-                    is_global: true,
-                }),
-            })),
-            Ok(LimitResult::Denied { retry_after }) => {
-                Ok(Response::new(CheckLimitResponse {
-                    allowed: false,
-                    retry_after_seconds: retry_after.as_secs() as u32,
-                    remaining_tokens: 0,
-                    metadata: Some(guardian_proto::LimitMetadata {
-                        node_id: "primary".to_string(),
-                        from_cache: false,
-                        latency_us: 100,
-                        is_global: true,
+                remaining_tokens,
+                limit,
+                reason: reason.to_string(),
+                metadata: self.metadata(started),
+                grant_id: grant_id.unwrap_or_default(),
+            }),
+            Ok(LimitResult::Denied { retry_after }) => Ok(CheckLimitResponse {
+                allowed: false,
+                retry_after_seconds: retry_after.as_secs_f64().ceil() as u32,
+                remaining_tokens,
+                limit,
+                reason: reason.to_string(),
+                metadata: self.metadata(started),
+                grant_id: String::new(),
+            }),
+            Err(e) => Err(Status::internal(format!("Rate limiter error: {}", e))),
+        }
+    }
+
+    /// Decide `checks` together: every entry takes its tokens or none does,
+    /// in one step of the backend, so no other check sees some taken and the
+    /// rest not. Fails with FAILED_PRECONDITION when the backend can't do
+    /// that, or when the entries fall in different tiers, whose buckets are
+    /// kept apart. Metrics, usage, traces and audit records are written once
+    /// the outcome is known.
+    async fn decide_all(
+        &self,
+        principal: Option<&Principal>,
+        checks: Vec<CheckLimitRequest>,
+    ) -> Result<Vec<CheckLimitResponse>, Status> {
+        let started = std::time::Instant::now();
+        let Some(first) = checks.first() else {
+            return Ok(Vec::new());
+        };
+        let tier_name = self.tiers.tier_for(&first.client_id, &first.tier);
+        if checks
+            .iter()
+            .any(|check| self.tiers.tier_for(&check.client_id, &check.tier) != tier_name)
+        {
+            return Err(Status::failed_precondition(
+                "All-or-nothing checks must all be limited by the same tier",
+            ));
+        }
+        let tier = self.tier_limiter(&first.client_id, &first.tier)?;
+        let mut entries = Vec::with_capacity(checks.len());
+        for check in &checks {
+            let namespace = namespace::resolve(principal, &check.namespace)?;
+            let key = namespace::client_key(&namespace, &check.client_id)?;
+            let listed = self.access.lookup(&key);
+            entries.push((check, namespace, key, listed));
+        }
+
+        // A deny-listed entry denies the lot without any bucket being
+        // touched; allow-listed ones spend nothing either way.
+        let listed_denial = entries.iter().any(|(.., listed)| {
+            listed
+                .as_ref()
+                .is_some_and(|entry| entry.access != Access::Allow)
+        });
+        let limited: Vec<(&str, u64)> = entries
+            .iter()
+            .filter(|(.., listed)| listed.is_none())
+            .map(|(check, _, key, _)| (key.as_str(), check.cost.max(1) as u64))
+            .collect();
+        let mut decided = Vec::new();
+        if !listed_denial && !limited.is_empty() {
+            let checked = match tier {
+                Some(tier) => tier.check_all(&limited).await,
+                None => self.limiter.check_all(&limited).await,
+            };
+            decided = match checked {
+                Ok(decided) => decided,
+                Err(e @ RateLimitError::Unsupported(_)) => {
+                    return Err(Status::failed_precondition(format!(
+                        "All-or-nothing checks need a backend that takes several keys at once: {}",
+                        e
+                    )))
+                }
+                Err(e) => {
+                    if let Some(metrics) = &self.metrics {
+                        for _ in &limited {
+                            metrics.record_decision(Decision::Error);
+                        }
+                    }
+                    return Err(Status::internal(format!("Rate limiter error: {}", e)));
+                }
+            };
+        }
+        let taken = !decided.is_empty()
+            && decided
+                .iter()
+                .all(|(result, ..)| *result == LimitResult::Allowed);
+
+        // Like a single check, the lot goes through when every short entry
+        // is governed by a rule in shadow mode, though nothing was taken.
+        let short: Vec<(&str, u64)> = limited
+            .iter()
+            .zip(&decided)
+            .filter(|(_, (.., reason))| *reason == DecisionReason::BucketExhausted)
+            .map(|(entry, _)| *entry)
+            .collect();
+        let shadowed: Option<Vec<String>> = match tier {
+            None if !short.is_empty() => {
+                short.iter().map(|(key, _)| self.shadow_rule(key)).collect()
+            }
+            _ => None,
+        };
+        if let Some(rules) = shadowed {
+            for ((key, cost), rule) in short.iter().zip(&rules) {
+                tracing::info!(
+                    %rule,
+                    %key,
+                    cost,
+                    "shadow rule would have denied an all-or-nothing check"
+                );
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_shadow_denial(rule);
+                }
+            }
+            for (result, _, reason) in &mut decided {
+                *result = LimitResult::Allowed;
+                *reason = DecisionReason::ShadowRule;
+            }
+        }
+
+        let allowed = !listed_denial
+            && decided
+                .iter()
+                .all(|(result, ..)| *result == LimitResult::Allowed);
+        let listed_wait = entries
+            .iter()
+            .filter_map(|(.., listed)| listed.as_ref())
+            .filter(|entry| entry.access != Access::Allow)
+            .map(|entry| {
+                entry
+                    .remaining()
+                    .map_or(PERMANENT_DENY_RETRY_SECS, |remaining| {
+                        remaining.as_secs_f64().ceil() as u32
+                    })
+            });
+        let limited_wait = decided.iter().filter_map(|(result, ..)| match result {
+            LimitResult::Denied { retry_after } => Some(retry_after.as_secs_f64().ceil() as u32),
+            LimitResult::Allowed => None,
+        });
+        let retry_after_seconds = listed_wait.chain(limited_wait).max().unwrap_or(0);
+
+        let mut decided = decided.into_iter();
+        let mut responses = Vec::with_capacity(entries.len());
+        for (check, namespace, key, listed) in &entries {
+            let cost = check.cost.max(1) as u64;
+            let (bucket, reason, rule) = match listed {
+                Some(entry) => {
+                    let reason = if allowed || entry.access != Access::Allow {
+                        entry.decision_reason()
+                    } else {
+                        DecisionReason::AllOrNothing
+                    };
+                    self.limiter.observe(&DecisionEvent {
+                        key,
+                        cost,
+                        allowed,
+                        reason,
+                    });
+                    let rule = match entry.access {
+                        Access::Allow => ALLOW_LIST_RULE,
+                        _ => DENY_LIST_RULE,
+                    };
+                    (None, reason, rule.to_string())
+                }
+                None => {
+                    let rule = self.governing_rule(key, &check.client_id, &check.tier);
+                    match decided.next() {
+                        Some((_, bucket, reason)) => (bucket, reason, rule),
+                        None => {
+                            // Denied by a deny-listed entry before the
+                            // limiter saw it.
+                            self.limiter.observe(&DecisionEvent {
+                                key,
+                                cost,
+                                allowed: false,
+                                reason: DecisionReason::AllOrNothing,
+                            });
+                            (None, DecisionReason::AllOrNothing, rule)
+                        }
+                    }
+                }
+            };
+            let took = taken && listed.is_none();
+
+            if let Some(metrics) = &self.metrics {
+                metrics.record_decision(if allowed {
+                    Decision::Allowed
+                } else {
+                    Decision::Denied
+                });
+            }
+            let spent = if allowed && listed.is_some() { 0 } else { cost };
+            self.usage_report.record(key, spent, allowed);
+            if let Some((recorder, watched)) = self.recording(key) {
+                recorder.record(DecisionTrace {
+                    at: std::time::SystemTime::now(),
+                    key: key.clone(),
+                    cost,
+                    allowed,
+                    reason,
+                    rule: rule.clone(),
+                    tokens: bucket.map(|bucket| {
+                        let taken = if took { cost } else { 0 };
+                        (bucket.remaining + taken, bucket.remaining)
                     }),
-                }))
+                    latency: started.elapsed(),
+                    watched,
+                });
             }
-            Err(e) => Err(Status::internal(format!("Rate limiter error: {}", e))),
+            if !allowed {
+                if listed.is_none() {
+                    if let Some(webhooks) = &self.webhooks {
+                        webhooks.record_denial(namespace, &check.client_id, key);
+                    }
+                }
+                self.audit(
+                    AuditAction::Denied,
+                    principal,
+                    namespace,
+                    &check.client_id,
+                    cost,
+                    |audit| match listed {
+                        Some(entry) if entry.access != Access::Allow => rule.clone(),
+                        _ => self.rule_for(audit, key, &check.client_id, &check.tier),
+                    },
+                );
+            }
+
+            let grant_id = (took && check.refundable)
+                .then(|| self.grants.issue(&grant_key(&check.tier, key), cost))
+                .flatten();
+            responses.push(CheckLimitResponse {
+                allowed,
+                retry_after_seconds,
+                remaining_tokens: bucket.map_or(0, |bucket| bucket.remaining),
+                limit: bucket.map_or(0, |bucket| bucket.capacity),
+                reason: reason.to_string(),
+                metadata: self.metadata(started),
+                grant_id: grant_id.unwrap_or_default(),
+            });
         }
+        Ok(responses)
     }
 
-    async fn get_usage(
+    /// The rule governing `key`, if it is in shadow mode. Tiers have no
+    /// shadow mode, so only pattern rules are consulted.
+    fn shadow_rule(&self, key: &str) -> Option<String> {
+        self.admin.as_ref()?.shadow_rule(key)
+    }
+
+    /// Decision for a client on the allow or deny list, made without
+    /// touching its bucket.
+    fn access_decision(
         &self,
-        request: Request<GetUsageRequest>,
-    ) -> Result<Response<GetUsageResponse>, Status> {
-        let req = request.into_inner();
-        let limiter = self.limiter.read().await;
+        entry: &AccessEntry,
+        started: std::time::Instant,
+    ) -> CheckLimitResponse {
+        let allowed = entry.access == Access::Allow;
+        if let Some(metrics) = &self.metrics {
+            metrics.record_decision(if allowed {
+                Decision::Allowed
+            } else {
+                Decision::Denied
+            });
+        }
+        let retry_after_seconds = match entry.remaining() {
+            _ if allowed => 0,
+            Some(remaining) => remaining.as_secs_f64().ceil() as u32,
+            None => PERMANENT_DENY_RETRY_SECS,
+        };
+        CheckLimitResponse {
+            allowed,
+            retry_after_seconds,
+            remaining_tokens: 0,
+            limit: 0,
+            reason: entry.decision_reason().to_string(),
+            metadata: self.metadata(started),
+            grant_id: String::new(),
+        }
+    }
+
+    /// Metadata for a check this node answered, `started` when it came in.
+    fn metadata(&self, started: std::time::Instant) -> Option<guardian_proto::LimitMetadata> {
+        Some(guardian_proto::LimitMetadata {
+            node_id: self.node.id.clone(),
+            from_cache: false,
+            latency_us: started.elapsed().as_micros() as u64,
+            is_global: true,
+        })
+    }
+
+    async fn usage(
+        &self,
+        principal: Option<&Principal>,
+        req: GetUsageRequest,
+    ) -> Result<GetUsageResponse, Status> {
+        let namespace = namespace::resolve(principal, &req.namespace)?;
+        let key = namespace::client_key(&namespace, &req.client_id)?;
 
-        match limiter.get_usage(&req.client_id).await {
-            Ok(usage) => Ok(Response::new(GetUsageResponse {
+        let tier = self.tier_limiter(&req.client_id, "")?;
+        let usage = match tier {
+            Some(tier) => tier.get_usage(&key).await,
+            None => self.limiter.get_usage(&key).await,
+        };
+        let bucket = match tier {
+            Some(tier) => tier.inspect(&key).await,
+            None => self.limiter.inspect(&key).await,
+        };
+        // Backends that can't describe their buckets report zeros.
+        let bucket = bucket.map_err(|e| Status::internal(format!("Failed to get usage: {}", e)))?;
+        let last_refill = bucket.and_then(|bucket| bucket.last_refill);
+        match usage {
+            Ok(usage) => Ok(GetUsageResponse {
                 used_tokens: usage,
-                total_capacity: 1000,
-                refill_rate: 100,
-                last_refill_timestamp: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs() as i64,
-            })),
+                total_capacity: bucket.map_or(0, |bucket| bucket.capacity),
+                refill_rate: bucket.map_or(0, |bucket| bucket.refill_rate),
+                last_refill_timestamp: last_refill.map_or(0, unix_seconds),
+            }),
             Err(e) => Err(Status::internal(format!("Failed to get usage: {}", e))),
         }
     }
 
-    async fn reset_limit(
+    async fn reset(
         &self,
-        request: Request<ResetLimitRequest>,
-    ) -> Result<Response<ResetLimitResponse>, Status> {
-        let _req = request.into_inner();
+        principal: Option<&Principal>,
+        req: ResetLimitRequest,
+        relayed: bool,
+    ) -> Result<ResetLimitResponse, Status> {
+        self.authorize_admin(principal, &req.admin_token).await?;
+        let namespace = namespace::resolve(principal, &req.namespace)?;
+        let key = namespace::client_key(&namespace, &req.client_id)?;
 
-        // Reset is not implemented in the current RateLimiter API
-        // For now, just return success
-        Ok(Response::new(ResetLimitResponse { 
-            success: true,
-            message: "Rate limit reset successfully".to_string(),
-        }))
+        let reset = match self.tier_limiter(&req.client_id, "")? {
+            Some(tier) => tier.reset(&key).await,
+            None => self.limiter.reset(&key).await,
+        };
+        match reset {
+            Ok(()) => {
+                self.audit(AuditAction::Reset, principal, &namespace, &req.client_id, 0, |audit| {
+                    self.rule_for(audit, &key, &req.client_id, "")
+                });
+                self.relay(
+                    relayed,
+                    PeerAction::Reset(ResetLimitRequest { namespace, ..req }),
+                );
+                Ok(ResetLimitResponse {
+                    success: true,
+                    message: "Rate limit reset successfully".to_string(),
+                })
+            }
+            Err(e) => Ok(ResetLimitResponse {
+                success: false,
+                message: format!("Failed to reset limit: {}", e),
+            }),
+        }
     }
 
-    async fn stream_limit_status(
+    /// Unlike a reset this needs no admin rights, only the grant an
+    /// allowed check returned: each is redeemed once, for at most the
+    /// tokens that check took, and never past the bucket's capacity.
+    async fn refund(
         &self,
-        request: Request<guardian_proto::StreamLimitRequest>,
-    ) -> Result<Response<Self::StreamLimitStatusStream>, Status> {
+        principal: Option<&Principal>,
+        req: RefundTokensRequest,
+    ) -> Result<RefundTokensResponse, Status> {
+        let namespace = namespace::resolve(principal, &req.namespace)?;
+        let key = namespace::client_key(&namespace, &req.client_id)?;
+        let tier = self.tier_limiter(&req.client_id, &req.tier)?;
+        let cost = self
+            .grants
+            .redeem(&req.grant_id, &grant_key(&req.tier, &key), req.cost.into())
+            .ok_or_else(|| Status::permission_denied("Unknown, expired or already used grant"))?;
+
+        let refund = match tier {
+            Some(tier) => tier.refund(&key, cost).await,
+            None => self.limiter.refund(&key, cost).await,
+        };
+        match refund {
+            Ok(()) => Ok(RefundTokensResponse {
+                success: true,
+                message: format!("Refunded {} tokens", cost),
+            }),
+            Err(e) => Ok(RefundTokensResponse {
+                success: false,
+                message: format!("Failed to refund tokens: {}", e),
+            }),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl<B: StorageBackend + 'static> RateLimiterTrait for GuardianService<B> {
+    type StreamLimitStatusStream = Pin<Box<dyn Stream<Item = Result<guardian_proto::LimitStatusUpdate, Status>> + Send>>;
+    type CheckLimitStreamStream = Pin<Box<dyn Stream<Item = Result<CheckLimitStreamResponse, Status>> + Send>>;
+
+    async fn check_limit(
+        &self,
+        request: Request<CheckLimitRequest>,
+    ) -> Result<Response<CheckLimitResponse>, Status> {
+        let span = telemetry::rpc_span("CheckLimit", request.metadata());
+        let principal = request.extensions().get::<Principal>().cloned();
         let req = request.into_inner();
-        let client_id = req.client_id.clone();
-        let limiter = self.limiter.clone();
+        span.record("guardian.client_id", req.client_id.as_str());
+        self.decide(principal.as_ref(), req)
+            .instrument(span)
+            .await
+            .map(Response::new)
+    }
+
+    async fn check_limit_batch(
+        &self,
+        request: Request<CheckLimitBatchRequest>,
+    ) -> Result<Response<CheckLimitBatchResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+
+        let mut results = Vec::with_capacity(req.checks.len());
+        for check in req.checks {
+            results.push(self.decide(principal.as_ref(), check).await?);
+        }
+
+        Ok(Response::new(CheckLimitBatchResponse { results }))
+    }
+
+    async fn check_limit_all(
+        &self,
+        request: Request<CheckLimitBatchRequest>,
+    ) -> Result<Response<CheckLimitBatchResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        let results = self.decide_all(principal.as_ref(), req.checks).await?;
+        Ok(Response::new(CheckLimitBatchResponse { results }))
+    }
+
+    async fn check_limit_stream(
+        &self,
+        request: Request<tonic::Streaming<CheckLimitStreamRequest>>,
+    ) -> Result<Response<Self::CheckLimitStreamStream>, Status> {
+        use futures_util::StreamExt;
+
+        let principal = request.extensions().get::<Principal>().cloned();
+        let mut inbound = request.into_inner();
+        let service = self.clone();
+        let mut shutdown = self.shutdown.subscribe();
 
+        // Up to STREAM_WINDOW checks are decided at once; FuturesOrdered
+        // still hands them back in arrival order, so responses leave in the
+        // order the requests came in.
         let stream = async_stream::stream! {
+            let mut in_flight = FuturesOrdered::new();
+            let mut reading = true;
             loop {
-                match limiter.read().await.get_usage(&client_id).await {
-                    Ok(remaining) => {
-                        yield Ok(guardian_proto::LimitStatusUpdate {
-                            client_id: client_id.clone(),
-                            remaining_tokens: remaining,
-                            timestamp: std::time::SystemTime::now()
-                                .duration_since(std::time::UNIX_EPOCH)
-                                .unwrap()
-                                .as_millis() as i64,
-                            status: 0,
+                let event = tokio::select! {
+                    Some(response) = in_flight.next() => StreamEvent::Decided(response),
+                    message = inbound.message(),
+                        if reading && in_flight.len() < STREAM_WINDOW =>
+                    {
+                        StreamEvent::Received(message)
+                    }
+                    // On shutdown no more checks are read; those in flight
+                    // are answered, then the client sees a clean end of
+                    // stream and can reconnect elsewhere.
+                    _ = shutdown.wait_for(|stopping| *stopping), if reading => {
+                        StreamEvent::Received(Ok(None))
+                    }
+                    else => break,
+                };
+                match event {
+                    StreamEvent::Decided(response) => yield Ok(response),
+                    StreamEvent::Received(Ok(Some(message))) => {
+                        let service = service.clone();
+                        let principal = principal.clone();
+                        in_flight.push_back(async move {
+                            let check = message.check.unwrap_or_default();
+                            stream_response(
+                                message.request_id,
+                                service.decide(principal.as_ref(), check).await,
+                            )
                         });
                     }
-                    Err(_) => break,
+                    StreamEvent::Received(Ok(None)) => reading = false,
+                    // Only a broken inbound stream ends the call; a failed
+                    // check is answered under its own request id.
+                    StreamEvent::Received(Err(status)) => {
+                        yield Err(status);
+                        break;
+                    }
                 }
-                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
             }
         };
 
         Ok(Response::new(Box::pin(stream)))
     }
-}
 
+    async fn get_usage(
+        &self,
+        request: Request<GetUsageRequest>,
+    ) -> Result<Response<GetUsageResponse>, Status> {
+        let span = telemetry::rpc_span("GetUsage", request.metadata());
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        span.record("guardian.client_id", req.client_id.as_str());
+        self.usage(principal.as_ref(), req)
+            .instrument(span)
+            .await
+            .map(Response::new)
+    }
+
+    async fn reset_limit(
+        &self,
+        request: Request<ResetLimitRequest>,
+    ) -> Result<Response<ResetLimitResponse>, Status> {
+        let span = telemetry::rpc_span("ResetLimit", request.metadata());
+        let principal = request.extensions().get::<Principal>().cloned();
+        let relayed = peers::from_peer(request.metadata());
+        let req = request.into_inner();
+        span.record("guardian.client_id", req.client_id.as_str());
+        self.reset(principal.as_ref(), req, relayed)
+            .instrument(span)
+            .await
+            .map(Response::new)
+    }
 
+    async fn refund_tokens(
+        &self,
+        request: Request<RefundTokensRequest>,
+    ) -> Result<Response<RefundTokensResponse>, Status> {
+        let span = telemetry::rpc_span("RefundTokens", request.metadata());
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        span.record("guardian.client_id", req.client_id.as_str());
+        self.refund(principal.as_ref(), req)
+            .instrument(span)
+            .await
+            .map(Response::new)
+    }
 
-pub struct RateLimitInterceptor {
-    limiter: Arc<RwLock<RateLimiter<MemoryBackend>>>,
-}
+    async fn acquire_lease(
+        &self,
+        request: Request<AcquireLeaseRequest>,
+    ) -> Result<Response<AcquireLeaseResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+        let key = namespace::client_key(&namespace, &req.client_id)?;
+        let id = lease_id(req.lease_id)?;
+        if req.tokens == 0 {
+            return Err(Status::invalid_argument("tokens must be positive"));
+        }
+        let ttl = lease_ttl(req.ttl_ms);
 
-impl RateLimitInterceptor {
-    pub fn new(config: TokenBucketConfig) -> Self {
-        let backend = MemoryBackend::new(config);
-        let limiter = RateLimiter::new(backend, true);
-        Self {
-            limiter: Arc::new(RwLock::new(limiter)),
+        let lease = match self.tier_limiter(&req.client_id, &req.tier)? {
+            Some(tier) => tier.acquire_lease(&key, id, req.tokens, ttl).await,
+            None => self.limiter.acquire_lease(&key, id, req.tokens, ttl).await,
         }
+        .map_err(|e| Status::internal(format!("Failed to acquire lease: {}", e)))?;
+        Ok(Response::new(match lease {
+            Some(lease) => AcquireLeaseResponse {
+                granted: true,
+                tokens: lease.tokens,
+                expires_at_ms: unix_millis(lease.expires_at),
+            },
+            None => AcquireLeaseResponse::default(),
+        }))
     }
 
-    pub async fn intercept(&self, req: Request<()>) -> Result<Request<()>, Status> {
-        // Extract client identifier (could be from headers, peer address, etc.)
-        let client_id = req
-            .metadata()
-            .get("client-id")
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("anonymous");
+    async fn renew_lease(
+        &self,
+        request: Request<RenewLeaseRequest>,
+    ) -> Result<Response<RenewLeaseResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+        let key = namespace::client_key(&namespace, &req.client_id)?;
+        let id = lease_id(req.lease_id)?;
+        let ttl = lease_ttl(req.ttl_ms);
 
-        let limiter = self.limiter.read().await;
-        match limiter.check_limit(client_id, 1).await {
-            Ok(LimitResult::Allowed) => Ok(req),
-            Ok(LimitResult::Denied { retry_after }) => Err(Status::resource_exhausted(format!(
-                "Rate limit exceeded. Retry after {} seconds",
-                retry_after.as_secs()
-            ))),
-            Err(e) => Err(Status::internal(format!("Interceptor error: {}", e))),
+        let lease = match self.tier_limiter(&req.client_id, &req.tier)? {
+            Some(tier) => tier.renew_lease(&key, id, ttl).await,
+            None => self.limiter.renew_lease(&key, id, ttl).await,
         }
+        .map_err(|e| Status::internal(format!("Failed to renew lease: {}", e)))?;
+        Ok(Response::new(match lease {
+            Some(lease) => RenewLeaseResponse {
+                renewed: true,
+                tokens: lease.tokens,
+                expires_at_ms: unix_millis(lease.expires_at),
+            },
+            None => RenewLeaseResponse::default(),
+        }))
     }
-}
 
+    async fn return_lease(
+        &self,
+        request: Request<ReturnLeaseRequest>,
+    ) -> Result<Response<ReturnLeaseResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+        let key = namespace::client_key(&namespace, &req.client_id)?;
+        let id = lease_id(req.lease_id)?;
 
+        let returned = match self.tier_limiter(&req.client_id, &req.tier)? {
+            Some(tier) => tier.return_lease(&key, id, req.unused).await,
+            None => self.limiter.return_lease(&key, id, req.unused).await,
+        }
+        .map_err(|e| Status::internal(format!("Failed to return lease: {}", e)))?;
+        Ok(Response::new(ReturnLeaseResponse { returned }))
+    }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = TokenBucketConfig {
-        capacity: 1000,
-        refill_rate: 100,
-        refill_interval: std::time::Duration::from_secs(1),
-    };
+    async fn set_limit_config(
+        &self,
+        request: Request<SetLimitConfigRequest>,
+    ) -> Result<Response<SetLimitConfigResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let relayed = peers::from_peer(request.metadata());
+        let req = request.into_inner();
+        let admin = self.limit_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+        let config = req
+            .config
+            .as_ref()
+            .ok_or_else(|| Status::invalid_argument("config is required"))?;
 
-    let backend = MemoryBackend::new(config.clone());
-    let limiter = RateLimiter::new(backend, true);
-    let service = GuardianService::new(limiter);
+        let pattern = namespace::rule_pattern(&namespace, &req.pattern);
+        match admin.set(&pattern, limit_from_proto(config)).await {
+            Ok(()) => {
+                let message = format!("Limit for '{}' updated", req.pattern);
+                self.relay(
+                    relayed,
+                    PeerAction::SetLimitConfig(SetLimitConfigRequest { namespace, ..req }),
+                );
+                Ok(Response::new(SetLimitConfigResponse {
+                    success: true,
+                    message,
+                }))
+            }
+            Err(e) => Ok(Response::new(SetLimitConfigResponse {
+                success: false,
+                message: format!("Failed to set limit: {}", e),
+            })),
+        }
+    }
 
-    let addr = "0.0.0.0:50051".parse()?;
-    println!("🛡️  Guardian Rate Limiter starting on {}", addr);
+    async fn get_limit_config(
+        &self,
+        request: Request<GetLimitConfigRequest>,
+    ) -> Result<Response<GetLimitConfigResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        let admin = self.limit_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
 
-    Server::builder()
-        .add_service(RateLimiterServer::new(service))
-        .serve(addr)
-        .await?;
+        let pattern = (!req.pattern.is_empty())
+            .then(|| namespace::rule_pattern(&namespace, &req.pattern));
+        let rules = admin
+            .rules(pattern.as_deref())
+            .into_iter()
+            .filter_map(|(pattern, limit)| {
+                Some(LimitRule {
+                    pattern: namespace::rule_name(&namespace, &pattern)?,
+                    config: Some(limit_to_proto(&limit)),
+                })
+            })
+            .collect();
 
-    Ok(())
-}
+        Ok(Response::new(GetLimitConfigResponse { rules }))
+    }
 
+    async fn delete_limit_config(
+        &self,
+        request: Request<DeleteLimitConfigRequest>,
+    ) -> Result<Response<DeleteLimitConfigResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let relayed = peers::from_peer(request.metadata());
+        let req = request.into_inner();
+        let admin = self.limit_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
 
+        match admin.delete(&namespace::rule_pattern(&namespace, &req.pattern)).await {
+            Ok(true) => {
+                let message = format!("Limit for '{}' deleted", req.pattern);
+                self.relay(
+                    relayed,
+                    PeerAction::DeleteLimitConfig(DeleteLimitConfigRequest { namespace, ..req }),
+                );
+                Ok(Response::new(DeleteLimitConfigResponse {
+                    success: true,
+                    message,
+                }))
+            }
+            Ok(false) => Ok(Response::new(DeleteLimitConfigResponse {
+                success: false,
+                message: format!("No limit configured for '{}'", req.pattern),
+            })),
+            Err(e) => Ok(Response::new(DeleteLimitConfigResponse {
+                success: false,
+                message: format!("Failed to delete limit: {}", e),
+            })),
+        }
+    }
 
-#[cfg(test)]
-mod client_example {
-    use super::guardian_proto::{rate_limiter_client::RateLimiterClient, CheckLimitRequest};
+    async fn list_keys(
+        &self,
+        request: Request<ListKeysRequest>,
+    ) -> Result<Response<ListKeysResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        self.authorize_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
 
-    #[tokio::test]
-    async fn test_client_integration() {
-        // This would connect to a running server
-        let mut client = RateLimiterClient::connect("http://localhost:50051")
+        let pattern = if req.pattern.is_empty() { "*" } else { req.pattern.as_str() };
+        let pattern = namespace::scoped(&namespace, pattern);
+        let after = (!req.page_token.is_empty())
+            .then(|| namespace::scoped(&namespace, &req.page_token));
+        let page_size = match req.page_size as usize {
+            0 => DEFAULT_PAGE_SIZE,
+            n => n.min(MAX_PAGE_SIZE),
+        };
+
+        // One extra key tells us whether another page follows.
+        let mut keys: Vec<String> = self
+            .limiter
+            .list_keys(&pattern, after.as_deref(), page_size + 1)
             .await
-            .unwrap();
+            .map_err(|e| Status::failed_precondition(format!("Failed to list keys: {}", e)))?
+            .iter()
+            .filter_map(|key| namespace::unscoped(&namespace, key).map(str::to_string))
+            .collect();
 
-        let request = tonic::Request::new(CheckLimitRequest {
-            client_id: "user123".to_string(),
-            cost: 10,
-            override_config: None,
+        let next_page_token = if keys.len() > page_size {
+            keys.truncate(page_size);
+            keys.last().cloned().unwrap_or_default()
+        } else {
+            String::new()
+        };
+        Ok(Response::new(ListKeysResponse {
+            keys,
+            next_page_token,
+        }))
+    }
+
+    async fn top_keys(
+        &self,
+        request: Request<TopKeysRequest>,
+    ) -> Result<Response<TopKeysResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        self.authorize_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+
+        let ranking = match req.ranking() {
+            guardian_proto::KeyRanking::Denials => KeyRanking::Denials,
+            guardian_proto::KeyRanking::Unspecified | guardian_proto::KeyRanking::Usage => {
+                KeyRanking::Usage
+            }
+        };
+        let limit = match req.limit as usize {
+            0 => DEFAULT_TOP_KEYS,
+            n => n.min(MAX_TOP_KEYS),
+        };
+
+        // Within a namespace, rank everything and keep that namespace's keys.
+        let candidates = if namespace.is_empty() { limit } else { usize::MAX };
+        let keys = self
+            .heavy_hitters
+            .top(candidates, ranking)
+            .into_iter()
+            .filter_map(|(key, stats)| {
+                Some(KeyActivity {
+                    client_id: namespace::unscoped(&namespace, &key)?.to_string(),
+                    allowed: stats.allowed,
+                    denied: stats.denied,
+                    tokens_consumed: stats.consumed,
+                })
+            })
+            .take(limit)
+            .collect();
+
+        Ok(Response::new(TopKeysResponse { keys }))
+    }
+
+    async fn get_usage_report(
+        &self,
+        request: Request<GetUsageReportRequest>,
+    ) -> Result<Response<GetUsageReportResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        self.authorize_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+
+        let counters = &self.usage_report;
+        let windows: Vec<std::time::Duration> = if req.window_seconds.is_empty() {
+            counters.windows().to_vec()
+        } else {
+            req.window_seconds
+                .iter()
+                .map(|&secs| std::time::Duration::from_secs(secs as u64))
+                .collect()
+        };
+        if let Some(window) = windows.iter().find(|w| w.is_zero() || **w > counters.retention()) {
+            return Err(Status::invalid_argument(format!(
+                "window of {}s is outside 1..={}s",
+                window.as_secs(),
+                counters.retention().as_secs()
+            )));
+        }
+
+        let mut keys: Vec<KeyUsageReport> = if req.client_ids.is_empty() {
+            counters
+                .keys()
+                .iter()
+                .filter_map(|key| Some((namespace::unscoped(&namespace, key)?, key)))
+                .map(|(client_id, key)| usage_report(client_id, key, counters, &windows))
+                .collect()
+        } else {
+            req.client_ids
+                .iter()
+                .map(|client_id| {
+                    let key = namespace::scoped(&namespace, client_id);
+                    usage_report(client_id, &key, counters, &windows)
+                })
+                .collect()
+        };
+        if req.client_ids.is_empty() {
+            let limit = match req.limit as usize {
+                0 => DEFAULT_PAGE_SIZE,
+                n => n.min(MAX_PAGE_SIZE),
+            };
+            // Busiest over the longest window first.
+            let longest = windows
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, window)| **window)
+                .map(|(i, _)| i);
+            if let Some(longest) = longest {
+                keys.sort_by(|a, b| {
+                    b.windows[longest]
+                        .tokens_consumed
+                        .cmp(&a.windows[longest].tokens_consumed)
+                        .then_with(|| a.client_id.cmp(&b.client_id))
+                });
+            }
+            keys.truncate(limit);
+        }
+
+        Ok(Response::new(GetUsageReportResponse { keys }))
+    }
+
+    async fn set_access_entry(
+        &self,
+        request: Request<SetAccessEntryRequest>,
+    ) -> Result<Response<SetAccessEntryResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let relayed = peers::from_peer(request.metadata());
+        let req = request.into_inner();
+        self.authorize_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+
+        let (access, list) = match req.list() {
+            AccessList::Allow => (Access::Allow, "allow"),
+            AccessList::Deny => (Access::Deny, "deny"),
+            AccessList::Unspecified => {
+                return Err(Status::invalid_argument("list must be ALLOW or DENY"))
+            }
+        };
+        if req.client_id.is_empty() {
+            return Err(Status::invalid_argument("client_id is required"));
+        }
+        let ttl = (req.ttl_seconds > 0)
+            .then(|| std::time::Duration::from_secs(u64::from(req.ttl_seconds)));
+
+        tracing::info!(
+            client_id = %req.client_id,
+            namespace = %namespace,
+            list,
+            ttl_seconds = req.ttl_seconds,
+            reason = %req.reason,
+            "access list entry set"
+        );
+        // The instance the change was made on alerts for it; peers don't.
+        if let (Access::Deny, Some(webhooks), false) = (access, &self.webhooks, relayed) {
+            webhooks.deny_listed(&namespace, &req.client_id, &req.reason, ttl);
+        }
+        let key = namespace::client_key(&namespace, &req.client_id)?;
+        self.access.set(key, access, ttl, req.reason.clone());
+        let message = format!("'{}' is on the {} list", req.client_id, list);
+        self.relay(
+            relayed,
+            PeerAction::SetAccessEntry(SetAccessEntryRequest { namespace, ..req }),
+        );
+        Ok(Response::new(SetAccessEntryResponse {
+            success: true,
+            message,
+        }))
+    }
+
+    async fn remove_access_entry(
+        &self,
+        request: Request<RemoveAccessEntryRequest>,
+    ) -> Result<Response<RemoveAccessEntryResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let relayed = peers::from_peer(request.metadata());
+        let req = request.into_inner();
+        self.authorize_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+
+        let key = namespace::client_key(&namespace, &req.client_id)?;
+        let removed = self.access.remove(&key);
+        let message = if removed {
+            format!("'{}' removed from the access lists", req.client_id)
+        } else {
+            format!("'{}' is not on an access list", req.client_id)
+        };
+        if removed {
+            tracing::info!(
+                client_id = %req.client_id,
+                namespace = %namespace,
+                "access list entry removed"
+            );
+            self.relay(
+                relayed,
+                PeerAction::RemoveAccessEntry(RemoveAccessEntryRequest { namespace, ..req }),
+            );
+        }
+        Ok(Response::new(RemoveAccessEntryResponse {
+            success: removed,
+            message,
+        }))
+    }
+
+    async fn list_access_entries(
+        &self,
+        request: Request<ListAccessEntriesRequest>,
+    ) -> Result<Response<ListAccessEntriesResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        self.authorize_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+
+        let wanted = match req.list() {
+            AccessList::Unspecified => None,
+            AccessList::Allow => Some(Access::Allow),
+            AccessList::Deny => Some(Access::Deny),
+        };
+        let entries = self
+            .access
+            .entries()
+            .into_iter()
+            .filter(|(_, entry)| wanted.is_none_or(|access| entry.access == access))
+            .filter_map(|(key, entry)| {
+                Some(access_entry_to_proto(namespace::unscoped(&namespace, &key)?, entry))
+            })
+            .collect();
+
+        Ok(Response::new(ListAccessEntriesResponse { entries }))
+    }
+
+    async fn inspect_key(
+        &self,
+        request: Request<InspectKeyRequest>,
+    ) -> Result<Response<InspectKeyResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        self.authorize_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+        let key = namespace::client_key(&namespace, &req.client_id)?;
+
+        let tier = self.tier_limiter(&req.client_id, "")?;
+        let (rule, limit) = match self.tiers.tier_for(&req.client_id, "") {
+            Some(name) => (format!("tier:{}", name), self.tiers.limit(name).cloned()),
+            None => match &self.admin {
+                Some(admin) => {
+                    let (rule, limit) = admin.rule_for(&key);
+                    (rule, Some(limit))
+                }
+                None => (DEFAULT_RULE.to_string(), None),
+            },
+        };
+        let bucket = match tier {
+            Some(tier) => tier.inspect(&key).await,
+            None => self.limiter.inspect(&key).await,
+        }
+        .map_err(|e| Status::internal(format!("Failed to inspect key: {}", e)))?;
+
+        let windows = self.usage_report.windows();
+        let recent = usage_report(&req.client_id, &key, &self.usage_report, windows);
+        Ok(Response::new(InspectKeyResponse {
+            rule,
+            // Without the rule at hand, the bucket still tells its size.
+            config: match limit {
+                Some(limit) => Some(limit_to_proto(&limit)),
+                None => bucket.map(|bucket| RateLimitConfig {
+                    capacity: bucket.capacity,
+                    refill_rate: bucket.refill_rate,
+                    refill_interval_seconds: 1,
+                    ..RateLimitConfig::default()
+                }),
+            },
+            tokens: bucket.map_or(0, |bucket| bucket.remaining),
+            last_refill_timestamp: bucket
+                .and_then(|bucket| bucket.last_refill)
+                .map_or(0, unix_seconds),
+            recent: recent.windows,
+            last_seen_timestamp: recent.last_seen_timestamp,
+            access: self
+                .access
+                .lookup(&key)
+                .map(|entry| access_entry_to_proto(&req.client_id, entry)),
+            node_id: self.node.id.clone(),
+            backend: self.node.backend.clone(),
+            client_id: req.client_id,
+        }))
+    }
+
+    async fn list_leases(
+        &self,
+        request: Request<ListLeasesRequest>,
+    ) -> Result<Response<ListLeasesResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        self.authorize_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+        let key = namespace::client_key(&namespace, &req.client_id)?;
+
+        let leases = match self.tier_limiter(&req.client_id, &req.tier)? {
+            Some(tier) => tier.leases(&key).await,
+            None => self.limiter.leases(&key).await,
+        }
+        .map_err(|e| Status::internal(format!("Failed to list leases: {}", e)))?;
+        Ok(Response::new(ListLeasesResponse {
+            leases: leases.iter().map(lease_to_proto).collect(),
+        }))
+    }
+
+    async fn get_decision_traces(
+        &self,
+        request: Request<GetDecisionTracesRequest>,
+    ) -> Result<Response<GetDecisionTracesResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        self.authorize_admin(principal.as_ref(), &req.admin_token).await?;
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+        let recorder = self
+            .recorder
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Decision recording is not enabled"))?;
+
+        let only =
+            (!req.client_id.is_empty()).then(|| namespace::scoped(&namespace, &req.client_id));
+        let include = |key: &str| match &only {
+            Some(only) => key == only,
+            None => namespace::unscoped(&namespace, key).is_some(),
+        };
+        let traces = recorder
+            .recent(include, req.limit as usize)
+            .into_iter()
+            .map(|trace| {
+                let (tokens_before, tokens_after) = trace.tokens.unwrap_or_default();
+                guardian_proto::DecisionTrace {
+                    timestamp_ms: trace
+                        .at
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as i64,
+                    client_id: namespace::unscoped(&namespace, &trace.key)
+                        .unwrap_or(&trace.key)
+                        .to_string(),
+                    cost: trace.cost,
+                    allowed: trace.allowed,
+                    reason: trace.reason.to_string(),
+                    rule: trace.rule,
+                    has_bucket: trace.tokens.is_some(),
+                    tokens_before,
+                    tokens_after,
+                    node_id: self.node.id.clone(),
+                    backend: self.node.backend.clone(),
+                    latency_us: trace.latency.as_micros() as u64,
+                    watched: trace.watched,
+                }
+            })
+            .collect();
+        Ok(Response::new(GetDecisionTracesResponse { traces }))
+    }
+
+    async fn get_stats(
+        &self,
+        request: Request<GetStatsRequest>,
+    ) -> Result<Response<GetStatsResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        self.authorize_admin(principal.as_ref(), &req.admin_token).await?;
+        let metrics = self
+            .metrics
+            .as_ref()
+            .ok_or_else(|| Status::unimplemented("Metrics are not enabled"))?;
+
+        let millis = |latency: std::time::Duration| latency.as_secs_f64() * 1000.0;
+        let operations = metrics
+            .backend_latency()
+            .into_iter()
+            .map(|latency| BackendOperationStats {
+                backend: latency.backend.to_string(),
+                operation: latency.operation.to_string(),
+                calls: latency.calls,
+                errors: latency.errors,
+                p50_ms: millis(latency.p50),
+                p95_ms: millis(latency.p95),
+                p99_ms: millis(latency.p99),
+            })
+            .collect();
+        let allowed = metrics.decisions(Decision::Allowed);
+        let denied = metrics.decisions(Decision::Denied);
+        let errors = metrics.decisions(Decision::Error);
+        let checks = allowed + denied + errors;
+        let share = |part: u64, whole: u64| {
+            if whole == 0 {
+                0.0
+            } else {
+                part as f64 / whole as f64
+            }
+        };
+        let cache = &config::CACHE_STATS;
+        let (cache_hits, cache_misses) = (cache.hits(), cache.misses());
+        Ok(Response::new(GetStatsResponse {
+            window_seconds: metrics.latency_window().as_secs() as u32,
+            operations,
+            checks,
+            allowed,
+            denied,
+            errors,
+            allow_rate: share(allowed, checks),
+            deny_rate: share(denied, checks),
+            active_keys: self
+                .active_keys
+                .as_ref()
+                .map_or(0, |active_keys| active_keys() as u64),
+            backend_healthy: self.limiter.health_check().await.is_ok(),
+            cache_hits,
+            cache_misses,
+            cache_hit_rate: share(cache_hits, cache_hits + cache_misses),
+            uptime_seconds: self.started.elapsed().as_secs(),
+        }))
+    }
+
+    async fn stream_limit_status(
+        &self,
+        request: Request<guardian_proto::StreamLimitRequest>,
+    ) -> Result<Response<Self::StreamLimitStatusStream>, Status> {
+        use tokio::sync::broadcast::error::RecvError;
+
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        let namespace = namespace::resolve(principal.as_ref(), &req.namespace)?;
+        let mut client_ids = Vec::new();
+        for client_id in std::iter::once(req.client_id).chain(req.client_ids) {
+            if !client_id.is_empty() && !client_ids.contains(&client_id) {
+                client_ids.push(client_id);
+            }
+        }
+        if client_ids.is_empty() {
+            return Err(Status::invalid_argument("client_id or client_ids is required"));
+        }
+        for client_id in &client_ids {
+            namespace::client_key(&namespace, client_id)?;
+        }
+        if client_ids.len() > MAX_STREAM_KEYS {
+            return Err(Status::invalid_argument(format!(
+                "at most {} keys per stream",
+                MAX_STREAM_KEYS
+            )));
+        }
+        let interval = match req.interval_seconds {
+            0 => HEARTBEAT_INTERVAL,
+            secs => std::time::Duration::from_secs(u64::from(secs)),
+        };
+        let below = req.below_tokens;
+        let limiter = self.limiter.clone();
+        let mut events = self.status.subscribe();
+        let mut shutdown = self.shutdown.subscribe();
+
+        // Send the current state, then only what changes it noticeably, with
+        // a heartbeat so quiet keys still show the stream is alive.
+        let stream = async_stream::stream! {
+            let mut watches = Vec::with_capacity(client_ids.len());
+            for client_id in client_ids {
+                let key = namespace::scoped(&namespace, &client_id);
+                let Ok(bucket) = limiter.inspect(&key).await else {
+                    return;
+                };
+                let mut watch = KeyWatch::new(client_id, key, bucket);
+                if below > 0 {
+                    watch = watch.with_notify_below(below);
+                }
+                if watch.reports(bucket.as_ref()) {
+                    yield Ok(watch.update(bucket.as_ref(), false));
+                }
+                watches.push(watch);
+            }
+
+            let heartbeat = tokio::time::sleep(interval);
+            tokio::pin!(heartbeat);
+            loop {
+                // `None` is a heartbeat, which re-reads every key.
+                let wake = tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(event) => match watches.iter_mut().position(|w| w.wants(&event)) {
+                            Some(index) => Some((index, event)),
+                            None => continue,
+                        },
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = &mut heartbeat => None,
+                    _ = shutdown.wait_for(|stopping| *stopping) => break,
+                };
+                let heartbeat_due = wake.is_none();
+                // Resets carry no bucket, so they are re-read too.
+                let targets = match wake {
+                    Some((index, event)) if !event.reset => {
+                        vec![(index, Some((event.bucket, event.denied)))]
+                    }
+                    Some((index, _)) => vec![(index, None)],
+                    None => (0..watches.len()).map(|index| (index, None)).collect(),
+                };
+                let mut sent = false;
+                for (index, known) in targets {
+                    let (bucket, denied) = match known {
+                        Some(known) => known,
+                        None => match limiter.inspect(watches[index].key()).await {
+                            Ok(bucket) => (bucket, false),
+                            Err(_) => return,
+                        },
+                    };
+                    let watch = &mut watches[index];
+                    watch.observe(bucket.as_ref());
+                    if watch.reports(bucket.as_ref()) {
+                        sent = true;
+                        yield Ok(watch.update(bucket.as_ref(), denied));
+                    }
+                }
+                if sent || heartbeat_due {
+                    heartbeat.as_mut().reset(tokio::time::Instant::now() + interval);
+                }
+            }
+        };
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+fn limit_from_proto(config: &RateLimitConfig) -> LimitConfig {
+    use guardian_proto::Algorithm as ProtoAlgorithm;
+
+    let algorithm = match config.algorithm() {
+        ProtoAlgorithm::Unspecified | ProtoAlgorithm::TokenBucket => Algorithm::TokenBucket,
+        ProtoAlgorithm::SlidingWindowLog | ProtoAlgorithm::SlidingWindowCounter => {
+            Algorithm::SlidingWindow
+        }
+        ProtoAlgorithm::FixedWindow => Algorithm::FixedWindow,
+    };
+    LimitConfig {
+        capacity: config.capacity,
+        refill_rate: config.refill_rate,
+        refill_interval_secs: u64::from(config.refill_interval_seconds.max(1)),
+        algorithm,
+        shadow: config.shadow,
+    }
+}
+
+fn limit_to_proto(limit: &LimitConfig) -> RateLimitConfig {
+    use guardian_proto::Algorithm as ProtoAlgorithm;
+
+    let algorithm = match limit.algorithm {
+        Algorithm::TokenBucket => ProtoAlgorithm::TokenBucket,
+        Algorithm::SlidingWindow => ProtoAlgorithm::SlidingWindowCounter,
+        Algorithm::FixedWindow => ProtoAlgorithm::FixedWindow,
+    };
+    RateLimitConfig {
+        capacity: limit.capacity,
+        refill_rate: limit.refill_rate,
+        refill_interval_seconds: limit.refill_interval_secs as u32,
+        algorithm: algorithm as i32,
+        shadow: limit.shadow,
+    }
+}
+
+fn access_entry_to_proto(client_id: &str, entry: AccessEntry) -> guardian_proto::AccessEntry {
+    let list = match entry.access {
+        Access::Allow => AccessList::Allow,
+        Access::Deny => AccessList::Deny,
+    };
+    guardian_proto::AccessEntry {
+        client_id: client_id.to_string(),
+        list: list as i32,
+        expires_at: entry.expires_at.map_or(0, unix_seconds),
+        reason: entry.reason,
+    }
+}
+
+/// `at` as Unix seconds, clamped to 0 for times before the epoch.
+fn unix_seconds(at: std::time::SystemTime) -> i64 {
+    at.duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// `at` as Unix milliseconds, clamped like `unix_seconds`.
+fn unix_millis(at: std::time::SystemTime) -> i64 {
+    at.duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[allow(clippy::result_large_err)]
+fn lease_id(id: u64) -> Result<u64, Status> {
+    match id {
+        0 => Err(Status::invalid_argument("lease_id is required")),
+        id => Ok(id),
+    }
+}
+
+fn lease_ttl(ttl_ms: u32) -> std::time::Duration {
+    match ttl_ms {
+        0 => DEFAULT_LEASE_TTL,
+        ms => std::time::Duration::from_millis(ms as u64),
+    }
+}
+
+/// What a check's grant is issued against: the key, and the tier that
+/// picked its bucket.
+fn grant_key(tier: &str, key: &str) -> String {
+    format!("{}|{}", tier, key)
+}
+
+fn lease_to_proto(lease: &Lease) -> TokenLease {
+    TokenLease {
+        lease_id: lease.id,
+        tokens: lease.tokens,
+        expires_at_ms: unix_millis(lease.expires_at),
+    }
+}
+
+/// What a CheckLimitStream call waits on next.
+enum StreamEvent {
+    Decided(CheckLimitStreamResponse),
+    /// A check from the client; `Ok(None)` once no more will be read.
+    Received(Result<Option<CheckLimitStreamRequest>, Status>),
+}
+
+/// One check's answer on a CheckLimitStream, carrying the failure in place
+/// of a result when the check failed.
+fn stream_response(
+    request_id: u64,
+    decided: Result<CheckLimitResponse, Status>,
+) -> CheckLimitStreamResponse {
+    match decided {
+        Ok(result) => CheckLimitStreamResponse {
+            request_id,
+            result: Some(result),
+            error: None,
+        },
+        Err(status) => CheckLimitStreamResponse {
+            request_id,
+            result: None,
+            error: Some(CheckLimitStreamError {
+                code: status.code() as i32,
+                message: status.message().to_string(),
+            }),
+        },
+    }
+}
+
+/// `key`'s counts over `windows`, reported under `client_id`.
+fn usage_report(
+    client_id: &str,
+    key: &str,
+    counters: &UsageCounters,
+    windows: &[std::time::Duration],
+) -> KeyUsageReport {
+    KeyUsageReport {
+        client_id: client_id.to_string(),
+        last_seen_timestamp: counters.last_seen(key).map_or(0, unix_seconds),
+        windows: windows
+            .iter()
+            .zip(counters.report(key, windows))
+            .map(|(window, stats)| UsageWindow {
+                window_seconds: window.as_secs() as u32,
+                allowed: stats.allowed,
+                denied: stats.denied,
+                tokens_consumed: stats.consumed,
+            })
+            .collect(),
+    }
+}
+
+
+
+type GrpcRouter = Router<Stack<AdmissionLayer, Stack<GrpcWebSupport, Identity>>>;
+
+/// Every gRPC service, ready to serve on one listener.
+fn grpc_router<B: StorageBackend + 'static>(
+    config: &GuardianConfig,
+    service: &GuardianService<B>,
+    health: HealthServer<impl Health>,
+    admission: &AdmissionLayer,
+) -> Result<GrpcRouter, Box<dyn std::error::Error>> {
+    let grpc = &config.global.grpc;
+    let secs = |secs: Option<u64>| secs.map(std::time::Duration::from_secs);
+    let web = GrpcWebSupport::new(config.global.grpc_web.as_ref());
+    // grpc-web goes outermost, so a shed call still reaches the browser
+    // as a grpc-web response it can read.
+    let mut server = Server::builder()
+        .accept_http1(web.enabled())
+        .http2_keepalive_interval(secs(grpc.keepalive_interval_secs))
+        .http2_keepalive_timeout(secs(grpc.keepalive_timeout_secs))
+        .tcp_keepalive(secs(grpc.tcp_keepalive_secs))
+        .layer(web)
+        .layer(admission.clone());
+    // Health probes stay unauthenticated so load balancers can reach them.
+    let mut router = server.add_service(health);
+    if config.global.reflection {
+        router = router
+            .add_service(reflection::v1(guardian_proto::FILE_DESCRIPTOR_SET)?)
+            .add_service(reflection::v1alpha(guardian_proto::FILE_DESCRIPTOR_SET)?);
+    }
+
+    let mut limiter = RateLimiterServer::new(service.clone());
+    let mut envoy = RateLimitServiceServer::new(RlsService::new(service.clone()));
+    let mut ext_authz = config.global.ext_authz.as_ref().map(|ext_authz| {
+        AuthorizationServer::new(ExtAuthzService::new(service.clone(), ext_authz.clone()))
+    });
+    for &compression in &grpc.compression {
+        limiter = limiter
+            .accept_compressed(compression.into())
+            .send_compressed(compression.into());
+        envoy = envoy
+            .accept_compressed(compression.into())
+            .send_compressed(compression.into());
+        ext_authz = ext_authz.map(|ext_authz| {
+            ext_authz
+                .accept_compressed(compression.into())
+                .send_compressed(compression.into())
         });
+    }
+    if let Some(max) = grpc.max_recv_message_bytes {
+        limiter = limiter.max_decoding_message_size(max);
+        envoy = envoy.max_decoding_message_size(max);
+        ext_authz = ext_authz.map(|ext_authz| ext_authz.max_decoding_message_size(max));
+    }
+    if let Some(max) = grpc.max_send_message_bytes {
+        limiter = limiter.max_encoding_message_size(max);
+        envoy = envoy.max_encoding_message_size(max);
+        ext_authz = ext_authz.map(|ext_authz| ext_authz.max_encoding_message_size(max));
+    }
+    Ok(match &config.auth {
+        Some(auth) => {
+            let interceptor = AuthInterceptor::from_config(auth)?;
+            let ext_authz =
+                ext_authz.map(|ext_authz| InterceptedService::new(ext_authz, interceptor.clone()));
+            router
+                .add_service(InterceptedService::new(limiter, interceptor.clone()))
+                .add_service(InterceptedService::new(envoy, interceptor))
+                .add_optional_service(ext_authz)
+        }
+        None => router
+            .add_service(limiter)
+            .add_service(envoy)
+            .add_optional_service(ext_authz),
+    })
+}
 
-        let response = client.check_limit(request).await.unwrap();
-        let result = response.into_inner();
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = config::Args::from_env();
+    if args.check_config {
+        std::process::exit(check::run(args).await);
+    }
+    let config = GuardianConfig::load_from_args()?;
+    let tracer_provider = logging::init(&config.global)?;
 
-        println!("Allowed: {}", result.allowed);
-        println!("Retry after: {} seconds", result.retry_after_seconds);
+    let router = Arc::new(config.build_backend().await?);
+    let admin = LimitAdmin::new(Arc::clone(&router), &config).await?;
+    if let Some(warm_start) = &config.warm_start {
+        let warmed = warm::run(warm_start, Arc::clone(&router) as _).await?;
+        tracing::info!(warmed, "warm start finished");
+    }
+
+    let leader = match &config.leader {
+        Some(leader) => {
+            let election = Arc::new(Election::new(leader, Arc::clone(&router) as _));
+            let mut jobs: Vec<Box<dyn Job>> = Vec::new();
+            if config.backends.lease.is_some() && leader.reclaim_leases_secs > 0 {
+                jobs.push(Box::new(ReclaimLeases::new(
+                    Arc::clone(&router) as _,
+                    std::time::Duration::from_secs(leader.reclaim_leases_secs),
+                )));
+            }
+            let tasks = election.start(jobs);
+            Some((election, tasks))
+        }
+        None => None,
+    };
+
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    tokio::spawn(health::report_backend_health(
+        health_reporter.clone(),
+        Arc::clone(&router),
+        health::PROBE_INTERVAL,
+    ));
+
+    let audit = match &config.audit {
+        Some(audit) => Some(Arc::new(
+            Auditor::from_config(audit).await?.with_rules(Arc::clone(&router)),
+        )),
+        None => None,
+    };
+
+    let metrics = Arc::new(Metrics::new()?);
+    let stats = Arc::new(StatsBackend::new(
+        MeteredBackend::new(router, Arc::clone(&metrics), config.backends.primary.kind()),
+        STATS_WINDOW,
+    ));
+    let limiter = RateLimiter::new(Arc::clone(&stats), config.global.fail_open);
+    let mut service = GuardianService::new(limiter)
+        .with_metrics(Arc::clone(&metrics))
+        .with_admin_token(config.global.admin_token.clone())
+        .with_limit_admin(Arc::new(admin))
+        .with_tiers(Tiers::from_config(&config).await?)
+        .with_usage_report(&config.usage_report)
+        .with_active_keys({
+            let stats = Arc::clone(&stats);
+            move || stats.active_keys()
+        })
+        .with_node(
+            config
+                .audit
+                .as_ref()
+                .and_then(|audit| audit.node_id.clone())
+                .unwrap_or_else(config::default_node_id),
+            config.backends.primary.kind(),
+        );
+    if let Some(audit) = audit {
+        service = service.with_audit(audit);
+    }
+    if let Some(recorder) = &config.decision_recorder {
+        service = service.with_decision_recorder(recorder);
+    }
+    if let Some(webhooks) = &config.webhooks {
+        service = service.with_webhooks(Arc::new(Notifier::from_config(webhooks)?));
+    }
+    if let Some(peers) = &config.peers {
+        service = service.with_peers(Arc::new(PeerMesh::new(
+            peers,
+            config.global.admin_token.clone(),
+        )));
+    }
+
+    let addr: std::net::SocketAddr = config.global.listen_addr.parse()?;
+    tracing::info!(%addr, "Guardian rate limiter starting");
+
+    if let Some(statsd) = &config.global.statsd {
+        tracing::info!(address = %statsd.address, "pushing metrics over statsd");
+        let stats = Arc::clone(&stats);
+        tokio::spawn(statsd::export(
+            statsd.clone(),
+            Arc::clone(&metrics),
+            service.heavy_hitters(),
+            move || stats.active_keys(),
+        ));
+    }
+    if config.global.metrics_port != 0 {
+        let metrics_addr = std::net::SocketAddr::new(addr.ip(), config.global.metrics_port);
+        let listener = tokio::net::TcpListener::bind(metrics_addr).await?;
+        tracing::info!("metrics available at http://{}/metrics", metrics_addr);
+        tokio::spawn(metrics::serve(
+            listener,
+            Arc::clone(&metrics),
+            service.heavy_hitters(),
+            move || stats.active_keys(),
+        ));
+    }
+
+    // One admission budget covers every listener.
+    let admission = AdmissionLayer::new(Arc::new(
+        Admission::new(config.global.admission).with_metrics(Arc::clone(&metrics)),
+    ));
+    let tls = match &config.global.tls {
+        Some(tls) => {
+            let (acceptor, cert) = tls::server_tls(tls)?;
+            let poll = (tls.reload_interval_secs > 0)
+                .then(|| std::time::Duration::from_secs(tls.reload_interval_secs));
+            tokio::spawn(tls::watch(cert, poll));
+            Some(acceptor)
+        }
+        None => None,
+    };
+    let router = grpc_router(&config, &service, health_service.clone(), &admission)?;
+
+    // Local clients on the socket skip TLS; file permissions guard access.
+    #[cfg(unix)]
+    let unix = match &config.global.unix_socket {
+        Some(path) => {
+            let _ = std::fs::remove_file(path);
+            let listener = tokio::net::UnixListener::bind(path)?;
+            tracing::info!(path = %path.display(), "listening on Unix socket");
+            let router = grpc_router(&config, &service, health_service, &admission)?;
+            let stopping = service.clone();
+            Some(router.serve_with_incoming_shutdown(
+                tokio_stream::wrappers::UnixListenerStream::new(listener),
+                async move { stopping.stopping().await },
+            ))
+        }
+        None => None,
+    };
+    #[cfg(not(unix))]
+    if config.global.unix_socket.is_some() {
+        return Err("unix_socket is only supported on Unix".into());
+    }
+
+    let http = match config.global.http_port {
+        Some(port) => {
+            let http_addr = std::net::SocketAddr::new(addr.ip(), port);
+            let listener = tokio::net::TcpListener::bind(http_addr).await?;
+            let auth = config.auth.as_ref().map(AuthInterceptor::from_config).transpose()?;
+            tracing::info!(%http_addr, "HTTP gateway listening");
+            Some(tokio::spawn(gateway::serve(listener, Gateway::new(service.clone(), auth))))
+        }
+        None => None,
+    };
+
+    let draining_service = service.clone();
+
+    // On SIGTERM: report NOT_SERVING, stop accepting connections, end open
+    // streams, and give in-flight RPCs until the deadline to finish.
+    let draining = Arc::new(tokio::sync::Notify::new());
+    let shutdown = {
+        let draining = Arc::clone(&draining);
+        let service = draining_service.clone();
+        async move {
+            shutdown::signal().await;
+            tracing::info!("shutdown requested, draining connections");
+            health_reporter.set_service_status("", ServingStatus::NotServing).await;
+            health_reporter
+                .set_service_status(rate_limiter_server::SERVICE_NAME, ServingStatus::NotServing)
+                .await;
+            service.begin_shutdown();
+            draining.notify_one();
+        }
+    };
+    // With TLS the listener is ours, so the certificate can change under it.
+    let server: Pin<Box<dyn std::future::Future<Output = Result<(), tonic::transport::Error>>>> =
+        match tls {
+            Some(acceptor) => {
+                let listener = tokio::net::TcpListener::bind(addr).await?;
+                let tcp_keepalive = config
+                    .global
+                    .grpc
+                    .tcp_keepalive_secs
+                    .map(std::time::Duration::from_secs);
+                let tcp = TcpIncoming::from_listener(listener, true, tcp_keepalive)
+                    .map_err(|e| e as Box<dyn std::error::Error>)?;
+                let incoming = tls::incoming(tcp, acceptor);
+                Box::pin(router.serve_with_incoming_shutdown(incoming, shutdown))
+            }
+            None => Box::pin(router.serve_with_shutdown(addr, shutdown)),
+        };
+    let deadline = async {
+        draining.notified().await;
+        tokio::time::sleep(std::time::Duration::from_secs(config.global.shutdown_grace_secs))
+            .await;
+    };
+    #[cfg(unix)]
+    let server = async {
+        match unix {
+            Some(unix) => tokio::try_join!(server, unix).map(|_| ()),
+            None => server.await,
+        }
+    };
+    tokio::select! {
+        result = server => result?,
+        _ = deadline => tracing::warn!("drain deadline passed, closing remaining connections"),
+    }
+    #[cfg(unix)]
+    if let Some(path) = &config.global.unix_socket {
+        let _ = std::fs::remove_file(path);
     }
+    if let Some(http) = http {
+        // Started draining together with gRPC, so normally finished already.
+        let grace = std::time::Duration::from_secs(config.global.shutdown_grace_secs);
+        let _ = tokio::time::timeout(grace, http).await;
+    }
+
+    if let Err(e) = draining_service.flush().await {
+        tracing::error!(error = %e, "failed to flush backend state");
+    }
+    if let Some((election, tasks)) = leader {
+        tasks.iter().for_each(|task| task.abort());
+        election.resign().await;
+    }
+    tracing::info!("shutdown complete");
+
+    if let Some(provider) = tracer_provider {
+        provider.shutdown()?;
+    }
+
+    Ok(())
 }
 
 
@@ -259,4 +2242,1266 @@ pub async fn check_with_mode<B: StorageBackend>(
             }
         }
     }
-}
\ No newline at end of file
+}
+
+
+
+#[cfg(test)]
+mod client_example {
+    use super::*;
+    use guardian_core::{MemoryBackend, TokenBucketConfig};
+    use guardian_proto::{rate_limiter_client::RateLimiterClient, CheckLimitRequest};
+
+    #[tokio::test]
+    async fn test_client_integration() {
+        // A service of its own on a free port, in place of a running one
+        let backend = MemoryBackend::new(TokenBucketConfig::default());
+        let service = GuardianService::new(RateLimiter::new(backend, false));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(RateLimiterServer::new(service))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let mut client = RateLimiterClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+
+        let request = tonic::Request::new(CheckLimitRequest {
+            client_id: "user123".to_string(),
+            cost: 10,
+            override_config: None,
+            tier: String::new(),
+            namespace: String::new(),
+            refundable: false,
+        });
+
+        let response = client.check_limit(request).await.unwrap();
+        let result = response.into_inner();
+
+        println!("Allowed: {}", result.allowed);
+        println!("Retry after: {} seconds", result.retry_after_seconds);
+        assert!(result.allowed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_core::{MemoryBackend, TokenBucketConfig};
+
+    #[tokio::test]
+    async fn test_reset_limit_restores_allowance() {
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(1),
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false))
+            .with_admin_token(Some("secret".to_string()));
+        let check = |cost| {
+            Request::new(CheckLimitRequest {
+                client_id: "user1".to_string(),
+                cost,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            })
+        };
+
+        assert!(service.check_limit(check(5)).await.unwrap().into_inner().allowed);
+        assert!(!service.check_limit(check(5)).await.unwrap().into_inner().allowed);
+
+        let reset = |admin_token: &str| {
+            Request::new(ResetLimitRequest {
+                client_id: "user1".to_string(),
+                admin_token: admin_token.to_string(),
+                namespace: String::new(),
+            })
+        };
+        let denied = service.reset_limit(reset("guess")).await.unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        assert!(!service.check_limit(check(5)).await.unwrap().into_inner().allowed);
+
+        assert!(service.reset_limit(reset("secret")).await.unwrap().into_inner().success);
+
+        // An admin principal from the auth interceptor needs no token.
+        let mut by_admin = reset("");
+        by_admin.extensions_mut().insert(Principal {
+            id: "ops".to_string(),
+            tenant: None,
+            admin: true,
+        });
+        assert!(service.reset_limit(by_admin).await.unwrap().into_inner().success);
+        assert!(service.check_limit(check(5)).await.unwrap().into_inner().allowed);
+    }
+
+    #[tokio::test]
+    async fn test_check_limit_batch_decides_each_entry() {
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(1),
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false));
+        let entry = |client_id: &str, cost| CheckLimitRequest {
+            client_id: client_id.to_string(),
+            cost,
+            override_config: None,
+            tier: String::new(),
+            namespace: String::new(),
+            refundable: false,
+        };
+
+        let response = service
+            .check_limit_batch(Request::new(CheckLimitBatchRequest {
+                checks: vec![entry("user1", 4), entry("user1", 4), entry("user2", 4)],
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let allowed: Vec<bool> = response.results.iter().map(|r| r.allowed).collect();
+        assert_eq!(allowed, vec![true, false, true]);
+    }
+
+    #[tokio::test]
+    async fn test_check_limit_all_takes_every_entry_or_none() {
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(3600),
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false));
+        let entry = |client_id: &str, cost| CheckLimitRequest {
+            client_id: client_id.to_string(),
+            cost,
+            override_config: None,
+            tier: String::new(),
+            namespace: String::new(),
+            refundable: true,
+        };
+        let all = |checks| async {
+            service
+                .check_limit_all(Request::new(CheckLimitBatchRequest { checks }))
+                .await
+                .unwrap()
+                .into_inner()
+                .results
+        };
+
+        let results = all(vec![
+            entry("user1", 3),
+            entry("user2", 6),
+            entry("user3", 1),
+        ])
+        .await;
+        let reasons: Vec<&str> = results.iter().map(|r| r.reason.as_str()).collect();
+        assert_eq!(
+            reasons,
+            vec!["all_or_nothing", "bucket_exhausted", "all_or_nothing"]
+        );
+        assert!(results.iter().all(|r| !r.allowed && r.grant_id.is_empty()));
+        // Every entry waits for user2's bucket.
+        assert!(results.iter().all(|r| r.retry_after_seconds == 1));
+        assert_eq!(results[0].remaining_tokens, 5);
+
+        // Nothing was taken, and the entries that now go through can be
+        // refunded one by one.
+        let results = all(vec![entry("user1", 5), entry("user3", 5)]).await;
+        assert!(results.iter().all(|r| r.allowed && !r.grant_id.is_empty()));
+        assert_eq!(results[1].remaining_tokens, 0);
+    }
+
+    #[tokio::test]
+    async fn test_check_limit_all_needs_a_backend_that_takes_keys_together() {
+        use guardian_core::RateLimitError;
+
+        /// Takes keys one at a time only.
+        struct OneAtATime(MemoryBackend);
+
+        #[tonic::async_trait]
+        impl StorageBackend for OneAtATime {
+            async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+                self.0.take_token(key, cost).await
+            }
+            async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+                self.0.get_usage(key).await
+            }
+            async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+                self.0.reset(key).await
+            }
+        }
+
+        let backend = OneAtATime(MemoryBackend::new(TokenBucketConfig::default()));
+        let service = GuardianService::new(RateLimiter::new(backend, true));
+        let entry = |client_id: &str| CheckLimitRequest {
+            client_id: client_id.to_string(),
+            cost: 1,
+            ..CheckLimitRequest::default()
+        };
+        let all = |checks| service.check_limit_all(Request::new(CheckLimitBatchRequest { checks }));
+
+        // Refused even though the limiter fails open, and nothing is taken.
+        let failed = all(vec![entry("user1"), entry("user2")]).await.unwrap_err();
+        assert_eq!(failed.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(service.limiter.get_usage("user1").await.unwrap(), 0);
+
+        // Keys governed by rules with buckets of their own can't be taken
+        // together either.
+        let mut config = GuardianConfig::default();
+        config
+            .limits
+            .insert("api:*".to_string(), LimitConfig::default());
+        let router = config.build_backend().await.unwrap();
+        let service = GuardianService::new(RateLimiter::new(router, false));
+        let failed = service
+            .check_limit_all(Request::new(CheckLimitBatchRequest {
+                checks: vec![entry("api:user1"), entry("web:user1")],
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(failed.code(), tonic::Code::FailedPrecondition);
+        let results = service
+            .check_limit_all(Request::new(CheckLimitBatchRequest {
+                checks: vec![entry("api:user1"), entry("api:user2")],
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        assert!(results.iter().all(|r| r.allowed));
+    }
+
+    #[tokio::test]
+    async fn test_check_limit_all_records_only_the_final_outcome() {
+        let router = Arc::new(GuardianConfig::default().build_backend().await.unwrap());
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let backend = MeteredBackend::new(router, Arc::clone(&metrics), "memory");
+        let service = GuardianService::new(RateLimiter::new(backend, false))
+            .with_metrics(Arc::clone(&metrics))
+            .with_admin_token(Some("secret".to_string()));
+        let entry = |client_id: &str, cost| CheckLimitRequest {
+            client_id: client_id.to_string(),
+            cost,
+            ..CheckLimitRequest::default()
+        };
+
+        let results = service
+            .check_limit_all(Request::new(CheckLimitBatchRequest {
+                checks: vec![entry("user1", 1), entry("user2", 1_000_000)],
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .results;
+        assert!(results.iter().all(|r| !r.allowed));
+
+        // user1's bucket had room, but its entry counts as denied.
+        let stats = service
+            .get_stats(Request::new(GetStatsRequest {
+                admin_token: "secret".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((stats.checks, stats.allowed, stats.denied), (2, 0, 2));
+        let text = metrics.encode();
+        assert!(
+            text.contains(r#"guardian_rule_decisions_total{decision="denied",rule="default"} 2"#)
+        );
+        assert!(!text.contains(r#"guardian_rule_decisions_total{decision="allowed""#));
+    }
+
+    #[tokio::test]
+    async fn test_clones_check_concurrently_against_one_limiter() {
+        use guardian_core::RateLimitError;
+
+        /// Holds every check until two are in flight at once.
+        struct Gated(MemoryBackend, tokio::sync::Barrier);
+
+        #[tonic::async_trait]
+        impl StorageBackend for Gated {
+            async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+                self.1.wait().await;
+                self.0.take_token(key, cost).await
+            }
+            async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+                self.0.get_usage(key).await
+            }
+            async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+                self.0.reset(key).await
+            }
+        }
+
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(3600),
+        };
+        let backend = Gated(MemoryBackend::new(config), tokio::sync::Barrier::new(2));
+        let service = GuardianService::new(RateLimiter::new(backend, false));
+        let check = |service: GuardianService<Gated>| async move {
+            let request = Request::new(CheckLimitRequest {
+                client_id: "user1".to_string(),
+                cost: 3,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            });
+            service.check_limit(request).await.unwrap().into_inner().allowed
+        };
+
+        let both = async { tokio::join!(check(service.clone()), check(service.clone())) };
+        let (first, second) = tokio::time::timeout(std::time::Duration::from_secs(5), both)
+            .await
+            .expect("checks were serialized");
+        // Both clones spent from the same bucket.
+        assert!(first != second);
+    }
+
+    #[tokio::test]
+    async fn test_refund_tokens_redeems_the_check_grant_once() {
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(3600),
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false));
+        let check = |cost| {
+            Request::new(CheckLimitRequest {
+                client_id: "user1".to_string(),
+                cost,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: true,
+            })
+        };
+        let refund = |client_id: &str, cost, grant_id: &str| {
+            Request::new(RefundTokensRequest {
+                client_id: client_id.to_string(),
+                cost,
+                tier: String::new(),
+                namespace: String::new(),
+                grant_id: grant_id.to_string(),
+            })
+        };
+
+        let allowed = service.check_limit(check(3)).await.unwrap().into_inner();
+        assert!(allowed.allowed && !allowed.grant_id.is_empty());
+        let denied = service.check_limit(check(3)).await.unwrap().into_inner();
+        assert!(!denied.allowed && denied.grant_id.is_empty());
+
+        // Without the grant, or against another client, nothing comes back.
+        let grant_id = allowed.grant_id.as_str();
+        for (client_id, grant_id) in [("user1", ""), ("user1", "guess"), ("user2", grant_id)] {
+            let refused = service.refund_tokens(refund(client_id, 3, grant_id)).await;
+            assert_eq!(refused.unwrap_err().code(), tonic::Code::PermissionDenied);
+        }
+
+        // Asking for more than the check took gives back only what it took.
+        let refunded = service.refund_tokens(refund("user1", 50, &allowed.grant_id)).await;
+        assert!(refunded.unwrap().into_inner().success);
+        let again = service.refund_tokens(refund("user1", 3, &allowed.grant_id)).await;
+        assert_eq!(again.unwrap_err().code(), tonic::Code::PermissionDenied);
+        assert!(service.check_limit(check(5)).await.unwrap().into_inner().allowed);
+        assert!(!service.check_limit(check(1)).await.unwrap().into_inner().allowed);
+
+        // Checks that don't ask for a grant leave nothing in the ledger.
+        let mut plain = check(1).into_inner();
+        plain.client_id = "user3".to_string();
+        plain.refundable = false;
+        let unrefundable = service.check_limit(Request::new(plain)).await.unwrap().into_inner();
+        assert!(unrefundable.allowed && unrefundable.grant_id.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_limit_config_admin_rpcs() {
+        let config = GuardianConfig::default();
+        let router = Arc::new(config.build_backend().await.unwrap());
+        let admin = LimitAdmin::new(Arc::clone(&router), &config).await.unwrap();
+        let service = GuardianService::new(RateLimiter::new(router, false))
+            .with_admin_token(Some("secret".to_string()))
+            .with_limit_admin(Arc::new(admin));
+        let set = |admin_token: &str| {
+            Request::new(SetLimitConfigRequest {
+                pattern: "trial:*".to_string(),
+                config: Some(RateLimitConfig {
+                    capacity: 3,
+                    refill_rate: 1,
+                    refill_interval_seconds: 60,
+                    algorithm: guardian_proto::Algorithm::TokenBucket as i32,
+                    shadow: false,
+                }),
+                admin_token: admin_token.to_string(),
+                namespace: String::new(),
+            })
+        };
+
+        let denied = service.set_limit_config(set("wrong")).await.unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        assert!(service.set_limit_config(set("secret")).await.unwrap().into_inner().success);
+
+        let rules = service
+            .get_limit_config(Request::new(GetLimitConfigRequest {
+                pattern: "trial:*".to_string(),
+                admin_token: "secret".to_string(),
+                namespace: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .rules;
+        assert_eq!(rules[0].config.as_ref().unwrap().capacity, 3);
+
+        let check = Request::new(CheckLimitRequest {
+            client_id: "trial:alice".to_string(),
+            cost: 4,
+            override_config: None,
+            tier: String::new(),
+            namespace: String::new(),
+            refundable: false,
+        });
+        assert!(!service.check_limit(check).await.unwrap().into_inner().allowed);
+        let usage = service
+            .get_usage(Request::new(GetUsageRequest {
+                client_id: "trial:alice".to_string(),
+                namespace: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((usage.total_capacity, usage.refill_rate), (3, 1));
+
+        let deleted = service
+            .delete_limit_config(Request::new(DeleteLimitConfigRequest {
+                pattern: "trial:*".to_string(),
+                admin_token: "secret".to_string(),
+                namespace: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(deleted.success);
+    }
+
+    #[tokio::test]
+    async fn test_tiers_get_their_own_limits() {
+        let mut config = GuardianConfig::default();
+        let tier = |capacity| LimitConfig {
+            capacity,
+            refill_rate: 1,
+            refill_interval_secs: 60,
+            ..LimitConfig::default()
+        };
+        config.tiers.insert("free".to_string(), tier(5));
+        config.tiers.insert("premium".to_string(), tier(50));
+        config
+            .client_tiers
+            .insert("acme".to_string(), "premium".to_string());
+
+        let limiter = RateLimiter::new(config.build_backend().await.unwrap(), false);
+        let service =
+            GuardianService::new(limiter).with_tiers(Tiers::from_config(&config).await.unwrap());
+        let check = |client_id: &str, tier: &str| {
+            Request::new(CheckLimitRequest {
+                client_id: client_id.to_string(),
+                cost: 20,
+                override_config: None,
+                tier: tier.to_string(),
+                namespace: String::new(),
+                refundable: false,
+            })
+        };
+
+        assert!(service.check_limit(check("acme", "")).await.unwrap().into_inner().allowed);
+        assert!(!service.check_limit(check("bob", "free")).await.unwrap().into_inner().allowed);
+        let unknown = service.check_limit(check("bob", "gold")).await.unwrap_err();
+        assert_eq!(unknown.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_shadow_rules_log_but_do_not_deny() {
+        let mut config = GuardianConfig::default();
+        let limit = |shadow| LimitConfig {
+            capacity: 1,
+            refill_rate: 1,
+            refill_interval_secs: 60,
+            shadow,
+            ..LimitConfig::default()
+        };
+        config.limits.insert("beta:*".to_string(), limit(true));
+        config.limits.insert("api:*".to_string(), limit(false));
+        let router = Arc::new(config.build_backend().await.unwrap());
+        let admin = LimitAdmin::new(Arc::clone(&router), &config).await.unwrap();
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let service = GuardianService::new(RateLimiter::new(router, false))
+            .with_metrics(Arc::clone(&metrics))
+            .with_limit_admin(Arc::new(admin));
+        let check = |client_id: &str| {
+            Request::new(CheckLimitRequest {
+                client_id: client_id.to_string(),
+                cost: 1,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            })
+        };
+
+        for _ in 0..3 {
+            assert!(service.check_limit(check("beta:alice")).await.unwrap().into_inner().allowed);
+        }
+        let shadowed = service.check_limit(check("beta:alice")).await.unwrap().into_inner();
+        assert_eq!(shadowed.reason, "shadow_rule");
+        let allowed = service.check_limit(check("api:alice")).await.unwrap().into_inner();
+        assert!(allowed.allowed);
+        assert_eq!(allowed.reason, "within_limit");
+        let denied = service.check_limit(check("api:alice")).await.unwrap().into_inner();
+        assert!(!denied.allowed);
+        assert_eq!(denied.reason, "bucket_exhausted");
+
+        let text = metrics.encode();
+        assert!(text.contains(r#"guardian_shadow_denials_total{rule="beta:*"} 3"#));
+        assert!(!text.contains(r#"rule="api:*""#));
+    }
+
+    #[tokio::test]
+    async fn test_get_stats_reports_backend_latency() {
+        let router = Arc::new(GuardianConfig::default().build_backend().await.unwrap());
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let backend = MeteredBackend::new(router, Arc::clone(&metrics), "memory");
+        let service = GuardianService::new(RateLimiter::new(backend, false))
+            .with_metrics(metrics)
+            .with_admin_token(Some("secret".to_string()))
+            .with_active_keys(|| 1);
+        for _ in 0..3 {
+            let check = Request::new(CheckLimitRequest {
+                client_id: "user1".to_string(),
+                cost: 1,
+                ..CheckLimitRequest::default()
+            });
+            service.check_limit(check).await.unwrap();
+        }
+
+        let stats = |admin_token: &str| {
+            Request::new(GetStatsRequest {
+                admin_token: admin_token.to_string(),
+            })
+        };
+        assert!(service.get_stats(stats("wrong")).await.is_err());
+        let stats = service.get_stats(stats("secret")).await.unwrap().into_inner();
+        assert_eq!(stats.window_seconds, 60);
+        let take = &stats.operations[0];
+        assert_eq!((take.backend.as_str(), take.operation.as_str()), ("memory", "take_token"));
+        assert_eq!((take.calls, take.errors), (3, 0));
+        assert!(take.p50_ms > 0.0 && take.p50_ms <= take.p95_ms && take.p95_ms <= take.p99_ms);
+
+        assert_eq!((stats.checks, stats.allowed, stats.denied), (3, 3, 0));
+        assert_eq!((stats.allow_rate, stats.deny_rate), (1.0, 0.0));
+        assert_eq!(stats.active_keys, 1);
+        assert!(stats.backend_healthy);
+    }
+
+    #[tokio::test]
+    async fn test_responses_report_backend_bucket() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(60),
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false));
+        let check = Request::new(CheckLimitRequest {
+            client_id: "user1".to_string(),
+            cost: 3,
+            override_config: None,
+            tier: String::new(),
+            namespace: String::new(),
+            refundable: false,
+        });
+        let decision = service.check_limit(check).await.unwrap().into_inner();
+        assert_eq!(decision.remaining_tokens, 7);
+
+        let usage = service
+            .get_usage(Request::new(GetUsageRequest {
+                client_id: "user1".to_string(),
+                namespace: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(usage.used_tokens, 3);
+        assert_eq!(usage.total_capacity, 10);
+        assert_eq!(usage.refill_rate, 1);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        assert!((now - 5..=now).contains(&usage.last_refill_timestamp));
+    }
+
+    #[tokio::test]
+    async fn test_batched_responses_report_backend_bucket() {
+        let mut config = GuardianConfig::default();
+        config.backends.batch_size = Some(4);
+        config.limits.insert(
+            DEFAULT_RULE.to_string(),
+            LimitConfig {
+                capacity: 10,
+                refill_rate: 1,
+                refill_interval_secs: 60,
+                ..LimitConfig::default()
+            },
+        );
+        let limiter = RateLimiter::new(config.build_backend().await.unwrap(), false);
+        let service = GuardianService::new(limiter);
+        let check = || {
+            Request::new(CheckLimitRequest {
+                client_id: "user1".to_string(),
+                cost: 1,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            })
+        };
+        // Answered from the batch after the first, and counting what is
+        // left of it.
+        for remaining in [9, 8, 7] {
+            let decision = service.check_limit(check()).await.unwrap().into_inner();
+            assert_eq!((decision.remaining_tokens, decision.limit), (remaining, 10));
+        }
+
+        let usage = service
+            .get_usage(Request::new(GetUsageRequest {
+                client_id: "user1".to_string(),
+                namespace: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!((usage.used_tokens, usage.total_capacity), (4, 10));
+
+        // Leases go to the bucket behind the batches.
+        let lease = service
+            .acquire_lease(Request::new(AcquireLeaseRequest {
+                client_id: "user1".to_string(),
+                namespace: String::new(),
+                tier: String::new(),
+                lease_id: 1,
+                tokens: 2,
+                ttl_ms: 0,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(lease.granted);
+        // The batch's last token, then a new batch from what the lease
+        // left.
+        service.check_limit(check()).await.unwrap();
+        let decision = service.check_limit(check()).await.unwrap().into_inner();
+        assert_eq!(decision.remaining_tokens, 3);
+    }
+
+    #[tokio::test]
+    async fn test_namespaces_isolate_buckets() {
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(60),
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false));
+        let check = |namespace: &str| {
+            Request::new(CheckLimitRequest {
+                client_id: "user1".to_string(),
+                cost: 5,
+                override_config: None,
+                tier: String::new(),
+                namespace: namespace.to_string(),
+                refundable: false,
+            })
+        };
+
+        assert!(service.check_limit(check("billing")).await.unwrap().into_inner().allowed);
+        assert!(!service.check_limit(check("billing")).await.unwrap().into_inner().allowed);
+        assert!(service.check_limit(check("search")).await.unwrap().into_inner().allowed);
+        assert!(service.check_limit(check("")).await.unwrap().into_inner().allowed);
+
+        let mut bound = check("search");
+        bound.extensions_mut().insert(Principal {
+            id: "svc".to_string(),
+            tenant: Some("billing".to_string()),
+            admin: false,
+        });
+        let denied = service.check_limit(bound).await.unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_inspect_key_explains_throttling() {
+        let mut config = GuardianConfig::default();
+        config.limits.insert(
+            "api:*".to_string(),
+            LimitConfig {
+                capacity: 3,
+                refill_rate: 1,
+                refill_interval_secs: 60,
+                ..LimitConfig::default()
+            },
+        );
+        let router = Arc::new(config.build_backend().await.unwrap());
+        let admin = LimitAdmin::new(Arc::clone(&router), &config).await.unwrap();
+        let service = GuardianService::new(RateLimiter::new(router, false))
+            .with_admin_token(Some("secret".to_string()))
+            .with_limit_admin(Arc::new(admin))
+            .with_node("node-a".to_string(), "memory");
+        let check = || {
+            Request::new(CheckLimitRequest {
+                client_id: "api:alice".to_string(),
+                cost: 2,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            })
+        };
+        assert!(service.check_limit(check()).await.unwrap().into_inner().allowed);
+        assert!(!service.check_limit(check()).await.unwrap().into_inner().allowed);
+
+        let inspect = |admin_token: &str| {
+            Request::new(InspectKeyRequest {
+                client_id: "api:alice".to_string(),
+                admin_token: admin_token.to_string(),
+                namespace: String::new(),
+            })
+        };
+        let denied = service.inspect_key(inspect("wrong")).await.unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+
+        let key = service.inspect_key(inspect("secret")).await.unwrap().into_inner();
+        assert_eq!(key.rule, "api:*");
+        let config = key.config.unwrap();
+        assert_eq!((config.capacity, config.refill_rate, config.refill_interval_seconds), (3, 1, 60));
+        assert_eq!(key.tokens, 1);
+        assert!(key.last_refill_timestamp > 0);
+        assert_eq!((key.recent[0].allowed, key.recent[0].denied), (1, 1));
+        assert!(key.last_seen_timestamp > 0);
+        assert!(key.access.is_none());
+        assert_eq!((key.node_id.as_str(), key.backend.as_str()), ("node-a", "memory"));
+    }
+
+    #[tokio::test]
+    async fn test_records_traces_of_watched_keys() {
+        let mut config = GuardianConfig::default();
+        config.limits.insert(
+            "api:*".to_string(),
+            LimitConfig {
+                capacity: 3,
+                refill_rate: 1,
+                refill_interval_secs: 60,
+                ..LimitConfig::default()
+            },
+        );
+        let router = Arc::new(config.build_backend().await.unwrap());
+        let admin = LimitAdmin::new(Arc::clone(&router), &config).await.unwrap();
+        let service = GuardianService::new(RateLimiter::new(router, false))
+            .with_admin_token(Some("secret".to_string()))
+            .with_limit_admin(Arc::new(admin))
+            .with_decision_recorder(&DecisionRecorderConfig {
+                sample_ratio: 0.0,
+                watch_keys: vec!["api:alice".to_string()],
+                capacity: 10,
+            })
+            .with_node("node-a".to_string(), "memory");
+        for client_id in ["api:alice", "api:alice", "api:bob"] {
+            let check = Request::new(CheckLimitRequest {
+                client_id: client_id.to_string(),
+                cost: 2,
+                ..CheckLimitRequest::default()
+            });
+            service.check_limit(check).await.unwrap();
+        }
+
+        let traces = service
+            .get_decision_traces(Request::new(GetDecisionTracesRequest {
+                admin_token: "secret".to_string(),
+                ..GetDecisionTracesRequest::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .traces;
+        // Only the watched key, newest first.
+        assert_eq!(traces.len(), 2);
+        let (denied, allowed) = (&traces[0], &traces[1]);
+        assert_eq!(allowed.client_id, "api:alice");
+        assert_eq!(allowed.rule, "api:*");
+        assert_eq!((allowed.tokens_before, allowed.tokens_after), (3, 1));
+        assert!(!denied.allowed);
+        assert_eq!(denied.reason, "bucket_exhausted");
+        assert_eq!((denied.tokens_before, denied.tokens_after), (1, 1));
+        assert!(denied.watched && denied.has_bucket);
+        assert_eq!((denied.node_id.as_str(), denied.backend.as_str()), ("node-a", "memory"));
+    }
+
+    #[tokio::test]
+    async fn test_access_lists_override_limits() {
+        let config = TokenBucketConfig {
+            capacity: 1,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(60),
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false))
+            .with_admin_token(Some("secret".to_string()));
+        let check = |client_id: &str| {
+            Request::new(CheckLimitRequest {
+                client_id: client_id.to_string(),
+                cost: 5,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            })
+        };
+        let set = |client_id: &str, list: AccessList, admin_token: &str| {
+            Request::new(SetAccessEntryRequest {
+                client_id: client_id.to_string(),
+                list: list as i32,
+                ttl_seconds: 0,
+                reason: "incident".to_string(),
+                admin_token: admin_token.to_string(),
+                namespace: String::new(),
+            })
+        };
+
+        let anonymous = service.set_access_entry(set("abuser", AccessList::Deny, "")).await;
+        assert_eq!(anonymous.unwrap_err().code(), tonic::Code::PermissionDenied);
+        service
+            .set_access_entry(set("abuser", AccessList::Deny, "secret"))
+            .await
+            .unwrap();
+        service
+            .set_access_entry(set("internal", AccessList::Allow, "secret"))
+            .await
+            .unwrap();
+
+        let denied = service.check_limit(check("abuser")).await.unwrap().into_inner();
+        assert!(!denied.allowed);
+        assert_eq!(denied.retry_after_seconds, PERMANENT_DENY_RETRY_SECS);
+        assert_eq!(denied.reason, "rule_matched");
+        assert!(service.check_limit(check("internal")).await.unwrap().into_inner().allowed);
+
+        let listed = service
+            .list_access_entries(Request::new(ListAccessEntriesRequest {
+                list: AccessList::Deny as i32,
+                admin_token: "secret".to_string(),
+                namespace: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.entries.len(), 1);
+        assert_eq!(listed.entries[0].client_id, "abuser");
+
+        let removed = service
+            .remove_access_entry(Request::new(RemoveAccessEntryRequest {
+                client_id: "abuser".to_string(),
+                admin_token: "secret".to_string(),
+                namespace: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(removed.success);
+        assert!(!service.check_limit(check("abuser")).await.unwrap().into_inner().allowed);
+    }
+
+    #[tokio::test]
+    async fn test_access_entries_reach_peers() {
+        let service = || {
+            let backend = MemoryBackend::new(TokenBucketConfig::default());
+            GuardianService::new(RateLimiter::new(backend, false))
+                .with_admin_token(Some("secret".to_string()))
+        };
+        let peer = service();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(RateLimiterServer::new(peer.clone()))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let peers = config::PeersConfig {
+            addresses: vec![addr.to_string()],
+            node_id: Some("a".to_string()),
+            api_key: None,
+            timeout_ms: 1000,
+        };
+        let mesh = PeerMesh::new(&peers, Some("secret".to_string()));
+        let service = service().with_peers(Arc::new(mesh));
+
+        // The caller's token is not needed on the peer, which gets this
+        // instance's own.
+        let mut by_admin = Request::new(SetAccessEntryRequest {
+            client_id: "abuser".to_string(),
+            list: AccessList::Deny as i32,
+            ttl_seconds: 0,
+            reason: "incident".to_string(),
+            admin_token: String::new(),
+            namespace: "team-a".to_string(),
+        });
+        by_admin.extensions_mut().insert(Principal {
+            id: "ops".to_string(),
+            tenant: None,
+            admin: true,
+        });
+        service.set_access_entry(by_admin).await.unwrap();
+
+        let key = namespace::scoped("team-a", "abuser");
+        for _ in 0..100 {
+            if peer.access.lookup(&key).is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let entry = peer.access.lookup(&key).expect("relayed to the peer");
+        assert_eq!((entry.access, entry.reason.as_str()), (Access::Deny, "incident"));
+    }
+
+    #[tokio::test]
+    async fn test_list_and_top_keys() {
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(60),
+        };
+        let backend = StatsBackend::new(MemoryBackend::new(config), STATS_WINDOW);
+        let service = GuardianService::new(RateLimiter::new(backend, false))
+            .with_admin_token(Some("secret".to_string()));
+        for (client_id, cost) in [("user:a", 5), ("user:a", 1), ("user:b", 1), ("bot:c", 1)] {
+            let check = Request::new(CheckLimitRequest {
+                client_id: client_id.to_string(),
+                cost,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            });
+            service.check_limit(check).await.unwrap();
+        }
+
+        let list = |page_token: &str| {
+            Request::new(ListKeysRequest {
+                pattern: "user:*".to_string(),
+                page_size: 1,
+                page_token: page_token.to_string(),
+                admin_token: "secret".to_string(),
+                namespace: String::new(),
+            })
+        };
+        let first = service.list_keys(list("")).await.unwrap().into_inner();
+        assert_eq!(first.keys, vec!["user:a"]);
+        let second = service
+            .list_keys(list(&first.next_page_token))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(second.keys, vec!["user:b"]);
+        assert!(second.next_page_token.is_empty());
+
+        let top = service
+            .top_keys(Request::new(TopKeysRequest {
+                limit: 1,
+                ranking: guardian_proto::KeyRanking::Denials as i32,
+                admin_token: "secret".to_string(),
+                namespace: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(top.keys[0].client_id, "user:a");
+        assert_eq!(top.keys[0].denied, 1);
+    }
+
+    #[tokio::test]
+    async fn test_check_stream_answers_failed_checks_in_place() {
+        use tokio_stream::StreamExt;
+
+        let service = GuardianService::new(RateLimiter::new(
+            MemoryBackend::new(TokenBucketConfig::default()),
+            false,
+        ));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(RateLimiterServer::new(service))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let mut client = guardian_proto::rate_limiter_client::RateLimiterClient::connect(
+            format!("http://{}", addr),
+        )
+        .await
+        .unwrap();
+
+        let checks = (0..200u64).map(|request_id| CheckLimitStreamRequest {
+            request_id,
+            check: Some(CheckLimitRequest {
+                client_id: format!("user{}", request_id),
+                cost: 1,
+                override_config: None,
+                tier: String::new(),
+                // Every tenth check names a namespace that can't exist.
+                namespace: if request_id % 10 == 3 { "a/b" } else { "" }.to_string(),
+                refundable: false,
+            }),
+        });
+        let responses: Vec<_> = client
+            .check_limit_stream(tokio_stream::iter(checks))
+            .await
+            .unwrap()
+            .into_inner()
+            .collect::<Result<_, _>>()
+            .await
+            .unwrap();
+
+        assert_eq!(responses.len(), 200);
+        for (request_id, response) in (0..200u64).zip(&responses) {
+            assert_eq!(response.request_id, request_id);
+            if request_id % 10 == 3 {
+                assert!(response.result.is_none());
+                let error = response.error.as_ref().unwrap();
+                assert_eq!(error.code, tonic::Code::InvalidArgument as i32);
+            } else {
+                assert!(response.result.as_ref().unwrap().allowed);
+                assert!(response.error.is_none());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_stream_ends_on_shutdown() {
+        use tokio_stream::StreamExt;
+
+        let service = GuardianService::new(RateLimiter::new(
+            MemoryBackend::new(TokenBucketConfig::default()),
+            false,
+        ));
+        let mut updates = service
+            .stream_limit_status(Request::new(guardian_proto::StreamLimitRequest {
+                client_id: "user1".to_string(),
+                namespace: String::new(),
+                client_ids: Vec::new(),
+                interval_seconds: 0,
+                below_tokens: 0,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert!(updates.next().await.is_some());
+        service.begin_shutdown();
+        let ended = tokio::time::timeout(std::time::Duration::from_millis(500), updates.next());
+        assert!(ended.await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_status_stream_pushes_denials() {
+        use tokio_stream::StreamExt;
+
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(60),
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false));
+        let mut updates = service
+            .stream_limit_status(Request::new(guardian_proto::StreamLimitRequest {
+                client_id: "user1".to_string(),
+                namespace: String::new(),
+                client_ids: Vec::new(),
+                interval_seconds: 0,
+                below_tokens: 0,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let first = updates.next().await.unwrap().unwrap();
+        assert_eq!(first.remaining_tokens, 10);
+
+        for cost in [1, 20] {
+            let check = Request::new(CheckLimitRequest {
+                client_id: "user1".to_string(),
+                cost,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            });
+            service.check_limit(check).await.unwrap();
+        }
+
+        // The small spend stays above every threshold, so the denial is next.
+        let next = tokio::time::timeout(std::time::Duration::from_millis(500), updates.next());
+        let update = next.await.unwrap().unwrap().unwrap();
+        assert_eq!(update.remaining_tokens, 9);
+        assert_eq!(update.status, guardian_proto::LimitStatus::Throttled as i32);
+    }
+
+    #[tokio::test]
+    async fn test_status_stream_follows_several_keys_below_a_threshold() {
+        use tokio_stream::StreamExt;
+
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(60),
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false));
+        let check = |client_id: &str, cost| {
+            Request::new(CheckLimitRequest {
+                client_id: client_id.to_string(),
+                cost,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            })
+        };
+        service.check_limit(check("user1", 8)).await.unwrap();
+        let mut updates = service
+            .stream_limit_status(Request::new(guardian_proto::StreamLimitRequest {
+                client_id: String::new(),
+                namespace: String::new(),
+                client_ids: vec!["user1".to_string(), "user2".to_string()],
+                interval_seconds: 0,
+                below_tokens: 5,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        // user2 starts full, so only user1 is reported.
+        let first = updates.next().await.unwrap().unwrap();
+        assert_eq!((first.client_id.as_str(), first.remaining_tokens), ("user1", 2));
+
+        service.check_limit(check("user2", 6)).await.unwrap();
+        service.check_limit(check("user2", 2)).await.unwrap();
+        let next = tokio::time::timeout(std::time::Duration::from_millis(500), updates.next());
+        let update = next.await.unwrap().unwrap().unwrap();
+        assert_eq!((update.client_id.as_str(), update.remaining_tokens), ("user2", 2));
+    }
+
+    #[tokio::test]
+    async fn test_denials_and_resets_are_audited() {
+        struct Capture(Arc<std::sync::Mutex<Vec<audit::AuditRecord>>>);
+
+        #[tonic::async_trait]
+        impl audit::AuditSink for Capture {
+            async fn write(&mut self, record: &audit::AuditRecord) -> std::io::Result<()> {
+                self.0.lock().unwrap().push(record.clone());
+                Ok(())
+            }
+
+            async fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut config = GuardianConfig::default();
+        config.limits.insert(
+            "api:*".to_string(),
+            LimitConfig {
+                capacity: 1,
+                refill_rate: 1,
+                refill_interval_secs: 60,
+                algorithm: Algorithm::TokenBucket,
+                shadow: false,
+            },
+        );
+        let router = Arc::new(config.build_backend().await.unwrap());
+        let records = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let auditor = Auditor::new(Box::new(Capture(Arc::clone(&records))), "node-a".to_string())
+            .with_rules(Arc::clone(&router));
+        let service = GuardianService::new(RateLimiter::new(router, false))
+            .with_admin_token(Some("secret".to_string()))
+            .with_audit(Arc::new(auditor));
+
+        for _ in 0..2 {
+            let check = Request::new(CheckLimitRequest {
+                client_id: "api:user1".to_string(),
+                cost: 1,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            });
+            service.check_limit(check).await.unwrap();
+        }
+        service
+            .reset_limit(Request::new(ResetLimitRequest {
+                client_id: "api:user1".to_string(),
+                admin_token: "secret".to_string(),
+                namespace: String::new(),
+            }))
+            .await
+            .unwrap();
+        service.flush().await.unwrap();
+
+        let records = records.lock().unwrap();
+        let actions: Vec<_> = records.iter().map(|r| r.action).collect();
+        assert_eq!(actions, vec![AuditAction::Denied, AuditAction::Reset]);
+        assert_eq!(records[0].rule, "api:*");
+        assert_eq!(records[0].node, "node-a");
+        assert_eq!(records[1].client_id, "api:user1");
+    }
+
+    #[tokio::test]
+    async fn test_usage_report_counts_recent_decisions() {
+        let config = TokenBucketConfig {
+            capacity: 5,
+            refill_rate: 1,
+            refill_interval: std::time::Duration::from_secs(60),
+        };
+        let service = GuardianService::new(RateLimiter::new(MemoryBackend::new(config), false))
+            .with_admin_token(Some("secret".to_string()));
+        for (client_id, cost) in [("user1", 3), ("user1", 3), ("user2", 1)] {
+            let check = Request::new(CheckLimitRequest {
+                client_id: client_id.to_string(),
+                cost,
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            });
+            service.check_limit(check).await.unwrap();
+        }
+        let report = |client_ids: Vec<String>, window_seconds: Vec<u32>| {
+            Request::new(GetUsageReportRequest {
+                client_ids,
+                window_seconds,
+                limit: 0,
+                admin_token: "secret".to_string(),
+                namespace: String::new(),
+            })
+        };
+
+        let all = service.get_usage_report(report(vec![], vec![])).await.unwrap().into_inner();
+        let clients: Vec<_> = all.keys.iter().map(|k| k.client_id.as_str()).collect();
+        assert_eq!(clients, vec!["user1", "user2"]);
+        let windows: Vec<_> = all.keys[0].windows.iter().map(|w| w.window_seconds).collect();
+        assert_eq!(windows, vec![300, 3600]);
+        let user1 = &all.keys[0].windows[0];
+        assert_eq!((user1.allowed, user1.denied, user1.tokens_consumed), (1, 1, 3));
+        assert!(all.keys[0].last_seen_timestamp > 0);
+
+        let too_long = service
+            .get_usage_report(report(vec!["user1".to_string()], vec![7200]))
+            .await;
+        assert_eq!(too_long.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,886 @@
+//! Prometheus metrics, served as text from `GET /metrics` on
+//! `global.metrics_port`. Scrapers asking for OpenMetrics get that format
+//! instead, with exemplars linking the denial counters to sampled traces.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use axum::http::{header, HeaderMap};
+use axum::routing::get;
+use guardian_core::{
+    BucketSnapshot, FailoverStats, KeyRanking, KeyStats, Lease, LimitResult, RateLimitError,
+    RouterBackend, StorageBackend, TokenDecision,
+};
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::{
+    Counter, Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+use tracing::Instrument;
+
+use crate::config::{DEFAULT_RULE, FAILOVER_STATS};
+use crate::heavy_hitters::HeavyHitters;
+use crate::latency::{LatencyTracker, OperationLatency};
+use crate::telemetry;
+
+/// Heavy hitters exported per ranking, keeping the key label's
+/// cardinality fixed.
+const EXPORTED_HEAVY_HITTERS: usize = 10;
+
+/// Backend latency percentiles cover calls from the last one to two of
+/// these.
+const LATENCY_WINDOW: Duration = Duration::from_secs(60);
+
+const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// The last sampled request counted by a denial series.
+struct Exemplar {
+    trace_id: String,
+    at: SystemTime,
+}
+
+pub struct Metrics {
+    registry: Registry,
+    decisions: IntCounterVec,
+    rule_decisions: IntCounterVec,
+    backend_latency: HistogramVec,
+    backend_errors: IntCounterVec,
+    backend_latency_quantiles: GaugeVec,
+    latency: LatencyTracker,
+    shed: IntCounterVec,
+    shadow_denials: IntCounterVec,
+    active_keys: IntGauge,
+    heavy_hitter_tokens: IntGaugeVec,
+    heavy_hitter_denials: IntGaugeVec,
+    degraded_backends: IntGauge,
+    failovers: IntCounter,
+    recoveries: IntCounter,
+    degraded_decisions: IntCounter,
+    degraded_seconds: Counter,
+    reconciled_tokens: IntCounter,
+    /// By series, as in [`series`].
+    exemplars: Mutex<HashMap<String, Exemplar>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Decision {
+    Allowed,
+    Denied,
+    Error,
+}
+
+impl Decision {
+    pub fn of<E>(result: &Result<LimitResult, E>) -> Self {
+        match result {
+            Ok(LimitResult::Allowed) => Decision::Allowed,
+            Ok(LimitResult::Denied { .. }) => Decision::Denied,
+            Err(_) => Decision::Error,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Decision::Allowed => "allowed",
+            Decision::Denied => "denied",
+            Decision::Error => "error",
+        }
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let decisions = IntCounterVec::new(
+            Opts::new("guardian_decisions_total", "Rate limit checks by decision"),
+            &["decision"],
+        )?;
+        let rule_decisions = IntCounterVec::new(
+            Opts::new(
+                "guardian_rule_decisions_total",
+                "Backend decisions by the limit rule (key pattern) they matched",
+            ),
+            &["rule", "decision"],
+        )?;
+        let backend_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "guardian_backend_latency_seconds",
+                "Storage backend call latency",
+            )
+            .buckets(vec![
+                0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25,
+            ]),
+            &["backend", "operation"],
+        )?;
+        let backend_errors = IntCounterVec::new(
+            Opts::new(
+                "guardian_backend_errors_total",
+                "Failed storage backend calls",
+            ),
+            &["backend", "operation"],
+        )?;
+        let backend_latency_quantiles = GaugeVec::new(
+            Opts::new(
+                "guardian_backend_latency_quantile_seconds",
+                "Storage backend call latency percentiles over the last one to two minutes",
+            ),
+            &["backend", "operation", "quantile"],
+        )?;
+        let shed = IntCounterVec::new(
+            Opts::new(
+                "guardian_shed_requests_total",
+                "RPCs refused because the service was overloaded",
+            ),
+            &["reason"],
+        )?;
+        let shadow_denials = IntCounterVec::new(
+            Opts::new(
+                "guardian_shadow_denials_total",
+                "Checks a shadow-mode rule would have denied, allowed instead",
+            ),
+            &["rule"],
+        )?;
+        let active_keys = IntGauge::new(
+            "guardian_active_keys",
+            "Keys with recent rate limit activity",
+        )?;
+        let heavy_hitter_tokens = IntGaugeVec::new(
+            Opts::new(
+                "guardian_heavy_hitter_tokens",
+                "Estimated recent tokens consumed by the keys consuming the most",
+            ),
+            &["key"],
+        )?;
+        let heavy_hitter_denials = IntGaugeVec::new(
+            Opts::new(
+                "guardian_heavy_hitter_denials",
+                "Estimated recent denials of the keys denied the most",
+            ),
+            &["key"],
+        )?;
+        let degraded_backends = IntGauge::new(
+            "guardian_backend_degraded",
+            "Limit rules currently served by the local fallback backend",
+        )?;
+        let failovers = IntCounter::new(
+            "guardian_failovers_total",
+            "Switches from the primary backend to the fallback",
+        )?;
+        let recoveries = IntCounter::new(
+            "guardian_failover_recoveries_total",
+            "Returns to the primary backend after a failover",
+        )?;
+        let degraded_decisions = IntCounter::new(
+            "guardian_degraded_decisions_total",
+            "Decisions made by the fallback backend",
+        )?;
+        let degraded_seconds = Counter::new(
+            "guardian_degraded_seconds_total",
+            "Time limit rules spent on the fallback, counted when each outage ends",
+        )?;
+        let reconciled_tokens = IntCounter::new(
+            "guardian_reconciled_tokens_total",
+            "Tokens spent on the fallback and charged to the primary on recovery",
+        )?;
+
+        let registry = Registry::new();
+        registry.register(Box::new(decisions.clone()))?;
+        registry.register(Box::new(rule_decisions.clone()))?;
+        registry.register(Box::new(backend_latency.clone()))?;
+        registry.register(Box::new(backend_errors.clone()))?;
+        registry.register(Box::new(backend_latency_quantiles.clone()))?;
+        registry.register(Box::new(shed.clone()))?;
+        registry.register(Box::new(shadow_denials.clone()))?;
+        registry.register(Box::new(active_keys.clone()))?;
+        registry.register(Box::new(heavy_hitter_tokens.clone()))?;
+        registry.register(Box::new(heavy_hitter_denials.clone()))?;
+        registry.register(Box::new(degraded_backends.clone()))?;
+        registry.register(Box::new(failovers.clone()))?;
+        registry.register(Box::new(recoveries.clone()))?;
+        registry.register(Box::new(degraded_decisions.clone()))?;
+        registry.register(Box::new(degraded_seconds.clone()))?;
+        registry.register(Box::new(reconciled_tokens.clone()))?;
+
+        Ok(Self {
+            registry,
+            decisions,
+            rule_decisions,
+            backend_latency,
+            backend_errors,
+            backend_latency_quantiles,
+            latency: LatencyTracker::new(LATENCY_WINDOW),
+            shed,
+            shadow_denials,
+            active_keys,
+            heavy_hitter_tokens,
+            heavy_hitter_denials,
+            degraded_backends,
+            failovers,
+            recoveries,
+            degraded_decisions,
+            degraded_seconds,
+            reconciled_tokens,
+            exemplars: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Count a decision returned to a caller, after fail-open and tiers.
+    pub fn record_decision(&self, decision: Decision) {
+        self.decisions.with_label_values(&[decision.label()]).inc();
+        if let Decision::Denied = decision {
+            self.record_exemplar("guardian_decisions_total", &[("decision", "denied")]);
+        }
+    }
+
+    /// Decisions of this kind returned since startup.
+    pub fn decisions(&self, decision: Decision) -> u64 {
+        self.decisions.with_label_values(&[decision.label()]).get()
+    }
+
+    /// Count an RPC refused by admission control; `reason` names the cap.
+    pub fn record_shed(&self, reason: &str) {
+        self.shed.with_label_values(&[reason]).inc();
+    }
+
+    /// Count a denial by the shadow-mode `rule` that was let through.
+    pub fn record_shadow_denial(&self, rule: &str) {
+        self.shadow_denials.with_label_values(&[rule]).inc();
+        self.record_exemplar("guardian_shadow_denials_total", &[("rule", rule)]);
+    }
+
+    /// Make the current request the exemplar of a denial series, if its
+    /// trace is sampled.
+    fn record_exemplar(&self, name: &str, labels: &[(&str, &str)]) {
+        if let Some(trace_id) = telemetry::sampled_trace_id() {
+            let exemplar = Exemplar {
+                trace_id,
+                at: SystemTime::now(),
+            };
+            self.exemplars
+                .lock()
+                .unwrap()
+                .insert(series(name, labels), exemplar);
+        }
+    }
+
+    pub fn set_active_keys(&self, keys: usize) {
+        self.active_keys.set(keys as i64);
+    }
+
+    /// Replace the heavy hitter gauges with the current top keys, so keys
+    /// that left the top stop being reported.
+    pub fn set_heavy_hitters(&self, heavy_hitters: &HeavyHitters) {
+        self.heavy_hitter_tokens.reset();
+        for (key, stats) in heavy_hitters.top(EXPORTED_HEAVY_HITTERS, KeyRanking::Usage) {
+            self.heavy_hitter_tokens
+                .with_label_values(&[&key])
+                .set(stats.consumed as i64);
+        }
+        self.heavy_hitter_denials.reset();
+        for (key, stats) in heavy_hitters.top(EXPORTED_HEAVY_HITTERS, KeyRanking::Denials) {
+            self.heavy_hitter_denials
+                .with_label_values(&[&key])
+                .set(stats.denied as i64);
+        }
+    }
+
+    /// Recent latency percentiles of each backend operation.
+    pub fn backend_latency(&self) -> Vec<OperationLatency> {
+        self.latency.recent()
+    }
+
+    pub fn latency_window(&self) -> Duration {
+        self.latency.window()
+    }
+
+    /// Replace the latency percentile gauges with the current window's,
+    /// so operations no longer called stop being reported.
+    pub fn set_backend_latency(&self) {
+        self.backend_latency_quantiles.reset();
+        for latency in self.latency.recent() {
+            for (quantile, value) in [
+                ("0.5", latency.p50),
+                ("0.95", latency.p95),
+                ("0.99", latency.p99),
+            ] {
+                self.backend_latency_quantiles
+                    .with_label_values(&[latency.backend, latency.operation, quantile])
+                    .set(value.as_secs_f64());
+            }
+        }
+    }
+
+    /// Bring the failover metrics up to date with `stats`.
+    pub fn set_failover(&self, stats: &FailoverStats) {
+        self.degraded_backends.set(stats.degraded() as i64);
+        let catch_up = |counter: &IntCounter, total: u64| {
+            counter.inc_by(total.saturating_sub(counter.get()));
+        };
+        catch_up(&self.failovers, stats.failovers());
+        catch_up(&self.recoveries, stats.recoveries());
+        catch_up(&self.degraded_decisions, stats.degraded_decisions());
+        catch_up(&self.reconciled_tokens, stats.reconciled_tokens());
+        let seconds = stats.degraded_time().as_secs_f64();
+        self.degraded_seconds
+            .inc_by((seconds - self.degraded_seconds.get()).max(0.0));
+    }
+
+    /// Bring every sampled metric up to date, before an export.
+    pub fn sample(&self, heavy_hitters: &HeavyHitters, active_keys: usize) {
+        self.set_active_keys(active_keys);
+        self.set_heavy_hitters(heavy_hitters);
+        self.set_backend_latency();
+        self.set_failover(&FAILOVER_STATS);
+    }
+
+    /// Current values of everything registered.
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.registry.gather()
+    }
+
+    /// Everything registered, in the Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        // Encoding into a Vec only fails on malformed metric families,
+        // which the fixed set above can't produce.
+        let _ = TextEncoder::new().encode(&self.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    /// Everything registered, in the OpenMetrics text format, with an
+    /// exemplar on each denial series that has counted a sampled request.
+    pub fn encode_openmetrics(&self) -> String {
+        let families = self.gather();
+        let exemplars = self.exemplars.lock().unwrap();
+        let mut out = String::new();
+        for family in &families {
+            let name = family.get_name();
+            let (base, kind) = match family.get_field_type() {
+                MetricType::COUNTER => (name.strip_suffix("_total").unwrap_or(name), "counter"),
+                MetricType::GAUGE => (name, "gauge"),
+                MetricType::HISTOGRAM => (name, "histogram"),
+                // Nothing registered is a summary or untyped.
+                _ => continue,
+            };
+            let _ = writeln!(out, "# TYPE {} {}", base, kind);
+            let _ = writeln!(out, "# HELP {} {}", base, escape(family.get_help()));
+            for metric in family.get_metric() {
+                let labels: Vec<(&str, &str)> = metric
+                    .get_label()
+                    .iter()
+                    .map(|label| (label.get_name(), label.get_value()))
+                    .collect();
+                match family.get_field_type() {
+                    MetricType::COUNTER => {
+                        let series = series(&format!("{}_total", base), &labels);
+                        let _ = write!(out, "{} {}", series, metric.get_counter().get_value());
+                        if let Some(exemplar) = exemplars.get(&series) {
+                            let at = exemplar.at.duration_since(UNIX_EPOCH).unwrap_or_default();
+                            let _ = write!(
+                                out,
+                                " # {{trace_id=\"{}\"}} 1 {:.3}",
+                                exemplar.trace_id,
+                                at.as_secs_f64()
+                            );
+                        }
+                        out.push('\n');
+                    }
+                    MetricType::GAUGE => {
+                        let value = metric.get_gauge().get_value();
+                        let _ = writeln!(out, "{} {}", series(name, &labels), value);
+                    }
+                    _ => {
+                        let histogram = metric.get_histogram();
+                        let count = histogram.get_sample_count();
+                        let buckets = histogram
+                            .get_bucket()
+                            .iter()
+                            .map(|bucket| {
+                                let le = bucket.get_upper_bound().to_string();
+                                (le, bucket.get_cumulative_count())
+                            })
+                            .chain([("+Inf".to_string(), count)]);
+                        for (le, cumulative) in buckets {
+                            let mut labels = labels.clone();
+                            labels.push(("le", &le));
+                            let series = series(&format!("{}_bucket", name), &labels);
+                            let _ = writeln!(out, "{} {}", series, cumulative);
+                        }
+                        let _ = writeln!(
+                            out,
+                            "{} {}",
+                            series(&format!("{}_count", name), &labels),
+                            count
+                        );
+                        let sum = histogram.get_sample_sum();
+                        let _ =
+                            writeln!(out, "{} {}", series(&format!("{}_sum", name), &labels), sum);
+                    }
+                }
+            }
+        }
+        out.push_str("# EOF\n");
+        out
+    }
+
+    fn observe<T>(
+        &self,
+        backend: &'static str,
+        operation: &'static str,
+        started: Instant,
+        result: &Result<T, RateLimitError>,
+    ) {
+        let latency = started.elapsed();
+        self.backend_latency
+            .with_label_values(&[backend, operation])
+            .observe(latency.as_secs_f64());
+        if result.is_err() {
+            self.backend_errors
+                .with_label_values(&[backend, operation])
+                .inc();
+        }
+        self.latency
+            .record(backend, operation, latency, result.is_err());
+    }
+}
+
+/// Times calls into the router, counts its decisions per limit rule, and
+/// traces each call as a child of the current RPC span. Calls are labelled
+/// with the primary backend's kind; while a rule is failed over, its calls
+/// are served by the fallback but still count under the primary.
+///
+/// Checks answered by a client tier don't pass through the router, so they
+/// only show up in `guardian_decisions_total`.
+pub struct MeteredBackend {
+    router: Arc<RouterBackend>,
+    metrics: Arc<Metrics>,
+    backend: &'static str,
+}
+
+impl MeteredBackend {
+    /// `backend` names the storage behind `router`, such as `"redis"`.
+    pub fn new(router: Arc<RouterBackend>, metrics: Arc<Metrics>, backend: &'static str) -> Self {
+        Self {
+            router,
+            metrics,
+            backend,
+        }
+    }
+
+    /// Count a decision on `key` under the limit rule governing it.
+    fn record_rule_decision(&self, key: &str, allowed: bool) {
+        let rule = self.router.pattern_for(key);
+        let rule = rule.as_deref().unwrap_or(DEFAULT_RULE);
+        let decision = if allowed {
+            Decision::Allowed
+        } else {
+            Decision::Denied
+        };
+        self.metrics
+            .rule_decisions
+            .with_label_values(&[rule, decision.label()])
+            .inc();
+        if let Decision::Denied = decision {
+            self.metrics.record_exemplar(
+                "guardian_rule_decisions_total",
+                &[("decision", "denied"), ("rule", rule)],
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MeteredBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        let started = Instant::now();
+        let result = self
+            .router
+            .take_token_detailed(key, cost)
+            .instrument(telemetry::backend_span("take_token", key))
+            .await;
+        self.metrics
+            .observe(self.backend, "take_token", started, &result);
+
+        if let Ok(decision) = &result {
+            self.record_rule_decision(key, decision.allowed);
+        }
+        result
+    }
+
+    /// Every entry counts under its rule as allowed only when they all were.
+    async fn take_all(
+        &self,
+        entries: &[(&str, u64)],
+    ) -> Result<Vec<TokenDecision>, RateLimitError> {
+        let started = Instant::now();
+        let first = entries.first().map_or("", |(key, _)| *key);
+        let result = self
+            .router
+            .take_all(entries)
+            .instrument(telemetry::backend_span("take_all", first))
+            .await;
+        self.metrics
+            .observe(self.backend, "take_all", started, &result);
+
+        if let Ok(decisions) = &result {
+            let taken = decisions.iter().all(|decision| decision.allowed);
+            for (key, _) in entries {
+                self.record_rule_decision(key, taken);
+            }
+        }
+        result
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let started = Instant::now();
+        let result = self
+            .router
+            .get_usage(key)
+            .instrument(telemetry::backend_span("get_usage", key))
+            .await;
+        self.metrics
+            .observe(self.backend, "get_usage", started, &result);
+        result
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.router.inspect(key).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let started = Instant::now();
+        let result = self
+            .router
+            .reset(key)
+            .instrument(telemetry::backend_span("reset", key))
+            .await;
+        self.metrics
+            .observe(self.backend, "reset", started, &result);
+        result
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        let started = Instant::now();
+        let result = self
+            .router
+            .refund(key, tokens)
+            .instrument(telemetry::backend_span("refund", key))
+            .await;
+        self.metrics
+            .observe(self.backend, "refund", started, &result);
+        result
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        let started = Instant::now();
+        let result = self
+            .router
+            .acquire_lease(key, id, tokens, ttl)
+            .instrument(telemetry::backend_span("acquire_lease", key))
+            .await;
+        self.metrics
+            .observe(self.backend, "acquire_lease", started, &result);
+        result
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        let started = Instant::now();
+        let result = self
+            .router
+            .renew_lease(key, id, ttl)
+            .instrument(telemetry::backend_span("renew_lease", key))
+            .await;
+        self.metrics
+            .observe(self.backend, "renew_lease", started, &result);
+        result
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        let started = Instant::now();
+        let result = self
+            .router
+            .return_lease(key, id, unused)
+            .instrument(telemetry::backend_span("return_lease", key))
+            .await;
+        self.metrics
+            .observe(self.backend, "return_lease", started, &result);
+        result
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.router.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        let started = Instant::now();
+        let result = self
+            .router
+            .exchange_demand(key, node, demand, ttl)
+            .instrument(telemetry::backend_span("exchange_demand", key))
+            .await;
+        self.metrics
+            .observe(self.backend, "exchange_demand", started, &result);
+        result
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        let started = Instant::now();
+        let result = self
+            .router
+            .hold_lock(name, holder, ttl)
+            .instrument(telemetry::backend_span("hold_lock", name))
+            .await;
+        self.metrics
+            .observe(self.backend, "hold_lock", started, &result);
+        result
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        let started = Instant::now();
+        let result = self
+            .router
+            .release_lock(name, holder)
+            .instrument(telemetry::backend_span("release_lock", name))
+            .await;
+        self.metrics
+            .observe(self.backend, "release_lock", started, &result);
+        result
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.router.health_check().await
+    }
+
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        self.router.flush().await
+    }
+
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        self.router.warm_up(key).await
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.router.list_keys(pattern, after, limit).await
+    }
+
+    async fn top_keys(
+        &self,
+        n: usize,
+        ranking: KeyRanking,
+    ) -> Result<Vec<(String, KeyStats)>, RateLimitError> {
+        self.router.top_keys(n, ranking).await
+    }
+}
+
+/// Serve `/metrics` on `listener`. `active_keys`, the heavy hitters, the
+/// latency percentiles and the backends' failover counters are sampled on
+/// each scrape.
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    metrics: Arc<Metrics>,
+    heavy_hitters: Arc<HeavyHitters>,
+    active_keys: impl Fn() -> usize + Send + Sync + 'static,
+) -> std::io::Result<()> {
+    let active_keys = Arc::new(active_keys);
+    let app = axum::Router::new().route(
+        "/metrics",
+        get(move |headers: HeaderMap| async move {
+            metrics.sample(&heavy_hitters, active_keys());
+            let openmetrics = headers
+                .get(header::ACCEPT)
+                .and_then(|accept| accept.to_str().ok())
+                .is_some_and(|accept| accept.contains("application/openmetrics-text"));
+            if openmetrics {
+                let content_type = [(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)];
+                (content_type, metrics.encode_openmetrics())
+            } else {
+                let content_type = [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)];
+                (content_type, metrics.encode())
+            }
+        }),
+    );
+    axum::serve(listener, app).await
+}
+
+/// `name{label="value",...}` as a series is written in the OpenMetrics
+/// text format. Labels are taken in the order given, which must be by name
+/// to match what the registry gathers.
+fn series(name: &str, labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return name.to_string();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+        .collect();
+    format!("{}{{{}}}", name, labels.join(","))
+}
+
+/// `value` escaped for a label value or HELP text.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_core::{MemoryBackend, Observer, TokenBucketConfig};
+
+    #[tokio::test]
+    async fn test_counts_decisions_per_rule() {
+        let small = TokenBucketConfig {
+            capacity: 1,
+            ..TokenBucketConfig::default()
+        };
+        let router = RouterBackend::new(MemoryBackend::new(TokenBucketConfig::default()))
+            .route("api:*", MemoryBackend::new(small));
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let backend = MeteredBackend::new(Arc::new(router), Arc::clone(&metrics), "memory");
+
+        assert!(backend.take_token("api:user1", 1).await.unwrap());
+        assert!(!backend.take_token("api:user1", 1).await.unwrap());
+        assert!(backend.take_token("web:user1", 1).await.unwrap());
+        metrics.record_decision(Decision::Denied);
+
+        let text = metrics.encode();
+        assert!(text.contains(r#"guardian_rule_decisions_total{decision="denied",rule="api:*"} 1"#));
+        assert!(
+            text.contains(r#"guardian_rule_decisions_total{decision="allowed",rule="default"} 1"#)
+        );
+        assert!(text.contains(r#"guardian_decisions_total{decision="denied"} 1"#));
+        assert!(text.contains(
+            r#"guardian_backend_latency_seconds_count{backend="memory",operation="take_token"} 3"#
+        ));
+
+        metrics.set_backend_latency();
+        let latency = metrics.backend_latency();
+        assert_eq!((latency[0].operation, latency[0].calls), ("take_token", 3));
+        assert!(metrics.encode().contains(
+            r#"guardian_backend_latency_quantile_seconds{backend="memory",operation="take_token",quantile="0.99"}"#
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_forwards_and_times_locks_and_demand() {
+        let router = RouterBackend::new(MemoryBackend::new(TokenBucketConfig::default()));
+        let metrics = Arc::new(Metrics::new().unwrap());
+        let backend = MeteredBackend::new(Arc::new(router), Arc::clone(&metrics), "memory");
+        let ttl = Duration::from_secs(60);
+
+        // The trait defaults would refuse these outright.
+        assert!(backend.hold_lock("leader", "a", ttl).await.unwrap());
+        assert!(!backend.hold_lock("leader", "b", ttl).await.unwrap());
+        backend.release_lock("leader", "a").await.unwrap();
+        assert!(backend.hold_lock("leader", "b", ttl).await.unwrap());
+        backend.exchange_demand("user1", "a", 5, ttl).await.unwrap();
+        let demand = backend.exchange_demand("user1", "b", 3, ttl).await.unwrap();
+        assert!(demand.contains(&("a".to_string(), 5)));
+
+        let text = metrics.encode();
+        for (operation, calls) in [
+            ("hold_lock", 3),
+            ("release_lock", 1),
+            ("exchange_demand", 2),
+        ] {
+            assert!(text.contains(&format!(
+                r#"guardian_backend_latency_seconds_count{{backend="memory",operation="{}"}} {}"#,
+                operation, calls
+            )));
+        }
+    }
+
+    #[test]
+    fn test_exports_only_the_current_heavy_hitters() {
+        let metrics = Metrics::new().unwrap();
+        let hitters = HeavyHitters::new(100, std::time::Duration::from_secs(300));
+        for n in 0..=EXPORTED_HEAVY_HITTERS as u64 {
+            hitters.on_check(
+                &format!("user:{}", n),
+                n + 1,
+                &LimitResult::Allowed,
+                Default::default(),
+            );
+        }
+        metrics.set_heavy_hitters(&hitters);
+
+        let text = metrics.encode();
+        assert!(text.contains(r#"guardian_heavy_hitter_tokens{key="user:10"} 11"#));
+        // The lightest of the eleven is left out.
+        assert!(!text.contains(r#"key="user:0""#));
+        assert!(!text.contains("guardian_heavy_hitter_denials{"));
+    }
+
+    #[test]
+    fn test_openmetrics_links_denials_to_sampled_traces() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let metrics = Metrics::new().unwrap();
+        let provider = opentelemetry_sdk::trace::TracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry().with(telemetry::layer(&provider));
+        let trace_id = tracing::subscriber::with_default(subscriber, || {
+            let _rpc = tracing::info_span!("rpc").entered();
+            metrics.record_decision(Decision::Denied);
+            metrics.record_decision(Decision::Allowed);
+            telemetry::sampled_trace_id().unwrap()
+        });
+        // Untraced denials are counted without replacing the exemplar.
+        metrics.record_decision(Decision::Denied);
+        metrics.record_shadow_denial("beta:*");
+        let ok: Result<(), RateLimitError> = Ok(());
+        metrics.observe("memory", "reset", Instant::now(), &ok);
+
+        let text = metrics.encode_openmetrics();
+        assert!(text.contains("# TYPE guardian_decisions counter\n"));
+        assert!(text.contains(&format!(
+            r#"guardian_decisions_total{{decision="denied"}} 2 # {{trace_id="{}"}} 1 "#,
+            trace_id
+        )));
+        assert!(text.contains("guardian_decisions_total{decision=\"allowed\"} 1\n"));
+        assert!(text.contains("guardian_shadow_denials_total{rule=\"beta:*\"} 1\n"));
+        assert!(text.contains("# TYPE guardian_backend_latency_seconds histogram\n"));
+        assert!(text.contains(
+            r#"guardian_backend_latency_seconds_bucket{backend="memory",operation="reset",le="+Inf"} 1"#
+        ));
+        assert!(text.ends_with("# EOF\n"));
+    }
+}
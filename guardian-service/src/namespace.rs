@@ -0,0 +1,169 @@
+// Multi-tenant namespaces: each namespace's keys and rule patterns live
+// under their own prefix in the backend keyspace, so teams sharing one
+// deployment never share buckets. The default namespace is unprefixed, so
+// its client ids may not start with what reads as another namespace's
+// prefix; ids such as Envoy's `edge:path=/login` can still hold `/`.
+
+use guardian_core::RateLimitError;
+use tonic::Status;
+
+use crate::auth::Principal;
+use crate::config::DEFAULT_RULE;
+
+/// Separates a namespace from the key or pattern inside it.
+pub const SEPARATOR: char = '/';
+
+pub fn validate(namespace: &str) -> Result<(), RateLimitError> {
+    if !namespace.is_empty() && !is_name(namespace) {
+        return Err(RateLimitError::ConfigError(format!(
+            "namespace '{}' may only contain ASCII letters, digits, '-', '_' and '.'",
+            namespace
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `name` could name a namespace.
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// The namespace whose prefix `key` starts with, if any. Default-namespace
+/// keys never have one.
+fn owner(key: &str) -> Option<&str> {
+    let (prefix, _) = key.split_once(SEPARATOR)?;
+    is_name(prefix).then_some(prefix)
+}
+
+/// The namespace a request acts in. Callers bound to a tenant get their
+/// own namespace by default and may not name another.
+#[allow(clippy::result_large_err)]
+pub fn resolve(principal: Option<&Principal>, requested: &str) -> Result<String, Status> {
+    let namespace = match principal.and_then(|p| p.tenant.as_deref()) {
+        Some(tenant) if requested.is_empty() || requested == tenant => tenant,
+        Some(_) => {
+            return Err(Status::permission_denied(
+                "Callers may only use their own tenant's namespace",
+            ))
+        }
+        None => requested,
+    };
+    validate(namespace).map_err(|e| Status::invalid_argument(e.to_string()))?;
+    Ok(namespace.to_string())
+}
+
+/// Backend key for a caller's `client_id` in `namespace`, refusing ids in
+/// the default namespace that would land in another namespace's keyspace.
+#[allow(clippy::result_large_err)]
+pub fn client_key(namespace: &str, client_id: &str) -> Result<String, Status> {
+    if namespace.is_empty() {
+        if let Some(owner) = owner(client_id) {
+            return Err(Status::invalid_argument(format!(
+                "client id '{}' would share buckets with namespace '{}'; use that namespace",
+                client_id, owner
+            )));
+        }
+    }
+    Ok(scoped(namespace, client_id))
+}
+
+/// Backend key (or rule pattern) for `key` in `namespace`. The default
+/// namespace is unprefixed, so existing keys keep their buckets.
+pub fn scoped(namespace: &str, key: &str) -> String {
+    if namespace.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}{}{}", namespace, SEPARATOR, key)
+    }
+}
+
+/// `key` relative to `namespace`, or `None` if it belongs elsewhere.
+pub fn unscoped<'a>(namespace: &str, key: &'a str) -> Option<&'a str> {
+    if namespace.is_empty() {
+        return owner(key).is_none().then_some(key);
+    }
+    key.strip_prefix(namespace)?.strip_prefix(SEPARATOR)
+}
+
+/// Rule pattern covering every key in `namespace`: its default limit.
+pub fn default_pattern(namespace: &str) -> String {
+    scoped(namespace, "*")
+}
+
+/// Rule pattern for an admin-supplied `pattern` in `namespace`, where
+/// `default` means the namespace's default limit.
+pub fn rule_pattern(namespace: &str, pattern: &str) -> String {
+    if !namespace.is_empty() && pattern == DEFAULT_RULE {
+        default_pattern(namespace)
+    } else {
+        scoped(namespace, pattern)
+    }
+}
+
+/// Inverse of `rule_pattern`, or `None` for rules outside `namespace`.
+pub fn rule_name(namespace: &str, pattern: &str) -> Option<String> {
+    if !namespace.is_empty() && pattern == default_pattern(namespace) {
+        return Some(DEFAULT_RULE.to_string());
+    }
+    unscoped(namespace, pattern).map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(tenant: Option<&str>) -> Principal {
+        Principal {
+            id: "svc".to_string(),
+            tenant: tenant.map(str::to_string),
+            admin: false,
+        }
+    }
+
+    #[test]
+    fn test_tenants_are_pinned_to_their_namespace() {
+        assert_eq!(resolve(None, "").unwrap(), "");
+        assert_eq!(resolve(None, "billing").unwrap(), "billing");
+        assert_eq!(resolve(Some(&principal(Some("acme"))), "").unwrap(), "acme");
+        let other = resolve(Some(&principal(Some("acme"))), "billing").unwrap_err();
+        assert_eq!(other.code(), tonic::Code::PermissionDenied);
+        let bad = resolve(None, "a/b").unwrap_err();
+        assert_eq!(bad.code(), tonic::Code::InvalidArgument);
+        assert!(resolve(None, "edge:path=").is_err());
+    }
+
+    #[test]
+    fn test_default_namespace_keeps_out_of_tenants() {
+        // "acme/login" in the default namespace would be tenant acme's
+        // "login" bucket.
+        assert_eq!(client_key("acme", "login").unwrap(), "acme/login");
+        let bad = client_key("", "acme/login").unwrap_err();
+        assert_eq!(bad.code(), tonic::Code::InvalidArgument);
+        assert_eq!(client_key("acme", "acme/login").unwrap(), "acme/acme/login");
+
+        // A prefix no namespace can have is fine.
+        assert_eq!(
+            client_key("", "edge:path=/login").unwrap(),
+            "edge:path=/login"
+        );
+        assert_eq!(unscoped("", "edge:path=/login"), Some("edge:path=/login"));
+        assert_eq!(unscoped("", "acme/login"), None);
+    }
+
+    #[test]
+    fn test_scoping_round_trips() {
+        assert_eq!(scoped("", "user:1"), "user:1");
+        assert_eq!(scoped("acme", "user:1"), "acme/user:1");
+        assert_eq!(unscoped("acme", "acme/user:1"), Some("user:1"));
+        assert_eq!(unscoped("acme", "acmex/user:1"), None);
+
+        assert_eq!(rule_pattern("acme", DEFAULT_RULE), "acme/*");
+        assert_eq!(rule_pattern("", DEFAULT_RULE), DEFAULT_RULE);
+        assert_eq!(rule_name("acme", "acme/*").as_deref(), Some(DEFAULT_RULE));
+        assert_eq!(rule_name("acme", "acme/api:*").as_deref(), Some("api:*"));
+        assert_eq!(rule_name("acme", "api:*"), None);
+    }
+}
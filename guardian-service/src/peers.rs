@@ -0,0 +1,187 @@
+// Peer mesh: admin actions taken on one instance (resets, access list
+// changes, limit rule changes) are sent straight to the other instances
+// over gRPC, so they apply everywhere within a round trip rather than when
+// each instance next reads the shared store, or, without one, never.
+
+use guardian_proto::rate_limiter_client::RateLimiterClient;
+use guardian_proto::{
+    DeleteLimitConfigRequest, RemoveAccessEntryRequest, ResetLimitRequest, SetAccessEntryRequest,
+    SetLimitConfigRequest,
+};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+use tonic::metadata::MetadataValue;
+use tonic::transport::Endpoint;
+use tonic::{Request, Status};
+
+use crate::config::{default_node_id, PeersConfig};
+
+/// Metadata naming the instance an admin RPC was relayed from. Actions
+/// carrying it are applied but not relayed again.
+pub const PEER_HEADER: &str = "x-guardian-peer";
+
+/// Whether a request was relayed by a peer.
+pub fn from_peer(metadata: &tonic::metadata::MetadataMap) -> bool {
+    metadata.contains_key(PEER_HEADER)
+}
+
+/// An admin action that succeeded here, with its namespace resolved.
+#[derive(Debug, Clone)]
+pub enum PeerAction {
+    Reset(ResetLimitRequest),
+    SetAccessEntry(SetAccessEntryRequest),
+    RemoveAccessEntry(RemoveAccessEntryRequest),
+    SetLimitConfig(SetLimitConfigRequest),
+    DeleteLimitConfig(DeleteLimitConfigRequest),
+}
+
+impl PeerAction {
+    fn name(&self) -> &'static str {
+        match self {
+            PeerAction::Reset(_) => "ResetLimit",
+            PeerAction::SetAccessEntry(_) => "SetAccessEntry",
+            PeerAction::RemoveAccessEntry(_) => "RemoveAccessEntry",
+            PeerAction::SetLimitConfig(_) => "SetLimitConfig",
+            PeerAction::DeleteLimitConfig(_) => "DeleteLimitConfig",
+        }
+    }
+
+    /// Authorize the action on peers with `admin_token`.
+    fn with_admin_token(mut self, admin_token: &str) -> Self {
+        let token = match &mut self {
+            PeerAction::Reset(req) => &mut req.admin_token,
+            PeerAction::SetAccessEntry(req) => &mut req.admin_token,
+            PeerAction::RemoveAccessEntry(req) => &mut req.admin_token,
+            PeerAction::SetLimitConfig(req) => &mut req.admin_token,
+            PeerAction::DeleteLimitConfig(req) => &mut req.admin_token,
+        };
+        *token = admin_token.to_string();
+        self
+    }
+}
+
+/// The other instances, and how to call them.
+pub struct PeerMesh {
+    addresses: Vec<String>,
+    node: String,
+    admin_token: Option<String>,
+    api_key: Option<String>,
+    timeout: Duration,
+}
+
+impl PeerMesh {
+    /// Peers named by `config`, authorized with this instance's
+    /// `admin_token` if it has one.
+    pub fn new(config: &PeersConfig, admin_token: Option<String>) -> Self {
+        Self {
+            addresses: config.addresses.clone(),
+            node: config.node_id.clone().unwrap_or_else(default_node_id),
+            admin_token,
+            api_key: config.api_key.clone(),
+            timeout: Duration::from_millis(config.timeout_ms),
+        }
+    }
+
+    /// Send `action` to every peer in the background. A peer that can't be
+    /// reached misses it; the shared store, where there is one, still
+    /// carries it there eventually.
+    pub fn tell(self: &Arc<Self>, action: PeerAction) {
+        let mesh = Arc::clone(self);
+        tokio::spawn(async move {
+            let action = match &mesh.admin_token {
+                Some(token) => action.with_admin_token(token),
+                None => action,
+            };
+            let mut sends = JoinSet::new();
+            for addr in mesh.resolve().await {
+                let mesh = Arc::clone(&mesh);
+                let action = action.clone();
+                sends.spawn(async move { (addr, mesh.send(addr, action).await) });
+            }
+            while let Some(sent) = sends.join_next().await {
+                if let Ok((addr, Err(e))) = sent {
+                    tracing::warn!(peer = %addr, error = %e, "failed to relay admin action");
+                }
+            }
+        });
+    }
+
+    /// Every address the peers' names stand for.
+    async fn resolve(&self) -> Vec<SocketAddr> {
+        let mut addrs = Vec::new();
+        for peer in &self.addresses {
+            match tokio::net::lookup_host(peer.as_str()).await {
+                Ok(found) => addrs.extend(found),
+                Err(e) => tracing::warn!(%peer, error = %e, "failed to resolve peer"),
+            }
+        }
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    async fn send(&self, addr: SocketAddr, action: PeerAction) -> Result<(), Status> {
+        let channel = Endpoint::from_shared(format!("http://{}", addr))
+            .map_err(|e| Status::invalid_argument(e.to_string()))?
+            .connect_timeout(self.timeout)
+            .timeout(self.timeout)
+            .connect()
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        let mut client = RateLimiterClient::new(channel);
+        let name = action.name();
+        let success = match action {
+            PeerAction::Reset(req) => {
+                client
+                    .reset_limit(self.request(req)?)
+                    .await?
+                    .into_inner()
+                    .success
+            }
+            PeerAction::SetAccessEntry(req) => {
+                client
+                    .set_access_entry(self.request(req)?)
+                    .await?
+                    .into_inner()
+                    .success
+            }
+            // Already gone there is as good as removed.
+            PeerAction::RemoveAccessEntry(req) => {
+                client.remove_access_entry(self.request(req)?).await?;
+                true
+            }
+            PeerAction::SetLimitConfig(req) => {
+                client
+                    .set_limit_config(self.request(req)?)
+                    .await?
+                    .into_inner()
+                    .success
+            }
+            PeerAction::DeleteLimitConfig(req) => {
+                client.delete_limit_config(self.request(req)?).await?;
+                true
+            }
+        };
+        if !success {
+            return Err(Status::internal(format!("{} failed on the peer", name)));
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn request<T>(&self, message: T) -> Result<Request<T>, Status> {
+        let mut request = Request::new(message);
+        let value = |text: &str| {
+            MetadataValue::try_from(text).map_err(|e| Status::invalid_argument(e.to_string()))
+        };
+        request
+            .metadata_mut()
+            .insert(PEER_HEADER, value(&self.node)?);
+        if let Some(api_key) = &self.api_key {
+            request.metadata_mut().insert("x-api-key", value(api_key)?);
+        }
+        Ok(request)
+    }
+}
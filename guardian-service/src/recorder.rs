@@ -0,0 +1,144 @@
+// Decision recorder: full traces of an evenly spaced sample of checks, and
+// of every check of watched keys, kept in a ring buffer for the
+// GetDecisionTraces admin RPC. For debugging why particular requests were
+// throttled without turning on logging for all of them.
+
+use guardian_core::{glob_match, DecisionReason};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::config::DecisionRecorderConfig;
+
+/// One recorded check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecisionTrace {
+    pub at: SystemTime,
+    /// The namespaced key.
+    pub key: String,
+    pub cost: u64,
+    pub allowed: bool,
+    pub reason: DecisionReason,
+    pub rule: String,
+    /// Tokens before and after, when the backend reported the bucket.
+    pub tokens: Option<(u64, u64)>,
+    pub latency: Duration,
+    pub watched: bool,
+}
+
+pub struct DecisionRecorder {
+    sample_ratio: f64,
+    watch_keys: Vec<String>,
+    capacity: usize,
+    /// Checks considered for sampling so far.
+    seen: AtomicU64,
+    traces: Mutex<VecDeque<DecisionTrace>>,
+}
+
+impl DecisionRecorder {
+    pub fn new(config: &DecisionRecorderConfig) -> Self {
+        Self {
+            sample_ratio: config.sample_ratio.clamp(0.0, 1.0),
+            watch_keys: config.watch_keys.clone(),
+            capacity: config.capacity.max(1),
+            seen: AtomicU64::new(0),
+            traces: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Whether to trace a check of `key`, and if so whether because it is
+    /// watched. Each call for an unwatched key counts towards the sample.
+    pub fn wants(&self, key: &str) -> Option<bool> {
+        if self
+            .watch_keys
+            .iter()
+            .any(|pattern| glob_match(pattern, key))
+        {
+            return Some(true);
+        }
+        if self.sample_ratio == 0.0 {
+            return None;
+        }
+        // Sampled when the running total of the ratio crosses an integer,
+        // so one in every 1/ratio checks is recorded.
+        let n = self.seen.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n * self.sample_ratio).floor() < ((n + 1.0) * self.sample_ratio).floor()).then_some(false)
+    }
+
+    pub fn record(&self, trace: DecisionTrace) {
+        let mut traces = self.traces.lock().unwrap();
+        if traces.len() == self.capacity {
+            traces.pop_front();
+        }
+        traces.push_back(trace);
+    }
+
+    /// Up to `limit` traces of keys `include` accepts, newest first; all of
+    /// them when `limit` is 0.
+    pub fn recent(&self, include: impl Fn(&str) -> bool, limit: usize) -> Vec<DecisionTrace> {
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        self.traces
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|trace| include(&trace.key))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(key: &str) -> DecisionTrace {
+        DecisionTrace {
+            at: SystemTime::now(),
+            key: key.to_string(),
+            cost: 1,
+            allowed: true,
+            reason: DecisionReason::WithinLimit,
+            rule: "default".to_string(),
+            tokens: Some((5, 4)),
+            latency: Duration::from_micros(40),
+            watched: false,
+        }
+    }
+
+    #[test]
+    fn test_samples_evenly_and_always_records_watched_keys() {
+        let recorder = DecisionRecorder::new(&DecisionRecorderConfig {
+            sample_ratio: 0.25,
+            watch_keys: vec!["vip:*".to_string()],
+            capacity: 10,
+        });
+        let sampled = (0..100)
+            .filter(|_| recorder.wants("user:1").is_some())
+            .count();
+        assert_eq!(sampled, 25);
+        assert_eq!(recorder.wants("vip:alice"), Some(true));
+    }
+
+    #[test]
+    fn test_keeps_the_newest_traces() {
+        let recorder = DecisionRecorder::new(&DecisionRecorderConfig {
+            sample_ratio: 1.0,
+            watch_keys: Vec::new(),
+            capacity: 3,
+        });
+        for n in 0..5 {
+            recorder.record(trace(&format!("user:{}", n)));
+        }
+        let keys: Vec<_> = recorder
+            .recent(|_| true, 0)
+            .into_iter()
+            .map(|trace| trace.key)
+            .collect();
+        assert_eq!(keys, ["user:4", "user:3", "user:2"]);
+        assert_eq!(recorder.recent(|key| key == "user:3", 5).len(), 1);
+        assert_eq!(recorder.recent(|_| true, 1).len(), 1);
+    }
+}
@@ -0,0 +1,80 @@
+//! gRPC server reflection, so grpcurl and similar tools can discover the
+//! Guardian API without a local copy of the proto files.
+//!
+//! Both the `v1` and the older `v1alpha` protocols are served since client
+//! tooling still varies in which one it speaks.
+
+use tonic_reflection::server::{v1, v1alpha, Builder, Error};
+
+/// Descriptor sets of every service the binary exposes.
+fn descriptors(guardian: &'static [u8]) -> Builder<'static> {
+    Builder::configure()
+        .register_encoded_file_descriptor_set(guardian)
+        .register_encoded_file_descriptor_set(crate::rls::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+}
+
+pub fn v1(
+    guardian: &'static [u8],
+) -> Result<v1::ServerReflectionServer<impl v1::ServerReflection>, Error> {
+    descriptors(guardian).build_v1()
+}
+
+pub fn v1alpha(
+    guardian: &'static [u8],
+) -> Result<v1alpha::ServerReflectionServer<impl v1alpha::ServerReflection>, Error> {
+    descriptors(guardian).build_v1alpha()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_proto::{rate_limiter_server::SERVICE_NAME, FILE_DESCRIPTOR_SET};
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tokio_stream::StreamExt;
+    use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+    use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+    use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+    use tonic_reflection::pb::v1::ServerReflectionRequest;
+
+    #[tokio::test]
+    async fn test_lists_every_served_service() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(v1(FILE_DESCRIPTOR_SET).unwrap())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        let channel = tonic::transport::Endpoint::from_shared(format!("http://{}", addr))
+            .unwrap()
+            .connect()
+            .await
+            .unwrap();
+        let mut client = ServerReflectionClient::new(channel);
+
+        let request = ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(MessageRequest::ListServices(String::new())),
+        };
+        let mut responses = client
+            .server_reflection_info(tokio_stream::once(request))
+            .await
+            .unwrap()
+            .into_inner();
+        let response = responses.next().await.unwrap().unwrap();
+
+        let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+            panic!("unexpected reflection response");
+        };
+        let names: Vec<_> = list.service.into_iter().map(|s| s.name).collect();
+        assert!(names.iter().any(|n| n == SERVICE_NAME));
+        assert!(names.iter().any(|n| n == "grpc.health.v1.Health"));
+        assert!(names
+            .iter()
+            .any(|n| n == "envoy.service.ratelimit.v3.RateLimitService"));
+        assert!(names
+            .iter()
+            .any(|n| n == "envoy.service.auth.v3.Authorization"));
+    }
+}
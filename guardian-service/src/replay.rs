@@ -0,0 +1,245 @@
+// Offline replay: recorded requests are run through a candidate config's
+// limits on simulated time, so the effect of a limit change can be seen
+// before it ships. Buckets follow the same arithmetic as the KV-backed
+// stores, driven by the recorded timestamps instead of the clock.
+
+use guardian_core::{glob_match, BucketState, RateLimitError};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::BufRead;
+
+use crate::config::{sorted, GuardianConfig, LimitConfig, DEFAULT_RULE};
+use crate::namespace;
+
+/// One recorded request, read from a JSON line. Audit log records qualify
+/// as written; other access logs need at least `timestamp_ms` and
+/// `client_id`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Entry {
+    /// Unix time in milliseconds.
+    pub timestamp_ms: u64,
+    #[serde(default)]
+    pub namespace: String,
+    pub client_id: String,
+    #[serde(default = "default_cost")]
+    pub cost: u64,
+    /// The tier the request named, if any.
+    #[serde(default)]
+    pub tier: String,
+    /// What the service did at the time, when the log says.
+    #[serde(default)]
+    pub action: Option<Action>,
+}
+
+fn default_cost() -> u64 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Allowed,
+    Denied,
+    /// The key's bucket was reset; not a request.
+    Reset,
+}
+
+/// What the candidate config does with one key's requests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyReport {
+    /// Limit rule pattern or `tier:<name>`.
+    pub rule: String,
+    pub allowed: u64,
+    pub denied: u64,
+    /// Requests a shadow rule would deny; counted in `allowed` too.
+    pub shadow_denied: u64,
+    /// Requests the log records as denied at the time.
+    pub denied_before: u64,
+}
+
+/// Entries from `reader`, one JSON object per line. Blank lines are
+/// ignored; returns the entries and how many lines couldn't be parsed.
+pub fn read(reader: impl BufRead) -> std::io::Result<(Vec<Entry>, usize)> {
+    let mut entries = Vec::new();
+    let mut unreadable = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => unreadable += 1,
+        }
+    }
+    Ok((entries, unreadable))
+}
+
+pub struct Replay {
+    /// Pattern rules, most specific first, as the router tries them.
+    rules: Vec<(String, LimitConfig)>,
+    default: LimitConfig,
+    tiers: HashMap<String, LimitConfig>,
+    client_tiers: HashMap<String, String>,
+    /// Bucket state per rule and key.
+    buckets: HashMap<(String, String), BucketState>,
+    keys: HashMap<String, KeyReport>,
+}
+
+impl Replay {
+    pub fn new(config: &GuardianConfig) -> Self {
+        let mut rules: Vec<_> = config.pattern_rules().into_iter().collect();
+        rules.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+        Self {
+            rules,
+            default: config.default_limit(),
+            tiers: config.tiers.clone(),
+            client_tiers: config.client_tiers.clone(),
+            buckets: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Replay `entries` in timestamp order.
+    pub fn run(&mut self, mut entries: Vec<Entry>) -> Result<(), RateLimitError> {
+        entries.sort_by_key(|entry| entry.timestamp_ms);
+        entries.iter().try_for_each(|entry| self.apply(entry))
+    }
+
+    /// Replay one entry. Entries must come in timestamp order; a request
+    /// naming a tier the config lacks is an error, as it is in the service.
+    pub fn apply(&mut self, entry: &Entry) -> Result<(), RateLimitError> {
+        let key = namespace::scoped(&entry.namespace, &entry.client_id);
+        let (rule, limit) = self.rule_for(&key, entry)?;
+        let bucket_key = (rule.clone(), key.clone());
+        if entry.action == Some(Action::Reset) {
+            self.buckets.remove(&bucket_key);
+            return Ok(());
+        }
+
+        let config = limit.bucket_config();
+        let now_ms = entry.timestamp_ms;
+        let allowed = self
+            .buckets
+            .entry(bucket_key)
+            .or_insert_with(|| BucketState::full(&config, now_ms))
+            .try_consume(entry.cost.max(1), &config, now_ms);
+
+        let report = self.keys.entry(key).or_default();
+        report.rule = rule;
+        if entry.action == Some(Action::Denied) {
+            report.denied_before += 1;
+        }
+        match (allowed, limit.shadow) {
+            (true, _) => report.allowed += 1,
+            (false, true) => {
+                report.allowed += 1;
+                report.shadow_denied += 1;
+            }
+            (false, false) => report.denied += 1,
+        }
+        Ok(())
+    }
+
+    /// Every key seen, in key order.
+    pub fn report(&self) -> Vec<(&String, &KeyReport)> {
+        sorted(&self.keys)
+    }
+
+    fn rule_for(&self, key: &str, entry: &Entry) -> Result<(String, LimitConfig), RateLimitError> {
+        let tier = if entry.tier.is_empty() {
+            self.client_tiers.get(&entry.client_id)
+        } else {
+            Some(&entry.tier)
+        };
+        if let Some(tier) = tier {
+            return match self.tiers.get(tier) {
+                Some(limit) => Ok((format!("tier:{}", tier), limit.clone())),
+                None => Err(RateLimitError::ConfigError(format!(
+                    "unknown tier '{}'",
+                    tier
+                ))),
+            };
+        }
+        Ok(self
+            .rules
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, key))
+            .map_or_else(
+                || (DEFAULT_RULE.to_string(), self.default.clone()),
+                |(pattern, limit)| (pattern.clone(), limit.clone()),
+            ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+limits:
+  default:
+    capacity: 2
+    refill_rate: 1
+  "search:*":
+    capacity: 1
+    refill_rate: 1
+    shadow: true
+tiers:
+  gold:
+    capacity: 5
+    refill_rate: 1
+client_tiers:
+  acme: "gold"
+"#;
+
+    #[test]
+    fn test_replays_on_recorded_time() {
+        let config: GuardianConfig = serde_yaml::from_str(CONFIG).unwrap();
+        let log = [
+            r#"{"timestamp_ms": 1000, "client_id": "user1"}"#,
+            r#"{"timestamp_ms": 1100, "client_id": "user1"}"#,
+            r#"{"timestamp_ms": 1200, "client_id": "user1", "action": "denied"}"#,
+            "not json",
+            "",
+            // A second later one token has come back.
+            r#"{"timestamp_ms": 2200, "client_id": "user1", "action": "denied"}"#,
+            r#"{"timestamp_ms": 1300, "client_id": "search:q", "cost": 2}"#,
+            r#"{"timestamp_ms": 1400, "client_id": "acme", "cost": 3}"#,
+            r#"{"timestamp_ms": 1500, "client_id": "acme", "cost": 3}"#,
+            r#"{"timestamp_ms": 1600, "client_id": "acme", "action": "reset"}"#,
+            r#"{"timestamp_ms": 1700, "client_id": "acme", "cost": 3}"#,
+        ]
+        .join("\n");
+        let (entries, unreadable) = read(log.as_bytes()).unwrap();
+        assert_eq!((entries.len(), unreadable), (9, 1));
+
+        let mut replay = Replay::new(&config);
+        replay.run(entries).unwrap();
+        let report: HashMap<_, _> = replay.report().into_iter().collect();
+
+        let user = report[&"user1".to_string()];
+        assert_eq!((user.allowed, user.denied, user.denied_before), (3, 1, 2));
+        assert_eq!(user.rule, DEFAULT_RULE);
+        let search = report[&"search:q".to_string()];
+        assert_eq!(
+            (search.allowed, search.denied, search.shadow_denied),
+            (1, 0, 1)
+        );
+        let acme = report[&"acme".to_string()];
+        assert_eq!(
+            (acme.rule.as_str(), acme.allowed, acme.denied),
+            ("tier:gold", 2, 1)
+        );
+
+        let unknown = Entry {
+            timestamp_ms: 3000,
+            namespace: String::new(),
+            client_id: "user2".to_string(),
+            cost: 1,
+            tier: "platinum".to_string(),
+            action: None,
+        };
+        assert!(replay.apply(&unknown).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+//! Envoy's rate limit service protocol (`envoy.service.ratelimit.v3`), so
+//! Envoy and Istio `rate_limit` filters can point straight at Guardian.
+//!
+//! Each descriptor becomes one Guardian key, `domain:key=value:key=value`
+//! (e.g. `edge:remote_address=10.0.0.1`), decided like any other check. Limit
+//! rules match those keys, so `edge:remote_address=*` limits every client
+//! address in the `edge` domain. The `ext_authz` mode builds descriptors
+//! itself and decides them here too.
+
+use guardian_core::StorageBackend;
+use tonic::{Request, Response, Status};
+
+use crate::auth::Principal;
+use guardian_proto::{CheckLimitRequest, CheckLimitResponse};
+use crate::GuardianService;
+
+use envoy::extensions::common::ratelimit::v3::RateLimitDescriptor;
+use envoy::service::ratelimit::v3::rate_limit_response::{Code, DescriptorStatus};
+use envoy::service::ratelimit::v3::rate_limit_service_server::RateLimitService;
+use envoy::service::ratelimit::v3::{RateLimitRequest, RateLimitResponse};
+
+pub use envoy::service::ratelimit::v3::rate_limit_service_server::RateLimitServiceServer;
+
+// Module tree mirrors the proto packages so cross-package references in the
+// generated code resolve.
+pub mod envoy {
+    pub mod config {
+        pub mod core {
+            pub mod v3 {
+                tonic::include_proto!("envoy.config.core.v3");
+            }
+        }
+    }
+    pub mod r#type {
+        pub mod v3 {
+            tonic::include_proto!("envoy.r#type.v3");
+        }
+    }
+    pub mod extensions {
+        pub mod common {
+            pub mod ratelimit {
+                pub mod v3 {
+                    tonic::include_proto!("envoy.extensions.common.ratelimit.v3");
+                }
+            }
+        }
+    }
+    pub mod service {
+        pub mod auth {
+            pub mod v3 {
+                tonic::include_proto!("envoy.service.auth.v3");
+            }
+        }
+        pub mod ratelimit {
+            pub mod v3 {
+                tonic::include_proto!("envoy.service.ratelimit.v3");
+            }
+        }
+    }
+}
+
+pub mod google {
+    pub mod rpc {
+        tonic::include_proto!("google.rpc");
+    }
+}
+
+/// Encoded descriptors of the Envoy protos, for server reflection.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("envoy_descriptor");
+
+/// `ShouldRateLimit` on top of a `GuardianService`, sharing its limiter,
+/// tiers and metrics.
+pub struct RlsService<B: StorageBackend + 'static> {
+    guardian: GuardianService<B>,
+}
+
+impl<B: StorageBackend + 'static> RlsService<B> {
+    pub fn new(guardian: GuardianService<B>) -> Self {
+        Self { guardian }
+    }
+
+    /// Check each of `descriptors` in `domain`, costing its own
+    /// `hits_addend` or else `hits`.
+    ///
+    /// Like Envoy's reference service, every descriptor is charged even
+    /// when an earlier one is already over its limit.
+    pub(crate) async fn decide(
+        &self,
+        principal: Option<&Principal>,
+        domain: &str,
+        descriptors: &[RateLimitDescriptor],
+        hits: u32,
+    ) -> Result<Vec<CheckLimitResponse>, Status> {
+        let mut results = Vec::with_capacity(descriptors.len());
+        for descriptor in descriptors {
+            let hits = descriptor.hits_addend.unwrap_or(u64::from(hits));
+            let check = CheckLimitRequest {
+                client_id: descriptor_key(domain, descriptor),
+                cost: u32::try_from(hits).unwrap_or(u32::MAX),
+                override_config: None,
+                tier: String::new(),
+                namespace: String::new(),
+                refundable: false,
+            };
+            results.push(self.guardian.decide(principal, check).await?);
+        }
+        Ok(results)
+    }
+}
+
+#[tonic::async_trait]
+impl<B: StorageBackend + 'static> RateLimitService for RlsService<B> {
+    async fn should_rate_limit(
+        &self,
+        request: Request<RateLimitRequest>,
+    ) -> Result<Response<RateLimitResponse>, Status> {
+        let principal = request.extensions().get::<Principal>().cloned();
+        let req = request.into_inner();
+        if req.domain.is_empty() {
+            return Err(Status::invalid_argument("domain is required"));
+        }
+
+        let results = self
+            .decide(
+                principal.as_ref(),
+                &req.domain,
+                &req.descriptors,
+                req.hits_addend,
+            )
+            .await?;
+        let statuses: Vec<_> = results
+            .into_iter()
+            .map(|result| DescriptorStatus {
+                code: if result.allowed {
+                    Code::Ok
+                } else {
+                    Code::OverLimit
+                } as i32,
+                current_limit: None,
+                limit_remaining: u32::try_from(result.remaining_tokens).unwrap_or(u32::MAX),
+                duration_until_reset: (!result.allowed).then(|| prost_types::Duration {
+                    seconds: i64::from(result.retry_after_seconds),
+                    nanos: 0,
+                }),
+            })
+            .collect();
+
+        let over_limit = statuses.iter().any(|s| s.code == Code::OverLimit as i32);
+        Ok(Response::new(RateLimitResponse {
+            overall_code: if over_limit {
+                Code::OverLimit
+            } else {
+                Code::Ok
+            } as i32,
+            statuses,
+            response_headers_to_add: Vec::new(),
+            request_headers_to_add: Vec::new(),
+        }))
+    }
+}
+
+/// The Guardian key for one descriptor: `domain:key=value:...`.
+pub(crate) fn descriptor_key(domain: &str, descriptor: &RateLimitDescriptor) -> String {
+    let mut key = domain.to_string();
+    for entry in &descriptor.entries {
+        key.push(':');
+        key.push_str(&entry.key);
+        key.push('=');
+        key.push_str(&entry.value);
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envoy::extensions::common::ratelimit::v3::rate_limit_descriptor::Entry;
+    use guardian_core::{MemoryBackend, RateLimiter, TokenBucketConfig};
+
+    fn descriptor(entries: &[(&str, &str)]) -> RateLimitDescriptor {
+        RateLimitDescriptor {
+            entries: entries
+                .iter()
+                .map(|(key, value)| Entry {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+            hits_addend: None,
+        }
+    }
+
+    #[test]
+    fn test_descriptor_key() {
+        let key = descriptor_key(
+            "edge",
+            &descriptor(&[("remote_address", "10.0.0.1"), ("path", "/login")]),
+        );
+        assert_eq!(key, "edge:remote_address=10.0.0.1:path=/login");
+    }
+
+    #[tokio::test]
+    async fn test_over_limit_descriptor_fails_the_request() {
+        let config = TokenBucketConfig {
+            capacity: 2,
+            ..TokenBucketConfig::default()
+        };
+        let rls = RlsService::new(GuardianService::new(RateLimiter::new(
+            MemoryBackend::new(config),
+            false,
+        )));
+        let request = || {
+            Request::new(RateLimitRequest {
+                domain: "edge".to_string(),
+                descriptors: vec![
+                    descriptor(&[("remote_address", "10.0.0.1")]),
+                    descriptor(&[("remote_address", "10.0.0.2")]),
+                ],
+                hits_addend: 0,
+            })
+        };
+
+        let first = rls.should_rate_limit(request()).await.unwrap().into_inner();
+        assert_eq!(first.overall_code, Code::Ok as i32);
+        assert_eq!(first.statuses.len(), 2);
+
+        // Only 10.0.0.1 is over its limit; one descriptor is enough to deny.
+        let mut second = request();
+        second.get_mut().descriptors[1] = descriptor(&[("remote_address", "10.0.0.3")]);
+        second.get_mut().hits_addend = 1;
+        rls.should_rate_limit(second).await.unwrap();
+        let third = rls.should_rate_limit(request()).await.unwrap().into_inner();
+        assert_eq!(third.overall_code, Code::OverLimit as i32);
+        assert_eq!(third.statuses[0].code, Code::OverLimit as i32);
+        assert_eq!(third.statuses[1].code, Code::Ok as i32);
+    }
+}
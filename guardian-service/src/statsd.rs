@@ -0,0 +1,251 @@
+// statsd/DogStatsD export: every `interval_secs`, the Prometheus registry
+// is sampled and pushed over UDP to a local agent, for fleets that collect
+// metrics with Datadog or another statsd agent instead of scraping.
+
+use prometheus::proto::{MetricFamily, MetricType};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+use crate::config::{StatsdConfig, StatsdFlavor};
+use crate::heavy_hitters::HeavyHitters;
+use crate::metrics::Metrics;
+
+/// Largest datagram sent, keeping clear of fragmentation on a 1500-byte
+/// MTU.
+const MAX_PACKET: usize = 1432;
+
+/// Turns gathered metric families into statsd lines.
+struct Formatter {
+    flavor: StatsdFlavor,
+    prefix: String,
+    /// The configured tags, preformatted as `k:v,k:v`.
+    tags: String,
+    /// Each counter's value at the last push, to send the increase.
+    sent: HashMap<String, f64>,
+}
+
+impl Formatter {
+    fn new(config: &StatsdConfig) -> Self {
+        let tags = config
+            .tags
+            .iter()
+            .map(|(name, value)| format!("{}:{}", sanitize(name), sanitize(value)))
+            .collect::<Vec<_>>()
+            .join(",");
+        Self {
+            flavor: config.flavor,
+            prefix: config.prefix.clone(),
+            tags,
+            sent: HashMap::new(),
+        }
+    }
+
+    fn lines(&mut self, families: &[MetricFamily]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for family in families {
+            for metric in family.get_metric() {
+                let labels: BTreeMap<&str, &str> = metric
+                    .get_label()
+                    .iter()
+                    .map(|label| (label.get_name(), label.get_value()))
+                    .collect();
+                let name = family.get_name();
+                match family.get_field_type() {
+                    MetricType::COUNTER => {
+                        let value = metric.get_counter().get_value();
+                        lines.extend(self.counter(name, &labels, value));
+                    }
+                    MetricType::GAUGE => {
+                        let value = metric.get_gauge().get_value();
+                        lines.push(self.line(name, &labels, value, "g"));
+                    }
+                    MetricType::HISTOGRAM => {
+                        let histogram = metric.get_histogram();
+                        let count = histogram.get_sample_count() as f64;
+                        let sum = histogram.get_sample_sum();
+                        lines.extend(self.counter(&format!("{}_count", name), &labels, count));
+                        lines.extend(self.counter(&format!("{}_sum", name), &labels, sum));
+                    }
+                    // Nothing registered is a summary or untyped.
+                    _ => {}
+                }
+            }
+        }
+        lines
+    }
+
+    /// A counter's increase since the last push, if it grew.
+    fn counter(&mut self, name: &str, labels: &BTreeMap<&str, &str>, value: f64) -> Option<String> {
+        let line = self.line(name, labels, 0.0, "c");
+        let sent = self.sent.insert(line, value).unwrap_or(0.0);
+        let increase = value - sent;
+        (increase > 0.0).then(|| self.line(name, labels, increase, "c"))
+    }
+
+    fn line(&self, name: &str, labels: &BTreeMap<&str, &str>, value: f64, kind: &str) -> String {
+        let mut line = format!("{}{}", self.prefix, name);
+        if self.flavor == StatsdFlavor::Statsd {
+            for value in labels.values() {
+                line.push('.');
+                line.push_str(&sanitize(value));
+            }
+        }
+        line.push_str(&format!(":{}|{}", value, kind));
+        if self.flavor == StatsdFlavor::DogStatsd {
+            let mut tags: Vec<String> = labels
+                .iter()
+                .map(|(name, value)| format!("{}:{}", name, sanitize(value)))
+                .collect();
+            if !self.tags.is_empty() {
+                tags.push(self.tags.clone());
+            }
+            if !tags.is_empty() {
+                line.push_str("|#");
+                line.push_str(&tags.join(","));
+            }
+        }
+        line
+    }
+}
+
+/// `value` with the characters statsd lines are split on replaced.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | '#' | ',' | '\n' | ' ' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Pack `lines` into datagrams of at most [`MAX_PACKET`] bytes, one line
+/// per datagram if it is longer.
+fn packets(lines: &[String]) -> Vec<String> {
+    let mut packets = Vec::new();
+    let mut packet = String::new();
+    for line in lines {
+        if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET {
+            packets.push(std::mem::take(&mut packet));
+        }
+        if !packet.is_empty() {
+            packet.push('\n');
+        }
+        packet.push_str(line);
+    }
+    if !packet.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Push `metrics` to the agent every interval, until the task is dropped.
+/// A failed send is logged and the next push goes ahead as usual.
+pub async fn export(
+    config: StatsdConfig,
+    metrics: Arc<Metrics>,
+    heavy_hitters: Arc<HeavyHitters>,
+    active_keys: impl Fn() -> usize + Send + Sync + 'static,
+) -> std::io::Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(&config.address).await?;
+    let mut formatter = Formatter::new(&config);
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        metrics.sample(&heavy_hitters, active_keys());
+        let lines = formatter.lines(&metrics.gather());
+        for packet in packets(&lines) {
+            if let Err(e) = socket.send(packet.as_bytes()).await {
+                tracing::warn!(error = %e, address = %config.address, "failed to send statsd metrics");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Decision;
+
+    fn config(flavor: StatsdFlavor) -> StatsdConfig {
+        StatsdConfig {
+            address: "127.0.0.1:8125".to_string(),
+            flavor,
+            prefix: String::new(),
+            tags: BTreeMap::from([("env".to_string(), "prod".to_string())]),
+            interval_secs: 10,
+        }
+    }
+
+    #[test]
+    fn test_dogstatsd_tags_and_counter_increases() {
+        let metrics = Metrics::new().unwrap();
+        let mut formatter = Formatter::new(&config(StatsdFlavor::DogStatsd));
+        metrics.record_decision(Decision::Denied);
+        metrics.record_decision(Decision::Denied);
+        metrics.record_shadow_denial("beta:*");
+        metrics.set_active_keys(7);
+
+        let lines = formatter.lines(&metrics.gather());
+        assert!(
+            lines.contains(&"guardian_decisions_total:2|c|#decision:denied,env:prod".to_string())
+        );
+        assert!(
+            lines.contains(&"guardian_shadow_denials_total:1|c|#rule:beta_*,env:prod".to_string())
+        );
+        assert!(lines.contains(&"guardian_active_keys:7|g|#env:prod".to_string()));
+
+        metrics.record_decision(Decision::Denied);
+        let lines = formatter.lines(&metrics.gather());
+        assert!(
+            lines.contains(&"guardian_decisions_total:1|c|#decision:denied,env:prod".to_string())
+        );
+        // Counters that didn't move aren't sent again.
+        assert!(!lines
+            .iter()
+            .any(|line| line.starts_with("guardian_shadow_denials_total")));
+    }
+
+    #[test]
+    fn test_plain_statsd_folds_labels_into_names() {
+        let metrics = Metrics::new().unwrap();
+        let mut config = config(StatsdFlavor::Statsd);
+        config.prefix = "edge.".to_string();
+        let mut formatter = Formatter::new(&config);
+        metrics.record_shed("concurrency");
+
+        let lines = formatter.lines(&metrics.gather());
+        assert!(lines.contains(&"edge.guardian_shed_requests_total.concurrency:1|c".to_string()));
+        assert!(lines.iter().all(|line| !line.contains('#')));
+    }
+
+    #[test]
+    fn test_packets_stay_under_the_mtu() {
+        let lines: Vec<String> = (0..100).map(|n| format!("metric_{}:{}|c", n, n)).collect();
+        let packets = packets(&lines);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|packet| packet.len() <= MAX_PACKET));
+        assert_eq!(packets.join("\n"), lines.join("\n"));
+    }
+
+    #[tokio::test]
+    async fn test_pushes_over_udp() {
+        let agent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut config = config(StatsdFlavor::DogStatsd);
+        config.address = agent.local_addr().unwrap().to_string();
+        let metrics = Arc::new(Metrics::new().unwrap());
+        metrics.record_decision(Decision::Allowed);
+        let hitters = Arc::new(HeavyHitters::new(10, Duration::from_secs(300)));
+        let exporter = tokio::spawn(export(config, metrics, hitters, || 3));
+
+        let mut buffer = [0; MAX_PACKET];
+        let len = agent.recv(&mut buffer).await.unwrap();
+        let packet = std::str::from_utf8(&buffer[..len]).unwrap();
+        assert!(packet.contains("guardian_decisions_total:1|c|#decision:allowed,env:prod"));
+        exporter.abort();
+    }
+}
@@ -0,0 +1,228 @@
+// Push-based StreamLimitStatus: limiter decisions are fanned out to open
+// status streams, which only forward the ones their client cares about.
+
+use guardian_core::{BucketSnapshot, LimitResult, Observer};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use guardian_proto::{LimitStatus, LimitStatusUpdate};
+
+/// Longest a status stream stays quiet before re-sending the current state,
+/// unless the request picks its own interval.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Most keys one status stream may follow.
+pub const MAX_STREAM_KEYS: usize = 100;
+
+/// Fractions of capacity (in percent) whose crossing is worth an update.
+const THRESHOLDS: [u64; 3] = [50, 25, 10];
+
+/// Decisions buffered per subscriber before slow streams start skipping.
+const EVENT_BUFFER: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct StatusEvent {
+    pub key: String,
+    pub bucket: Option<BucketSnapshot>,
+    pub denied: bool,
+    pub reset: bool,
+}
+
+/// Limiter observer that broadcasts every decision to subscribed streams.
+pub struct StatusHub {
+    events: broadcast::Sender<StatusEvent>,
+}
+
+impl Default for StatusHub {
+    fn default() -> Self {
+        Self {
+            events: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+}
+
+impl StatusHub {
+    pub fn subscribe(&self) -> broadcast::Receiver<StatusEvent> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, event: impl FnOnce() -> StatusEvent) {
+        // Skip building the event when no stream is listening.
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(event());
+        }
+    }
+}
+
+impl Observer for StatusHub {
+    fn on_decision(&self, key: &str, result: &LimitResult, bucket: Option<&BucketSnapshot>) {
+        self.publish(|| StatusEvent {
+            key: key.to_string(),
+            bucket: bucket.copied(),
+            denied: matches!(result, LimitResult::Denied { .. }),
+            reset: false,
+        });
+    }
+
+    fn on_reset(&self, key: &str) {
+        self.publish(|| StatusEvent {
+            key: key.to_string(),
+            bucket: None,
+            denied: false,
+            reset: true,
+        });
+    }
+}
+
+/// One stream's view of its key: decides which events are news.
+pub struct KeyWatch {
+    client_id: String,
+    /// Backend key, which differs from `client_id` inside a namespace.
+    key: String,
+    level: Option<usize>,
+    below: Option<u64>,
+}
+
+impl KeyWatch {
+    pub fn new(client_id: String, key: String, bucket: Option<BucketSnapshot>) -> Self {
+        Self {
+            client_id,
+            key,
+            level: bucket.as_ref().map(threshold_level),
+            below: None,
+        }
+    }
+
+    /// Only report the key while it has fewer than `tokens` left.
+    pub fn with_notify_below(mut self, tokens: u64) -> Self {
+        self.below = Some(tokens);
+        self
+    }
+
+    /// Whether the client asked to hear about a key in this state. States
+    /// the backend can't describe are always reported.
+    pub fn reports(&self, bucket: Option<&BucketSnapshot>) -> bool {
+        match (self.below, bucket) {
+            (Some(below), Some(bucket)) => bucket.remaining < below,
+            _ => true,
+        }
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Whether `event` should reach the client: denials and resets always
+    /// do, other decisions only when they move the key across a threshold.
+    pub fn wants(&mut self, event: &StatusEvent) -> bool {
+        if event.key != self.key {
+            return false;
+        }
+        let level = event.bucket.as_ref().map(threshold_level);
+        let crossed = level.is_some() && level != self.level;
+        if level.is_some() {
+            self.level = level;
+        }
+        event.denied || event.reset || crossed
+    }
+
+    /// Record a state that was sent without going through `wants`.
+    pub fn observe(&mut self, bucket: Option<&BucketSnapshot>) {
+        if let Some(bucket) = bucket {
+            self.level = Some(threshold_level(bucket));
+        }
+    }
+
+    pub fn update(&self, bucket: Option<&BucketSnapshot>, denied: bool) -> LimitStatusUpdate {
+        let status = match bucket {
+            _ if denied => LimitStatus::Throttled,
+            Some(bucket) if bucket.remaining == 0 => LimitStatus::Exhausted,
+            Some(_) => LimitStatus::Healthy,
+            None => LimitStatus::Unspecified,
+        };
+        LimitStatusUpdate {
+            client_id: self.client_id.clone(),
+            remaining_tokens: bucket.map_or(0, |bucket| bucket.remaining),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as i64,
+            status: status as i32,
+        }
+    }
+}
+
+/// How many thresholds the bucket has fallen below.
+fn threshold_level(bucket: &BucketSnapshot) -> usize {
+    let percent = bucket.remaining.saturating_mul(100) / bucket.capacity.max(1);
+    THRESHOLDS
+        .iter()
+        .filter(|&&threshold| percent < threshold)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_core::{MemoryBackend, RateLimiter, TokenBucketConfig};
+    use std::sync::Arc;
+
+    fn bucket(remaining: u64) -> BucketSnapshot {
+        BucketSnapshot {
+            capacity: 100,
+            remaining,
+            refill_rate: 1,
+            last_refill: None,
+        }
+    }
+
+    fn decision(key: &str, remaining: u64, denied: bool) -> StatusEvent {
+        StatusEvent {
+            key: key.to_string(),
+            bucket: Some(bucket(remaining)),
+            denied,
+            reset: false,
+        }
+    }
+
+    #[test]
+    fn test_watch_only_wants_threshold_crossings_and_denials() {
+        let mut watch = KeyWatch::new("user1".to_string(), "user1".to_string(), Some(bucket(100)));
+
+        assert!(!watch.wants(&decision("user1", 90, false)));
+        assert!(!watch.wants(&decision("user2", 5, true)));
+        assert!(watch.wants(&decision("user1", 45, false)));
+        assert!(!watch.wants(&decision("user1", 40, false)));
+        assert!(watch.wants(&decision("user1", 40, true)));
+        assert!(watch.wants(&decision("user1", 60, false)));
+    }
+
+    #[test]
+    fn test_watch_filters_by_remaining_tokens() {
+        let watch = KeyWatch::new("user1".to_string(), "user1".to_string(), None)
+            .with_notify_below(20);
+
+        assert!(!watch.reports(Some(&bucket(20))));
+        assert!(watch.reports(Some(&bucket(19))));
+        assert!(watch.reports(None));
+    }
+
+    #[tokio::test]
+    async fn test_hub_publishes_limiter_decisions() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(60),
+        };
+        let hub = Arc::new(StatusHub::default());
+        let limiter = RateLimiter::new(MemoryBackend::new(config), false)
+            .with_observer(Arc::clone(&hub) as Arc<dyn Observer>);
+        let mut events = hub.subscribe();
+
+        limiter.check_limit("user1", 20).await.unwrap();
+        let event = events.recv().await.unwrap();
+        assert_eq!(event.key, "user1");
+        assert!(event.denied);
+        assert_eq!(event.bucket.unwrap().remaining, 10);
+    }
+}
@@ -0,0 +1,134 @@
+//! OpenTelemetry tracing. RPC spans continue the caller's W3C trace context
+//! (the `traceparent` / `tracestate` metadata), so rate-limit latency shows
+//! up inside the caller's distributed trace.
+
+use guardian_core::RateLimitError;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::{TraceContextExt, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, TracerProvider};
+use opentelemetry_sdk::Resource;
+use tonic::metadata::{KeyRef, MetadataMap};
+use tracing::Span;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use crate::config::TracingConfig;
+use guardian_proto::rate_limiter_server::SERVICE_NAME;
+
+/// Exporter pipeline sending spans to `config.otlp_endpoint`. Call
+/// `shutdown` on it before exiting to flush what's still buffered.
+pub fn provider(config: &TracingConfig) -> Result<TracerProvider, RateLimitError> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(&config.otlp_endpoint)
+        .build()
+        .map_err(|e| RateLimitError::ConfigError(format!("OTLP exporter: {}", e)))?;
+
+    let provider = TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            config.service_name.clone(),
+        )]))
+        .build();
+
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(provider)
+}
+
+/// Subscriber layer turning `tracing` spans into OpenTelemetry spans.
+pub fn layer<S>(provider: &TracerProvider) -> impl Layer<S>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer("guardian-service"))
+}
+
+/// Server span for one RPC, parented to the trace context in `metadata`.
+pub fn rpc_span(method: &'static str, metadata: &MetadataMap) -> Span {
+    let span = tracing::info_span!(
+        "rpc",
+        otel.name = %format_args!("{}/{}", SERVICE_NAME, method),
+        otel.kind = "server",
+        rpc.system = "grpc",
+        rpc.service = SERVICE_NAME,
+        rpc.method = method,
+        guardian.client_id = tracing::field::Empty,
+    );
+    let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&MetadataExtractor(metadata))
+    });
+    span.set_parent(parent);
+    span
+}
+
+/// The trace the current span belongs to, as 32 hex digits, if it is
+/// sampled and so will reach the collector.
+pub fn sampled_trace_id() -> Option<String> {
+    let context = Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    (span_context.is_valid() && span_context.is_sampled())
+        .then(|| span_context.trace_id().to_string())
+}
+
+/// Child span for one storage backend call.
+pub fn backend_span(operation: &'static str, key: &str) -> Span {
+    tracing::info_span!(
+        "backend",
+        otel.name = %format_args!("backend.{}", operation),
+        guardian.key = key,
+    )
+}
+
+struct MetadataExtractor<'a>(&'a MetadataMap);
+
+impl Extractor for MetadataExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .keys()
+            .filter_map(|key| match key {
+                KeyRef::Ascii(key) => Some(key.as_str()),
+                KeyRef::Binary(_) => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::propagation::TextMapPropagator;
+
+    #[test]
+    fn test_extracts_w3c_trace_context_from_metadata() {
+        let mut metadata = MetadataMap::new();
+        metadata.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+
+        let context = TraceContextPropagator::new().extract(&MetadataExtractor(&metadata));
+        let span = context.span();
+        let parent = span.span_context();
+        assert!(parent.is_remote());
+        assert_eq!(
+            parent.trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert!(parent.is_sampled());
+    }
+}
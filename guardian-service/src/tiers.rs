@@ -0,0 +1,111 @@
+// Client tiers: named limit classes (free, premium, ...) chosen per request
+// or from the config's client table, each with buckets of its own size.
+
+use guardian_core::{Observer, RateLimitError, RateLimiter, StorageBackend};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::{build_storage, GuardianConfig, LimitConfig};
+
+pub type TierLimiter = RateLimiter<Box<dyn StorageBackend>>;
+
+/// Per-tier limiters plus the client → tier assignments from config.
+///
+/// Tiered checks bypass the key-pattern rules: a client in a tier is
+/// limited by that tier alone.
+#[derive(Default)]
+pub struct Tiers {
+    limiters: HashMap<String, TierLimiter>,
+    limits: HashMap<String, LimitConfig>,
+    clients: HashMap<String, String>,
+}
+
+impl Tiers {
+    pub async fn from_config(config: &GuardianConfig) -> Result<Self, RateLimitError> {
+        let mut limiters = HashMap::new();
+        for (name, limit) in &config.tiers {
+            let backend = build_storage(&config.backends, limit.bucket_config()).await?;
+            limiters.insert(
+                name.clone(),
+                RateLimiter::new(backend, config.global.fail_open),
+            );
+        }
+
+        Ok(Self {
+            limiters,
+            limits: config.tiers.clone(),
+            clients: config.client_tiers.clone(),
+        })
+    }
+
+    /// The tier for `client_id`: `requested` when non-empty, otherwise the
+    /// one assigned in config. `None` means no tier applies.
+    pub fn tier_for<'a>(&'a self, client_id: &str, requested: &'a str) -> Option<&'a str> {
+        if requested.is_empty() {
+            self.clients.get(client_id).map(String::as_str)
+        } else {
+            Some(requested)
+        }
+    }
+
+    /// The limiter for the tier `tier_for` picks.
+    pub fn limiter_for(
+        &self,
+        client_id: &str,
+        requested: &str,
+    ) -> Result<Option<&TierLimiter>, RateLimitError> {
+        let Some(tier) = self.tier_for(client_id, requested) else {
+            return Ok(None);
+        };
+
+        self.limiters
+            .get(tier)
+            .map(Some)
+            .ok_or_else(|| RateLimitError::ConfigError(format!("unknown tier '{}'", tier)))
+    }
+
+    /// The limits configured for `tier`.
+    pub fn limit(&self, tier: &str) -> Option<&LimitConfig> {
+        self.limits.get(tier)
+    }
+
+    /// Report every tier's decisions to `observer`.
+    pub fn with_observer(mut self, observer: Arc<dyn Observer>) -> Self {
+        self.limiters = self
+            .limiters
+            .into_iter()
+            .map(|(name, limiter)| (name, limiter.with_observer(Arc::clone(&observer))))
+            .collect();
+        self
+    }
+
+    pub async fn flush(&self) -> Result<(), RateLimitError> {
+        for limiter in self.limiters.values() {
+            limiter.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tier_resolution() {
+        let mut config = GuardianConfig::default();
+        config
+            .tiers
+            .insert("premium".to_string(), LimitConfig::default());
+        config
+            .client_tiers
+            .insert("acme".to_string(), "premium".to_string());
+        let tiers = Tiers::from_config(&config).await.unwrap();
+
+        assert!(tiers.limiter_for("acme", "").unwrap().is_some());
+        assert!(tiers.limiter_for("someone", "").unwrap().is_none());
+        assert!(tiers.limiter_for("someone", "premium").unwrap().is_some());
+        assert!(tiers.limiter_for("acme", "gold").is_err());
+        assert_eq!(tiers.limit("premium").unwrap().capacity, 1000);
+    }
+}
@@ -0,0 +1,264 @@
+// TLS for the gRPC listener, optionally requiring client certificates.
+//
+// The server certificate is served through a resolver that can be swapped
+// at runtime: the files are re-read on SIGHUP and polled for changes, so
+// rotated certificates (e.g. from cert-manager) take effect for new
+// handshakes while established connections carry on undisturbed.
+
+use guardian_core::RateLimitError;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::transport::server::TcpIncoming;
+
+use crate::config::TlsConfig;
+
+/// Handshakes slower than this are dropped, so stalled clients can't pile up.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepted connections waiting for the server to pick them up.
+const ACCEPT_BACKLOG: usize = 128;
+
+/// The server certificate and key, as last loaded from disk.
+#[derive(Debug)]
+pub struct ServerCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Loaded>,
+}
+
+#[derive(Debug)]
+struct Loaded {
+    pem: (Vec<u8>, Vec<u8>),
+    key: Arc<CertifiedKey>,
+}
+
+impl ServerCert {
+    fn load(cert_path: &Path, key_path: &Path) -> Result<Self, RateLimitError> {
+        let pem = (read_pem(cert_path)?, read_pem(key_path)?);
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: RwLock::new(Loaded {
+                key: certified_key(&pem.0, &pem.1)?,
+                pem,
+            }),
+        })
+    }
+
+    /// Re-read the certificate and key, switching to them if they changed.
+    /// On failure (say, a half-written file) the current pair stays in use.
+    pub fn reload(&self) -> Result<bool, RateLimitError> {
+        let pem = (read_pem(&self.cert_path)?, read_pem(&self.key_path)?);
+        if self.current.read().unwrap().pem == pem {
+            return Ok(false);
+        }
+        let key = certified_key(&pem.0, &pem.1)?;
+        *self.current.write().unwrap() = Loaded { pem, key };
+        Ok(true)
+    }
+}
+
+impl ResolvesServerCert for ServerCert {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(Arc::clone(&self.current.read().unwrap().key))
+    }
+}
+
+/// Build the acceptor for the gRPC listener, and the certificate it serves
+/// so [`watch`] can keep it current.
+pub fn server_tls(tls: &TlsConfig) -> Result<(TlsAcceptor, Arc<ServerCert>), RateLimitError> {
+    // rustls can't choose on its own when several crypto providers are
+    // compiled in; an error here only means one is installed already.
+    let _ = rustls::crypto::ring::default_provider().install_default();
+
+    let cert = Arc::new(ServerCert::load(&tls.cert_path, &tls.key_path)?);
+    let builder = ServerConfig::builder();
+    let builder = match &tls.client_ca_path {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for ca in CertificateDer::pem_slice_iter(&read_pem(ca_path)?) {
+                let ca = ca.map_err(|e| invalid(ca_path, e))?;
+                roots.add(ca).map_err(|e| invalid(ca_path, e))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+            let verifier = if tls.client_auth_optional {
+                verifier.allow_unauthenticated()
+            } else {
+                verifier
+            };
+            builder.with_client_cert_verifier(verifier.build().map_err(|e| invalid(ca_path, e))?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(Arc::clone(&cert) as Arc<dyn ResolvesServerCert>);
+    config.alpn_protocols = vec![b"h2".to_vec()];
+    Ok((TlsAcceptor::from(Arc::new(config)), cert))
+}
+
+/// Reload `cert` on SIGHUP and, when `poll` is set, whenever its files
+/// change. Runs until the process exits.
+pub async fn watch(cert: Arc<ServerCert>, poll: Option<Duration>) {
+    #[cfg(unix)]
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(signal) => Some(signal),
+        Err(e) => {
+            tracing::warn!(error = %e, "cannot listen for SIGHUP");
+            None
+        }
+    };
+    loop {
+        #[cfg(unix)]
+        let hangup_received = async {
+            match &mut hangup {
+                Some(signal) => signal.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let hangup_received = std::future::pending::<Option<()>>();
+        let tick = async {
+            match poll {
+                Some(interval) => tokio::time::sleep(interval).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            _ = hangup_received => {}
+            _ = tick => {}
+        }
+
+        match cert.reload() {
+            Ok(true) => tracing::info!(
+                cert = %cert.cert_path.display(),
+                "reloaded TLS certificate"
+            ),
+            Ok(false) => {}
+            Err(e) => tracing::warn!(error = %e, "TLS certificate reload failed"),
+        }
+    }
+}
+
+/// TLS connections over those `tcp` accepts. Each handshake runs on its
+/// own task, so a slow client doesn't hold up the others.
+pub fn incoming(
+    mut tcp: TcpIncoming,
+    acceptor: TlsAcceptor,
+) -> ReceiverStream<std::io::Result<TlsStream<TcpStream>>> {
+    let (tx, rx) = mpsc::channel(ACCEPT_BACKLOG);
+    tokio::spawn(async move {
+        while let Some(accepted) = tcp.next().await {
+            let stream = match accepted {
+                Ok(stream) => stream,
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to accept connection");
+                    continue;
+                }
+            };
+            // The server has shut down.
+            if tx.is_closed() {
+                break;
+            }
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => {
+                        let _ = tx.send(Ok(stream)).await;
+                    }
+                    Ok(Err(e)) => tracing::debug!(%peer, error = %e, "TLS handshake failed"),
+                    Err(_) => tracing::debug!(%peer, "TLS handshake timed out"),
+                }
+            });
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+fn certified_key(cert_pem: &[u8], key_pem: &[u8]) -> Result<Arc<CertifiedKey>, RateLimitError> {
+    let chain = CertificateDer::pem_slice_iter(cert_pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| RateLimitError::ConfigError(format!("invalid TLS certificate: {}", e)))?;
+    if chain.is_empty() {
+        return Err(RateLimitError::ConfigError(
+            "TLS certificate file holds no certificates".to_string(),
+        ));
+    }
+    let key = PrivateKeyDer::from_pem_slice(key_pem)
+        .map_err(|e| RateLimitError::ConfigError(format!("invalid TLS key: {}", e)))?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| RateLimitError::ConfigError(format!("unsupported TLS key: {}", e)))?;
+    Ok(Arc::new(CertifiedKey::new(chain, key)))
+}
+
+fn read_pem(path: &Path) -> Result<Vec<u8>, RateLimitError> {
+    std::fs::read(path).map_err(|e| {
+        RateLimitError::ConfigError(format!("failed to read {}: {}", path.display(), e))
+    })
+}
+
+fn invalid(path: &Path, error: impl std::fmt::Display) -> RateLimitError {
+    RateLimitError::ConfigError(format!("invalid CA bundle {}: {}", path.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_certificate_is_a_config_error() {
+        let tls = TlsConfig {
+            cert_path: "/nonexistent/server.pem".into(),
+            key_path: "/nonexistent/server.key".into(),
+            client_ca_path: None,
+            client_auth_optional: false,
+            reload_interval_secs: 0,
+        };
+        let err = server_tls(&tls).err().unwrap();
+        assert!(err.to_string().contains("/nonexistent/server.pem"));
+    }
+
+    #[test]
+    fn test_reload_picks_up_rotated_certificate() {
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let dir = std::env::temp_dir().join(format!("guardian-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("tls.crt"), dir.join("tls.key"));
+        let install = |name: &str| {
+            std::fs::copy(testdata.join(format!("{}.pem", name)), &cert_path).unwrap();
+            std::fs::copy(testdata.join(format!("{}.key", name)), &key_path).unwrap();
+        };
+        let serving = |cert: &ServerCert| cert.current.read().unwrap().key.cert[0].clone();
+
+        install("server-a");
+        let cert = ServerCert::load(&cert_path, &key_path).unwrap();
+        let first = serving(&cert);
+        assert!(!cert.reload().unwrap());
+
+        install("server-b");
+        assert!(cert.reload().unwrap());
+        let second = serving(&cert);
+        assert_ne!(first, second);
+
+        // A broken rotation keeps the last good pair.
+        std::fs::write(&key_path, "not a key").unwrap();
+        assert!(cert.reload().is_err());
+        assert_eq!(serving(&cert), second);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+// Per-key usage over recent time windows (by default the last 5 minutes and
+// hour) for GetUsageReport, counted by the service as it answers checks so
+// dashboards don't have to re-aggregate raw metrics. At most `max_keys` keys
+// are counted; past that, the ones seen least recently make room.
+
+use guardian_core::KeyStats;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::UsageReportConfig;
+
+/// Counts for one slot of `resolution` width.
+struct Slot {
+    index: u64,
+    stats: KeyStats,
+}
+
+/// One key's slots, oldest first.
+struct KeyCounters {
+    slots: VecDeque<Slot>,
+    last_seen: SystemTime,
+}
+
+/// Allowed, denied and consumed counts per key, kept in fixed-width slots
+/// for as long as the longest window needs them.
+pub struct UsageCounters {
+    resolution: Duration,
+    /// Windows reported when a request doesn't pick its own.
+    windows: Vec<Duration>,
+    retention: Duration,
+    max_keys: usize,
+    started: Instant,
+    keys: Mutex<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    keys: HashMap<String, KeyCounters>,
+    /// Slot in which idle keys were last dropped.
+    swept: u64,
+}
+
+impl Counters {
+    /// Forget the least recently seen tenth of the keys (at least one).
+    fn evict(&mut self) {
+        let mut seen: Vec<SystemTime> = self.keys.values().map(|key| key.last_seen).collect();
+        let cutoff = (seen.len() / 10).max(1) - 1;
+        let (_, &mut newest_evicted, _) = seen.select_nth_unstable(cutoff);
+        let mut quota = cutoff + 1;
+        self.keys.retain(|_, key| {
+            if quota > 0 && key.last_seen <= newest_evicted {
+                quota -= 1;
+                return false;
+            }
+            true
+        });
+    }
+}
+
+impl UsageCounters {
+    pub fn new(config: &UsageReportConfig) -> Self {
+        let resolution = Duration::from_secs(config.resolution_secs.max(1));
+        let windows: Vec<Duration> = config
+            .windows_secs
+            .iter()
+            .map(|&secs| Duration::from_secs(secs))
+            .collect();
+        let retention = windows.iter().copied().max().unwrap_or(resolution);
+        Self {
+            resolution,
+            windows,
+            retention,
+            max_keys: config.max_keys.max(1),
+            started: Instant::now(),
+            keys: Mutex::default(),
+        }
+    }
+
+    pub fn windows(&self) -> &[Duration] {
+        &self.windows
+    }
+
+    /// Longest window a report may ask for.
+    pub fn retention(&self) -> Duration {
+        self.retention
+    }
+
+    fn slot_index(&self, now: Instant) -> u64 {
+        (now.duration_since(self.started).as_nanos() / self.resolution.as_nanos()) as u64
+    }
+
+    /// Slots covering `window`, rounded up to whole slots.
+    fn slots_in(&self, window: Duration) -> u64 {
+        window.as_nanos().div_ceil(self.resolution.as_nanos()) as u64
+    }
+
+    pub fn record(&self, key: &str, cost: u64, allowed: bool) {
+        let index = self.slot_index(Instant::now());
+        let kept = self.slots_in(self.retention);
+        let mut counters = self.keys.lock().unwrap();
+        // Idle keys are dropped once per slot rather than on every call.
+        if counters.swept != index {
+            counters.swept = index;
+            counters.keys.retain(|_, key| {
+                key.slots
+                    .back()
+                    .is_some_and(|slot| slot.index + kept > index)
+            });
+        }
+        if counters.keys.len() >= self.max_keys && !counters.keys.contains_key(key) {
+            counters.evict();
+        }
+
+        let counted = counters
+            .keys
+            .entry(key.to_string())
+            .or_insert_with(|| KeyCounters {
+                slots: VecDeque::new(),
+                last_seen: SystemTime::now(),
+            });
+        counted.last_seen = SystemTime::now();
+        let slots = &mut counted.slots;
+        if slots.back().is_none_or(|slot| slot.index != index) {
+            slots.push_back(Slot {
+                index,
+                stats: KeyStats::default(),
+            });
+        }
+        while slots.front().is_some_and(|slot| slot.index + kept <= index) {
+            slots.pop_front();
+        }
+        let stats = &mut slots.back_mut().unwrap().stats;
+        if allowed {
+            stats.allowed += 1;
+            stats.consumed += cost;
+        } else {
+            stats.denied += 1;
+        }
+    }
+
+    /// Totals for `key` over each of `windows`, in order.
+    pub fn report(&self, key: &str, windows: &[Duration]) -> Vec<KeyStats> {
+        let index = self.slot_index(Instant::now());
+        let counters = self.keys.lock().unwrap();
+        let slots = counters.keys.get(key).map(|key| &key.slots);
+        windows
+            .iter()
+            .map(|&window| {
+                let since = (index + 1).saturating_sub(self.slots_in(window));
+                let mut total = KeyStats::default();
+                for slot in slots
+                    .into_iter()
+                    .flatten()
+                    .filter(|slot| slot.index >= since)
+                {
+                    total.allowed += slot.stats.allowed;
+                    total.denied += slot.stats.denied;
+                    total.consumed += slot.stats.consumed;
+                }
+                total
+            })
+            .collect()
+    }
+
+    /// When `key` was last checked, if that is inside the retention
+    /// period and it is still counted.
+    pub fn last_seen(&self, key: &str) -> Option<SystemTime> {
+        let counters = self.keys.lock().unwrap();
+        let last_seen = counters.keys.get(key)?.last_seen;
+        let age = SystemTime::now()
+            .duration_since(last_seen)
+            .unwrap_or_default();
+        (age < self.retention).then_some(last_seen)
+    }
+
+    /// Keys with activity inside the retention period, sorted.
+    pub fn keys(&self) -> Vec<String> {
+        let index = self.slot_index(Instant::now());
+        let kept = self.slots_in(self.retention);
+        let counters = self.keys.lock().unwrap();
+        let mut keys: Vec<String> = counters
+            .keys
+            .iter()
+            .filter(|(_, key)| {
+                key.slots
+                    .back()
+                    .is_some_and(|slot| slot.index + kept > index)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.sort();
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_only_count_recent_slots() {
+        let counters = UsageCounters::new(&UsageReportConfig {
+            windows_secs: vec![300, 3600],
+            resolution_secs: 60,
+            ..UsageReportConfig::default()
+        });
+        counters.record("user1", 3, true);
+        counters.record("user1", 5, false);
+        counters.record("user2", 1, true);
+
+        let report = counters.report("user1", counters.windows());
+        assert_eq!(
+            report,
+            vec![
+                KeyStats {
+                    allowed: 1,
+                    denied: 1,
+                    consumed: 3
+                };
+                2
+            ]
+        );
+        assert_eq!(
+            counters.report("idle", &[Duration::from_secs(60)]),
+            vec![KeyStats::default()]
+        );
+        assert_eq!(counters.keys(), vec!["user1", "user2"]);
+        assert!(counters.last_seen("user1").unwrap() <= SystemTime::now());
+        assert!(counters.last_seen("idle").is_none());
+    }
+
+    #[test]
+    fn test_least_recently_seen_keys_make_room() {
+        let counters = UsageCounters::new(&UsageReportConfig {
+            max_keys: 20,
+            ..UsageReportConfig::default()
+        });
+        for n in 0..20 {
+            counters.record(&format!("user{:02}", n), 1, true);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        counters.record("user00", 1, false);
+        counters.record("late", 1, true);
+
+        let keys = counters.keys();
+        assert_eq!(keys.len(), 19);
+        // user00 was seen again, so user01 and user02 went instead.
+        assert!(keys.contains(&"user00".to_string()));
+        assert!(!keys.contains(&"user01".to_string()));
+        assert!(!keys.contains(&"user02".to_string()));
+        assert_eq!(
+            counters.report("user00", &[Duration::from_secs(60)])[0].denied,
+            1
+        );
+    }
+}
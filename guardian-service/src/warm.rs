@@ -0,0 +1,155 @@
+// Warm start: before the listeners open, the hottest keys get their local
+// state prepared (leased slices, reserved batches, cached buckets), so the
+// first seconds after a deploy don't send every one of them to the shared
+// store at the same moment.
+
+use guardian_core::{RateLimitError, StorageBackend};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+use crate::config::WarmStartConfig;
+
+/// Keys warmed at once; enough to finish quickly without turning warm-up
+/// into the burst it is meant to avoid.
+const CONCURRENCY: usize = 32;
+
+/// Keys requested per `list_keys` page while scanning.
+const SCAN_PAGE: usize = 1000;
+
+/// The keys to warm: those in the keys file, then those found by each scan
+/// pattern, without duplicates and at most `max_keys`. A scan the backend
+/// can't answer is skipped; an unreadable keys file is an error.
+pub async fn keys(
+    config: &WarmStartConfig,
+    backend: &dyn StorageBackend,
+) -> Result<Vec<String>, RateLimitError> {
+    let mut keys = Vec::new();
+    let mut seen = HashSet::new();
+    let mut add = |key: &str| {
+        if keys.len() < config.max_keys && seen.insert(key.to_string()) {
+            keys.push(key.to_string());
+        }
+    };
+
+    if let Some(path) = &config.keys_file {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            RateLimitError::ConfigError(format!("failed to read {}: {}", path.display(), e))
+        })?;
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .for_each(&mut add);
+    }
+
+    for pattern in &config.scan {
+        let mut after: Option<String> = None;
+        loop {
+            let page = match backend
+                .list_keys(pattern, after.as_deref(), SCAN_PAGE)
+                .await
+            {
+                Ok(page) => page,
+                Err(e) => {
+                    tracing::warn!(%pattern, error = %e, "warm start: cannot scan for keys");
+                    break;
+                }
+            };
+            page.iter().map(String::as_str).for_each(&mut add);
+            if page.len() < SCAN_PAGE {
+                break;
+            }
+            after = page.last().cloned();
+        }
+    }
+    Ok(keys)
+}
+
+/// Warm every key in `config` on `backend`, giving up after the configured
+/// timeout. Returns how many keys were warmed.
+pub async fn run(
+    config: &WarmStartConfig,
+    backend: Arc<dyn StorageBackend>,
+) -> Result<usize, RateLimitError> {
+    let keys = keys(config, backend.as_ref()).await?;
+    let total = keys.len();
+    let mut warmed = 0;
+    let warming = async {
+        let mut tasks = JoinSet::new();
+        for key in keys {
+            if tasks.len() >= CONCURRENCY {
+                warmed += finished(tasks.join_next().await);
+            }
+            let backend = Arc::clone(&backend);
+            tasks.spawn(async move {
+                let result = backend.warm_up(&key).await;
+                if let Err(e) = &result {
+                    tracing::debug!(%key, error = %e, "warm start: key not warmed");
+                }
+                result.is_ok()
+            });
+        }
+        while let Some(done) = tasks.join_next().await {
+            warmed += finished(Some(done));
+        }
+    };
+    if tokio::time::timeout(Duration::from_secs(config.timeout_secs), warming)
+        .await
+        .is_err()
+    {
+        tracing::warn!(warmed, total, "warm start timed out; serving anyway");
+    }
+    Ok(warmed)
+}
+
+fn finished(done: Option<Result<bool, tokio::task::JoinError>>) -> usize {
+    usize::from(matches!(done, Some(Ok(true))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_core::{MemoryBackend, TieredBackend, TieredConfig, TokenBucketConfig};
+
+    #[tokio::test]
+    async fn test_warms_listed_and_scanned_keys() {
+        let memory = Arc::new(MemoryBackend::new(TokenBucketConfig::default()));
+        for key in ["api:a", "api:b", "web:c"] {
+            memory.take_token(key, 1).await.unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("guardian-warm-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "# hot keys\nvip:1\n\napi:a\n").unwrap();
+        let config = WarmStartConfig {
+            keys_file: Some(path.clone()),
+            scan: vec!["api:*".to_string()],
+            max_keys: 100,
+            timeout_secs: 5,
+        };
+
+        let tiered = TieredBackend::new(
+            Arc::clone(&memory),
+            TieredConfig {
+                slice_size: 5,
+                max_staleness: Duration::from_secs(60),
+                prefetch_below: 0,
+            },
+        );
+        assert_eq!(
+            keys(&config, &tiered).await.unwrap(),
+            ["vip:1", "api:a", "api:b"]
+        );
+        assert_eq!(run(&config, Arc::new(tiered)).await.unwrap(), 3);
+        assert_eq!(memory.get_usage("vip:1").await.unwrap(), 5);
+        assert_eq!(memory.get_usage("api:b").await.unwrap(), 6);
+        assert_eq!(memory.get_usage("web:c").await.unwrap(), 1);
+
+        let capped = WarmStartConfig {
+            max_keys: 2,
+            ..config
+        };
+        assert_eq!(keys(&capped, memory.as_ref()).await.unwrap().len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+// Webhook alerts: POST a JSON event to configured URLs when a key is denied
+// too often or is put on the deny list, so alerting doesn't wait on a
+// metrics scrape. Delivery happens in the background with retries.
+
+use guardian_core::RateLimitError;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+
+use crate::config::WebhookConfig;
+
+/// Events waiting for delivery before new ones are dropped.
+const QUEUE_DEPTH: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// `denials` denials of one key within `window_secs`.
+    DenialRate {
+        namespace: String,
+        client_id: String,
+        denials: u64,
+        window_secs: u64,
+        timestamp_ms: u64,
+    },
+    /// The key was put on the deny list, until `expires_at` (Unix seconds)
+    /// if it lapses.
+    DenyListed {
+        namespace: String,
+        client_id: String,
+        reason: String,
+        expires_at: Option<u64>,
+        timestamp_ms: u64,
+    },
+}
+
+struct DenialWindow {
+    started: Instant,
+    denials: u64,
+}
+
+/// Counts denials per key in fixed windows and queues an event the first
+/// time a key reaches the threshold in a window.
+pub struct Notifier {
+    tx: mpsc::Sender<WebhookEvent>,
+    threshold: u64,
+    window: Duration,
+    denials: Mutex<HashMap<String, DenialWindow>>,
+}
+
+impl Notifier {
+    pub fn from_config(config: &WebhookConfig) -> Result<Self, RateLimitError> {
+        if config.urls.is_empty() {
+            return Err(RateLimitError::ConfigError(
+                "webhooks: at least one URL is required".to_string(),
+            ));
+        }
+        if config.denial_threshold == 0 || config.window_secs == 0 {
+            return Err(RateLimitError::ConfigError(
+                "webhooks: denial_threshold and window_secs must be positive".to_string(),
+            ));
+        }
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .map_err(|e| RateLimitError::ConfigError(format!("webhooks: {}", e)))?;
+
+        let (tx, rx) = mpsc::channel(QUEUE_DEPTH);
+        tokio::spawn(deliver_events(
+            rx,
+            client,
+            config.urls.clone(),
+            config.max_retries,
+            Duration::from_millis(config.retry_backoff_ms),
+        ));
+        Ok(Self {
+            tx,
+            threshold: config.denial_threshold,
+            window: Duration::from_secs(config.window_secs),
+            denials: Mutex::default(),
+        })
+    }
+
+    pub fn record_denial(&self, namespace: &str, client_id: &str, key: &str) {
+        let now = Instant::now();
+        let denials = {
+            let mut windows = self.denials.lock().unwrap();
+            let window = windows.entry(key.to_string()).or_insert(DenialWindow {
+                started: now,
+                denials: 0,
+            });
+            if now.duration_since(window.started) >= self.window {
+                window.started = now;
+                window.denials = 0;
+            }
+            window.denials += 1;
+            let denials = window.denials;
+            // A key crossing the threshold is a good moment to forget idle ones.
+            if denials == self.threshold {
+                windows.retain(|_, window| now.duration_since(window.started) < self.window);
+            }
+            denials
+        };
+        if denials == self.threshold {
+            self.send(WebhookEvent::DenialRate {
+                namespace: namespace.to_string(),
+                client_id: client_id.to_string(),
+                denials,
+                window_secs: self.window.as_secs(),
+                timestamp_ms: timestamp_ms(),
+            });
+        }
+    }
+
+    pub fn deny_listed(
+        &self,
+        namespace: &str,
+        client_id: &str,
+        reason: &str,
+        ttl: Option<Duration>,
+    ) {
+        self.send(WebhookEvent::DenyListed {
+            namespace: namespace.to_string(),
+            client_id: client_id.to_string(),
+            reason: reason.to_string(),
+            expires_at: ttl.map(|ttl| {
+                (SystemTime::now() + ttl)
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs()
+            }),
+            timestamp_ms: timestamp_ms(),
+        });
+    }
+
+    fn send(&self, event: WebhookEvent) {
+        if self.tx.try_send(event).is_err() {
+            tracing::warn!("webhook queue full, dropping event");
+        }
+    }
+}
+
+fn timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Post every event to every URL. Each delivery retries on its own, so one
+/// slow endpoint doesn't hold back the rest.
+async fn deliver_events(
+    mut rx: mpsc::Receiver<WebhookEvent>,
+    client: reqwest::Client,
+    urls: Vec<String>,
+    max_retries: u32,
+    backoff: Duration,
+) {
+    while let Some(event) = rx.recv().await {
+        for url in &urls {
+            tokio::spawn(deliver(
+                client.clone(),
+                url.clone(),
+                event.clone(),
+                max_retries,
+                backoff,
+            ));
+        }
+    }
+}
+
+/// Retries connection failures, 429s and 5xx responses with exponential
+/// backoff; other responses are final.
+async fn deliver(
+    client: reqwest::Client,
+    url: String,
+    event: WebhookEvent,
+    max_retries: u32,
+    backoff: Duration,
+) {
+    let mut delay = backoff;
+    for attempt in 0..=max_retries {
+        if attempt > 0 {
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+        let error = match client.post(&url).json(&event).send().await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => {
+                let status = response.status();
+                if !(status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS) {
+                    tracing::warn!(%url, %status, "webhook rejected");
+                    return;
+                }
+                status.to_string()
+            }
+            Err(e) => e.to_string(),
+        };
+        tracing::debug!(%url, attempt, %error, "webhook delivery failed");
+    }
+    tracing::warn!(%url, attempts = max_retries + 1, "giving up on webhook delivery");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, routing::post, Json, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_threshold_breach_is_posted_with_retries() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let (tx, mut delivered) = mpsc::channel(1);
+        let app = Router::new().route(
+            "/hook",
+            post({
+                let received = Arc::clone(&received);
+                let attempts = Arc::clone(&attempts);
+                move |Json(event): Json<serde_json::Value>| async move {
+                    // Fail the first attempt to exercise the retry.
+                    if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                        return StatusCode::SERVICE_UNAVAILABLE;
+                    }
+                    received.lock().unwrap().push(event);
+                    let _ = tx.send(()).await;
+                    StatusCode::OK
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let notifier = Notifier::from_config(&WebhookConfig {
+            urls: vec![format!("http://{}/hook", addr)],
+            denial_threshold: 3,
+            window_secs: 60,
+            max_retries: 2,
+            retry_backoff_ms: 10,
+            timeout_ms: 1000,
+        })
+        .unwrap();
+        for _ in 0..5 {
+            notifier.record_denial("", "user1", "user1");
+        }
+        notifier.record_denial("", "user2", "user2");
+
+        tokio::time::timeout(Duration::from_secs(5), delivered.recv())
+            .await
+            .unwrap();
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["event"], "denial_rate");
+        assert_eq!(received[0]["client_id"], "user1");
+        assert_eq!(received[0]["denials"], 3);
+    }
+}
@@ -0,0 +1,212 @@
+use async_trait::async_trait;
+use guardian_core::{BucketState, RateLimitError, StorageBackend, TokenBucketConfig};
+use parking_lot::Mutex;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Buckets untouched for this long are deleted by `prune_idle`.
+const BUCKET_TTL: Duration = Duration::from_secs(3600);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS buckets (
+        key            TEXT PRIMARY KEY,
+        tokens         INTEGER NOT NULL,
+        last_refill_ms INTEGER NOT NULL,
+        updated_at_ms  INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS buckets_updated_at ON buckets (updated_at_ms);
+";
+
+/// Durable single-node backend on an embedded SQLite database.
+///
+/// One connection in WAL mode serves as the single writer; every operation
+/// runs on the blocking pool so the async runtime never waits on disk.
+/// Rate-limit state survives restarts without any external service.
+pub struct SqliteBackend {
+    conn: Arc<Mutex<Connection>>,
+    config: TokenBucketConfig,
+}
+
+impl SqliteBackend {
+    pub fn open(path: impl AsRef<Path>, config: TokenBucketConfig) -> Result<Self, RateLimitError> {
+        let conn = Connection::open(path).map_err(sqlite_error)?;
+        Self::init(conn, config)
+    }
+
+    /// Non-persistent database, mainly for tests.
+    pub fn open_in_memory(config: TokenBucketConfig) -> Result<Self, RateLimitError> {
+        let conn = Connection::open_in_memory().map_err(sqlite_error)?;
+        Self::init(conn, config)
+    }
+
+    fn init(conn: Connection, config: TokenBucketConfig) -> Result<Self, RateLimitError> {
+        // WAL lets readers (e.g. backup tools) proceed alongside our writer;
+        // NORMAL sync is durable across process crashes, which is what
+        // rate-limit state needs.
+        conn.pragma_update(None, "journal_mode", "WAL")
+            .map_err(sqlite_error)?;
+        conn.pragma_update(None, "synchronous", "NORMAL")
+            .map_err(sqlite_error)?;
+        conn.busy_timeout(Duration::from_secs(5))
+            .map_err(sqlite_error)?;
+        conn.execute_batch(SCHEMA).map_err(sqlite_error)?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            config,
+        })
+    }
+
+    /// Delete buckets idle for longer than the TTL, returning how many were
+    /// removed. Call periodically from a maintenance task.
+    pub async fn prune_idle(&self) -> Result<usize, RateLimitError> {
+        let cutoff = current_millis().saturating_sub(BUCKET_TTL.as_millis() as u64);
+        self.with_conn(move |conn| {
+            conn.execute(
+                "DELETE FROM buckets WHERE updated_at_ms < ?1",
+                params![cutoff as i64],
+            )
+        })
+        .await
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> Result<T, RateLimitError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || f(&mut conn.lock()))
+            .await
+            .map_err(|e| RateLimitError::StorageError(format!("SQLite task error: {}", e)))?
+            .map_err(sqlite_error)
+    }
+}
+
+fn load(conn: &Connection, key: &str) -> rusqlite::Result<Option<BucketState>> {
+    conn.query_row(
+        "SELECT tokens, last_refill_ms FROM buckets WHERE key = ?1",
+        params![key],
+        |row| {
+            Ok(BucketState {
+                tokens: row.get::<_, i64>(0)? as u64,
+                last_refill_ms: row.get::<_, i64>(1)? as u64,
+            })
+        },
+    )
+    .optional()
+}
+
+#[async_trait]
+impl StorageBackend for SqliteBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        let key = key.to_string();
+        let config = self.config.clone();
+
+        self.with_conn(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let now_ms = current_millis();
+            let mut state = load(&tx, &key)?.unwrap_or_else(|| BucketState::full(&config, now_ms));
+
+            let allowed = state.try_consume(cost, &config, now_ms);
+            tx.execute(
+                "INSERT INTO buckets (key, tokens, last_refill_ms, updated_at_ms)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (key) DO UPDATE SET
+                     tokens = excluded.tokens,
+                     last_refill_ms = excluded.last_refill_ms,
+                     updated_at_ms = excluded.updated_at_ms",
+                params![
+                    key,
+                    state.tokens as i64,
+                    state.last_refill_ms as i64,
+                    now_ms as i64
+                ],
+            )?;
+            tx.commit()?;
+            Ok(allowed)
+        })
+        .await
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let key = key.to_string();
+        let config = self.config.clone();
+
+        self.with_conn(move |conn| {
+            Ok(match load(conn, &key)? {
+                Some(mut state) => {
+                    state.refill(&config, current_millis());
+                    config.capacity.saturating_sub(state.tokens)
+                }
+                None => 0,
+            })
+        })
+        .await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let key = key.to_string();
+        self.with_conn(move |conn| {
+            conn.execute("DELETE FROM buckets WHERE key = ?1", params![key])
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+fn current_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn sqlite_error(e: rusqlite::Error) -> RateLimitError {
+    RateLimitError::StorageError(format!("SQLite error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TokenBucketConfig {
+        TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_backend() {
+        let backend = SqliteBackend::open_in_memory(config()).unwrap();
+
+        assert!(backend.take_token("user1", 6).await.unwrap());
+        assert!(!backend.take_token("user1", 6).await.unwrap());
+        assert_eq!(backend.get_usage("user1").await.unwrap(), 6);
+
+        backend.reset("user1").await.unwrap();
+        assert_eq!(backend.get_usage("user1").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_state_survives_reopen() {
+        let path = std::env::temp_dir().join(format!("guardian-sqlite-{}.db", std::process::id()));
+
+        {
+            let backend = SqliteBackend::open(&path, config()).unwrap();
+            assert!(backend.take_token("user1", 8).await.unwrap());
+        }
+
+        let backend = SqliteBackend::open(&path, config()).unwrap();
+        assert!(!backend.take_token("user1", 8).await.unwrap());
+
+        drop(backend);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
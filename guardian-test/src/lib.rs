@@ -0,0 +1,501 @@
+//! Test doubles for code built on Guardian. [`MockBackend`] is a storage
+//! backend whose answers can be scripted and whose failures can be
+//! injected, and which records every call made to it; [`MockClock`] is a
+//! clock that only moves when told to, so tests of refill and expiry run
+//! without sleeping. [`sim`] runs several nodes against one limit, to check
+//! how far the cluster strays from it.
+//!
+//! ```
+//! use guardian_core::{LimitResult, RateLimiter, TokenBucketConfig};
+//! use guardian_test::MockBackend;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! # async fn example() {
+//! let backend = Arc::new(MockBackend::new(TokenBucketConfig {
+//!     capacity: 1,
+//!     refill_rate: 1,
+//!     refill_interval: Duration::from_secs(1),
+//! }));
+//! let limiter = RateLimiter::new(Arc::clone(&backend), false);
+//!
+//! let allowed = |result| matches!(result, Ok(LimitResult::Allowed));
+//! assert!(allowed(limiter.check_limit("user1", 1).await));
+//! assert!(!allowed(limiter.check_limit("user1", 1).await));
+//! backend.clock().advance(Duration::from_secs(1));
+//! assert!(allowed(limiter.check_limit("user1", 1).await));
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use guardian_core::{
+    BucketSnapshot, Clock, Lease, MemoryBackend, RateLimitError, StorageBackend, TokenBucketConfig,
+    TokenDecision,
+};
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+pub mod sim;
+
+// ============================================================================
+// CLOCK
+// ============================================================================
+
+/// A clock that stands still until moved. Clones share one present, so a
+/// test keeps a clone to move the clock of whatever it handed one to.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl MockClock {
+    /// A clock stopped at the present.
+    pub fn new() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+
+    pub fn starting_at(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Move the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock() += by;
+    }
+
+    /// Move the clock to `now`, which may be in its past.
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock() = now;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock()
+    }
+}
+
+// ============================================================================
+// BACKEND
+// ============================================================================
+
+/// A call made to a [`MockBackend`], with its arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Call {
+    TakeToken {
+        key: String,
+        cost: u64,
+    },
+    GetUsage {
+        key: String,
+    },
+    Reset {
+        key: String,
+    },
+    Refund {
+        key: String,
+        tokens: u64,
+    },
+    AcquireLease {
+        key: String,
+        id: u64,
+        tokens: u64,
+    },
+    RenewLease {
+        key: String,
+        id: u64,
+    },
+    ReturnLease {
+        key: String,
+        id: u64,
+        unused: u64,
+    },
+    Leases {
+        key: String,
+    },
+    ExchangeDemand {
+        key: String,
+        node: String,
+        demand: u64,
+    },
+    HoldLock {
+        name: String,
+        holder: String,
+    },
+    ReleaseLock {
+        name: String,
+        holder: String,
+    },
+    Inspect {
+        key: String,
+    },
+    HealthCheck,
+    Flush,
+    WarmUp {
+        key: String,
+    },
+    ListKeys {
+        pattern: String,
+    },
+}
+
+#[derive(Default)]
+struct Script {
+    /// Decisions to give for each key's next takes, in order.
+    decisions: HashMap<String, VecDeque<bool>>,
+    /// Errors to fail the next calls with, in order.
+    failures: VecDeque<String>,
+    /// The error every call fails with until healed.
+    outage: Option<String>,
+}
+
+/// A storage backend for tests. Calls it has no script for are answered by
+/// an in-memory backend running on its own [`MockClock`]; scripted
+/// decisions and injected failures take precedence, and every call is
+/// recorded whichever answers it.
+pub struct MockBackend {
+    memory: MemoryBackend,
+    clock: MockClock,
+    script: Mutex<Script>,
+    calls: Mutex<Vec<Call>>,
+}
+
+impl MockBackend {
+    /// Buckets shaped by `config`, refilled by a clock stopped at the
+    /// present.
+    pub fn new(config: TokenBucketConfig) -> Self {
+        Self::with_clock(config, MockClock::new())
+    }
+
+    /// Buckets shaped by `config`, refilled by `clock`.
+    pub fn with_clock(config: TokenBucketConfig, clock: MockClock) -> Self {
+        Self {
+            memory: MemoryBackend::new(config).with_clock(Arc::new(clock.clone())),
+            clock,
+            script: Mutex::default(),
+            calls: Mutex::default(),
+        }
+    }
+
+    /// The clock buckets refill by.
+    pub fn clock(&self) -> &MockClock {
+        &self.clock
+    }
+
+    /// Answer the next takes on `key` with `decisions`, in order, without
+    /// touching its bucket. Takes past the end of the script are answered
+    /// by the bucket again.
+    pub fn script(&self, key: &str, decisions: impl IntoIterator<Item = bool>) {
+        self.script
+            .lock()
+            .decisions
+            .entry(key.to_string())
+            .or_default()
+            .extend(decisions);
+    }
+
+    /// Fail the next call, of any kind, with a storage error carrying
+    /// `message`. Queued failures are used up in order.
+    pub fn fail_next(&self, message: impl Into<String>) {
+        self.script.lock().failures.push_back(message.into());
+    }
+
+    /// Fail every call with a storage error carrying `message` until
+    /// [`heal`](Self::heal)ed, as a store that has gone away would.
+    pub fn fail_all(&self, message: impl Into<String>) {
+        self.script.lock().outage = Some(message.into());
+    }
+
+    /// End an outage started by [`fail_all`](Self::fail_all). Failures
+    /// queued by [`fail_next`](Self::fail_next) are kept.
+    pub fn heal(&self) {
+        self.script.lock().outage = None;
+    }
+
+    /// Every call made so far, oldest first.
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().clone()
+    }
+
+    /// Forget the calls made so far.
+    pub fn clear_calls(&self) {
+        self.calls.lock().clear();
+    }
+
+    /// Record `call`, then fail it if a failure is due.
+    fn enter(&self, call: Call) -> Result<(), RateLimitError> {
+        self.calls.lock().push(call);
+        let mut script = self.script.lock();
+        let failure = match &script.outage {
+            Some(message) => Some(message.clone()),
+            None => script.failures.pop_front(),
+        };
+        match failure {
+            Some(message) => Err(RateLimitError::StorageError(message)),
+            None => Ok(()),
+        }
+    }
+
+    fn scripted(&self, key: &str) -> Option<bool> {
+        let mut script = self.script.lock();
+        let decisions = script.decisions.get_mut(key)?;
+        let decision = decisions.pop_front();
+        if decisions.is_empty() {
+            script.decisions.remove(key);
+        }
+        decision
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MockBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        Ok(self.take_token_detailed(key, cost).await?.allowed)
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        self.enter(Call::TakeToken {
+            key: key.to_string(),
+            cost,
+        })?;
+        match self.scripted(key) {
+            Some(allowed) => Ok(TokenDecision {
+                allowed,
+                bucket: None,
+            }),
+            None => self.memory.take_token_detailed(key, cost).await,
+        }
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        self.enter(Call::GetUsage {
+            key: key.to_string(),
+        })?;
+        self.memory.get_usage(key).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.enter(Call::Reset {
+            key: key.to_string(),
+        })?;
+        self.memory.reset(key).await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.enter(Call::Refund {
+            key: key.to_string(),
+            tokens,
+        })?;
+        self.memory.refund(key, tokens).await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.enter(Call::AcquireLease {
+            key: key.to_string(),
+            id,
+            tokens,
+        })?;
+        self.memory.acquire_lease(key, id, tokens, ttl).await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.enter(Call::RenewLease {
+            key: key.to_string(),
+            id,
+        })?;
+        self.memory.renew_lease(key, id, ttl).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.enter(Call::ReturnLease {
+            key: key.to_string(),
+            id,
+            unused,
+        })?;
+        self.memory.return_lease(key, id, unused).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.enter(Call::Leases {
+            key: key.to_string(),
+        })?;
+        self.memory.leases(key).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.enter(Call::ExchangeDemand {
+            key: key.to_string(),
+            node: node.to_string(),
+            demand,
+        })?;
+        self.memory.exchange_demand(key, node, demand, ttl).await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.enter(Call::HoldLock {
+            name: name.to_string(),
+            holder: holder.to_string(),
+        })?;
+        self.memory.hold_lock(name, holder, ttl).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.enter(Call::ReleaseLock {
+            name: name.to_string(),
+            holder: holder.to_string(),
+        })?;
+        self.memory.release_lock(name, holder).await
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.enter(Call::Inspect {
+            key: key.to_string(),
+        })?;
+        self.memory.inspect(key).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.enter(Call::HealthCheck)
+    }
+
+    async fn flush(&self) -> Result<(), RateLimitError> {
+        self.enter(Call::Flush)
+    }
+
+    async fn warm_up(&self, key: &str) -> Result<(), RateLimitError> {
+        self.enter(Call::WarmUp {
+            key: key.to_string(),
+        })
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.enter(Call::ListKeys {
+            pattern: pattern.to_string(),
+        })?;
+        self.memory.list_keys(pattern, after, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(capacity: u64, refill_rate: u64) -> TokenBucketConfig {
+        TokenBucketConfig {
+            capacity,
+            refill_rate,
+            refill_interval: Duration::from_secs(1),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scripted_decisions_come_before_the_bucket() {
+        let backend = MockBackend::new(config(1, 0));
+        backend.script("user1", [false, true]);
+
+        assert!(!backend.take_token("user1", 1).await.unwrap());
+        assert!(backend.take_token("user1", 1).await.unwrap());
+        // The script is used up and the bucket was never touched.
+        assert!(backend.take_token("user1", 1).await.unwrap());
+        assert!(!backend.take_token("user1", 1).await.unwrap());
+        assert_eq!(
+            backend.calls()[0],
+            Call::TakeToken {
+                key: "user1".to_string(),
+                cost: 1
+            }
+        );
+        assert_eq!(backend.calls().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_injected_failures_are_recorded_and_used_up() {
+        let backend = MockBackend::new(config(10, 0));
+        backend.fail_next("timed out");
+        assert!(matches!(
+            backend.take_token("user1", 1).await,
+            Err(RateLimitError::StorageError(message)) if message == "timed out"
+        ));
+        assert!(backend.take_token("user1", 1).await.is_ok());
+
+        backend.fail_all("connection refused");
+        assert!(backend.health_check().await.is_err());
+        assert!(backend.reset("user1").await.is_err());
+        backend.heal();
+        assert!(backend.health_check().await.is_ok());
+
+        assert_eq!(
+            backend.calls()[2..],
+            [
+                Call::HealthCheck,
+                Call::Reset {
+                    key: "user1".to_string()
+                },
+                Call::HealthCheck,
+            ]
+        );
+        backend.clear_calls();
+        assert!(backend.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_buckets_refill_and_leases_lapse_by_the_mock_clock() {
+        let backend = MockBackend::new(config(2, 1));
+        assert!(backend.take_token("user1", 2).await.unwrap());
+        assert!(!backend.take_token("user1", 1).await.unwrap());
+        backend.clock().advance(Duration::from_secs(1));
+        assert!(backend.take_token("user1", 1).await.unwrap());
+        assert!(!backend.take_token("user1", 1).await.unwrap());
+
+        let lease = backend
+            .acquire_lease("user2", 1, 1, Duration::from_secs(30))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(backend.leases("user2").await.unwrap(), [lease]);
+        backend.clock().advance(Duration::from_secs(30));
+        assert!(backend.leases("user2").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,656 @@
+//! A cluster of Guardian nodes in one process, on a simulated network and
+//! clock, for checking how closely the cluster as a whole keeps to a limit.
+//!
+//! Every node reaches the shared store, and its gossip peers, across a
+//! network with a set one-way delay, and any node can be cut off from it.
+//! Time is tokio's: under a paused runtime (`#[tokio::test(start_paused =
+//! true)]`) a minute of traffic runs in milliseconds, and nothing waits
+//! on real time or the OS scheduler, so a run can be repeated.
+//!
+//! ```
+//! use guardian_core::TokenBucketConfig;
+//! use guardian_test::sim::{Mode, Simulation};
+//! use std::time::Duration;
+//!
+//! # async fn example() {
+//! let config = TokenBucketConfig {
+//!     capacity: 100,
+//!     refill_rate: 10,
+//!     refill_interval: Duration::from_secs(1),
+//! };
+//! let sim = Simulation::new(config, 3, Mode::Batching { batch_size: 10 })
+//!     .with_delay(Duration::from_millis(5));
+//! let report = sim.run("user1", 50, Duration::from_secs(10)).await;
+//! assert!(report.total.allowed <= sim.budget(report.elapsed));
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use guardian_core::{
+    BatchingBackend, BucketSnapshot, Clock, Lease, LeaseConfig, LeasingBackend, MemoryBackend,
+    RateLimitError, StorageBackend, TokenBucketConfig, TokenDecision,
+};
+use guardian_gossip::{GossipBackend, GossipConfig, GossipMode, GossipNode, Transport};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// Port every simulated node gossips on; nodes differ by address.
+const GOSSIP_PORT: u16 = 7946;
+
+// ============================================================================
+// CLOCK
+// ============================================================================
+
+/// Wall-clock time that follows tokio's clock, so it stands still and
+/// jumps ahead with a paused runtime. Starts at the same instant in every
+/// simulation.
+#[derive(Debug, Clone)]
+pub struct SimClock {
+    base: SystemTime,
+    started: Instant,
+}
+
+impl SimClock {
+    pub fn new() -> Self {
+        Self {
+            base: SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> SystemTime {
+        self.base + self.started.elapsed()
+    }
+}
+
+// ============================================================================
+// NETWORK
+// ============================================================================
+
+#[derive(Default)]
+struct Network {
+    /// One way, between any two parties.
+    delay: Duration,
+    partitioned: HashSet<usize>,
+    /// Each gossip address's node and inbox.
+    inboxes: HashMap<SocketAddr, (usize, mpsc::UnboundedSender<Vec<u8>>)>,
+}
+
+impl Network {
+    fn reachable(&self, node: usize) -> bool {
+        !self.partitioned.contains(&node)
+    }
+}
+
+/// A node's gossip socket. Datagrams arrive after the network's delay,
+/// unless either end was partitioned when they were sent.
+struct SimSocket {
+    node: usize,
+    addr: SocketAddr,
+    network: Arc<Mutex<Network>>,
+    inbox: tokio::sync::Mutex<mpsc::UnboundedReceiver<Vec<u8>>>,
+}
+
+impl SimSocket {
+    fn bind(network: &Arc<Mutex<Network>>, node: usize) -> Self {
+        let addr = SocketAddr::from((Ipv4Addr::new(10, 0, 0, node as u8 + 1), GOSSIP_PORT));
+        let (sender, inbox) = mpsc::unbounded_channel();
+        network.lock().inboxes.insert(addr, (node, sender));
+        Self {
+            node,
+            addr,
+            network: Arc::clone(network),
+            inbox: tokio::sync::Mutex::new(inbox),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for SimSocket {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+
+    async fn send_to(&self, datagram: &[u8], peer: SocketAddr) -> io::Result<()> {
+        let (delay, inbox) = {
+            let network = self.network.lock();
+            match network.inboxes.get(&peer) {
+                Some((to, inbox)) if network.reachable(self.node) && network.reachable(*to) => {
+                    (network.delay, inbox.clone())
+                }
+                // Lost, as UDP would lose it.
+                _ => return Ok(()),
+            }
+        };
+        let datagram = datagram.to_vec();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let _ = inbox.send(datagram);
+        });
+        Ok(())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        let datagram = self
+            .inbox
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        let len = datagram.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&datagram[..len]);
+        Ok(len)
+    }
+}
+
+/// A node's connection to the shared store. Each call waits out the delay
+/// there and back, and fails if the node is partitioned at either end of
+/// the trip; a call that fails on the way back has still been made.
+struct StoreLink {
+    node: usize,
+    store: Arc<MemoryBackend>,
+    network: Arc<Mutex<Network>>,
+}
+
+impl StoreLink {
+    async fn travel(&self) -> Result<(), RateLimitError> {
+        let delay = self.network.lock().delay;
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        if !self.network.lock().reachable(self.node) {
+            return Err(RateLimitError::StorageError(format!(
+                "sim: node {} is partitioned from the store",
+                self.node
+            )));
+        }
+        Ok(())
+    }
+
+    async fn call<T>(
+        &self,
+        call: impl Future<Output = Result<T, RateLimitError>>,
+    ) -> Result<T, RateLimitError> {
+        self.travel().await?;
+        let result = call.await;
+        self.travel().await?;
+        result
+    }
+}
+
+#[async_trait]
+impl StorageBackend for StoreLink {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        self.call(self.store.take_token(key, cost)).await
+    }
+
+    async fn take_token_detailed(
+        &self,
+        key: &str,
+        cost: u64,
+    ) -> Result<TokenDecision, RateLimitError> {
+        self.call(self.store.take_token_detailed(key, cost)).await
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        self.call(self.store.get_usage(key)).await
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        self.call(self.store.reset(key)).await
+    }
+
+    async fn refund(&self, key: &str, tokens: u64) -> Result<(), RateLimitError> {
+        self.call(self.store.refund(key, tokens)).await
+    }
+
+    async fn acquire_lease(
+        &self,
+        key: &str,
+        id: u64,
+        tokens: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.call(self.store.acquire_lease(key, id, tokens, ttl))
+            .await
+    }
+
+    async fn renew_lease(
+        &self,
+        key: &str,
+        id: u64,
+        ttl: Duration,
+    ) -> Result<Option<Lease>, RateLimitError> {
+        self.call(self.store.renew_lease(key, id, ttl)).await
+    }
+
+    async fn return_lease(&self, key: &str, id: u64, unused: u64) -> Result<u64, RateLimitError> {
+        self.call(self.store.return_lease(key, id, unused)).await
+    }
+
+    async fn leases(&self, key: &str) -> Result<Vec<Lease>, RateLimitError> {
+        self.call(self.store.leases(key)).await
+    }
+
+    async fn exchange_demand(
+        &self,
+        key: &str,
+        node: &str,
+        demand: u64,
+        ttl: Duration,
+    ) -> Result<Vec<(String, u64)>, RateLimitError> {
+        self.call(self.store.exchange_demand(key, node, demand, ttl))
+            .await
+    }
+
+    async fn hold_lock(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, RateLimitError> {
+        self.call(self.store.hold_lock(name, holder, ttl)).await
+    }
+
+    async fn release_lock(&self, name: &str, holder: &str) -> Result<(), RateLimitError> {
+        self.call(self.store.release_lock(name, holder)).await
+    }
+
+    async fn inspect(&self, key: &str) -> Result<Option<BucketSnapshot>, RateLimitError> {
+        self.call(self.store.inspect(key)).await
+    }
+
+    async fn health_check(&self) -> Result<(), RateLimitError> {
+        self.call(self.store.health_check()).await
+    }
+
+    async fn list_keys(
+        &self,
+        pattern: &str,
+        after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<String>, RateLimitError> {
+        self.call(self.store.list_keys(pattern, after, limit)).await
+    }
+}
+
+// ============================================================================
+// SIMULATION
+// ============================================================================
+
+/// How each node limits.
+#[derive(Debug, Clone)]
+pub enum Mode {
+    /// Every check goes to the shared store.
+    Direct,
+    /// Tokens reserved from the store `batch_size` at a time
+    /// (`BatchingBackend`).
+    Batching { batch_size: u64 },
+    /// Tokens leased from the store (`LeasingBackend`).
+    Leasing(LeaseConfig),
+    /// No store: each node keeps every bucket itself and gossips its
+    /// spending to the others every `interval`.
+    Gossip {
+        interval: Duration,
+        mode: GossipMode,
+    },
+}
+
+/// Checks answered one way or another.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub allowed: u64,
+    pub denied: u64,
+    /// Checks that failed, e.g. on a node partitioned from the store.
+    pub errors: u64,
+}
+
+impl Tally {
+    fn add(&mut self, other: Tally) {
+        self.allowed += other.allowed;
+        self.denied += other.denied;
+        self.errors += other.errors;
+    }
+}
+
+/// What one [`Simulation::run`] saw.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub total: Tally,
+    /// By node, in order.
+    pub nodes: Vec<Tally>,
+    /// From the first check sent to the last answered.
+    pub elapsed: Duration,
+}
+
+/// Nodes limiting one config together, in one of the [`Mode`]s.
+pub struct Simulation {
+    config: TokenBucketConfig,
+    clock: SimClock,
+    network: Arc<Mutex<Network>>,
+    store: Arc<MemoryBackend>,
+    nodes: Vec<Arc<dyn StorageBackend>>,
+}
+
+impl Simulation {
+    /// `nodes` nodes, with no network delay. Gossiping nodes start
+    /// exchanging at once, so this must be called on a runtime.
+    pub fn new(config: TokenBucketConfig, nodes: usize, mode: Mode) -> Self {
+        let clock = SimClock::new();
+        let network = Arc::new(Mutex::new(Network::default()));
+        let store =
+            Arc::new(MemoryBackend::new(config.clone()).with_clock(Arc::new(clock.clone())));
+        let link = |node| StoreLink {
+            node,
+            store: Arc::clone(&store),
+            network: Arc::clone(&network),
+        };
+        let nodes: Vec<Arc<dyn StorageBackend>> = match mode {
+            Mode::Direct => (0..nodes)
+                .map(|node| Arc::new(link(node)) as Arc<dyn StorageBackend>)
+                .collect(),
+            Mode::Batching { batch_size } => (0..nodes)
+                .map(|node| Arc::new(BatchingBackend::new(link(node), batch_size)) as _)
+                .collect(),
+            Mode::Leasing(lease) => (0..nodes)
+                .map(|node| {
+                    let leasing = LeasingBackend::new(link(node), lease.clone())
+                        .with_clock(Arc::new(clock.clone()));
+                    Arc::new(leasing) as _
+                })
+                .collect(),
+            Mode::Gossip { interval, mode } => {
+                let sockets: Vec<_> = (0..nodes)
+                    .map(|node| Arc::new(SimSocket::bind(&network, node)))
+                    .collect();
+                sockets
+                    .iter()
+                    .map(|socket| {
+                        let gossip = GossipConfig {
+                            peers: sockets.iter().map(|peer| peer.addr.to_string()).collect(),
+                            interval,
+                            mode,
+                            ..GossipConfig::default()
+                        };
+                        let node = GossipNode::start_with(
+                            gossip,
+                            Arc::clone(socket) as _,
+                            Arc::new(clock.clone()),
+                        )
+                        .expect("simulated sockets have addresses");
+                        Arc::new(GossipBackend::new(node, config.clone())) as _
+                    })
+                    .collect()
+            }
+        };
+        Self {
+            config,
+            clock,
+            network,
+            store,
+            nodes,
+        }
+    }
+
+    /// Delay every datagram and every leg of a store call by `delay`.
+    pub fn with_delay(self, delay: Duration) -> Self {
+        self.set_delay(delay);
+        self
+    }
+
+    pub fn set_delay(&self, delay: Duration) {
+        self.network.lock().delay = delay;
+    }
+
+    /// Cut `node` off from the store and its peers. Datagrams already on
+    /// their way still arrive.
+    pub fn partition(&self, node: usize) {
+        self.network.lock().partitioned.insert(node);
+    }
+
+    pub fn heal(&self, node: usize) {
+        self.network.lock().partitioned.remove(&node);
+    }
+
+    pub fn node(&self, node: usize) -> &Arc<dyn StorageBackend> {
+        &self.nodes[node]
+    }
+
+    /// The store the nodes share; unused in [`Mode::Gossip`].
+    pub fn store(&self) -> &Arc<MemoryBackend> {
+        &self.store
+    }
+
+    pub fn clock(&self) -> &SimClock {
+        &self.clock
+    }
+
+    /// The most a single bucket could allow over `elapsed`: full to start
+    /// with, plus its refill.
+    pub fn budget(&self, elapsed: Duration) -> u64 {
+        self.config.capacity
+            + (elapsed.as_secs_f64() * self.config.refill_rate as f64).floor() as u64
+    }
+
+    /// Send every node `rate` single-token checks a second on `key` for
+    /// `duration`, each on schedule whether or not earlier ones have been
+    /// answered.
+    pub async fn run(&self, key: &str, rate: u32, duration: Duration) -> Report {
+        let interval = Duration::from_secs(1) / rate.max(1);
+        let checks = duration.as_nanos() / interval.as_nanos().max(1);
+        let started = Instant::now();
+        let mut nodes = JoinSet::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let node = Arc::clone(node);
+            let key = key.to_string();
+            nodes.spawn(async move {
+                let mut ticks = tokio::time::interval(interval);
+                let mut answers = JoinSet::new();
+                for _ in 0..checks {
+                    ticks.tick().await;
+                    let node = Arc::clone(&node);
+                    let key = key.clone();
+                    answers.spawn(async move { node.take_token(&key, 1).await });
+                }
+                let mut tally = Tally::default();
+                while let Some(answer) = answers.join_next().await {
+                    match answer.expect("checks don't panic") {
+                        Ok(true) => tally.allowed += 1,
+                        // Batching refuses a check it can't reserve for.
+                        Ok(false) | Err(RateLimitError::LimitExceeded(_)) => tally.denied += 1,
+                        Err(_) => tally.errors += 1,
+                    }
+                }
+                (index, tally)
+            });
+        }
+
+        let mut report = Report {
+            nodes: vec![Tally::default(); self.nodes.len()],
+            ..Report::default()
+        };
+        while let Some(node) = nodes.join_next().await {
+            let (index, tally) = node.expect("nodes don't panic");
+            report.nodes[index] = tally;
+            report.total.add(tally);
+        }
+        report.elapsed = started.elapsed();
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODES: usize = 4;
+    /// Checks a second per node: twice the refill across the cluster.
+    const RATE: u32 = 500;
+    const DELAY: Duration = Duration::from_millis(5);
+    const INTERVAL: Duration = Duration::from_millis(100);
+    const DURATION: Duration = Duration::from_secs(10);
+
+    /// A token a millisecond, so the store's bucket, which credits whole
+    /// milliseconds' refill, never drops part of a token.
+    fn config() -> TokenBucketConfig {
+        TokenBucketConfig {
+            capacity: 500,
+            refill_rate: 1000,
+            refill_interval: Duration::from_secs(1),
+        }
+    }
+
+    fn lease() -> LeaseConfig {
+        LeaseConfig {
+            size: 20,
+            ttl: Duration::from_secs(2),
+        }
+    }
+
+    fn gossip(mode: GossipMode) -> Mode {
+        Mode::Gossip {
+            interval: INTERVAL,
+            mode,
+        }
+    }
+
+    /// Admitted no more than the bucket allows, and short of it by at
+    /// most `held`, tokens taken from the store but not yet spent, and
+    /// the refill during the last round trip, which came too late for any
+    /// check.
+    fn assert_within(sim: &Simulation, report: &Report, held: u64) {
+        let budget = sim.budget(report.elapsed);
+        let allowed = report.total.allowed;
+        let too_late = sim.budget(DELAY * 2) - config().capacity;
+        assert!(allowed <= budget, "allowed {} of {}", allowed, budget);
+        assert!(
+            allowed + held + too_late >= budget,
+            "allowed {} of {}",
+            allowed,
+            budget
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_direct_checks_admit_the_budget() {
+        let sim = Simulation::new(config(), NODES, Mode::Direct).with_delay(DELAY);
+        let report = sim.run("user1", RATE, DURATION).await;
+        assert_eq!(report.total.errors, 0);
+        assert_within(&sim, &report, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batching_strands_at_most_a_batch_per_node() {
+        let batch_size = 20;
+        let sim = Simulation::new(config(), NODES, Mode::Batching { batch_size }).with_delay(DELAY);
+        let report = sim.run("user1", RATE, DURATION).await;
+        assert_eq!(report.total.errors, 0);
+        // Plus what's left in the bucket, too little for a batch.
+        assert_within(&sim, &report, (NODES as u64 + 1) * batch_size);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_leasing_holds_at_most_a_lease_per_node() {
+        let sim = Simulation::new(config(), NODES, Mode::Leasing(lease())).with_delay(DELAY);
+        let report = sim.run("user1", RATE, DURATION).await;
+        assert_eq!(report.total.errors, 0);
+        assert_within(&sim, &report, NODES as u64 * lease().size);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_a_partitioned_leaseholder_cannot_push_the_cluster_over() {
+        let sim = Simulation::new(config(), NODES, Mode::Leasing(lease())).with_delay(DELAY);
+        let (report, ()) = tokio::join!(sim.run("user1", RATE, DURATION), async {
+            tokio::time::sleep(DURATION / 4).await;
+            sim.partition(0);
+            tokio::time::sleep(DURATION / 2).await;
+            sim.heal(0);
+        });
+        assert!(report.nodes[0].errors > 0);
+        assert!(report.nodes[1..].iter().all(|node| node.errors == 0));
+        assert!(report.total.allowed <= sim.budget(report.elapsed));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gossip_overshoots_a_burst_by_what_peers_spend_unheard() {
+        let config = TokenBucketConfig {
+            refill_rate: 0,
+            ..config()
+        };
+        // What the others spend before a node hears of it.
+        let unheard =
+            (NODES as u64 - 1) * RATE as u64 * (INTERVAL + DELAY).as_millis() as u64 / 1000;
+        for mode in [GossipMode::Deltas, GossipMode::Crdt] {
+            let sim = Simulation::new(config.clone(), NODES, gossip(mode)).with_delay(DELAY);
+            let report = sim.run("user1", RATE, DURATION).await;
+            let allowed = report.total.allowed;
+            assert!(
+                allowed >= config.capacity,
+                "{:?}: allowed {}",
+                mode,
+                allowed
+            );
+            assert!(
+                allowed <= config.capacity + unheard,
+                "{:?}: allowed {}",
+                mode,
+                allowed
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gossip_under_sustained_load_is_held_to_the_limit_per_node() {
+        // An empty bucket can't go into debt, so spending a node hears of
+        // once it has spent its own refill is forgiven, and under sustained
+        // load each node ends up keeping to the limit by itself.
+        let config = TokenBucketConfig {
+            refill_rate: 100,
+            ..config()
+        };
+        for mode in [GossipMode::Deltas, GossipMode::Crdt] {
+            let sim = Simulation::new(config.clone(), NODES, gossip(mode)).with_delay(DELAY);
+            let report = sim.run("user1", RATE, DURATION).await;
+            let budget = sim.budget(report.elapsed);
+            assert!(report.nodes.iter().all(|node| node.allowed <= budget));
+            assert!(report.total.allowed <= NODES as u64 * budget);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_crdt_gossip_charges_partitioned_spending_once_healed() {
+        let config = TokenBucketConfig {
+            refill_rate: 0,
+            ..config()
+        };
+        let sim = Simulation::new(config.clone(), 2, gossip(GossipMode::Crdt)).with_delay(DELAY);
+        sim.partition(1);
+        let apart = sim.run("user1", RATE, DURATION).await;
+        // Each side spends the whole bucket.
+        assert_eq!(apart.nodes[0].allowed, config.capacity);
+        assert_eq!(apart.nodes[1].allowed, config.capacity);
+
+        sim.heal(1);
+        // Long enough for a full exchange of counters.
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        let together = sim.run("user1", RATE, DURATION).await;
+        assert_eq!(together.total.allowed, 0);
+    }
+}
@@ -0,0 +1,198 @@
+use async_trait::async_trait;
+use guardian_core::{BucketState, RateLimitError, StorageBackend, TokenBucketConfig};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tikv_client::{Transaction, TransactionClient};
+
+/// Token buckets stored in TiKV, one key per bucket under a prefix.
+///
+/// Each consumption runs in a pessimistic transaction: `get_for_update`
+/// locks the bucket key, so concurrent limiters queue on the lock instead of
+/// racing, and the refill/consume step runs in Rust rather than a server-side
+/// script. TiKV has no per-key TTL in the transactional API, so buckets live
+/// until `reset`.
+pub struct TikvBackend {
+    client: TransactionClient,
+    config: TokenBucketConfig,
+    prefix: String,
+}
+
+impl TikvBackend {
+    /// Connect through the cluster's PD endpoints (e.g. `127.0.0.1:2379`).
+    pub async fn new<S: Into<String>>(
+        pd_endpoints: Vec<S>,
+        config: TokenBucketConfig,
+    ) -> Result<Self, RateLimitError> {
+        let client = TransactionClient::new(pd_endpoints)
+            .await
+            .map_err(|e| tikv_error("TiKV connection error", e))?;
+        Ok(Self::with_client(client, config, "guardian:bucket:"))
+    }
+
+    pub fn with_client(
+        client: TransactionClient,
+        config: TokenBucketConfig,
+        prefix: impl Into<String>,
+    ) -> Self {
+        Self {
+            client,
+            config,
+            prefix: prefix.into(),
+        }
+    }
+
+    fn bucket_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    async fn consume(
+        &self,
+        txn: &mut Transaction,
+        bucket_key: String,
+        cost: u64,
+    ) -> Result<bool, RateLimitError> {
+        let now_ms = Self::current_millis();
+        let current = txn
+            .get_for_update(bucket_key.clone())
+            .await
+            .map_err(|e| tikv_error("TiKV get error", e))?;
+
+        let mut state = match current {
+            Some(value) => decode_state(&value)?,
+            None => BucketState::full(&self.config, now_ms),
+        };
+        if !state.try_consume(cost, &self.config, now_ms) {
+            return Ok(false);
+        }
+
+        txn.put(bucket_key, encode_state(&state))
+            .await
+            .map_err(|e| tikv_error("TiKV put error", e))?;
+        Ok(true)
+    }
+
+    fn current_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64
+    }
+}
+
+#[async_trait]
+impl StorageBackend for TikvBackend {
+    async fn take_token(&self, key: &str, cost: u64) -> Result<bool, RateLimitError> {
+        let mut txn = self
+            .client
+            .begin_pessimistic()
+            .await
+            .map_err(|e| tikv_error("TiKV begin error", e))?;
+
+        match self.consume(&mut txn, self.bucket_key(key), cost).await {
+            Ok(true) => {
+                txn.commit()
+                    .await
+                    .map_err(|e| tikv_error("TiKV commit error", e))?;
+                Ok(true)
+            }
+            // Denials and failures release the lock without writing.
+            other => {
+                let _ = txn.rollback().await;
+                other
+            }
+        }
+    }
+
+    async fn get_usage(&self, key: &str) -> Result<u64, RateLimitError> {
+        let mut snapshot = self.client.snapshot(
+            self.client
+                .current_timestamp()
+                .await
+                .map_err(|e| tikv_error("TiKV timestamp error", e))?,
+            Default::default(),
+        );
+
+        match snapshot
+            .get(self.bucket_key(key))
+            .await
+            .map_err(|e| tikv_error("TiKV get error", e))?
+        {
+            Some(value) => {
+                let mut state = decode_state(&value)?;
+                state.refill(&self.config, Self::current_millis());
+                Ok(self.config.capacity.saturating_sub(state.tokens))
+            }
+            None => Ok(0),
+        }
+    }
+
+    async fn reset(&self, key: &str) -> Result<(), RateLimitError> {
+        let mut txn = self
+            .client
+            .begin_optimistic()
+            .await
+            .map_err(|e| tikv_error("TiKV begin error", e))?;
+        txn.delete(self.bucket_key(key))
+            .await
+            .map_err(|e| tikv_error("TiKV delete error", e))?;
+        txn.commit()
+            .await
+            .map_err(|e| tikv_error("TiKV commit error", e))?;
+        Ok(())
+    }
+}
+
+fn encode_state(state: &BucketState) -> Vec<u8> {
+    format!("{}:{}", state.tokens, state.last_refill_ms).into_bytes()
+}
+
+fn decode_state(value: &[u8]) -> Result<BucketState, RateLimitError> {
+    let invalid = || RateLimitError::StorageError("TiKV bucket value is malformed".to_string());
+
+    let text = std::str::from_utf8(value).map_err(|_| invalid())?;
+    let (tokens, last_refill_ms) = text.split_once(':').ok_or_else(invalid)?;
+    Ok(BucketState {
+        tokens: tokens.parse().map_err(|_| invalid())?,
+        last_refill_ms: last_refill_ms.parse().map_err(|_| invalid())?,
+    })
+}
+
+fn tikv_error(context: &str, e: tikv_client::Error) -> RateLimitError {
+    RateLimitError::StorageError(format!("{}: {}", context, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_state_round_trip() {
+        let state = BucketState {
+            tokens: 7,
+            last_refill_ms: 1_700_000_000_000,
+        };
+        assert_eq!(decode_state(&encode_state(&state)).unwrap(), state);
+        assert!(decode_state(b"7").is_err());
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires a PD + TiKV cluster
+    async fn test_tikv_backend() {
+        let config = TokenBucketConfig {
+            capacity: 10,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        };
+
+        let backend = TikvBackend::new(vec!["127.0.0.1:2379"], config)
+            .await
+            .unwrap();
+        backend.reset("test_user").await.unwrap();
+
+        assert!(backend.take_token("test_user", 6).await.unwrap());
+        assert!(!backend.take_token("test_user", 6).await.unwrap());
+        assert_eq!(backend.get_usage("test_user").await.unwrap(), 6);
+
+        backend.reset("test_user").await.unwrap();
+    }
+}
@@ -0,0 +1,436 @@
+//! Rate limiting for [tonic](https://docs.rs/tonic) gRPC servers: a Tower
+//! layer that checks each call with Guardian before it reaches a service.
+//!
+//! ```no_run
+//! use guardian_client::GuardianClient;
+//! use guardian_tonic::{Key, RateLimitInterceptor};
+//! use tonic::transport::Server;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let guardian = GuardianClient::connect("http://127.0.0.1:50051").await?;
+//! // One bucket per caller and method, such as
+//! // "billing.Invoices/Create:tenant-id:acme".
+//! let limits = RateLimitInterceptor::new(guardian)
+//!     .with_key(Key::Metadata("tenant-id".to_string()))
+//!     .with_per_method(true);
+//! let server = Server::builder().layer(limits);
+//! // server.add_service(InvoicesServer::new(invoices)).serve(addr).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A denied call never reaches the service; it fails with
+//! `RESOURCE_EXHAUSTED`, carrying `retry-after` and the `ratelimit-*`
+//! values as metadata. Handlers that limit only some calls can check
+//! them with [`RateLimitInterceptor::check`] instead.
+//!
+//! On the client side, [`OutboundRateLimit`] throttles the calls a
+//! channel makes to stay within a partner's quota.
+
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use guardian_client::{GuardianApi, LimitCheckResult};
+use guardian_core::{LimitResult, RateLimiter, StorageBackend};
+use guardian_extractors::{HttpParts, RequestParts};
+use tonic::body::BoxBody;
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::TcpConnectInfo;
+use tonic::{GrpcMethod, Status};
+use tower::{Layer, Service};
+
+mod outbound;
+
+pub use guardian_extractors::{Extractor, TrustedProxies};
+pub use outbound::{OutboundRateLimit, OutboundRateLimitService, DEFAULT_MAX_WAIT};
+
+/// What calls are limited by. Calls without one (a missing metadata
+/// entry, an unknown peer) go through unchecked.
+#[derive(Debug, Clone)]
+pub enum Key {
+    /// A metadata entry's value, as `<name>:<value>`, such as an API key
+    /// or tenant id.
+    Metadata(String),
+    /// The peer's IP address, as `ip:<addr>`.
+    PeerIp,
+    /// The method called, as `method:<package.Service>/<Method>`, for a
+    /// limit shared by all callers of each method.
+    Method,
+    /// A shared [`Extractor`], for keys that mean the same as in
+    /// Guardian's HTTP middleware, such as the client's address behind a
+    /// proxy. Metadata is seen as headers.
+    Extractor(Extractor),
+}
+
+impl Key {
+    fn of(&self, call: &dyn RequestParts) -> Option<String> {
+        match self {
+            Self::Metadata(name) => Some(format!("{}:{}", name, call.header(name)?)),
+            Self::PeerIp => Some(format!("ip:{}", call.peer_ip()?)),
+            Self::Method => Extractor::grpc_method().extract(call),
+            Self::Extractor(extractor) => extractor.extract(call),
+        }
+    }
+}
+
+/// A `tonic::Request` as the shared extractors see it.
+struct CallParts<'a> {
+    metadata: &'a MetadataMap,
+    peer: Option<IpAddr>,
+    path: String,
+}
+
+impl RequestParts for CallParts<'_> {
+    fn header_values(&self, name: &str) -> Vec<&str> {
+        self.metadata
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect()
+    }
+
+    fn peer_ip(&self) -> Option<IpAddr> {
+        self.peer
+    }
+
+    fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+/// Where decisions come from.
+#[async_trait]
+trait Limiter: Send + Sync {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String>;
+}
+
+struct Remote<A>(A);
+
+#[async_trait]
+impl<A: GuardianApi> Limiter for Remote<A> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        self.0
+            .check_limit_detailed(key, cost)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend + 'static> Limiter for RateLimiter<B> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        let (result, bucket) = self
+            .check_limit_detailed(key, cost.into())
+            .await
+            .map_err(|e| e.to_string())?;
+        let retry_after = match result {
+            LimitResult::Allowed => Duration::ZERO,
+            LimitResult::Denied { retry_after } => retry_after,
+        };
+        Ok(LimitCheckResult {
+            allowed: matches!(result, LimitResult::Allowed),
+            retry_after_seconds: retry_after.as_secs_f64().ceil() as u32,
+            remaining_tokens: bucket.map_or(0, |bucket| bucket.remaining),
+            limit: bucket.map_or(0, |bucket| bucket.capacity),
+        })
+    }
+}
+
+/// Limits the calls to a tonic server; see the [crate docs](crate).
+#[derive(Clone)]
+pub struct RateLimitInterceptor {
+    limiter: Arc<dyn Limiter>,
+    key: Key,
+    per_method: bool,
+    fail_open: bool,
+}
+
+impl RateLimitInterceptor {
+    /// Decide through a Guardian service: a
+    /// [`GuardianClient`](guardian_client::GuardianClient), or anything
+    /// else implementing [`GuardianApi`].
+    pub fn new(api: impl GuardianApi + 'static) -> Self {
+        Self::with_limiter(Remote(api))
+    }
+
+    /// Decide in this process, with a `guardian-core` limiter, for servers
+    /// that run no Guardian service.
+    pub fn embedded<B: StorageBackend + 'static>(limiter: RateLimiter<B>) -> Self {
+        Self::with_limiter(limiter)
+    }
+
+    fn with_limiter(limiter: impl Limiter + 'static) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            key: Key::PeerIp,
+            per_method: false,
+            fail_open: true,
+        }
+    }
+
+    /// What calls are limited by; [`Key::PeerIp`] unless set.
+    pub fn with_key(mut self, key: Key) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Prefix keys with the method called (`package.Service/Method:`), so
+    /// each method gets buckets, and service limit rules, of its own.
+    pub fn with_per_method(mut self, per_method: bool) -> Self {
+        self.per_method = per_method;
+        self
+    }
+
+    /// Whether to let calls through when the limiter can't decide (the
+    /// default), or fail them with `UNAVAILABLE`.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Check a call from inside its handler, costing `cost` tokens, for
+    /// services that limit only some methods or price them differently.
+    #[allow(clippy::result_large_err)]
+    pub async fn check<T>(&self, request: &tonic::Request<T>, cost: u32) -> Result<(), Status> {
+        let method = request
+            .extensions()
+            .get::<GrpcMethod>()
+            .map(|method| format!("{}/{}", method.service(), method.method()));
+        let key = self.key.of(&CallParts {
+            metadata: request.metadata(),
+            peer: request.remote_addr().map(|addr| addr.ip()),
+            path: format!("/{}", method.as_deref().unwrap_or_default()),
+        });
+        self.admit(self.scoped(key, method.as_deref()), cost).await
+    }
+
+    fn scoped(&self, key: Option<String>, method: Option<&str>) -> Option<String> {
+        let key = key?;
+        match (self.per_method, method) {
+            (true, Some(method)) => Some(format!("{}:{}", method, key)),
+            _ => Some(key),
+        }
+    }
+
+    /// What the layer does with a call's key, as a future that doesn't
+    /// borrow the interceptor.
+    #[allow(clippy::result_large_err)]
+    fn admit(
+        &self,
+        key: Option<String>,
+        cost: u32,
+    ) -> impl Future<Output = Result<(), Status>> + Send + 'static {
+        let limiter = Arc::clone(&self.limiter);
+        let fail_open = self.fail_open;
+        async move {
+            let Some(key) = key else {
+                return Ok(());
+            };
+            match limiter.check(&key, cost).await {
+                Ok(decision) if !decision.allowed => Err(rate_limited(&decision)),
+                Err(e) if !fail_open => Err(Status::unavailable(format!(
+                    "rate limiter unavailable: {}",
+                    e
+                ))),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+/// `RESOURCE_EXHAUSTED`, carrying `decision`'s rate-limit headers as
+/// metadata.
+fn rate_limited(decision: &LimitCheckResult) -> Status {
+    let mut status = Status::resource_exhausted(format!(
+        "Rate limit exceeded. Retry after {} seconds",
+        decision.retry_after_seconds
+    ));
+    for (name, value) in decision.to_headers() {
+        if let Ok(value) = value.parse() {
+            status.metadata_mut().insert(name, value);
+        }
+    }
+    status
+}
+
+impl<S> Layer<S> for RateLimitInterceptor {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            interceptor: self.clone(),
+        }
+    }
+}
+
+/// A service that checks each call with Guardian before passing it on.
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    interceptor: RateLimitInterceptor,
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for RateLimitService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+        // Keep the service that was polled ready for this call.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let method = req.uri().path().strip_prefix('/');
+        let peer = req
+            .extensions()
+            .get::<TcpConnectInfo>()
+            .and_then(|info| info.remote_addr);
+        let key = self
+            .interceptor
+            .key
+            .of(&HttpParts::new(&req).with_peer(peer.map(|addr| addr.ip())));
+        let admit = self
+            .interceptor
+            .admit(self.interceptor.scoped(key, method), 1);
+        Box::pin(async move {
+            if let Err(status) = admit.await {
+                return Ok(status.into_http());
+            }
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    use guardian_client::{ClientError, MockGuardianClient};
+    use tonic::Code;
+    use tower::ServiceExt;
+
+    const METHOD: &str = "/billing.Invoices/Create";
+
+    fn service(
+        interceptor: RateLimitInterceptor,
+    ) -> impl Service<http::Request<()>, Response = http::Response<BoxBody>, Error = Infallible> + Clone
+    {
+        tower::ServiceBuilder::new().layer(interceptor).service_fn(
+            |_req: http::Request<()>| async {
+                Ok::<_, Infallible>(http::Response::new(tonic::body::empty_body()))
+            },
+        )
+    }
+
+    fn call(tenant: Option<&str>) -> http::Request<()> {
+        let mut builder = http::Request::builder()
+            .uri(METHOD)
+            .extension(TcpConnectInfo {
+                local_addr: None,
+                remote_addr: Some("10.0.0.1:4000".parse().unwrap()),
+            });
+        if let Some(tenant) = tenant {
+            builder = builder.header("tenant-id", tenant);
+        }
+        builder.body(()).unwrap()
+    }
+
+    fn grpc_status(response: &http::Response<BoxBody>) -> Option<&str> {
+        response.headers().get("grpc-status")?.to_str().ok()
+    }
+
+    #[tokio::test]
+    async fn test_denied_calls_get_resource_exhausted() {
+        let mock = MockGuardianClient::new();
+        mock.deny("billing.Invoices/Create:tenant-id:acme", 7);
+        let limits = RateLimitInterceptor::new(mock.clone())
+            .with_key(Key::Metadata("tenant-id".to_string()))
+            .with_per_method(true);
+        let service = service(limits);
+
+        let allowed = service.clone().oneshot(call(Some("other"))).await.unwrap();
+        assert_eq!(grpc_status(&allowed), None);
+        let unkeyed = service.clone().oneshot(call(None)).await.unwrap();
+        assert_eq!(grpc_status(&unkeyed), None);
+        assert_eq!(mock.calls().len(), 1);
+
+        let denied = service.oneshot(call(Some("acme"))).await.unwrap();
+        assert_eq!(grpc_status(&denied), Some("8"));
+        assert_eq!(denied.headers()["retry-after"], "7");
+    }
+
+    #[tokio::test]
+    async fn test_peer_ip_method_and_extractor_keys() {
+        let mock = MockGuardianClient::new();
+        let proxies = TrustedProxies::new(["10.0.0.0/8"]).unwrap();
+        let behind_proxy = Extractor::client_ip(proxies).prefixed("edge");
+        for key in [Key::PeerIp, Key::Method, Key::Extractor(behind_proxy)] {
+            let limits = RateLimitInterceptor::new(mock.clone()).with_key(key);
+            let mut call = call(None);
+            call.headers_mut()
+                .insert("x-forwarded-for", "203.0.113.9".parse().unwrap());
+            service(limits).oneshot(call).await.unwrap();
+        }
+        let keys: Vec<_> = mock
+            .calls()
+            .into_iter()
+            .map(|call| call.client_id)
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "ip:10.0.0.1",
+                "method:billing.Invoices/Create",
+                "edge:ip:203.0.113.9"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_from_a_handler() {
+        let mock = MockGuardianClient::new();
+        mock.deny("Invoices/Create:tenant-id:acme", 3);
+        mock.push_response("tenant-id:acme", Err(ClientError::DeadlineExceeded));
+        let mut request = tonic::Request::new(());
+        request
+            .metadata_mut()
+            .insert("tenant-id", "acme".parse().unwrap());
+        request
+            .extensions_mut()
+            .insert(GrpcMethod::new("Invoices", "Create"));
+
+        let limits = RateLimitInterceptor::new(mock.clone())
+            .with_key(Key::Metadata("tenant-id".to_string()));
+        let status = limits
+            .clone()
+            .with_per_method(true)
+            .check(&request, 5)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "3");
+
+        let status = limits
+            .with_fail_open(false)
+            .check(&request, 1)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(mock.calls()[0].cost, 5);
+    }
+}
@@ -0,0 +1,277 @@
+//! Throttling for the calls a tonic client makes, so a service stays
+//! within a downstream partner's quota.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use guardian_client::GuardianApi;
+use guardian_core::{RateLimiter, StorageBackend};
+use tonic::body::BoxBody;
+use tonic::Status;
+use tower::{Layer, Service};
+
+use crate::{rate_limited, Limiter, Remote};
+
+/// How long a call over quota waits for tokens by default.
+pub const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(30);
+
+/// Limits the calls made through a tonic channel, per method, against
+/// one partner's quota.
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use guardian_client::GuardianClient;
+/// use guardian_tonic::OutboundRateLimit;
+/// use tonic::transport::Channel;
+/// use tower::ServiceBuilder;
+///
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// let guardian = GuardianClient::connect("http://127.0.0.1:50051").await?;
+/// let channel = Channel::from_static("http://partner.example:443").connect().await?;
+/// // Calls count against "acme:<package.Service>/<Method>" buckets; a
+/// // call over quota waits up to 2s for tokens before failing locally.
+/// let channel = ServiceBuilder::new()
+///     .layer(
+///         OutboundRateLimit::new(guardian, "acme")
+///             .with_method_cost("acme.Reports/Generate", 10)
+///             .with_max_wait(Duration::from_secs(2)),
+///     )
+///     .service(channel);
+/// // let client = ReportsClient::new(channel);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct OutboundRateLimit {
+    limiter: Arc<dyn Limiter>,
+    partner: String,
+    costs: Arc<HashMap<String, u32>>,
+    max_wait: Duration,
+    fail_open: bool,
+}
+
+impl OutboundRateLimit {
+    /// Decide through a Guardian service. Keys are
+    /// `<partner>:<package.Service>/<Method>`.
+    pub fn new(api: impl GuardianApi + 'static, partner: impl Into<String>) -> Self {
+        Self::with_limiter(Remote(api), partner.into())
+    }
+
+    /// Decide in this process, with a `guardian-core` limiter, for quotas
+    /// only this process calls against.
+    pub fn embedded<B: StorageBackend + 'static>(
+        limiter: RateLimiter<B>,
+        partner: impl Into<String>,
+    ) -> Self {
+        Self::with_limiter(limiter, partner.into())
+    }
+
+    fn with_limiter(limiter: impl Limiter + 'static, partner: String) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            partner,
+            costs: Arc::default(),
+            max_wait: DEFAULT_MAX_WAIT,
+            fail_open: true,
+        }
+    }
+
+    /// Tokens each call of `method` (`package.Service/Method`) costs; 1
+    /// unless set.
+    pub fn with_method_cost(mut self, method: impl Into<String>, cost: u32) -> Self {
+        Arc::make_mut(&mut self.costs).insert(method.into(), cost);
+        self
+    }
+
+    /// How long a call over quota may wait for tokens before it fails;
+    /// [`DEFAULT_MAX_WAIT`] unless set. Zero fails it at once.
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Whether to make calls when the limiter can't decide (the default),
+    /// or fail them with `UNAVAILABLE`.
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Take `cost` tokens from `key`, waiting for them within `max_wait`.
+    #[allow(clippy::result_large_err)]
+    async fn acquire(&self, key: &str, cost: u32) -> Result<(), Status> {
+        let mut waited = Duration::ZERO;
+        loop {
+            match self.limiter.check(key, cost).await {
+                Ok(decision) if decision.allowed => return Ok(()),
+                Ok(decision) => {
+                    let wait = Duration::from_secs(decision.retry_after_seconds.max(1).into());
+                    if waited + wait > self.max_wait {
+                        return Err(rate_limited(&decision));
+                    }
+                    tokio::time::sleep(wait).await;
+                    waited += wait;
+                }
+                Err(_) if self.fail_open => return Ok(()),
+                Err(e) => {
+                    return Err(Status::unavailable(format!(
+                        "rate limiter unavailable: {}",
+                        e
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl<S> Layer<S> for OutboundRateLimit {
+    type Service = OutboundRateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        OutboundRateLimitService {
+            inner,
+            limit: self.clone(),
+        }
+    }
+}
+
+/// A channel that takes each call's tokens before making it. A call that
+/// can't have them fails locally, without reaching the partner.
+#[derive(Clone)]
+pub struct OutboundRateLimitService<S> {
+    inner: S,
+    limit: OutboundRateLimit,
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for OutboundRateLimitService<S>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<ReqBody>) -> Self::Future {
+        // Keep the service that was polled ready for this call.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let method = req.uri().path().trim_start_matches('/');
+        let cost = self.limit.costs.get(method).copied().unwrap_or(1);
+        let key = format!("{}:{}", self.limit.partner, method);
+        let limit = self.limit.clone();
+        Box::pin(async move {
+            if let Err(status) = limit.acquire(&key, cost).await {
+                return Ok(status.into_http());
+            }
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use guardian_client::{ClientError, LimitCheckResult, MockGuardianClient};
+    use tower::ServiceExt;
+
+    /// A channel answering OK and counting the calls that reached it.
+    fn channel(
+        limit: OutboundRateLimit,
+    ) -> (
+        impl Service<http::Request<()>, Response = http::Response<BoxBody>, Error = Infallible> + Clone,
+        Arc<AtomicU32>,
+    ) {
+        let sent = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&sent);
+        let service =
+            tower::ServiceBuilder::new()
+                .layer(limit)
+                .service_fn(move |_req: http::Request<()>| {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    async { Ok::<_, Infallible>(http::Response::new(tonic::body::empty_body())) }
+                });
+        (service, sent)
+    }
+
+    fn call(method: &str) -> http::Request<()> {
+        http::Request::builder().uri(method).body(()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_calls_over_quota_fail_locally() {
+        let mock = MockGuardianClient::new();
+        mock.deny("acme:acme.Reports/Generate", 30);
+        let limit = OutboundRateLimit::new(mock.clone(), "acme")
+            .with_method_cost("acme.Reports/Generate", 10)
+            .with_max_wait(Duration::ZERO);
+        let (channel, sent) = channel(limit);
+
+        let response = channel
+            .clone()
+            .oneshot(call("/acme.Reports/List"))
+            .await
+            .unwrap();
+        assert!(response.headers().get("grpc-status").is_none());
+        let response = channel
+            .oneshot(call("/acme.Reports/Generate"))
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["grpc-status"], "8");
+        assert_eq!(response.headers()["retry-after"], "30");
+        assert_eq!(sent.load(Ordering::Relaxed), 1);
+
+        let costs: Vec<_> = mock.calls().into_iter().map(|call| call.cost).collect();
+        assert_eq!(costs, [1, 10]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waits_for_tokens_within_max_wait() {
+        let mock = MockGuardianClient::new();
+        let denied = LimitCheckResult {
+            allowed: false,
+            retry_after_seconds: 2,
+            remaining_tokens: 0,
+            limit: 5,
+        };
+        mock.push_response("acme:acme.Reports/List", Ok(denied));
+        let limit =
+            OutboundRateLimit::new(mock.clone(), "acme").with_max_wait(Duration::from_secs(5));
+        let (channel, sent) = channel(limit);
+
+        let started = tokio::time::Instant::now();
+        channel.oneshot(call("/acme.Reports/List")).await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+        assert_eq!(sent.load(Ordering::Relaxed), 1);
+        assert_eq!(mock.calls().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_limiter_errors_fail_open_unless_told_otherwise() {
+        let mock = MockGuardianClient::new();
+        mock.push_response("acme:acme.Reports/List", Err(ClientError::DeadlineExceeded));
+        mock.push_response("acme:acme.Reports/List", Err(ClientError::DeadlineExceeded));
+
+        let (open, _) = channel(OutboundRateLimit::new(mock.clone(), "acme"));
+        let response = open.oneshot(call("/acme.Reports/List")).await.unwrap();
+        assert!(response.headers().get("grpc-status").is_none());
+
+        let (closed, sent) = channel(OutboundRateLimit::new(mock, "acme").with_fail_open(false));
+        let response = closed.oneshot(call("/acme.Reports/List")).await.unwrap();
+        assert_eq!(response.headers()["grpc-status"], "14");
+        assert_eq!(sent.load(Ordering::Relaxed), 0);
+    }
+}
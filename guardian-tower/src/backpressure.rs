@@ -0,0 +1,209 @@
+//! A `guardian-core` limiter as backpressure: [`Backpressure`] is ready
+//! only once its key's bucket has paid for the next request, and stays
+//! pending until the bucket has refilled enough. Tower's own middleware
+//! then sees the limit as readiness, so `LoadShed` sheds over it, `Buffer`
+//! queues behind it, and callers driving `ready()` simply wait.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use guardian_core::{BucketSnapshot, LimitResult, RateLimiter, StorageBackend};
+use tower::{Layer, Service};
+
+/// Wraps services in [`Backpressure`], all drawing on one bucket.
+pub struct BackpressureLayer<B: StorageBackend> {
+    limiter: Arc<RateLimiter<B>>,
+    key: Arc<str>,
+    cost: u64,
+}
+
+impl<B: StorageBackend> Clone for BackpressureLayer<B> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: Arc::clone(&self.limiter),
+            key: Arc::clone(&self.key),
+            cost: self.cost,
+        }
+    }
+}
+
+impl<B: StorageBackend> BackpressureLayer<B> {
+    /// Take a token from `key` for each request.
+    pub fn new(limiter: Arc<RateLimiter<B>>, key: impl Into<String>) -> Self {
+        Self {
+            limiter,
+            key: key.into().into(),
+            cost: 1,
+        }
+    }
+
+    /// Tokens each request costs. A cost over the bucket's capacity is
+    /// never paid, leaving the service pending for good.
+    pub fn with_cost(mut self, cost: u64) -> Self {
+        self.cost = cost;
+        self
+    }
+}
+
+impl<S, B: StorageBackend> Layer<S> for BackpressureLayer<B> {
+    type Service = Backpressure<S, B>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Backpressure {
+            inner,
+            layer: self.clone(),
+            state: State::Unpaid,
+        }
+    }
+}
+
+enum State {
+    Unpaid,
+    Paying(Pin<Box<dyn Future<Output = ()> + Send>>),
+    /// The next request's tokens are taken.
+    Paid,
+}
+
+/// A service whose readiness waits on a Guardian bucket.
+///
+/// The tokens are taken in `poll_ready`, so a service made ready and then
+/// dropped without a call has spent them, as with tower's own `RateLimit`.
+/// Clones start unpaid.
+pub struct Backpressure<S, B: StorageBackend> {
+    inner: S,
+    layer: BackpressureLayer<B>,
+    state: State,
+}
+
+impl<S: Clone, B: StorageBackend> Clone for Backpressure<S, B> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+            state: State::Unpaid,
+        }
+    }
+}
+
+impl<S, B, Request> Service<Request> for Backpressure<S, B>
+where
+    S: Service<Request>,
+    B: StorageBackend + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        loop {
+            match &mut self.state {
+                State::Unpaid => {
+                    let layer = self.layer.clone();
+                    self.state = State::Paying(Box::pin(pay(layer)));
+                }
+                State::Paying(paying) => {
+                    ready!(paying.as_mut().poll(cx));
+                    self.state = State::Paid;
+                }
+                State::Paid => return self.inner.poll_ready(cx),
+            }
+        }
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        assert!(
+            matches!(self.state, State::Paid),
+            "Backpressure called before poll_ready"
+        );
+        self.state = State::Unpaid;
+        self.inner.call(request)
+    }
+}
+
+/// Resolves once the layer's bucket has paid for a request.
+async fn pay<B: StorageBackend>(layer: BackpressureLayer<B>) {
+    loop {
+        let wait = match layer
+            .limiter
+            .check_limit_detailed(&layer.key, layer.cost)
+            .await
+        {
+            Ok((LimitResult::Allowed, _)) => return,
+            Ok((LimitResult::Denied { retry_after }, bucket)) => bucket
+                .and_then(|bucket| refilled_in(bucket, layer.cost))
+                .unwrap_or(retry_after),
+            Err(e) => {
+                tracing::warn!(error = %e, key = &*layer.key, "rate limiter error, holding requests");
+                Duration::from_secs(1)
+            }
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// How long until `bucket` holds `cost` tokens, if it refills.
+fn refilled_in(bucket: BucketSnapshot, cost: u64) -> Option<Duration> {
+    if bucket.refill_rate == 0 {
+        return None;
+    }
+    let missing = cost.saturating_sub(bucket.remaining).max(1);
+    Some(Duration::from_millis(
+        (missing * 1000).div_ceil(bucket.refill_rate),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_core::{MemoryBackend, TokenBucketConfig};
+    use std::convert::Infallible;
+    use tower::load_shed::LoadShedLayer;
+    use tower::{ServiceBuilder, ServiceExt};
+
+    fn limiter(capacity: u64, refill_rate: u64) -> Arc<RateLimiter<MemoryBackend>> {
+        let config = TokenBucketConfig {
+            capacity,
+            refill_rate,
+            ..TokenBucketConfig::default()
+        };
+        Arc::new(RateLimiter::new(MemoryBackend::new(config), false))
+    }
+
+    fn echo() -> impl Service<u32, Response = u32, Error = Infallible> + Clone {
+        tower::service_fn(|n: u32| async move { Ok::<_, Infallible>(n) })
+    }
+
+    // Buckets refill by the wall clock, so these run in real time.
+
+    #[tokio::test]
+    async fn test_pending_until_the_bucket_refills() {
+        let layer = BackpressureLayer::new(limiter(2, 20), "jobs");
+        let mut service = layer.layer(echo());
+
+        for n in 0..2 {
+            assert_eq!(service.ready().await.unwrap().call(n).await.unwrap(), n);
+        }
+        let started = std::time::Instant::now();
+        assert_eq!(service.ready().await.unwrap().call(2).await.unwrap(), 2);
+        // A token comes back every 50ms.
+        assert!(started.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[tokio::test]
+    async fn test_load_shed_sheds_while_pending() {
+        let mut service = ServiceBuilder::new()
+            .layer(LoadShedLayer::new())
+            .layer(BackpressureLayer::new(limiter(1, 20), "api"))
+            .service(echo());
+
+        assert_eq!(service.ready().await.unwrap().call(1).await.unwrap(), 1);
+        let shed = service.ready().await.unwrap().call(2).await.unwrap_err();
+        assert!(shed.is::<tower::load_shed::error::Overloaded>());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(service.ready().await.unwrap().call(3).await.unwrap(), 3);
+    }
+}
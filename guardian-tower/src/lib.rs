@@ -0,0 +1,490 @@
+//! A [Tower](https://docs.rs/tower) layer that checks each request with
+//! Guardian before the service it wraps sees it. It fits anything built
+//! on `tower::Service` over `http` requests: axum and hyper servers, tonic
+//! gRPC servers, or an HTTP client stack.
+//!
+//! ```no_run
+//! use std::convert::Infallible;
+//!
+//! use guardian_client::GuardianClient;
+//! use guardian_tower::GuardianLayer;
+//! use tower::ServiceBuilder;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let guardian = GuardianClient::connect("http://127.0.0.1:50051").await?;
+//! // One bucket per API key; requests without one go through unchecked.
+//! let layer = GuardianLayer::new(guardian, |req: &http::Request<String>| {
+//!     let key = req.headers().get("x-api-key")?.to_str().ok()?;
+//!     Some(format!("api_key:{}", key))
+//! });
+//! let service = ServiceBuilder::new()
+//!     .layer(layer)
+//!     .service_fn(|_req: http::Request<String>| async {
+//!         Ok::<_, Infallible>(http::Response::new("hello".to_string()))
+//!     });
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A denied request is answered without reaching the service: with
+//! `429 Too Many Requests` and the decision's rate-limit headers
+//! (`retry-after` among them), or for gRPC calls with status
+//! `RESOURCE_EXHAUSTED` and the same values as metadata.
+//!
+//! With the `hyper` feature, [`GuardianService`] also implements hyper's
+//! own `Service` trait, so `layer.layer(hyper::service::service_fn(handler))`
+//! can be served by `hyper::server::conn` directly. Handlers that would
+//! rather check requests themselves can call [`GuardianLayer::check`].
+//!
+//! For keys that match the other Guardian middleware, let the closure
+//! call a `guardian-extractors` extractor:
+//! `move |req| extractor.extract(&HttpParts::new(req))`.
+//!
+//! Where a limit should hold requests back rather than answer them, such
+//! as in front of a client or a worker pool, [`BackpressureLayer`] turns a
+//! `guardian-core` bucket into readiness: the service is pending until
+//! the bucket has paid for the next request, for any request type.
+
+mod backpressure;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use guardian_client::{GuardianApi, LimitCheckResult};
+use guardian_core::{LimitResult, RateLimiter, StorageBackend};
+use http::header::{HeaderName, HeaderValue, CONTENT_TYPE};
+use http::{Request, Response, StatusCode};
+use tower::{Layer, Service};
+
+pub use backpressure::{Backpressure, BackpressureLayer};
+
+/// gRPC status codes the layer answers with.
+const RESOURCE_EXHAUSTED: &str = "8";
+const UNAVAILABLE: &str = "14";
+
+/// Tokens a request costs, when not 1. Insert it into the request's
+/// extensions in a layer before this one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuardianCost(pub u32);
+
+/// Where decisions come from.
+#[async_trait]
+trait Limiter: Send + Sync {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String>;
+}
+
+struct Remote<A>(A);
+
+#[async_trait]
+impl<A: GuardianApi> Limiter for Remote<A> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        self.0
+            .check_limit_detailed(key, cost)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend + 'static> Limiter for RateLimiter<B> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        let (result, bucket) = self
+            .check_limit_detailed(key, cost.into())
+            .await
+            .map_err(|e| e.to_string())?;
+        let retry_after = match result {
+            LimitResult::Allowed => Duration::ZERO,
+            LimitResult::Denied { retry_after } => retry_after,
+        };
+        Ok(LimitCheckResult {
+            allowed: matches!(result, LimitResult::Allowed),
+            retry_after_seconds: retry_after.as_secs_f64().ceil() as u32,
+            remaining_tokens: bucket.map_or(0, |bucket| bucket.remaining),
+            limit: bucket.map_or(0, |bucket| bucket.capacity),
+        })
+    }
+}
+
+/// Wraps a service in [`GuardianService`]; see the [crate docs](crate).
+///
+/// `K` picks each request's key. Requests it returns `None` for go
+/// through unchecked.
+pub struct GuardianLayer<K> {
+    limiter: Arc<dyn Limiter>,
+    key: Arc<K>,
+    fail_open: bool,
+}
+
+impl<K> GuardianLayer<K> {
+    /// Decide through a Guardian service: a
+    /// [`GuardianClient`](guardian_client::GuardianClient), or anything
+    /// else implementing [`GuardianApi`].
+    pub fn new(api: impl GuardianApi + 'static, key: K) -> Self {
+        Self::with_limiter(Remote(api), key)
+    }
+
+    /// Decide in this process, with a `guardian-core` limiter, for apps
+    /// that run no Guardian service. The limiter's own `fail_open`
+    /// applies to its backend's errors.
+    pub fn embedded<B: StorageBackend + 'static>(limiter: RateLimiter<B>, key: K) -> Self {
+        Self::with_limiter(limiter, key)
+    }
+
+    fn with_limiter(limiter: impl Limiter + 'static, key: K) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            key: Arc::new(key),
+            fail_open: true,
+        }
+    }
+
+    /// Whether to pass requests on when the limiter can't decide (the
+    /// default), or answer them with `503 Service Unavailable` (gRPC
+    /// `UNAVAILABLE`).
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// Check `req` without wrapping a service, for handlers written
+    /// against `http` types directly: `None` if it may go on, or the
+    /// response to answer it with.
+    pub async fn check<ReqBody, ResBody>(&self, req: &Request<ReqBody>) -> Option<Response<ResBody>>
+    where
+        K: Fn(&Request<ReqBody>) -> Option<String>,
+        ResBody: Default,
+    {
+        self.admit(req).await
+    }
+
+    /// What [`check`](Self::check) does, as a future that doesn't borrow
+    /// `req`, so the request can be passed on once it's ready.
+    fn admit<ReqBody, ResBody>(
+        &self,
+        req: &Request<ReqBody>,
+    ) -> impl Future<Output = Option<Response<ResBody>>> + Send + 'static
+    where
+        K: Fn(&Request<ReqBody>) -> Option<String>,
+        ResBody: Default,
+    {
+        let key = (self.key)(req);
+        let cost = req
+            .extensions()
+            .get::<GuardianCost>()
+            .map_or(1, |cost| cost.0);
+        let grpc = is_grpc(req);
+        let limiter = Arc::clone(&self.limiter);
+        let fail_open = self.fail_open;
+        async move {
+            let key = key?;
+            match limiter.check(&key, cost).await {
+                Ok(decision) if !decision.allowed => Some(denied(grpc, &decision)),
+                Err(_) if !fail_open => Some(unavailable(grpc)),
+                _ => None,
+            }
+        }
+    }
+}
+
+impl<K> Clone for GuardianLayer<K> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: Arc::clone(&self.limiter),
+            key: Arc::clone(&self.key),
+            fail_open: self.fail_open,
+        }
+    }
+}
+
+impl<S, K> Layer<S> for GuardianLayer<K> {
+    type Service = GuardianService<S, K>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GuardianService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// A service that checks each request with Guardian before passing it on.
+pub struct GuardianService<S, K> {
+    inner: S,
+    layer: GuardianLayer<K>,
+}
+
+impl<S: Clone, K> Clone for GuardianService<S, K> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            layer: self.layer.clone(),
+        }
+    }
+}
+
+impl<S, K, ReqBody, ResBody> Service<Request<ReqBody>> for GuardianService<S, K>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    K: Fn(&Request<ReqBody>) -> Option<String>,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // Keep the service that was polled ready for this request.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let admit = self.layer.admit(&req);
+        Box::pin(async move {
+            if let Some(response) = admit.await {
+                return Ok(response);
+            }
+            inner.call(req).await
+        })
+    }
+}
+
+/// hyper's own service trait, so the layer wraps `hyper::service::service_fn`
+/// handlers as they are, with no tower adapter in between.
+#[cfg(feature = "hyper")]
+impl<S, K, ReqBody, ResBody> hyper::service::Service<Request<ReqBody>> for GuardianService<S, K>
+where
+    S: hyper::service::Service<Request<ReqBody>, Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+    K: Fn(&Request<ReqBody>) -> Option<String>,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        let inner = self.inner.clone();
+        let admit = self.layer.admit(&req);
+        Box::pin(async move {
+            if let Some(response) = admit.await {
+                return Ok(response);
+            }
+            inner.call(req).await
+        })
+    }
+}
+
+fn is_grpc<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|content_type| content_type.as_bytes().starts_with(b"application/grpc"))
+}
+
+/// The answer to a denied request, carrying `decision`'s rate-limit
+/// headers.
+fn denied<B: Default>(grpc: bool, decision: &LimitCheckResult) -> Response<B> {
+    let mut response = if grpc {
+        grpc_status(RESOURCE_EXHAUSTED, "rate limited")
+    } else {
+        status(StatusCode::TOO_MANY_REQUESTS)
+    };
+    for (name, value) in decision.to_headers() {
+        if let Ok(value) = HeaderValue::try_from(value) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(name), value);
+        }
+    }
+    response
+}
+
+fn unavailable<B: Default>(grpc: bool) -> Response<B> {
+    if grpc {
+        grpc_status(UNAVAILABLE, "rate limiter unavailable")
+    } else {
+        status(StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+fn status<B: Default>(status: StatusCode) -> Response<B> {
+    let mut response = Response::new(B::default());
+    *response.status_mut() = status;
+    response
+}
+
+/// A trailers-only gRPC response: the status goes in the headers, with no
+/// body.
+fn grpc_status<B: Default>(code: &'static str, message: &'static str) -> Response<B> {
+    let mut response = Response::new(B::default());
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+    headers.insert("grpc-status", HeaderValue::from_static(code));
+    headers.insert("grpc-message", HeaderValue::from_static(message));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use guardian_client::{ClientError, MockGuardianClient};
+    use tower::ServiceExt;
+
+    /// A service answering 200 and counting the requests it saw.
+    fn counted<K>(
+        layer: GuardianLayer<K>,
+    ) -> (
+        impl Service<Request<String>, Response = Response<String>, Error = Infallible> + Clone,
+        Arc<AtomicU32>,
+    )
+    where
+        K: Fn(&Request<String>) -> Option<String>,
+    {
+        let seen = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&seen);
+        let service =
+            tower::ServiceBuilder::new()
+                .layer(layer)
+                .service_fn(move |_req: Request<String>| {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    async { Ok::<_, Infallible>(Response::new("ok".to_string())) }
+                });
+        (service, seen)
+    }
+
+    /// Keyed by the `x-user` header.
+    fn user_key(req: &Request<String>) -> Option<String> {
+        let user = req.headers().get("x-user")?.to_str().ok()?;
+        Some(format!("user:{}", user))
+    }
+
+    fn request(user: Option<&str>, grpc: bool) -> Request<String> {
+        let mut builder = Request::builder().uri("/search");
+        if let Some(user) = user {
+            builder = builder.header("x-user", user);
+        }
+        if grpc {
+            builder = builder.header(CONTENT_TYPE, "application/grpc");
+        }
+        builder.body(String::new()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_denied_requests_get_429_or_resource_exhausted() {
+        let mock = MockGuardianClient::new();
+        mock.deny("user:42", 7);
+        let (service, seen) = counted(GuardianLayer::new(mock.clone(), user_key));
+
+        let allowed = service
+            .clone()
+            .oneshot(request(Some("1"), false))
+            .await
+            .unwrap();
+        assert_eq!(allowed.status(), StatusCode::OK);
+        let unkeyed = service.clone().oneshot(request(None, false)).await.unwrap();
+        assert_eq!(unkeyed.status(), StatusCode::OK);
+        assert_eq!(mock.calls().len(), 1);
+
+        let denied = service
+            .clone()
+            .oneshot(request(Some("42"), false))
+            .await
+            .unwrap();
+        assert_eq!(denied.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(denied.headers()["retry-after"], "7");
+
+        let denied = service.oneshot(request(Some("42"), true)).await.unwrap();
+        assert_eq!(denied.status(), StatusCode::OK);
+        assert_eq!(denied.headers()["grpc-status"], RESOURCE_EXHAUSTED);
+        assert_eq!(denied.headers()["retry-after"], "7");
+        assert_eq!(seen.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_limiter_errors_fail_open_unless_told_otherwise() {
+        let mock = MockGuardianClient::new();
+        mock.push_response("user:1", Err(ClientError::DeadlineExceeded));
+        let (open, _) = counted(GuardianLayer::new(mock.clone(), user_key));
+        let response = open.oneshot(request(Some("1"), false)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        mock.push_response("user:1", Err(ClientError::DeadlineExceeded));
+        mock.push_response("user:1", Err(ClientError::DeadlineExceeded));
+        let (closed, seen) =
+            counted(GuardianLayer::new(mock.clone(), user_key).with_fail_open(false));
+        let response = closed
+            .clone()
+            .oneshot(request(Some("1"), false))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = closed.oneshot(request(Some("1"), true)).await.unwrap();
+        assert_eq!(response.headers()["grpc-status"], UNAVAILABLE);
+        assert_eq!(seen.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_check_answers_like_the_service() {
+        let mock = MockGuardianClient::new();
+        mock.deny("user:42", 7);
+        let layer = GuardianLayer::new(mock, user_key);
+
+        let allowed: Option<Response<String>> = layer.check(&request(Some("1"), false)).await;
+        assert!(allowed.is_none());
+        let denied: Response<String> = layer.check(&request(Some("42"), true)).await.unwrap();
+        assert_eq!(denied.headers()["grpc-status"], RESOURCE_EXHAUSTED);
+    }
+
+    #[cfg(feature = "hyper")]
+    #[tokio::test]
+    async fn test_wraps_hyper_service_fn() {
+        use hyper::service::Service as _;
+
+        let mock = MockGuardianClient::new();
+        mock.deny("user:42", 7);
+        let service = GuardianLayer::new(mock, user_key).layer(hyper::service::service_fn(
+            |_req: Request<String>| async { Ok::<_, Infallible>(Response::new("ok".to_string())) },
+        ));
+
+        let allowed = service.call(request(Some("1"), false)).await.unwrap();
+        assert_eq!(allowed.body(), "ok");
+        let denied = service.call(request(Some("42"), false)).await.unwrap();
+        assert_eq!(denied.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_embedded_limiter() {
+        let backend = guardian_core::MemoryBackend::new(guardian_core::TokenBucketConfig {
+            capacity: 1,
+            refill_rate: 1,
+            refill_interval: Duration::from_secs(1),
+        });
+        let (service, _) = counted(GuardianLayer::embedded(
+            RateLimiter::new(backend, true),
+            user_key,
+        ));
+        let first = service
+            .clone()
+            .oneshot(request(Some("1"), false))
+            .await
+            .unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let second = service.oneshot(request(Some("1"), false)).await.unwrap();
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(second.headers()["ratelimit-limit"], "1");
+    }
+}
@@ -0,0 +1,346 @@
+//! [warp](https://docs.rs/warp) filters that check each request with
+//! Guardian, to compose into existing filter chains.
+//!
+//! ```no_run
+//! use guardian_client::GuardianClient;
+//! use guardian_warp::Guardian;
+//! use warp::Filter;
+//!
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let guardian = Guardian::new(GuardianClient::connect("http://127.0.0.1:50051").await?);
+//! let hello = warp::path("hello")
+//!     .and(guardian.limit(guardian_warp::client_ip()))
+//!     .map(|| "hello");
+//! // Uploads count against the "upload:x-api-key:<key>" bucket, 10 tokens
+//! // at a time.
+//! let uploads = guardian.clone().with_scope("upload").with_cost(10);
+//! let upload = warp::path("upload")
+//!     .and(uploads.limit(guardian_warp::header("x-api-key")))
+//!     .map(|| "stored");
+//! warp::serve(hello.or(upload).recover(guardian_warp::recover))
+//!     .run(([0, 0, 0, 0], 8080))
+//!     .await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A denied request is rejected with [`RateLimited`], which
+//! [`recover`] turns into `429 Too Many Requests` with `retry-after` and
+//! the decision's rate-limit headers.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use guardian_client::{GuardianApi, LimitCheckResult};
+use guardian_core::{LimitResult, RateLimiter, StorageBackend};
+use serde_json::Value;
+use warp::http::header::{HeaderName, HeaderValue};
+use warp::http::StatusCode;
+use warp::reject::{Reject, Rejection};
+use warp::reply::{Reply, Response};
+use warp::Filter;
+
+/// The rejection for a request over its limit.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub key: String,
+    pub retry_after: Duration,
+    /// The decision, for its rate-limit headers.
+    pub decision: LimitCheckResult,
+}
+
+impl Reject for RateLimited {}
+
+impl RateLimited {
+    /// `429 Too Many Requests`, with `retry-after` and the decision's
+    /// rate-limit headers.
+    pub fn to_response(&self) -> Response {
+        let mut response =
+            warp::reply::with_status("rate limited", StatusCode::TOO_MANY_REQUESTS).into_response();
+        for (name, value) in self.decision.to_headers() {
+            if let Ok(value) = HeaderValue::try_from(value) {
+                response
+                    .headers_mut()
+                    .insert(HeaderName::from_static(name), value);
+            }
+        }
+        response
+    }
+}
+
+/// The rejection for a request the limiter couldn't decide, when failing
+/// closed.
+#[derive(Debug)]
+pub struct LimiterUnavailable(pub String);
+
+impl Reject for LimiterUnavailable {}
+
+/// Answer this crate's rejections, passing any other on. Use it with
+/// `Filter::recover`, or call it from your own recovery function.
+pub async fn recover(rejection: Rejection) -> Result<Response, Rejection> {
+    if let Some(limited) = rejection.find::<RateLimited>() {
+        return Ok(limited.to_response());
+    }
+    if rejection.find::<LimiterUnavailable>().is_some() {
+        return Ok(StatusCode::SERVICE_UNAVAILABLE.into_response());
+    }
+    Err(rejection)
+}
+
+/// Key by the peer's IP address, as `ip:<addr>`. Behind a proxy, use
+/// [`header`] with the header the proxy sets instead.
+pub fn client_ip() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::addr::remote().and_then(|addr: Option<SocketAddr>| async move {
+        Ok::<_, Rejection>(addr.map(|addr| format!("ip:{}", addr.ip())))
+    })
+}
+
+/// Key by a header's value, as `<name>:<value>`, such as an API key.
+pub fn header(
+    name: &'static str,
+) -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(name)
+        .map(move |value: Option<String>| value.map(|value| format!("{}:{}", name, value)))
+}
+
+/// Key by a claim of the bearer token in `Authorization`, as
+/// `<claim>:<value>`. The token's signature isn't checked here, so
+/// authenticate before this filter.
+pub fn jwt_claim(
+    claim: &'static str,
+) -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").map(move |value: Option<String>| {
+        let token = value?.strip_prefix("Bearer ")?.to_string();
+        let payload = URL_SAFE_NO_PAD.decode(token.split('.').nth(1)?).ok()?;
+        let claims: Value = serde_json::from_slice(&payload).ok()?;
+        let value = match claims.get(claim)? {
+            Value::String(value) => value.clone(),
+            Value::Number(value) => value.to_string(),
+            _ => return None,
+        };
+        Some(format!("{}:{}", claim, value))
+    })
+}
+
+/// Where decisions come from.
+#[async_trait]
+trait Limiter: Send + Sync {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String>;
+}
+
+struct Remote<A>(A);
+
+#[async_trait]
+impl<A: GuardianApi> Limiter for Remote<A> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        self.0
+            .check_limit_detailed(key, cost)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl<B: StorageBackend + 'static> Limiter for RateLimiter<B> {
+    async fn check(&self, key: &str, cost: u32) -> Result<LimitCheckResult, String> {
+        let (result, bucket) = self
+            .check_limit_detailed(key, cost.into())
+            .await
+            .map_err(|e| e.to_string())?;
+        let retry_after = match result {
+            LimitResult::Allowed => Duration::ZERO,
+            LimitResult::Denied { retry_after } => retry_after,
+        };
+        Ok(LimitCheckResult {
+            allowed: matches!(result, LimitResult::Allowed),
+            retry_after_seconds: retry_after.as_secs_f64().ceil() as u32,
+            remaining_tokens: bucket.map_or(0, |bucket| bucket.remaining),
+            limit: bucket.map_or(0, |bucket| bucket.capacity),
+        })
+    }
+}
+
+/// Makes rate-limiting filters; see the [crate docs](crate).
+#[derive(Clone)]
+pub struct Guardian {
+    limiter: Arc<dyn Limiter>,
+    scope: Option<String>,
+    cost: u32,
+    fail_open: bool,
+}
+
+impl Guardian {
+    /// Decide through a Guardian service: a
+    /// [`GuardianClient`](guardian_client::GuardianClient), or anything
+    /// else implementing [`GuardianApi`].
+    pub fn new(api: impl GuardianApi + 'static) -> Self {
+        Self::with_limiter(Remote(api))
+    }
+
+    /// Decide in this process, with a `guardian-core` limiter, for apps
+    /// that run no Guardian service.
+    pub fn embedded<B: StorageBackend + 'static>(limiter: RateLimiter<B>) -> Self {
+        Self::with_limiter(limiter)
+    }
+
+    fn with_limiter(limiter: impl Limiter + 'static) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            scope: None,
+            cost: 1,
+            fail_open: true,
+        }
+    }
+
+    /// Prefix keys with `scope:`, so the routes it guards get buckets (and
+    /// the service's limit rules) of their own.
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Tokens each request costs; 1 unless set.
+    pub fn with_cost(mut self, cost: u32) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    /// Whether to let requests through when the limiter can't decide (the
+    /// default), or reject them with [`LimiterUnavailable`].
+    pub fn with_fail_open(mut self, fail_open: bool) -> Self {
+        self.fail_open = fail_open;
+        self
+    }
+
+    /// A filter that passes requests within the limit of the key `key`
+    /// extracts and rejects the rest with [`RateLimited`]. Requests `key`
+    /// finds no key for pass unchecked.
+    pub fn limit<K>(&self, key: K) -> impl Filter<Extract = (), Error = Rejection> + Clone
+    where
+        K: Filter<Extract = (Option<String>,), Error = Rejection> + Clone + Send + Sync + 'static,
+    {
+        let guardian = self.clone();
+        key.and_then(move |key: Option<String>| {
+            let guardian = guardian.clone();
+            async move { guardian.check(key).await }
+        })
+        .untuple_one()
+    }
+
+    async fn check(&self, key: Option<String>) -> Result<(), Rejection> {
+        let Some(key) = key else {
+            return Ok(());
+        };
+        let key = match &self.scope {
+            Some(scope) => format!("{}:{}", scope, key),
+            None => key,
+        };
+        match self.limiter.check(&key, self.cost).await {
+            Ok(decision) if decision.allowed => Ok(()),
+            Ok(decision) => Err(warp::reject::custom(RateLimited {
+                key,
+                retry_after: Duration::from_secs(decision.retry_after_seconds.into()),
+                decision,
+            })),
+            Err(_) if self.fail_open => Ok(()),
+            Err(e) => Err(warp::reject::custom(LimiterUnavailable(e))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guardian_client::{ClientError, MockGuardianClient};
+
+    const PEER: ([u8; 4], u16) = ([10, 0, 0, 1], 4000);
+
+    #[tokio::test]
+    async fn test_rejects_denied_requests_with_retry_after() {
+        let mock = MockGuardianClient::new();
+        let guardian = Guardian::new(mock.clone());
+        let routes = warp::path("search")
+            .and(
+                guardian
+                    .with_scope("search")
+                    .with_cost(5)
+                    .limit(client_ip()),
+            )
+            .map(|| "results")
+            .recover(recover);
+
+        let response = warp::test::request()
+            .path("/search")
+            .remote_addr(PEER.into())
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        mock.deny("search:ip:10.0.0.1", 4);
+        let response = warp::test::request()
+            .path("/search")
+            .remote_addr(PEER.into())
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "4");
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[1].cost, 5);
+    }
+
+    #[tokio::test]
+    async fn test_header_and_jwt_claim_keys() {
+        let mock = MockGuardianClient::new();
+        let guardian = Guardian::new(mock.clone());
+        let payload = URL_SAFE_NO_PAD.encode(r#"{"sub":"user-7"}"#);
+        let bearer = format!("Bearer e30.{}.sig", payload);
+
+        let by_header = guardian.limit(header("x-api-key"));
+        let by_claim = guardian.limit(jwt_claim("sub"));
+        for filter in [by_header.boxed(), by_claim.boxed()] {
+            warp::test::request()
+                .header("x-api-key", "k1")
+                .header("authorization", &bearer)
+                .filter(&filter)
+                .await
+                .unwrap();
+            // Neither header: not checked.
+            warp::test::request().filter(&filter).await.unwrap();
+        }
+
+        let keys: Vec<_> = mock
+            .calls()
+            .into_iter()
+            .map(|call| call.client_id)
+            .collect();
+        assert_eq!(keys, ["x-api-key:k1", "sub:user-7"]);
+    }
+
+    #[tokio::test]
+    async fn test_fails_open_unless_told_otherwise() {
+        let mock = MockGuardianClient::new();
+        mock.push_response("ip:10.0.0.1", Err(ClientError::DeadlineExceeded));
+        mock.push_response("ip:10.0.0.1", Err(ClientError::DeadlineExceeded));
+
+        let open = Guardian::new(mock.clone()).limit(client_ip());
+        let passed = warp::test::request()
+            .remote_addr(PEER.into())
+            .filter(&open)
+            .await;
+        assert!(passed.is_ok());
+
+        let closed = Guardian::new(mock).with_fail_open(false).limit(client_ip());
+        let rejection = warp::test::request()
+            .remote_addr(PEER.into())
+            .filter(&closed)
+            .await
+            .unwrap_err();
+        assert!(rejection.find::<LimiterUnavailable>().is_some());
+    }
+}